@@ -5,6 +5,8 @@ use std::sync::Arc;
 use criterion::{criterion_group, criterion_main, Criterion};
 use jemallocator::Jemalloc;
 
+use surrealkv::Durability;
+use surrealkv::IsolationLevel;
 use surrealkv::Options;
 use surrealkv::Store;
 use tempdir::TempDir;
@@ -168,6 +170,81 @@ fn concurrent_insert(c: &mut Criterion) {
     rt.shutdown_background();
 }
 
+fn concurrent_durable_commits(c: &mut Criterion) {
+    let thread_count = 8_u32;
+    let commit_count = 100_u32;
+
+    let mut group = c.benchmark_group("durable commits");
+    group.sample_size(10);
+    group.throughput(criterion::Throughput::Elements(
+        (thread_count * commit_count) as u64,
+    ));
+
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(8)
+        .enable_all()
+        .build()
+        .unwrap();
+    // The stores are dropped in the runtime, which closes them.
+    let _guard = rt.enter();
+
+    for isolation_level in [
+        IsolationLevel::SnapshotIsolation,
+        IsolationLevel::SerializableSnapshotIsolation,
+    ] {
+        let db = rt.block_on(async {
+            let mut opts = Options::new();
+            opts.dir = create_temp_directory().path().to_path_buf();
+            opts.isolation_level = isolation_level;
+            Arc::new(Store::new(opts).expect("should create store"))
+        });
+
+        // Every commit syncs the commit log, and reads the key it writes, which is validated
+        // against the commits in flight.
+        group.bench_function(
+            format!(
+                "{} commits ({} threads, {:?})",
+                thread_count * commit_count,
+                thread_count,
+                isolation_level
+            ),
+            |b| {
+                b.iter(|| {
+                    let handles: Vec<_> = (0..thread_count)
+                        .map(|thread| {
+                            let db = db.clone();
+                            rt.spawn(async move {
+                                let key = thread.to_be_bytes();
+                                for i in 0..commit_count {
+                                    let mut txn = db.begin().unwrap();
+                                    txn.set_durability(Durability::Immediate);
+                                    txn.get(&key).unwrap();
+                                    txn.set(&key, &i.to_be_bytes()).unwrap();
+                                    txn.commit().await.unwrap();
+                                }
+                            })
+                        })
+                        .collect();
+
+                    for handle in handles {
+                        rt.block_on(handle).unwrap();
+                    }
+                })
+            },
+        );
+
+        rt.block_on(async {
+            db.close().await.unwrap();
+        });
+    }
+
+    rt.shutdown_background();
+}
+
 criterion_group!(benches_sequential, bulk_insert, sequential_insert_read);
-criterion_group!(benches_concurrent, concurrent_insert);
+criterion_group!(
+    benches_concurrent,
+    concurrent_insert,
+    concurrent_durable_commits
+);
 criterion_main!(benches_sequential, benches_concurrent);
@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::Result;
+
+/// Arguments of a command: positional arguments, and `--name value` or `--name` options.
+pub(crate) struct Args {
+    positional: Vec<String>,
+    options: HashMap<String, Option<String>>,
+}
+
+impl Args {
+    /// Parses the arguments of a command taking `positional` arguments, the options listed
+    /// in `with_value` followed by a value, and the `flags` options without one.
+    pub(crate) fn parse(
+        argv: Vec<String>,
+        positional: &[&str],
+        with_value: &[&str],
+        flags: &[&str],
+    ) -> Result<Self> {
+        let mut args = Self {
+            positional: Vec::new(),
+            options: HashMap::new(),
+        };
+        let mut argv = argv.into_iter();
+        while let Some(arg) = argv.next() {
+            if with_value.contains(&arg.as_str()) {
+                let value = argv
+                    .next()
+                    .ok_or_else(|| format!("missing value for `{}`", arg))?;
+                args.options.insert(arg, Some(value));
+            } else if flags.contains(&arg.as_str()) {
+                args.options.insert(arg, None);
+            } else if arg.starts_with("--") {
+                return Err(format!("unknown option `{}`", arg).into());
+            } else {
+                args.positional.push(arg);
+            }
+        }
+
+        if args.positional.len() < positional.len() {
+            let missing = positional[args.positional.len()..].join("> <");
+            return Err(format!("missing <{}>", missing).into());
+        }
+        if args.positional.len() > positional.len() {
+            return Err(format!(
+                "unexpected argument `{}`",
+                args.positional[positional.len()]
+            )
+            .into());
+        }
+        Ok(args)
+    }
+
+    /// Returns the `i`th positional argument.
+    pub(crate) fn positional(&self, i: usize) -> &str {
+        &self.positional[i]
+    }
+
+    /// Returns the value of an option, if given.
+    pub(crate) fn value(&self, name: &str) -> Option<&str> {
+        self.options.get(name).and_then(|v| v.as_deref())
+    }
+
+    /// Returns the value of an option parsed as a `T`, if given.
+    pub(crate) fn parsed<T: FromStr>(&self, name: &str) -> Result<Option<T>> {
+        match self.value(name) {
+            Some(value) => match value.parse() {
+                Ok(value) => Ok(Some(value)),
+                Err(_) => Err(format!("invalid value `{}` for `{}`", value, name).into()),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Returns true if a flag is given.
+    pub(crate) fn flag(&self, name: &str) -> bool {
+        self.options.contains_key(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Args;
+
+    fn argv(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_args() {
+        let args = Args::parse(
+            argv(&["--count", "3", "dir", "--verbose"]),
+            &["dir"],
+            &["--count", "--interval"],
+            &["--verbose"],
+        )
+        .unwrap();
+        assert_eq!(args.positional(0), "dir");
+        assert_eq!(args.parsed::<u64>("--count").unwrap(), Some(3));
+        assert_eq!(args.parsed::<u64>("--interval").unwrap(), None);
+        assert!(args.flag("--verbose"));
+
+        let parse = |a: &[&str]| Args::parse(argv(a), &["dir"], &["--count"], &[]);
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["dir", "other"]).is_err());
+        assert!(parse(&["dir", "--count"]).is_err());
+        assert!(parse(&["dir", "--unknown"]).is_err());
+        assert!(parse(&["dir", "--count", "x"])
+            .unwrap()
+            .parsed::<u64>("--count")
+            .is_err());
+    }
+}
@@ -0,0 +1,39 @@
+use std::path::PathBuf;
+
+use surrealkv::Store;
+
+use crate::{args::Args, block_on, store_options, Result};
+
+/// Runs `skv backup <dir> <file> [--incremental --since <marker>]`.
+pub(crate) fn backup(argv: Vec<String>) -> Result<()> {
+    let args = Args::parse(argv, &["dir", "file"], &["--since"], &["--incremental"])?;
+    let dir = PathBuf::from(args.positional(0));
+    let file = PathBuf::from(args.positional(1));
+    let since = match (args.flag("--incremental"), args.parsed::<u64>("--since")?) {
+        (true, Some(since)) => since,
+        (false, None) => 0,
+        (true, None) => return Err("`--incremental` requires `--since <marker>`".into()),
+        (false, Some(_)) => return Err("`--since` requires `--incremental`".into()),
+    };
+
+    let opts = store_options(&dir)?;
+    let marker = block_on(async {
+        let store = Store::new(opts)?;
+        let marker = store.backup(&file, since).await;
+        store.close().await?;
+        marker
+    })??;
+    println!("{}", marker);
+    Ok(())
+}
+
+/// Runs `skv restore <file> <dir>`.
+pub(crate) fn restore(argv: Vec<String>) -> Result<()> {
+    let args = Args::parse(argv, &["file", "dir"], &[], &[])?;
+    let file = PathBuf::from(args.positional(0));
+    let dir = PathBuf::from(args.positional(1));
+
+    let marker = block_on(Store::restore(&file, &dir))??;
+    println!("{}", marker);
+    Ok(())
+}
@@ -0,0 +1,122 @@
+use std::str::FromStr;
+
+use crate::Result;
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encoding of the keys and values given on the command line and printed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Encoding {
+    Raw,    // UTF-8 text. Printed bytes that are not valid UTF-8 are replaced.
+    Hex,    // Two lowercase hex digits per byte.
+    Base64, // Standard base64, with padding.
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(Self::Raw),
+            "hex" => Ok(Self::Hex),
+            "base64" => Ok(Self::Base64),
+            _ => Err(format!("unknown encoding `{}`", s)),
+        }
+    }
+}
+
+impl Encoding {
+    pub(crate) fn decode(&self, s: &str) -> Result<Vec<u8>> {
+        match self {
+            Self::Raw => Ok(s.as_bytes().to_vec()),
+            Self::Hex => {
+                if s.len() % 2 != 0 {
+                    return Err(format!("odd number of hex digits in `{}`", s).into());
+                }
+                (0..s.len())
+                    .step_by(2)
+                    .map(|i| {
+                        u8::from_str_radix(&s[i..i + 2], 16)
+                            .map_err(|_| format!("invalid hex in `{}`", s).into())
+                    })
+                    .collect()
+            }
+            Self::Base64 => {
+                let invalid = || format!("invalid base64 in `{}`", s);
+                let s = s.trim_end_matches('=');
+                let mut out = Vec::with_capacity(s.len() * 3 / 4);
+                let (mut bits, mut n) = (0u32, 0);
+                for c in s.bytes() {
+                    let v = BASE64_ALPHABET
+                        .iter()
+                        .position(|a| *a == c)
+                        .ok_or_else(invalid)?;
+                    bits = bits << 6 | v as u32;
+                    n += 6;
+                    if n >= 8 {
+                        n -= 8;
+                        out.push((bits >> n) as u8);
+                    }
+                }
+                // The leftover bits are padding, and must be zero.
+                if n >= 6 || bits & ((1 << n) - 1) != 0 {
+                    return Err(invalid().into());
+                }
+                Ok(out)
+            }
+        }
+    }
+
+    pub(crate) fn encode(&self, b: &[u8]) -> String {
+        match self {
+            Self::Raw => String::from_utf8_lossy(b).into_owned(),
+            Self::Hex => b.iter().map(|b| format!("{:02x}", b)).collect(),
+            Self::Base64 => {
+                let mut out = String::with_capacity(b.len().div_ceil(3) * 4);
+                for chunk in b.chunks(3) {
+                    let bits = chunk
+                        .iter()
+                        .enumerate()
+                        .fold(0u32, |bits, (i, b)| bits | (*b as u32) << (16 - 8 * i));
+                    for i in 0..4 {
+                        if i <= chunk.len() {
+                            out.push(
+                                BASE64_ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char,
+                            );
+                        } else {
+                            out.push('=');
+                        }
+                    }
+                }
+                out
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Encoding;
+
+    #[test]
+    fn encodings_roundtrip() {
+        let cases: [(&[u8], &str, &str); 4] = [
+            (b"", "", ""),
+            (b"f", "66", "Zg=="),
+            (b"fo\xff", "666fff", "Zm//"),
+            (b"foob", "666f6f62", "Zm9vYg=="),
+        ];
+        for (bytes, hex, base64) in cases {
+            assert_eq!(Encoding::Hex.encode(bytes), hex);
+            assert_eq!(Encoding::Hex.decode(hex).unwrap(), bytes);
+            assert_eq!(Encoding::Base64.encode(bytes), base64);
+            assert_eq!(Encoding::Base64.decode(base64).unwrap(), bytes);
+        }
+        assert!(Encoding::Hex.decode("6").is_err());
+        assert!(Encoding::Hex.decode("zz").is_err());
+        assert!(Encoding::Base64.decode("Zh==").is_err());
+        assert!(Encoding::Base64.decode("Z").is_err());
+        assert_eq!(Encoding::Raw.decode("a b").unwrap(), b"a b");
+    }
+}
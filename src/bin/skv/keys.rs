@@ -0,0 +1,247 @@
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+
+use surrealkv::{Durability, Store};
+
+use crate::{args::Args, block_on, encoding::Encoding, store_options, Result};
+
+/// A mutation given on the command line or in a transaction file.
+#[derive(Debug, PartialEq, Eq)]
+enum Mutation {
+    Put(Vec<u8>, Vec<u8>),
+    Del(Vec<u8>),
+}
+
+/// Runs `skv get <dir> <key> [--at-version <version>] [--encoding <encoding>]`.
+pub(crate) fn get(argv: Vec<String>) -> Result<()> {
+    let args = Args::parse(argv, &["dir", "key"], &["--at-version", "--encoding"], &[])?;
+    let encoding = encoding(&args)?;
+    let key = encoding.decode(args.positional(1))?;
+    let at_version: Option<u64> = args.parsed("--at-version")?;
+
+    let value = with_store(Path::new(args.positional(0)), |store| {
+        let txn = store.begin()?;
+        Ok(match at_version {
+            Some(version) => txn.get_at_version(&key, version)?,
+            None => txn.get(&key)?,
+        })
+    })?;
+    match value {
+        Some(value) => {
+            println!("{}", encoding.encode(&value));
+            Ok(())
+        }
+        None => Err(format!("key `{}` not found", args.positional(1)).into()),
+    }
+}
+
+/// Runs `skv scan <dir> [--prefix <prefix>] [--limit <n>] [--at-version <version>]
+/// [--encoding <encoding>]`, printing a `key<TAB>value` line per key.
+pub(crate) fn scan(argv: Vec<String>) -> Result<()> {
+    let args = Args::parse(
+        argv,
+        &["dir"],
+        &["--prefix", "--limit", "--at-version", "--encoding"],
+        &[],
+    )?;
+    let encoding = encoding(&args)?;
+    let prefix = match args.value("--prefix") {
+        Some(prefix) => encoding.decode(prefix)?,
+        None => Vec::new(),
+    };
+    let end = prefix_end(&prefix);
+    let limit: Option<usize> = args.parsed("--limit")?;
+    let at_version: Option<u64> = args.parsed("--at-version")?;
+
+    let results = with_store(Path::new(args.positional(0)), |store| {
+        let txn = store.begin()?;
+        let range = (
+            std::ops::Bound::Included(&prefix[..]),
+            match &end {
+                Some(end) => std::ops::Bound::Excluded(&end[..]),
+                None => std::ops::Bound::Unbounded,
+            },
+        );
+        Ok(match at_version {
+            Some(version) => txn.scan_at_version(range, version, limit)?,
+            None => txn.scan(range, limit)?,
+        })
+    })?;
+
+    let mut stdout = std::io::stdout().lock();
+    for (key, value, ..) in results {
+        writeln!(
+            stdout,
+            "{}\t{}",
+            encoding.encode(&key),
+            encoding.encode(&value)
+        )?;
+    }
+    Ok(())
+}
+
+/// Runs `skv put <dir> <key> <value>` and `skv del <dir> <key>`, or either with
+/// `--txn-file <file>` to apply the mutations of the file in a single transaction.
+pub(crate) fn mutate(argv: Vec<String>, del: bool) -> Result<()> {
+    let txn_file = argv.iter().any(|arg| arg == "--txn-file");
+    let positional: &[&str] = match (txn_file, del) {
+        (true, _) => &["dir"],
+        (false, true) => &["dir", "key"],
+        (false, false) => &["dir", "key", "value"],
+    };
+    let args = Args::parse(argv, positional, &["--txn-file", "--encoding"], &[])?;
+    let encoding = encoding(&args)?;
+
+    let mutations = match args.value("--txn-file") {
+        Some(file) => {
+            let path = PathBuf::from(file);
+            let text = std::fs::read_to_string(&path)
+                .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+            parse_txn_file(&text, encoding)?
+        }
+        None if del => vec![Mutation::Del(encoding.decode(args.positional(1))?)],
+        None => vec![Mutation::Put(
+            encoding.decode(args.positional(1))?,
+            encoding.decode(args.positional(2))?,
+        )],
+    };
+    if mutations.is_empty() {
+        return Err("no mutations to apply".into());
+    }
+
+    let dir = PathBuf::from(args.positional(0));
+    let opts = store_options(&dir)?;
+    let version = block_on(async {
+        let store = Store::new(opts)?;
+        let applied = apply(&store, &mutations).await;
+        store.close().await?;
+        applied
+    })??;
+
+    // The mutations are printed once committed, so that the output records the change made.
+    let mut stdout = std::io::stdout().lock();
+    for mutation in &mutations {
+        match mutation {
+            Mutation::Put(key, value) => writeln!(
+                stdout,
+                "put {} {}",
+                encoding.encode(key),
+                encoding.encode(value)
+            )?,
+            Mutation::Del(key) => writeln!(stdout, "del {}", encoding.encode(key))?,
+        }
+    }
+    writeln!(stdout, "committed version {}", version)?;
+    Ok(())
+}
+
+/// Applies the mutations in a single transaction, and returns the version it committed.
+async fn apply(store: &Store, mutations: &[Mutation]) -> surrealkv::Result<u64> {
+    let mut txn = store.begin()?;
+    txn.set_durability(Durability::Immediate);
+    for mutation in mutations {
+        match mutation {
+            Mutation::Put(key, value) => txn.set(key, value)?,
+            Mutation::Del(key) => txn.delete(key)?,
+        }
+    }
+    let prepared = txn.prepare().await?;
+    let version = prepared.version().unwrap_or_default();
+    prepared.commit().await?;
+    Ok(version)
+}
+
+/// Parses a transaction file: a `put <key> <value>` or `del <key>` mutation per line, with
+/// the keys and values in the chosen encoding. Empty lines and lines starting with `#` are
+/// skipped. With the raw encoding, the value is the rest of the line after the key.
+fn parse_txn_file(text: &str, encoding: Encoding) -> Result<Vec<Mutation>> {
+    let mut mutations = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim_start();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |what: &str| format!("line {}: {}", i + 1, what);
+        let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let rest = rest.trim_start();
+        let mutation = match command {
+            "put" => {
+                let (key, value) = rest
+                    .split_once(' ')
+                    .ok_or_else(|| invalid("expected `put <key> <value>`"))?;
+                Mutation::Put(
+                    encoding.decode(key).map_err(|e| invalid(&e.to_string()))?,
+                    encoding
+                        .decode(value)
+                        .map_err(|e| invalid(&e.to_string()))?,
+                )
+            }
+            "del" if !rest.is_empty() && !rest.contains(' ') => {
+                Mutation::Del(encoding.decode(rest).map_err(|e| invalid(&e.to_string()))?)
+            }
+            "del" => return Err(invalid("expected `del <key>`").into()),
+            _ => return Err(invalid(&format!("unknown command `{}`", command)).into()),
+        };
+        mutations.push(mutation);
+    }
+    Ok(mutations)
+}
+
+/// Opens the store in `dir`, reads from it and closes it.
+fn with_store<T>(dir: &Path, f: impl FnOnce(&Store) -> surrealkv::Result<T>) -> Result<T> {
+    let opts = store_options(dir)?;
+    Ok(block_on(async {
+        let store = Store::new(opts)?;
+        let read = f(&store);
+        store.close().await?;
+        read
+    })??)
+}
+
+fn encoding(args: &Args) -> Result<Encoding> {
+    Ok(args.parsed("--encoding")?.unwrap_or(Encoding::Raw))
+}
+
+/// Returns the first key after all the keys starting with `prefix`, or None if there is no
+/// such key.
+fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_txn_file, prefix_end, Mutation};
+    use crate::encoding::Encoding;
+
+    #[test]
+    fn parse_mutations() {
+        let text = "# fix the user\nput user:1 {\"name\": \"a\"}\n\n  del user:2\n";
+        assert_eq!(
+            parse_txn_file(text, Encoding::Raw).unwrap(),
+            vec![
+                Mutation::Put(b"user:1".to_vec(), b"{\"name\": \"a\"}".to_vec()),
+                Mutation::Del(b"user:2".to_vec()),
+            ]
+        );
+        assert_eq!(
+            parse_txn_file("put 00ff 01", Encoding::Hex).unwrap(),
+            vec![Mutation::Put(vec![0, 0xff], vec![1])]
+        );
+        assert!(parse_txn_file("put key", Encoding::Raw).is_err());
+        assert!(parse_txn_file("del a b", Encoding::Raw).is_err());
+        assert!(parse_txn_file("set a b", Encoding::Raw).is_err());
+        assert!(parse_txn_file("put zz 01", Encoding::Hex).is_err());
+
+        assert_eq!(prefix_end(b"ab"), Some(b"ac".to_vec()));
+        assert_eq!(prefix_end(&[1, 0xff]), Some(vec![2]));
+        assert_eq!(prefix_end(&[0xff]), None);
+        assert_eq!(prefix_end(b""), None);
+    }
+}
@@ -0,0 +1,92 @@
+//! `skv` inspects and operates surrealkv stores from the command line.
+
+mod args;
+mod backup;
+mod encoding;
+mod keys;
+mod top;
+
+use std::path::Path;
+use std::process::ExitCode;
+
+use surrealkv::{Options, Store};
+
+pub(crate) type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+const USAGE: &str = "\
+Usage: skv <command> [arguments]
+
+Commands:
+  top <dir> [--interval <seconds>] [--count <frames>]
+      Shows the stats of a store. A running store is followed through the stats it
+      publishes (see `Options::stats_publish_interval`), a closed store is opened to
+      report them once.
+  backup <dir> <file> [--incremental --since <marker>]
+      Writes a backup of a store that is not open to <file>, and prints its marker. An
+      incremental backup holds the transactions committed after the backup that printed
+      <marker>.
+  restore <file> <dir>
+      Restores a backup into <dir>, and prints its marker. A full backup creates the
+      store, the incremental backups are then restored on top of it in order.
+  get <dir> <key> [--at-version <version>]
+      Prints the value of a key, or the value it had at <version> (see the versions
+      printed by put and del).
+  scan <dir> [--prefix <prefix>] [--limit <n>] [--at-version <version>]
+      Prints the keys starting with <prefix> and their values, a key and a value separated
+      by a tab per line.
+  put <dir> <key> <value>
+  del <dir> <key>
+  put|del <dir> --txn-file <file>
+      Sets or deletes a key, or applies the mutations of <file> in a single transaction:
+      a `put <key> <value>` or `del <key>` line each, `#` starting a comment line. The
+      mutations are printed with the version they committed once they are durable.
+  The commands reading or writing keys take [--encoding raw|hex|base64], the encoding of
+  the keys and values given and printed, raw by default.
+  help
+      Prints this message.
+";
+
+fn main() -> ExitCode {
+    let mut argv = std::env::args().skip(1);
+    let Some(command) = argv.next() else {
+        eprint!("{}", USAGE);
+        return ExitCode::from(2);
+    };
+    let argv: Vec<String> = argv.collect();
+
+    let result = match command.as_str() {
+        "top" => top::run(argv),
+        "backup" => backup::backup(argv),
+        "restore" => backup::restore(argv),
+        "get" => keys::get(argv),
+        "scan" => keys::scan(argv),
+        "put" => keys::mutate(argv, false),
+        "del" => keys::mutate(argv, true),
+        "help" | "--help" | "-h" => {
+            print!("{}", USAGE);
+            Ok(())
+        }
+        _ => Err(format!("unknown command `{}`, see `skv help`", command).into()),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("skv: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Runs a future on a runtime for the store writer.
+pub(crate) fn block_on<F: std::future::Future>(f: F) -> Result<F::Output> {
+    let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+    Ok(runtime.block_on(f))
+}
+
+/// Returns the options of the store in `dir`, failing if there is none.
+pub(crate) fn store_options(dir: &Path) -> Result<Options> {
+    match Store::persisted_options(dir)? {
+        Some(opts) => Ok(opts),
+        None => Err(format!("no store in {}", dir.display()).into()),
+    }
+}
@@ -0,0 +1,211 @@
+use std::fmt::Write as _;
+use std::io::{IsTerminal, Write as _};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use surrealkv::{Percentiles, Store, StoreStats};
+
+use crate::{args::Args, block_on, store_options, Result};
+
+/// Runs `skv top <dir> [--interval <seconds>] [--count <frames>]`.
+///
+/// If the store publishes its stats, they are read again every interval and the rates are
+/// computed between two frames, until the store is closed or `--count` frames are shown.
+/// Otherwise the store is opened, which requires that no other process has it open, and its
+/// stats are shown once.
+pub(crate) fn run(argv: Vec<String>) -> Result<()> {
+    let args = Args::parse(argv, &["dir"], &["--interval", "--count"], &[])?;
+    let dir = PathBuf::from(args.positional(0));
+    let interval = Duration::from_secs_f64(args.parsed("--interval")?.unwrap_or(1.0));
+    let count: Option<u64> = args.parsed("--count")?;
+    let mut stdout = std::io::stdout();
+
+    if Store::published_stats(&dir)?.is_none() {
+        let stats = offline_stats(&dir)?;
+        write!(stdout, "{}", render(&dir, "offline", &stats, None))?;
+        return Ok(());
+    }
+
+    let mut previous = None;
+    let mut frames = 0;
+    loop {
+        let Some(stats) = Store::published_stats(&dir)? else {
+            writeln!(stdout, "The store was closed.")?;
+            return Ok(());
+        };
+        if stdout.is_terminal() {
+            // Clear the screen and move the cursor to the top left corner.
+            write!(stdout, "\x1b[2J\x1b[H")?;
+        }
+        write!(
+            stdout,
+            "{}",
+            render(&dir, "attached", &stats, previous.as_ref())
+        )?;
+        stdout.flush()?;
+
+        frames += 1;
+        if count.is_some_and(|count| frames >= count) {
+            return Ok(());
+        }
+        previous = Some(stats);
+        std::thread::sleep(interval);
+    }
+}
+
+/// Opens the store in `dir` to read its stats.
+fn offline_stats(dir: &Path) -> Result<StoreStats> {
+    let mut opts = store_options(dir)?;
+    opts.track_memory = true;
+    block_on(async {
+        let store = Store::new(opts)?;
+        let stats = store.stats();
+        store.close().await?;
+        Ok(stats)
+    })?
+}
+
+/// Renders a frame. The rates are computed since the `previous` frame, or over the lifetime
+/// of the store for the first one.
+fn render(dir: &Path, mode: &str, stats: &StoreStats, previous: Option<&StoreStats>) -> String {
+    let base = previous.cloned().unwrap_or_default();
+    let elapsed_ms = stats.uptime_ms.saturating_sub(base.uptime_ms);
+    let per_sec = |now: u64, before: u64| match elapsed_ms {
+        0 => 0.0,
+        ms => now.saturating_sub(before) as f64 * 1000.0 / ms as f64,
+    };
+
+    let hits = stats.cache_reads.saturating_sub(base.cache_reads);
+    let misses = stats.log_reads.saturating_sub(base.log_reads);
+    let hit_ratio = match hits + misses {
+        0 => "-".to_string(),
+        reads => format!("{:.1}%", hits as f64 * 100.0 / reads as f64),
+    };
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "skv top - {} ({}, up {})\n",
+        dir.display(),
+        mode,
+        duration(stats.uptime_ms * 1_000_000)
+    );
+    let _ = writeln!(
+        out,
+        "throughput   commits/s {:<10.1} fsyncs/s {}",
+        per_sec(
+            stats.commit_batch_entries.count,
+            base.commit_batch_entries.count
+        ),
+        stats.fsyncs_per_sec
+    );
+    let _ = writeln!(
+        out,
+        "cache        hit ratio {:<10} cached reads {}   log reads {}   value threshold {}",
+        hit_ratio,
+        stats.cache_reads,
+        stats.log_reads,
+        bytes(stats.value_threshold)
+    );
+    let _ = writeln!(
+        out,
+        "backlog      commit queue {}   write buffer {}   mirror pending {} (lag {})",
+        stats.commit_queue_depth,
+        bytes(stats.write_buffer_bytes),
+        stats.mirror_pending,
+        duration(stats.mirror_lag_ns)
+    );
+    let _ = writeln!(
+        out,
+        "memory       index {}   value cache {}   transactions {}\n",
+        bytes(stats.index_bytes),
+        bytes(stats.value_cache_bytes),
+        bytes(stats.transaction_bytes)
+    );
+
+    let _ = writeln!(
+        out,
+        "{:<20}{:>10}{:>10}{:>10}{:>10}{:>10}",
+        "", "count", "p50", "p90", "p99", "max"
+    );
+    let row = |out: &mut String, name: &str, p: &Percentiles, f: fn(u64) -> String| {
+        let _ = writeln!(
+            out,
+            "{:<20}{:>10}{:>10}{:>10}{:>10}{:>10}",
+            name,
+            p.count,
+            f(p.p50),
+            f(p.p90),
+            f(p.p99),
+            f(p.max)
+        );
+    };
+    row(
+        &mut out,
+        "commit queue time",
+        &stats.commit_queue_ns,
+        duration,
+    );
+    row(&mut out, "fsync time", &stats.commit_fsync_ns, duration);
+    row(
+        &mut out,
+        "entries per commit",
+        &stats.commit_batch_entries,
+        |n| n.to_string(),
+    );
+    out
+}
+
+fn duration(ns: u64) -> String {
+    match ns {
+        0..=999 => format!("{}ns", ns),
+        1_000..=999_999 => format!("{:.1}us", ns as f64 / 1e3),
+        1_000_000..=999_999_999 => format!("{:.1}ms", ns as f64 / 1e6),
+        _ => format!("{:.1}s", ns as f64 / 1e9),
+    }
+}
+
+fn bytes(n: u64) -> String {
+    match n {
+        0..=1023 => format!("{}B", n),
+        1024..=1_048_575 => format!("{:.1}KiB", n as f64 / 1024.0),
+        1_048_576..=1_073_741_823 => format!("{:.1}MiB", n as f64 / 1_048_576.0),
+        _ => format!("{:.1}GiB", n as f64 / 1_073_741_824.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use surrealkv::StoreStats;
+
+    use super::render;
+
+    #[test]
+    fn rates_between_frames() {
+        let mut before = StoreStats {
+            uptime_ms: 1000,
+            cache_reads: 10,
+            log_reads: 10,
+            ..Default::default()
+        };
+        before.commit_batch_entries.count = 100;
+        let mut now = StoreStats {
+            uptime_ms: 3000,
+            cache_reads: 100,
+            log_reads: 20,
+            ..Default::default()
+        };
+        now.commit_batch_entries.count = 500;
+
+        let frame = render(Path::new("db"), "attached", &now, Some(&before));
+        assert!(frame.contains("commits/s 200.0"));
+        assert!(frame.contains("hit ratio 90.0%"));
+
+        // The first frame reports the rates over the lifetime of the store.
+        let frame = render(Path::new("db"), "attached", &now, None);
+        assert!(frame.contains("commits/s 166.7"));
+        assert!(frame.contains("hit ratio 83.3%"));
+    }
+}
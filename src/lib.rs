@@ -1,6 +1,47 @@
 pub mod storage;
 
+pub use storage::kv::active::TransactionInfo;
+#[cfg(feature = "tokio")]
+pub use storage::kv::async_store::{AsyncStore, AsyncTransaction};
+pub use storage::kv::audit::WriteValidator;
+pub use storage::kv::batch::WriteBatch;
+pub use storage::kv::clock::{ExpiryClock, SharedExpiryClock, TimestampSource, WallClock};
+pub use storage::kv::compaction::{
+    CompactionDecision, CompactionFilter, CompactionProgress, ProgressCallback,
+};
+pub use storage::kv::compression::{CompressionFormat, CompressionRule};
+pub use storage::kv::conflict::{ConflictPolicy, ConflictResolver};
+pub use storage::kv::consistency::CommitToken;
+pub use storage::kv::cursor::Cursor;
+pub use storage::kv::durable::DurabilityCallback;
 pub use storage::kv::error::{Error, Result};
-pub use storage::kv::option::{IsolationLevel, Options};
+pub use storage::kv::events;
+#[cfg(feature = "arrow")]
+pub use storage::kv::export::ArrowExport;
+pub use storage::kv::fsync::FsyncHook;
+pub use storage::kv::gc::{DiskStats, GcEstimate, SegmentUsage};
+pub use storage::kv::handle::{ReadHandle, WriteHandle};
+pub use storage::kv::ingest::IngestBuffer;
+pub use storage::kv::invariant::{InvariantHook, InvariantViolation};
+pub use storage::kv::iterator::ScanIterator;
+pub use storage::kv::jobs::{JobKind, JobPriorities};
+pub use storage::kv::keyspace::Keyspace;
+pub use storage::kv::lock::LockToken;
+pub use storage::kv::merge::MergeOperator;
+pub use storage::kv::mirror::{MirrorBatch, MirrorSink, MirrorTarget, Mutation};
+pub use storage::kv::nested::ChildTransaction;
+pub use storage::kv::option::{
+    FsyncFailurePolicy, InvariantPolicy, IsolationLevel, MissingSegments, Options,
+    SegmentRedundancy, VersionRetention,
+};
+pub use storage::kv::queue::Claim;
+pub use storage::kv::quota::QuotaHook;
+pub use storage::kv::stats::{Percentiles, StoreStats};
 pub use storage::kv::store::Store;
-pub use storage::kv::transaction::{Durability, Transaction};
+pub use storage::kv::transaction::{
+    Durability, PageToken, PreparedTransaction, ReadOptions, Transaction,
+};
+pub use storage::kv::view::ReadView;
+pub use storage::kv::watch::{Operation, Watch, WatchFilter};
+pub use storage::log::record::{RecordFlags, RECORD_HEADER_VERSION};
+pub use storage::vfs;
@@ -0,0 +1,150 @@
+use std::backtrace::Backtrace;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use hashbrown::HashMap;
+use parking_lot::Mutex;
+
+use crate::storage::kv::transaction::Mode;
+
+/// A description of an open transaction, returned by
+/// [`Store::active_transactions`](crate::Store::active_transactions).
+#[derive(Clone, Debug)]
+pub struct TransactionInfo {
+    pub id: u64,       // Identifier of the transaction, in the order transactions began.
+    pub mode: Mode,    // Mode of the transaction.
+    pub read_ts: u64,  // Timestamp of the versions the transaction reads.
+    pub age: Duration, // Time since the transaction began.
+    pub backtrace: Option<String>, // Where the transaction began, if `Options::capture_backtraces` is set in a debug build.
+}
+
+/// What is recorded about an open transaction.
+struct Registration {
+    mode: Mode,
+    read_ts: u64,
+    started_at: Instant,
+    backtrace: Option<Arc<Backtrace>>,
+}
+
+/// The number of shards of the registrations, so that transactions beginning on different
+/// threads rarely contend for the same lock.
+const SHARDS: usize = 16;
+
+/// `ActiveTransactions` tracks the open transactions, so that the ones keeping old versions
+/// alive (in snapshots and in the conflict detection state of the oracle) can be found.
+pub(crate) struct ActiveTransactions {
+    capture_backtraces: bool,
+    next_id: AtomicU64,
+    registrations: Box<[Mutex<HashMap<u64, Registration>>]>,
+}
+
+impl ActiveTransactions {
+    pub(crate) fn new(capture_backtraces: bool) -> Self {
+        Self {
+            capture_backtraces,
+            next_id: AtomicU64::new(1),
+            registrations: (0..SHARDS).map(|_| Mutex::new(HashMap::new())).collect(),
+        }
+    }
+
+    fn shard(&self, id: u64) -> &Mutex<HashMap<u64, Registration>> {
+        &self.registrations[id as usize % SHARDS]
+    }
+
+    /// Registers a transaction that began, and returns its identifier.
+    pub(crate) fn register(&self, mode: Mode, read_ts: u64) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+        // Capturing a backtrace is costly, so it is only done in debug builds when asked for.
+        // It is only resolved to symbols when the transactions are listed.
+        let backtrace = (cfg!(debug_assertions) && self.capture_backtraces)
+            .then(|| Arc::new(Backtrace::force_capture()));
+
+        self.shard(id).lock().insert(
+            id,
+            Registration {
+                mode,
+                read_ts,
+                started_at: Instant::now(),
+                backtrace,
+            },
+        );
+        id
+    }
+
+    /// Unregisters a transaction that was closed.
+    pub(crate) fn unregister(&self, id: u64) {
+        self.shard(id).lock().remove(&id);
+    }
+
+    /// Returns the open transactions, oldest read timestamp first.
+    pub(crate) fn list(&self) -> Vec<TransactionInfo> {
+        let now = Instant::now();
+        let mut infos = Vec::new();
+        for shard in self.registrations.iter() {
+            infos.extend(shard.lock().iter().map(|(id, r)| TransactionInfo {
+                id: *id,
+                mode: r.mode,
+                read_ts: r.read_ts,
+                age: now.saturating_duration_since(r.started_at),
+                backtrace: r.backtrace.as_ref().map(|b| b.to_string()),
+            }));
+        }
+        infos.sort_by_key(|info| (info.read_ts, info.id));
+        infos
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+    use crate::storage::kv::transaction::Mode;
+
+    use tempdir::TempDir;
+
+    #[tokio::test]
+    async fn active_transactions() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        let store = Store::new(opts).expect("should create store");
+        assert!(store.active_transactions().is_empty());
+
+        let leaked = store.begin_with_mode(Mode::ReadOnly).unwrap();
+        let mut txn = store.begin().unwrap();
+        txn.set(b"k", b"v").unwrap();
+        txn.commit().await.unwrap();
+
+        // Committed transactions are no longer active.
+        let active = store.active_transactions();
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].mode, Mode::ReadOnly);
+
+        let newer = store.begin().unwrap();
+        let active = store.active_transactions();
+        assert_eq!(active.len(), 2);
+        assert!(active[0].read_ts < active[1].read_ts);
+        assert!(active[0].age >= active[1].age);
+        assert_eq!(active[0].id, store.oldest_transaction().unwrap().id);
+
+        drop(leaked);
+        drop(newer);
+        assert!(store.active_transactions().is_empty());
+        assert!(store.oldest_transaction().is_none());
+    }
+
+    #[tokio::test]
+    async fn capture_backtraces() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.capture_backtraces = true;
+        let store = Store::new(opts).expect("should create store");
+
+        let _txn = store.begin().unwrap();
+        let active = store.active_transactions();
+        assert_eq!(active[0].backtrace.is_some(), cfg!(debug_assertions));
+    }
+}
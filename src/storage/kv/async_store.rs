@@ -0,0 +1,451 @@
+use std::collections::VecDeque;
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+
+use futures::{stream, Stream};
+use tokio::runtime::Handle;
+use tokio::sync::oneshot::{self, error::TryRecvError};
+use tokio::task::spawn_blocking;
+
+use crate::storage::kv::{
+    consistency::CommitToken,
+    error::{Error, Result},
+    iterator::ScanIterator,
+    option::Options,
+    store::Store,
+    transaction::{Durability, Mode, ScanResult, Transaction},
+};
+
+/// Number of entries a stream reads on the blocking thread pool at a time.
+const STREAM_BATCH_SIZE: usize = 128;
+
+/// A store whose blocking work runs on the blocking thread pool of tokio, for embedding the
+/// store in an async server. Opening the store, which replays its commit log, is run on the
+/// pool, as are the reads and commits of its [`AsyncTransaction`]s.
+///
+/// The store used underneath is returned by [`store`](AsyncStore::store), for the methods
+/// that are not wrapped.
+#[derive(Clone)]
+pub struct AsyncStore {
+    store: Arc<Store>,
+}
+
+impl AsyncStore {
+    /// Opens the store with the given options, on the blocking thread pool.
+    pub async fn open(opts: Options) -> Result<Self> {
+        let store = run_blocking(move || Store::new(opts)).await?;
+        Ok(Self::from_store(store))
+    }
+
+    /// Returns an async store wrapping `store`.
+    pub fn from_store(store: Store) -> Self {
+        Self {
+            store: Arc::new(store),
+        }
+    }
+
+    /// Returns the store used underneath.
+    pub fn store(&self) -> &Arc<Store> {
+        &self.store
+    }
+
+    /// Begins a new read-write transaction.
+    pub fn begin(&self) -> Result<AsyncTransaction> {
+        self.begin_with_mode(Mode::ReadWrite)
+    }
+
+    /// Begins a new transaction with the given mode.
+    pub fn begin_with_mode(&self, mode: Mode) -> Result<AsyncTransaction> {
+        Ok(AsyncTransaction {
+            txn: Some(self.store.begin_with_mode(mode)?),
+            pending: None,
+            committed: None,
+        })
+    }
+
+    /// Returns a stream of the live entries of a range, in key order, read from a snapshot
+    /// as with [`Store::iter`]. The entries are read in batches on the blocking thread pool,
+    /// and the next batch is only read once the entries of the previous one are consumed, so
+    /// that a slow consumer holds back the scan rather than letting entries pile up.
+    pub fn scan_stream<'a, R>(&self, range: R) -> Result<impl Stream<Item = Result<ScanResult>>>
+    where
+        R: RangeBounds<&'a [u8]>,
+    {
+        let iter = self.store.iter(range)?;
+        let state = (Some(iter), VecDeque::new());
+        Ok(stream::unfold(state, |(iter, mut buffer)| async move {
+            if let Some(entry) = buffer.pop_front() {
+                return Some((entry, (iter, buffer)));
+            }
+            let iter = iter?;
+            let batch = run_blocking(move || Ok(next_batch(iter))).await;
+            let (iter, mut buffer) = match batch {
+                Ok(batch) => batch,
+                Err(e) => return Some((Err(e), (None, buffer))),
+            };
+            buffer.pop_front().map(|entry| (entry, (iter, buffer)))
+        }))
+    }
+
+    /// Closes the store, waiting for the commits queued for the writer.
+    pub async fn close(&self) -> Result<()> {
+        self.store.close().await
+    }
+}
+
+/// A transaction of an [`AsyncStore`]. The writes are buffered in memory, as with
+/// [`Transaction`], and return immediately. The reads, which may read the values from the
+/// commit log, and the commit, which validates and compresses the writes, run on the blocking
+/// thread pool.
+///
+/// The transaction is moved to the pool while a read or the commit runs, and the operations
+/// are cancellation safe: if the future of one is dropped before it completes, the operation
+/// runs to completion on the pool and the transaction is handed back to this one. After a
+/// dropped read, the transaction is left as it was, and the next async call waits for the
+/// read to finish, while `set` and `delete` return `Error::TransactionBusy` until it has.
+/// A dropped commit is committed or fails as a whole, and the next call to `commit` returns
+/// its outcome; the transaction is closed either way.
+pub struct AsyncTransaction {
+    txn: Option<Transaction>,
+    // Hands the transaction back from the operation running on the pool, with the outcome
+    // of the commit if the operation is one.
+    pending: Option<oneshot::Receiver<Returned>>,
+    // The outcome of a commit whose future was dropped, returned by the next `commit`.
+    committed: Option<Result<()>>,
+}
+
+type Returned = (Transaction, Option<Result<()>>);
+
+impl AsyncTransaction {
+    /// Sets the durability level of the commit.
+    pub fn set_durability(&mut self, durability: Durability) -> Result<()> {
+        self.txn()?.set_durability(durability);
+        Ok(())
+    }
+
+    /// Adds a key-value pair to the transaction.
+    pub fn set(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.txn()?.set(key, value)
+    }
+
+    /// Adds the deletion of a key to the transaction.
+    pub fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.txn()?.delete(key)
+    }
+
+    /// Gets the value of a key, if it exists.
+    pub async fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let key = key.to_vec();
+        self.run(move |txn| txn.get(&key)).await
+    }
+
+    /// Gets the values of several keys, as [`Transaction::get_many`] does.
+    pub async fn get_many<K: AsRef<[u8]>>(&mut self, keys: &[K]) -> Result<Vec<Option<Vec<u8>>>> {
+        let keys: Vec<Vec<u8>> = keys.iter().map(|key| key.as_ref().to_vec()).collect();
+        self.run(move |txn| txn.get_many(&keys)).await
+    }
+
+    /// Scans a range of keys, as [`Transaction::scan`] does.
+    pub async fn scan<'b, R>(&mut self, range: R, limit: Option<usize>) -> Result<Vec<ScanResult>>
+    where
+        R: RangeBounds<&'b [u8]>,
+    {
+        let range = owned_range(&range);
+        self.run(move |txn| txn.scan(as_slices(&range), limit))
+            .await
+    }
+
+    /// Scans the keys of a range, as [`Transaction::keys`] does.
+    pub async fn keys<'b, R>(&mut self, range: R, limit: Option<usize>) -> Result<Vec<Vec<u8>>>
+    where
+        R: RangeBounds<&'b [u8]>,
+    {
+        let range = owned_range(&range);
+        self.run(move |txn| txn.keys(as_slices(&range), limit))
+            .await
+    }
+
+    /// Commits the transaction. If the future of a previous call was dropped, this returns
+    /// the outcome of that commit once it is known.
+    pub async fn commit(&mut self) -> Result<()> {
+        self.recover().await;
+        if let Some(committed) = self.committed.take() {
+            return committed;
+        }
+        let handle = Handle::current();
+        let result = self
+            .spawn(move |txn| {
+                let result = handle.block_on(txn.commit());
+                (result.clone(), Some(result))
+            })
+            .await;
+        self.committed = None;
+        result
+    }
+
+    /// Returns the token of the commit of the transaction, as [`Transaction::commit_token`]
+    /// does.
+    pub fn commit_token(&self) -> Option<CommitToken> {
+        self.txn.as_ref().and_then(Transaction::commit_token)
+    }
+
+    /// Rolls the transaction back. A transaction still used by an operation whose future was
+    /// dropped is rolled back once the operation is done.
+    pub fn rollback(&mut self) {
+        self.pending = None;
+        if let Some(mut txn) = self.txn.take() {
+            txn.rollback();
+        }
+    }
+
+    /// Returns the transaction used underneath.
+    pub fn into_inner(mut self) -> Result<Transaction> {
+        self.try_recover()?;
+        self.txn.take().ok_or(Error::TransactionClosed)
+    }
+
+    fn txn(&mut self) -> Result<&mut Transaction> {
+        self.try_recover()?;
+        self.txn.as_mut().ok_or(Error::TransactionClosed)
+    }
+
+    /// Runs `f` with the transaction on the blocking thread pool.
+    async fn run<T, F>(&mut self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Transaction) -> Result<T> + Send + 'static,
+    {
+        self.recover().await;
+        self.spawn(move |txn| (f(txn), None)).await
+    }
+
+    /// Runs `f` with the transaction on the blocking thread pool, which hands the transaction
+    /// back with the commit outcome `f` returns, if any, even if this future is dropped.
+    async fn spawn<T, F>(&mut self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Transaction) -> (Result<T>, Option<Result<()>>) + Send + 'static,
+    {
+        let mut txn = self.txn.take().ok_or(Error::TransactionClosed)?;
+        let (tx, rx) = oneshot::channel();
+        self.pending = Some(rx);
+        let result = run_blocking(move || {
+            let (result, committed) = f(&mut txn);
+            let _ = tx.send((txn, committed));
+            Ok(result)
+        })
+        .await;
+        self.recover().await;
+        result?
+    }
+
+    /// Waits for the operation running on the blocking thread pool, if any, to hand the
+    /// transaction back. The transaction is lost if the operation panicked.
+    async fn recover(&mut self) {
+        if let Some(pending) = &mut self.pending {
+            let returned = pending.await;
+            self.pending = None;
+            if let Ok(returned) = returned {
+                self.returned(returned);
+            }
+        }
+    }
+
+    /// Takes the transaction back from the operation running on the blocking thread pool, if
+    /// any, or returns `Error::TransactionBusy` if it is not done yet.
+    fn try_recover(&mut self) -> Result<()> {
+        if let Some(pending) = &mut self.pending {
+            match pending.try_recv() {
+                Ok(returned) => self.returned(returned),
+                Err(TryRecvError::Empty) => return Err(Error::TransactionBusy),
+                Err(TryRecvError::Closed) => {}
+            }
+            self.pending = None;
+        }
+        Ok(())
+    }
+
+    fn returned(&mut self, (txn, committed): Returned) {
+        self.txn = Some(txn);
+        if committed.is_some() {
+            self.committed = committed;
+        }
+    }
+}
+
+/// Reads the next entries of a stream. The iterator is dropped once it is exhausted or an
+/// entry fails.
+fn next_batch(mut iter: ScanIterator) -> (Option<ScanIterator>, VecDeque<Result<ScanResult>>) {
+    let mut batch = VecDeque::with_capacity(STREAM_BATCH_SIZE);
+    while batch.len() < STREAM_BATCH_SIZE {
+        match iter.next() {
+            Some(Ok(entry)) => batch.push_back(Ok(entry)),
+            Some(Err(e)) => {
+                batch.push_back(Err(e));
+                return (None, batch);
+            }
+            None => return (None, batch),
+        }
+    }
+    (Some(iter), batch)
+}
+
+/// Runs `f` on the blocking thread pool.
+async fn run_blocking<T, F>(f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    spawn_blocking(f).await.map_err(|e| {
+        Error::ReceiveError(format!(
+            "Error occurred while running on the blocking thread pool. JoinError: {}",
+            e
+        ))
+    })?
+}
+
+fn owned_range<'b, R>(range: &R) -> (Bound<Vec<u8>>, Bound<Vec<u8>>)
+where
+    R: RangeBounds<&'b [u8]>,
+{
+    let owned = |bound: Bound<&&[u8]>| match bound {
+        Bound::Included(key) => Bound::Included(key.to_vec()),
+        Bound::Excluded(key) => Bound::Excluded(key.to_vec()),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    (owned(range.start_bound()), owned(range.end_bound()))
+}
+
+fn as_slices(range: &(Bound<Vec<u8>>, Bound<Vec<u8>>)) -> (Bound<&[u8]>, Bound<&[u8]>) {
+    fn as_slice(bound: &Bound<Vec<u8>>) -> Bound<&[u8]> {
+        match bound {
+            Bound::Included(key) => Bound::Included(key),
+            Bound::Excluded(key) => Bound::Excluded(key),
+            Bound::Unbounded => Bound::Unbounded,
+        }
+    }
+    (as_slice(&range.0), as_slice(&range.1))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{FutureExt, StreamExt};
+
+    use super::AsyncStore;
+    use crate::storage::kv::error::Error;
+    use crate::storage::kv::option::Options;
+
+    use tempdir::TempDir;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn transactions_run_on_the_blocking_pool() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        let store = AsyncStore::open(opts.clone()).await.unwrap();
+
+        let mut txn = store.begin().unwrap();
+        txn.set(b"k1", b"v1").unwrap();
+        txn.set(b"k2", b"v2").unwrap();
+        assert_eq!(txn.get(b"k1").await.unwrap().unwrap(), b"v1");
+        txn.commit().await.unwrap();
+        assert!(matches!(
+            txn.get(b"k1").await,
+            Err(Error::TransactionClosed)
+        ));
+
+        let mut txn = store.begin().unwrap();
+        txn.delete(b"k1").unwrap();
+        assert_eq!(txn.keys(.., None).await.unwrap(), [b"k2".to_vec()]);
+        txn.commit().await.unwrap();
+        store.close().await.unwrap();
+
+        let store = AsyncStore::open(opts).await.unwrap();
+        let mut txn = store.begin().unwrap();
+        let entries = txn.scan(.., None).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].1, b"v2");
+        drop(txn);
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn dropped_operations_leave_the_transaction_usable() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        let store = AsyncStore::open(opts).await.unwrap();
+
+        let mut txn = store.begin().unwrap();
+        txn.set(b"k1", b"v1").unwrap();
+        // A read dropped after it started hands the transaction back once it is done.
+        let _ = txn.get(b"k1").now_or_never();
+        assert_eq!(txn.get(b"k1").await.unwrap().unwrap(), b"v1");
+        txn.set(b"k2", b"v2").unwrap();
+
+        // A commit dropped after it started runs to completion, and its outcome is
+        // returned by the next commit, unless it completed before it was dropped.
+        match txn.commit().now_or_never() {
+            Some(committed) => committed.unwrap(),
+            None => txn.commit().await.unwrap(),
+        }
+        assert!(matches!(
+            txn.get(b"k1").await,
+            Err(Error::TransactionClosed)
+        ));
+        assert!(matches!(txn.commit().await, Err(Error::TransactionClosed)));
+
+        let mut txn = store.begin().unwrap();
+        assert_eq!(txn.get(b"k2").await.unwrap().unwrap(), b"v2");
+
+        // A commit failing after its future was dropped reports the failure.
+        let mut first = store.begin().unwrap();
+        first.get(b"k1").await.unwrap();
+        first.set(b"k1", b"first").unwrap();
+        txn.set(b"k1", b"second").unwrap();
+        txn.commit().await.unwrap();
+        let committed = match first.commit().now_or_never() {
+            Some(committed) => committed,
+            None => first.commit().await,
+        };
+        assert!(matches!(committed, Err(Error::TransactionReadConflict)));
+
+        let mut txn = store.begin().unwrap();
+        assert_eq!(txn.get(b"k1").await.unwrap().unwrap(), b"second");
+        drop(txn);
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn scan_stream() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        let store = AsyncStore::open(opts).await.unwrap();
+
+        let mut txn = store.begin().unwrap();
+        for i in 0..300u32 {
+            txn.set(&i.to_be_bytes(), b"v").unwrap();
+        }
+        txn.commit().await.unwrap();
+
+        let stream = store.scan_stream(..).unwrap();
+        futures::pin_mut!(stream);
+        // The entries written after the stream is created are not seen by it.
+        let mut txn = store.begin().unwrap();
+        txn.set(&1000u32.to_be_bytes(), b"v").unwrap();
+        txn.commit().await.unwrap();
+
+        let mut keys = Vec::new();
+        while let Some(entry) = stream.next().await {
+            keys.push(entry.unwrap().0);
+        }
+        let expected: Vec<Vec<u8>> = (0..300u32).map(|i| i.to_be_bytes().to_vec()).collect();
+        assert_eq!(keys, expected);
+
+        let start = 250u32.to_be_bytes();
+        let stream = store.scan_stream(&start[..]..).unwrap();
+        assert_eq!(stream.count().await, 51);
+        store.close().await.unwrap();
+    }
+}
@@ -0,0 +1,132 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+
+use crate::storage::kv::{
+    entry::Entry,
+    error::Result,
+    events::{self, Level, Value},
+    store::{Core, Store},
+    util::is_system_key,
+};
+
+/// Validator of the writes sampled by `Options::write_audit_interval`, with the key and the
+/// value written. The writes it fails are counted in `StoreStats::write_audit_failures` and
+/// reported, and committed all the same.
+pub type WriteValidator = Arc<dyn Fn(&[u8], &[u8]) -> Result<()> + Send + Sync>;
+
+/// `WriteAudit` validates a sample of the writes committed, such as to detect the keys of a
+/// shared store that are not encoded as its users expect before they break their scans.
+///
+/// One in `Options::write_audit_interval` values written is validated, as the transaction
+/// commits and before its values are compressed: the validator runs on the committing task,
+/// and should be cheap. The deletions, the merge operands and the other entries that write
+/// no value are not sampled, nor are the system keys.
+#[derive(Default)]
+pub(crate) struct WriteAudit {
+    validator: RwLock<Option<WriteValidator>>,
+    writes: AtomicU64, // Number of values written since the store was opened.
+}
+
+impl WriteAudit {
+    pub(crate) fn set_validator(&self, validator: Option<WriteValidator>) {
+        *self.validator.write() = validator;
+    }
+
+    pub(crate) fn validator(&self) -> Option<WriteValidator> {
+        self.validator.read().clone()
+    }
+
+    /// Validates the sampled writes among the entries of a transaction committing.
+    pub(crate) fn check(&self, core: &Core, entries: &[Entry]) {
+        let interval = core.opts.write_audit_interval;
+        if interval == 0 {
+            return;
+        }
+        let Some(validator) = self.validator() else {
+            return;
+        };
+        let stats = &core.stats;
+        for entry in entries.iter().filter(|entry| writes_value(entry)) {
+            let writes = self.writes.fetch_add(1, Ordering::Relaxed) + 1;
+            if writes % interval != 0 {
+                continue;
+            }
+            stats.write_audits.fetch_add(1, Ordering::Relaxed);
+            let Err(err) = validator(&entry.key, &entry.value) else {
+                continue;
+            };
+            stats.write_audit_failures.fetch_add(1, Ordering::Relaxed);
+            let key = String::from_utf8_lossy(&entry.key);
+            let error = err.to_string();
+            events::emit(
+                events::AUDIT,
+                Level::Warn,
+                "write failed its audit",
+                &[("key", Value::Str(&key)), ("error", Value::Str(&error))],
+            );
+        }
+    }
+}
+
+/// Returns true if an entry writes a value of a user key.
+fn writes_value(entry: &Entry) -> bool {
+    !(entry.is_deleted()
+        || entry.is_merge()
+        || entry.is_touch()
+        || entry.is_rename()
+        || entry.is_range_delete()
+        || entry.is_clear()
+        || is_system_key(&entry.key))
+}
+
+impl Store {
+    /// Sets the validator of the writes sampled by `Options::write_audit_interval`, see
+    /// [`WriteValidator`], or removes it with None.
+    pub fn set_write_validator(&self, validator: Option<WriteValidator>) {
+        let core = &self.inner.as_ref().unwrap().core;
+        core.write_audit.set_validator(validator);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::storage::kv::error::Error;
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+
+    use tempdir::TempDir;
+
+    #[tokio::test]
+    async fn sampled_writes_are_audited() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.write_audit_interval = 2;
+        let store = Store::new(opts).expect("should create store");
+        // The keys are expected to be 4 bytes long.
+        store.set_write_validator(Some(Arc::new(|key: &[u8], _: &[u8]| match key.len() {
+            4 => Ok(()),
+            _ => Err(Error::AuditFailed("key is not a u32".to_string())),
+        })));
+
+        let mut txn = store.begin().unwrap();
+        for key in [&b"key1"[..], b"bad1", b"key3", b"bad", b"k5", b"bad!"] {
+            txn.set(key, b"v").unwrap();
+        }
+        txn.delete(b"x").unwrap();
+        txn.commit().await.unwrap();
+
+        // Every second value is validated, and the failed ones are still committed.
+        let stats = store.stats();
+        assert_eq!(stats.write_audits, 3);
+        assert_eq!(stats.write_audit_failures, 1);
+        let txn = store.begin().unwrap();
+        assert_eq!(txn.get(b"bad").unwrap(), Some(b"v".to_vec()));
+        drop(txn);
+        store.close().await.unwrap();
+    }
+}
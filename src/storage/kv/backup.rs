@@ -0,0 +1,489 @@
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::storage::{
+    kv::{
+        compaction,
+        entry::{Entry, TxRecord},
+        error::{Error, Result},
+        events::{self, Activity, Value},
+        meta::Metadata as KvMetadata,
+        option::Options,
+        reader::{Reader, TxReader},
+        store::{Core, Store},
+        transaction::Durability,
+        util::calculate_crc32,
+    },
+    log::{read_field, write_field, Metadata, MultiSegmentReader, SegmentRef, BLOCK_SIZE},
+};
+
+/// Bytes a backup file starts with.
+const BACKUP_MAGIC: &[u8; 8] = b"SKVBAK01";
+
+const META_KEY_SINCE: &str = "since";
+const META_KEY_UNTIL: &str = "until";
+const META_KEY_MANIFESTS: &str = "manifests";
+
+// A backup holds the transaction records of the commit log whose id (the version of their
+// entries) is in `since + 1..=until`, in commit order. A full backup starts after version 0;
+// an incremental backup starts after the `until` marker of the previous backup, and can only
+// be restored on top of it. Restoring a backup replays its transactions with their original
+// versions and commit timestamps, so the history of the keys is kept.
+//
+// Backup file format:
+//
+//   |-------|---------------|------------|-----|------------|---|
+//   | magic | header_len(4) |   header   | ... record_len(4) | record | ... | 0(4) |
+//   |-------|---------------|------------|-----|------------|---|
+//
+// The header holds `since`, `until` and the options the store has been opened with over its
+// lifetime, which the restored store is created with. A record is encoded as:
+//
+//   | id(8) | ts(8) | num_entries(4) | entries: [md_len(4) | md | key_len(4) | key | value_len(4) | value] | crc32(4) |
+
+/// Writes the transactions of `core` committed after version `since` to `path`, and returns
+/// the version the backup goes up to, which is the marker of the next incremental backup.
+pub(crate) async fn backup(core: &Core, path: &Path, since: u64) -> Result<u64> {
+    let activity = Activity::start(events::BACKUP, "backup", &[("since", Value::U64(since))]);
+    let until = write_backup(core, path, since).await;
+    activity.finish(until, |until| vec![("until", *until)])
+}
+
+async fn write_backup(core: &Core, path: &Path, since: u64) -> Result<u64> {
+    if !core.opts.should_persist_data() {
+        return Err(Error::InvalidOptions(
+            "a store without disk persistence cannot be backed up".to_string(),
+        ));
+    }
+
+    // Commits are held while the point is taken, so that the transactions up to it are all
+    // in the commit log, which is made durable.
+    let until = {
+        let _commits = core.oracle.write_lock.lock().await;
+        core.clog.as_ref().unwrap().read().sync()?;
+        core.indexer.read().version()
+    };
+    if since > until {
+        return Err(Error::InvalidBackup(format!(
+            "the store has no version {} to back up from",
+            since
+        )));
+    }
+
+    let mut header = Metadata::new(None);
+    header.put_uint(META_KEY_SINCE, since);
+    header.put_uint(META_KEY_UNTIL, until);
+    // The options of the open store may not be flushed to the manifest yet.
+    let mut manifests = Core::load_manifests(&core.opts)?;
+    let current = core.opts.to_metadata();
+    if manifests.last() != Some(&current) {
+        manifests.push(current);
+    }
+    header.put_uint(META_KEY_MANIFESTS, manifests.len() as u64);
+    for (i, manifest) in manifests.iter().enumerate() {
+        header.put(&manifest_key(i), &manifest.to_bytes()?);
+    }
+
+    // The backup is written next to its destination and moved in place once complete.
+    let tmp = path.with_extension("tmp");
+    let mut out = BufWriter::new(File::create(&tmp)?);
+    out.write_all(BACKUP_MAGIC)?;
+    write_field(&header.to_bytes()?, &mut out)?;
+    if until > since {
+        for_each_record(&core.opts, |tx| {
+            // The markers of a compaction are not replayed, its records are.
+            if compaction::marker(tx).is_some() {
+                return Ok(true);
+            }
+            if tx.header.id > since {
+                write_field(&encode_record(tx), &mut out)?;
+            }
+            Ok(tx.header.id < until)
+        })?;
+    }
+    write_field(&[], &mut out)?;
+    out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    fs::rename(&tmp, path)?;
+
+    Ok(until)
+}
+
+/// Restores the backup in `path` into the store in `dir`, and returns the version the store
+/// is restored up to. A full backup is restored into a new store, an incremental backup on
+/// top of the store restored up to its start.
+pub(crate) async fn restore(path: &Path, dir: &Path) -> Result<u64> {
+    let activity = Activity::start(events::BACKUP, "restore", &[]);
+    let restored = restore_backup(path, dir).await;
+    activity.finish(restored, |version| vec![("version", *version)])
+}
+
+async fn restore_backup(path: &Path, dir: &Path) -> Result<u64> {
+    let mut input = BufReader::new(File::open(path)?);
+    let (since, until, manifests) = read_header(&mut input)?;
+    let mut manifests = manifests
+        .into_iter()
+        .map(|manifest| Options::from_metadata(manifest, dir.to_path_buf()))
+        .collect::<Result<Vec<_>>>()?;
+    let opts = manifests
+        .pop()
+        .ok_or_else(|| Error::InvalidBackup("the backup holds no options".to_string()))?;
+
+    let exists = Store::persisted_options(dir)?.is_some();
+    if since == 0 && exists {
+        return Err(Error::InvalidBackup(format!(
+            "a full backup is restored into a new store, and {} holds one",
+            dir.display()
+        )));
+    }
+    if since > 0 && !exists {
+        return Err(Error::InvalidBackup(format!(
+            "an incremental backup is restored into the store it follows, and {} holds none",
+            dir.display()
+        )));
+    }
+
+    // The restored store is opened with the options of the backed up store in turn, so that
+    // it keeps the compression rules the values may have been written with.
+    if !exists {
+        for opts in manifests {
+            Store::new(opts)?.close().await?;
+        }
+    }
+
+    let store = Store::new(opts)?;
+    let restored = async {
+        let core = &store.inner.as_ref().unwrap().core;
+        let version = core.indexer.read().version();
+        if version != since {
+            return Err(Error::InvalidBackup(format!(
+                "the backup starts after version {}, and the store is at version {}",
+                since, version
+            )));
+        }
+
+        loop {
+            let record = read_field(&mut input)?;
+            if record.is_empty() {
+                break;
+            }
+            let (tx_id, commit_ts, entries) = decode_record(&record)?;
+            let durability = if tx_id == until {
+                Durability::Immediate
+            } else {
+                Durability::Eventual
+            };
+            let done = core
+                .send_to_write_channel(entries, tx_id, commit_ts, durability)
+                .await?;
+            done.recv().await??;
+        }
+        Ok(core.indexer.read().version())
+    }
+    .await;
+    store.close().await?;
+
+    let restored = restored?;
+    if restored != until {
+        return Err(Error::InvalidBackup(format!(
+            "the backup goes up to version {} but holds transactions up to version {}",
+            until, restored
+        )));
+    }
+    Ok(restored)
+}
+
+/// Checks the backups in `paths` without restoring them, and returns the version they go up
+/// to. The backups are checked as the chain they are restored in: each one must start at the
+/// version the previous one goes up to, and hold its options followed by the ones the store
+/// was opened with since. Every transaction record must match its checksum and follow the
+/// previous one, and the last must be at the version the backup goes up to.
+pub(crate) fn verify(paths: &[&Path]) -> Result<u64> {
+    let activity = Activity::start(
+        events::BACKUP,
+        "verify",
+        &[("backups", Value::U64(paths.len() as u64))],
+    );
+    let verified = verify_backups(paths);
+    activity.finish(verified, |version| vec![("version", *version)])
+}
+
+fn verify_backups(paths: &[&Path]) -> Result<u64> {
+    let mut previous: Option<(u64, Vec<Metadata>)> = None;
+    for path in paths {
+        let invalid =
+            |reason: String| Error::InvalidBackup(format!("{}: {}", path.display(), reason));
+        let mut input = BufReader::new(File::open(path)?);
+        let (since, until, manifests) = read_header(&mut input).map_err(|e| match e {
+            Error::InvalidBackup(reason) => invalid(reason),
+            e => invalid(format!("the header cannot be read: {}", e)),
+        })?;
+        if manifests.is_empty() {
+            return Err(invalid("the backup holds no options".to_string()));
+        }
+        let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        for manifest in &manifests {
+            Options::from_metadata(manifest.clone(), dir.clone())?;
+        }
+        if let Some((version, previous)) = &previous {
+            if since != *version {
+                return Err(invalid(format!(
+                    "the backup starts after version {}, and the previous one goes up to version {}",
+                    since, version
+                )));
+            }
+            if !manifests.starts_with(previous) {
+                return Err(invalid(
+                    "the backup does not hold the options of the previous one".to_string(),
+                ));
+            }
+        }
+
+        let mut last = since;
+        loop {
+            let record = read_field(&mut input)
+                .map_err(|_| invalid(format!("the backup is truncated after version {}", last)))?;
+            if record.is_empty() {
+                break;
+            }
+            let (tx_id, ..) = decode_record(&record).map_err(|_| {
+                invalid(format!(
+                    "the transaction record after version {} is corrupted",
+                    last
+                ))
+            })?;
+            if tx_id <= last || tx_id > until {
+                return Err(invalid(format!(
+                    "the transaction at version {} follows version {} in a backup up to version {}",
+                    tx_id, last, until
+                )));
+            }
+            last = tx_id;
+        }
+        if last != until {
+            return Err(invalid(format!(
+                "the backup goes up to version {} but holds transactions up to version {}",
+                until, last
+            )));
+        }
+        if input.read(&mut [0])? != 0 {
+            return Err(invalid("the backup has bytes after its end".to_string()));
+        }
+        previous = Some((until, manifests));
+    }
+    previous
+        .map(|(version, _)| version)
+        .ok_or_else(|| Error::InvalidBackup("no backup to verify".to_string()))
+}
+
+/// Reads the start of a backup file, and returns the versions it goes from and up to, and the
+/// manifests of the options the store was opened with.
+fn read_header<R: Read>(input: &mut R) -> Result<(u64, u64, Vec<Metadata>)> {
+    let mut magic = [0; BACKUP_MAGIC.len()];
+    input.read_exact(&mut magic)?;
+    if &magic != BACKUP_MAGIC {
+        return Err(Error::InvalidBackup("not a backup file".to_string()));
+    }
+    let mut header = Metadata::new(None);
+    header.read_from(&mut &read_field(input)?[..])?;
+    let since = header.get_uint(META_KEY_SINCE)?;
+    let until = header.get_uint(META_KEY_UNTIL)?;
+    let mut manifests = Vec::new();
+    for i in 0..header.get_uint(META_KEY_MANIFESTS)? as usize {
+        let bytes = header
+            .get(&manifest_key(i))
+            .ok_or(Error::CorruptedMetadata)?;
+        let mut manifest = Metadata::new(None);
+        manifest.read_from(&mut &bytes[..])?;
+        manifests.push(manifest);
+    }
+    Ok((since, until, manifests))
+}
+
+fn manifest_key(i: usize) -> String {
+    format!("{}.{}", META_KEY_MANIFESTS, i)
+}
+
+/// Reads the transaction records of the commit log in order, until `f` returns false.
+fn for_each_record<F>(opts: &Options, mut f: F) -> Result<()>
+where
+    F: FnMut(&TxRecord) -> Result<bool>,
+{
+    let segments = SegmentRef::read_segments_from_directory(&*opts.vfs, &opts.dir.join("clog"))?;
+    let reader = MultiSegmentReader::new(&opts.vfs, segments)?;
+    let reader = Reader::new_from(reader, opts.max_segment_size, BLOCK_SIZE);
+    let mut tx_reader = TxReader::new(reader, opts.max_key_size, opts.max_value_size);
+    let mut tx = TxRecord::new(opts.max_entries_per_txn as usize);
+    loop {
+        tx.reset();
+        tx_reader.read_into(&mut tx)?;
+        if !f(&tx)? {
+            return Ok(());
+        }
+    }
+}
+
+fn encode_record(tx: &TxRecord) -> Bytes {
+    let mut buf = BytesMut::new();
+    buf.put_u64(tx.header.id);
+    buf.put_u64(tx.header.ts);
+    buf.put_u32(tx.entries.len() as u32);
+    for entry in &tx.entries {
+        let metadata = entry
+            .metadata
+            .as_ref()
+            .map_or_else(Bytes::new, |md| md.to_bytes());
+        for field in [&metadata, &entry.key, &entry.value] {
+            buf.put_u32(field.len() as u32);
+            buf.put_slice(field);
+        }
+    }
+    let crc = calculate_crc32(&buf);
+    buf.put_u32(crc);
+    buf.freeze()
+}
+
+fn decode_record(record: &[u8]) -> Result<(u64, u64, Vec<Entry>)> {
+    let corrupted = || Error::InvalidBackup("corrupted transaction record".to_string());
+    if record.len() < 24 {
+        return Err(corrupted());
+    }
+    let (body, crc) = record.split_at(record.len() - 4);
+    if calculate_crc32(body) != u32::from_be_bytes(crc.try_into().unwrap()) {
+        return Err(corrupted());
+    }
+
+    let mut buf = body;
+    let tx_id = buf.get_u64();
+    let commit_ts = buf.get_u64();
+    let num_entries = buf.get_u32();
+    let field = |buf: &mut &[u8]| {
+        if buf.remaining() < 4 {
+            return Err(corrupted());
+        }
+        let len = buf.get_u32() as usize;
+        if buf.remaining() < len {
+            return Err(corrupted());
+        }
+        Ok(buf.copy_to_bytes(len))
+    };
+
+    let mut entries = Vec::with_capacity(num_entries as usize);
+    for _ in 0..num_entries {
+        let metadata = field(&mut buf)?;
+        let key = field(&mut buf)?;
+        let value = field(&mut buf)?;
+        entries.push(Entry {
+            key,
+            metadata: match metadata.is_empty() {
+                true => None,
+                false => Some(KvMetadata::from_bytes(&metadata)?),
+            },
+            value,
+            ts: 0,
+        });
+    }
+    if buf.has_remaining() {
+        return Err(corrupted());
+    }
+    Ok((tx_id, commit_ts, entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::kv::error::Error;
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+
+    use tempdir::TempDir;
+
+    #[tokio::test]
+    async fn backup_and_restore_incrementally() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().join("db");
+        opts.max_value_threshold = 4;
+        let store = Store::new(opts).expect("should create store");
+        for i in 0..50u32 {
+            let mut txn = store.begin().unwrap();
+            txn.set(&i.to_be_bytes(), &[i as u8; 20]).unwrap();
+            txn.commit().await.unwrap();
+        }
+
+        let full = temp_dir.path().join("full.skv");
+        let marker = store.backup(&full, 0).await.unwrap();
+        assert_eq!(marker, 50);
+
+        let mut txn = store.begin().unwrap();
+        txn.delete(&3u32.to_be_bytes()).unwrap();
+        txn.set(b"new", b"value").unwrap();
+        txn.commit().await.unwrap();
+        let incremental = temp_dir.path().join("incremental.skv");
+        assert_eq!(store.backup(&incremental, marker).await.unwrap(), 51);
+        store.close().await.unwrap();
+
+        // The incremental backup only applies on top of the full one.
+        let restored = temp_dir.path().join("restored");
+        assert!(Store::restore(&incremental, &restored).await.is_err());
+        assert_eq!(Store::restore(&full, &restored).await.unwrap(), 50);
+        assert!(Store::restore(&full, &restored).await.is_err());
+        assert_eq!(Store::restore(&incremental, &restored).await.unwrap(), 51);
+
+        let opts = Store::persisted_options(&restored).unwrap().unwrap();
+        let store = Store::new(opts).expect("should open restored store");
+        let txn = store.begin().unwrap();
+        assert_eq!(txn.get(&7u32.to_be_bytes()).unwrap().unwrap(), vec![7; 20]);
+        assert!(txn.get(&3u32.to_be_bytes()).unwrap().is_none());
+        assert_eq!(txn.get(b"new").unwrap().unwrap(), b"value");
+
+        // The versions are kept: 7 was written by the eighth transaction.
+        let key = 7u32.to_be_bytes();
+        let scanned = txn.scan(&key[..]..=&key[..], None).unwrap();
+        assert_eq!(scanned[0].2, 8);
+    }
+
+    #[tokio::test]
+    async fn verify_backup_chain() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().join("db");
+        let store = Store::new(opts).expect("should create store");
+        for i in 0..20u32 {
+            let mut txn = store.begin().unwrap();
+            txn.set(&i.to_be_bytes(), &[i as u8; 20]).unwrap();
+            txn.commit().await.unwrap();
+        }
+        let full = temp_dir.path().join("full.skv");
+        let marker = store.backup(&full, 0).await.unwrap();
+        let mut txn = store.begin().unwrap();
+        txn.set(b"new", b"value").unwrap();
+        txn.commit().await.unwrap();
+        let incremental = temp_dir.path().join("incremental.skv");
+        store.backup(&incremental, marker).await.unwrap();
+        store.close().await.unwrap();
+
+        assert_eq!(Store::verify_backup(&[&full]).unwrap(), 20);
+        assert_eq!(Store::verify_backup(&[&full, &incremental]).unwrap(), 21);
+        assert!(Store::verify_backup(&[&incremental, &full]).is_err());
+        assert!(Store::verify_backup(&[]).is_err());
+
+        // A damaged record and a truncated backup are found.
+        let bytes = std::fs::read(&full).unwrap();
+        let damaged = temp_dir.path().join("damaged.skv");
+        let mut copy = bytes.clone();
+        let at = copy.len() - 30;
+        copy[at] ^= 0xff;
+        std::fs::write(&damaged, copy).unwrap();
+        assert!(matches!(
+            Store::verify_backup(&[&damaged]),
+            Err(Error::InvalidBackup(_))
+        ));
+        std::fs::write(&damaged, &bytes[..bytes.len() - 10]).unwrap();
+        assert!(matches!(
+            Store::verify_backup(&[&damaged, &incremental]),
+            Err(Error::InvalidBackup(_))
+        ));
+    }
+}
@@ -0,0 +1,237 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use crate::storage::kv::{
+    durable::DurabilityCallback,
+    entry::Entry,
+    error::Result,
+    store::Core,
+    transaction::{Durability, Mode, Transaction},
+};
+
+/// A batch of writes applied atomically with
+/// [`Store::write_batch`](crate::Store::write_batch).
+///
+/// A batch is built apart from the store: it holds no snapshot nor slot of
+/// `Options::max_active_transactions` while its writes are added, and it reads nothing, so it
+/// has no reads to check for conflicts. It is applied as a write-only transaction, in one
+/// record of the commit log and one update of the index. The transactions running meanwhile
+/// still see its writes as conflicting with their own reads.
+///
+/// The writes of a key replace the ones added before them.
+#[derive(Clone, Default)]
+pub struct WriteBatch {
+    entries: BTreeMap<Bytes, Entry>,
+    durability: Durability,
+    callbacks: Vec<DurabilityCallback>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a key-value pair to the batch.
+    pub fn set(&mut self, key: &[u8], value: &[u8]) {
+        self.write(Entry::new(key, value));
+    }
+
+    /// Adds the deletion of a key to the batch.
+    pub fn delete(&mut self, key: &[u8]) {
+        let mut entry = Entry::new(key, &[]);
+        entry.mark_delete();
+        self.write(entry);
+    }
+
+    /// Sets the durability level the batch is applied with.
+    pub fn set_durability(&mut self, durability: Durability) {
+        self.durability = durability;
+    }
+
+    /// Adds a callback told once the writes of the batch are durable on disk, whatever the
+    /// durability level it is applied with: the commit log holding them is synced by a later
+    /// commit with `Durability::Immediate`, by an index checkpoint or by closing the store. It
+    /// is given the error the batch failed to commit with, or the one of the commit log that
+    /// may have lost it. The callbacks run on the thread syncing the commit log, and should
+    /// not block it. A store kept in memory tells them once the batch is committed.
+    pub fn on_durable(&mut self, callback: DurabilityCallback) {
+        self.callbacks.push(callback);
+    }
+
+    /// Returns the number of keys written by the batch.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if no write is added to the batch.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Removes the writes of the batch.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn write(&mut self, entry: Entry) {
+        self.entries.insert(entry.key.clone(), entry);
+    }
+}
+
+/// Applies the writes of a batch. They are checked before any is written: if one cannot be
+/// written, or if the batch writes more than `Options::max_entries_per_txn` keys, nothing is.
+/// The durability callbacks of the batch are told once it is synced, or the error it failed
+/// with.
+pub(crate) async fn apply(core: &Arc<Core>, mut batch: WriteBatch) -> Result<()> {
+    let callbacks = std::mem::take(&mut batch.callbacks);
+    match commit(core, batch).await {
+        Ok(Some(version)) if core.opts.should_persist_data() => {
+            core.durable.watch(version, callbacks);
+            Ok(())
+        }
+        Ok(_) => {
+            callbacks.iter().for_each(|callback| callback(Ok(())));
+            Ok(())
+        }
+        Err(err) => {
+            callbacks
+                .iter()
+                .for_each(|callback| callback(Err(err.clone())));
+            Err(err)
+        }
+    }
+}
+
+/// Commits the writes of a batch, and returns the version of the commit, or None if the
+/// batch wrote nothing.
+async fn commit(core: &Arc<Core>, batch: WriteBatch) -> Result<Option<u64>> {
+    let mut txn = Transaction::new(core.clone(), Mode::WriteOnly)?;
+    for entry in batch.entries.values() {
+        txn.check_write(entry)?;
+    }
+    txn.write_all(batch.entries.into_values().collect())?;
+    txn.set_durability(batch.durability);
+    txn.commit().await?;
+    Ok(txn.commit_token().map(|token| token.version()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::WriteBatch;
+    use crate::storage::kv::error::{Error, Result};
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+    use crate::storage::kv::transaction::Durability;
+
+    use tempdir::TempDir;
+
+    #[tokio::test]
+    async fn batches_are_applied_atomically() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.max_entries_per_txn = 3;
+        opts.max_key_size = 8;
+        let store = Store::new(opts).expect("should create store");
+
+        let mut txn = store.begin().unwrap();
+        txn.set(b"a", b"1").unwrap();
+        txn.commit().await.unwrap();
+
+        // A transaction that read a key written by a batch conflicts with it.
+        let mut reader = store.begin().unwrap();
+        reader.get(b"a").unwrap();
+        reader.set(b"c", b"1").unwrap();
+
+        let mut batch = WriteBatch::new();
+        batch.set(b"a", b"2");
+        batch.set(b"b", b"1");
+        batch.delete(b"a");
+        batch.set(b"a", b"3");
+        assert_eq!(batch.len(), 2);
+        store.write_batch(batch.clone()).await.unwrap();
+        assert!(matches!(
+            reader.commit().await,
+            Err(Error::TransactionReadConflict)
+        ));
+
+        let txn = store.begin().unwrap();
+        assert_eq!(txn.get(b"a").unwrap().unwrap(), b"3");
+        assert_eq!(txn.get(b"b").unwrap().unwrap(), b"1");
+        drop(txn);
+
+        // Nothing is written if a write of the batch is refused.
+        batch.clear();
+        batch.set(b"d", b"1");
+        batch.set(b"too long key", b"1");
+        assert!(matches!(
+            store.write_batch(batch.clone()).await,
+            Err(Error::MaxKeyLengthExceeded)
+        ));
+        batch.clear();
+        for key in [b"d", b"e", b"f", b"g"] {
+            batch.set(key, b"1");
+        }
+        assert!(matches!(
+            store.write_batch(batch).await,
+            Err(Error::MaxTransactionEntriesLimitExceeded)
+        ));
+        let txn = store.begin().unwrap();
+        assert!(txn.get(b"d").unwrap().is_none());
+        drop(txn);
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn durability_callbacks_are_told_once_synced() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        let store = Store::new(opts).expect("should create store");
+
+        let synced = Arc::new(AtomicUsize::new(0));
+        let batch = |key: &[u8], durability| {
+            let mut batch = WriteBatch::new();
+            batch.set(key, b"1");
+            batch.set_durability(durability);
+            let synced = synced.clone();
+            batch.on_durable(Arc::new(move |res: Result<()>| {
+                assert!(res.is_ok());
+                synced.fetch_add(1, Ordering::SeqCst);
+            }));
+            batch
+        };
+
+        // An eventual batch is synced by the next immediate one.
+        store
+            .write_batch(batch(b"a", Durability::Eventual))
+            .await
+            .unwrap();
+        assert_eq!(synced.load(Ordering::SeqCst), 0);
+        store
+            .write_batch(batch(b"b", Durability::Immediate))
+            .await
+            .unwrap();
+        assert_eq!(synced.load(Ordering::SeqCst), 2);
+
+        store
+            .write_batch(batch(b"c", Durability::Weak))
+            .await
+            .unwrap();
+        store.checkpoint_index().await.unwrap();
+        assert_eq!(synced.load(Ordering::SeqCst), 3);
+
+        store
+            .write_batch(batch(b"d", Durability::Eventual))
+            .await
+            .unwrap();
+        assert_eq!(synced.load(Ordering::SeqCst), 3);
+        store.close().await.unwrap();
+        assert_eq!(synced.load(Ordering::SeqCst), 4);
+    }
+}
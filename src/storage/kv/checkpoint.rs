@@ -0,0 +1,574 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use parking_lot::Mutex;
+use vart::{art::KV, Key, VariableSizeKey};
+
+use crate::storage::{
+    kv::{
+        entry::ValueRef,
+        error::{Error, Result},
+        events::{self, Level, Value},
+        flags::FlagIndex,
+        indexer::Indexer,
+        intern::KeyCodec,
+        util::calculate_crc32,
+    },
+    log::Metadata,
+    vfs::{SharedVfs, Vfs},
+};
+
+/// Number of shards the index is split into. The dirty shards are tracked in a `u64`.
+const SHARDS: usize = u64::BITS as usize;
+
+const MANIFEST_FILE: &str = "CHECKPOINT";
+const SHARD_EXTENSION: &str = "shard";
+
+const META_KEY_OFFSET: &str = "offset";
+const META_KEY_SEGMENT_SIZE: &str = "segment_size";
+const META_KEY_GENERATION: &str = "generation";
+
+/// Returns the shard holding a key.
+fn shard_of(key: &[u8]) -> usize {
+    calculate_crc32(key) as usize % SHARDS
+}
+
+fn shard_name(shard: usize, generation: u64) -> String {
+    format!("{:02}-{:020}.{}", shard, generation, SHARD_EXTENSION)
+}
+
+fn shard_meta_key(shard: usize) -> String {
+    format!("shard_{}", shard)
+}
+
+/// What the last checkpoint is made of.
+#[derive(Clone)]
+struct CheckpointState {
+    offset: u64,       // Offset of the commit log up to which the checkpoint holds the index.
+    segment_size: u64, // Segment size of the commit log the offset refers to.
+    generation: u64,   // Generation of the last checkpoint, 0 if there is none.
+    shards: Vec<u64>,  // Generation of the file of each shard, 0 if none was written.
+}
+
+impl CheckpointState {
+    fn empty(segment_size: u64) -> Self {
+        Self {
+            offset: 0,
+            segment_size,
+            generation: 0,
+            shards: vec![0; SHARDS],
+        }
+    }
+
+    /// Reads the state from the manifest in `dir`, or returns the empty state if there is
+    /// no checkpoint.
+    fn read(vfs: &dyn Vfs, dir: &Path, segment_size: u64) -> Result<Self> {
+        let manifest = dir.join(MANIFEST_FILE);
+        if !vfs.exists(&manifest) {
+            return Ok(Self::empty(segment_size));
+        }
+        let mut metadata = Metadata::new(None);
+        metadata.read_from(&mut &vfs.read(&manifest)?[..])?;
+        Self::from_metadata(&metadata)
+    }
+
+    fn to_metadata(&self) -> Metadata {
+        let mut metadata = Metadata::new(None);
+        metadata.put_uint(META_KEY_OFFSET, self.offset);
+        metadata.put_uint(META_KEY_SEGMENT_SIZE, self.segment_size);
+        metadata.put_uint(META_KEY_GENERATION, self.generation);
+        for (shard, generation) in self.shards.iter().enumerate() {
+            metadata.put_uint(&shard_meta_key(shard), *generation);
+        }
+        metadata
+    }
+
+    fn from_metadata(metadata: &Metadata) -> Result<Self> {
+        Ok(Self {
+            offset: metadata.get_uint(META_KEY_OFFSET)?,
+            segment_size: metadata.get_uint(META_KEY_SEGMENT_SIZE)?,
+            generation: metadata.get_uint(META_KEY_GENERATION)?,
+            shards: (0..SHARDS)
+                .map(|shard| metadata.get_uint(&shard_meta_key(shard)))
+                .collect::<std::result::Result<_, _>>()?,
+        })
+    }
+}
+
+/// `IndexCheckpoint` writes the index to disk incrementally, so that opening the store only
+/// replays the commit log written after the last checkpoint instead of the whole log.
+///
+/// The keys are split into shards by a hash of the key, and the shards changed by a commit
+/// are marked dirty. A checkpoint writes a new file for each dirty shard only, holding the
+/// latest version of each of its keys, and then the `CHECKPOINT` manifest listing the file
+/// of every shard and the offset of the commit log they were taken at. The files of the
+/// shards that did not change are carried over from the previous checkpoints.
+///
+/// The commit log stays the source of truth: a checkpoint that cannot be read is ignored,
+/// and the index is rebuilt from the whole log.
+pub(crate) struct IndexCheckpoint {
+    vfs: SharedVfs,
+    dir: Option<PathBuf>,
+    segment_size: u64,
+    dirty: AtomicU64,
+    state: Mutex<CheckpointState>,
+}
+
+impl IndexCheckpoint {
+    /// Opens the checkpoints kept in `dir` on `vfs`. Without a directory, checkpoints are not
+    /// written.
+    pub(crate) fn open(vfs: &SharedVfs, dir: Option<&Path>, segment_size: u64) -> Result<Self> {
+        let mut state = CheckpointState::empty(segment_size);
+
+        if let Some(dir) = dir {
+            vfs.create_dir_all(dir, None)?;
+            state = CheckpointState::read(&**vfs, dir, segment_size)?;
+
+            // Remove the files of the checkpoints that were not completed.
+            for entry in vfs.read_dir(dir)? {
+                let path = entry.path();
+                let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+                let listed = (0..SHARDS).any(|shard| {
+                    state.shards[shard] != 0 && name == shard_name(shard, state.shards[shard])
+                });
+                if name != MANIFEST_FILE && !listed {
+                    vfs.remove_file(path)?;
+                }
+            }
+        }
+
+        Ok(Self {
+            vfs: vfs.clone(),
+            dir: dir.map(Path::to_path_buf),
+            segment_size,
+            // Until a checkpoint is loaded, every shard has to be written.
+            dirty: AtomicU64::new(u64::MAX),
+            state: Mutex::new(state),
+        })
+    }
+
+    /// Opens the last checkpoint of a store without writing to its directory, which leaves
+    /// the files of a checkpoint being written by the store in place.
+    pub(crate) fn open_read_only(vfs: &SharedVfs, dir: &Path, segment_size: u64) -> Result<Self> {
+        Ok(Self {
+            vfs: vfs.clone(),
+            dir: Some(dir.to_path_buf()),
+            segment_size,
+            dirty: AtomicU64::new(u64::MAX),
+            state: Mutex::new(CheckpointState::read(&**vfs, dir, segment_size)?),
+        })
+    }
+
+    /// Returns the generation of the checkpoint, which is 0 if there is none.
+    pub(crate) fn generation(&self) -> u64 {
+        self.state.lock().generation
+    }
+
+    /// Loads the last checkpoint into the empty index, and returns the offset of the commit
+    /// log to replay from. It returns 0 if there is no usable checkpoint.
+    pub(crate) fn load(
+        &self,
+        log_size: u64,
+        indexer: &mut Indexer,
+        flag_index: &mut FlagIndex,
+    ) -> Result<u64> {
+        let state = self.state.lock().clone();
+        let dir = match &self.dir {
+            Some(dir) if state.offset > 0 => dir,
+            _ => return Ok(0),
+        };
+        if state.offset > log_size {
+            events::emit(
+                events::CHECKPOINT,
+                Level::Warn,
+                "ignoring index checkpoint beyond the end of the commit log",
+                &[
+                    ("offset", Value::U64(state.offset)),
+                    ("log_bytes", Value::U64(log_size)),
+                ],
+            );
+            return Ok(0);
+        }
+        // The offsets in the index refer to the segments of the commit log.
+        if state.segment_size != self.segment_size {
+            return Ok(0);
+        }
+
+        let mut kv_pairs = Vec::new();
+        for (shard, generation) in state.shards.iter().enumerate() {
+            if *generation == 0 {
+                continue;
+            }
+            let path = dir.join(shard_name(shard, *generation));
+            let read = self
+                .vfs
+                .read(&path)
+                .map_err(Error::from)
+                .and_then(|buf| decode_shard(Bytes::from(buf), &mut kv_pairs));
+            if let Err(err) = read {
+                let (path, error) = (path.display().to_string(), err.to_string());
+                events::emit(
+                    events::CHECKPOINT,
+                    Level::Warn,
+                    "ignoring unreadable index checkpoint",
+                    &[("path", Value::Str(&path)), ("error", Value::Str(&error))],
+                );
+                return Ok(0);
+            }
+        }
+
+        let metadata = kv_pairs
+            .iter()
+            .map(|kv: &KV<VariableSizeKey, Bytes>| ValueRef::decode_metadata(&kv.value))
+            .collect::<Result<Vec<_>>>()?;
+        let keys: Vec<Bytes> = kv_pairs
+            .iter()
+            .map(|kv| Bytes::copy_from_slice(kv.key.as_slice()))
+            .collect();
+        flag_index.apply(keys.iter().zip(metadata.iter().map(Option::as_ref)));
+
+        // The versions must be inserted in increasing order.
+        kv_pairs.sort_by_key(|kv| kv.version);
+        indexer.bulk_insert(&mut kv_pairs)?;
+
+        self.dirty.store(0, Ordering::Relaxed);
+        Ok(state.offset)
+    }
+
+    /// Marks the shards of the keys as changed since the last checkpoint.
+    pub(crate) fn mark<'a, I>(&self, keys: I)
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        let mask = keys
+            .into_iter()
+            .fold(0u64, |mask, key| mask | (1 << shard_of(key)));
+        self.dirty.fetch_or(mask, Ordering::Relaxed);
+    }
+
+    /// Marks all the shards as changed, when the index is cleared.
+    pub(crate) fn mark_all(&self) {
+        self.dirty.store(u64::MAX, Ordering::Relaxed);
+    }
+
+    /// Takes the shards changed since the last checkpoint. They are marked clean until
+    /// `restore` is called, if writing them fails.
+    pub(crate) fn take_dirty(&self) -> u64 {
+        self.dirty.swap(0, Ordering::Relaxed)
+    }
+
+    pub(crate) fn restore(&self, dirty: u64) {
+        self.dirty.fetch_or(dirty, Ordering::Relaxed);
+    }
+
+    /// Writes a checkpoint of the `dirty` shards, taken at `offset` of the commit log, from
+    /// the entries of the index, whose keys are translated back with `keys`. It returns the
+    /// number of shards written.
+    pub(crate) fn write<I>(
+        &self,
+        offset: u64,
+        dirty: u64,
+        keys: &KeyCodec,
+        entries: I,
+    ) -> Result<usize>
+    where
+        I: IntoIterator<Item = (Vec<u8>, Bytes, u64, u64)>,
+    {
+        let dir = match &self.dir {
+            Some(dir) => dir,
+            None => return Ok(0),
+        };
+
+        let mut shards: Vec<Option<BytesMut>> = (0..SHARDS)
+            .map(|shard| (dirty & (1 << shard) != 0).then(BytesMut::new))
+            .collect();
+        for (key, value, version, ts) in entries {
+            let key = &keys.decode(key)?[..];
+            if let Some(buf) = &mut shards[shard_of(key)] {
+                buf.put_u32(key.len() as u32);
+                buf.put(key);
+                buf.put_u32(value.len() as u32);
+                buf.put(value.as_ref());
+                buf.put_u64(version);
+                buf.put_u64(ts);
+            }
+        }
+
+        let mut state = self.state.lock();
+        let mut next = state.clone();
+        next.offset = offset;
+        next.segment_size = self.segment_size;
+        next.generation += 1;
+
+        let mut written = 0;
+        for (shard, buf) in shards.iter_mut().enumerate() {
+            if let Some(buf) = buf {
+                let crc = calculate_crc32(buf);
+                buf.put_u32(crc);
+                let path = dir.join(shard_name(shard, next.generation));
+                self.vfs.write(&path, &buf[..])?;
+                next.shards[shard] = next.generation;
+                written += 1;
+            }
+        }
+
+        let manifest = dir.join(MANIFEST_FILE);
+        let tmp = manifest.with_extension("tmp");
+        self.vfs.write(&tmp, &next.to_metadata().to_bytes()?)?;
+        self.vfs.rename(&tmp, &manifest)?;
+
+        // The files of the rewritten shards are no longer listed.
+        for (shard, generation) in state.shards.iter().enumerate() {
+            if *generation != 0 && next.shards[shard] != *generation {
+                self.vfs
+                    .remove_file(&dir.join(shard_name(shard, *generation)))?;
+            }
+        }
+        *state = next;
+
+        Ok(written)
+    }
+}
+
+/// Decodes the entries of a shard file, checking its checksum.
+fn decode_shard(mut buf: Bytes, kv_pairs: &mut Vec<KV<VariableSizeKey, Bytes>>) -> Result<()> {
+    if buf.len() < 4 {
+        return Err(Error::CorruptedIndex);
+    }
+    let crc = (&buf[buf.len() - 4..]).get_u32();
+    buf.truncate(buf.len() - 4);
+    if calculate_crc32(&buf) != crc {
+        return Err(Error::CorruptedIndex);
+    }
+
+    while buf.has_remaining() {
+        let key = read_field(&mut buf)?;
+        let value = read_field(&mut buf)?;
+        if buf.remaining() < 16 {
+            return Err(Error::CorruptedIndex);
+        }
+        kv_pairs.push(KV {
+            key: VariableSizeKey::from_slice(&key),
+            value,
+            version: buf.get_u64(),
+            ts: buf.get_u64(),
+        });
+    }
+    Ok(())
+}
+
+fn read_field(buf: &mut Bytes) -> Result<Bytes> {
+    if buf.remaining() < 4 {
+        return Err(Error::CorruptedIndex);
+    }
+    let len = buf.get_u32() as usize;
+    if buf.remaining() < len {
+        return Err(Error::CorruptedIndex);
+    }
+    Ok(buf.split_to(len))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::storage::kv::error::Error;
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+    use crate::storage::kv::transaction::Mode;
+
+    use tempdir::TempDir;
+
+    fn options(temp_dir: &TempDir) -> Options {
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.max_segment_size = 4096;
+        opts.max_value_threshold = 8;
+        opts.indexed_flags = 1;
+        opts
+    }
+
+    async fn set(store: &Store, i: u32, value: &[u8]) {
+        let mut txn = store.begin().unwrap();
+        txn.set(&i.to_be_bytes(), value).unwrap();
+        txn.commit().await.unwrap();
+    }
+
+    fn get(store: &Store, i: u32) -> Option<Vec<u8>> {
+        store.begin().unwrap().get(&i.to_be_bytes()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn checkpoint_and_reopen() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let store = Store::new(options(&temp_dir)).expect("should create store");
+
+        // Spread the values over a few segments, with some inlined in the index.
+        for i in 0..100u32 {
+            set(&store, i, &[i as u8; 100]).await;
+        }
+        set(&store, 6, b"small").await;
+        let mut txn = store.begin().unwrap();
+        txn.delete(&5u32.to_be_bytes()).unwrap();
+        txn.set_with_flags(&7u32.to_be_bytes(), b"flagged", 1)
+            .unwrap();
+        txn.commit().await.unwrap();
+
+        // All the shards are written first, then only the changed ones.
+        assert_eq!(store.checkpoint_index().await.unwrap(), 64);
+        assert_eq!(store.checkpoint_index().await.unwrap(), 0);
+        set(&store, 200, &[2; 100]).await;
+        assert_eq!(store.checkpoint_index().await.unwrap(), 1);
+
+        // The commits after the checkpoint are replayed from the commit log.
+        set(&store, 300, &[3; 100]).await;
+        set(&store, 8, b"changed").await;
+        let segments = store.segments_for_range(..);
+        store.close().await.unwrap();
+
+        let store = Store::new(options(&temp_dir)).expect("should reopen store");
+        assert_eq!(get(&store, 4).unwrap(), [4; 100]);
+        assert_eq!(get(&store, 5), None);
+        assert_eq!(get(&store, 6).unwrap(), b"small");
+        assert_eq!(get(&store, 8).unwrap(), b"changed");
+        assert_eq!(get(&store, 99).unwrap(), [99; 100]);
+        assert_eq!(get(&store, 200).unwrap(), [2; 100]);
+        assert_eq!(get(&store, 300).unwrap(), [3; 100]);
+        assert_eq!(
+            store.flagged_keys(0, ..).unwrap(),
+            vec![7u32.to_be_bytes().to_vec()]
+        );
+        assert_eq!(store.segments_for_range(..), segments);
+
+        // Only the shards replayed are dirty.
+        let written = store.checkpoint_index().await.unwrap();
+        assert!(written > 0 && written <= 2);
+
+        // New commits get versions after the ones of the checkpoint.
+        set(&store, 4, b"after").await;
+        assert_eq!(get(&store, 4).unwrap(), b"after");
+    }
+
+    #[tokio::test]
+    async fn tombstones_are_purged() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let store = Store::new(options(&temp_dir)).expect("should create store");
+        for i in 0..4u32 {
+            set(&store, i, b"value").await;
+        }
+        for i in [1u32, 2] {
+            let mut txn = store.begin().unwrap();
+            txn.delete(&i.to_be_bytes()).unwrap();
+            txn.commit().await.unwrap();
+        }
+        let version = store.latest_commit_token().unwrap();
+        store.checkpoint_index().await.unwrap();
+        store.close().await.unwrap();
+
+        // The tombstone of the last commit is kept to resume its version.
+        let store = Store::new(options(&temp_dir)).expect("should reopen store");
+        let txn = store.begin().unwrap();
+        assert!(txn.history(&1u32.to_be_bytes()).unwrap().is_empty());
+        assert_eq!(txn.history(&2u32.to_be_bytes()).unwrap().len(), 1);
+        assert_eq!(get(&store, 1), None);
+        assert_eq!(get(&store, 3).unwrap(), b"value");
+        assert_eq!(store.latest_commit_token().unwrap(), version);
+        drop(txn);
+
+        // The tombstones committed from the GC watermark are kept.
+        let mut txn = store.begin().unwrap();
+        txn.delete(&3u32.to_be_bytes()).unwrap();
+        txn.commit().await.unwrap();
+        set(&store, 10, b"value").await;
+        store.set_gc_watermark(0);
+        store.checkpoint_index().await.unwrap();
+        store.close().await.unwrap();
+
+        let store = Store::new(options(&temp_dir)).expect("should reopen store");
+        let txn = store.begin().unwrap();
+        assert_eq!(txn.history(&3u32.to_be_bytes()).unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn unreadable_checkpoint_is_ignored() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let store = Store::new(options(&temp_dir)).expect("should create store");
+        for i in 0..50u32 {
+            set(&store, i, &[i as u8; 100]).await;
+        }
+        store.checkpoint_index().await.unwrap();
+        set(&store, 50, &[50; 100]).await;
+        store.close().await.unwrap();
+
+        let dir = temp_dir.path().join("checkpoint");
+        for entry in fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|e| e == "shard") {
+                fs::write(&path, b"garbage").unwrap();
+            }
+        }
+
+        // The index is rebuilt from the whole commit log.
+        let store = Store::new(options(&temp_dir)).expect("should reopen store");
+        for i in 0..=50u32 {
+            assert_eq!(get(&store, i).unwrap(), [i as u8; 100]);
+        }
+        assert_eq!(store.checkpoint_index().await.unwrap(), 64);
+    }
+
+    #[tokio::test]
+    async fn open_checkpoint_of_live_store() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let store = Store::new(options(&temp_dir)).expect("should create store");
+        set(&store, 1, b"one").await;
+        match Store::open_checkpoint(temp_dir.path()) {
+            Err(Error::CheckpointUnavailable) => {}
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+
+        for i in 0..50u32 {
+            set(&store, i, &[i as u8; 100]).await;
+        }
+        store.checkpoint_index().await.unwrap();
+        set(&store, 50, &[50; 100]).await;
+
+        // The checkpoint is read as of the point it was taken, while the store is open.
+        let view = Store::open_checkpoint(temp_dir.path()).expect("should open checkpoint");
+        let txn = view.begin_with_mode(Mode::ReadOnly).unwrap();
+        for i in 0..50u32 {
+            assert_eq!(txn.get(&i.to_be_bytes()).unwrap().unwrap(), [i as u8; 100]);
+        }
+        assert_eq!(txn.get(&50u32.to_be_bytes()).unwrap(), None);
+        drop(txn);
+        assert!(matches!(view.begin(), Err(Error::StoreReadOnly)));
+        assert!(matches!(
+            view.checkpoint_index().await,
+            Err(Error::StoreReadOnly)
+        ));
+
+        // A fresh view sees the next checkpoint, while the older one is still readable.
+        store.checkpoint_index().await.unwrap();
+        set(&store, 51, &[51; 100]).await;
+        let fresh = Store::open_checkpoint(temp_dir.path()).expect("should open checkpoint");
+        assert_eq!(get_read_only(&fresh, 50).unwrap(), [50; 100]);
+        assert_eq!(get_read_only(&fresh, 51), None);
+        assert_eq!(get_read_only(&view, 1).unwrap(), [1; 100]);
+        fresh.close().await.unwrap();
+        view.close().await.unwrap();
+
+        // The store is not disturbed by the views.
+        set(&store, 52, &[52; 100]).await;
+        store.close().await.unwrap();
+        let store = Store::new(options(&temp_dir)).expect("should reopen store");
+        for i in 0..=52u32 {
+            assert_eq!(get(&store, i).unwrap(), [i as u8; 100]);
+        }
+        store.close().await.unwrap();
+    }
+
+    fn get_read_only(store: &Store, i: u32) -> Option<Vec<u8>> {
+        let txn = store.begin_with_mode(Mode::ReadOnly).unwrap();
+        txn.get(&i.to_be_bytes()).unwrap()
+    }
+}
@@ -0,0 +1,152 @@
+use std::sync::Arc;
+
+use crate::storage::{
+    kv::{
+        entry::Entry,
+        error::{Error, Result},
+        meta::Metadata,
+        redundancy::Redundancy,
+        segments::SegmentKeyRanges,
+        store::Core,
+        transaction::{Durability, Mode, Transaction},
+        util::system_key,
+    },
+    log::aof::log::Aol,
+};
+
+/// Subsystem of the system key of the clear markers, see `util::system_key`.
+const CLEARS: &[u8] = b"clear";
+
+/// Returns the entry dropping all the keys of the store, written by `Store::clear`.
+///
+/// A clear is written to the commit log as this one entry, the marker, which the writer makes
+/// the first record of a new segment. The index is emptied when the marker is indexed, or
+/// replayed, before the marker is inserted, so that the versions go on from it. Once the
+/// marker is durable, the segments before its own are removed, see `remove_segments`: a store
+/// that crashed in between replays them first, drops them at the marker, and removes them
+/// when it is opened.
+pub(crate) fn entry() -> Result<Entry> {
+    let key = system_key(CLEARS, b"marker")?;
+    let mut entry = Entry::new(&key, &[]);
+    entry.mark_clear();
+    Ok(entry)
+}
+
+/// Returns true if the metadata is the one of a clear marker.
+pub(crate) fn is_clear(metadata: Option<&Metadata>) -> bool {
+    metadata.is_some_and(|md| md.cleared())
+}
+
+/// Commits a clear of the store, see `Store::clear`.
+pub(crate) async fn clear(core: &Arc<Core>) -> Result<()> {
+    if core.is_read_only() {
+        return Err(Error::StoreReadOnly);
+    }
+    let mut txn = Transaction::new(core.clone(), Mode::WriteOnly)?;
+    txn.write_all(vec![entry()?])?;
+    txn.set_durability(Durability::Immediate);
+    txn.commit().await
+}
+
+/// Removes the segments of the commit log below `segment_id`, which a clear marker starts,
+/// with their key ranges and redundancy.
+pub(crate) fn remove_segments(
+    clog: &Aol,
+    segment_keys: &mut SegmentKeyRanges,
+    redundancy: &Redundancy,
+    segment_id: u64,
+) -> Result<()> {
+    clog.remove_segments_before(segment_id)?;
+    segment_keys.remove_before(segment_id)?;
+    redundancy.remove_before(segment_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use crate::storage::kv::error::Error;
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+
+    use tempdir::TempDir;
+
+    fn segments(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn keys(store: &Store) -> Vec<Vec<u8>> {
+        let txn = store.begin().unwrap();
+        let entries = txn.scan(.., None).unwrap();
+        entries.into_iter().map(|entry| entry.0).collect()
+    }
+
+    #[tokio::test]
+    async fn stores_are_cleared_in_place() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().join("store");
+        opts.max_segment_size = 4096;
+        let clog = opts.dir.join("clog");
+        let store = Store::new(opts.clone()).expect("should create store");
+
+        for i in 0..100u32 {
+            let mut txn = store.begin().unwrap();
+            txn.set(format!("k{:03}", i).as_bytes(), &[0; 100]).unwrap();
+            txn.commit().await.unwrap();
+        }
+        store.append_to_stream(b"events", b"1").await.unwrap();
+        store.checkpoint_index().await.unwrap();
+        assert!(segments(&clog).len() > 2);
+        let before = temp_dir.path().join("before");
+        fs::create_dir(&before).unwrap();
+        for name in segments(&clog) {
+            fs::copy(clog.join(&name), before.join(&name)).unwrap();
+        }
+
+        // A transaction that read a dropped key conflicts with the clear.
+        let mut reader = store.begin().unwrap();
+        reader.get(b"k001").unwrap();
+        reader.set(b"other", b"1").unwrap();
+
+        store.clear().await.unwrap();
+        assert!(matches!(
+            reader.commit().await,
+            Err(Error::TransactionReadConflict)
+        ));
+        assert!(keys(&store).is_empty());
+        assert!(store.read_stream(b"events", ..).unwrap().is_empty());
+        assert_eq!(segments(&clog).len(), 1);
+
+        // The store is usable, and the versions go on.
+        let cleared = store.latest_commit_token().unwrap();
+        let mut txn = store.begin().unwrap();
+        txn.set(b"k001", b"2").unwrap();
+        txn.commit().await.unwrap();
+        assert!(txn.commit_token().unwrap() > cleared);
+        assert_eq!(store.append_to_stream(b"events", b"2").await.unwrap(), 0);
+        store.close().await.unwrap();
+
+        // The checkpoint taken before the clear is replayed past, and the segments dropped
+        // by a clear the store crashed in are removed when it is opened.
+        let store = Store::new(opts.clone()).expect("should open store");
+        assert_eq!(keys(&store), [b"k001"]);
+        store.close().await.unwrap();
+        let mut names = segments(&before);
+        names.pop();
+        for name in &names {
+            fs::copy(before.join(name), clog.join(name)).unwrap();
+        }
+        let store = Store::new(opts).expect("should open store");
+        assert_eq!(keys(&store), [b"k001"]);
+        assert_eq!(store.read_stream(b"events", ..).unwrap().len(), 1);
+        assert_eq!(segments(&clog).len(), 1);
+        store.close().await.unwrap();
+    }
+}
@@ -0,0 +1,216 @@
+use std::fmt;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use crate::storage::kv::{
+    events::{self, Level, Value},
+    util::now,
+};
+
+/// Where the commit timestamps of a store are taken from, see
+/// [`Store::timestamp_source`](crate::Store::timestamp_source).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimestampSource {
+    /// The wall clock, in nanoseconds since the Unix epoch.
+    WallClock,
+    /// The last commit timestamp, incremented for each commit, while the wall clock is behind
+    /// it after going back.
+    Monotonic,
+}
+
+/// `CommitClock` hands out the commit timestamps of a store, which increase with each commit
+/// even if the wall clock goes back, so that the reads at a timestamp and the last writer
+/// wins resolution keep the commit order.
+pub(crate) struct CommitClock {
+    last: AtomicU64, // Last commit timestamp handed out, or replayed from the store.
+    monotonic: AtomicBool, // Set while the wall clock is behind the last commit timestamp.
+    regressions: AtomicU64, // Number of times the wall clock was found to go back.
+}
+
+impl CommitClock {
+    /// Returns a clock whose timestamps follow `last_ts`, the last commit timestamp of the
+    /// store.
+    pub(crate) fn new(last_ts: u64) -> Self {
+        Self {
+            last: AtomicU64::new(last_ts),
+            monotonic: AtomicBool::new(false),
+            regressions: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the timestamp of the next commit: the wall clock time, or the last commit
+    /// timestamp plus one if the wall clock is not past it. The clock going back is counted
+    /// and reported once, until the wall clock catches up.
+    pub(crate) fn next(&self) -> u64 {
+        let wall = now();
+        let last = self
+            .last
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |last| {
+                Some(wall.max(last.saturating_add(1)))
+            })
+            .unwrap();
+        if wall > last {
+            self.monotonic.store(false, Ordering::Relaxed);
+            return wall;
+        }
+        if wall < last && !self.monotonic.swap(true, Ordering::Relaxed) {
+            self.regressions.fetch_add(1, Ordering::Relaxed);
+            events::emit(
+                events::CLOCK,
+                Level::Warn,
+                "wall clock went back, incrementing the commit timestamps",
+                &[("behind_ns", Value::U64(last - wall))],
+            );
+        }
+        last.saturating_add(1)
+    }
+
+    /// Returns where the last commit timestamp was taken from.
+    pub(crate) fn source(&self) -> TimestampSource {
+        match self.monotonic.load(Ordering::Relaxed) {
+            true => TimestampSource::Monotonic,
+            false => TimestampSource::WallClock,
+        }
+    }
+
+    /// Returns the number of times the wall clock was found to go back.
+    pub(crate) fn regressions(&self) -> u64 {
+        self.regressions.load(Ordering::Relaxed)
+    }
+}
+
+/// The clock the expiry of the keys is evaluated against, set in `Options::expiry_clock`: the
+/// times the TTLs of the writes elapse at, the reads of the expired keys as missing ones and
+/// the sweeps of the expired keys all follow it, so that tests or simulations can control when
+/// the keys expire. The commit timestamps keep following the wall clock.
+///
+/// The closures returning the time implement it.
+pub trait ExpiryClock: Send + Sync {
+    /// Returns the current time, in nanoseconds since the Unix epoch.
+    fn now(&self) -> u64;
+}
+
+impl<F> ExpiryClock for F
+where
+    F: Fn() -> u64 + Send + Sync,
+{
+    fn now(&self) -> u64 {
+        self()
+    }
+}
+
+/// The wall clock, the default [`ExpiryClock`].
+pub struct WallClock;
+
+impl ExpiryClock for WallClock {
+    fn now(&self) -> u64 {
+        now()
+    }
+}
+
+/// An [`ExpiryClock`] shared by the stores opened with it, set in `Options::expiry_clock`.
+///
+/// Two handles are equal if they share the same clock. The default handle is the one of the
+/// wall clock, [`WallClock`], shared by all the default options.
+#[derive(Clone)]
+pub struct SharedExpiryClock(Arc<dyn ExpiryClock>);
+
+impl SharedExpiryClock {
+    pub fn new<C: ExpiryClock + 'static>(clock: C) -> Self {
+        Self(Arc::new(clock))
+    }
+}
+
+impl Default for SharedExpiryClock {
+    fn default() -> Self {
+        static WALL: OnceLock<SharedExpiryClock> = OnceLock::new();
+        WALL.get_or_init(|| Self::new(WallClock)).clone()
+    }
+}
+
+impl Deref for SharedExpiryClock {
+    type Target = dyn ExpiryClock;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.0
+    }
+}
+
+impl PartialEq for SharedExpiryClock {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::as_ptr(&self.0) as *const () == Arc::as_ptr(&other.0) as *const ()
+    }
+}
+
+impl Eq for SharedExpiryClock {}
+
+impl fmt::Debug for SharedExpiryClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "SharedExpiryClock({:p})",
+            Arc::as_ptr(&self.0) as *const ()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::{CommitClock, SharedExpiryClock, TimestampSource};
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+    use crate::storage::kv::util::now;
+
+    use tempdir::TempDir;
+
+    #[test]
+    fn commit_timestamps_survive_clock_regressions() {
+        let clock = CommitClock::new(0);
+        let ts = clock.next();
+        assert!(ts > 0 && clock.next() > ts);
+        assert_eq!(clock.source(), TimestampSource::WallClock);
+
+        // The store committed at a time the wall clock is now behind.
+        let ahead = now() + Duration::from_secs(3600).as_nanos() as u64;
+        let clock = CommitClock::new(ahead);
+        assert_eq!(clock.next(), ahead + 1);
+        assert_eq!(clock.next(), ahead + 2);
+        assert_eq!(clock.source(), TimestampSource::Monotonic);
+        assert_eq!(clock.regressions(), 1);
+    }
+
+    #[tokio::test]
+    async fn keys_expire_by_the_expiry_clock() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let time = Arc::new(AtomicU64::new(1_000));
+        let clock = time.clone();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.expiry_clock = SharedExpiryClock::new(move || clock.load(Ordering::SeqCst));
+        let store = Store::new(opts).expect("should create store");
+
+        let mut txn = store.begin().unwrap();
+        txn.set_with_ttl(b"a", b"1", Duration::from_nanos(10))
+            .unwrap();
+        txn.commit().await.unwrap();
+
+        // The key lives until the clock reaches its expiry, whatever the wall clock.
+        time.store(1_009, Ordering::SeqCst);
+        let txn = store.begin().unwrap();
+        assert_eq!(txn.get(b"a").unwrap(), Some(b"1".to_vec()));
+        drop(txn);
+        assert_eq!(store.sweep_expired().await.unwrap(), 0);
+
+        time.store(1_010, Ordering::SeqCst);
+        let txn = store.begin().unwrap();
+        assert_eq!(txn.get(b"a").unwrap(), None);
+        drop(txn);
+        assert_eq!(store.sweep_expired().await.unwrap(), 1);
+        store.close().await.unwrap();
+    }
+}
@@ -0,0 +1,618 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use hashbrown::HashMap;
+
+use crate::storage::kv::{
+    checkpoint::IndexCheckpoint,
+    clear,
+    entry::{Entry, TxRecord, Value, ValueRef},
+    error::{Error, Result},
+    events::{self, Activity},
+    flags::FlagIndex,
+    gc,
+    indexer::{IndexVersion, Indexer, KeyRange},
+    jobs::JobKind,
+    merge,
+    option::Options,
+    segments::SegmentKeyRanges,
+    store::Core,
+    transaction::Durability,
+    util::{is_system_key, system_key},
+};
+use crate::storage::log::SegmentRef;
+
+// A compaction rewrites the versions the index keeps in a new segment of the commit log, and
+// removes the segments before it, which hold the versions compacted away. Commits wait while
+// the versions are rewritten:
+//
+//   | ...segments removed... | start | records of the versions kept... | end | commits... |
+//
+// The versions are rewritten with their original version and commit timestamp, in version
+// order, the versions of a commit in one record. The markers are records of a single entry,
+// marked as a compaction (see `Entry::mark_compaction`), which are not indexed, with the
+// version of the index the compaction was written at.
+//
+// Replaying the log, the records following a start marker are indexed into a new index, which
+// replaces the index at the end marker, so that a checkpoint or segments left from before the
+// compaction are replayed past. A compaction whose end marker is missing, as the store failed
+// meanwhile, is dropped: the segments before it were not removed, as they are only once the
+// end marker is durable.
+
+/// Subsystem of the system key of the compaction markers, see `util::system_key`.
+const COMPACTIONS: &[u8] = b"compaction";
+
+const START: &[u8] = b"start";
+const END: &[u8] = b"end";
+
+/// A marker of a compaction in the commit log.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Marker {
+    Start,
+    End,
+}
+
+fn marker_entry(marker: &[u8]) -> Result<Entry> {
+    let key = system_key(COMPACTIONS, b"marker")?;
+    let mut entry = Entry::new(&key, marker);
+    entry.mark_compaction();
+    Ok(entry)
+}
+
+/// Returns the compaction marker a transaction record is, if it is one.
+pub(crate) fn marker(tx: &TxRecord) -> Option<Marker> {
+    let [entry] = &tx.entries[..] else {
+        return None;
+    };
+    if !entry.metadata.as_ref().is_some_and(|md| md.compaction()) {
+        return None;
+    }
+    match &entry.value[..] {
+        START => Some(Marker::Start),
+        END => Some(Marker::End),
+        _ => None,
+    }
+}
+
+/// The index replayed from the records of a compaction, which replaces the index of the store
+/// at its end marker.
+pub(crate) struct Replay {
+    version: u64,    // Version of the index the compaction was written at.
+    segment_id: u64, // Segment the compaction starts, the segments before it are dropped.
+    pub(crate) indexer: Indexer,
+    pub(crate) flag_index: FlagIndex,
+}
+
+impl Replay {
+    /// Starts replaying the compaction whose start marker is `tx`, in segment `segment_id`.
+    pub(crate) fn new(opts: &Options, tx: &TxRecord, segment_id: u64, indexer: &Indexer) -> Self {
+        Self {
+            version: tx.header.id,
+            segment_id,
+            indexer: indexer.empty(),
+            flag_index: FlagIndex::new(opts.indexed_flags),
+        }
+    }
+
+    /// Returns true if `tx` is a record of the compaction: a commit that followed it has a
+    /// later version, which means that the compaction failed before its end marker.
+    pub(crate) fn holds(&self, tx: &TxRecord) -> bool {
+        tx.header.id <= self.version
+    }
+
+    /// Replaces the index of the store with the one replayed, and drops the segments before
+    /// the compaction.
+    pub(crate) fn finish(
+        self,
+        indexer: &mut Indexer,
+        flag_index: &mut FlagIndex,
+        index_checkpoint: &IndexCheckpoint,
+        segment_keys: &mut SegmentKeyRanges,
+    ) -> Result<()> {
+        indexer.replace(self.indexer, self.version);
+        *flag_index = self.flag_index;
+        index_checkpoint.mark_all();
+        segment_keys.remove_before(self.segment_id)
+    }
+}
+
+/// Bytes of the commit log written between two reports of the progress of a compaction.
+const PROGRESS_BYTES: u64 = 1 << 20;
+
+/// What a compaction of the commit log has done so far, told to the callback given to
+/// [`Store::compact`](crate::Store::compact).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CompactionProgress {
+    pub versions_written: usize, // Number of versions of the keys rewritten so far.
+    pub versions: usize,         // Number of versions of the keys the compaction rewrites.
+    pub bytes_written: u64,      // Bytes of the commit log written so far.
+}
+
+/// Callback told the progress of a compaction, about every MiB written and once all the
+/// versions are rewritten. It is called while the commits wait, and should not block.
+pub type ProgressCallback = Arc<dyn Fn(&CompactionProgress) + Send + Sync>;
+
+/// Tells the progress of a compaction to its callback, if any.
+struct Reporter {
+    progress: CompactionProgress,
+    callback: Option<ProgressCallback>,
+    reported_bytes: u64, // Bytes written when the progress was last reported.
+}
+
+impl Reporter {
+    fn written(&mut self, versions: usize, bytes: u64) {
+        self.progress.versions_written += versions;
+        self.progress.bytes_written += bytes;
+        if self.progress.bytes_written - self.reported_bytes >= PROGRESS_BYTES {
+            self.report();
+        }
+    }
+
+    fn report(&mut self) {
+        self.reported_bytes = self.progress.bytes_written;
+        if let Some(callback) = &self.callback {
+            callback(&self.progress);
+        }
+    }
+}
+
+/// What a [`CompactionFilter`] does with a version of a key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CompactionDecision {
+    /// Rewrite the version as it is.
+    Keep,
+    /// Rewrite the version as a deletion of the key.
+    Remove,
+    /// Rewrite the version with this value, compressed as the rules of the key say.
+    Replace(Vec<u8>),
+}
+
+/// A filter of the versions of the keys rewritten by the compactions of the commit log, set
+/// with [`Store::set_compaction_filter`](crate::Store::set_compaction_filter), to expire or
+/// scrub the values by the rules of an application.
+///
+/// It is called with each version of a user key that holds a value, with its value and its
+/// commit timestamp, while the commits wait. A version removed is rewritten as a deletion at
+/// its version, so that the older versions of its key are not read in its place. The
+/// versions left out of the compaction, and the ones the compaction drops, are not filtered,
+/// and the mirrors and the watches are not told of the changes.
+pub trait CompactionFilter: Send + Sync {
+    fn filter(&self, key: &[u8], value: &[u8], ts: u64) -> CompactionDecision;
+}
+
+/// Returns the number of sealed segments of the commit log of a store that persists its data
+/// that are less than a quarter full, see `Options::tiny_segment_merge`.
+pub(crate) fn tiny_segments(opts: &Options) -> Result<usize> {
+    let mut segments =
+        SegmentRef::read_segments_from_directory(&*opts.vfs, &opts.dir.join("clog"))?;
+    // The last segment is the active one.
+    segments.pop();
+    let mut tiny = 0;
+    for segment in &segments {
+        let len = opts.vfs.metadata(&segment.file_path)?.len();
+        if len.saturating_sub(segment.file_header_offset) < opts.max_segment_size / 4 {
+            tiny += 1;
+        }
+    }
+    Ok(tiny)
+}
+
+/// Queues a merge of the tiny sealed segments of the commit log as a background job, if the
+/// store of `core` was opened with at least `Options::tiny_segment_merge` of them.
+///
+/// The offsets of the values follow from the segment holding them, so the segments cannot be
+/// concatenated as they are: they are merged by compacting the log, which rewrites the
+/// versions kept into segments filled up to `Options::max_segment_size`, and switches to them
+/// atomically once its end marker is durable. Commits wait while it rewrites the versions.
+pub(crate) fn merge_tiny_segments(core: &Arc<Core>) {
+    let threshold = core.opts.tiny_segment_merge;
+    if threshold == 0 || core.is_read_only() || !core.opts.should_persist_data() {
+        return;
+    }
+    match tiny_segments(&core.opts) {
+        Ok(tiny) if tiny >= threshold => (),
+        _ => return,
+    }
+    let job = core.clone();
+    core.jobs.submit(JobKind::Compaction, async move {
+        // A failed merge is reported by its activity, and the next opening tries again.
+        let _ = compact(&job, (Bound::Unbounded, Bound::Unbounded), None).await;
+    });
+}
+
+/// Compacts the commit log of `core`, see `Store::compact`, and returns the number of bytes
+/// of the commit log reclaimed.
+pub(crate) async fn compact(
+    core: &Arc<Core>,
+    range: KeyRange,
+    progress: Option<ProgressCallback>,
+) -> Result<u64> {
+    let activity = Activity::start(events::COMPACTION, "log compaction", &[]);
+    let reclaimed = compact_log(core, range, progress).await;
+    activity.finish(reclaimed, |reclaimed| vec![("bytes_reclaimed", *reclaimed)])
+}
+
+async fn compact_log(
+    core: &Arc<Core>,
+    range: KeyRange,
+    progress: Option<ProgressCallback>,
+) -> Result<u64> {
+    if core.is_closed() {
+        return Err(Error::StoreClosed);
+    }
+    core.invariants.check()?;
+    if core.is_read_only() {
+        return Err(Error::StoreReadOnly);
+    }
+    if !core.opts.should_persist_data() {
+        return Ok(0);
+    }
+
+    let log_bytes = gc::log_bytes(&core.opts)?;
+    let segment_id = {
+        // Commits are held while the versions are rewritten, so that the index holds all the
+        // transactions of the commit log, and none is written between the markers.
+        let _commits = core.oracle.write_lock.lock().await;
+        core.oracle.wait_indexed().await;
+        rewrite(core, &range, progress)?
+    };
+
+    // The end marker is durable: the segments before the compaction are no longer replayed.
+    let clog = core.clog.as_ref().unwrap().read();
+    clear::remove_segments(
+        &clog,
+        &mut core.segment_keys.lock(),
+        &core.redundancy,
+        segment_id,
+    )?;
+    drop(clog);
+    core.quota.measure()?;
+    Ok(log_bytes.saturating_sub(gc::log_bytes(&core.opts)?))
+}
+
+/// Rewrites the versions the index keeps in a new segment of the commit log, between the
+/// markers of the compaction, and rebuilds the index with their new offsets. It returns the
+/// segment the compaction starts.
+fn rewrite(core: &Arc<Core>, range: &KeyRange, progress: Option<ProgressCallback>) -> Result<u64> {
+    let segment_id = {
+        let clog = core.clog.as_ref().unwrap().read();
+        clog.start_segment()
+            .map_err(|err| core.log_failed(&clog, err.into()))?
+    };
+
+    // The versions kept are the ones `shrink_index` keeps, in the range.
+    let mut indexer = core.indexer.write();
+    let version = indexer.version();
+    let last_ts = indexer.last_ts();
+    let merged = merge::collapse(core, &*indexer, indexer.index.iter())?;
+    let mut versions = core.retained_versions(&indexer, &merged, range)?;
+    versions.sort_by_key(|(_, _, version, _)| *version);
+    let mut reporter = Reporter {
+        progress: CompactionProgress {
+            versions: versions.len(),
+            ..CompactionProgress::default()
+        },
+        callback: progress,
+        reported_bytes: 0,
+    };
+
+    let filter = core.compaction_filter.read().clone();
+    let mut rewritten = Rewritten {
+        versions: Vec::with_capacity(versions.len()),
+        flag_index: FlagIndex::new(core.opts.indexed_flags),
+    };
+
+    write_marker(core, START, version, last_ts)?;
+    let mut record: Vec<(Vec<u8>, Entry)> = Vec::new();
+    let mut record_version = (0, 0);
+    for (index_key, value, version, ts) in versions {
+        if !record.is_empty() && record_version.0 != version {
+            let record = std::mem::take(&mut record);
+            relocate(core, record, record_version, &mut rewritten, &mut reporter)?;
+        }
+        if let Some(entry) = entry(core, filter.as_deref(), &index_key, &value, ts)? {
+            record.push((index_key, entry));
+            record_version = (version, ts);
+        }
+    }
+    if !record.is_empty() {
+        relocate(core, record, record_version, &mut rewritten, &mut reporter)?;
+    }
+    reporter.report();
+
+    // The end marker syncs the records of the compaction, and the commits before it.
+    let offset = write_marker(core, END, version, last_ts)?;
+    let clog = core.clog.as_ref().unwrap().read();
+    clog.sync()
+        .map_err(|err| core.log_failed(&clog, err.into()))?;
+    drop(clog);
+    core.durable.synced(version);
+    core.redundancy
+        .seal_before(offset / core.opts.max_segment_size, false);
+
+    indexer.rebuild(rewritten.versions)?;
+    // The flags of the versions the filter removed are dropped.
+    *core.flag_index.write() = rewritten.flag_index;
+    core.index_checkpoint.mark_all();
+    Ok(segment_id)
+}
+
+/// Returns the entry rewriting a version of an index key, as the filter decides, or None if
+/// it is not rewritten.
+fn entry(
+    core: &Arc<Core>,
+    filter: Option<&dyn CompactionFilter>,
+    index_key: &[u8],
+    value: &Bytes,
+    ts: u64,
+) -> Result<Option<Entry>> {
+    let mut value_ref = ValueRef::new(core.clone());
+    value_ref.decode(ts, value)?;
+    // A clear marker is only replayed to drop the keys before it, which are compacted away.
+    if clear::is_clear(value_ref.key_value_metadata.as_ref()) {
+        return Ok(None);
+    }
+    let key = core.keys.decode(index_key.to_vec())?;
+    let mut entry = Entry {
+        key: Bytes::from(key),
+        metadata: value_ref.key_value_metadata.clone(),
+        value: Bytes::from(value_ref.resolve_stored(None)?),
+        ts,
+    };
+    let filtered = entry.metadata.as_ref().map_or(true, |md| {
+        !md.deleted() && !md.merged() && !md.renamed() && !md.range_deleted()
+    });
+    let Some(filter) = filter.filter(|_| filtered && !is_system_key(&entry.key)) else {
+        return Ok(Some(entry));
+    };
+    match filter.filter(&entry.key, &value_ref.resolve()?, ts) {
+        CompactionDecision::Keep => (),
+        CompactionDecision::Remove => {
+            entry.value = Bytes::new();
+            entry.metadata = None;
+            entry.mark_delete();
+        }
+        CompactionDecision::Replace(value) => {
+            entry.value = Bytes::from(value);
+            if let Some(md) = &mut entry.metadata {
+                md.as_uncompressed();
+            }
+            core.compressor
+                .compress_entries(std::slice::from_mut(&mut entry))?;
+        }
+    }
+    Ok(Some(entry))
+}
+
+/// The versions rewritten by a compaction, at their new offsets, and the flags of the keys
+/// as of the last of them.
+struct Rewritten {
+    versions: Vec<IndexVersion>,
+    flag_index: FlagIndex,
+}
+
+/// Writes the entries of a version in a record of the commit log, and adds them to the
+/// versions the index is rebuilt with, at their new offsets.
+fn relocate(
+    core: &Arc<Core>,
+    record: Vec<(Vec<u8>, Entry)>,
+    (version, ts): (u64, u64),
+    rewritten: &mut Rewritten,
+    reporter: &mut Reporter,
+) -> Result<()> {
+    let (index_keys, entries): (Vec<_>, Vec<_>) = record.into_iter().unzip();
+    rewritten.flag_index.apply_entries(&entries);
+    let tx_record = TxRecord::new_with_entries(entries, version, ts);
+    reporter.written(index_keys.len(), tx_record.encoded_len() as u64);
+    let mut offsets = HashMap::new();
+    let offset = append(core, &tx_record, &mut offsets)?;
+    core.segment_keys.lock().record(
+        offset / core.opts.max_segment_size,
+        tx_record.entries.iter().map(|entry| &entry.key[..]),
+    )?;
+    for (index_key, entry) in index_keys.into_iter().zip(&tx_record.entries) {
+        let value = ValueRef::encode(
+            &entry.key,
+            &entry.value,
+            entry.metadata.as_ref(),
+            &offsets,
+            core.opts.max_value_threshold,
+        );
+        rewritten.versions.push((index_key, value, version, ts));
+    }
+    Ok(())
+}
+
+/// Writes a marker of the compaction, and returns its offset.
+fn write_marker(core: &Arc<Core>, marker: &[u8], version: u64, ts: u64) -> Result<u64> {
+    let tx_record = TxRecord::new_with_entries(vec![marker_entry(marker)?], version, ts);
+    append(core, &tx_record, &mut HashMap::new())
+}
+
+/// Appends a record to the commit log, as the writer does, and returns its offset. It is
+/// synced with the end marker.
+fn append(
+    core: &Arc<Core>,
+    tx_record: &TxRecord,
+    offsets: &mut HashMap<Bytes, usize>,
+) -> Result<u64> {
+    core.append_log(tx_record, offsets, Durability::Weak)
+        .map_err(|err| core.log_failed(&core.clog.as_ref().unwrap().read(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+    use std::sync::Arc;
+
+    use parking_lot::Mutex;
+
+    use super::{
+        tiny_segments, CompactionDecision, CompactionFilter, CompactionProgress, ProgressCallback,
+    };
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+
+    use tempdir::TempDir;
+
+    async fn write(store: &Store, value: u8) {
+        for i in 0..100u32 {
+            let mut txn = store.begin().unwrap();
+            txn.set(&i.to_be_bytes(), &[value; 100]).unwrap();
+            txn.commit().await.unwrap();
+        }
+    }
+
+    fn get(store: &Store, i: u32) -> Option<Vec<u8>> {
+        store.begin().unwrap().get(&i.to_be_bytes()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn compaction_keeps_the_live_versions() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.max_segment_size = 16 * 1024;
+        let store = Store::new(opts.clone()).expect("should create store");
+
+        write(&store, 1).await;
+        // The segments left from before a checkpoint are replayed past too.
+        store.checkpoint_index().await.unwrap();
+        write(&store, 2).await;
+        let mut txn = store.begin().unwrap();
+        txn.delete(&0u32.to_be_bytes()).unwrap();
+        txn.commit().await.unwrap();
+
+        assert!(store.compact_log().await.unwrap() > 0);
+        assert_eq!(get(&store, 0), None);
+        assert_eq!(get(&store, 1).unwrap(), [2; 100]);
+
+        // The store goes on committing after the compaction.
+        let mut txn = store.begin().unwrap();
+        txn.set(&1u32.to_be_bytes(), &[3; 100]).unwrap();
+        txn.commit().await.unwrap();
+        let last = txn.commit_token().unwrap().version();
+        store.close().await.unwrap();
+
+        let store = Store::new(opts).expect("should reopen store");
+        assert_eq!(get(&store, 0), None);
+        assert_eq!(get(&store, 1).unwrap(), [3; 100]);
+        assert_eq!(get(&store, 99).unwrap(), [2; 100]);
+        let mut txn = store.begin().unwrap();
+        txn.set(&2u32.to_be_bytes(), &[3; 100]).unwrap();
+        txn.commit().await.unwrap();
+        assert!(txn.commit_token().unwrap().version() > last);
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn compaction_of_a_range_keeps_the_history_outside_it() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.max_segment_size = 16 * 1024;
+        let store = Store::new(opts).expect("should create store");
+
+        write(&store, 1).await;
+        let first = store.inner.as_ref().unwrap().core.indexer.read().version();
+        write(&store, 2).await;
+
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let progress: ProgressCallback = {
+            let reports = reports.clone();
+            Arc::new(move |progress: &CompactionProgress| reports.lock().push(progress.clone()))
+        };
+        let end = 50u32.to_be_bytes();
+        let range = (Bound::Unbounded, Bound::Excluded(&end[..]));
+        assert!(store.compact(range, Some(progress)).await.unwrap() > 0);
+
+        // The keys of the range keep their latest version, the others all of theirs.
+        let txn = store.begin().unwrap();
+        assert_eq!(
+            txn.get_at_version(&0u32.to_be_bytes(), first).unwrap(),
+            None
+        );
+        let old = txn.get_at_version(&99u32.to_be_bytes(), first).unwrap();
+        assert_eq!(old.unwrap(), [1; 100]);
+        assert_eq!(get(&store, 0).unwrap(), [2; 100]);
+
+        let reports = reports.lock().clone();
+        let last = reports.last().unwrap();
+        assert_eq!(last.versions, 150);
+        assert_eq!(last.versions_written, last.versions);
+        assert!(reports.len() == 1 && last.bytes_written > 150 * 100);
+        store.close().await.unwrap();
+    }
+
+    struct Scrubber;
+
+    impl CompactionFilter for Scrubber {
+        fn filter(&self, key: &[u8], value: &[u8], _ts: u64) -> CompactionDecision {
+            match key.first() {
+                Some(b't') => CompactionDecision::Remove,
+                Some(b's') => CompactionDecision::Replace(value.to_ascii_uppercase()),
+                _ => CompactionDecision::Keep,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn compaction_filter_removes_and_replaces_values() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        let store = Store::new(opts.clone()).expect("should create store");
+        store.set_compaction_filter(Some(Arc::new(Scrubber)));
+
+        let mut txn = store.begin().unwrap();
+        txn.set(b"keep", b"value").unwrap();
+        txn.set(b"temp", b"value").unwrap();
+        txn.set(b"secret", b"value").unwrap();
+        txn.commit().await.unwrap();
+        store.compact_log().await.unwrap();
+
+        let read = |store: &Store, key: &[u8]| store.begin().unwrap().get(key).unwrap();
+        assert_eq!(read(&store, b"keep").unwrap(), b"value");
+        assert_eq!(read(&store, b"temp"), None);
+        assert_eq!(read(&store, b"secret").unwrap(), b"VALUE");
+        store.close().await.unwrap();
+
+        let store = Store::new(opts).expect("should reopen store");
+        assert_eq!(read(&store, b"keep").unwrap(), b"value");
+        assert_eq!(read(&store, b"temp"), None);
+        assert_eq!(read(&store, b"secret").unwrap(), b"VALUE");
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn tiny_segments_are_merged_at_opening() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.max_segment_size = 64 * 1024;
+
+        // Segments started after a single commit, as clears start them, are left barely filled.
+        let store = Store::new(opts.clone()).expect("should create store");
+        for i in 0..6u32 {
+            let mut txn = store.begin().unwrap();
+            txn.set(&i.to_be_bytes(), &[1; 100]).unwrap();
+            txn.commit().await.unwrap();
+            let clog = store.inner.as_ref().unwrap().core.clog.as_ref().unwrap();
+            clog.read().start_segment().unwrap();
+        }
+        store.close().await.unwrap();
+        assert_eq!(tiny_segments(&opts).unwrap(), 6);
+
+        opts.tiny_segment_merge = 4;
+        let store = Store::new(opts.clone()).expect("should open store");
+        store.close().await.unwrap();
+        assert_eq!(tiny_segments(&opts).unwrap(), 0);
+
+        let store = Store::new(opts.clone()).expect("should reopen store");
+        for i in 0..6u32 {
+            assert_eq!(get(&store, i).unwrap(), [1; 100]);
+        }
+        store.close().await.unwrap();
+    }
+}
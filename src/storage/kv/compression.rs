@@ -0,0 +1,496 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use hashbrown::HashMap;
+
+use crate::storage::kv::{
+    entry::Entry,
+    error::{Error, Result},
+    meta::Metadata,
+    util::calculate_crc32,
+};
+
+/// Default zstd compression level used by [`CompressionRule::new`].
+pub const DEFAULT_COMPRESSION_LEVEL: i32 = 3;
+
+/// The compression format applied to values stored under a key prefix.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CompressionFormat {
+    /// Values are stored as is.
+    None = 0,
+    /// Values are compressed with zstd.
+    Zstd = 1,
+}
+
+impl CompressionFormat {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(CompressionFormat::None),
+            1 => Some(CompressionFormat::Zstd),
+            _ => None,
+        }
+    }
+}
+
+/// A `CompressionRule` describes how values whose keys start with `prefix` are compressed.
+///
+/// When several rules match a key, the rule with the longest prefix wins. A rule with an
+/// empty prefix acts as the default for all keys. Keys not matched by any rule are stored
+/// uncompressed.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CompressionRule {
+    pub prefix: Vec<u8>,             // Key prefix the rule applies to.
+    pub format: CompressionFormat,   // Compression format for matching values.
+    pub level: i32,                  // Compression level passed to the compressor.
+    pub dictionary: Option<Vec<u8>>, // Optional dictionary shared by all values of the prefix.
+}
+
+impl CompressionRule {
+    /// Creates a new rule for the given prefix using the default compression level.
+    pub fn new(prefix: &[u8], format: CompressionFormat) -> Self {
+        Self {
+            prefix: prefix.to_vec(),
+            format,
+            level: DEFAULT_COMPRESSION_LEVEL,
+            dictionary: None,
+        }
+    }
+
+    /// Sets the compression level of the rule.
+    pub fn with_level(mut self, level: i32) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Sets the dictionary of the rule.
+    pub fn with_dictionary(mut self, dictionary: Vec<u8>) -> Self {
+        self.dictionary = Some(dictionary);
+        self
+    }
+
+    /// Returns the id used to refer to the rule's dictionary from value metadata,
+    /// or 0 if the rule has no dictionary.
+    fn dictionary_id(&self) -> u32 {
+        self.dictionary.as_ref().map_or(0, |d| dictionary_id(d))
+    }
+}
+
+/// Computes the id of a dictionary. The id 0 is reserved for "no dictionary".
+fn dictionary_id(dictionary: &[u8]) -> u32 {
+    calculate_crc32(dictionary).max(1)
+}
+
+/// Encodes compression rules into a byte vector so they can be stored in the manifest.
+///
+/// [count: u32] followed by, for every rule,
+/// [prefix_len: u32][prefix][format: u8][level: i32][dict_len: u32][dict]
+/// where a `dict_len` of u32::MAX means the rule has no dictionary.
+pub(crate) fn encode_rules(rules: &[CompressionRule]) -> Vec<u8> {
+    let mut buf = BytesMut::new();
+    buf.put_u32(rules.len() as u32);
+    for rule in rules {
+        buf.put_u32(rule.prefix.len() as u32);
+        buf.put(rule.prefix.as_slice());
+        buf.put_u8(rule.format as u8);
+        buf.put_i32(rule.level);
+        match &rule.dictionary {
+            Some(dictionary) => {
+                buf.put_u32(dictionary.len() as u32);
+                buf.put(dictionary.as_slice());
+            }
+            None => buf.put_u32(u32::MAX),
+        }
+    }
+    buf.to_vec()
+}
+
+/// Decodes compression rules previously encoded with [`encode_rules`].
+pub(crate) fn decode_rules(mut buf: &[u8]) -> Result<Vec<CompressionRule>> {
+    fn read_bytes(buf: &mut &[u8], len: usize) -> Result<Vec<u8>> {
+        if buf.remaining() < len {
+            return Err(Error::CorruptedMetadata);
+        }
+        let bytes = buf[..len].to_vec();
+        buf.advance(len);
+        Ok(bytes)
+    }
+
+    if buf.remaining() < 4 {
+        return Err(Error::CorruptedMetadata);
+    }
+    let count = buf.get_u32() as usize;
+    let mut rules = Vec::with_capacity(count);
+    for _ in 0..count {
+        if buf.remaining() < 4 {
+            return Err(Error::CorruptedMetadata);
+        }
+        let prefix_len = buf.get_u32() as usize;
+        let prefix = read_bytes(&mut buf, prefix_len)?;
+        if buf.remaining() < 9 {
+            return Err(Error::CorruptedMetadata);
+        }
+        let format = CompressionFormat::from_u8(buf.get_u8()).ok_or(Error::CorruptedMetadata)?;
+        let level = buf.get_i32();
+        let dictionary = match buf.get_u32() {
+            u32::MAX => None,
+            len => Some(read_bytes(&mut buf, len as usize)?),
+        };
+        rules.push(CompressionRule {
+            prefix,
+            format,
+            level,
+            dictionary,
+        });
+    }
+
+    if buf.has_remaining() {
+        return Err(Error::CorruptedMetadata);
+    }
+
+    Ok(rules)
+}
+
+/// Checks that the rules can be applied by this build of the crate, which only compresses
+/// values with zstd if its `zstd` feature is enabled.
+pub(crate) fn check_rules(rules: &[CompressionRule]) -> Result<()> {
+    if !cfg!(feature = "zstd") && rules.iter().any(|r| r.format == CompressionFormat::Zstd) {
+        return Err(Error::InvalidOptions(
+            "zstd compression requires the zstd feature".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// The zstd codec.
+#[cfg(feature = "zstd")]
+mod codec {
+    use zstd::dict::{DecoderDictionary, EncoderDictionary};
+
+    use crate::storage::kv::error::{Error, Result};
+
+    pub(super) type Encoder = EncoderDictionary<'static>;
+    pub(super) type Decoder = DecoderDictionary<'static>;
+
+    pub(super) fn encoder(dictionary: &[u8], level: i32) -> Encoder {
+        EncoderDictionary::copy(dictionary, level)
+    }
+
+    pub(super) fn decoder(dictionary: &[u8]) -> Decoder {
+        DecoderDictionary::copy(dictionary)
+    }
+
+    pub(super) fn compress(value: &[u8], level: i32, encoder: Option<&Encoder>) -> Result<Vec<u8>> {
+        match encoder {
+            Some(encoder) => zstd::bulk::Compressor::with_prepared_dictionary(encoder)
+                .and_then(|mut c| c.compress(value)),
+            None => zstd::bulk::compress(value, level),
+        }
+        .map_err(|e| Error::CompressionError(e.to_string()))
+    }
+
+    pub(super) fn decompress(data: &[u8], decoder: Option<&Decoder>) -> Result<Vec<u8>> {
+        let capacity = zstd::zstd_safe::get_frame_content_size(data)
+            .ok()
+            .flatten()
+            .ok_or_else(|| Error::CompressionError("missing frame content size".to_string()))?
+            as usize;
+
+        let decompressor = match decoder {
+            Some(decoder) => zstd::bulk::Decompressor::with_prepared_dictionary(decoder),
+            None => zstd::bulk::Decompressor::new(),
+        };
+        decompressor
+            .and_then(|mut d| d.decompress(data, capacity))
+            .map_err(|e| Error::CompressionError(e.to_string()))
+    }
+}
+
+/// The codec of a build without the `zstd` feature, which fails to compress or decompress.
+#[cfg(not(feature = "zstd"))]
+mod codec {
+    use crate::storage::kv::error::{Error, Result};
+
+    pub(super) struct Encoder;
+    pub(super) struct Decoder;
+
+    pub(super) fn encoder(_: &[u8], _: i32) -> Encoder {
+        Encoder
+    }
+
+    pub(super) fn decoder(_: &[u8]) -> Decoder {
+        Decoder
+    }
+
+    pub(super) fn compress(_: &[u8], _: i32, _: Option<&Encoder>) -> Result<Vec<u8>> {
+        Err(unavailable())
+    }
+
+    pub(super) fn decompress(_: &[u8], _: Option<&Decoder>) -> Result<Vec<u8>> {
+        Err(unavailable())
+    }
+
+    fn unavailable() -> Error {
+        Error::CompressionError("zstd support requires the zstd feature".to_string())
+    }
+}
+
+/// A rule prepared for compressing values.
+struct PreparedRule {
+    rule: CompressionRule,
+    dictionary_id: u32,
+    encoder: Option<codec::Encoder>,
+}
+
+/// `Compressor` applies the configured compression rules to the values of a transaction
+/// before they are written, and decompresses values when they are read back.
+///
+/// Dictionaries of rules that were configured in earlier runs of the store are kept around
+/// for decompression, so that changing the rules does not make older values unreadable.
+pub(crate) struct Compressor {
+    /// Rules sorted by descending prefix length so that the first match is the longest one.
+    rules: Vec<PreparedRule>,
+    /// Decoder dictionaries indexed by dictionary id.
+    decoders: HashMap<u32, codec::Decoder>,
+}
+
+impl Compressor {
+    /// Creates a compressor from the current rules, and the rules of earlier runs whose
+    /// dictionaries may still be referenced by stored values.
+    pub(crate) fn new(rules: &[CompressionRule], historic: &[CompressionRule]) -> Self {
+        let mut decoders = HashMap::new();
+        for rule in rules.iter().chain(historic.iter()) {
+            if let Some(dictionary) = &rule.dictionary {
+                decoders
+                    .entry(dictionary_id(dictionary))
+                    .or_insert_with(|| codec::decoder(dictionary));
+            }
+        }
+
+        let mut rules: Vec<PreparedRule> = rules
+            .iter()
+            .map(|rule| PreparedRule {
+                rule: rule.clone(),
+                dictionary_id: rule.dictionary_id(),
+                encoder: rule
+                    .dictionary
+                    .as_ref()
+                    .map(|d| codec::encoder(d, rule.level)),
+            })
+            .collect();
+        rules.sort_by_key(|r| std::cmp::Reverse(r.rule.prefix.len()));
+
+        Self { rules, decoders }
+    }
+
+    /// Returns true if no values will be compressed.
+    fn is_disabled(&self) -> bool {
+        self.rules
+            .iter()
+            .all(|r| r.rule.format == CompressionFormat::None)
+    }
+
+    /// Finds the rule with the longest prefix matching the key.
+    fn rule_for(&self, key: &[u8]) -> Option<&PreparedRule> {
+        self.rules.iter().find(|r| key.starts_with(&r.rule.prefix))
+    }
+
+    /// Compresses the values of the given entries in place, according to the rule matching
+    /// each key. Values are only replaced if the compressed form is smaller, so incompressible
+    /// data is stored as is.
+    pub(crate) fn compress_entries(&self, entries: &mut [Entry]) -> Result<()> {
+        if self.is_disabled() {
+            return Ok(());
+        }
+
+        for entry in entries.iter_mut() {
+            if entry.value.is_empty() || entry.is_rename() {
+                continue;
+            }
+            if let Some(md) = &entry.metadata {
+                if md.deleted() {
+                    continue;
+                }
+            }
+
+            let prepared = match self.rule_for(&entry.key) {
+                Some(prepared) if prepared.rule.format == CompressionFormat::Zstd => prepared,
+                _ => continue,
+            };
+
+            let encoder = prepared.encoder.as_ref();
+            let compressed = codec::compress(&entry.value, prepared.rule.level, encoder)?;
+
+            if compressed.len() >= entry.value.len() {
+                continue;
+            }
+
+            entry.value = Bytes::from(compressed);
+            entry
+                .metadata
+                .get_or_insert_with(Metadata::new)
+                .as_compressed(prepared.rule.format as u8, prepared.dictionary_id);
+        }
+
+        Ok(())
+    }
+
+    /// Decompresses a value stored with the given format and dictionary id.
+    pub(crate) fn decompress(
+        &self,
+        format: u8,
+        dictionary_id: u32,
+        data: &[u8],
+    ) -> Result<Vec<u8>> {
+        match CompressionFormat::from_u8(format) {
+            Some(CompressionFormat::None) => Ok(data.to_vec()),
+            Some(CompressionFormat::Zstd) => {
+                let decoder = match dictionary_id {
+                    0 => None,
+                    _ => Some(self.decoders.get(&dictionary_id).ok_or_else(|| {
+                        Error::CompressionError(format!(
+                            "unknown compression dictionary: {}",
+                            dictionary_id
+                        ))
+                    })?),
+                };
+                codec::decompress(data, decoder)
+            }
+            None => Err(Error::CompressionError(format!(
+                "unknown compression format: {}",
+                format
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+
+    use tempdir::TempDir;
+
+    fn create_temp_directory() -> TempDir {
+        TempDir::new("test").unwrap()
+    }
+
+    #[cfg(feature = "zstd")]
+    fn compressible_value() -> Vec<u8> {
+        "ts=1700000000,cpu=0.5,mem=0.25;"
+            .repeat(64)
+            .as_bytes()
+            .to_vec()
+    }
+
+    #[test]
+    fn encode_decode_rules() {
+        let rules = vec![
+            CompressionRule::new(b"ts/", CompressionFormat::Zstd).with_level(9),
+            CompressionRule::new(b"blob/", CompressionFormat::None),
+            CompressionRule::new(b"", CompressionFormat::Zstd).with_dictionary(vec![1, 2, 3]),
+        ];
+
+        let decoded = decode_rules(&encode_rules(&rules)).unwrap();
+        assert_eq!(decoded, rules);
+
+        assert!(decode_rules(&[0, 0, 0, 1]).is_err());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn longest_prefix_wins() {
+        let rules = vec![
+            CompressionRule::new(b"", CompressionFormat::Zstd),
+            CompressionRule::new(b"blob/", CompressionFormat::None),
+        ];
+        let compressor = Compressor::new(&rules, &[]);
+
+        let value = compressible_value();
+        let mut entries = vec![Entry::new(b"ts/1", &value), Entry::new(b"blob/1", &value)];
+        compressor.compress_entries(&mut entries).unwrap();
+
+        let (format, dict_id) = entries[0].metadata.as_ref().unwrap().compression().unwrap();
+        assert!(entries[0].value.len() < value.len());
+        assert_eq!(
+            compressor
+                .decompress(format, dict_id, &entries[0].value)
+                .unwrap(),
+            value
+        );
+
+        assert!(entries[1].metadata.is_none());
+        assert_eq!(entries[1].value.as_ref(), value.as_slice());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn incompressible_values_are_stored_as_is() {
+        let rules = vec![CompressionRule::new(b"", CompressionFormat::Zstd)];
+        let compressor = Compressor::new(&rules, &[]);
+
+        let value: Vec<u8> = (0..64u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+        let mut entries = vec![Entry::new(b"k", &value)];
+        compressor.compress_entries(&mut entries).unwrap();
+
+        assert!(entries[0].metadata.is_none());
+        assert_eq!(entries[0].value.as_ref(), value.as_slice());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[tokio::test]
+    async fn compressed_values_survive_reopen() {
+        let temp_dir = create_temp_directory();
+
+        let dictionary = compressible_value();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.compression = vec![
+            CompressionRule::new(b"ts/", CompressionFormat::Zstd)
+                .with_dictionary(dictionary.clone()),
+            CompressionRule::new(b"blob/", CompressionFormat::None),
+        ];
+
+        let value = compressible_value();
+        let small = b"tiny".to_vec();
+        {
+            let store = Store::new(opts.clone()).expect("should create store");
+            let mut txn = store.begin().unwrap();
+            txn.set(b"ts/1", &value).unwrap();
+            txn.set(b"ts/2", &small).unwrap();
+            txn.set(b"blob/1", &value).unwrap();
+            txn.commit().await.unwrap();
+
+            let txn = store.begin().unwrap();
+            assert_eq!(txn.get(b"ts/1").unwrap().unwrap(), value);
+            assert_eq!(txn.get(b"ts/2").unwrap().unwrap(), small);
+            assert_eq!(txn.get(b"blob/1").unwrap().unwrap(), value);
+            store.close().await.unwrap();
+        }
+
+        // Reopen without any compression rules: values written with the old
+        // dictionary must still be readable.
+        opts.compression = Vec::new();
+        let store = Store::new(opts).expect("should reopen store");
+        let txn = store.begin().unwrap();
+        assert_eq!(txn.get(b"ts/1").unwrap().unwrap(), value);
+        assert_eq!(txn.get(b"ts/2").unwrap().unwrap(), small);
+        assert_eq!(txn.get(b"blob/1").unwrap().unwrap(), value);
+
+        let results = txn.scan(&b"ts/"[..]..&b"ts0"[..], None).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].1, value);
+        store.close().await.unwrap();
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    #[test]
+    fn zstd_rules_require_the_feature() {
+        let temp_dir = create_temp_directory();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.compression = vec![CompressionRule::new(b"", CompressionFormat::Zstd)];
+        assert!(matches!(Store::new(opts), Err(Error::InvalidOptions(_))));
+    }
+}
@@ -0,0 +1,204 @@
+use std::sync::Arc;
+
+use hashbrown::HashSet;
+
+use crate::storage::kv::{
+    clear,
+    error::Result,
+    mirror::{MirrorBatch, Mutation},
+    store::Core,
+    transaction::{Mode, Transaction},
+    util::is_system_key,
+};
+
+/// Callback resolving a mutation received for a key with its local value: it is given the
+/// key, the local value and the value of the mutation, `None` standing for a missing or
+/// deleted key, and returns the value to keep, `None` deleting the key.
+pub type ConflictResolver =
+    Arc<dyn Fn(&[u8], Option<&[u8]>, Option<&[u8]>) -> Option<Vec<u8>> + Send + Sync>;
+
+/// How the mutations applied to a store with [`Store::apply_batch`](crate::Store::apply_batch)
+/// are resolved with the local writes of their keys, so that a store synced both ways does not
+/// lose the writes made on each side.
+#[derive(Clone)]
+pub enum ConflictPolicy {
+    /// Apply every mutation over the local value, as the mirrors of a store do.
+    Overwrite,
+    /// Apply a mutation if the batch committed after the latest local version of its key,
+    /// deletions included. The local version is kept on a tie.
+    LastWriterWins,
+    /// Apply a mutation only if its key is missing or deleted locally.
+    KeepLocal,
+    /// Keep the value returned by the resolver.
+    Custom(ConflictResolver),
+}
+
+/// Applies the mutations of a batch in one transaction, and returns the number of mutations
+/// applied, see `Store::apply_batch`. The mutations of a key already applied from the batch
+/// are applied over it, as the batch is in commit order.
+pub(crate) async fn apply_batch(
+    core: &Arc<Core>,
+    batch: &MirrorBatch,
+    policy: &ConflictPolicy,
+) -> Result<usize> {
+    // A clear drops all the local keys, whichever way the mutations are resolved.
+    if batch.cleared {
+        clear::clear(core).await?;
+    }
+    let mode = match policy {
+        ConflictPolicy::Overwrite => Mode::WriteOnly,
+        _ => Mode::ReadWrite,
+    };
+    let mut txn = Transaction::new(core.clone(), mode)?;
+    let mut applied = HashSet::new();
+    for mutation in &batch.mutations {
+        let value = match applied.contains(&mutation.key[..]) {
+            true => mutation.value.clone(),
+            false => match resolve(&txn, batch.commit_ts, mutation, policy)? {
+                Some(value) => value,
+                None => continue,
+            },
+        };
+        // The keys the store keeps for the keyspaces, streams, queues and locks are mirrored
+        // along with the user keys.
+        match (&value, is_system_key(&mutation.key)) {
+            (Some(value), false) => txn.set(&mutation.key, value)?,
+            (Some(value), true) => txn.set_system(&mutation.key, value)?,
+            (None, false) => txn.delete(&mutation.key)?,
+            (None, true) => txn.delete_system(&mutation.key)?,
+        }
+        applied.insert(&mutation.key[..]);
+    }
+    if applied.is_empty() {
+        return Ok(0);
+    }
+    txn.commit().await?;
+    Ok(batch
+        .mutations
+        .iter()
+        .filter(|mutation| applied.contains(&mutation.key[..]))
+        .count())
+}
+
+/// Returns the value to write for a mutation, or None to keep the local value.
+fn resolve(
+    txn: &Transaction,
+    commit_ts: u64,
+    mutation: &Mutation,
+    policy: &ConflictPolicy,
+) -> Result<Option<Option<Vec<u8>>>> {
+    let key = &mutation.key;
+    let resolved = match policy {
+        ConflictPolicy::Overwrite => Some(mutation.value.clone()),
+        ConflictPolicy::LastWriterWins => {
+            (commit_ts > txn.latest_ts(key)?).then(|| mutation.value.clone())
+        }
+        ConflictPolicy::KeepLocal => txn.get(key)?.is_none().then(|| mutation.value.clone()),
+        ConflictPolicy::Custom(resolver) => {
+            let local = txn.get(key)?;
+            let value = resolver(key, local.as_deref(), mutation.value.as_deref());
+            (value != local).then_some(value)
+        }
+    };
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::ConflictPolicy;
+    use crate::storage::kv::mirror::{MirrorBatch, Mutation};
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+    use crate::storage::kv::util::now;
+
+    use tempdir::TempDir;
+
+    fn batch(commit_ts: u64, mutations: &[(&[u8], Option<&[u8]>)]) -> MirrorBatch {
+        MirrorBatch {
+            commit_ts,
+            mutations: mutations
+                .iter()
+                .map(|(key, value)| Mutation {
+                    key: key.to_vec(),
+                    value: value.map(|value| value.to_vec()),
+                })
+                .collect(),
+            cleared: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn batches_are_applied_with_the_conflict_policy() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        let store = Store::new(opts).expect("should create store");
+
+        let before = now() - 1;
+        let mut txn = store.begin().unwrap();
+        txn.set(b"a", b"local").unwrap();
+        txn.set(b"b", b"local").unwrap();
+        txn.commit().await.unwrap();
+        let mut txn = store.begin().unwrap();
+        txn.delete(b"b").unwrap();
+        txn.commit().await.unwrap();
+        let value = |key: &[u8]| store.begin().unwrap().get(key).unwrap();
+
+        // A batch older than the local writes, deletions included, is not applied.
+        let old = batch(
+            before,
+            &[
+                (b"a", Some(b"old")),
+                (b"b", Some(b"old")),
+                (b"c", Some(b"old")),
+            ],
+        );
+        let applied = store
+            .apply_batch(&old, &ConflictPolicy::LastWriterWins)
+            .await
+            .unwrap();
+        assert_eq!(applied, 1);
+        assert_eq!(value(b"a").unwrap(), b"local");
+        assert_eq!(value(b"b"), None);
+        assert_eq!(value(b"c").unwrap(), b"old");
+
+        // The mutations of a key already applied are applied over it.
+        let new = batch(now(), &[(b"a", Some(b"new")), (b"a", None)]);
+        let applied = store
+            .apply_batch(&new, &ConflictPolicy::LastWriterWins)
+            .await
+            .unwrap();
+        assert_eq!(applied, 2);
+        assert_eq!(value(b"a"), None);
+
+        let remote = batch(now(), &[(b"b", Some(b"remote")), (b"c", Some(b"remote"))]);
+        let applied = store
+            .apply_batch(&remote, &ConflictPolicy::KeepLocal)
+            .await
+            .unwrap();
+        assert_eq!(applied, 1);
+        assert_eq!(value(b"b").unwrap(), b"remote");
+        assert_eq!(value(b"c").unwrap(), b"old");
+
+        // The resolver merges the local and remote values.
+        let concat = ConflictPolicy::Custom(Arc::new(|_: &[u8], local, remote| {
+            Some([local.unwrap_or_default(), remote.unwrap_or_default()].concat())
+        }));
+        let remote = batch(now(), &[(b"c", Some(b"+remote"))]);
+        assert_eq!(store.apply_batch(&remote, &concat).await.unwrap(), 1);
+        assert_eq!(value(b"c").unwrap(), b"old+remote");
+
+        let remote = batch(before, &[(b"c", None)]);
+        assert_eq!(
+            store
+                .apply_batch(&remote, &ConflictPolicy::Overwrite)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(value(b"c"), None);
+        store.close().await.unwrap();
+    }
+}
@@ -0,0 +1,154 @@
+use tokio::sync::watch;
+
+use crate::storage::kv::error::{Error, Result};
+
+/// A token of a commit, returned by [`Transaction::commit_token`](crate::Transaction::commit_token)
+/// once the transaction is committed.
+///
+/// The token is the version the transaction committed at: the tokens of the commits of a store
+/// increase with each commit, and are the same on the stores applying its transactions at
+/// their versions, such as its mirrors. A service fronting the store with caches passes the
+/// token of a write along, and a reader elsewhere calls
+/// [`Store::wait_for_version`](crate::Store::wait_for_version) with it before reading, so
+/// that it reads its writes. The token is sent between processes as its version.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CommitToken(u64);
+
+impl CommitToken {
+    /// Returns the token of the commit at `version`.
+    pub fn new(version: u64) -> Self {
+        Self(version)
+    }
+
+    /// Returns the version of the commit.
+    pub fn version(&self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Visible {
+    version: u64, // Last version visible to the transactions begun on the store.
+    closed: bool,
+}
+
+/// `VersionWatch` tracks the last version of the store visible to new transactions, for the
+/// callers waiting for a version to be reached.
+pub(crate) struct VersionWatch {
+    tx: watch::Sender<Visible>,
+}
+
+impl VersionWatch {
+    pub(crate) fn new(version: u64) -> Self {
+        let (tx, _) = watch::channel(Visible {
+            version,
+            closed: false,
+        });
+        Self { tx }
+    }
+
+    /// Records that the commit at `version` is in the index.
+    pub(crate) fn advance(&self, version: u64) {
+        self.tx.send_if_modified(|visible| {
+            let advanced = version > visible.version;
+            if advanced {
+                visible.version = version;
+            }
+            advanced
+        });
+    }
+
+    /// Wakes up the callers waiting for a version, as no commit is visible anymore.
+    pub(crate) fn close(&self) {
+        self.tx.send_modify(|visible| visible.closed = true);
+    }
+
+    pub(crate) fn version(&self) -> u64 {
+        self.tx.borrow().version
+    }
+
+    /// Waits until `version` is visible, or returns `Error::StoreClosed` if the store is
+    /// closed first.
+    pub(crate) async fn wait_for(&self, version: u64) -> Result<()> {
+        let mut rx = self.tx.subscribe();
+        let visible = *rx
+            .wait_for(|visible| visible.closed || visible.version >= version)
+            .await
+            .map_err(|_| Error::StoreClosed)?;
+        match visible.version >= version {
+            true => Ok(()),
+            false => Err(Error::StoreClosed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::CommitToken;
+    use crate::storage::kv::error::Error;
+    use crate::storage::kv::mirror::MirrorTarget;
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+
+    use tempdir::TempDir;
+
+    #[tokio::test]
+    async fn readers_wait_for_the_commit_token() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().join("source");
+        let source = Store::new(opts.clone()).expect("should create store");
+        let mut replica_opts = opts.clone();
+        replica_opts.dir = temp_dir.path().join("replica");
+        source
+            .start_mirror(MirrorTarget::Store(replica_opts.clone()))
+            .unwrap();
+
+        let mut txn = source.begin().unwrap();
+        assert_eq!(txn.commit_token(), None);
+        txn.set(b"a", b"1").unwrap();
+        txn.commit().await.unwrap();
+        let first = txn.commit_token().unwrap();
+        let mut txn = source.begin().unwrap();
+        txn.set(b"a", b"2").unwrap();
+        txn.commit().await.unwrap();
+        let token = txn.commit_token().unwrap();
+        assert!(token > first);
+        assert_eq!(source.latest_commit_token().unwrap(), token);
+        source.wait_for_version(token).await.unwrap();
+
+        // The replica reads the write once it reaches the token.
+        source.stop_mirror().await.unwrap();
+        source.close().await.unwrap();
+        let replica = Arc::new(Store::new(replica_opts).expect("should open replica"));
+        let token = CommitToken::new(token.version());
+        replica.wait_for_version(token).await.unwrap();
+        let txn = replica.begin().unwrap();
+        assert_eq!(txn.get(b"a").unwrap().unwrap(), b"2");
+        drop(txn);
+
+        // A caller waits for the commit of the token.
+        let ahead = CommitToken::new(token.version() + 1);
+        let waiter = tokio::spawn({
+            let replica = replica.clone();
+            async move { replica.wait_for_version(ahead).await }
+        });
+        let mut txn = replica.begin().unwrap();
+        txn.set(b"b", b"1").unwrap();
+        txn.commit().await.unwrap();
+        assert_eq!(txn.commit_token(), Some(ahead));
+        waiter.await.unwrap().unwrap();
+
+        // A caller waiting for a token the store does not reach is woken up when it closes.
+        let ahead = CommitToken::new(ahead.version() + 1);
+        let waiter = tokio::spawn({
+            let replica = replica.clone();
+            async move { replica.wait_for_version(ahead).await }
+        });
+        tokio::task::yield_now().await;
+        replica.close().await.unwrap();
+        assert!(matches!(waiter.await.unwrap(), Err(Error::StoreClosed)));
+    }
+}
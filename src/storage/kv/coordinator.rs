@@ -0,0 +1,420 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use vart::TrieError;
+
+use crate::storage::kv::{
+    entry::{Entry, ValueRef},
+    error::{Error, Result},
+    snapshot::Snapshot,
+    store::Core,
+    transaction::{Mode, PreparedTransaction, Transaction},
+    util::{now, prefix_end, system_key, SYSTEM_KEY_PREFIX},
+};
+
+/// Subsystem prefix of the keys recording the coordinated commits in progress.
+const COORDINATOR_SUBSYSTEM: &[u8] = b"2pc/";
+
+// Kinds of the keys recorded for a coordinated commit.
+const HEADER: u8 = b'h';
+const UNDO_KEY: u8 = b'k';
+const UNDO_VALUE: u8 = b'v';
+
+/// How to undo the write of a key by a coordinated commit.
+struct Undo {
+    key: Bytes,
+    flags: u64,
+    value: Option<Bytes>, // The value the key had before, if it existed.
+}
+
+/// What a store records about a coordinated commit, in the keys written along with the
+/// transaction committed to it.
+struct Marker {
+    participant: u32, // Position of the store among the transactions of the commit.
+    decider: u32,     // Position of the store whose commit decides the outcome.
+    version: u64,     // Version written by the transaction of this store.
+    undo: BTreeMap<u32, Undo>,
+    keys: Vec<Bytes>, // Keys of the marker, removed once the outcome is settled.
+}
+
+fn marker_key(id: u64, kind: u8, index: Option<u32>) -> Result<Bytes> {
+    let mut key = system_key(COORDINATOR_SUBSYSTEM, &id.to_be_bytes())?;
+    key.put_u8(kind);
+    if let Some(index) = index {
+        key.put_u32(index);
+    }
+    Ok(key.freeze())
+}
+
+/// Returns the entries recording the coordinated commit `id` in a prepared transaction: the
+/// positions of the store and of the decider, and the latest committed version of every key
+/// the transaction writes. The commit lock is held, so these versions stay the latest ones.
+fn marker_entries(
+    txn: &PreparedTransaction<'_>,
+    id: u64,
+    participant: u32,
+    decider: u32,
+) -> Result<Vec<Entry>> {
+    let mut header = BytesMut::new();
+    header.put_u32(participant);
+    header.put_u32(decider);
+    header.put_u64(txn.version().unwrap_or_default());
+    let mut entries = vec![Entry::new(&marker_key(id, HEADER, None)?, &header)];
+
+    let snapshot = Snapshot::take(txn.core().clone(), now())?;
+    for (index, entry) in txn.entries().iter().enumerate() {
+        let index = index as u32;
+        let (flags, value) = match snapshot.get(&entry.key[..].into()) {
+            Ok(val_ref) => (
+                val_ref.key_value_metadata().map_or(0, |md| md.flags()),
+                Some(val_ref.resolve_merged(&entry.key, None)?),
+            ),
+            Err(Error::IndexError(TrieError::KeyNotFound)) => (0, None),
+            Err(err) => return Err(err),
+        };
+
+        let mut undo_key = BytesMut::new();
+        undo_key.put_u64(flags);
+        undo_key.put(&entry.key[..]);
+        entries.push(Entry::new(
+            &marker_key(id, UNDO_KEY, Some(index))?,
+            &undo_key,
+        ));
+        if let Some(value) = value {
+            entries.push(Entry::new(
+                &marker_key(id, UNDO_VALUE, Some(index))?,
+                &value,
+            ));
+        }
+    }
+    Ok(entries)
+}
+
+/// Reads the markers of the coordinated commits recorded in a store, by commit.
+fn read_markers(core: &Arc<Core>) -> Result<BTreeMap<u64, Marker>> {
+    let mut start = SYSTEM_KEY_PREFIX.to_vec();
+    start.extend_from_slice(COORDINATOR_SUBSYSTEM);
+    let end = prefix_end(&start).unwrap();
+
+    let txn = Transaction::new(core.clone(), Mode::ReadOnly)?;
+    let mut markers = BTreeMap::new();
+    for (key, value, ..) in txn.scan(&start[..]..&end[..], None)? {
+        // [SYSTEM_KEY_PREFIX][subsystem][name_len: u16 = 8][id: u64][kind: u8][index: u32]?
+        let mut name = &key[start.len()..];
+        if name.len() < 11 || name.get_u16() != 8 {
+            return Err(Error::CorruptedIndex);
+        }
+        let id = name.get_u64();
+        let kind = name.get_u8();
+
+        let marker = markers.entry(id).or_insert_with(|| Marker {
+            participant: 0,
+            decider: 0,
+            version: 0,
+            undo: BTreeMap::new(),
+            keys: Vec::new(),
+        });
+        marker.keys.push(Bytes::from(key.clone()));
+        let mut value = &value[..];
+        match kind {
+            HEADER if value.len() == 16 => {
+                marker.participant = value.get_u32();
+                marker.decider = value.get_u32();
+                marker.version = value.get_u64();
+            }
+            UNDO_KEY | UNDO_VALUE if name.len() == 4 => {
+                let undo = marker.undo.entry(name.get_u32()).or_insert_with(|| Undo {
+                    key: Bytes::new(),
+                    flags: 0,
+                    value: None,
+                });
+                if kind == UNDO_VALUE {
+                    undo.value = Some(Bytes::copy_from_slice(value));
+                } else if value.len() > 8 {
+                    undo.flags = value.get_u64();
+                    undo.key = Bytes::copy_from_slice(value);
+                } else {
+                    return Err(Error::CorruptedIndex);
+                }
+            }
+            _ => return Err(Error::CorruptedIndex),
+        }
+    }
+    Ok(markers)
+}
+
+/// Settles a coordinated commit in a store: the writes of the commit are undone if the commit
+/// did not complete, and the marker is removed.
+async fn settle(core: &Arc<Core>, marker: &Marker, committed: bool) -> Result<()> {
+    let mut txn = Transaction::new(core.clone(), Mode::ReadWrite)?;
+    if !committed {
+        let snapshot = Snapshot::take(core.clone(), now())?;
+        for undo in marker.undo.values() {
+            // A key written again since the commit keeps its newer value.
+            let unchanged = match snapshot.get_with_filters(&undo.key[..].into(), &NO_FILTERS) {
+                Ok(val_ref) => val_ref.ts() == marker.version,
+                Err(Error::IndexError(TrieError::KeyNotFound)) => false,
+                Err(err) => return Err(err),
+            };
+            if !unchanged {
+                continue;
+            }
+            match &undo.value {
+                Some(value) => {
+                    let mut entry = Entry::new(&undo.key, value);
+                    entry.set_flags(undo.flags);
+                    txn.write_system(entry)?;
+                }
+                None => txn.delete_system(&undo.key)?,
+            }
+        }
+    }
+    for key in &marker.keys {
+        txn.delete_system(key)?;
+    }
+    txn.commit().await
+}
+
+const NO_FILTERS: [fn(&ValueRef, u64) -> Result<()>; 0] = [];
+
+/// Commits transactions of different stores so that either all of them commit or none does.
+///
+/// All the transactions are prepared first, which validates them and takes the commit lock
+/// of every store. Every store then records, along with its transaction, how to undo it, and
+/// the store of the first transaction commits last: once it has committed, the whole commit
+/// has. The commit locks are held until then, and the undo records are removed afterwards.
+///
+/// If the process stops in between, `recover` settles the commits that were in progress.
+/// On error, none of the transactions is committed.
+pub(crate) async fn commit_all(txns: &mut [Transaction]) -> Result<()> {
+    // Transactions without writes have nothing to commit.
+    let mut participants: Vec<(u32, &mut Transaction)> = txns
+        .iter_mut()
+        .enumerate()
+        .filter(|(_, txn)| txn.has_writes())
+        .map(|(index, txn)| (index as u32, txn))
+        .collect();
+    if participants.len() < 2 {
+        return match participants.pop() {
+            Some((_, txn)) => txn.commit().await,
+            None => Ok(()),
+        };
+    }
+
+    // The commit locks are taken in the same order by every coordinated commit, so that two
+    // of them touching the same stores do not wait on each other.
+    participants.sort_by_key(|(_, txn)| Arc::as_ptr(&txn.core) as usize);
+    if participants
+        .windows(2)
+        .any(|pair| Arc::ptr_eq(&pair[0].1.core, &pair[1].1.core))
+    {
+        return Err(Error::InvalidParticipants);
+    }
+    let decider = participants.iter().map(|(index, _)| *index).min().unwrap();
+
+    let mut prepared = Vec::with_capacity(participants.len());
+    for (index, txn) in participants {
+        prepared.push((index, txn.prepare().await?));
+    }
+
+    let id = now();
+    for (index, txn) in prepared.iter_mut() {
+        for entry in marker_entries(txn, id, *index, decider)? {
+            txn.add_entry(entry);
+        }
+    }
+
+    // The decider is written last.
+    prepared.sort_by_key(|(index, _)| *index == decider);
+    for i in 0..prepared.len() {
+        if let Err(err) = prepared[i].1.write().await {
+            // The commit did not complete: release the stores and undo the writes done.
+            let written: Vec<Arc<Core>> = prepared[..i]
+                .iter()
+                .map(|(_, txn)| txn.core().clone())
+                .collect();
+            drop(prepared);
+            for core in written {
+                if let Some(marker) = read_markers(&core)?.get(&id) {
+                    settle(&core, marker, false).await?;
+                }
+            }
+            return Err(err);
+        }
+    }
+
+    let cores: Vec<Arc<Core>> = prepared.iter().map(|(_, txn)| txn.core().clone()).collect();
+    drop(prepared);
+
+    // The commit is complete. The markers are removed from the decider last, as its marker
+    // tells `recover` that the commit completed. A marker left behind is removed by it.
+    for core in cores {
+        let removed = match read_markers(&core)?.get(&id) {
+            Some(marker) => settle(&core, marker, true).await,
+            None => Ok(()),
+        };
+        if removed.is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Settles the coordinated commits that were in progress when the process stopped. The
+/// stores are the ones of the transactions of the commits, in the same order. The commits
+/// whose decider did not commit are undone, and the others are kept. It returns the number
+/// of commits undone.
+pub(crate) async fn recover(cores: &[Arc<Core>]) -> Result<usize> {
+    let markers = cores.iter().map(read_markers).collect::<Result<Vec<_>>>()?;
+
+    let mut ids = BTreeSet::new();
+    for (position, store_markers) in markers.iter().enumerate() {
+        for (id, marker) in store_markers {
+            if marker.participant as usize != position || marker.decider as usize >= cores.len() {
+                return Err(Error::InvalidParticipants);
+            }
+            ids.insert((*id, marker.decider as usize));
+        }
+    }
+
+    let mut undone = 0;
+    for (id, decider) in ids {
+        let committed = markers[decider].contains_key(&id);
+        if !committed {
+            undone += 1;
+        }
+
+        // The decider is settled last, as its marker tells whether the commit completed.
+        let mut positions: Vec<usize> = (0..cores.len())
+            .filter(|position| markers[*position].contains_key(&id))
+            .collect();
+        positions.sort_by_key(|position| *position == decider);
+        for position in positions {
+            settle(&cores[position], &markers[position][&id], committed).await?;
+        }
+    }
+    Ok(undone)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+
+    use tempdir::TempDir;
+
+    fn create_store(temp_dir: &TempDir, name: &str) -> Store {
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().join(name);
+        Store::new(opts).expect("should create store")
+    }
+
+    fn get(store: &Store, key: &[u8]) -> Option<Vec<u8>> {
+        store.begin().unwrap().get(key).unwrap()
+    }
+
+    fn markers(store: &Store) -> usize {
+        super::read_markers(&store.inner.as_ref().unwrap().core)
+            .unwrap()
+            .len()
+    }
+
+    #[tokio::test]
+    async fn commit_all() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let a = create_store(&temp_dir, "a");
+        let b = create_store(&temp_dir, "b");
+
+        let mut txn = a.begin().unwrap();
+        txn.set(b"k1", b"old").unwrap();
+        txn.commit().await.unwrap();
+
+        let mut txn_a = a.begin().unwrap();
+        txn_a.set(b"k1", b"new").unwrap();
+        let mut txn_b = b.begin().unwrap();
+        txn_b.set(b"k2", b"new").unwrap();
+        Store::commit_all(&mut [txn_a, txn_b]).await.unwrap();
+
+        assert_eq!(get(&a, b"k1").unwrap(), b"new");
+        assert_eq!(get(&b, b"k2").unwrap(), b"new");
+        assert_eq!(markers(&a) + markers(&b), 0);
+
+        // A conflict in one store commits none of the transactions.
+        let mut txn_a = a.begin().unwrap();
+        txn_a.get(b"k1").unwrap();
+        txn_a.set(b"k1", b"lost").unwrap();
+        let mut txn_b = b.begin().unwrap();
+        txn_b.set(b"k2", b"lost").unwrap();
+        let mut txn = a.begin().unwrap();
+        txn.set(b"k1", b"concurrent").unwrap();
+        txn.commit().await.unwrap();
+        assert!(Store::commit_all(&mut [txn_a, txn_b]).await.is_err());
+        assert_eq!(get(&a, b"k1").unwrap(), b"concurrent");
+        assert_eq!(get(&b, b"k2").unwrap(), b"new");
+
+        // The transactions must belong to different stores.
+        let mut txn_1 = a.begin().unwrap();
+        txn_1.set(b"k3", b"v").unwrap();
+        let mut txn_2 = a.begin().unwrap();
+        txn_2.set(b"k4", b"v").unwrap();
+        assert!(matches!(
+            Store::commit_all(&mut [txn_1, txn_2]).await,
+            Err(crate::storage::kv::error::Error::InvalidParticipants)
+        ));
+    }
+
+    #[tokio::test]
+    async fn recover_incomplete_commit() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let a = create_store(&temp_dir, "a");
+        let b = create_store(&temp_dir, "b");
+        let mut txn = b.begin().unwrap();
+        txn.set(b"k1", b"old").unwrap();
+        txn.set(b"k3", b"kept").unwrap();
+        txn.commit().await.unwrap();
+
+        // The process stops after the second store committed, before the decider did.
+        let mut txn_a = a.begin().unwrap();
+        txn_a.set(b"k0", b"new").unwrap();
+        let mut txn_b = b.begin().unwrap();
+        txn_b.set(b"k1", b"new").unwrap();
+        txn_b.set(b"k2", b"new").unwrap();
+        {
+            let mut prepared_a = txn_a.prepare().await.unwrap();
+            let mut prepared_b = txn_b.prepare().await.unwrap();
+            for entry in super::marker_entries(&prepared_a, 1, 0, 0).unwrap() {
+                prepared_a.add_entry(entry);
+            }
+            for entry in super::marker_entries(&prepared_b, 1, 1, 0).unwrap() {
+                prepared_b.add_entry(entry);
+            }
+            prepared_b.commit().await.unwrap();
+        }
+        assert_eq!(get(&a, b"k0"), None);
+        assert_eq!(get(&b, b"k1").unwrap(), b"new");
+
+        // A completed commit is kept, with its markers removed.
+        let mut txn_a = a.begin().unwrap();
+        txn_a.set(b"k9", b"done").unwrap();
+        let mut txn_b = b.begin().unwrap();
+        txn_b.set(b"k3", b"done").unwrap();
+        let mut prepared_a = txn_a.prepare().await.unwrap();
+        let mut prepared_b = txn_b.prepare().await.unwrap();
+        for entry in super::marker_entries(&prepared_a, 2, 0, 0).unwrap() {
+            prepared_a.add_entry(entry);
+        }
+        for entry in super::marker_entries(&prepared_b, 2, 1, 0).unwrap() {
+            prepared_b.add_entry(entry);
+        }
+        prepared_b.commit().await.unwrap();
+        prepared_a.commit().await.unwrap();
+
+        assert_eq!(Store::recover_commits(&[&a, &b]).await.unwrap(), 1);
+        assert_eq!(get(&b, b"k1").unwrap(), b"old");
+        assert_eq!(get(&b, b"k2"), None);
+        assert_eq!(get(&a, b"k9").unwrap(), b"done");
+        assert_eq!(get(&b, b"k3").unwrap(), b"done");
+        assert_eq!(markers(&a) + markers(&b), 0);
+    }
+}
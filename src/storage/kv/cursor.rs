@@ -0,0 +1,267 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use vart::VariableSizeKey;
+
+use crate::storage::kv::{
+    error::Result,
+    store::Core,
+    transaction::{Mode, ScanResult, Transaction},
+};
+
+/// Position of a cursor.
+enum Position {
+    Unset,
+    At(ScanResult),
+    BeforeFirst,
+    AfterLast,
+}
+
+/// A cursor over the live entries of a store, in key order, returned by
+/// [`Store::cursor`](crate::Store::cursor). It is styled after the cursors of LMDB: it is
+/// positioned with `seek`, `seek_to_first` or `seek_to_last`, moved with `next` and `prev`,
+/// and the entry it is at is read with `key`, `value` and `version`.
+///
+/// Each move returns true if the cursor is at an entry afterwards. Moving past the last
+/// entry (or before the first) leaves the cursor past the end: `prev` then moves back to the
+/// last entry (or `next` to the first). An unpositioned cursor moves to the first entry on
+/// `next`, and to the last on `prev`.
+///
+/// The cursor reads from the snapshot of a read-only transaction taken when it is created,
+/// like [`ScanIterator`](crate::ScanIterator), and does not visit the system keys. The index
+/// has no reverse iterator: the first backward move walks the keys from the first one to the
+/// cursor, without reading their values, and the following backward moves step through the
+/// keys walked.
+pub struct Cursor {
+    txn: Transaction,
+    position: Position,
+    // Keys before the entry the cursor is at, in key order, if they were walked.
+    before: Option<Vec<Vec<u8>>>,
+}
+
+impl Cursor {
+    pub(crate) fn new(core: &Arc<Core>) -> Result<Self> {
+        Ok(Self {
+            txn: Transaction::new(core.clone(), Mode::ReadOnly)?,
+            position: Position::Unset,
+            before: None,
+        })
+    }
+
+    /// Moves the cursor to the first entry whose key is greater than or equal to `key`.
+    pub fn seek(&mut self, key: &[u8]) -> Result<bool> {
+        let entry = self.read((Bound::Included(index_key(key)), Bound::Unbounded))?;
+        self.before = None;
+        Ok(self.set(entry, Position::AfterLast))
+    }
+
+    /// Moves the cursor to the first entry.
+    pub fn seek_to_first(&mut self) -> Result<bool> {
+        let entry = self.read((Bound::Unbounded, Bound::Unbounded))?;
+        self.before = Some(Vec::new());
+        Ok(self.set(entry, Position::AfterLast))
+    }
+
+    /// Moves the cursor to the last entry.
+    pub fn seek_to_last(&mut self) -> Result<bool> {
+        self.before = Some(self.walk(Bound::Unbounded)?);
+        self.step_back()
+    }
+
+    /// Moves the cursor to the next entry.
+    // A cursor moves both ways, so it is not an `Iterator`.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<bool> {
+        let key = match &self.position {
+            Position::At((key, ..)) => key.clone(),
+            Position::Unset | Position::BeforeFirst => return self.seek_to_first(),
+            Position::AfterLast => return Ok(false),
+        };
+        let entry = self.read((Bound::Excluded(index_key(&key)), Bound::Unbounded))?;
+        if entry.is_some() {
+            // The key left behind is the last one before the next entry.
+            if let Some(before) = &mut self.before {
+                before.push(key);
+            }
+        }
+        Ok(self.set(entry, Position::AfterLast))
+    }
+
+    /// Moves the cursor to the previous entry.
+    pub fn prev(&mut self) -> Result<bool> {
+        match &self.position {
+            Position::At((key, ..)) => {
+                if self.before.is_none() {
+                    self.before = Some(self.walk(Bound::Excluded(key))?);
+                }
+                self.step_back()
+            }
+            Position::Unset | Position::AfterLast => self.seek_to_last(),
+            Position::BeforeFirst => Ok(false),
+        }
+    }
+
+    /// Returns the key of the entry the cursor is at.
+    pub fn key(&self) -> Option<&[u8]> {
+        match &self.position {
+            Position::At((key, ..)) => Some(key),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of the entry the cursor is at.
+    pub fn value(&self) -> Option<&[u8]> {
+        match &self.position {
+            Position::At((_, value, ..)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the version of the entry the cursor is at.
+    pub fn version(&self) -> Option<u64> {
+        match &self.position {
+            Position::At((_, _, version, _)) => Some(*version),
+            _ => None,
+        }
+    }
+
+    /// Moves the cursor to the last of the keys walked before it.
+    fn step_back(&mut self) -> Result<bool> {
+        let key = self.before.as_mut().and_then(|before| before.pop());
+        let entry = match key {
+            Some(key) => {
+                let key = index_key(&key);
+                self.read((Bound::Included(key.clone()), Bound::Included(key)))?
+            }
+            None => None,
+        };
+        if entry.is_none() {
+            self.before = None;
+        }
+        Ok(self.set(entry, Position::BeforeFirst))
+    }
+
+    /// Returns the keys up to `end`, without resolving their values.
+    fn walk(&self, end: Bound<&[u8]>) -> Result<Vec<Vec<u8>>> {
+        let mut keys = Vec::new();
+        self.txn.walk_keys((Bound::Unbounded, end), |key, _| {
+            keys.push(key.to_vec());
+            true
+        })?;
+        Ok(keys)
+    }
+
+    /// Reads the first entry of a range of index keys.
+    fn read(
+        &self,
+        range: (Bound<VariableSizeKey>, Bound<VariableSizeKey>),
+    ) -> Result<Option<ScanResult>> {
+        let mut entries = self
+            .txn
+            .scan_keys(range, false, Some(1), false, true, None)?;
+        Ok(entries.pop())
+    }
+
+    /// Moves the cursor to `entry`, or to `end` if there is none.
+    fn set(&mut self, entry: Option<ScanResult>, end: Position) -> bool {
+        self.position = match entry {
+            Some(entry) => Position::At(entry),
+            None => end,
+        };
+        matches!(self.position, Position::At(_))
+    }
+}
+
+fn index_key(key: &[u8]) -> VariableSizeKey {
+    VariableSizeKey::from_slice_with_termination(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+
+    use tempdir::TempDir;
+
+    async fn create_store(temp_dir: &TempDir, keys: &[&[u8]]) -> Store {
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        let store = Store::new(opts).expect("should create store");
+        let mut txn = store.begin().unwrap();
+        for key in keys {
+            txn.set(key, &[key[0]]).unwrap();
+        }
+        txn.commit().await.unwrap();
+        store
+    }
+
+    #[tokio::test]
+    async fn cursor_moves() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let store = create_store(&temp_dir, &[b"b", b"d", b"f", b"h"]).await;
+        let mut cursor = store.cursor().unwrap();
+        assert!(cursor.key().is_none());
+
+        assert!(cursor.seek(b"c").unwrap());
+        assert_eq!(cursor.key(), Some(&b"d"[..]));
+        assert_eq!(cursor.value(), Some(&b"d"[..]));
+        assert_eq!(cursor.version(), Some(1));
+        assert!(cursor.next().unwrap());
+        assert_eq!(cursor.key(), Some(&b"f"[..]));
+        assert!(cursor.prev().unwrap());
+        assert!(cursor.prev().unwrap());
+        assert_eq!(cursor.key(), Some(&b"b"[..]));
+        assert!(!cursor.prev().unwrap());
+        assert!(cursor.key().is_none());
+        assert!(!cursor.prev().unwrap());
+
+        // Past the first entry, the cursor moves forward from the first one again.
+        assert!(cursor.next().unwrap());
+        assert_eq!(cursor.key(), Some(&b"b"[..]));
+        assert!(cursor.next().unwrap());
+        assert!(cursor.next().unwrap());
+        assert!(cursor.prev().unwrap());
+        assert_eq!(cursor.key(), Some(&b"d"[..]));
+
+        assert!(cursor.seek_to_last().unwrap());
+        assert_eq!(cursor.key(), Some(&b"h"[..]));
+        assert!(!cursor.next().unwrap());
+        assert!(!cursor.next().unwrap());
+        assert!(cursor.prev().unwrap());
+        assert_eq!(cursor.key(), Some(&b"h"[..]));
+        assert!(cursor.prev().unwrap());
+        assert_eq!(cursor.key(), Some(&b"f"[..]));
+
+        assert!(cursor.seek_to_first().unwrap());
+        assert_eq!(cursor.key(), Some(&b"b"[..]));
+        assert!(!cursor.seek(b"i").unwrap());
+        assert!(cursor.prev().unwrap());
+        assert_eq!(cursor.key(), Some(&b"h"[..]));
+        drop(cursor);
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn cursor_reads_its_snapshot() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let store = create_store(&temp_dir, &[b"a", b"c"]).await;
+        let mut cursor = store.cursor().unwrap();
+
+        let mut txn = store.begin().unwrap();
+        txn.set(b"b", b"b").unwrap();
+        txn.delete(b"c").unwrap();
+        txn.commit().await.unwrap();
+
+        assert!(cursor.seek_to_first().unwrap());
+        assert!(cursor.next().unwrap());
+        assert_eq!(cursor.key(), Some(&b"c"[..]));
+        assert!(!cursor.next().unwrap());
+        drop(cursor);
+
+        let mut cursor = store.cursor().unwrap();
+        assert!(cursor.seek_to_last().unwrap());
+        assert_eq!(cursor.key(), Some(&b"b"[..]));
+        drop(cursor);
+        store.close().await.unwrap();
+    }
+}
@@ -0,0 +1,63 @@
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::storage::kv::error::{Error, Result};
+
+/// Callback told once the writes of a batch are durable, see
+/// [`WriteBatch::on_durable`](crate::WriteBatch::on_durable). It is given `Ok` once the commit
+/// log holding them is synced to disk, or the error that may have lost them.
+pub type DurabilityCallback = Arc<dyn Fn(Result<()>) + Send + Sync>;
+
+#[derive(Default)]
+struct Watched {
+    synced: u64, // Last version of the commit log known to be synced to disk.
+    pending: Vec<(u64, DurabilityCallback)>, // Callbacks waiting for their version to be synced.
+}
+
+/// `DurabilityWatch` tells the durability callbacks of the commits when the commit log holding
+/// them is synced: by the commits written with `Durability::Immediate`, which sync the commits
+/// written before them too, by the index checkpoints and by closing the store.
+#[derive(Default)]
+pub(crate) struct DurabilityWatch {
+    watched: Mutex<Watched>,
+}
+
+impl DurabilityWatch {
+    /// Tells `callbacks` when the commit at `version`, which is written to the commit log, is
+    /// synced, or right away if it already is.
+    pub(crate) fn watch(&self, version: u64, callbacks: Vec<DurabilityCallback>) {
+        let mut watched = self.watched.lock();
+        if version > watched.synced {
+            let pending = callbacks.into_iter().map(|callback| (version, callback));
+            watched.pending.extend(pending);
+            return;
+        }
+        drop(watched);
+        callbacks.iter().for_each(|callback| callback(Ok(())));
+    }
+
+    /// Records that the commit log is synced up to the commit at `version`, and tells the
+    /// callbacks of the commits up to it.
+    pub(crate) fn synced(&self, version: u64) {
+        let durable: Vec<_> = {
+            let mut watched = self.watched.lock();
+            watched.synced = watched.synced.max(version);
+            let (durable, pending) = std::mem::take(&mut watched.pending)
+                .into_iter()
+                .partition(|(watched, _)| *watched <= version);
+            watched.pending = pending;
+            durable
+        };
+        durable.iter().for_each(|(_, callback)| callback(Ok(())));
+    }
+
+    /// Tells the callbacks waiting for their commit to be synced that it may be lost, after
+    /// writing or syncing the commit log failed with `err`.
+    pub(crate) fn failed(&self, err: &Error) {
+        let pending = std::mem::take(&mut self.watched.lock().pending);
+        pending
+            .iter()
+            .for_each(|(_, callback)| callback(Err(err.clone())));
+    }
+}
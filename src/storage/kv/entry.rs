@@ -1,21 +1,29 @@
 use hashbrown::HashMap;
 use std::io::Cursor;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::Instant;
 
-use bytes::{Buf, BufMut, Bytes, BytesMut};
+use bytes::{buf::UninitSlice, Buf, BufMut, Bytes, BytesMut};
+use crc32fast::Hasher as crc32Hasher;
 
 use crate::storage::{
     kv::error::{Error, Result},
+    kv::merge::{self, Origin},
     kv::meta::Metadata,
     kv::store::Core,
-    kv::util::{calculate_crc32, calculate_crc32_combined},
+    kv::util::{calculate_crc32, calculate_crc32_combined, check_deadline},
 };
 
 pub(crate) const MD_SIZE: usize = 1; // Size of txmdLen and kvmdLen in bytes
-pub(crate) const MAX_KV_METADATA_SIZE: usize = 1; // Maximum size of key-value metadata in bytes
+pub(crate) const MAX_KV_METADATA_SIZE: usize = 64; // Maximum size of key-value metadata in bytes
 pub(crate) const MAX_TX_METADATA_SIZE: usize = 0; // Maximum size of transaction metadata in bytes
 pub(crate) const TRANSACTION_HEADER_VERSION: u16 = 1; // Version of the transaction header
 
+/// Largest gap between two values of a segment of the commit log that `ValueRef::resolve_all`
+/// reads together. The bytes in between are read and dropped.
+const COALESCE_GAP: u64 = 64 * 1024;
+
 #[derive(Debug, Clone)]
 pub(crate) struct Entry {
     pub(crate) key: Bytes,
@@ -41,6 +49,114 @@ impl Entry {
         self.metadata.as_mut().unwrap().as_deleted(true).unwrap();
     }
 
+    pub(crate) fn set_flags(&mut self, flags: u64) {
+        self.metadata
+            .get_or_insert_with(Metadata::new)
+            .with_flags(flags);
+    }
+
+    /// Sets the time the entry expires at, in nanoseconds since the Unix epoch.
+    pub(crate) fn set_expiry(&mut self, at: Option<u64>) {
+        self.metadata
+            .get_or_insert_with(Metadata::new)
+            .with_expiry(at);
+    }
+
+    /// Marks the entry as only changing the expiry of its key. Its value is empty, the
+    /// index keeps the value of the previous version of the key.
+    pub(crate) fn mark_touch(&mut self) {
+        self.metadata.get_or_insert_with(Metadata::new).as_touched();
+    }
+
+    /// Marks the entry as merging operands into the value of its key. Its value is the list of
+    /// operands, see `merge::encode_operand`.
+    pub(crate) fn mark_merge(&mut self) {
+        self.metadata.get_or_insert_with(Metadata::new).as_merged();
+    }
+
+    pub(crate) fn is_merge(&self) -> bool {
+        self.metadata.as_ref().is_some_and(|md| md.merged())
+    }
+
+    /// Marks the entry as appending chunks to the value of its key, see
+    /// `Transaction::append_value`. It is merged as a list of operands, which are appended to
+    /// the value rather than merged with the merge operator.
+    pub(crate) fn mark_append(&mut self) {
+        self.mark_merge();
+        self.metadata.as_mut().unwrap().as_appended();
+    }
+
+    pub(crate) fn is_append(&self) -> bool {
+        self.metadata.as_ref().is_some_and(|md| md.appended())
+    }
+
+    /// Marks the entry as deleting the keys of a range. Its key is a system key, its value
+    /// the range, see `range_delete::encode_range`. It is also marked deleted, so that its key
+    /// never has a value.
+    pub(crate) fn mark_range_delete(&mut self) {
+        self.mark_delete();
+        self.metadata.as_mut().unwrap().as_range_deleted();
+    }
+
+    pub(crate) fn is_range_delete(&self) -> bool {
+        self.metadata.as_ref().is_some_and(|md| md.range_deleted())
+    }
+
+    /// Marks the entry as dropping all the keys of the store, see `clear::entry`. It is also
+    /// marked deleted, so that its key never has a value.
+    pub(crate) fn mark_clear(&mut self) {
+        self.mark_delete();
+        self.metadata.as_mut().unwrap().as_cleared();
+    }
+
+    pub(crate) fn is_clear(&self) -> bool {
+        self.metadata.as_ref().is_some_and(|md| md.cleared())
+    }
+
+    /// Marks the entry as a marker of a compaction of the commit log, see
+    /// `compaction::marker`. It is not indexed.
+    pub(crate) fn mark_compaction(&mut self) {
+        self.metadata
+            .get_or_insert_with(Metadata::new)
+            .as_compaction();
+    }
+
+    /// Returns the time the entry expires at, if it expires.
+    pub(crate) fn expires_at(&self) -> Option<u64> {
+        self.metadata.as_ref().and_then(|md| md.expires_at())
+    }
+
+    pub(crate) fn is_touch(&self) -> bool {
+        self.metadata.as_ref().is_some_and(|md| md.touched())
+    }
+
+    /// Marks the touched entry as also changing the flags of its key to its own, see
+    /// `Transaction::set_meta_bits`.
+    pub(crate) fn mark_reflag(&mut self) {
+        self.mark_touch();
+        self.metadata.as_mut().unwrap().as_reflagged();
+    }
+
+    pub(crate) fn is_reflag(&self) -> bool {
+        self.metadata.as_ref().is_some_and(|md| md.reflagged())
+    }
+
+    /// Marks the entry as moving the value of another key to its own, see
+    /// `Transaction::rename`. Its value is the other key: the index takes the value of its
+    /// latest version, which is not written again, with the expiry and the flags of the entry.
+    pub(crate) fn mark_rename(&mut self) {
+        self.metadata.get_or_insert_with(Metadata::new).as_renamed();
+    }
+
+    pub(crate) fn is_rename(&self) -> bool {
+        self.metadata.as_ref().is_some_and(|md| md.renamed())
+    }
+
+    /// Returns the user flags of the entry, or 0 if none are set.
+    pub(crate) fn flags(&self) -> u64 {
+        self.metadata.as_ref().map_or(0, |md| md.flags())
+    }
+
     pub(crate) fn is_deleted(&self) -> bool {
         if let Some(metadata) = &self.metadata {
             metadata.deleted()
@@ -140,30 +256,40 @@ impl TxRecord {
         self.header.num_entries += 1;
     }
 
-    pub(crate) fn encode(
+    /// Encodes the record at `current_offset` in the commit log, into a buffer or directly
+    /// into a slot of the log, and stores the offsets of the values in `offset_tracker`.
+    pub(crate) fn encode<B: BufMut>(
         &self,
-        buf: &mut BytesMut,
+        buf: &mut B,
         current_offset: u64,
         offset_tracker: &mut HashMap<Bytes, usize>,
     ) -> Result<()> {
+        let mut buf = Crc32Buf::new(buf);
+
         // Encode header
-        self.header.encode(buf);
+        self.header.encode(&mut buf);
 
         // Encode entries and store offsets
         for entry in &self.entries {
-            let mut offset = entry.encode(buf)?;
+            let mut offset = buf.len + entry.encode(&mut buf)?;
             offset += current_offset as usize;
 
             // Store the offset for the current entry
             offset_tracker.insert(entry.key.clone(), offset);
         }
 
-        let crc = calculate_crc32(buf);
+        let crc = buf.hasher.clone().finalize();
         buf.put_u32(crc);
 
         Ok(())
     }
 
+    /// Returns the number of bytes of the encoded record.
+    pub(crate) fn encoded_len(&self) -> usize {
+        let entries: usize = self.entries.iter().map(TxEntry::encoded_len).sum();
+        self.header.encoded_len() + entries + 4
+    }
+
     pub(crate) fn to_buf(&self, buf: &mut BytesMut) -> Result<()> {
         // Encode header
         self.header.encode(buf);
@@ -205,7 +331,7 @@ impl TxHeader {
         self.num_entries = 0;
     }
 
-    pub(crate) fn encode(&self, buf: &mut BytesMut) {
+    pub(crate) fn encode<B: BufMut>(&self, buf: &mut B) {
         let (md_len, md_bytes) = match &self.metadata {
             Some(metadata) => {
                 let md_bytes = metadata.to_bytes();
@@ -225,6 +351,11 @@ impl TxHeader {
             buf.put(md_bytes);
         }
     }
+
+    fn encoded_len(&self) -> usize {
+        let md_len = self.metadata.as_ref().map_or(0, |md| md.to_bytes().len());
+        8 + 8 + 2 + 4 + 2 + md_len
+    }
 }
 
 #[derive(Debug)]
@@ -238,12 +369,14 @@ pub(crate) struct TxEntry {
 }
 
 impl TxEntry {
-    pub(crate) fn encode(&self, buf: &mut BytesMut) -> Result<usize> {
+    /// Encodes the entry, and returns the position of the value in it.
+    pub(crate) fn encode<B: BufMut>(&self, buf: &mut B) -> Result<usize> {
         // Encode metadata, if present
+        let mut md_len = 0;
         if let Some(metadata) = &self.metadata {
             let md_bytes = metadata.to_bytes();
-            let md_len = md_bytes.len() as u16;
-            buf.put_u16(md_len);
+            md_len = md_bytes.len();
+            buf.put_u16(md_len as u16);
             buf.put(md_bytes);
         } else {
             buf.put_u16(0);
@@ -253,16 +386,59 @@ impl TxEntry {
         buf.put_u32(self.key_len);
         buf.put(self.key.as_ref());
         buf.put_u32(self.value_len);
-        let offset = buf.len();
+        let offset = 2 + md_len + 4 + self.key.len() + 4;
         buf.put(self.value.as_ref());
         buf.put_u32(self.crc32);
 
         Ok(offset)
     }
+
+    fn encoded_len(&self) -> usize {
+        let md_len = self.metadata.as_ref().map_or(0, |md| md.to_bytes().len());
+        2 + md_len + 4 + self.key.len() + 4 + self.value.len() + 4
+    }
+}
+
+/// A `BufMut` counting the bytes put into an inner buffer, and computing their CRC32.
+struct Crc32Buf<'a, B> {
+    inner: &'a mut B,
+    hasher: crc32Hasher,
+    len: usize,
+}
+
+impl<'a, B: BufMut> Crc32Buf<'a, B> {
+    fn new(inner: &'a mut B) -> Self {
+        Self {
+            inner,
+            hasher: crc32Hasher::new(),
+            len: 0,
+        }
+    }
+}
+
+unsafe impl<B: BufMut> BufMut for Crc32Buf<'_, B> {
+    fn remaining_mut(&self) -> usize {
+        self.inner.remaining_mut()
+    }
+
+    unsafe fn advance_mut(&mut self, cnt: usize) {
+        // The bytes put are the first `cnt` bytes of the chunk, initialized by the caller.
+        let chunk = self.inner.chunk_mut();
+        self.hasher
+            .update(std::slice::from_raw_parts(chunk.as_mut_ptr(), cnt));
+        self.len += cnt;
+        self.inner.advance_mut(cnt);
+    }
+
+    fn chunk_mut(&mut self) -> &mut UninitSlice {
+        self.inner.chunk_mut()
+    }
 }
 
 pub(crate) trait Value {
     fn resolve(&self) -> Result<Vec<u8>>;
+    fn resolve_by(&self, deadline: Option<Instant>) -> Result<Vec<u8>>;
+    fn resolve_merged(&self, key: &[u8], deadline: Option<Instant>) -> Result<Vec<u8>>;
     fn ts(&self) -> u64;
     fn key_value_metadata(&self) -> Option<&Metadata>;
     fn length(&self) -> usize;
@@ -277,24 +453,55 @@ pub struct ValueRef {
     pub(crate) value: Option<Bytes>,
     pub(crate) key_value_metadata: Option<Metadata>,
     /// The underlying store for the transaction.
-    store: Arc<Core>,
+    pub(crate) store: Arc<Core>,
 }
 
 impl Value for ValueRef {
     /// Resolves the value associated with this instance.
     /// If the value is present, it returns a cloned vector of the value.
     /// If the value offset is present, it reads the value from the offset in the commit log.
+    /// Values stored compressed are decompressed before being returned.
     fn resolve(&self) -> Result<Vec<u8>> {
-        // Check if the value is present directly
-        if let Some(value) = &self.value {
-            Ok(value.to_vec())
-        } else if let Some(value_offset) = self.value_offset {
-            // Resolve from the specified offset
-            self.resolve_from_offset(value_offset)
-        } else {
-            // If neither value nor offset is present, return an error
-            Err(Error::EmptyValue)
+        self.resolve_by(None)
+    }
+
+    /// Resolves the value as `resolve` does, failing with `Error::DeadlineExceeded` rather
+    /// than reading it from the commit log once the deadline has passed.
+    fn resolve_by(&self, deadline: Option<Instant>) -> Result<Vec<u8>> {
+        let value = self.resolve_stored(deadline)?;
+        self.decompress(value)
+    }
+
+    /// Resolves the value as `resolve_by` does, merging it with the older versions of `key`
+    /// if it is a list of merge operands.
+    ///
+    /// A value that is no longer cached, and that cannot be read from the commit log at its
+    /// offset any more, as a compaction of the log moved it since the snapshot was taken, is
+    /// read where the compaction moved its version of the key.
+    fn resolve_merged(&self, key: &[u8], deadline: Option<Instant>) -> Result<Vec<u8>> {
+        let value = match self.resolve_by(deadline) {
+            Ok(value) => value,
+            Err(err) if self.value_offset.is_some() => {
+                return match self.relocated(key)? {
+                    Some(relocated) => relocated.resolve_merged(key, deadline),
+                    None => Err(err),
+                };
+            }
+            Err(err) => return Err(err),
+        };
+        if !self.is_merge() {
+            return Ok(value);
         }
+        merge::merge_value(
+            &self.store,
+            &self.store.indexer,
+            key,
+            self.ts,
+            &value,
+            self.is_append(),
+            Origin::Committed,
+            deadline,
+        )
     }
 
     fn ts(&self) -> u64 {
@@ -311,6 +518,130 @@ impl Value for ValueRef {
 }
 
 impl ValueRef {
+    /// Resolves the values of several valueRefs of the keys they are paired with, as
+    /// `resolve_by` does for each. The values that are not cached are read from the commit log
+    /// in offset order, and the values of a segment less than `COALESCE_GAP` bytes apart are
+    /// read together, in a single read.
+    ///
+    /// The values of a read that fails, as a compaction of the log moved them since the
+    /// snapshot was taken, are read one by one where it moved them, as `resolve_merged` does.
+    pub(crate) fn resolve_all(
+        refs: &[(Bytes, ValueRef)],
+        deadline: Option<Instant>,
+    ) -> Result<Vec<Vec<u8>>> {
+        let (keys, refs): (Vec<_>, Vec<_>) = refs.iter().map(|(key, r)| (key, r)).unzip();
+        let mut values = vec![Vec::new(); refs.len()];
+        let mut reads = Vec::new();
+        for (i, val_ref) in refs.iter().enumerate() {
+            match (&val_ref.value, val_ref.value_offset) {
+                (None, Some(offset)) if val_ref.store.value_cache.peek(&offset).is_none() => {
+                    reads.push((offset, i))
+                }
+                _ => values[i] = val_ref.resolve_by(deadline)?,
+            }
+        }
+        reads.sort_unstable();
+
+        let mut reads = reads.into_iter().peekable();
+        while let Some((start, first)) = reads.next() {
+            let store = &refs[first].store;
+            let segment_size = store.opts.max_segment_size;
+            let mut end = start + refs[first].value_length as u64;
+            let mut run = vec![(start, first)];
+            while let Some(&(offset, i)) = reads.peek() {
+                if offset / segment_size != start / segment_size || offset > end + COALESCE_GAP {
+                    break;
+                }
+                end = end.max(offset + refs[i].value_length as u64);
+                run.push((offset, i));
+                reads.next();
+            }
+
+            let buf = match refs[first].read_log(start, (end - start) as usize, deadline) {
+                Ok(buf) => buf,
+                Err(Error::DeadlineExceeded) => return Err(Error::DeadlineExceeded),
+                Err(_) => {
+                    for (_, i) in run {
+                        values[i] = refs[i].resolve_merged(keys[i], deadline)?;
+                    }
+                    continue;
+                }
+            };
+            store
+                .stats
+                .log_reads
+                .fetch_add(run.len() as u64, Ordering::Relaxed);
+            for (offset, i) in run {
+                let at = (offset - start) as usize;
+                let stored = buf[at..at + refs[i].value_length].to_vec();
+                store.cache_value(offset, Bytes::copy_from_slice(&stored));
+                values[i] = refs[i].decompress(stored)?;
+            }
+        }
+        Ok(values)
+    }
+
+    /// Returns the valueRef of the version of `key` of this one in the index, if it was moved
+    /// by a compaction of the commit log, which keeps the versions of the keys.
+    fn relocated(&self, key: &[u8]) -> Result<Option<ValueRef>> {
+        let Some(index_key) = self.store.keys.lookup(key) else {
+            return Ok(None);
+        };
+        let found = self.store.indexer.read().get_at(&index_key, self.ts)?;
+        let Some((value, version, _)) = found.filter(|(_, version, _)| *version == self.ts) else {
+            return Ok(None);
+        };
+        let mut relocated = ValueRef::new(self.store.clone());
+        relocated.decode(version, &value)?;
+        if relocated.value.is_none() && relocated.value_offset == self.value_offset {
+            return Ok(None);
+        }
+        Ok(Some(relocated))
+    }
+
+    /// Decompresses a value as it is stored, if it was stored compressed.
+    fn decompress(&self, value: Vec<u8>) -> Result<Vec<u8>> {
+        match self
+            .key_value_metadata
+            .as_ref()
+            .and_then(|md| md.compression())
+        {
+            Some((format, dictionary_id)) => {
+                self.store
+                    .compressor
+                    .decompress(format, dictionary_id, &value)
+            }
+            None => Ok(value),
+        }
+    }
+
+    /// Returns the value as it is stored, which may be compressed.
+    pub(crate) fn resolve_stored(&self, deadline: Option<Instant>) -> Result<Vec<u8>> {
+        // Check if the value is present directly
+        if let Some(value) = &self.value {
+            Ok(value.to_vec())
+        } else if let Some(value_offset) = self.value_offset {
+            // Resolve from the specified offset
+            self.resolve_from_offset(value_offset, deadline)
+        } else {
+            // If neither value nor offset is present, return an error
+            Err(Error::EmptyValue)
+        }
+    }
+
+    /// Returns true if the value is a list of merge operands.
+    pub(crate) fn is_merge(&self) -> bool {
+        self.key_value_metadata
+            .as_ref()
+            .is_some_and(|md| md.merged())
+    }
+
+    pub(crate) fn is_append(&self) -> bool {
+        self.key_value_metadata
+            .as_ref()
+            .is_some_and(|md| md.appended())
+    }
+
     pub(crate) fn new(store: Arc<Core>) -> Self {
         ValueRef {
             ts: 0,
@@ -399,12 +730,68 @@ impl ValueRef {
         }
 
         // Decode key-value metadata
+        self.key_value_metadata = Self::decode_key_value_metadata(&mut cursor)?;
+
+        Ok(())
+    }
+
+    /// Returns the byte representation of a valueRef with its expiry replaced by `expires_at`,
+    /// and its flags by `flags` if given, keeping the value, or its offset in the commit log,
+    /// and the rest of its metadata. Returns `None` if the valueRef is a deletion.
+    pub(crate) fn encode_touched(
+        encoded_bytes: &Bytes,
+        expires_at: Option<u64>,
+        flags: Option<u64>,
+    ) -> Result<Option<Bytes>> {
+        let mut metadata = Self::decode_metadata(encoded_bytes)?.unwrap_or_else(Metadata::new);
+        if metadata.deleted() {
+            return Ok(None);
+        }
+        metadata.with_expiry(expires_at);
+        if let Some(flags) = flags {
+            metadata.with_flags(flags);
+        }
+
+        // The metadata follows the flag, the value length and the value or its offset.
+        let flag = encoded_bytes[0];
+        let value_length = u32::from_be_bytes(encoded_bytes[1..5].try_into().unwrap()) as usize;
+        let value_end = 5 + if flag == 1 { value_length } else { 8 };
+
+        let md_bytes = metadata.to_bytes();
+        let mut buf = BytesMut::with_capacity(value_end + 2 + md_bytes.len());
+        buf.put(&encoded_bytes[..value_end]);
+        buf.put_u16(md_bytes.len() as u16);
+        buf.put(md_bytes);
+        Ok(Some(buf.freeze()))
+    }
+
+    /// Decodes only the key-value metadata from the byte representation of a valueRef.
+    pub(crate) fn decode_metadata(encoded_bytes: &Bytes) -> Result<Option<Metadata>> {
+        let mut cursor = Cursor::new(encoded_bytes);
+        if encoded_bytes.len() < 5 {
+            return Err(Error::CorruptedIndex);
+        }
+
+        // Skip the value, or its offset in the commit log
+        let flag = cursor.get_u8();
+        let value_length = cursor.get_u32() as usize;
+        let skipped = if flag == 1 { value_length } else { 8 };
+        if encoded_bytes.len() < cursor.position() as usize + skipped {
+            return Err(Error::CorruptedIndex);
+        }
+        cursor.advance(skipped);
+
+        Self::decode_key_value_metadata(&mut cursor)
+    }
+
+    fn decode_key_value_metadata(cursor: &mut Cursor<&Bytes>) -> Result<Option<Metadata>> {
+        let encoded_bytes = *cursor.get_ref();
         if encoded_bytes.len() < cursor.position() as usize + MD_SIZE {
             return Err(Error::CorruptedIndex);
         }
 
         let kv_metadata_len = cursor.get_u16() as usize;
-        if kv_metadata_len > 0 {
+        let metadata = if kv_metadata_len > 0 {
             if kv_metadata_len > MAX_KV_METADATA_SIZE {
                 return Err(Error::CorruptedIndex);
             }
@@ -412,42 +799,86 @@ impl ValueRef {
                 return Err(Error::CorruptedIndex);
             }
             let kv_metadata_bytes =
-                cursor.get_ref()[cursor.position() as usize..][..kv_metadata_len].as_ref();
+                encoded_bytes[cursor.position() as usize..][..kv_metadata_len].as_ref();
             cursor.advance(kv_metadata_len);
-            self.key_value_metadata = Some(Metadata::from_bytes(kv_metadata_bytes)?);
+            Some(Metadata::from_bytes(kv_metadata_bytes)?)
         } else {
-            self.key_value_metadata = None;
-        }
+            None
+        };
 
         // Ensure all the data is read
         if cursor.position() as usize != encoded_bytes.len() {
             return Err(Error::CorruptedIndex);
         }
 
-        Ok(())
+        Ok(metadata)
     }
 
     /// Resolves the value from the given offset in the commit log.
     /// If the offset exists in the value cache, it returns the cached value.
     /// Otherwise, it reads the value from the commit log, caches it, and returns it.
-    fn resolve_from_offset(&self, value_offset: u64) -> Result<Vec<u8>> {
+    /// If `read_probe_interval` is set, one in that many cached reads is verified against
+    /// the commit log, and mismatches are counted in the store stats.
+    fn resolve_from_offset(&self, value_offset: u64, deadline: Option<Instant>) -> Result<Vec<u8>> {
         // Check if the offset exists in value_cache and return if found
         if let Some(value) = self.store.value_cache.get(&value_offset) {
-            return Ok(value.to_vec());
+            let stats = &self.store.stats;
+            let reads = stats.cache_reads.fetch_add(1, Ordering::Relaxed) + 1;
+
+            // Verify a sample of the cached reads against the commit log.
+            let interval = self.store.opts.read_probe_interval;
+            if interval == 0 || reads % interval != 0 {
+                return Ok(value.to_vec());
+            }
+
+            // A probe is skipped rather than failing a read by its deadline.
+            if check_deadline(deadline).is_err() {
+                return Ok(value.to_vec());
+            }
+            stats.read_probes.fetch_add(1, Ordering::Relaxed);
+            let buf = self.read_from_log(value_offset, deadline)?;
+            if calculate_crc32(&buf) == calculate_crc32(&value) {
+                return Ok(value.to_vec());
+            }
+
+            // The commit log is the source of truth: count the mismatch and
+            // replace the cached value.
+            stats.read_probe_mismatches.fetch_add(1, Ordering::Relaxed);
+            self.store
+                .cache_value(value_offset, Bytes::from(buf.clone()));
+            return Ok(buf);
         }
 
-        // Read the value from the commit log at the specified offset
-        let mut buf = vec![0; self.value_length];
-        let vlog = self.store.clog.as_ref().unwrap().read();
-        vlog.read_at(&mut buf, value_offset)?;
+        let buf = self.read_from_log(value_offset, deadline)?;
+        self.store.stats.log_reads.fetch_add(1, Ordering::Relaxed);
 
         // Store the offset and value in value_cache
         self.store
-            .value_cache
-            .insert(value_offset, Bytes::from(buf.clone()));
+            .cache_value(value_offset, Bytes::from(buf.clone()));
 
         Ok(buf)
     }
+
+    /// Reads the value from the commit log at the given offset, unless the deadline passes
+    /// first, waiting for the commit log while it is written to.
+    fn read_from_log(&self, value_offset: u64, deadline: Option<Instant>) -> Result<Vec<u8>> {
+        self.read_log(value_offset, self.value_length, deadline)
+    }
+
+    /// Reads `len` bytes of the commit log at the given offset, as `read_from_log` does.
+    fn read_log(&self, offset: u64, len: usize, deadline: Option<Instant>) -> Result<Vec<u8>> {
+        check_deadline(deadline)?;
+        let mut buf = vec![0; len];
+        let clog = self.store.clog.as_ref().unwrap();
+        let vlog = match deadline {
+            Some(deadline) => clog
+                .try_read_until(deadline)
+                .ok_or(Error::DeadlineExceeded)?,
+            None => clog.read(),
+        };
+        vlog.read_at(&mut buf, offset)?;
+        Ok(buf)
+    }
 }
 
 #[cfg(test)]
@@ -503,6 +934,57 @@ mod tests {
         // );
     }
 
+    #[tokio::test]
+    async fn values_moved_while_read_are_read_again_from_the_log() {
+        let temp_dir = create_temp_directory();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.max_value_threshold = 2;
+        let store = Store::new(opts).expect("should create store");
+        let core = &store.inner.as_ref().unwrap().core;
+
+        let mut txn = store.begin().unwrap();
+        txn.set(b"foo", b"bar").unwrap();
+        txn.commit().await.unwrap();
+
+        // The snapshot refers to the offset of the value before the compaction removes its
+        // segment, and the cached value is evicted meanwhile.
+        let txn = store.begin().unwrap();
+        store.compact_log().await.unwrap();
+        core.value_cache.clear();
+        assert_eq!(txn.get(b"foo").unwrap().unwrap(), b"bar");
+        core.value_cache.clear();
+        assert_eq!(
+            txn.get_many(&[b"foo".as_slice(), b"baz".as_slice()])
+                .unwrap(),
+            vec![Some(b"bar".to_vec()), None]
+        );
+        assert_eq!(store.begin().unwrap().get(b"foo").unwrap().unwrap(), b"bar");
+        store.close().await.unwrap();
+    }
+
+    #[test]
+    fn tx_record_encode() {
+        let mut kvmd = Metadata::new();
+        kvmd.as_deleted(true).expect("failed to set deleted");
+        let mut deleted = Entry::new(b"deleted", b"");
+        deleted.metadata = Some(kvmd);
+        let entries = vec![Entry::new(b"key", b"some value"), deleted];
+        let tx_record = TxRecord::new_with_entries(entries, 7, 9);
+
+        let mut buf = BytesMut::new();
+        let mut offsets = HashMap::new();
+        tx_record.encode(&mut buf, 1000, &mut offsets).unwrap();
+
+        // The length is known before encoding, and the checksum covers all the record
+        assert_eq!(buf.len(), tx_record.encoded_len());
+        let (record, crc) = buf.split_at(buf.len() - 4);
+        assert_eq!(crc, calculate_crc32(record).to_be_bytes());
+
+        let value_at = offsets[&Bytes::from_static(b"key")] - 1000;
+        assert_eq!(&buf[value_at..value_at + 10], b"some value");
+    }
+
     #[tokio::test]
     async fn txn_with_value_read_from_clog() {
         // Create a temporary directory for testing
@@ -631,4 +1113,48 @@ mod tests {
             assert_eq!(val, value);
         }
     }
+
+    #[tokio::test]
+    async fn read_probes_detect_stale_cache() {
+        let temp_dir = create_temp_directory();
+
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.max_value_threshold = 2;
+        opts.read_probe_interval = 1;
+
+        let store = Store::new(opts).expect("should create store");
+        let core = store.inner.as_ref().unwrap().core.clone();
+
+        let mut txn = store.begin().unwrap();
+        txn.set(b"foo", b"bar").unwrap();
+        txn.commit().await.unwrap();
+
+        // The first read populates the cache, the second one is served from it and verified.
+        let txn = store.begin().unwrap();
+        assert_eq!(txn.get(b"foo").unwrap().unwrap(), b"bar");
+        assert_eq!(txn.get(b"foo").unwrap().unwrap(), b"bar");
+        let stats = store.stats();
+        assert_eq!(stats.cache_reads, 1);
+        assert_eq!(stats.read_probes, 1);
+        assert_eq!(stats.read_probe_mismatches, 0);
+
+        // Corrupt the cached value.
+        let snapshot = core.indexer.write().snapshot().unwrap();
+        let (encoded, version, _) = snapshot
+            .get(&vart::VariableSizeKey::from_slice_with_termination(b"foo"))
+            .unwrap();
+        let mut value_ref = ValueRef::new(core.clone());
+        value_ref.decode(version, &encoded).unwrap();
+        core.value_cache
+            .insert(value_ref.value_offset.unwrap(), Bytes::from("baz"));
+
+        // The probe detects the mismatch and serves the value from the commit log.
+        assert_eq!(txn.get(b"foo").unwrap().unwrap(), b"bar");
+        let stats = store.stats();
+        assert_eq!(stats.read_probes, 2);
+        assert_eq!(stats.read_probe_mismatches, 1);
+        assert_eq!(txn.get(b"foo").unwrap().unwrap(), b"bar");
+        assert_eq!(store.stats().read_probe_mismatches, 1);
+    }
 }
@@ -0,0 +1,266 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+
+use crate::storage::kv::{
+    error::{Error, Result},
+    events::{self, Activity, Value},
+    store::Core,
+    transaction::{Mode, Transaction},
+    util::prefix_end,
+};
+
+/// Function converting the payload of a value from an old schema version to the current one.
+pub(crate) type MigrateFn = dyn Fn(u8, &[u8]) -> Vec<u8> + Send + Sync;
+
+/// Wraps a payload in a value envelope: [version: u8][payload]
+pub(crate) fn encode(version: u8, payload: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(1 + payload.len());
+    buf.push(version);
+    buf.extend_from_slice(payload);
+    buf
+}
+
+/// Splits a value envelope into its schema version and payload.
+pub(crate) fn decode(value: &[u8]) -> Result<(u8, &[u8])> {
+    match value.split_first() {
+        Some((version, payload)) => Ok((*version, payload)),
+        None => Err(Error::InvalidValueEnvelope),
+    }
+}
+
+/// A schema migration registered for the values under a key prefix.
+struct Migration {
+    prefix: Vec<u8>,
+    version: u8,
+    f: Arc<MigrateFn>,
+}
+
+/// `Migrations` holds the schema migrations applied to enveloped values when they are read.
+pub(crate) struct Migrations {
+    migrations: RwLock<Vec<Migration>>,
+}
+
+impl Migrations {
+    pub(crate) fn new() -> Self {
+        Self {
+            migrations: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Registers the migrations registered in `other`.
+    pub(crate) fn register_all(&self, other: &Migrations) {
+        for m in other.migrations.read().iter() {
+            self.register(&m.prefix, m.version, m.f.clone());
+        }
+    }
+
+    /// Registers a migration, replacing any migration registered for the same prefix.
+    pub(crate) fn register(&self, prefix: &[u8], version: u8, f: Arc<MigrateFn>) {
+        let mut migrations = self.migrations.write();
+        migrations.retain(|m| m.prefix != prefix);
+        migrations.push(Migration {
+            prefix: prefix.to_vec(),
+            version,
+            f,
+        });
+        // Keep the longest prefixes first so that the most specific migration wins.
+        migrations.sort_by_key(|m| std::cmp::Reverse(m.prefix.len()));
+    }
+
+    /// Migrates an enveloped value read from the given key if a migration targeting a newer
+    /// version is registered for it. Returns the resulting version and payload, and whether
+    /// the value was migrated.
+    pub(crate) fn apply(&self, key: &[u8], value: &[u8]) -> Result<(u8, Vec<u8>, bool)> {
+        let (version, payload) = decode(value)?;
+        let migrations = self.migrations.read();
+        match migrations.iter().find(|m| key.starts_with(&m.prefix)) {
+            Some(m) if version < m.version => Ok((m.version, (m.f)(version, payload), true)),
+            _ => Ok((version, payload.to_vec(), false)),
+        }
+    }
+}
+
+/// Eagerly rewrites all the enveloped values under `prefix` older than `version`,
+/// in batches of at most `max_entries_per_txn` entries. Returns the number of values rewritten.
+pub(crate) async fn migrate(
+    core: &Arc<Core>,
+    prefix: &[u8],
+    version: u8,
+    f: Arc<MigrateFn>,
+) -> Result<usize> {
+    // Reads happening while the values are being rewritten see them migrated.
+    core.migrations.register(prefix, version, f.clone());
+
+    let activity = Activity::start(
+        events::MIGRATION,
+        "value migration",
+        &[("version", Value::U64(version as u64))],
+    );
+    let migrated = rewrite_values(core, prefix, version, &*f).await;
+    activity.finish(migrated, |migrated| {
+        vec![("values_migrated", *migrated as u64)]
+    })
+}
+
+async fn rewrite_values(
+    core: &Arc<Core>,
+    prefix: &[u8],
+    version: u8,
+    f: &MigrateFn,
+) -> Result<usize> {
+    let end = prefix_end(prefix);
+    let batch_size = core.opts.max_entries_per_txn as usize;
+    let mut cursor: Option<Vec<u8>> = None;
+    let mut migrated = 0;
+
+    loop {
+        let mut txn = Transaction::new(core.clone(), Mode::ReadWrite)?;
+        let start = match &cursor {
+            Some(key) => Bound::Excluded(&key[..]),
+            None => Bound::Included(prefix),
+        };
+        let end = match &end {
+            Some(key) => Bound::Excluded(&key[..]),
+            None => Bound::Unbounded,
+        };
+
+        let batch = txn.scan((start, end), Some(batch_size))?;
+        let last_key = match batch.last() {
+            Some((key, _, _, _)) => key.clone(),
+            None => return Ok(migrated),
+        };
+
+        let mut rewritten = 0;
+        for (key, value, _, _) in &batch {
+            let (old_version, payload) = decode(value)?;
+            if old_version < version {
+                txn.set(key, &encode(version, &f(old_version, payload)))?;
+                rewritten += 1;
+            }
+        }
+
+        match txn.commit().await {
+            Ok(()) => {
+                migrated += rewritten;
+                cursor = Some(last_key);
+            }
+            // A value of the batch was updated concurrently, retry the batch.
+            Err(Error::TransactionReadConflict) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+
+    use tempdir::TempDir;
+
+    fn create_store() -> (Store, TempDir) {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        (Store::new(opts).expect("should create store"), temp_dir)
+    }
+
+    fn upgrade(old_version: u8, payload: &[u8]) -> Vec<u8> {
+        assert_eq!(old_version, 1);
+        let mut new = payload.to_vec();
+        new.extend_from_slice(b"+v2");
+        new
+    }
+
+    #[test]
+    fn encode_decode_envelope() {
+        let value = encode(3, b"payload");
+        assert_eq!(decode(&value).unwrap(), (3, &b"payload"[..]));
+        assert!(matches!(decode(&[]), Err(Error::InvalidValueEnvelope)));
+    }
+
+    #[tokio::test]
+    async fn lazy_migration_on_read() {
+        let (store, _temp_dir) = create_store();
+
+        let mut txn = store.begin().unwrap();
+        txn.set_versioned(b"user/1", 1, b"alice").unwrap();
+        txn.set_versioned(b"order/1", 1, b"book").unwrap();
+        txn.set_versioned(b"user/2", 3, b"bob").unwrap();
+        txn.commit().await.unwrap();
+
+        store.register_migration(b"user/", 2, upgrade);
+
+        // Read-only transactions see the migrated value without rewriting it.
+        let mut txn = store.begin_with_mode(Mode::ReadOnly).unwrap();
+        assert_eq!(
+            txn.get_versioned(b"user/1").unwrap().unwrap(),
+            (2, b"alice+v2".to_vec())
+        );
+        assert_eq!(
+            txn.get_versioned(b"order/1").unwrap().unwrap(),
+            (1, b"book".to_vec())
+        );
+        assert_eq!(txn.get(b"user/1").unwrap().unwrap(), encode(1, b"alice"));
+
+        // Read-write transactions persist the migrated value on commit. The values at a newer
+        // version are neither migrated nor written back.
+        let mut txn = store.begin().unwrap();
+        txn.get_versioned(b"user/1").unwrap().unwrap();
+        assert_eq!(
+            txn.get_versioned(b"user/2").unwrap().unwrap(),
+            (3, b"bob".to_vec())
+        );
+        txn.commit().await.unwrap();
+
+        let txn = store.begin().unwrap();
+        assert_eq!(txn.get(b"user/1").unwrap().unwrap(), encode(2, b"alice+v2"));
+        assert_eq!(txn.get(b"user/2").unwrap().unwrap(), encode(3, b"bob"));
+    }
+
+    #[tokio::test]
+    async fn eager_migration() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.max_entries_per_txn = 4;
+        let store = Arc::new(Store::new(opts).expect("should create store"));
+
+        for i in 0..10u8 {
+            let mut txn = store.begin().unwrap();
+            let version = if i < 3 || i % 2 == 0 { 1 } else { 2 };
+            txn.set_versioned(&[b'u', i], version, b"x").unwrap();
+            txn.commit().await.unwrap();
+        }
+        let mut txn = store.begin().unwrap();
+        txn.set_versioned(b"v", 1, b"x").unwrap();
+        txn.set_versioned(b"u_new", 3, b"x").unwrap();
+        txn.commit().await.unwrap();
+
+        // Run the migration as a background job.
+        let job = {
+            let store = store.clone();
+            tokio::spawn(async move {
+                store
+                    .migrate_values(b"u", 2, |old_version, payload| {
+                        assert_eq!(old_version, 1);
+                        payload.to_vec()
+                    })
+                    .await
+            })
+        };
+        assert_eq!(job.await.unwrap().unwrap(), 6);
+
+        let txn = store.begin().unwrap();
+        for i in 0..10u8 {
+            assert_eq!(txn.get(&[b'u', i]).unwrap().unwrap(), encode(2, b"x"));
+        }
+        assert_eq!(txn.get(b"v").unwrap().unwrap(), encode(1, b"x"));
+        assert_eq!(txn.get(b"u_new").unwrap().unwrap(), encode(3, b"x"));
+    }
+}
@@ -16,23 +16,24 @@ pub enum Error {
     LogError(LogError),                 // An error occurred in the log
     EmptyKey,                           // The key is empty
     TransactionClosed,                  // The transaction was closed
-    NonExpirable,                       // The entry cannot be expired
-    CorruptedMetadata,                  // The metadata is corrupted
-    TransactionReadOnly,                // The transaction is read-only
-    IndexError(TrieError),              // An error occurred in the index
-    MaxKeyLengthExceeded,               // The maximum key length was exceeded
-    MaxValueLengthExceeded,             // The maximum value length was exceeded
-    KeyNotFound,                        // The key was not found
-    CorruptedIndex,                     // The index is corrupted
-    TransactionReadConflict,            // A read conflict occurred in the transaction
-    StoreClosed,                        // The store was closed
-    InvalidAttributeData,               // The attribute data is invalid
-    UnknownAttributeType,               // The attribute type is unknown
+    PrepareTimedOut,     // The prepared transaction held the commit lock for too long
+    NonExpirable,        // The entry cannot be expired
+    CorruptedMetadata,   // The metadata is corrupted
+    TransactionReadOnly, // The transaction is read-only
+    IndexError(TrieError), // An error occurred in the index
+    MaxKeyLengthExceeded, // The maximum key length was exceeded
+    MaxValueLengthExceeded, // The maximum value length was exceeded
+    KeyNotFound,         // The key was not found
+    CorruptedIndex,      // The index is corrupted
+    TransactionReadConflict, // A read conflict occurred in the transaction
+    StoreClosed,         // The store was closed
+    InvalidAttributeData, // The attribute data is invalid
+    UnknownAttributeType, // The attribute type is unknown
     CorruptedTransactionRecord(String), // The transaction record is corrupted
     CorruptedTransactionHeader(String), // The transaction header is corrupted
-    InvalidTransactionRecordId,         // The transaction record ID is invalid
-    EmptyValue,                         // The value in the record is empty
-    ManifestNotFound,                   // The manifest was not found
+    InvalidTransactionRecordId, // The transaction record ID is invalid
+    EmptyValue,          // The value in the record is empty
+    ManifestNotFound,    // The manifest was not found
     MaxTransactionEntriesLimitExceeded, // The maximum number of entries in a transaction was exceeded
     TransactionWriteOnly,               // The transaction is write-only
     SendError(String),
@@ -40,15 +41,50 @@ pub enum Error {
     MismatchedSegmentID(u64, u64),
     MaxKeySizeCannotBeDecreased, // The maximum key size cannot be decreased
     MaxValueSizeCannotBeDecreased, // The maximum value size cannot be decreased
+    CompressionError(String),    // An error occurred while compressing or decompressing a value
+    ClaimLost, // The claimed queue item was acknowledged, released or reclaimed by someone else
+    LockHeld,  // The advisory lock is held by someone else
+    LockNotHeld, // The advisory lock is not held by the token
+    InvalidValueEnvelope, // The value is not a valid schema-versioned envelope
+    MirrorAlreadyRunning, // A mirror is already running for the store
+    InvalidOptions(String), // The options are invalid for the operation
+    IncompatibleOptions(String), // The options cannot be changed for an existing store
+    TooManyTransactions, // The maximum number of active transactions is reached
+    FlagNotIndexed(u8), // The flag is not listed in `Options::indexed_flags`
+    InvalidParticipants, // The stores do not match the transactions of a coordinated commit
+    InvalidBackup(String), // The backup is invalid or cannot be restored into the store
+    Poisoned, // An internal invariant violation poisoned the store, see `Options::invariant_policy`
+    StoreReadOnly, // The store was opened read-only, from a checkpoint
+    CheckpointUnavailable, // The store has no index checkpoint that can be read
+    NonMonotonicVersion(u64, u64), // The commit version is not after the last version of the store
+    VersionOutOfRange(u64), // The commit version is the largest one, which no version can follow
+    QuotaExceeded(u64, u64), // The commit would grow the store past `Options::max_db_size`
+    StoreSuspended, // The store is suspended, see `Store::suspend`
+    FsyncFailed(String), // Writing or syncing the commit log failed, see `Options::fsync_failure_policy`
+    DiskFull,            // The disk is full, see `Options::reserved_space`
+    DeadlineExceeded,    // The read did not complete before the deadline of its `ReadOptions`
+    ValueMismatch(Option<Vec<u8>>), // The current value of the key is not the expected one, see `Transaction::cas`
+    TransactionBusy, // An operation of the transaction whose future was dropped is still running
+    CounterOverflow, // The counter would go below 0 or past `u64::MAX`, see `Transaction::incr`
+    InvalidCounter,  // The value of the key is not a counter, see `Transaction::incr`
+    MergeOperatorMissing, // No merge operator is registered, see `Store::set_merge_operator`
+    MergeBaseUnavailable, // The versions the operands are merged with were dropped by an index shrink
+    ExportError(String), // Decoding or writing the exported entries failed, see `Store::export_arrow`
+    AuditFailed(String), // A write sampled by `Options::write_audit_interval` failed its validation
+    MissingSegments(Vec<u64>), // Segments of the commit log are missing, see `Options::missing_segments`
+    SegmentUnavailable(u64),   // The key is in the range of a missing segment of the commit log
+    ReservedKey,               // The key is in the keyspace reserved for the data of the store
 }
 
 /// Error structure for encoding errors
+#[allow(dead_code)]
 #[derive(Debug)]
 pub struct EncodeError {
     message: String,
 }
 
 /// Error structure for decoding errors
+#[allow(dead_code)]
 #[derive(Debug)]
 pub struct DecodeError {
     message: String,
@@ -78,6 +114,10 @@ impl fmt::Display for Error {
             Error::TransactionClosed => {
                 write!(f, "This transaction has been closed")
             }
+            Error::PrepareTimedOut => write!(
+                f,
+                "The prepared transaction held the commit lock for longer than the prepare timeout"
+            ),
             Error::NonExpirable => write!(f, "This entry cannot be expired"),
             Error::CorruptedMetadata => write!(f, "Corrupted metadata"),
             Error::TransactionReadOnly => write!(f, "This transaction is read-only"),
@@ -113,6 +153,84 @@ impl fmt::Display for Error {
             ),
             Error::MaxKeySizeCannotBeDecreased => write!(f, "Max key size cannot be decreased"),
             Error::MaxValueSizeCannotBeDecreased => write!(f, "Max value size cannot be decreased"),
+            Error::CompressionError(err) => write!(f, "Compression error: {}", err),
+            Error::ClaimLost => write!(f, "Claim on the queue item was lost"),
+            Error::LockHeld => write!(f, "Lock is held by someone else"),
+            Error::LockNotHeld => write!(f, "Lock is not held by this token"),
+            Error::InvalidValueEnvelope => write!(f, "Invalid value envelope"),
+            Error::MirrorAlreadyRunning => write!(f, "Mirror already running"),
+            Error::InvalidOptions(err) => write!(f, "Invalid options: {}", err),
+            Error::TooManyTransactions => write!(f, "Too many active transactions"),
+            Error::FlagNotIndexed(flag) => write!(f, "Flag {} is not indexed", flag),
+            Error::InvalidParticipants => write!(
+                f,
+                "The stores do not match the transactions of the coordinated commit"
+            ),
+            Error::InvalidBackup(err) => write!(f, "Invalid backup: {}", err),
+            Error::Poisoned => write!(
+                f,
+                "The store is poisoned by an internal invariant violation"
+            ),
+            Error::StoreReadOnly => write!(f, "The store is read-only"),
+            Error::StoreSuspended => write!(f, "The store is suspended"),
+            Error::FsyncFailed(err) => {
+                write!(f, "Writing or syncing the commit log failed: {}", err)
+            }
+            Error::DiskFull => write!(f, "The disk is full"),
+            Error::DeadlineExceeded => write!(f, "The deadline of the read was exceeded"),
+            Error::TransactionBusy => write!(
+                f,
+                "An operation of the transaction whose future was dropped is still running"
+            ),
+            Error::CounterOverflow => write!(f, "The counter would overflow"),
+            Error::InvalidCounter => write!(f, "The value of the key is not a counter"),
+            Error::MergeOperatorMissing => write!(f, "No merge operator is registered"),
+            Error::MergeBaseUnavailable => write!(
+                f,
+                "The versions the merge operands apply to are no longer in the index"
+            ),
+            Error::ExportError(err) => write!(f, "Export error: {}", err),
+            Error::AuditFailed(err) => write!(f, "Write audit failed: {}", err),
+            Error::ValueMismatch(_) => {
+                write!(f, "The current value of the key is not the expected one")
+            }
+            Error::MissingSegments(ids) => write!(
+                f,
+                "Segments of the commit log are missing: {}",
+                ids.iter()
+                    .map(|id| id.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Error::ReservedKey => write!(f, "The key is in the keyspace reserved by the store"),
+            Error::SegmentUnavailable(id) => write!(
+                f,
+                "The key is unavailable, as segment {} of the commit log is missing",
+                id
+            ),
+            Error::NonMonotonicVersion(version, last) => write!(
+                f,
+                "Commit version {} is not after the last version {} of the store",
+                version, last
+            ),
+            Error::VersionOutOfRange(version) => write!(
+                f,
+                "Commit version {} is out of range: no version can follow it",
+                version
+            ),
+            Error::QuotaExceeded(size, max) => write!(
+                f,
+                "The commit would grow the store to {} bytes, past its maximum size of {} bytes",
+                size, max
+            ),
+            Error::CheckpointUnavailable => {
+                write!(f, "The store has no readable index checkpoint")
+            }
+            Error::IncompatibleOptions(diff) => write!(
+                f,
+                "Options incompatible with the existing store (persisted -> provided): {}",
+                diff
+            ),
         }
     }
 }
@@ -120,6 +238,18 @@ impl fmt::Display for Error {
 // Implementation of Error trait for Error
 impl std::error::Error for Error {}
 
+impl Error {
+    /// Returns true if the error reports that the disk is full.
+    pub(crate) fn is_disk_full(&self) -> bool {
+        match self {
+            Error::DiskFull => true,
+            Error::IoError(err) => crate::storage::log::is_disk_full(err),
+            Error::LogError(err) => err.is_disk_full(),
+            _ => false,
+        }
+    }
+}
+
 // Implementation to convert io::Error into Error
 impl From<io::Error> for Error {
     fn from(e: io::Error) -> Error {
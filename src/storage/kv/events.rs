@@ -0,0 +1,329 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::Instant;
+
+use parking_lot::RwLock;
+
+use crate::storage::kv::error::Result;
+
+/// Target of the events of `Store::rewrite` and `Store::compact_log`.
+pub const COMPACTION: &str = "surrealkv::compaction";
+/// Target of the events of `Store::shrink_index`, which drops the old versions and the
+/// tombstones from the index.
+pub const GC: &str = "surrealkv::gc";
+/// Target of the events of the index checkpoints.
+pub const CHECKPOINT: &str = "surrealkv::checkpoint";
+/// Target of the events of loading the index and repairing the commit log when a store is
+/// opened.
+pub const RECOVERY: &str = "surrealkv::recovery";
+/// Target of the events of the mirror.
+pub const MIRROR: &str = "surrealkv::mirror";
+/// Target of the events of `Store::migrate_values`.
+pub const MIGRATION: &str = "surrealkv::migration";
+/// Target of the events of `Store::backup` and `Store::restore`.
+pub const BACKUP: &str = "surrealkv::backup";
+/// Target of the events of the internal invariant violations, see
+/// `Options::invariant_policy`.
+pub const INVARIANT: &str = "surrealkv::invariant";
+/// Target of the events of the writer: failed commits, stats publishing and closing.
+pub const WRITER: &str = "surrealkv::writer";
+/// Target of the events of the clock the commit timestamps are taken from.
+pub const CLOCK: &str = "surrealkv::clock";
+/// Target of the events of the writes failing their audit, see `Options::write_audit_interval`.
+pub const AUDIT: &str = "surrealkv::audit";
+
+/// Severity of an event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Value of a field of an event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Value<'a> {
+    U64(u64),
+    Str(&'a str),
+}
+
+impl fmt::Display for Value<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::U64(n) => write!(f, "{}", n),
+            Value::Str(s) => write!(f, "{:?}", s),
+        }
+    }
+}
+
+/// An event of the background work of a store, such as the start, the finish or the
+/// failure of a checkpoint. The quantities describing the work, like the number of shards
+/// written or the duration in milliseconds, are in `fields`.
+#[derive(Debug)]
+pub struct Event<'a> {
+    /// One of the targets of this module, such as [`CHECKPOINT`].
+    pub target: &'static str,
+    pub level: Level,
+    pub message: &'a str,
+    pub fields: &'a [(&'static str, Value<'a>)],
+}
+
+impl fmt::Display for Event<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}: {}{}",
+            self.level,
+            self.target,
+            self.message,
+            Fields(self.fields)
+        )
+    }
+}
+
+/// The fields of an event, written after its message.
+struct Fields<'a>(&'a [(&'static str, Value<'a>)]);
+
+impl fmt::Display for Fields<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in self.0 {
+            write!(f, " {}={}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<Level> for log::Level {
+    fn from(level: Level) -> Self {
+        match level {
+            Level::Error => log::Level::Error,
+            Level::Warn => log::Level::Warn,
+            Level::Info => log::Level::Info,
+            Level::Debug => log::Level::Debug,
+        }
+    }
+}
+
+/// Receives the events of the stores of the process.
+///
+/// Without a sink, the events go to the `log` facade. A sink is the bridge to another one:
+/// forwarding each event to `tracing`, with its target, level and fields, lets the existing
+/// log pipelines of an application capture the maintenance activity of its stores.
+pub trait EventSink: Send + Sync {
+    /// Returns true if events of `level` for `target` are wanted. The fields of the events
+    /// that are not wanted are not computed.
+    fn enabled(&self, _target: &str, _level: Level) -> bool {
+        true
+    }
+
+    fn event(&self, event: &Event<'_>);
+}
+
+static SINK: RwLock<Option<Arc<dyn EventSink>>> = parking_lot::const_rwlock(None);
+
+/// Sets the sink receiving the events of all the stores of the process, replacing the
+/// previous one. Without a sink, the events are emitted through the `log` facade, with their
+/// target and level, and their fields after the message.
+pub fn set_event_sink(sink: Option<Arc<dyn EventSink>>) {
+    *SINK.write() = sink;
+}
+
+/// Returns true if events of `level` for `target` are received.
+pub(crate) fn enabled(target: &'static str, level: Level) -> bool {
+    let sink = SINK.read().clone();
+    match sink {
+        Some(sink) => sink.enabled(target, level),
+        None => log::log_enabled!(target: target, level.into()),
+    }
+}
+
+/// Emits an event.
+pub(crate) fn emit(
+    target: &'static str,
+    level: Level,
+    message: &str,
+    fields: &[(&'static str, Value<'_>)],
+) {
+    let event = Event {
+        target,
+        level,
+        message,
+        fields,
+    };
+    // The sink is called without holding the lock, so that it can replace itself.
+    let sink = SINK.read().clone();
+    match sink {
+        Some(sink) if sink.enabled(target, level) => sink.event(&event),
+        Some(_) => {}
+        None => log::log!(target: target, level.into(), "{}{}", message, Fields(fields)),
+    }
+}
+
+/// A unit of background work, whose start and finish or failure are emitted as events.
+pub(crate) struct Activity {
+    target: &'static str,
+    name: &'static str,
+    started: Instant,
+}
+
+impl Activity {
+    /// Emits the start of the work `name`, described by `fields`.
+    pub(crate) fn start(
+        target: &'static str,
+        name: &'static str,
+        fields: &[(&'static str, Value<'_>)],
+    ) -> Self {
+        if enabled(target, Level::Info) {
+            emit(target, Level::Info, &format!("{} started", name), fields);
+        }
+        Self {
+            target,
+            name,
+            started: Instant::now(),
+        }
+    }
+
+    /// Emits the finish of the work, with the fields describing its result and its duration,
+    /// or its failure, and returns the result.
+    pub(crate) fn finish<T, F>(self, result: Result<T>, fields: F) -> Result<T>
+    where
+        F: FnOnce(&T) -> Vec<(&'static str, u64)>,
+    {
+        let duration_ms = (
+            "duration_ms",
+            Value::U64(self.started.elapsed().as_millis() as u64),
+        );
+        match &result {
+            Ok(value) => {
+                if enabled(self.target, Level::Info) {
+                    let mut fields: Vec<_> = fields(value)
+                        .into_iter()
+                        .map(|(name, n)| (name, Value::U64(n)))
+                        .collect();
+                    fields.push(duration_ms);
+                    let message = format!("{} finished", self.name);
+                    emit(self.target, Level::Info, &message, &fields);
+                }
+            }
+            Err(err) => {
+                let error = err.to_string();
+                let message = format!("{} failed", self.name);
+                let fields = [("error", Value::Str(&error)), duration_ms];
+                emit(self.target, Level::Error, &message, &fields);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use parking_lot::Mutex;
+
+    use super::*;
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+
+    use tempdir::TempDir;
+
+    // The tests replacing the sink of the process run one at a time.
+    static SINK_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl EventSink for Recorder {
+        fn enabled(&self, target: &str, _level: Level) -> bool {
+            target == GC || target == CHECKPOINT
+        }
+
+        fn event(&self, event: &Event<'_>) {
+            self.0.lock().push(event.to_string());
+        }
+    }
+
+    #[tokio::test]
+    async fn background_work_events() {
+        let _lock = SINK_LOCK.lock().await;
+        let recorder = Arc::new(Recorder(Mutex::new(Vec::new())));
+        set_event_sink(Some(recorder.clone()));
+
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        let store = Store::new(opts).expect("should create store");
+        let mut txn = store.begin().unwrap();
+        txn.set(b"key", b"value").unwrap();
+        txn.commit().await.unwrap();
+        store.shrink_index().unwrap();
+        store.checkpoint_index().await.unwrap();
+        store.close().await.unwrap();
+        set_event_sink(None);
+
+        // Other tests may emit events at the same time.
+        let events = recorder.0.lock();
+        assert!(events
+            .iter()
+            .any(|e| e.starts_with("INFO surrealkv::gc: index shrink started index_bytes=")));
+        // The first checkpoint writes every shard.
+        assert!(events.iter().any(|e| e.starts_with(
+            "INFO surrealkv::checkpoint: index checkpoint finished shards_written=64 offset="
+        ) && e.contains(" duration_ms=")));
+        assert!(events.iter().all(|e| !e.contains("surrealkv::writer")));
+    }
+
+    struct Logger(Mutex<Vec<String>>);
+
+    impl log::Log for Logger {
+        fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+            metadata.target() == MIRROR
+        }
+
+        fn log(&self, record: &log::Record<'_>) {
+            if self.enabled(record.metadata()) {
+                let line = format!("{} {}: {}", record.level(), record.target(), record.args());
+                self.0.lock().push(line);
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: Logger = Logger(parking_lot::const_mutex(Vec::new()));
+
+    #[test]
+    fn events_without_sink_go_to_log() {
+        let _lock = SINK_LOCK.blocking_lock();
+        log::set_logger(&LOGGER).unwrap();
+        log::set_max_level(log::LevelFilter::Debug);
+
+        assert!(enabled(MIRROR, Level::Debug));
+        assert!(!enabled(GC, Level::Error));
+        emit(
+            MIRROR,
+            Level::Debug,
+            "mirror test event",
+            &[("batches", Value::U64(3)), ("target", Value::Str("sink"))],
+        );
+        assert!(LOGGER
+            .0
+            .lock()
+            .iter()
+            .any(|line| line
+                == "DEBUG surrealkv::mirror: mirror test event batches=3 target=\"sink\""));
+    }
+}
@@ -0,0 +1,213 @@
+use std::io::Write;
+
+use arrow_array::{ArrayRef, RecordBatch, RecordBatchReader};
+use arrow_schema::{ArrowError, SchemaRef};
+use parquet::arrow::ArrowWriter;
+
+use crate::storage::kv::{
+    error::{Error, Result},
+    iterator::ScanIterator,
+    transaction::ScanResult,
+};
+
+/// Number of entries decoded into each record batch.
+const BATCH_ROWS: usize = 8192;
+
+/// The record batches of a range of the store, returned by
+/// [`Store::export_arrow`](crate::Store::export_arrow).
+///
+/// The entries of the range are read in key order from a snapshot taken when the export is
+/// created, as a [`ScanIterator`] reads them, and decoded `BATCH_ROWS` at a time into the
+/// columns of a batch by the decoder of the export. The export is a [`RecordBatchReader`],
+/// which Arrow consumers read from directly. The errors of the store are returned as
+/// `ArrowError::ExternalError`, holding the `Error`.
+pub struct ArrowExport<F> {
+    entries: ScanIterator,
+    schema: SchemaRef,
+    decode: F,
+    batch: Vec<ScanResult>,
+}
+
+impl<F> ArrowExport<F>
+where
+    F: FnMut(&[ScanResult]) -> std::result::Result<Vec<ArrayRef>, ArrowError>,
+{
+    pub(crate) fn new(entries: ScanIterator, schema: SchemaRef, decode: F) -> Self {
+        Self {
+            entries,
+            schema,
+            decode,
+            batch: Vec::new(),
+        }
+    }
+
+    fn next_batch(&mut self) -> std::result::Result<Option<RecordBatch>, ArrowError> {
+        self.batch.clear();
+        while self.batch.len() < BATCH_ROWS {
+            match self.entries.next() {
+                Some(entry) => self
+                    .batch
+                    .push(entry.map_err(|e| ArrowError::ExternalError(Box::new(e)))?),
+                None => break,
+            }
+        }
+        if self.batch.is_empty() {
+            return Ok(None);
+        }
+        let columns = (self.decode)(&self.batch)?;
+        RecordBatch::try_new(self.schema.clone(), columns).map(Some)
+    }
+}
+
+impl<F> Iterator for ArrowExport<F>
+where
+    F: FnMut(&[ScanResult]) -> std::result::Result<Vec<ArrayRef>, ArrowError>,
+{
+    type Item = std::result::Result<RecordBatch, ArrowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_batch().transpose()
+    }
+}
+
+impl<F> RecordBatchReader for ArrowExport<F>
+where
+    F: FnMut(&[ScanResult]) -> std::result::Result<Vec<ArrayRef>, ArrowError>,
+{
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
+
+/// Writes the record batches of an export to `writer` as a Parquet file, and returns the
+/// number of rows written.
+pub(crate) fn write_parquet<F, W>(export: ArrowExport<F>, writer: W) -> Result<u64>
+where
+    F: FnMut(&[ScanResult]) -> std::result::Result<Vec<ArrayRef>, ArrowError>,
+    W: Write + Send,
+{
+    let mut writer = ArrowWriter::try_new(writer, export.schema.clone(), None)
+        .map_err(|e| Error::ExportError(e.to_string()))?;
+    let mut rows = 0;
+    for batch in export {
+        let batch = batch.map_err(from_arrow)?;
+        writer
+            .write(&batch)
+            .map_err(|e| Error::ExportError(e.to_string()))?;
+        rows += batch.num_rows() as u64;
+    }
+    writer
+        .close()
+        .map_err(|e| Error::ExportError(e.to_string()))?;
+    Ok(rows)
+}
+
+/// Returns the error of the store an Arrow error holds, or an `Error::ExportError`.
+fn from_arrow(err: ArrowError) -> Error {
+    match err {
+        ArrowError::ExternalError(err) => match err.downcast::<Error>() {
+            Ok(err) => *err,
+            Err(err) => Error::ExportError(err.to_string()),
+        },
+        err => Error::ExportError(err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Array, ArrayRef, BinaryArray, StringArray, UInt64Array};
+    use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+    use bytes::Bytes;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    use crate::storage::kv::error::Error;
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+    use crate::storage::kv::transaction::ScanResult;
+
+    use tempdir::TempDir;
+
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("key", DataType::Binary, false),
+            Field::new("value", DataType::Utf8, false),
+            Field::new("version", DataType::UInt64, false),
+        ]))
+    }
+
+    fn decode(entries: &[ScanResult]) -> Result<Vec<ArrayRef>, ArrowError> {
+        let mut values = Vec::with_capacity(entries.len());
+        for (_, value, _, _) in entries {
+            let value =
+                std::str::from_utf8(value).map_err(|e| ArrowError::ParseError(e.to_string()))?;
+            values.push(value);
+        }
+        Ok(vec![
+            Arc::new(BinaryArray::from_iter_values(
+                entries.iter().map(|entry| &entry.0),
+            )),
+            Arc::new(StringArray::from(values)),
+            Arc::new(UInt64Array::from_iter_values(
+                entries.iter().map(|entry| entry.2),
+            )),
+        ])
+    }
+
+    #[tokio::test]
+    async fn ranges_are_exported() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        let store = Store::new(opts).expect("should create store");
+
+        for chunk in 0..10u32 {
+            let mut txn = store.begin().unwrap();
+            for i in chunk * 1_000..(chunk + 1) * 1_000 {
+                txn.set(&i.to_be_bytes(), i.to_string().as_bytes()).unwrap();
+            }
+            txn.commit().await.unwrap();
+        }
+
+        // The entries are decoded in batches.
+        let start = 10u32.to_be_bytes();
+        let batches = store
+            .export_arrow(&start[..].., schema(), decode)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].num_rows() + batches[1].num_rows(), 9_990);
+        let values = batches[0].column(1);
+        let values = values.as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(values.value(0), "10");
+
+        let mut file = Vec::new();
+        let rows = store
+            .export_parquet(.., schema(), decode, &mut file)
+            .unwrap();
+        assert_eq!(rows, 10_000);
+        let reader = ParquetRecordBatchReaderBuilder::try_new(Bytes::from(file))
+            .unwrap()
+            .build()
+            .unwrap();
+        let mut read = 0;
+        for batch in reader {
+            let batch = batch.unwrap();
+            let keys = batch.column(0);
+            let keys = keys.as_any().downcast_ref::<BinaryArray>().unwrap();
+            assert_eq!(keys.value(0), (read as u32).to_be_bytes());
+            read += batch.num_rows();
+        }
+        assert_eq!(read, 10_000);
+
+        // The errors of the decoder are returned.
+        let mut txn = store.begin().unwrap();
+        txn.set(b"z", &[0xff]).unwrap();
+        txn.commit().await.unwrap();
+        let err = store.export_parquet(.., schema(), decode, Vec::new());
+        assert!(matches!(err, Err(Error::ExportError(_))));
+        store.close().await.unwrap();
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::BTreeSet;
+use std::ops::{Bound, RangeBounds};
+
+use bytes::Bytes;
+
+use crate::storage::kv::{
+    entry::Entry,
+    error::{Error, Result},
+    meta::Metadata,
+};
+
+/// Number of user flags an entry can carry.
+const FLAGS: usize = u64::BITS as usize;
+
+/// `FlagIndex` keeps, for each user flag listed in `Options::indexed_flags`, the set of keys
+/// whose latest version carries the flag, so that the flagged keys of a range can be listed
+/// without scanning it (for example to find the entries to replicate or to sweep).
+///
+/// The sets are updated when transactions are written to the index, and hold only the keys
+/// whose latest committed version is live and flagged: they are not versioned. Like the
+/// index, they are rebuilt from the commit log when the store is opened.
+pub(crate) struct FlagIndex {
+    indexed: u64,
+    keys: Vec<BTreeSet<Bytes>>,
+}
+
+impl FlagIndex {
+    pub(crate) fn new(indexed: u64) -> Self {
+        Self {
+            indexed,
+            keys: (0..FLAGS).map(|_| BTreeSet::new()).collect(),
+        }
+    }
+
+    /// Updates the sets with the entries of a transaction, in commit order.
+    pub(crate) fn apply<'a, I>(&mut self, entries: I)
+    where
+        I: IntoIterator<Item = (&'a Bytes, Option<&'a Metadata>)>,
+    {
+        if self.indexed == 0 {
+            return;
+        }
+        for (key, metadata) in entries {
+            let flags = match metadata {
+                Some(md) if !md.deleted() => md.flags(),
+                _ => 0,
+            };
+            for flag in 0..FLAGS {
+                let bit = 1 << flag;
+                if self.indexed & bit == 0 {
+                    continue;
+                }
+                if flags & bit != 0 {
+                    self.keys[flag].insert(key.clone());
+                } else {
+                    self.keys[flag].remove(&key[..]);
+                }
+            }
+        }
+    }
+
+    /// Updates the sets with the entries of a transaction.
+    pub(crate) fn apply_entries(&mut self, entries: &[Entry]) {
+        // Touch entries keep the flags of their key, unless they replace them.
+        self.apply(
+            entries
+                .iter()
+                .filter(|e| !e.is_touch() || e.is_reflag())
+                .map(|e| (&e.key, e.metadata.as_ref())),
+        );
+    }
+
+    /// Drops the keys of all the sets, when the store is cleared.
+    pub(crate) fn clear(&mut self) {
+        self.keys.iter_mut().for_each(BTreeSet::clear);
+    }
+
+    /// Returns the keys in the range whose latest version carries `flag`, in key order.
+    pub(crate) fn flagged<'a, R>(&self, flag: u8, range: R) -> Result<Vec<Vec<u8>>>
+    where
+        R: RangeBounds<&'a [u8]>,
+    {
+        if flag as usize >= FLAGS || self.indexed & (1 << flag) == 0 {
+            return Err(Error::FlagNotIndexed(flag));
+        }
+        let bounds: (Bound<&[u8]>, Bound<&[u8]>) =
+            (range.start_bound().cloned(), range.end_bound().cloned());
+        Ok(self.keys[flag as usize]
+            .range::<[u8], _>(bounds)
+            .map(|key| key.to_vec())
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::kv::error::Error;
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+
+    use tempdir::TempDir;
+
+    const REPLICATE: u8 = 0;
+    const ARCHIVED: u8 = 3;
+
+    #[tokio::test]
+    async fn flagged_keys() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.indexed_flags = 1 << REPLICATE;
+        let store = Store::new(opts.clone()).expect("should create store");
+
+        let mut txn = store.begin().unwrap();
+        for i in 0..10u8 {
+            let flags = if i % 2 == 0 { 1 << REPLICATE } else { 0 };
+            txn.set_with_flags(&[b'k', i], b"v", flags | 1 << ARCHIVED)
+                .unwrap();
+        }
+        txn.commit().await.unwrap();
+
+        // Overwriting a key without the flag, or deleting it, takes it out of the index.
+        let mut txn = store.begin().unwrap();
+        txn.set(&[b'k', 2], b"v2").unwrap();
+        txn.delete(&[b'k', 4]).unwrap();
+        txn.set_with_flags(&[b'k', 5], b"v2", 1 << REPLICATE)
+            .unwrap();
+        assert_eq!(txn.get_flags(&[b'k', 5]).unwrap(), Some(1 << REPLICATE));
+        txn.commit().await.unwrap();
+
+        let expected: Vec<Vec<u8>> = [0, 5, 6, 8].iter().map(|i| vec![b'k', *i]).collect();
+        assert_eq!(store.flagged_keys(REPLICATE, ..).unwrap(), expected);
+        assert_eq!(
+            store
+                .flagged_keys(REPLICATE, &[b'k', 1][..]..&[b'k', 6][..])
+                .unwrap(),
+            expected[1..2].to_vec()
+        );
+        assert!(matches!(
+            store.flagged_keys(ARCHIVED, ..),
+            Err(Error::FlagNotIndexed(ARCHIVED))
+        ));
+
+        let txn = store.begin().unwrap();
+        assert_eq!(txn.get_flags(&[b'k', 1]).unwrap(), Some(1 << ARCHIVED));
+        assert_eq!(txn.get_flags(&[b'k', 2]).unwrap(), Some(0));
+        assert_eq!(txn.get_flags(&[b'k', 4]).unwrap(), None);
+        drop(txn);
+        store.close().await.unwrap();
+
+        // The index is rebuilt when the store is reopened.
+        let store = Store::new(opts).expect("should reopen store");
+        assert_eq!(store.flagged_keys(REPLICATE, ..).unwrap(), expected);
+    }
+}
@@ -0,0 +1,165 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+
+use crate::storage::kv::{
+    error::{Error, Result},
+    events::{self, Level, Value},
+    invariant::Invariants,
+    option::FsyncFailurePolicy,
+};
+
+/// Hook invoked when writing or syncing the commit log fails, with the error, before
+/// `Options::fsync_failure_policy` is applied.
+pub type FsyncHook = Arc<dyn Fn(&Error) + Send + Sync>;
+
+/// Applies `Options::fsync_failure_policy` to the failures of the commit log.
+///
+/// Once a write or a sync failed, the pages written since the last sync may have been
+/// dropped by the OS: a later sync can succeed without them having reached the disk. The
+/// failed commit is then reported as failed, and no later commit is written to the log, so
+/// that no commit is reported as durable while the log may be missing some of it.
+pub(crate) struct FsyncGate {
+    policy: FsyncFailurePolicy,
+    failed: AtomicBool, // Set once a failure made the store read-only.
+    failures: AtomicU64,
+    hook: RwLock<Option<FsyncHook>>,
+}
+
+impl FsyncGate {
+    pub(crate) fn new(policy: FsyncFailurePolicy) -> Self {
+        Self {
+            policy,
+            failed: AtomicBool::new(false),
+            failures: AtomicU64::new(0),
+            hook: RwLock::new(None),
+        }
+    }
+
+    pub(crate) fn set_hook(&self, hook: Option<FsyncHook>) {
+        *self.hook.write() = hook;
+    }
+
+    pub(crate) fn hook(&self) -> Option<FsyncHook> {
+        self.hook.read().clone()
+    }
+
+    /// Returns true if a failure made the store read-only.
+    pub(crate) fn is_failed(&self) -> bool {
+        self.failed.load(Ordering::Acquire)
+    }
+
+    /// Returns `Error::StoreReadOnly` if a failure made the store read-only.
+    pub(crate) fn check(&self) -> Result<()> {
+        match self.is_failed() {
+            true => Err(Error::StoreReadOnly),
+            false => Ok(()),
+        }
+    }
+
+    /// Returns the number of failures reported.
+    pub(crate) fn failures(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    /// Reports that writing or syncing the commit log failed with `err`, and applies the
+    /// policy: it makes the store read-only, poisons it, or panics. It returns the error of
+    /// the failed operation.
+    pub(crate) fn failed(&self, invariants: &Invariants, err: Error) -> Error {
+        let error = self.report("writing or syncing the commit log failed", &err);
+        match self.policy {
+            FsyncFailurePolicy::ReadOnly => self.failed.store(true, Ordering::Release),
+            FsyncFailurePolicy::Poison => invariants.poison(),
+            FsyncFailurePolicy::Panic => {
+                panic!("writing or syncing the commit log failed: {}", error)
+            }
+        }
+        Error::FsyncFailed(error)
+    }
+
+    /// Reports that writing to the store failed because the disk is full. The store is made
+    /// read-only whatever the policy, as no write can succeed until space is freed, and
+    /// `Error::DiskFull` is returned.
+    pub(crate) fn disk_full(&self, err: Error) -> Error {
+        self.report("the disk is full", &err);
+        self.failed.store(true, Ordering::Release);
+        Error::DiskFull
+    }
+
+    /// Emits the event of a failure, counts it and invokes the hook. It returns the error
+    /// message.
+    fn report(&self, message: &str, err: &Error) -> String {
+        let error = err.to_string();
+        events::emit(
+            events::WRITER,
+            Level::Error,
+            message,
+            &[("error", Value::Str(&error))],
+        );
+        self.failures.fetch_add(1, Ordering::Relaxed);
+        let hook = self.hook();
+        if let Some(hook) = hook {
+            hook(err);
+        }
+        error
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::Arc;
+
+    use parking_lot::Mutex;
+
+    use crate::storage::kv::error::Error;
+    use crate::storage::kv::option::{FsyncFailurePolicy, Options};
+    use crate::storage::kv::store::Store;
+    use crate::storage::kv::transaction::Mode;
+
+    use tempdir::TempDir;
+
+    fn io_error() -> Error {
+        Error::IoError(Arc::new(io::Error::other("EIO")))
+    }
+
+    #[tokio::test]
+    async fn a_failure_stops_the_commits() {
+        for policy in [FsyncFailurePolicy::ReadOnly, FsyncFailurePolicy::Poison] {
+            let temp_dir = TempDir::new("test").unwrap();
+            let mut opts = Options::new();
+            opts.dir = temp_dir.path().to_path_buf();
+            opts.fsync_failure_policy = policy;
+            let store = Store::new(opts).expect("should create store");
+            let errors = Arc::new(Mutex::new(Vec::new()));
+            let seen = errors.clone();
+            store.set_fsync_failure_hook(move |err| seen.lock().push(err.to_string()));
+
+            let mut txn = store.begin().unwrap();
+            txn.set(b"k1", b"v1").unwrap();
+            txn.commit().await.unwrap();
+            let mut pending = store.begin().unwrap();
+            pending.set(b"k2", b"v2").unwrap();
+
+            let core = &store.inner.as_ref().unwrap().core;
+            let err = core.fsync.failed(&core.invariants, io_error());
+            assert!(matches!(err, Error::FsyncFailed(_)));
+            assert_eq!(errors.lock().len(), 1);
+            assert_eq!(store.stats().fsync_failures, 1);
+
+            let refused = |err: &Error| match policy {
+                FsyncFailurePolicy::ReadOnly => matches!(err, Error::StoreReadOnly),
+                _ => matches!(err, Error::Poisoned),
+            };
+            let err = pending.commit().await.unwrap_err();
+            assert!(refused(&err), "{}", err);
+            if policy == FsyncFailurePolicy::ReadOnly {
+                assert!(refused(&store.begin().err().unwrap()));
+                let txn = store.begin_with_mode(Mode::ReadOnly).unwrap();
+                assert_eq!(txn.get(b"k1").unwrap().unwrap(), b"v1");
+                assert!(txn.get(b"k2").unwrap().is_none());
+            }
+        }
+    }
+}
@@ -0,0 +1,527 @@
+use std::ops::Bound;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use hashbrown::{HashMap, HashSet};
+use vart::VariableSizeKey;
+
+use crate::storage::{
+    kv::{
+        compaction,
+        entry::{TxRecord, ValueRef},
+        error::Result,
+        events::{self, Activity, Level, Value},
+        jobs::JobKind,
+        option::Options,
+        quota,
+        reader::{Reader, TxReader},
+        store::Core,
+        util::is_system_key,
+        view::ReadView,
+    },
+    log::{MultiSegmentReader, SegmentRef, BLOCK_SIZE},
+};
+
+/// Maximum number of commit log segments read to estimate the garbage.
+const SAMPLE_SEGMENTS: usize = 16;
+
+/// An estimate of the garbage held by the commit log, returned by
+/// [`Store::gc_estimate`](crate::Store::gc_estimate).
+///
+/// The garbage is made of the versions of the keys that were overwritten or deleted, of the
+/// tombstones and of the values that expired, which `Store::rewrite` does not copy. It is
+/// estimated from a sample of the segments, so it is only exact for small commit logs.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GcEstimate {
+    pub log_bytes: u64,          // Bytes of the commit log.
+    pub reclaimable_bytes: u64,  // Estimated bytes of the commit log holding garbage.
+    pub read_bytes: u64,         // Estimated bytes a rewrite reads.
+    pub write_bytes: u64,        // Estimated bytes a rewrite writes.
+    pub sampled_segments: usize, // Number of segments read for the estimate.
+}
+
+/// The use of a sealed segment of the commit log by the values stored in it, past
+/// `Options::max_value_threshold`, returned by
+/// [`Store::segment_usage`](crate::Store::segment_usage).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SegmentUsage {
+    pub segment_id: u64,       // Id of the segment.
+    pub bytes: u64,            // Bytes of the records of the segment.
+    pub live_value_bytes: u64, // Bytes of the values of the segment that the index refers to.
+}
+
+impl SegmentUsage {
+    /// Returns the share of the bytes of the segment held by live values, 1 for an empty
+    /// segment.
+    pub fn live_ratio(&self) -> f64 {
+        match self.bytes {
+            0 => 1.0,
+            bytes => self.live_value_bytes as f64 / bytes as f64,
+        }
+    }
+
+    /// Returns the estimated bytes of the segment that are dead: its bytes other than the
+    /// live values. The records of the live keys whose values are stored in the index are
+    /// counted as dead, so that the estimate is an upper bound.
+    pub fn dead_bytes(&self) -> u64 {
+        self.bytes.saturating_sub(self.live_value_bytes)
+    }
+}
+
+/// The use of the disk by a store, returned by
+/// [`Store::disk_stats`](crate::Store::disk_stats), to decide when to compact its commit log.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DiskStats {
+    pub segments: Vec<SegmentUsage>, // Use of the sealed segments of the commit log, in segment order.
+    pub disk_bytes: u64,             // Bytes of the files of the store.
+    pub live_keys: u64,              // Number of user keys whose latest version is not deleted.
+    pub live_bytes: u64,             // Bytes of the keys and values, as stored, of those versions.
+    pub space_amp: f64,              // Space amplification, see `Store::space_amp`.
+}
+
+/// Returns the use of the disk by the store of `core`, see `Store::disk_stats`, and records
+/// its space amplification in the stats.
+pub(crate) fn disk_stats(core: &Arc<Core>) -> Result<DiskStats> {
+    if !core.opts.should_persist_data() {
+        return Ok(DiskStats::default());
+    }
+    let segments = segment_usage(core)?;
+    let disk_bytes = quota::dir_size(&*core.opts.vfs, &core.opts.dir)?;
+    let (live_keys, live_bytes) = live_data(core)?;
+    Ok(DiskStats {
+        segments,
+        disk_bytes,
+        live_keys,
+        live_bytes,
+        space_amp: record_space_amp(core, disk_bytes, live_bytes),
+    })
+}
+
+/// Returns the use of the sealed segments of the commit log of `core` by the values the
+/// versions of the index that `shrink_index` keeps refer to, in segment order. The values shared by several versions,
+/// as the touched keys share them, are counted once.
+pub(crate) fn segment_usage(core: &Arc<Core>) -> Result<Vec<SegmentUsage>> {
+    let opts = &core.opts;
+    if !opts.should_persist_data() {
+        return Ok(Vec::new());
+    }
+    let mut segments =
+        SegmentRef::read_segments_from_directory(&*opts.vfs, &opts.dir.join("clog"))?;
+    segments.sort_by_key(|segment| segment.id);
+    // The last segment is the active one.
+    segments.pop();
+
+    let mut live = HashMap::new();
+    let mut counted = HashSet::new();
+    let retention = core.retention();
+    let indexer = core.indexer.read();
+    for (key, value, version, _) in indexer.index.iter() {
+        let index_key = VariableSizeKey::from_slice(&key);
+        let older = indexer.retained_versions(&index_key, *version, &retention)?;
+        let versions = older
+            .into_iter()
+            .map(|(value, version, _)| (value, version));
+        for (value, version) in std::iter::once((value.clone(), *version)).chain(versions) {
+            let mut val_ref = ValueRef::new(core.clone());
+            val_ref.decode(version, &value)?;
+            if let Some(offset) = val_ref
+                .value_offset
+                .filter(|offset| counted.insert(*offset))
+            {
+                *live.entry(offset / opts.max_segment_size).or_insert(0) +=
+                    val_ref.value_length as u64;
+            }
+        }
+    }
+    drop(indexer);
+
+    let mut usage = Vec::with_capacity(segments.len());
+    for segment in segments {
+        let bytes = segments_bytes(opts, std::slice::from_ref(&segment))?;
+        usage.push(SegmentUsage {
+            segment_id: segment.id,
+            bytes,
+            live_value_bytes: live.get(&segment.id).copied().unwrap_or(0),
+        });
+    }
+    Ok(usage)
+}
+
+/// Collects the dead values of the commit log of `core`, see `Store::collect_values`, and
+/// returns the number of bytes of the commit log reclaimed.
+pub(crate) async fn collect_values(core: &Arc<Core>, min_live_ratio: f64) -> Result<u64> {
+    let usage = segment_usage(core)?;
+    let below = usage
+        .iter()
+        .filter(|segment| segment.live_ratio() < min_live_ratio)
+        .count();
+    events::emit(
+        events::GC,
+        Level::Debug,
+        "segment usage measured",
+        &[
+            ("sealed_segments", Value::U64(usage.len() as u64)),
+            ("segments_below_ratio", Value::U64(below as u64)),
+        ],
+    );
+    if below == 0 {
+        return Ok(0);
+    }
+    compaction::compact(core, (Bound::Unbounded, Bound::Unbounded), None).await
+}
+
+/// Estimates the garbage of the commit log of `core` by reading up to `SAMPLE_SEGMENTS`
+/// segments, spread over the log, and checking which of their entries are still the live
+/// version of their key. The share of garbage of the sample is applied to the whole log.
+pub(crate) fn estimate(core: &Arc<Core>) -> Result<GcEstimate> {
+    let activity = Activity::start(events::GC, "gc estimate", &[]);
+    let estimate = estimate_garbage(core);
+    activity.finish(estimate, |estimate| {
+        vec![
+            ("log_bytes", estimate.log_bytes),
+            ("reclaimable_bytes", estimate.reclaimable_bytes),
+        ]
+    })
+}
+
+fn estimate_garbage(core: &Arc<Core>) -> Result<GcEstimate> {
+    let opts = &core.opts;
+    if !opts.should_persist_data() {
+        return Ok(GcEstimate::default());
+    }
+
+    let mut segments =
+        SegmentRef::read_segments_from_directory(&*opts.vfs, &opts.dir.join("clog"))?;
+    segments.sort_by_key(|segment| segment.id);
+    let log_bytes = segments_bytes(opts, &segments)?;
+
+    // The entries are checked against the index as of now.
+    let view = ReadView::new(core)?;
+    let step = segments.len().div_ceil(SAMPLE_SEGMENTS).max(1);
+    let (mut sampled, mut garbage) = (0, 0);
+    let mut sampled_segments = 0;
+    for segment in segments.into_iter().step_by(step) {
+        let (read, stale) = sample_segment(core, &view, segment)?;
+        sampled += read;
+        garbage += stale;
+        sampled_segments += 1;
+    }
+
+    let reclaimable_bytes = match sampled {
+        0 => 0,
+        _ => (log_bytes as u128 * garbage as u128 / sampled as u128) as u64,
+    };
+    let live_bytes = log_bytes - reclaimable_bytes;
+    Ok(GcEstimate {
+        log_bytes,
+        reclaimable_bytes,
+        read_bytes: live_bytes,
+        write_bytes: live_bytes,
+        sampled_segments,
+    })
+}
+
+/// Returns the bytes of the commit log of a store that persists its data.
+pub(crate) fn log_bytes(opts: &Options) -> Result<u64> {
+    let segments = SegmentRef::read_segments_from_directory(&*opts.vfs, &opts.dir.join("clog"))?;
+    segments_bytes(opts, &segments)
+}
+
+/// Returns the bytes of the records of the segments, without their headers.
+fn segments_bytes(opts: &Options, segments: &[SegmentRef]) -> Result<u64> {
+    let mut bytes = 0;
+    for segment in segments {
+        let len = opts.vfs.metadata(&segment.file_path)?.len();
+        bytes += len.saturating_sub(segment.file_header_offset);
+    }
+    Ok(bytes)
+}
+
+/// Returns the space amplification of the store of `core`, see `Store::space_amp`, and
+/// records it in the stats.
+pub(crate) fn space_amp(core: &Arc<Core>) -> Result<f64> {
+    if !core.opts.should_persist_data() {
+        return Ok(0.0);
+    }
+    let disk_bytes = quota::dir_size(&*core.opts.vfs, &core.opts.dir)?;
+    let (_, live_bytes) = live_data(core)?;
+    Ok(record_space_amp(core, disk_bytes, live_bytes))
+}
+
+/// Returns the space amplification of `disk_bytes` of files holding `live_bytes` of live
+/// data, and records it in the stats.
+fn record_space_amp(core: &Core, disk_bytes: u64, live_bytes: u64) -> f64 {
+    let amp = match live_bytes {
+        0 => 0.0,
+        live_bytes => disk_bytes as f64 / live_bytes as f64,
+    };
+    core.stats
+        .space_amp_pct
+        .store((amp * 100.0) as u64, Ordering::Relaxed);
+    amp
+}
+
+/// Returns the number of the user keys whose latest version is not deleted, and the bytes
+/// of their keys and of their values, as stored.
+fn live_data(core: &Arc<Core>) -> Result<(u64, u64)> {
+    let indexer = core.indexer.read();
+    let (mut keys, mut bytes) = (0, 0);
+    for (key, value, version, _) in indexer.index.iter() {
+        let mut val_ref = ValueRef::new(core.clone());
+        val_ref.decode(*version, value)?;
+        let deleted = val_ref
+            .key_value_metadata
+            .as_ref()
+            .is_some_and(|md| md.deleted());
+        let key = core.keys.decode(key)?;
+        if !deleted && !is_system_key(&key) {
+            keys += 1;
+            bytes += (key.len() + val_ref.value_length) as u64;
+        }
+    }
+    Ok((keys, bytes))
+}
+
+/// `GcSchedule` compacts the commit log in the background while the space amplification of
+/// the store is above `Options::target_space_amp`.
+///
+/// The space amplification is measured each time the commit log moves to a new segment, by a
+/// background job, which compacts the log if it is above the target. A
+/// compaction may not meet the target, as the store holds more than its live keys and values:
+/// the target is then taken to be the space amplification the compaction reached, until one
+/// meets the target again.
+#[derive(Default)]
+pub(crate) struct GcSchedule {
+    segment_id: AtomicU64, // Segment of the commit log when the space amplification was last measured.
+    floor_pct: AtomicU64, // Space amplification left by the last compaction, in percent, if above the target.
+    running: AtomicBool, // True while a job measuring the space amplification, and compacting the log, is queued or running.
+}
+
+impl GcSchedule {
+    /// Starts measuring the space amplification, if the commit log moved to a new segment
+    /// since it was last measured and no measure is running.
+    pub(crate) fn committed(&self, core: &Arc<Core>) {
+        let target = core.opts.target_space_amp;
+        if target == 0 || !core.opts.should_persist_data() {
+            return;
+        }
+        let Ok(offset) = core.clog.as_ref().unwrap().read().offset() else {
+            return;
+        };
+        let segment_id = offset / core.opts.max_segment_size;
+        if self.segment_id.swap(segment_id, Ordering::AcqRel) == segment_id {
+            return;
+        }
+        if self.running.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let job = core.clone();
+        let queued = core.jobs.submit(JobKind::Gc, async move {
+            // A failed compaction is reported by its activity, and the next segment tries again.
+            let _ = job.gc_schedule.collect(&job, target).await;
+            job.gc_schedule.running.store(false, Ordering::Release);
+        });
+        if queued.is_none() {
+            self.running.store(false, Ordering::Release);
+        }
+    }
+
+    /// Compacts the commit log if the space amplification is above the target.
+    async fn collect(&self, core: &Arc<Core>, target: u64) -> Result<()> {
+        let threshold = target.max(self.floor_pct.load(Ordering::Acquire));
+        if (space_amp(core)? * 100.0) as u64 <= threshold {
+            return Ok(());
+        }
+        compaction::compact(core, (Bound::Unbounded, Bound::Unbounded), None).await?;
+        let reached = (space_amp(core)? * 100.0) as u64;
+        let floor = if reached > target { reached } else { 0 };
+        self.floor_pct.store(floor, Ordering::Release);
+        Ok(())
+    }
+}
+
+/// Reads the transaction records of a segment, and returns the bytes read and the bytes of
+/// the entries that are no longer the live version of their key. The reading stops at the
+/// first record that cannot be read, such as the one being written at the end of the log.
+///
+/// The bytes of a record are split between its entries in proportion to their size.
+fn sample_segment(core: &Core, view: &ReadView, segment: SegmentRef) -> Result<(u64, u64)> {
+    let opts = &core.opts;
+    let reader = MultiSegmentReader::new(&opts.vfs, vec![segment])?;
+    let reader = Reader::new_from(reader, opts.max_segment_size, BLOCK_SIZE);
+    let mut tx_reader = TxReader::new(reader, opts.max_key_size, opts.max_value_size);
+    let mut tx = TxRecord::new(opts.max_entries_per_txn as usize);
+
+    let (mut read, mut garbage) = (0, 0);
+    loop {
+        let start = tx_reader.offset();
+        tx.reset();
+        if tx_reader.read_into(&mut tx).is_err() {
+            break;
+        }
+        let len = tx_reader.offset() - start;
+
+        let (mut total, mut stale) = (0u64, 0u64);
+        for entry in &tx.entries {
+            let size = (entry.key.len() + entry.value.len()) as u64 + 1;
+            total += size;
+            if view.live_version(&entry.key)? != Some(tx.header.id) {
+                stale += size;
+            }
+        }
+        read += len;
+        if total > 0 {
+            garbage += (len as u128 * stale as u128 / total as u128) as u64;
+        }
+    }
+    Ok((read, garbage))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+
+    use tempdir::TempDir;
+
+    #[tokio::test]
+    async fn garbage_is_estimated() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.max_segment_size = 16 * 1024;
+        let store = Store::new(opts).expect("should create store");
+
+        let write = |value: u8| {
+            let store = &store;
+            async move {
+                for i in 0..100u32 {
+                    let mut txn = store.begin().unwrap();
+                    txn.set(&i.to_be_bytes(), &[value; 100]).unwrap();
+                    txn.commit().await.unwrap();
+                }
+            }
+        };
+        write(1).await;
+        let estimate = store.gc_estimate().unwrap();
+        assert!(estimate.log_bytes > 0);
+        assert_eq!(estimate.reclaimable_bytes, 0);
+        assert_eq!(estimate.write_bytes, estimate.log_bytes);
+
+        // Overwriting every key turns half of the log into garbage, deleting makes it all.
+        write(2).await;
+        let estimate = store.gc_estimate().unwrap();
+        assert!(estimate.sampled_segments > 1);
+        let share = estimate.reclaimable_bytes as f64 / estimate.log_bytes as f64;
+        assert!((0.4..0.6).contains(&share), "{}", share);
+
+        let mut txn = store.begin().unwrap();
+        for i in 0..100u32 {
+            txn.delete(&i.to_be_bytes()).unwrap();
+        }
+        txn.commit().await.unwrap();
+        let estimate = store.gc_estimate().unwrap();
+        assert_eq!(estimate.reclaimable_bytes, estimate.log_bytes);
+        assert_eq!(estimate.read_bytes, 0);
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn dead_values_are_collected() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.max_segment_size = 16 * 1024;
+        opts.max_value_threshold = 16;
+        let store = Store::new(opts).expect("should create store");
+
+        for value in 1..=4 {
+            for i in 0..100u32 {
+                let mut txn = store.begin().unwrap();
+                txn.set(&i.to_be_bytes(), &[value; 100]).unwrap();
+                txn.commit().await.unwrap();
+            }
+        }
+        // Only the last values are live.
+        let usage = store.segment_usage().unwrap();
+        assert!(usage.len() > 1);
+        assert_eq!(usage[0].live_value_bytes, 0);
+        assert!(usage[0].bytes > 0);
+
+        assert!(store.collect_values(0.5).await.unwrap() > 0);
+        let usage = store.segment_usage().unwrap();
+        assert!(usage.iter().all(|segment| segment.live_ratio() > 0.5));
+        assert_eq!(store.collect_values(0.5).await.unwrap(), 0);
+        let value = store.begin().unwrap().get(&7u32.to_be_bytes()).unwrap();
+        assert_eq!(value.unwrap(), [4; 100]);
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn disk_stats_report_the_live_data() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.max_segment_size = 16 * 1024;
+        opts.max_value_threshold = 16;
+        let store = Store::new(opts).expect("should create store");
+
+        for value in 1..=2 {
+            for i in 0..100u32 {
+                let mut txn = store.begin().unwrap();
+                txn.set(&i.to_be_bytes(), &[value; 100]).unwrap();
+                txn.commit().await.unwrap();
+            }
+        }
+        let mut txn = store.begin().unwrap();
+        txn.delete(&0u32.to_be_bytes()).unwrap();
+        txn.commit().await.unwrap();
+
+        let stats = store.disk_stats().unwrap();
+        assert_eq!(stats.live_keys, 99);
+        assert_eq!(stats.live_bytes, 99 * (4 + 100));
+        assert!(stats.disk_bytes > stats.live_bytes);
+        assert_eq!(stats.space_amp, store.space_amp().unwrap());
+        assert_eq!(stats.segments, store.segment_usage().unwrap());
+        // The first segment mostly holds overwritten values.
+        let first = &stats.segments[0];
+        assert_eq!(first.dead_bytes() + first.live_value_bytes, first.bytes);
+        assert!(first.dead_bytes() > first.live_value_bytes);
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn space_amp_is_kept_near_its_target() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.max_segment_size = 16 * 1024;
+        let store = Store::new(opts.clone()).expect("should create store");
+
+        // Each round overwrites all the keys.
+        async fn write(store: &Store, rounds: u8) {
+            for value in 0..rounds {
+                for i in 0..100u32 {
+                    let mut txn = store.begin().unwrap();
+                    txn.set(&i.to_be_bytes(), &[value; 100]).unwrap();
+                    txn.commit().await.unwrap();
+                }
+            }
+        }
+        write(&store, 5).await;
+        let amp = store.space_amp().unwrap();
+        assert!(amp > 4.0, "{}", amp);
+        assert_eq!(store.stats().space_amp_pct, (amp * 100.0) as u64);
+        store.close().await.unwrap();
+
+        opts.target_space_amp = 300;
+        let store = Store::new(opts.clone()).expect("should reopen store");
+        write(&store, 5).await;
+        // Closing waits for the compaction running.
+        store.close().await.unwrap();
+        let store = Store::new(opts).expect("should reopen store");
+        let amp = store.space_amp().unwrap();
+        assert!(amp < 4.0, "{}", amp);
+        store.close().await.unwrap();
+    }
+}
@@ -0,0 +1,109 @@
+use std::sync::Arc;
+
+use crate::storage::kv::{
+    batch::WriteBatch,
+    error::Result,
+    store::Store,
+    transaction::{Mode, Transaction},
+    view::ReadView,
+};
+
+/// The handle writing to a store split with [`Store::split`], for the applications with a
+/// single writer thread.
+///
+/// The handle is the only one the store is written through: it cannot be cloned, and moving
+/// it to the writer thread leaves no other writer. The transactions it begins are read-write,
+/// and the readers of the store are the [`ReadHandle`]s it returns.
+pub struct WriteHandle {
+    store: Arc<Store>,
+}
+
+/// A handle reading from a store split with [`Store::split`]. The handles can be cloned and
+/// sent to any number of reader threads, and the transactions they begin are read-only.
+#[derive(Clone)]
+pub struct ReadHandle {
+    store: Arc<Store>,
+}
+
+impl Store {
+    /// Splits the store into its unique write handle and a read handle. The store is closed
+    /// with [`WriteHandle::close`], or once all the handles are dropped.
+    pub fn split(self) -> (WriteHandle, ReadHandle) {
+        let store = Arc::new(self);
+        let reader = ReadHandle {
+            store: store.clone(),
+        };
+        (WriteHandle { store }, reader)
+    }
+}
+
+impl WriteHandle {
+    /// Begins a new read-write transaction.
+    pub fn begin(&self) -> Result<Transaction> {
+        self.store.begin_with_mode(Mode::ReadWrite)
+    }
+
+    /// Commits a batch of writes, see [`Store::write_batch`].
+    pub async fn write_batch(&self, batch: WriteBatch) -> Result<()> {
+        self.store.write_batch(batch).await
+    }
+
+    /// Returns a new read handle of the store.
+    pub fn reader(&self) -> ReadHandle {
+        ReadHandle {
+            store: self.store.clone(),
+        }
+    }
+
+    /// Closes the store. The read handles left return `Error::StoreClosed`.
+    pub async fn close(self) -> Result<()> {
+        self.store.close().await
+    }
+}
+
+impl ReadHandle {
+    /// Begins a new read-only transaction.
+    pub fn begin(&self) -> Result<Transaction> {
+        self.store.begin_with_mode(Mode::ReadOnly)
+    }
+
+    /// Returns a read-only view of the store as of now, see [`Store::snapshot`].
+    pub fn snapshot(&self) -> Result<ReadView> {
+        self.store.snapshot()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::kv::error::Error;
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+
+    use tempdir::TempDir;
+
+    #[tokio::test]
+    async fn writes_go_through_the_write_handle() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        let (writer, reader) = Store::new(opts).expect("should create store").split();
+
+        let mut txn = writer.begin().unwrap();
+        txn.set(b"a", b"1").unwrap();
+        txn.commit().await.unwrap();
+
+        let readers = [reader.clone(), writer.reader()];
+        for reader in &readers {
+            let mut txn = reader.begin().unwrap();
+            assert_eq!(txn.get(b"a").unwrap().unwrap(), b"1");
+            assert!(matches!(
+                txn.set(b"a", b"2"),
+                Err(Error::TransactionReadOnly)
+            ));
+        }
+        assert!(reader.snapshot().unwrap().get(b"a").unwrap().is_some());
+
+        writer.close().await.unwrap();
+        assert!(matches!(reader.begin(), Err(Error::StoreClosed)));
+    }
+}
@@ -0,0 +1,164 @@
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::storage::{
+    kv::{
+        events::{self, Level, Value},
+        option::Options,
+    },
+    vfs::{OpenOptions, SharedVfs, Vfs},
+};
+
+/// Name of the file holding the reserved space, in the directory of the store.
+pub(crate) const RESERVE_FILE: &str = "reserve";
+
+/// `Headroom` reserves `Options::reserved_space` bytes of disk space for the store.
+///
+/// The space is taken when the store is opened, by a file of zeroes written and synced in the
+/// directory of the store, as a file of that length with no data could be sparse and hold no
+/// block. When the disk fills, the file is removed, which frees the space the store needs to
+/// flush the manifest and the commit log, and write the index checkpoint, while it closes.
+/// The reservation is best effort: a store opened on a disk that is already too full to hold
+/// it is opened without.
+pub(crate) struct Headroom {
+    vfs: SharedVfs,
+    path: Option<PathBuf>, // None if no space is reserved.
+    reserved: AtomicBool,
+}
+
+impl Headroom {
+    pub(crate) fn new(opts: &Options) -> Self {
+        if opts.reserved_space == 0 || !opts.should_persist_data() {
+            return Self::none();
+        }
+
+        let path = opts.dir.join(RESERVE_FILE);
+        let reserved = match reserve(&*opts.vfs, &path, opts.reserved_space) {
+            Ok(()) => true,
+            Err(e) => {
+                let error = e.to_string();
+                events::emit(
+                    events::WRITER,
+                    Level::Warn,
+                    "the disk space could not be reserved",
+                    &[
+                        ("reserved_space", Value::U64(opts.reserved_space)),
+                        ("error", Value::Str(&error)),
+                    ],
+                );
+                let _ = opts.vfs.remove_file(&path);
+                false
+            }
+        };
+        Self {
+            vfs: opts.vfs.clone(),
+            path: Some(path),
+            reserved: AtomicBool::new(reserved),
+        }
+    }
+
+    /// Returns a headroom reserving no space.
+    pub(crate) fn none() -> Self {
+        Self {
+            vfs: SharedVfs::default(),
+            path: None,
+            reserved: AtomicBool::new(false),
+        }
+    }
+
+    /// Frees the reserved space, if any. It returns true if it was freed by this call.
+    pub(crate) fn release(&self) -> bool {
+        let path = match &self.path {
+            Some(path) if self.reserved.swap(false, Ordering::AcqRel) => path,
+            _ => return false,
+        };
+        match self.vfs.remove_file(path) {
+            Ok(()) => true,
+            Err(e) => {
+                let error = e.to_string();
+                events::emit(
+                    events::WRITER,
+                    Level::Error,
+                    "the reserved disk space could not be freed",
+                    &[("error", Value::Str(&error))],
+                );
+                false
+            }
+        }
+    }
+}
+
+/// Writes a file of `size` zeroes at `path`, unless it already holds as many bytes.
+fn reserve(vfs: &dyn Vfs, path: &Path, size: u64) -> io::Result<()> {
+    if vfs
+        .metadata(path)
+        .is_ok_and(|metadata| metadata.len() == size)
+    {
+        return Ok(());
+    }
+    let mut file = vfs.open(path, &OpenOptions::create_file())?;
+    let zeroes = [0u8; 64 * 1024];
+    let mut left = size;
+    while left > 0 {
+        let len = left.min(zeroes.len() as u64) as usize;
+        file.write_all(&zeroes[..len])?;
+        left -= len as u64;
+    }
+    file.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use crate::storage::kv::error::Error;
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+    use crate::storage::log::Error as LogError;
+
+    use super::RESERVE_FILE;
+
+    use tempdir::TempDir;
+
+    #[test]
+    fn disk_full_errors() {
+        let full = || io::Error::from_raw_os_error(if cfg!(windows) { 112 } else { 28 });
+        assert!(Error::from(full()).is_disk_full());
+        assert!(Error::LogError(LogError::from(full())).is_disk_full());
+        assert!(!Error::from(io::Error::other("EIO")).is_disk_full());
+    }
+
+    #[tokio::test]
+    async fn space_is_released_when_the_disk_fills() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.reserved_space = 100 * 1024;
+        let store = Store::new(opts.clone()).expect("should create store");
+        let reserve = temp_dir.path().join(RESERVE_FILE);
+        assert_eq!(std::fs::metadata(&reserve).unwrap().len(), 100 * 1024);
+
+        let mut txn = store.begin().unwrap();
+        txn.set(b"k1", b"v1").unwrap();
+        txn.commit().await.unwrap();
+
+        let core = &store.inner.as_ref().unwrap().core;
+        let full = Error::from(io::Error::from_raw_os_error(28));
+        assert!(matches!(core.disk_failed(full), Error::DiskFull));
+        assert!(!reserve.exists());
+        assert_eq!(store.stats().fsync_failures, 1);
+
+        // The store is read-only, and still closes.
+        assert!(matches!(store.begin().err().unwrap(), Error::StoreReadOnly));
+        store.close().await.unwrap();
+
+        // The space is reserved again when the store is opened.
+        let store = Store::new(opts).expect("should open store");
+        assert!(reserve.exists());
+        let txn = store.begin().unwrap();
+        assert_eq!(txn.get(b"k1").unwrap().unwrap(), b"v1");
+        drop(txn);
+        store.close().await.unwrap();
+    }
+}
@@ -1,25 +1,73 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
 use bytes::Bytes;
 
-use crate::storage::kv::error::Result;
+use crate::storage::kv::{
+    entry::ValueRef, error::Result, intern::KeyCodec, transaction::to_key_range,
+    util::is_system_key,
+};
 
 use vart::{
     art::{Tree as VartIndex, KV},
     snapshot::Snapshot as VartSnapshot,
-    VariableSizeKey,
+    Key, TrieError, VariableSizeKey,
 };
 
+/// Estimated size of the version and timestamp kept alongside every version of a key.
+const INDEX_VERSION_OVERHEAD: usize = 16;
+
+/// A version of a key of the index: its index key, value, version and timestamp.
+pub(crate) type IndexVersion = (Vec<u8>, Bytes, u64, u64);
+
+/// A range of keys, see `Store::compact`.
+pub(crate) type KeyRange = (Bound<Vec<u8>>, Bound<Vec<u8>>);
+
+/// The versions of the keys kept when the index is rebuilt by `Store::shrink_index`, see
+/// `Options::version_retention` and `Store::set_gc_watermark`.
+pub(crate) struct Retention {
+    pub(crate) versions: usize, // Number of latest versions of each key kept, the latest included.
+    pub(crate) since_ts: u64,   // Commit timestamp from which all the versions are kept.
+}
+
+impl Retention {
+    /// Returns true if the version of a key committed at `ts`, with `newer` versions above
+    /// it, is kept.
+    pub(crate) fn retains(&self, newer: usize, ts: u64) -> bool {
+        newer < self.versions || ts >= self.since_ts
+    }
+}
+
 /// The `Indexer` struct is responsible for managing the index of key-value pairs.
 /// It uses a `vart` index, which is a type of persistent, lock-free B+ tree.
 pub(crate) struct Indexer {
     pub(crate) index: VartIndex<VariableSizeKey, Bytes>,
+    /// Translation of the keys of the store to the keys of the index.
+    keys: Arc<KeyCodec>,
+    /// Approximate number of bytes inserted in the index. The index keeps all the versions
+    /// of the keys, so this only grows.
+    bytes: u64,
+    /// Number of bytes of the keys inserted in the index, as given and as stored in the index.
+    key_bytes: (u64, u64),
+    /// Version of the index when it was last rebuilt by `rebuild`, or 0.
+    compacted_at: u64,
+    /// Largest commit timestamp inserted in the index, kept when it is cleared.
+    last_ts: u64,
 }
 
 impl Indexer {
     /// Creates a new `Indexer` instance.
     /// The maximum number of active snapshots is set based on the provided options.
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new(keys: Arc<KeyCodec>) -> Self {
         let index = VartIndex::new();
-        Self { index }
+        Self {
+            index,
+            keys,
+            bytes: 0,
+            key_bytes: (0, 0),
+            compacted_at: 0,
+            last_ts: 0,
+        }
     }
 
     /// Creates a snapshot of the current state of the index.
@@ -29,16 +77,207 @@ impl Indexer {
     }
 
     /// Inserts multiple key-value pairs into the index.
-    /// Note: Currently, the keys are cloned to ensure they are null-terminated.
-    /// This is a known issue that needs to be fixed.
+    /// Note: Currently, the keys are cloned to ensure they are null-terminated (and their
+    /// prefixes interned). This is a known issue that needs to be fixed.
     pub fn bulk_insert(&mut self, kv_pairs: &mut [KV<VariableSizeKey, Bytes>]) -> Result<()> {
         kv_pairs.iter_mut().for_each(|kv| {
-            kv.key = kv.key.terminate();
+            let len = kv.key.len();
+            kv.key = self.keys.encode(kv.key.to_slice());
+            self.key_bytes.0 += len as u64;
+            self.key_bytes.1 += (kv.key.len() - 1) as u64;
+            self.bytes += (kv.key.len() + kv.value.len() + INDEX_VERSION_OVERHEAD) as u64;
+            self.last_ts = self.last_ts.max(kv.ts);
         });
         self.index.bulk_insert(kv_pairs)?;
         Ok(())
     }
 
+    /// Returns the versions of an index key older than `version`, its latest one, that
+    /// `retention` keeps, newest first, with their version and timestamp.
+    pub(crate) fn retained_versions(
+        &self,
+        key: &VariableSizeKey,
+        version: u64,
+        retention: &Retention,
+    ) -> Result<Vec<(Bytes, u64, u64)>> {
+        let mut retained = Vec::new();
+        let mut below = version - 1;
+        while let Some((value, found, ts)) = self.get_at(key, below)? {
+            if !retention.retains(retained.len() + 1, ts) {
+                break;
+            }
+            retained.push((value, found, ts));
+            below = found - 1;
+        }
+        Ok(retained)
+    }
+
+    /// Rebuilds the index with only the given versions of the keys. It returns the approximate number of bytes
+    /// reclaimed. Snapshots taken before the rebuild keep seeing the previous index.
+    pub(crate) fn rebuild(&mut self, versions: Vec<IndexVersion>) -> Result<u64> {
+        let mut kv_pairs = Vec::with_capacity(versions.len());
+        for (key, value, version, ts) in versions {
+            kv_pairs.push(KV {
+                key: VariableSizeKey::from(self.keys.decode(key)?),
+                value,
+                version,
+                ts,
+            });
+        }
+
+        // The versions must be inserted in increasing order.
+        kv_pairs.sort_by_key(|kv| kv.version);
+        let mut compacted = Indexer::new(self.keys.clone());
+        compacted.bulk_insert(&mut kv_pairs)?;
+        compacted.compacted_at = self.version();
+
+        let reclaimed = self.bytes.saturating_sub(compacted.bytes);
+        *self = compacted;
+        Ok(reclaimed)
+    }
+
+    /// Drops all the keys of the index, see `Store::clear`. The versions inserted next have to
+    /// follow the current version all the same. Snapshots taken before keep seeing the
+    /// previous index.
+    pub(crate) fn clear(&mut self) {
+        let last_ts = self.last_ts;
+        *self = Indexer::new(self.keys.clone());
+        self.last_ts = last_ts;
+    }
+
+    /// Returns an empty index translating the keys as this one does, which the versions
+    /// relocated by a compaction of the commit log are replayed into, see `replace`.
+    pub(crate) fn empty(&self) -> Self {
+        Indexer::new(self.keys.clone())
+    }
+
+    /// Replaces the index with the one `compacted` replayed from a compaction of the commit
+    /// log written at `version`, which is the version of the index from then on, whatever
+    /// the versions the compaction kept. Snapshots taken before keep seeing the previous
+    /// index.
+    pub(crate) fn replace(&mut self, mut compacted: Indexer, version: u64) {
+        compacted.compacted_at = version;
+        compacted.last_ts = compacted.last_ts.max(self.last_ts);
+        *self = compacted;
+    }
+
+    /// Returns the largest commit timestamp inserted in the index.
+    pub(crate) fn last_ts(&self) -> u64 {
+        self.last_ts
+    }
+
+    /// Returns the value, version and timestamp of the latest version of an index key (see
+    /// `KeyCodec`) that is not newer than `version`.
+    pub(crate) fn get_at(
+        &self,
+        key: &VariableSizeKey,
+        version: u64,
+    ) -> Result<Option<(Bytes, u64, u64)>> {
+        // The index reads version 0 as the latest one.
+        if version == 0 || self.index.version() == 0 {
+            return Ok(None);
+        }
+        match self.index.get(key, version) {
+            Ok((_, value, version, ts)) => Ok(Some((value, version, ts))),
+            Err(TrieError::KeyNotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Returns the index value written by a touch entry (see `Entry::mark_touch`): the latest
+    /// value of its key with the new expiry, and the new flags if it changes them. Returns
+    /// `None` if the key is missing or deleted, in which case the entry has nothing to change.
+    pub(crate) fn touched(
+        &self,
+        key: &[u8],
+        expires_at: Option<u64>,
+        flags: Option<u64>,
+    ) -> Result<Option<Bytes>> {
+        let Some(key) = self.keys.lookup(key) else {
+            return Ok(None);
+        };
+        let Some((value, ..)) = self.get_at(&key, self.version())? else {
+            return Ok(None);
+        };
+        ValueRef::encode_touched(&value, expires_at, flags)
+    }
+
+    /// Returns the index value written by a rename entry (see `Entry::mark_rename`): the
+    /// latest value of the key it renames, with the expiry and the flags of the entry. Returns
+    /// `None` if that key is missing or deleted, in which case the entry has nothing to move.
+    pub(crate) fn renamed(
+        &self,
+        from: &[u8],
+        expires_at: Option<u64>,
+        flags: u64,
+    ) -> Result<Option<Bytes>> {
+        self.touched(from, expires_at, Some(flags))
+    }
+
+    /// Returns the keys of a range whose latest version is not deleted, in key order.
+    pub(crate) fn live_keys(&self, range: (Bound<&[u8]>, Bound<&[u8]>)) -> Result<Vec<Vec<u8>>> {
+        let mut keys = Vec::new();
+        let Some(range) = self.keys.range(to_key_range(&range)) else {
+            return Ok(keys);
+        };
+        for (key, value, _, _) in self.index.range(range) {
+            if ValueRef::decode_metadata(value)?.is_some_and(|md| md.deleted()) {
+                continue;
+            }
+            keys.push(self.keys.decode(key)?);
+        }
+        Ok(keys)
+    }
+
+    /// Appends to `expired` the user keys whose latest version expired by `now`, among the
+    /// `limit` keys following `after` in key order, and returns the last key examined, or None
+    /// if the keys were all examined.
+    pub(crate) fn expired_keys(
+        &self,
+        after: Option<&[u8]>,
+        limit: usize,
+        now: u64,
+        expired: &mut Vec<Vec<u8>>,
+    ) -> Result<Option<Vec<u8>>> {
+        let range = (
+            after.map_or(Bound::Unbounded, Bound::Excluded),
+            Bound::Unbounded,
+        );
+        let Some(range) = self.keys.range(to_key_range(&range)) else {
+            return Ok(None);
+        };
+        for (examined, (key, value, _, _)) in self.index.range(range).enumerate() {
+            let key = self.keys.decode(key)?;
+            let expires_at = ValueRef::decode_metadata(value)?
+                .filter(|md| !md.deleted())
+                .and_then(|md| md.expires_at());
+            if expires_at.is_some_and(|at| at <= now) && !is_system_key(&key) {
+                expired.push(key.clone());
+            }
+            if examined + 1 == limit {
+                return Ok(Some(key));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the approximate number of bytes inserted in the index.
+    pub(crate) fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Returns the number of bytes the interning of the key prefixes saved on the keys
+    /// inserted in the index.
+    pub(crate) fn key_bytes_saved(&self) -> u64 {
+        self.key_bytes.0.saturating_sub(self.key_bytes.1)
+    }
+
+    /// Returns the version of the index when it was last rebuilt by `rebuild`, or 0. Only the
+    /// latest version of each key and the versions retained are kept below it.
+    pub(crate) fn compacted_at(&self) -> u64 {
+        self.compacted_at
+    }
+
     /// Returns the current version of the index.
     pub fn version(&self) -> u64 {
         self.index.version()
@@ -0,0 +1,178 @@
+use std::sync::Arc;
+
+use crate::storage::kv::{
+    error::Result,
+    store::Core,
+    transaction::{Durability, Mode, Transaction},
+};
+
+/// A buffer of writes owned by one loading thread, returned by
+/// [`Store::ingest_buffer`](crate::Store::ingest_buffer).
+///
+/// The writes are accumulated in a write-only transaction of the buffer, which takes no lock
+/// shared with the other threads, and are committed together when `batch_size` entries are
+/// buffered. Loaders writing disjoint keys from many threads then synchronize once per batch
+/// rather than once per write. Each batch is committed atomically, but the batches of a buffer
+/// are not: a failed batch leaves the batches committed before it in the store.
+///
+/// The writes still buffered are discarded if the buffer is dropped, so `flush` is called once
+/// the last entry is written.
+pub struct IngestBuffer {
+    core: Arc<Core>,
+    batch_size: usize,
+    durability: Durability,
+    txn: Option<Transaction>, // The transaction of the current batch, begun on its first write.
+    len: usize,               // Number of writes in the current batch.
+    ingested: u64,            // Number of writes committed by the buffer.
+}
+
+impl IngestBuffer {
+    pub(crate) fn new(core: Arc<Core>, batch_size: usize) -> Self {
+        // A batch is committed as one transaction, which holds at most `max_entries_per_txn`.
+        let batch_size = batch_size.clamp(1, core.opts.max_entries_per_txn.max(1) as usize);
+        Self {
+            core,
+            batch_size,
+            durability: Durability::Eventual,
+            txn: None,
+            len: 0,
+            ingested: 0,
+        }
+    }
+
+    /// Sets the durability level of the batches committed from then on.
+    pub fn set_durability(&mut self, durability: Durability) {
+        self.durability = durability;
+    }
+
+    /// Buffers a key-value pair, and commits the batch if it is full.
+    pub async fn set(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.txn()?.set(key, value)?;
+        self.written().await
+    }
+
+    /// Buffers the deletion of a key, and commits the batch if it is full.
+    pub async fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.txn()?.delete(key)?;
+        self.written().await
+    }
+
+    /// Commits the buffered writes.
+    pub async fn flush(&mut self) -> Result<()> {
+        let Some(mut txn) = self.txn.take() else {
+            return Ok(());
+        };
+        let len = std::mem::take(&mut self.len);
+        txn.set_durability(self.durability);
+        txn.commit().await?;
+        self.ingested += len as u64;
+        Ok(())
+    }
+
+    /// Returns the number of writes buffered and not yet committed.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if no write is buffered.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of writes committed by the buffer.
+    pub fn ingested(&self) -> u64 {
+        self.ingested
+    }
+
+    fn txn(&mut self) -> Result<&mut Transaction> {
+        if self.txn.is_none() {
+            self.txn = Some(Transaction::new(self.core.clone(), Mode::WriteOnly)?);
+        }
+        Ok(self.txn.as_mut().unwrap())
+    }
+
+    async fn written(&mut self) -> Result<()> {
+        self.len += 1;
+        if self.len >= self.batch_size {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+
+    use tempdir::TempDir;
+
+    #[test]
+    fn parallel_ingest() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let _guard = rt.enter();
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        let store = Arc::new(Store::new(opts).expect("should create store"));
+
+        // Each thread loads its own keys, through its own buffer.
+        let threads = 4;
+        let per_thread = 1000u32;
+        std::thread::scope(|s| {
+            for t in 0..threads {
+                let store = store.clone();
+                let rt = &rt;
+                s.spawn(move || {
+                    let mut buf = store.ingest_buffer(64);
+                    rt.block_on(async {
+                        for i in 0..per_thread {
+                            let key = format!("t{}-{:05}", t, i);
+                            buf.set(key.as_bytes(), &i.to_be_bytes()).await.unwrap();
+                        }
+                        // Whole batches are committed as they fill up, the rest on flush.
+                        assert_eq!(buf.ingested(), (per_thread / 64 * 64) as u64);
+                        assert_eq!(buf.len(), (per_thread % 64) as usize);
+                        buf.flush().await.unwrap();
+                    });
+                    assert!(buf.is_empty());
+                    assert_eq!(buf.ingested(), per_thread as u64);
+                });
+            }
+        });
+
+        let txn = store.begin().unwrap();
+        for t in 0..threads {
+            for i in 0..per_thread {
+                let key = format!("t{}-{:05}", t, i);
+                let value = txn.get(key.as_bytes()).unwrap().unwrap();
+                assert_eq!(value, i.to_be_bytes());
+            }
+        }
+        drop(txn);
+        rt.block_on(store.close()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn unflushed_writes_are_discarded() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        let store = Store::new(opts).expect("should create store");
+
+        let mut buf = store.ingest_buffer(2);
+        buf.set(b"a", b"1").await.unwrap();
+        buf.set(b"b", b"2").await.unwrap();
+        buf.delete(b"a").await.unwrap();
+        assert_eq!(buf.len(), 1);
+        drop(buf);
+
+        let txn = store.begin().unwrap();
+        assert_eq!(txn.get(b"a").unwrap().unwrap(), b"1");
+        assert_eq!(txn.get(b"b").unwrap().unwrap(), b"2");
+        drop(txn);
+        store.close().await.unwrap();
+    }
+}
@@ -0,0 +1,343 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use bytes::Bytes;
+use hashbrown::HashMap;
+use parking_lot::RwLock;
+use vart::VariableSizeKey;
+
+use crate::storage::kv::error::{Error, Result};
+
+/// Byte ending the label of a prefix in an index key. The digits of the labels are the bytes
+/// above it, so that no label followed by this byte is a prefix of another one.
+const LABEL_END: u8 = 1;
+
+/// Byte of the smallest digit of the labels.
+const DIGIT_BASE: u8 = 2;
+
+/// Number of digits of the labels.
+const DIGITS: u16 = 256 - DIGIT_BASE as u16;
+
+/// `KeyCodec` translates the keys of the store to the keys of the index, interning their
+/// first `Options::intern_prefix_len` bytes if it is set.
+///
+/// An interned prefix is replaced in the index by a label of a few bytes, held once in a
+/// table. The labels are ordered as the prefixes they stand for, and a label is never a
+/// prefix of another, so the keys of the index keep the order of the keys of the store and
+/// ranges of keys map to ranges of the index. A key shorter than the prefix length is its own
+/// prefix. The labels are assigned on first use and are not persisted, as the index is
+/// rebuilt from the commit log (or from checkpoints holding the keys) when the store opens.
+pub(crate) struct KeyCodec {
+    prefix_len: usize,
+    table: RwLock<PrefixTable>,
+}
+
+#[derive(Default)]
+struct PrefixTable {
+    labels: BTreeMap<Bytes, Bytes>, // Label of every interned prefix, in prefix order.
+    prefixes: HashMap<Bytes, Bytes>, // Prefix of every label.
+    bytes: usize,                   // Bytes of the prefixes and the labels.
+}
+
+impl KeyCodec {
+    pub(crate) fn new(prefix_len: usize) -> Self {
+        Self {
+            prefix_len,
+            table: RwLock::new(PrefixTable::default()),
+        }
+    }
+
+    /// Returns the index key (terminated with a null byte) of a key, interning its prefix.
+    pub(crate) fn encode(&self, key: &[u8]) -> VariableSizeKey {
+        if self.prefix_len == 0 {
+            return VariableSizeKey::from_slice_with_termination(key);
+        }
+        let (prefix, suffix) = key.split_at(key.len().min(self.prefix_len));
+        let label = self.table.read().labels.get(prefix).cloned();
+        let label = label.unwrap_or_else(|| self.intern(prefix));
+        Self::join(&label, suffix)
+    }
+
+    /// Returns the index key of a key, or None if its prefix was never interned, in which
+    /// case the key is not in the index.
+    pub(crate) fn lookup(&self, key: &[u8]) -> Option<VariableSizeKey> {
+        if self.prefix_len == 0 {
+            return Some(VariableSizeKey::from_slice_with_termination(key));
+        }
+        let (prefix, suffix) = key.split_at(key.len().min(self.prefix_len));
+        let label = self.table.read().labels.get(prefix)?.clone();
+        Some(Self::join(&label, suffix))
+    }
+
+    /// Returns the range of the index holding the keys of a range of keys (terminated with a
+    /// null byte), or None if no index key can be in it.
+    pub(crate) fn range(
+        &self,
+        range: (Bound<VariableSizeKey>, Bound<VariableSizeKey>),
+    ) -> Option<(Bound<VariableSizeKey>, Bound<VariableSizeKey>)> {
+        if self.prefix_len == 0 {
+            return Some(range);
+        }
+
+        // The keys with a prefix that was never interned are not in the index. A bound with
+        // such a prefix is moved to the first key of the next interned prefix: the keys with
+        // a smaller prefix are below the bound, and the others above it.
+        let table = self.table.read();
+        let bound = |key: &VariableSizeKey, included: bool, start: bool| {
+            let key = &key.to_slice()[..key.to_slice().len() - 1];
+            let (prefix, suffix) = key.split_at(key.len().min(self.prefix_len));
+            if let Some(label) = table.labels.get(prefix) {
+                let key = Self::join(label, suffix);
+                return Some(match included {
+                    true => Bound::Included(key),
+                    false => Bound::Excluded(key),
+                });
+            }
+            let next = table
+                .labels
+                .range::<[u8], _>((Bound::Excluded(prefix), Bound::Unbounded))
+                .next();
+            match (next, start) {
+                (Some((_, label)), true) => Some(Bound::Included(Self::join(label, &[]))),
+                (Some((_, label)), false) => Some(Bound::Excluded(Self::join(label, &[]))),
+                (None, true) => None,
+                (None, false) => Some(Bound::Unbounded),
+            }
+        };
+
+        let start = match &range.0 {
+            Bound::Included(key) => bound(key, true, true)?,
+            Bound::Excluded(key) => bound(key, false, true)?,
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let end = match &range.1 {
+            Bound::Included(key) => bound(key, true, false)?,
+            Bound::Excluded(key) => bound(key, false, false)?,
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        Some((start, end))
+    }
+
+    /// Returns the key of an index key (terminated with a null byte), without the null byte.
+    pub(crate) fn decode(&self, mut key: Vec<u8>) -> Result<Vec<u8>> {
+        // the keys in the vart leaf are terminated with a null byte
+        key.truncate(key.len().saturating_sub(1));
+        if self.prefix_len == 0 {
+            return Ok(key);
+        }
+
+        let end = key
+            .iter()
+            .position(|b| *b == LABEL_END)
+            .ok_or(Error::CorruptedIndex)?;
+        let table = self.table.read();
+        let prefix = table
+            .prefixes
+            .get(&key[..end])
+            .ok_or(Error::CorruptedIndex)?;
+        let mut decoded = Vec::with_capacity(prefix.len() + key.len() - end - 1);
+        decoded.extend_from_slice(prefix);
+        decoded.extend_from_slice(&key[end + 1..]);
+        Ok(decoded)
+    }
+
+    /// Returns the number of prefixes interned, and the bytes of the table holding them.
+    pub(crate) fn stats(&self) -> (u64, u64) {
+        let table = self.table.read();
+        (table.labels.len() as u64, table.bytes as u64)
+    }
+
+    /// Assigns a label to a prefix, between the labels of the prefixes around it.
+    fn intern(&self, prefix: &[u8]) -> Bytes {
+        let mut table = self.table.write();
+        if let Some(label) = table.labels.get(prefix) {
+            return label.clone();
+        }
+
+        let digits = |label: &Bytes| label.iter().map(|b| b - DIGIT_BASE).collect::<Vec<u8>>();
+        let before = table
+            .labels
+            .range::<[u8], _>((Bound::Unbounded, Bound::Excluded(prefix)))
+            .next_back()
+            .map(|(_, label)| digits(label))
+            .unwrap_or_default();
+        let after = table
+            .labels
+            .range::<[u8], _>((Bound::Excluded(prefix), Bound::Unbounded))
+            .next()
+            .map(|(_, label)| digits(label));
+        let label: Bytes = between(&before, after.as_deref())
+            .into_iter()
+            .map(|d| d + DIGIT_BASE)
+            .collect();
+
+        let prefix = Bytes::copy_from_slice(prefix);
+        table.bytes += 2 * (prefix.len() + label.len());
+        table.labels.insert(prefix.clone(), label.clone());
+        table.prefixes.insert(label.clone(), prefix);
+        label
+    }
+
+    fn join(label: &[u8], suffix: &[u8]) -> VariableSizeKey {
+        let mut key = Vec::with_capacity(label.len() + suffix.len() + 2);
+        key.extend_from_slice(label);
+        key.push(LABEL_END);
+        key.extend_from_slice(suffix);
+        key.push(0);
+        VariableSizeKey::from(key)
+    }
+}
+
+/// Returns the digits of a label between the labels `low` and `high` (None if there is no
+/// label above `low`). The labels are read as fractions, the digits after the last one
+/// being zeros; none of them ends with a zero, so that there is always a label in between.
+fn between(low: &[u8], high: Option<&[u8]>) -> Vec<u8> {
+    if let Some(high) = high {
+        let common = high
+            .iter()
+            .enumerate()
+            .take_while(|(i, d)| low.get(*i).copied().unwrap_or(0) == **d)
+            .count();
+        if common > 0 {
+            let mut label = high[..common].to_vec();
+            label.extend(between(
+                low.get(common..).unwrap_or_default(),
+                Some(&high[common..]),
+            ));
+            return label;
+        }
+    }
+
+    let first = low.first().copied().unwrap_or(0) as u16;
+    let limit = high.map_or(DIGITS, |high| high[0] as u16);
+    if limit - first > 1 {
+        vec![((first + limit) / 2) as u8]
+    } else if high.is_some_and(|high| high.len() > 1) {
+        vec![limit as u8]
+    } else {
+        let mut label = vec![first as u8];
+        label.extend(between(low.get(1..).unwrap_or_default(), None));
+        label
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+
+    use rand::{seq::SliceRandom, Rng};
+    use vart::VariableSizeKey;
+
+    use super::KeyCodec;
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+
+    use tempdir::TempDir;
+
+    #[test]
+    fn encoding_keeps_key_order() {
+        let codec = KeyCodec::new(4);
+        let mut rng = rand::thread_rng();
+        let mut keys: Vec<Vec<u8>> = (0..2000)
+            .map(|_| {
+                let len = rng.gen_range(1..8);
+                (0..len).map(|_| rng.gen_range(0..4) * 85).collect()
+            })
+            .collect();
+        keys.shuffle(&mut rng);
+
+        let mut encoded: Vec<(Vec<u8>, Vec<u8>)> = keys
+            .iter()
+            .map(|key| (codec.encode(key).to_slice().to_vec(), key.clone()))
+            .collect();
+        encoded.sort();
+        keys.sort();
+        keys.dedup();
+        encoded.dedup();
+        assert_eq!(
+            encoded
+                .iter()
+                .map(|(_, key)| key.clone())
+                .collect::<Vec<_>>(),
+            keys
+        );
+        for (index_key, key) in encoded {
+            assert_eq!(codec.decode(index_key).unwrap(), key);
+        }
+    }
+
+    #[test]
+    fn range_of_unknown_prefixes() {
+        let codec = KeyCodec::new(2);
+        for key in [&b"bb1"[..], b"dd1", b"dd2"] {
+            codec.encode(key);
+        }
+        let key = |k: &[u8]| VariableSizeKey::from_slice_with_termination(k);
+
+        // A range starting after the last prefix is empty, one ending there is not bounded.
+        assert!(codec
+            .range((Bound::Included(key(b"ee")), Bound::Unbounded))
+            .is_none());
+        let (start, end) = codec
+            .range((Bound::Excluded(key(b"cc")), Bound::Included(key(b"ee"))))
+            .unwrap();
+        assert_eq!(start, Bound::Included(codec.encode(b"dd")));
+        assert_eq!(end, Bound::Unbounded);
+        assert!(codec.lookup(b"cc").is_none());
+    }
+
+    #[tokio::test]
+    async fn interned_prefixes() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.intern_prefix_len = 17;
+        let store = Store::new(opts.clone()).expect("should create store");
+
+        let tenants = [
+            "tenant-0000000001",
+            "tenant-0000000002",
+            "tenant-0000000003",
+        ];
+        let mut txn = store.begin().unwrap();
+        for (i, tenant) in tenants.iter().enumerate().rev() {
+            for j in 0..100u32 {
+                let key = format!("{}/{:04}", tenant, j);
+                txn.set(key.as_bytes(), &[i as u8]).unwrap();
+            }
+        }
+        txn.commit().await.unwrap();
+
+        let stats = store.stats();
+        assert_eq!(stats.interned_prefixes, 3);
+        assert!(stats.interned_key_bytes_saved > 300 * 10);
+
+        // The keys are scanned in their order, across and within the prefixes.
+        let txn = store.begin().unwrap();
+        let start = b"tenant-0000000002/0050";
+        let entries = txn.scan(&start[..].., None).unwrap();
+        assert_eq!(entries.len(), 150);
+        assert_eq!(entries[0].0, start);
+        assert_eq!(entries[149].0, b"tenant-0000000003/0099");
+        assert!(txn.get(b"tenant-0000000009/0000").unwrap().is_none());
+        assert!(txn.scan(&b"u"[..].., None).unwrap().is_empty());
+        assert_eq!(txn.scan(&b"a"[..]..&b"t"[..], None).unwrap().len(), 0);
+        drop(txn);
+        store.shrink_index().unwrap();
+        store.checkpoint_index().await.unwrap();
+        store.close().await.unwrap();
+
+        // The index is rebuilt from the checkpoint with the prefixes interned again.
+        let store = Store::new(opts).expect("should reopen store");
+        let txn = store.begin().unwrap();
+        let entries: Vec<_> = store.iter(..).unwrap().map(|e| e.unwrap()).collect();
+        assert_eq!(entries.len(), 300);
+        assert!(entries.windows(2).all(|w| w[0].0 < w[1].0));
+        assert_eq!(
+            txn.get(b"tenant-0000000001/0007").unwrap().unwrap(),
+            vec![0u8]
+        );
+        drop(txn);
+        store.close().await.unwrap();
+    }
+}
@@ -0,0 +1,212 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+
+use crate::storage::kv::{
+    error::{Error, Result},
+    events::{self, Level, Value},
+    option::InvariantPolicy,
+};
+
+/// A violated internal invariant of a store, passed to the hook set with
+/// `Store::set_invariant_hook`.
+#[derive(Clone, Debug)]
+pub struct InvariantViolation {
+    /// The invariant that does not hold.
+    pub invariant: &'static str,
+    /// The state found, such as the versions or the offsets involved.
+    pub context: String,
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.invariant, self.context)
+    }
+}
+
+/// Hook invoked with the invariant violations of a store, before the policy is applied.
+pub type InvariantHook = Arc<dyn Fn(&InvariantViolation) + Send + Sync>;
+
+/// Applies `Options::invariant_policy` to the invariant violations of a store, and tracks
+/// whether the store is poisoned.
+pub(crate) struct Invariants {
+    policy: InvariantPolicy,
+    hook: RwLock<Option<InvariantHook>>,
+    poisoned: AtomicBool,
+}
+
+impl Invariants {
+    pub(crate) fn new(policy: InvariantPolicy) -> Self {
+        Self {
+            policy,
+            hook: RwLock::new(None),
+            poisoned: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn set_hook(&self, hook: Option<InvariantHook>) {
+        *self.hook.write() = hook;
+    }
+
+    pub(crate) fn hook(&self) -> Option<InvariantHook> {
+        self.hook.read().clone()
+    }
+
+    /// Poisons the store: every later operation returns `Error::Poisoned`.
+    pub(crate) fn poison(&self) {
+        self.poisoned.store(true, Ordering::Release);
+    }
+
+    /// Returns `Error::Poisoned` if an invariant violation poisoned the store.
+    pub(crate) fn check(&self) -> Result<()> {
+        match self.poisoned.load(Ordering::Acquire) {
+            true => Err(Error::Poisoned),
+            false => Ok(()),
+        }
+    }
+
+    /// Reports that `invariant` does not hold, and applies the policy: it panics, poisons
+    /// the store and returns `Error::Poisoned`, or returns Ok for the caller to carry on as
+    /// best it can.
+    pub(crate) fn violated(&self, invariant: &'static str, context: String) -> Result<()> {
+        let violation = InvariantViolation { invariant, context };
+        events::emit(
+            events::INVARIANT,
+            Level::Error,
+            "invariant violated",
+            &[
+                ("invariant", Value::Str(violation.invariant)),
+                ("context", Value::Str(&violation.context)),
+            ],
+        );
+        let hook = self.hook();
+        if let Some(hook) = hook {
+            hook(&violation);
+        }
+
+        match self.policy {
+            InvariantPolicy::Panic => panic!("invariant violated: {}", violation),
+            InvariantPolicy::Poison => {
+                self.poison();
+                Err(Error::Poisoned)
+            }
+            InvariantPolicy::Continue => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use parking_lot::Mutex;
+
+    use crate::storage::kv::entry::Entry;
+    use crate::storage::kv::error::Error;
+    use crate::storage::kv::option::{InvariantPolicy, Options};
+    use crate::storage::kv::store::Store;
+    use crate::storage::kv::transaction::Durability;
+
+    use tempdir::TempDir;
+
+    /// Writes a transaction with a version older than the last one written, which breaks
+    /// the order of the versions in the index.
+    async fn write_stale_version(store: &Store) -> crate::Result<()> {
+        let core = &store.inner.as_ref().unwrap().core;
+        let entries = vec![Entry::new(b"stale", b"value")];
+        let done = core
+            .send_to_write_channel(entries, 1, 1, Durability::Eventual)
+            .await?;
+        done.recv().await?
+    }
+
+    async fn store_with_versions(temp_dir: &TempDir, policy: InvariantPolicy) -> Store {
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.invariant_policy = policy;
+        let store = Store::new(opts).expect("should create store");
+        for _ in 0..2 {
+            let mut txn = store.begin().unwrap();
+            txn.set(b"key", b"value").unwrap();
+            txn.commit().await.unwrap();
+        }
+        store
+    }
+
+    #[tokio::test]
+    async fn poisoned_store() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let store = store_with_versions(&temp_dir, InvariantPolicy::Poison).await;
+        let violations = Arc::new(Mutex::new(Vec::new()));
+        {
+            let violations = violations.clone();
+            store.set_invariant_hook(move |v| violations.lock().push(v.clone()));
+        }
+        let mut txn = store.begin().unwrap();
+        txn.set(b"other", b"value").unwrap();
+
+        assert!(matches!(
+            write_stale_version(&store).await,
+            Err(Error::Poisoned)
+        ));
+        {
+            let violations = violations.lock();
+            assert_eq!(violations.len(), 1);
+            assert_eq!(violations[0].context, "version 1 after version 2");
+        }
+
+        // Every operation fails from then on, including the open transactions.
+        assert!(matches!(store.begin(), Err(Error::Poisoned)));
+        assert!(matches!(txn.get(b"key"), Err(Error::Poisoned)));
+        assert!(matches!(txn.commit().await, Err(Error::Poisoned)));
+        assert!(matches!(
+            store.checkpoint_index().await,
+            Err(Error::Poisoned)
+        ));
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn continue_after_violation() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let store = store_with_versions(&temp_dir, InvariantPolicy::Continue).await;
+        let calls = Arc::new(Mutex::new(0));
+        {
+            let calls = calls.clone();
+            store.set_invariant_hook(move |_| *calls.lock() += 1);
+        }
+
+        // The transaction is refused, and the store stays usable.
+        assert!(matches!(
+            write_stale_version(&store).await,
+            Err(Error::InvalidTransactionRecordId)
+        ));
+        assert_eq!(*calls.lock(), 1);
+        let mut txn = store.begin().unwrap();
+        txn.set(b"key", b"new").unwrap();
+        txn.commit().await.unwrap();
+        store.close().await.unwrap();
+
+        // Nothing of the refused transaction was written.
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        let store = Store::new(opts).expect("should reopen store");
+        let txn = store.begin().unwrap();
+        assert_eq!(txn.get(b"key").unwrap().unwrap(), b"new");
+        assert!(txn.get(b"stale").unwrap().is_none());
+        drop(txn);
+        store.close().await.unwrap();
+    }
+
+    #[test]
+    fn panic_on_violation() {
+        let invariants = super::Invariants::new(InvariantPolicy::Panic);
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _ = invariants.violated("test invariant", String::new());
+        }));
+        assert!(panicked.is_err());
+        assert!(invariants.check().is_ok());
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::VecDeque;
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+
+use vart::VariableSizeKey;
+
+use crate::storage::kv::{
+    error::Result,
+    store::Core,
+    transaction::{Mode, ScanResult, Transaction},
+    util::is_system_key,
+};
+
+/// Number of entries read from the snapshot at a time.
+const BATCH_SIZE: usize = 128;
+
+/// An iterator over the live entries of a range, in key order, returned by
+/// [`Store::iter`](crate::Store::iter).
+///
+/// The iterator reads from the snapshot of a read-only transaction, which it holds until it
+/// is dropped or checkpointed. Writes, deletes and index compaction (`Store::shrink_index`)
+/// happening meanwhile are not seen by the iterator. The commit log segments are never
+/// removed while the store is open, so the values the snapshot refers to stay readable.
+///
+/// The snapshot keeps the versions it refers to in memory, and the transaction is listed by
+/// `Store::active_transactions` (and counts towards `Options::max_active_transactions`).
+/// Very long scans can call [`checkpoint`](ScanIterator::checkpoint) periodically to release
+/// them.
+pub struct ScanIterator {
+    txn: Transaction,
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+    include_system_keys: bool,
+    // Whether the values are left unresolved, see `keys_only`.
+    keys_only: bool,
+    // Bound of the entries not read from the snapshot yet.
+    next: Bound<Vec<u8>>,
+    // Key of the last entry returned.
+    last: Option<Vec<u8>>,
+    buffer: VecDeque<ScanResult>,
+    done: bool,
+}
+
+impl ScanIterator {
+    pub(crate) fn new<'a, R>(core: &Arc<Core>, range: R) -> Result<Self>
+    where
+        R: RangeBounds<&'a [u8]>,
+    {
+        // System keys are only returned if the range starts inside the system keyspace.
+        let include_system_keys = match range.start_bound() {
+            Bound::Included(start) | Bound::Excluded(start) => is_system_key(start),
+            Bound::Unbounded => false,
+        };
+        let start = to_owned_bound(range.start_bound());
+
+        Ok(Self {
+            txn: Transaction::new(core.clone(), Mode::ReadOnly)?,
+            next: start.clone(),
+            start,
+            end: to_owned_bound(range.end_bound()),
+            include_system_keys,
+            keys_only: false,
+            last: None,
+            buffer: VecDeque::new(),
+            done: false,
+        })
+    }
+
+    /// Makes the iterator return the entries with empty values. The values are then not read
+    /// from the commit log nor added to the value cache, which avoids most of the reads of a
+    /// scan enumerating keys.
+    pub fn keys_only(mut self) -> Self {
+        self.keys_only = true;
+        self
+    }
+
+    /// Releases the snapshot and takes a new one, so that the versions written since the
+    /// iterator was created (or last checkpointed) are no longer kept alive by it.
+    ///
+    /// The iteration resumes after the last entry returned, and the entries that follow are
+    /// read as of the checkpoint: a scan with checkpoints is not consistent as a whole, but
+    /// every entry is returned at most once, in key order.
+    pub fn checkpoint(&mut self) -> Result<()> {
+        let core = self.txn.core.clone();
+        self.txn.rollback();
+        self.buffer.clear();
+        self.next = match &self.last {
+            Some(key) => Bound::Excluded(key.clone()),
+            None => self.start.clone(),
+        };
+
+        match Transaction::new(core, Mode::ReadOnly) {
+            Ok(txn) => {
+                self.txn = txn;
+                self.done = false;
+                Ok(())
+            }
+            Err(e) => {
+                self.done = true;
+                Err(e)
+            }
+        }
+    }
+
+    /// Reads the next batch of entries from the snapshot.
+    fn fill(&mut self) -> Result<()> {
+        let range = (to_index_key(&self.next), to_index_key(&self.end));
+        let batch = self.txn.scan_keys(
+            range,
+            self.include_system_keys,
+            Some(BATCH_SIZE),
+            false,
+            !self.keys_only,
+            None,
+        )?;
+
+        if batch.len() < BATCH_SIZE {
+            self.done = true;
+        }
+        if let Some((key, ..)) = batch.last() {
+            self.next = Bound::Excluded(key.clone());
+        }
+        self.buffer.extend(batch);
+        Ok(())
+    }
+}
+
+impl Iterator for ScanIterator {
+    type Item = Result<ScanResult>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buffer.is_empty() && !self.done {
+            if let Err(e) = self.fill() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+
+        let entry = self.buffer.pop_front()?;
+        self.last = Some(entry.0.clone());
+        Some(Ok(entry))
+    }
+}
+
+fn to_owned_bound(bound: Bound<&&[u8]>) -> Bound<Vec<u8>> {
+    match bound {
+        Bound::Included(k) => Bound::Included(k.to_vec()),
+        Bound::Excluded(k) => Bound::Excluded(k.to_vec()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+fn to_index_key(bound: &Bound<Vec<u8>>) -> Bound<VariableSizeKey> {
+    match bound {
+        Bound::Included(k) => Bound::Included(VariableSizeKey::from_slice_with_termination(k)),
+        Bound::Excluded(k) => Bound::Excluded(VariableSizeKey::from_slice_with_termination(k)),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+
+    use tempdir::TempDir;
+
+    fn create_store() -> (Store, TempDir) {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.max_value_threshold = 0;
+        (Store::new(opts).expect("should create store"), temp_dir)
+    }
+
+    async fn fill(store: &Store, n: u32) {
+        let mut txn = store.begin().unwrap();
+        for i in 0..n {
+            txn.set(&i.to_be_bytes(), &i.to_le_bytes()).unwrap();
+        }
+        txn.commit().await.unwrap();
+    }
+
+    fn keys(entries: &[(Vec<u8>, Vec<u8>, u64, u64)]) -> Vec<u32> {
+        entries
+            .iter()
+            .map(|(k, ..)| u32::from_be_bytes(k[..].try_into().unwrap()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn iter_range() {
+        let (store, _temp_dir) = create_store();
+        fill(&store, 1000).await;
+
+        let entries: Vec<_> = store.iter(..).unwrap().map(|e| e.unwrap()).collect();
+        assert_eq!(keys(&entries), (0..1000).collect::<Vec<_>>());
+        assert_eq!(entries[7].1, 7u32.to_le_bytes());
+
+        let start = 100u32.to_be_bytes();
+        let end = 300u32.to_be_bytes();
+        let entries: Vec<_> = store
+            .iter(&start[..]..=&end[..])
+            .unwrap()
+            .map(|e| e.unwrap())
+            .collect();
+        assert_eq!(keys(&entries), (100..=300).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn iter_is_stable_under_concurrent_changes() {
+        let (store, _temp_dir) = create_store();
+        fill(&store, 500).await;
+
+        let mut iter = store.iter(..).unwrap();
+        let mut seen = keys(&[iter.next().unwrap().unwrap()]);
+
+        // Delete everything, add a key and compact the index while the scan runs.
+        let mut txn = store.begin().unwrap();
+        for i in 0..500u32 {
+            txn.delete(&i.to_be_bytes()).unwrap();
+        }
+        txn.set(&1000u32.to_be_bytes(), b"new").unwrap();
+        txn.commit().await.unwrap();
+        store.shrink_index().unwrap();
+
+        let rest: Vec<_> = iter.map(|e| e.unwrap()).collect();
+        seen.extend(keys(&rest));
+        assert_eq!(seen, (0..500).collect::<Vec<_>>());
+        for (i, (_, v, ..)) in rest.iter().enumerate() {
+            assert_eq!(v[..], (i as u32 + 1).to_le_bytes());
+        }
+    }
+
+    #[tokio::test]
+    async fn iter_checkpoint() {
+        let (store, _temp_dir) = create_store();
+        fill(&store, 300).await;
+
+        let mut iter = store.iter(..).unwrap();
+        let first: Vec<_> = iter.by_ref().take(200).map(|e| e.unwrap()).collect();
+        assert_eq!(store.active_transactions().len(), 1);
+        let pinned = store.active_transactions()[0].id;
+
+        let mut txn = store.begin().unwrap();
+        txn.delete(&250u32.to_be_bytes()).unwrap();
+        txn.set(&150u32.to_be_bytes(), b"changed").unwrap();
+        txn.commit().await.unwrap();
+
+        // The snapshot is released and taken again, and the scan resumes where it stopped.
+        iter.checkpoint().unwrap();
+        let active = store.active_transactions();
+        assert_eq!(active.len(), 1);
+        assert_ne!(active[0].id, pinned);
+
+        let rest: Vec<_> = iter.map(|e| e.unwrap()).collect();
+        assert_eq!(keys(&first), (0..200).collect::<Vec<_>>());
+        assert_eq!(
+            keys(&rest),
+            (200..300).filter(|i| *i != 250).collect::<Vec<_>>()
+        );
+        assert!(store.active_transactions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn keys_only() {
+        let (store, _temp_dir) = create_store();
+        fill(&store, 300).await;
+        let reads = |store: &Store| {
+            let stats = store.stats();
+            stats.cache_reads + stats.log_reads
+        };
+        let before = reads(&store);
+
+        // The keys are listed without reading the values from the commit log or the cache.
+        let entries: Vec<_> = store
+            .iter(..)
+            .unwrap()
+            .keys_only()
+            .map(|e| e.unwrap())
+            .collect();
+        assert_eq!(keys(&entries), (0..300).collect::<Vec<_>>());
+        assert!(entries.iter().all(|(_, value, ..)| value.is_empty()));
+
+        let mut txn = store.begin().unwrap();
+        txn.delete(&5u32.to_be_bytes()).unwrap();
+        let start = 3u32.to_be_bytes();
+        let end = 7u32.to_be_bytes();
+        let listed: Vec<_> = txn
+            .keys(&start[..]..&end[..], None)
+            .unwrap()
+            .iter()
+            .map(|k| u32::from_be_bytes(k[..].try_into().unwrap()))
+            .collect();
+        assert_eq!(listed, [3, 4, 6]);
+        assert_eq!(txn.keys(.., Some(2)).unwrap().len(), 2);
+        assert_eq!(reads(&store), before);
+
+        assert_eq!(store.iter(..).unwrap().count(), 300);
+        assert!(reads(&store) > before);
+    }
+}
@@ -0,0 +1,260 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::thread;
+
+use parking_lot::{Condvar, Mutex};
+use tokio::runtime::Handle;
+use tokio::sync::oneshot;
+use tokio::task::spawn_blocking;
+
+/// The kinds of the background jobs of a store, run in the order of their priority, see
+/// `Options::job_priorities`.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum JobKind {
+    /// Publications of the stats, see `Options::stats_publish_interval`.
+    Flush,
+    /// Merges of the tiny segments of the commit log, see `Options::tiny_segment_merge`.
+    Compaction,
+    /// Compactions of the commit log meeting `Options::target_space_amp`.
+    Gc,
+    /// Sweeps of the expired keys, see `Options::expiry_sweep_interval`.
+    Expiry,
+}
+
+/// Priorities of the kinds of background jobs. The queued job of the highest priority runs
+/// first, and the jobs of the same priority in the order they were queued.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct JobPriorities {
+    pub flush: u8,      // Priority of the publications of the stats.
+    pub compaction: u8, // Priority of the merges of the tiny segments.
+    pub gc: u8,         // Priority of the compactions meeting the space amplification target.
+    pub expiry: u8,     // Priority of the sweeps of the expired keys.
+}
+
+impl Default for JobPriorities {
+    /// The short jobs run first, the compactions last.
+    fn default() -> Self {
+        Self {
+            flush: 3,
+            compaction: 0,
+            gc: 1,
+            expiry: 2,
+        }
+    }
+}
+
+impl JobPriorities {
+    fn of(&self, kind: JobKind) -> u8 {
+        match kind {
+            JobKind::Flush => self.flush,
+            JobKind::Compaction => self.compaction,
+            JobKind::Gc => self.gc,
+            JobKind::Expiry => self.expiry,
+        }
+    }
+}
+
+type Job = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+struct Queued {
+    priority: u8,
+    seq: Reverse<u64>, // The jobs queued first run first.
+    job: Job,
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        (self.priority, self.seq) == (other.priority, other.seq)
+    }
+}
+
+impl Eq for Queued {}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.priority, self.seq).cmp(&(other.priority, other.seq))
+    }
+}
+
+#[derive(Default)]
+struct Queue {
+    jobs: BinaryHeap<Queued>,
+    seq: u64,
+    threads: usize, // Threads of the pool started.
+    idle: usize,    // Threads of the pool waiting for a job.
+    stopped: bool,
+}
+
+#[derive(Default)]
+struct Shared {
+    queue: Mutex<Queue>,
+    queued: Condvar, // Notified when a job is queued, or the pool stopped.
+    done: Condvar,   // Notified when a job is done.
+}
+
+/// A job queued in the pool, which can be waited for.
+pub(crate) struct JobHandle {
+    done: oneshot::Receiver<()>,
+}
+
+impl JobHandle {
+    /// Waits for the job to run, blocking the thread.
+    pub(crate) fn wait_blocking(self) {
+        let _ = self.done.blocking_recv();
+    }
+}
+
+/// `Jobs` is the pool of the threads running the background jobs of a store: the sweeps of
+/// the expired keys, the compactions of the commit log and the publications of the stats.
+///
+/// The pool starts up to `Options::background_threads` threads, as the jobs are queued, so
+/// that the maintenance of the store keeps to a bounded number of threads, apart from the
+/// runtime the commits are written on. The jobs are futures, run on the runtime the store was
+/// opened on, each driven by its thread of the pool. The threads stop once the pool is
+/// stopped and the jobs queued are run.
+pub(crate) struct Jobs {
+    threads: usize,
+    priorities: JobPriorities,
+    handle: Option<Handle>,
+    shared: Arc<Shared>,
+}
+
+impl Jobs {
+    pub(crate) fn new(threads: usize, priorities: JobPriorities) -> Self {
+        Self {
+            threads: threads.max(1),
+            priorities,
+            handle: Handle::try_current().ok(),
+            shared: Arc::default(),
+        }
+    }
+
+    /// Returns true if the store was opened on a runtime, which the jobs run on.
+    pub(crate) fn has_runtime(&self) -> bool {
+        self.handle.is_some()
+    }
+
+    /// Queues a job of `kind`, or returns None if the pool is stopped, or the store was not
+    /// opened on a runtime.
+    pub(crate) fn submit<F>(&self, kind: JobKind, job: F) -> Option<JobHandle>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let handle = self.handle.clone()?;
+        let (done_tx, done) = oneshot::channel();
+        let job = Box::pin(async move {
+            job.await;
+            let _ = done_tx.send(());
+        });
+
+        let mut queue = self.shared.queue.lock();
+        if queue.stopped {
+            return None;
+        }
+        queue.seq += 1;
+        let seq = Reverse(queue.seq);
+        let priority = self.priorities.of(kind);
+        queue.jobs.push(Queued { priority, seq, job });
+        if queue.idle == 0 && queue.threads < self.threads {
+            queue.threads += 1;
+            let shared = self.shared.clone();
+            thread::spawn(move || run(&shared, &handle));
+        } else {
+            self.shared.queued.notify_one();
+        }
+        Some(JobHandle { done })
+    }
+
+    /// Stops the pool, and waits for the jobs queued to run. The jobs queued from then on
+    /// are refused.
+    pub(crate) async fn stop(&self) {
+        let shared = self.shared.clone();
+        let _ = spawn_blocking(move || {
+            let mut queue = shared.queue.lock();
+            queue.stopped = true;
+            shared.queued.notify_all();
+            while !queue.jobs.is_empty() || queue.idle < queue.threads {
+                shared.done.wait(&mut queue);
+            }
+        })
+        .await;
+    }
+}
+
+impl Drop for Jobs {
+    fn drop(&mut self) {
+        self.shared.queue.lock().stopped = true;
+        self.shared.queued.notify_all();
+    }
+}
+
+/// Runs the jobs of the pool, the queued job of the highest priority first, until the pool is
+/// stopped and its jobs are run.
+fn run(shared: &Shared, handle: &Handle) {
+    let mut queue = shared.queue.lock();
+    loop {
+        match queue.jobs.pop() {
+            Some(Queued { job, .. }) => {
+                drop(queue);
+                handle.block_on(job);
+                queue = shared.queue.lock();
+            }
+            None if queue.stopped => {
+                queue.threads -= 1;
+                shared.done.notify_all();
+                return;
+            }
+            None => {
+                queue.idle += 1;
+                shared.done.notify_all();
+                shared.queued.wait(&mut queue);
+                queue.idle -= 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use parking_lot::Mutex;
+
+    use super::{JobKind, JobPriorities, Jobs};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn jobs_run_by_priority() {
+        let jobs = Jobs::new(1, JobPriorities::default());
+        let ran = Arc::new(Mutex::new(Vec::new()));
+
+        // The first job holds the only thread while the others are queued.
+        let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+        jobs.submit(JobKind::Gc, async move {
+            let _ = release_rx.recv_timeout(Duration::from_secs(5));
+        })
+        .unwrap();
+        for kind in [JobKind::Compaction, JobKind::Expiry, JobKind::Flush] {
+            let ran = ran.clone();
+            jobs.submit(kind, async move { ran.lock().push(kind) });
+        }
+        release_tx.send(()).unwrap();
+
+        // Stopping the pool waits for the jobs queued, and refuses the next ones.
+        jobs.stop().await;
+        assert_eq!(
+            *ran.lock(),
+            vec![JobKind::Flush, JobKind::Expiry, JobKind::Compaction]
+        );
+        assert!(jobs.submit(JobKind::Flush, async {}).is_none());
+    }
+}
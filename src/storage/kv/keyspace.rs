@@ -0,0 +1,248 @@
+use std::ops::{Bound, RangeBounds};
+
+use bytes::{BufMut, Bytes};
+
+use crate::storage::kv::{
+    error::{Error, Result},
+    util::{prefix_end, system_key},
+};
+
+/// Subsystem prefix of the keys of the named keyspaces.
+const KEYSPACE_SUBSYSTEM: &[u8] = b"ks/";
+
+/// A named keyspace of a store, accessed through the `*_in` methods of a transaction such as
+/// [`Transaction::set_in`](crate::Transaction::set_in).
+///
+/// The keys of a keyspace are kept apart from the keys of the other keyspaces and from the
+/// default keyspace, whose scans do not return them. A transaction can read and write any
+/// number of keyspaces: it sees its own writes in all of them, commits them atomically, and
+/// its reads of every keyspace are checked for conflicts. The maximum key size applies to the
+/// keys of a keyspace once prefixed with the name of the keyspace.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Keyspace {
+    name: Bytes,
+    prefix: Bytes, // Prefix of the keys of the keyspace in the store.
+}
+
+impl Keyspace {
+    /// Returns the keyspace with the given name. It returns `Error::EmptyKey` if the name is
+    /// empty.
+    pub fn new(name: &[u8]) -> Result<Self> {
+        Ok(Self {
+            name: Bytes::copy_from_slice(name),
+            prefix: system_key(KEYSPACE_SUBSYSTEM, name)?.freeze(),
+        })
+    }
+
+    /// Returns the name of the keyspace.
+    pub fn name(&self) -> &[u8] {
+        &self.name
+    }
+
+    /// Returns the key in the store of a key of the keyspace.
+    pub(crate) fn key(&self, key: &[u8]) -> Result<Vec<u8>> {
+        if key.is_empty() {
+            return Err(Error::EmptyKey);
+        }
+        let mut buf = Vec::with_capacity(self.prefix.len() + key.len());
+        buf.put(&self.prefix[..]);
+        buf.put(key);
+        Ok(buf)
+    }
+
+    /// Returns the range of keys in the store of a range of keys of the keyspace.
+    pub(crate) fn range<'a, R>(&self, range: &R) -> (Bound<Vec<u8>>, Bound<Vec<u8>>)
+    where
+        R: RangeBounds<&'a [u8]>,
+    {
+        let prefixed = |key: &[u8]| [&self.prefix[..], key].concat();
+        let start = match range.start_bound() {
+            Bound::Included(key) => Bound::Included(prefixed(key)),
+            Bound::Excluded(key) => Bound::Excluded(prefixed(key)),
+            Bound::Unbounded => Bound::Included(self.prefix.to_vec()),
+        };
+        let end = match range.end_bound() {
+            Bound::Included(key) => Bound::Included(prefixed(key)),
+            Bound::Excluded(key) => Bound::Excluded(prefixed(key)),
+            // The prefix ends with the name, after the system prefix: it is never all 0xff.
+            Bound::Unbounded => Bound::Excluded(prefix_end(&self.prefix).unwrap()),
+        };
+        (start, end)
+    }
+
+    /// Returns the key of the keyspace of a key in the store.
+    pub(crate) fn strip(&self, mut key: Vec<u8>) -> Vec<u8> {
+        key.drain(..self.prefix.len());
+        key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Keyspace;
+    use crate::storage::kv::error::Error;
+    use crate::storage::kv::option::{IsolationLevel, Options};
+    use crate::storage::kv::store::Store;
+
+    use tempdir::TempDir;
+
+    fn create_store(temp_dir: &TempDir, isolation_level: IsolationLevel) -> Store {
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.isolation_level = isolation_level;
+        Store::new(opts).expect("should create store")
+    }
+
+    fn keys(entries: &[(Vec<u8>, Vec<u8>, u64, u64)]) -> Vec<&[u8]> {
+        entries.iter().map(|(k, ..)| &k[..]).collect()
+    }
+
+    #[tokio::test]
+    async fn read_your_writes_across_keyspaces() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let store = create_store(&temp_dir, IsolationLevel::SerializableSnapshotIsolation);
+        let users = Keyspace::new(b"users").unwrap();
+        let orders = Keyspace::new(b"orders").unwrap();
+
+        let mut txn = store.begin().unwrap();
+        txn.set_in(&users, b"alice", b"1").unwrap();
+        txn.set_in(&users, b"bob", b"2").unwrap();
+        txn.set_in(&orders, b"alice", b"order").unwrap();
+        txn.set(b"alice", b"default").unwrap();
+
+        // The uncommitted writes are seen in every keyspace, and only in their own.
+        assert_eq!(txn.get_in(&users, b"alice").unwrap().unwrap(), b"1");
+        assert_eq!(txn.get_in(&orders, b"alice").unwrap().unwrap(), b"order");
+        assert!(txn.get_in(&orders, b"bob").unwrap().is_none());
+        let entries = txn.scan_in(&users, .., None).unwrap();
+        assert_eq!(keys(&entries), [&b"alice"[..], b"bob"]);
+        let entries = txn.scan_in(&users, &b"b"[..].., None).unwrap();
+        assert_eq!(keys(&entries), [&b"bob"[..]]);
+        assert_eq!(keys(&txn.scan(.., None).unwrap()), [&b"alice"[..]]);
+
+        txn.delete_in(&users, b"alice").unwrap();
+        assert_eq!(keys(&txn.scan_in(&users, .., None).unwrap()), [&b"bob"[..]]);
+        txn.commit().await.unwrap();
+
+        // The writes to all the keyspaces were committed together.
+        let txn = store.begin().unwrap();
+        assert!(txn.get_in(&users, b"alice").unwrap().is_none());
+        assert_eq!(txn.get_in(&users, b"bob").unwrap().unwrap(), b"2");
+        assert_eq!(
+            keys(&txn.scan_in(&orders, .., None).unwrap()),
+            [&b"alice"[..]]
+        );
+        assert_eq!(store.iter(..).unwrap().count(), 1);
+        drop(txn);
+
+        // A rolled back transaction leaves none of its keyspaces changed.
+        let mut txn = store.begin().unwrap();
+        txn.set_in(&users, b"carol", b"3").unwrap();
+        txn.set_in(&orders, b"carol", b"order").unwrap();
+        txn.rollback();
+        let txn = store.begin().unwrap();
+        assert!(txn.get_in(&users, b"carol").unwrap().is_none());
+        assert!(txn.get_in(&orders, b"carol").unwrap().is_none());
+        drop(txn);
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn conflicts_across_keyspaces() {
+        for isolation_level in [
+            IsolationLevel::SnapshotIsolation,
+            IsolationLevel::SerializableSnapshotIsolation,
+        ] {
+            let temp_dir = TempDir::new("test").unwrap();
+            let store = create_store(&temp_dir, isolation_level);
+            let stock = Keyspace::new(b"stock").unwrap();
+            let orders = Keyspace::new(b"orders").unwrap();
+
+            let mut txn = store.begin().unwrap();
+            txn.set_in(&stock, b"item", b"1").unwrap();
+            txn.commit().await.unwrap();
+
+            // A transaction reading one keyspace and writing another conflicts with a
+            // transaction writing what it read.
+            let mut order = store.begin().unwrap();
+            assert_eq!(order.get_in(&stock, b"item").unwrap().unwrap(), b"1");
+            order.set_in(&orders, b"order-1", b"item").unwrap();
+
+            let mut restock = store.begin().unwrap();
+            restock.set_in(&stock, b"item", b"0").unwrap();
+            restock.commit().await.unwrap();
+
+            assert!(matches!(
+                order.commit().await,
+                Err(Error::TransactionReadConflict)
+            ));
+
+            // The same key in another keyspace is another key.
+            let mut order = store.begin().unwrap();
+            assert!(order.get_in(&orders, b"item").unwrap().is_none());
+            order.set_in(&orders, b"order-2", b"item").unwrap();
+
+            let mut restock = store.begin().unwrap();
+            restock.set_in(&stock, b"item", b"5").unwrap();
+            restock.commit().await.unwrap();
+            order.commit().await.unwrap();
+            store.close().await.unwrap();
+        }
+
+        // Scans of a keyspace are checked for conflicts with the keys written in the range.
+        let temp_dir = TempDir::new("test").unwrap();
+        let store = create_store(&temp_dir, IsolationLevel::SerializableSnapshotIsolation);
+        let stock = Keyspace::new(b"stock").unwrap();
+        let orders = Keyspace::new(b"orders").unwrap();
+
+        let mut txn = store.begin().unwrap();
+        txn.set_in(&stock, b"item", b"1").unwrap();
+        txn.commit().await.unwrap();
+
+        let mut order = store.begin().unwrap();
+        assert_eq!(order.scan_in(&stock, .., None).unwrap().len(), 1);
+        order.set_in(&orders, b"order-3", b"item").unwrap();
+
+        let mut restock = store.begin().unwrap();
+        restock.set_in(&stock, b"new-item", b"1").unwrap();
+        restock.commit().await.unwrap();
+
+        assert!(matches!(
+            order.commit().await,
+            Err(Error::TransactionReadConflict)
+        ));
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn system_keys_are_reserved() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let store = create_store(&temp_dir, IsolationLevel::SnapshotIsolation);
+        let users = Keyspace::new(b"users").unwrap();
+        let key = users.key(b"alice").unwrap();
+
+        let mut txn = store.begin().unwrap();
+        txn.set_in(&users, b"alice", b"1").unwrap();
+        txn.commit().await.unwrap();
+
+        // The keys of the system keyspace cannot be written as user keys, which would overwrite
+        // the data of the store.
+        let mut txn = store.begin().unwrap();
+        assert!(matches!(txn.set(&key, b"2"), Err(Error::ReservedKey)));
+        assert!(matches!(txn.delete(&key), Err(Error::ReservedKey)));
+        assert!(matches!(txn.incr(&key, 1), Err(Error::ReservedKey)));
+        assert!(matches!(txn.rename(&key, b"bob"), Err(Error::ReservedKey)));
+        assert!(matches!(
+            txn.delete_range(&key[..]..),
+            Err(Error::ReservedKey)
+        ));
+        txn.set(b"\xff", b"user").unwrap();
+        txn.delete_range(&b"\xff"[..]..).unwrap();
+        txn.commit().await.unwrap();
+
+        let txn = store.begin().unwrap();
+        assert_eq!(txn.get_in(&users, b"alice").unwrap().unwrap(), b"1");
+        assert!(txn.get(b"\xff").unwrap().is_none());
+        store.close().await.unwrap();
+    }
+}
@@ -0,0 +1,186 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::storage::kv::{
+    error::{Error, Result},
+    store::Core,
+    transaction::{Durability, Mode, Transaction},
+    util::{now, system_key},
+};
+
+/// Subsystem prefix of the keys holding advisory locks.
+const LOCK_SUBSYSTEM: &[u8] = b"lock/";
+
+/// A token proving ownership of an advisory lock, returned by
+/// [`Store::lock`](crate::Store::lock) and consumed by [`Store::unlock`](crate::Store::unlock).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LockToken {
+    key: Bytes,      // Key of the lock in the store.
+    token: u64,      // Token identifying this acquisition of the lock.
+    expires_at: u64, // Time at which the lease expires, in nanoseconds since the Unix epoch.
+}
+
+impl LockToken {
+    /// Returns the time at which the lease expires, in nanoseconds since the Unix epoch.
+    pub fn expires_at(&self) -> u64 {
+        self.expires_at
+    }
+}
+
+/// Decodes the value of a lock into its expiry time and token.
+fn decode(value: &[u8]) -> Result<(u64, u64)> {
+    if value.len() != 16 {
+        return Err(Error::CorruptedIndex);
+    }
+    Ok((
+        u64::from_be_bytes(value[..8].try_into().unwrap()),
+        u64::from_be_bytes(value[8..].try_into().unwrap()),
+    ))
+}
+
+/// Acquires the advisory lock with the given name for the duration of the lease.
+///
+/// The lock is stored durably, so it is still held after the store is reopened until the lease
+/// expires. It returns `Error::LockHeld` if the lock is held by someone else and its lease has
+/// not expired.
+pub(crate) async fn lock(core: &Arc<Core>, name: &[u8], ttl: Duration) -> Result<LockToken> {
+    let key = system_key(LOCK_SUBSYSTEM, name)?.freeze();
+
+    let mut txn = Transaction::new(core.clone(), Mode::ReadWrite)?;
+    let ts = now();
+    if let Some(value) = txn.get(&key)? {
+        let (expires_at, _) = decode(&value)?;
+        if expires_at > ts {
+            return Err(Error::LockHeld);
+        }
+    }
+
+    let expires_at = ts.saturating_add(ttl.as_nanos() as u64);
+    let mut value = BytesMut::with_capacity(16);
+    value.put_u64(expires_at);
+    value.put_u64(ts);
+    txn.set_system(&key, &value)?;
+
+    // The lock must survive a crash once it has been handed out.
+    txn.set_durability(Durability::Immediate);
+    match txn.commit().await {
+        Ok(()) => Ok(LockToken {
+            key,
+            token: ts,
+            expires_at,
+        }),
+        // Someone else acquired the lock concurrently.
+        Err(Error::TransactionReadConflict) => Err(Error::LockHeld),
+        Err(e) => Err(e),
+    }
+}
+
+/// Releases an advisory lock.
+///
+/// It returns `Error::LockNotHeld` if the lock was released already, or was acquired by someone
+/// else after the lease of the token expired.
+pub(crate) async fn unlock(core: &Arc<Core>, token: &LockToken) -> Result<()> {
+    let mut txn = Transaction::new(core.clone(), Mode::ReadWrite)?;
+    match txn.get(&token.key)? {
+        Some(value) if decode(&value)?.1 == token.token => {}
+        _ => return Err(Error::LockNotHeld),
+    }
+
+    txn.delete_system(&token.key)?;
+    txn.set_durability(Durability::Immediate);
+    match txn.commit().await {
+        Err(Error::TransactionReadConflict) => Err(Error::LockNotHeld),
+        res => res,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::storage::kv::error::Error;
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+
+    use tempdir::TempDir;
+
+    #[tokio::test]
+    async fn lock_unlock() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        let store = Store::new(opts).expect("should create store");
+
+        let token = store
+            .lock(b"migrator", Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert!(matches!(
+            store.lock(b"migrator", Duration::from_secs(60)).await,
+            Err(Error::LockHeld)
+        ));
+
+        // Other locks are independent.
+        let other = store.lock(b"other", Duration::from_secs(60)).await.unwrap();
+
+        store.unlock(&token).await.unwrap();
+        assert!(matches!(
+            store.unlock(&token).await,
+            Err(Error::LockNotHeld)
+        ));
+
+        let token = store
+            .lock(b"migrator", Duration::from_secs(60))
+            .await
+            .unwrap();
+        store.unlock(&token).await.unwrap();
+        store.unlock(&other).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn lock_expires() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        let store = Store::new(opts).expect("should create store");
+
+        let stale = store
+            .lock(b"migrator", Duration::from_millis(1))
+            .await
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+
+        let token = store
+            .lock(b"migrator", Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert!(matches!(
+            store.unlock(&stale).await,
+            Err(Error::LockNotHeld)
+        ));
+        store.unlock(&token).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn lock_survives_reopen() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+
+        let store = Store::new(opts.clone()).expect("should create store");
+        let token = store
+            .lock(b"migrator", Duration::from_secs(60))
+            .await
+            .unwrap();
+        store.close().await.unwrap();
+
+        let store = Store::new(opts).expect("should reopen store");
+        assert!(matches!(
+            store.lock(b"migrator", Duration::from_secs(60)).await,
+            Err(Error::LockHeld)
+        ));
+        store.unlock(&token).await.unwrap();
+    }
+}
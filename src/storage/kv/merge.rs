@@ -0,0 +1,453 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use hashbrown::HashMap;
+use parking_lot::RwLock;
+use vart::VariableSizeKey;
+
+use crate::storage::kv::{
+    entry::{Value, ValueRef},
+    error::{Error, Result},
+    indexer::Indexer,
+    meta::Metadata,
+    snapshot::is_expired,
+    store::Core,
+    util::check_deadline,
+};
+
+/// Merge operator of a store, registered with
+/// [`Store::set_merge_operator`](crate::Store::set_merge_operator). It is called with a key,
+/// its base value, if any, and the operands merged into it since, oldest first, and returns
+/// the merged value of the key.
+pub type MergeOperator = Arc<dyn Fn(&[u8], Option<&[u8]>, &[&[u8]]) -> Vec<u8> + Send + Sync>;
+
+/// Where the operands being merged were written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Origin {
+    /// Committed, at their version of the index.
+    Committed,
+    /// Not yet in the index, at the version above the versions the operands are merged with.
+    Uncommitted,
+}
+
+/// `Merger` holds the merge operator of a store, which `Transaction::merge` writes operands
+/// for.
+///
+/// A merge is written as a list of operands rather than as a value: the index keeps it as
+/// the version of its key, and the value is merged from the list and the older versions of
+/// the key when it is read, back to the base value of the key, the latest version that is
+/// not a list of operands. The versions are collapsed into the merged value when the index
+/// is shrunk or checkpointed. The chunks of `Transaction::append_value` are written as lists
+/// of operands too, which are appended to the value rather than merged by the operator.
+pub(crate) struct Merger {
+    operator: RwLock<Option<MergeOperator>>,
+}
+
+impl Merger {
+    pub(crate) fn new() -> Self {
+        Self {
+            operator: RwLock::new(None),
+        }
+    }
+
+    pub(crate) fn set_operator(&self, operator: Option<MergeOperator>) {
+        *self.operator.write() = operator;
+    }
+
+    /// Returns the merge operator, if one is registered.
+    pub(crate) fn get(&self) -> Option<MergeOperator> {
+        self.operator.read().clone()
+    }
+
+    /// Returns the merge operator, or `Error::MergeOperatorMissing` if none is registered.
+    pub(crate) fn operator(&self) -> Result<MergeOperator> {
+        self.get().ok_or(Error::MergeOperatorMissing)
+    }
+}
+
+/// The versions of the index the values merged with operands are read from.
+pub(crate) trait Versions {
+    /// Returns the latest version of an index key that is not newer than `version`.
+    fn get_at(&self, key: &VariableSizeKey, version: u64) -> Result<Option<(Bytes, u64, u64)>>;
+
+    /// Returns the current version of the index.
+    fn version(&self) -> u64;
+
+    /// Returns the version the index was last shrunk at, below which only the latest version
+    /// of each key was kept.
+    fn compacted_at(&self) -> u64;
+}
+
+impl Versions for Indexer {
+    fn get_at(&self, key: &VariableSizeKey, version: u64) -> Result<Option<(Bytes, u64, u64)>> {
+        Indexer::get_at(self, key, version)
+    }
+
+    fn version(&self) -> u64 {
+        Indexer::version(self)
+    }
+
+    fn compacted_at(&self) -> u64 {
+        Indexer::compacted_at(self)
+    }
+}
+
+/// The live index, locked for each read.
+impl Versions for RwLock<Indexer> {
+    fn get_at(&self, key: &VariableSizeKey, version: u64) -> Result<Option<(Bytes, u64, u64)>> {
+        self.read().get_at(key, version)
+    }
+
+    fn version(&self) -> u64 {
+        self.read().version()
+    }
+
+    fn compacted_at(&self) -> u64 {
+        self.read().compacted_at()
+    }
+}
+
+/// Appends an operand to an encoded list of operands:
+/// [operand_len: u32][operand]...
+pub(crate) fn encode_operand(list: &mut BytesMut, operand: &[u8]) {
+    list.put_u32(operand.len() as u32);
+    list.put(operand);
+}
+
+/// Decodes a list of operands encoded by `encode_operand`.
+fn decode_operands(mut list: &[u8]) -> Result<Vec<&[u8]>> {
+    let mut operands = Vec::new();
+    while !list.is_empty() {
+        if list.len() < 4 {
+            return Err(Error::CorruptedIndex);
+        }
+        let len = list.get_u32() as usize;
+        if list.len() < len {
+            return Err(Error::CorruptedIndex);
+        }
+        operands.push(&list[..len]);
+        list.advance(len);
+    }
+    Ok(operands)
+}
+
+/// Returns the value of `key` merged from `operands`, the list of operands written at
+/// `version`, and from the older versions of the key back to its base value. A deleted or
+/// expired version is a base with no value, as is the key not being in the index. The lists
+/// of chunks appended, such as `operands` if `appended`, are appended to the value merged
+/// from the versions below them, the other lists are merged with the merge operator.
+///
+/// The older versions are read from the live index. Committed operands are looked up at
+/// their version first, which holds the merged value once the index was shrunk. Uncommitted
+/// ones are merged with the versions up to `version - 1`, the snapshot of their transaction.
+/// If the index was shrunk since, those versions may be gone, in which case
+/// `Error::MergeBaseUnavailable` is returned.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn merge_value<V: Versions>(
+    core: &Arc<Core>,
+    versions: &V,
+    key: &[u8],
+    version: u64,
+    operands: &[u8],
+    appended: bool,
+    origin: Origin,
+    deadline: Option<Instant>,
+) -> Result<Vec<u8>> {
+    let mut lists = vec![(operands.to_vec(), appended)];
+    let mut base = None;
+
+    if let Some(index_key) = core.keys.lookup(key) {
+        if origin == Origin::Committed {
+            match versions.get_at(&index_key, version)? {
+                Some((value, found, _)) if found == version => {
+                    let val_ref = decode(core, found, &value)?;
+                    if !val_ref.is_merge() {
+                        return val_ref.resolve_by(deadline);
+                    }
+                }
+                _ => return Err(Error::MergeBaseUnavailable),
+            }
+        }
+
+        let mut below = version.saturating_sub(1);
+        loop {
+            check_deadline(deadline)?;
+            let Some((value, found, _)) = versions.get_at(&index_key, below)? else {
+                // The versions older than the latest one are dropped by a shrink.
+                if below < versions.compacted_at()
+                    && versions.get_at(&index_key, versions.version())?.is_some()
+                {
+                    return Err(Error::MergeBaseUnavailable);
+                }
+                break;
+            };
+            let val_ref = decode(core, found, &value)?;
+            let md = val_ref.key_value_metadata();
+            if md.is_some_and(|md| md.deleted())
+                || is_expired(core, md.and_then(|md| md.expires_at()))
+            {
+                break;
+            }
+            let value = val_ref.resolve_by(deadline)?;
+            if !val_ref.is_merge() {
+                base = Some(value);
+                break;
+            }
+            lists.push((value, md.is_some_and(|md| md.appended())));
+            below = found - 1;
+        }
+    }
+
+    // The operands merged one after the other are merged at once, the chunks appended are
+    // appended to the value merged before them.
+    let mut value = base;
+    let mut merged = Vec::new();
+    for (list, appended) in lists.iter().rev() {
+        if !appended {
+            merged.extend(decode_operands(list)?);
+            continue;
+        }
+        if !merged.is_empty() {
+            value = Some(core.merger.operator()?(key, value.as_deref(), &merged));
+            merged.clear();
+        }
+        let value = value.get_or_insert_with(Vec::new);
+        for chunk in decode_operands(list)? {
+            value.extend_from_slice(chunk);
+        }
+    }
+    if !merged.is_empty() {
+        value = Some(core.merger.operator()?(key, value.as_deref(), &merged));
+    }
+    Ok(value.unwrap_or_default())
+}
+
+/// Returns the index values collapsing the lists of operands that are the latest version of
+/// their key into their merged value, keyed by index key, for the versions iterated.
+pub(crate) fn collapse<'a, V, I>(core: &Arc<Core>, versions: &V, entries: I) -> Result<Merged>
+where
+    V: Versions,
+    I: IntoIterator<Item = (Vec<u8>, &'a Bytes, &'a u64, &'a u64)>,
+{
+    let mut merged = HashMap::new();
+    for (index_key, value, version, _) in entries {
+        if let Some(value) = collapse_version(core, versions, &index_key, value, *version)? {
+            merged.insert(index_key, value);
+        }
+    }
+    Ok(merged)
+}
+
+/// Returns the index value collapsing a version of a key into its merged value, or None if
+/// the version is not a list of operands.
+pub(crate) fn collapse_version<V: Versions>(
+    core: &Arc<Core>,
+    versions: &V,
+    index_key: &[u8],
+    value: &Bytes,
+    version: u64,
+) -> Result<Option<Bytes>> {
+    let val_ref = decode(core, version, value)?;
+    if !val_ref.is_merge() {
+        return Ok(None);
+    }
+    let key = core.keys.decode(index_key.to_vec())?;
+    let operands = val_ref.resolve()?;
+    let value = merge_value(
+        core,
+        versions,
+        &key,
+        version,
+        &operands,
+        val_ref.is_append(),
+        Origin::Committed,
+        None,
+    )?;
+
+    // The merged value keeps the expiry of the key, if it was touched.
+    let md = val_ref
+        .key_value_metadata()
+        .and_then(|md| md.expires_at())
+        .map(|at| {
+            let mut md = Metadata::new();
+            md.with_expiry(Some(at));
+            md
+        });
+    Ok(Some(ValueRef::encode_mem(&Bytes::from(value), md.as_ref())))
+}
+
+/// Merged index values, keyed by index key.
+pub(crate) type Merged = HashMap<Vec<u8>, Bytes>;
+
+fn decode(core: &Arc<Core>, version: u64, value: &Bytes) -> Result<ValueRef> {
+    let mut val_ref = ValueRef::new(core.clone());
+    val_ref.decode(version, value)?;
+    Ok(val_ref)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use crate::storage::kv::error::Error;
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+
+    use tempdir::TempDir;
+
+    fn append(_: &[u8], base: Option<&[u8]>, operands: &[&[u8]]) -> Vec<u8> {
+        let mut value = base.unwrap_or_default().to_vec();
+        for operand in operands {
+            value.extend_from_slice(operand);
+        }
+        value
+    }
+
+    #[tokio::test]
+    async fn operands_are_merged_on_read() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        let store = Store::new(opts.clone()).expect("should create store");
+
+        let mut txn = store.begin().unwrap();
+        assert!(matches!(
+            txn.merge(b"log", b"a"),
+            Err(Error::MergeOperatorMissing)
+        ));
+        store.set_merge_operator(append);
+
+        // Concurrent merges into a key do not conflict.
+        txn.set(b"log", b"a").unwrap();
+        txn.commit().await.unwrap();
+        let mut first = store.begin().unwrap();
+        let mut second = store.begin().unwrap();
+        first.merge(b"log", b"b").unwrap();
+        first.merge(b"log", b"c").unwrap();
+        assert_eq!(first.get(b"log").unwrap().unwrap(), b"abc");
+        second.merge(b"log", b"d").unwrap();
+        second.merge(b"new", b"x").unwrap();
+        first.commit().await.unwrap();
+        second.commit().await.unwrap();
+
+        let view = store.snapshot().unwrap();
+        assert_eq!(view.get(b"log").unwrap().unwrap(), b"abcd");
+        let txn = store.begin().unwrap();
+        let entries = txn.scan(.., None).unwrap();
+        assert_eq!(entries[0].1, b"abcd");
+        assert_eq!(entries[1].1, b"x");
+        drop(txn);
+
+        // A merge into a key written by the transaction is applied to the write.
+        let mut txn = store.begin().unwrap();
+        txn.delete(b"new").unwrap();
+        txn.merge(b"new", b"y").unwrap();
+        txn.commit().await.unwrap();
+
+        // Shrinking the index collapses the operands, which views taken before still read.
+        let core = store.inner.as_ref().unwrap().core.clone();
+        core.shrink_index().unwrap();
+        assert_eq!(view.get(b"log").unwrap().unwrap(), b"abcd");
+        let mut txn = store.begin().unwrap();
+        assert_eq!(txn.get(b"new").unwrap().unwrap(), b"y");
+        txn.merge(b"log", b"e").unwrap();
+        txn.commit().await.unwrap();
+
+        // The checkpoint holds the merged value, the operands merged since are replayed.
+        store.checkpoint_index().await.unwrap();
+        let mut txn = store.begin().unwrap();
+        txn.merge(b"log", b"f").unwrap();
+        txn.commit().await.unwrap();
+        store.close().await.unwrap();
+
+        // The operands are merged again once the store is reopened.
+        let store = Store::new(opts).expect("should open store");
+        let txn = store.begin().unwrap();
+        assert!(matches!(txn.get(b"log"), Err(Error::MergeOperatorMissing)));
+        store.set_merge_operator(append);
+        assert_eq!(txn.get(b"log").unwrap().unwrap(), b"abcdef");
+        drop(txn);
+        store.close().await.unwrap();
+    }
+
+    fn clog_size(dir: &Path) -> u64 {
+        fs::read_dir(dir.join("clog"))
+            .unwrap()
+            .map(|entry| entry.unwrap().metadata().unwrap().len())
+            .sum()
+    }
+
+    #[tokio::test]
+    async fn values_are_appended_in_chunks() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        let store = Store::new(opts.clone()).expect("should create store");
+        let big = vec![7u8; 16 * 1024];
+
+        // The chunks are appended without a merge operator, and only they are written.
+        let mut txn = store.begin().unwrap();
+        txn.set(b"blob", &big).unwrap();
+        txn.append_value(b"new", b"x").unwrap();
+        txn.commit().await.unwrap();
+        let before = clog_size(temp_dir.path());
+        let mut expected = big.clone();
+        for chunk in [&b"a"[..], b"b", b"c"] {
+            let mut txn = store.begin().unwrap();
+            txn.append_value(b"blob", chunk).unwrap();
+            txn.commit().await.unwrap();
+            expected.extend_from_slice(chunk);
+        }
+        assert!(clog_size(temp_dir.path()) - before < 1024);
+
+        // Concurrent appends do not conflict, and are applied in commit order. A transaction
+        // reads its appends, and appends to its own writes.
+        let mut first = store.begin().unwrap();
+        let mut second = store.begin().unwrap();
+        first.append_value(b"blob", b"d").unwrap();
+        first.append_value(b"blob", b"e").unwrap();
+        let mut read = expected.clone();
+        read.extend_from_slice(b"de");
+        assert_eq!(first.get(b"blob").unwrap().unwrap(), read);
+        second.append_value(b"blob", b"f").unwrap();
+        second.set(b"other", b"1").unwrap();
+        second.append_value(b"other", b"2").unwrap();
+        second.commit().await.unwrap();
+        first.commit().await.unwrap();
+        expected.extend_from_slice(b"fde");
+
+        let txn = store.begin().unwrap();
+        assert_eq!(txn.get(b"blob").unwrap().unwrap(), expected);
+        assert_eq!(txn.get(b"new").unwrap().unwrap(), b"x");
+        assert_eq!(txn.get(b"other").unwrap().unwrap(), b"12");
+        drop(txn);
+
+        // The chunks are appended to the merges committed before them, and the other way round.
+        store.set_merge_operator(append);
+        let mut txn = store.begin().unwrap();
+        txn.merge(b"new", b"y").unwrap();
+        txn.commit().await.unwrap();
+        let mut txn = store.begin().unwrap();
+        txn.append_value(b"new", b"z").unwrap();
+        txn.commit().await.unwrap();
+        let txn = store.begin().unwrap();
+        assert_eq!(txn.get(b"new").unwrap().unwrap(), b"xyz");
+        drop(txn);
+
+        // Shrinking the index collapses the chunks, the chunks appended since are replayed.
+        store.shrink_index().unwrap();
+        let mut txn = store.begin().unwrap();
+        txn.append_value(b"blob", b"g").unwrap();
+        txn.commit().await.unwrap();
+        expected.extend_from_slice(b"g");
+        store.close().await.unwrap();
+
+        let store = Store::new(opts).expect("should open store");
+        let txn = store.begin().unwrap();
+        assert_eq!(txn.get(b"blob").unwrap().unwrap(), expected);
+        drop(txn);
+        store.close().await.unwrap();
+    }
+}
@@ -4,27 +4,54 @@ use hashbrown::HashSet;
 use crate::storage::kv::error::{Error, Result};
 
 /// An enumeration of possible attributes for a key-value pair.
-/// Currently, only the `Deleted` attribute is defined.
 /// More attribute types can be added as variants.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 enum Attribute {
     Deleted,
+    /// The value is stored compressed with the given format and dictionary id.
+    Compressed {
+        format: u8,
+        dictionary_id: u32,
+    },
+    /// Flags set by the user on the entry.
+    Flags(u64),
+    /// The entry expires at the given time, in nanoseconds since the Unix epoch.
+    Expires(u64),
+    /// The entry only changes the expiry of the key, whose value is kept.
+    Touched,
+    /// The value is a list of operands merged into the previous value of the key.
+    Merged,
+    /// The entry deletes the keys of the range its value encodes.
+    RangeDeleted,
+    /// The entry drops all the keys of the store, see `Store::clear`.
+    Cleared,
+    /// The entry, which is touched, also replaces the flags of the key with its own.
+    Reflagged,
+    /// The entry moves the value of the key its value names to its own key.
+    Renamed,
+    /// The entry, which is merged, appends its operands to the previous value of the key.
+    Appended,
+    /// The entry marks the start or the end of a compaction of the commit log, see
+    /// `Store::compact_log`.
+    Compaction,
 }
 
 impl Attribute {
-    /// Converts a `u8` value into an `Attribute` variant.
-    /// Returns `None` if the value does not correspond to a known attribute.
-    fn from_u8(value: u8) -> Option<Attribute> {
-        match value {
-            0 => Some(Attribute::Deleted),
-            _ => None,
-        }
-    }
-
     /// Returns a `u8` that represents the kind of the attribute.
     fn kind(&self) -> u8 {
         match self {
             Attribute::Deleted => 0,
+            Attribute::Compressed { .. } => 1,
+            Attribute::Flags(_) => 2,
+            Attribute::Expires(_) => 3,
+            Attribute::Touched => 4,
+            Attribute::Merged => 5,
+            Attribute::RangeDeleted => 6,
+            Attribute::Cleared => 7,
+            Attribute::Reflagged => 8,
+            Attribute::Renamed => 9,
+            Attribute::Appended => 10,
+            Attribute::Compaction => 11,
         }
     }
 
@@ -32,19 +59,69 @@ impl Attribute {
     fn serialize(&self) -> Bytes {
         match self {
             Attribute::Deleted => Bytes::new(),
+            Attribute::Compressed {
+                format,
+                dictionary_id,
+            } => {
+                let mut buf = BytesMut::with_capacity(5);
+                buf.extend_from_slice(&[*format]);
+                buf.extend_from_slice(&dictionary_id.to_be_bytes());
+                buf.freeze()
+            }
+            Attribute::Flags(flags) => Bytes::copy_from_slice(&flags.to_be_bytes()),
+            Attribute::Expires(at) => Bytes::copy_from_slice(&at.to_be_bytes()),
+            Attribute::Touched
+            | Attribute::Merged
+            | Attribute::RangeDeleted
+            | Attribute::Cleared
+            | Attribute::Reflagged
+            | Attribute::Renamed
+            | Attribute::Appended
+            | Attribute::Compaction => Bytes::new(),
         }
     }
 
-    /// Deserializes an attribute from a byte slice.
+    /// Deserializes an attribute of the given kind from a byte slice, consuming its payload.
     /// Returns `Error::UnknownAttributeType` if the attribute type is unknown.
-    fn deserialize(&self, bytes: &mut &[u8]) -> Result<Attribute> {
-        if bytes.is_empty() {
-            return Ok(Attribute::Deleted);
+    fn deserialize(kind: u8, bytes: &mut &[u8]) -> Result<Attribute> {
+        match kind {
+            0 => Ok(Attribute::Deleted),
+            1 => {
+                if bytes.len() < 5 {
+                    return Err(Error::InvalidAttributeData);
+                }
+                let format = bytes[0];
+                let dictionary_id = u32::from_be_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]);
+                *bytes = &bytes[5..]; // Consume the attribute payload
+                Ok(Attribute::Compressed {
+                    format,
+                    dictionary_id,
+                })
+            }
+            2 | 3 => {
+                if bytes.len() < 8 {
+                    return Err(Error::InvalidAttributeData);
+                }
+                let mut payload = [0; 8];
+                payload.copy_from_slice(&bytes[..8]);
+                *bytes = &bytes[8..]; // Consume the attribute payload
+                let payload = u64::from_be_bytes(payload);
+                Ok(if kind == 2 {
+                    Attribute::Flags(payload)
+                } else {
+                    Attribute::Expires(payload)
+                })
+            }
+            4 => Ok(Attribute::Touched),
+            5 => Ok(Attribute::Merged),
+            6 => Ok(Attribute::RangeDeleted),
+            7 => Ok(Attribute::Cleared),
+            8 => Ok(Attribute::Reflagged),
+            9 => Ok(Attribute::Renamed),
+            10 => Ok(Attribute::Appended),
+            11 => Ok(Attribute::Compaction),
+            _ => Err(Error::UnknownAttributeType),
         }
-
-        let _value = bytes[0];
-        *bytes = &bytes[1..]; // Consume the attribute byte
-        Err(Error::UnknownAttributeType)
     }
 }
 
@@ -79,11 +156,161 @@ impl Metadata {
         self.attributes.contains(&Attribute::Deleted)
     }
 
-    /// Serializes the metadata into a byte vector.
+    /// Marks the value as compressed with the given format and dictionary id,
+    /// replacing any previous compression attribute.
+    pub(crate) fn as_compressed(&mut self, format: u8, dictionary_id: u32) {
+        self.attributes
+            .retain(|attr| !matches!(attr, Attribute::Compressed { .. }));
+        self.attributes.insert(Attribute::Compressed {
+            format,
+            dictionary_id,
+        });
+    }
+
+    /// Removes the compression attribute, when the value is replaced uncompressed.
+    pub(crate) fn as_uncompressed(&mut self) {
+        self.attributes
+            .retain(|attr| !matches!(attr, Attribute::Compressed { .. }));
+    }
+
+    /// Returns the compression format and dictionary id if the value is compressed.
+    pub(crate) fn compression(&self) -> Option<(u8, u32)> {
+        self.attributes.iter().find_map(|attr| match attr {
+            Attribute::Compressed {
+                format,
+                dictionary_id,
+            } => Some((*format, *dictionary_id)),
+            _ => None,
+        })
+    }
+
+    /// Sets the user flags, replacing any previous flags. No flags are stored if `flags` is 0.
+    pub(crate) fn with_flags(&mut self, flags: u64) {
+        self.attributes
+            .retain(|attr| !matches!(attr, Attribute::Flags(_)));
+        if flags != 0 {
+            self.attributes.insert(Attribute::Flags(flags));
+        }
+    }
+
+    /// Returns the user flags, or 0 if none are set.
+    pub(crate) fn flags(&self) -> u64 {
+        self.attributes
+            .iter()
+            .find_map(|attr| match attr {
+                Attribute::Flags(flags) => Some(*flags),
+                _ => None,
+            })
+            .unwrap_or(0)
+    }
+
+    /// Sets the expiry time, in nanoseconds since the Unix epoch, replacing any previous one.
+    /// The entry does not expire if `at` is `None`.
+    pub(crate) fn with_expiry(&mut self, at: Option<u64>) {
+        self.attributes
+            .retain(|attr| !matches!(attr, Attribute::Expires(_)));
+        if let Some(at) = at {
+            self.attributes.insert(Attribute::Expires(at));
+        }
+    }
+
+    /// Returns the expiry time, in nanoseconds since the Unix epoch, if the entry expires.
+    pub(crate) fn expires_at(&self) -> Option<u64> {
+        self.attributes.iter().find_map(|attr| match attr {
+            Attribute::Expires(at) => Some(*at),
+            _ => None,
+        })
+    }
+
+    /// Marks the entry as only changing the expiry of the key.
+    pub(crate) fn as_touched(&mut self) {
+        self.attributes.insert(Attribute::Touched);
+    }
+
+    /// Checks if the 'touched' attribute is present.
+    pub(crate) fn touched(&self) -> bool {
+        self.attributes.contains(&Attribute::Touched)
+    }
+
+    /// Marks the value as a list of merge operands.
+    pub(crate) fn as_merged(&mut self) {
+        self.attributes.insert(Attribute::Merged);
+    }
+
+    /// Checks if the 'merged' attribute is present.
+    pub(crate) fn merged(&self) -> bool {
+        self.attributes.contains(&Attribute::Merged)
+    }
+
+    /// Marks the entry as deleting a range of keys.
+    pub(crate) fn as_range_deleted(&mut self) {
+        self.attributes.insert(Attribute::RangeDeleted);
+    }
+
+    /// Checks if the 'range deleted' attribute is present.
+    pub(crate) fn range_deleted(&self) -> bool {
+        self.attributes.contains(&Attribute::RangeDeleted)
+    }
+
+    /// Marks the entry as dropping all the keys of the store.
+    pub(crate) fn as_cleared(&mut self) {
+        self.attributes.insert(Attribute::Cleared);
+    }
+
+    /// Checks if the 'cleared' attribute is present.
+    pub(crate) fn cleared(&self) -> bool {
+        self.attributes.contains(&Attribute::Cleared)
+    }
+
+    /// Marks the touched entry as replacing the flags of its key.
+    pub(crate) fn as_reflagged(&mut self) {
+        self.attributes.insert(Attribute::Reflagged);
+    }
+
+    /// Checks if the 'reflagged' attribute is present.
+    pub(crate) fn reflagged(&self) -> bool {
+        self.attributes.contains(&Attribute::Reflagged)
+    }
+
+    /// Marks the entry as moving the value of another key to its own.
+    pub(crate) fn as_renamed(&mut self) {
+        self.attributes.insert(Attribute::Renamed);
+    }
+
+    /// Checks if the 'renamed' attribute is present.
+    pub(crate) fn renamed(&self) -> bool {
+        self.attributes.contains(&Attribute::Renamed)
+    }
+
+    /// Marks the merged entry as appending its operands to the value of its key.
+    pub(crate) fn as_appended(&mut self) {
+        self.attributes.insert(Attribute::Appended);
+    }
+
+    /// Checks if the 'appended' attribute is present.
+    pub(crate) fn appended(&self) -> bool {
+        self.attributes.contains(&Attribute::Appended)
+    }
+
+    /// Marks the entry as a marker of a compaction of the commit log.
+    pub(crate) fn as_compaction(&mut self) {
+        self.attributes.insert(Attribute::Compaction);
+    }
+
+    /// Checks if the 'compaction' attribute is present.
+    pub(crate) fn compaction(&self) -> bool {
+        self.attributes.contains(&Attribute::Compaction)
+    }
+
+    /// Serializes the metadata into a byte vector. The attributes are serialized in the order
+    /// of their kinds, so that the same metadata always has the same bytes, which the
+    /// checksums of the commit log records are computed over.
     pub(crate) fn to_bytes(&self) -> Bytes {
         let mut buf = BytesMut::new();
 
-        for attr in &self.attributes {
+        let mut attributes: Vec<_> = self.attributes.iter().collect();
+        attributes.sort_by_key(|attr| attr.kind());
+        for attr in attributes {
             buf.extend_from_slice(&[attr.kind()]);
             buf.extend_from_slice(&attr.serialize());
         }
@@ -99,10 +326,8 @@ impl Metadata {
         while !cursor.is_empty() {
             let attr_kind = cursor[0];
             cursor = &cursor[1..]; // Move cursor to the next byte
-            if let Some(attr) = Attribute::from_u8(attr_kind) {
-                attr.deserialize(&mut cursor)?;
-                attributes.insert(attr);
-            }
+            let attr = Attribute::deserialize(attr_kind, &mut cursor)?;
+            attributes.insert(attr);
         }
 
         Ok(Metadata { attributes })
@@ -142,7 +367,7 @@ mod tests {
         metadata.as_deleted(true).unwrap();
         let bytes = metadata.to_bytes();
         assert_eq!(bytes.len(), 1);
-        assert_eq!(bytes[0], Attribute::Deleted as u8);
+        assert_eq!(bytes[0], Attribute::Deleted.kind());
 
         // Test serialization without 'deleted' attribute
         metadata.as_deleted(false).unwrap();
@@ -164,4 +389,63 @@ mod tests {
         );
         assert_eq!(metadata.deleted(), deserialized_metadata.deleted());
     }
+
+    #[test]
+    fn compressed_roundtrip() {
+        let mut metadata = Metadata::new();
+        metadata.as_deleted(true).unwrap();
+        metadata.as_compressed(1, 42);
+        metadata.as_compressed(1, 7);
+        assert_eq!(metadata.attributes.len(), 2);
+
+        let bytes = metadata.to_bytes();
+        let deserialized_metadata = Metadata::from_bytes(bytes.as_ref()).unwrap();
+        assert!(deserialized_metadata.deleted());
+        assert_eq!(deserialized_metadata.compression(), Some((1, 7)));
+    }
+
+    #[test]
+    fn flags_roundtrip() {
+        let mut metadata = Metadata::new();
+        assert_eq!(metadata.flags(), 0);
+        metadata.with_flags(0b101);
+        metadata.with_flags(0b110);
+        metadata.as_compressed(1, 7);
+
+        let bytes = metadata.to_bytes();
+        let deserialized_metadata = Metadata::from_bytes(bytes.as_ref()).unwrap();
+        assert_eq!(deserialized_metadata.flags(), 0b110);
+        assert_eq!(deserialized_metadata.compression(), Some((1, 7)));
+
+        metadata.with_flags(0);
+        let bytes = metadata.to_bytes();
+        assert_eq!(Metadata::from_bytes(bytes.as_ref()).unwrap().flags(), 0);
+    }
+
+    #[test]
+    fn expiry_roundtrip() {
+        let mut metadata = Metadata::new();
+        assert_eq!(metadata.expires_at(), None);
+        metadata.with_expiry(Some(10));
+        metadata.with_expiry(Some(20));
+        metadata.with_flags(0b1);
+        metadata.as_touched();
+
+        let bytes = metadata.to_bytes();
+        let deserialized_metadata = Metadata::from_bytes(bytes.as_ref()).unwrap();
+        assert_eq!(deserialized_metadata.expires_at(), Some(20));
+        assert_eq!(deserialized_metadata.flags(), 0b1);
+        assert!(deserialized_metadata.touched());
+
+        metadata.with_expiry(None);
+        assert_eq!(metadata.expires_at(), None);
+    }
+
+    #[test]
+    fn unknown_attribute() {
+        assert!(matches!(
+            Metadata::from_bytes(&[200]),
+            Err(Error::UnknownAttributeType)
+        ));
+    }
 }
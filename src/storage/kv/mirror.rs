@@ -0,0 +1,462 @@
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use async_channel::{unbounded, Receiver, Sender};
+use parking_lot::Mutex;
+use tokio::task::JoinHandle;
+
+use crate::storage::kv::{
+    conflict::ConflictPolicy,
+    entry::Entry,
+    error::{Error, Result},
+    events::{self, Level, Value},
+    merge::{self, Origin},
+    option::Options,
+    stats::Stats,
+    store::{Core, Store},
+    transaction::resolve_renamed,
+};
+
+/// A committed mutation of a key. A `value` of `None` is a deletion.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mutation {
+    pub key: Vec<u8>,
+    pub value: Option<Vec<u8>>,
+}
+
+/// The mutations of a committed transaction, in commit order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MirrorBatch {
+    pub commit_ts: u64,           // Commit timestamp of the transaction.
+    pub mutations: Vec<Mutation>, // Mutations of the transaction.
+    pub cleared: bool,            // The transaction cleared the store, see `Store::clear`.
+}
+
+/// Callback receiving the batches mirrored from a store.
+pub type MirrorSink = Arc<dyn Fn(&MirrorBatch) -> Result<()> + Send + Sync>;
+
+/// The target committed mutations are mirrored to.
+#[allow(clippy::large_enum_variant)]
+pub enum MirrorTarget {
+    /// Mirror into a second store opened with the given options, which may use another
+    /// directory and another layout or format than the source store.
+    Store(Options),
+    /// Hand the batches to a callback.
+    Sink(MirrorSink),
+}
+
+impl MirrorBatch {
+    /// Builds a batch from the entries of a transaction as they are written to the log,
+    /// decompressing the values stored compressed. The entries only changing the expiry of a
+    /// key are not mutations of its value, and are left out. The merge operands are merged
+    /// with the latest versions of their key, which the entries are written over, and the
+    /// renamed keys are resolved to the latest values of the keys they are renamed from.
+    /// The marker of a clear is no mutation either: it marks the batch as a clear.
+    pub(crate) fn from_entries(
+        core: &Arc<Core>,
+        entries: &[Entry],
+        commit_ts: u64,
+    ) -> Result<Self> {
+        let mutations = entries
+            .iter()
+            .filter(|entry| !entry.is_touch() && !entry.is_clear())
+            .map(|entry| {
+                if entry.is_deleted() {
+                    return Ok(Mutation {
+                        key: entry.key.to_vec(),
+                        value: None,
+                    });
+                }
+                if entry.is_rename() {
+                    let version = core.indexer.read().version();
+                    return Ok(Mutation {
+                        key: entry.key.to_vec(),
+                        value: Some(resolve_renamed(core, &entry.value, version, None)?),
+                    });
+                }
+                let mut value = match entry.metadata.as_ref().and_then(|md| md.compression()) {
+                    Some((format, dictionary_id)) => {
+                        core.compressor
+                            .decompress(format, dictionary_id, &entry.value)?
+                    }
+                    None => entry.value.to_vec(),
+                };
+                if entry.is_merge() {
+                    let version = core.indexer.read().version() + 1;
+                    value = merge::merge_value(
+                        core,
+                        &core.indexer,
+                        &entry.key,
+                        version,
+                        &value,
+                        entry.is_append(),
+                        Origin::Uncommitted,
+                        None,
+                    )?;
+                }
+                Ok(Mutation {
+                    key: entry.key.to_vec(),
+                    value: Some(value),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            commit_ts,
+            mutations,
+            cleared: entries.iter().any(|entry| entry.is_clear()),
+        })
+    }
+}
+
+/// A running mirror: the channel feeding it and the task applying the batches.
+struct RunningMirror {
+    tx: Sender<MirrorBatch>,
+    handle: JoinHandle<()>,
+}
+
+/// `Mirror` asynchronously replays the mutations committed to a store onto a second target,
+/// so that a new layout or format can be run side by side with the current one before
+/// cutting over. Batches are applied in commit order; the lag is reported in the store stats.
+pub(crate) struct Mirror {
+    running: Mutex<Option<RunningMirror>>,
+}
+
+impl Mirror {
+    pub(crate) fn new() -> Self {
+        Self {
+            running: Mutex::new(None),
+        }
+    }
+
+    /// Returns true if committed mutations are being mirrored.
+    pub(crate) fn is_running(&self) -> bool {
+        self.running.lock().is_some()
+    }
+
+    /// Starts mirroring to the target. Only the transactions committed from now on are mirrored.
+    pub(crate) fn start(&self, core: &Arc<Core>, target: MirrorTarget) -> Result<()> {
+        let mut running = self.running.lock();
+        if running.is_some() {
+            return Err(Error::MirrorAlreadyRunning);
+        }
+
+        let target = match target {
+            MirrorTarget::Sink(sink) => Target::Sink(sink),
+            MirrorTarget::Store(opts) => Target::Store(Store::new(opts)?),
+        };
+
+        let (tx, rx) = unbounded();
+        let name = match &target {
+            Target::Store(_) => "store",
+            Target::Sink(_) => "sink",
+        };
+        events::emit(
+            events::MIRROR,
+            Level::Info,
+            "mirror started",
+            &[("target", Value::Str(name))],
+        );
+        let handle = tokio::spawn(run(core.clone(), rx, target));
+        *running = Some(RunningMirror { tx, handle });
+        Ok(())
+    }
+
+    /// Queues a committed batch for mirroring.
+    pub(crate) fn send(&self, stats: &Stats, batch: MirrorBatch) {
+        if let Some(running) = self.running.lock().as_ref() {
+            stats
+                .mirror_last_committed_ts
+                .fetch_max(batch.commit_ts, Ordering::Relaxed);
+            stats.mirror_pending.fetch_add(1, Ordering::Relaxed);
+            // The channel is unbounded and only closed when the mirror stops.
+            let _ = running.tx.try_send(batch);
+        }
+    }
+
+    /// Stops mirroring, waiting for the queued batches to be applied.
+    pub(crate) async fn stop(&self) -> Result<()> {
+        let running = self.running.lock().take();
+        if let Some(running) = running {
+            running.tx.close();
+            running.handle.await.map_err(|e| {
+                Error::ReceiveError(format!(
+                    "Error occurred while stopping the mirror. JoinError: {}",
+                    e
+                ))
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// The target of a running mirror.
+enum Target {
+    Store(Store),
+    Sink(MirrorSink),
+}
+
+impl Target {
+    /// Applies a batch to the target.
+    async fn apply(&self, batch: &MirrorBatch) -> Result<()> {
+        match self {
+            Target::Sink(sink) => sink(batch),
+            Target::Store(store) => store
+                .apply_batch(batch, &ConflictPolicy::Overwrite)
+                .await
+                .map(|_| ()),
+        }
+    }
+}
+
+/// Applies the batches received on the channel until it is closed and drained.
+async fn run(core: Arc<Core>, rx: Receiver<MirrorBatch>, target: Target) {
+    let (mut applied, mut failed) = (0, 0);
+    while let Ok(batch) = rx.recv().await {
+        let stats = &core.stats;
+        // A failed batch is counted and skipped, so that a faulty target does not hold
+        // back the source store.
+        match target.apply(&batch).await {
+            Ok(()) => applied += 1,
+            Err(err) => {
+                failed += 1;
+                stats.mirror_errors.fetch_add(1, Ordering::Relaxed);
+                let error = err.to_string();
+                events::emit(
+                    events::MIRROR,
+                    Level::Warn,
+                    "mirror batch failed",
+                    &[
+                        ("commit_ts", Value::U64(batch.commit_ts)),
+                        ("mutations", Value::U64(batch.mutations.len() as u64)),
+                        ("error", Value::Str(&error)),
+                    ],
+                );
+            }
+        }
+        stats
+            .mirror_last_applied_ts
+            .fetch_max(batch.commit_ts, Ordering::Relaxed);
+        stats.mirror_pending.fetch_sub(1, Ordering::Relaxed);
+        stats.mirror_batches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    if let Target::Store(store) = target {
+        if let Err(err) = store.close().await {
+            core.stats.mirror_errors.fetch_add(1, Ordering::Relaxed);
+            let error = err.to_string();
+            events::emit(
+                events::MIRROR,
+                Level::Warn,
+                "closing the mirror store failed",
+                &[("error", Value::Str(&error))],
+            );
+        }
+    }
+    events::emit(
+        events::MIRROR,
+        Level::Info,
+        "mirror stopped",
+        &[
+            ("batches_applied", Value::U64(applied)),
+            ("batches_failed", Value::U64(failed)),
+        ],
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use parking_lot::Mutex;
+
+    use super::*;
+    use crate::storage::kv::keyspace::Keyspace;
+
+    use tempdir::TempDir;
+
+    fn options(dir: &TempDir) -> Options {
+        let mut opts = Options::new();
+        opts.dir = dir.path().to_path_buf();
+        opts
+    }
+
+    #[tokio::test]
+    async fn mirror_to_sink() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let store = Store::new(options(&temp_dir)).expect("should create store");
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = {
+            let received = received.clone();
+            Arc::new(move |batch: &MirrorBatch| {
+                received.lock().push(batch.clone());
+                Ok(())
+            })
+        };
+        store.start_mirror(MirrorTarget::Sink(sink)).unwrap();
+        assert!(matches!(
+            store.start_mirror(MirrorTarget::Sink(Arc::new(|_: &MirrorBatch| Ok(())))),
+            Err(Error::MirrorAlreadyRunning)
+        ));
+
+        let mut txn = store.begin().unwrap();
+        txn.set(b"a", b"1").unwrap();
+        txn.set(b"b", b"2").unwrap();
+        txn.commit().await.unwrap();
+        let mut txn = store.begin().unwrap();
+        txn.delete(b"a").unwrap();
+        txn.commit().await.unwrap();
+
+        store.stop_mirror().await.unwrap();
+
+        let received = received.lock();
+        assert_eq!(received.len(), 2);
+        assert_eq!(
+            received[0].mutations,
+            vec![
+                Mutation {
+                    key: b"a".to_vec(),
+                    value: Some(b"1".to_vec())
+                },
+                Mutation {
+                    key: b"b".to_vec(),
+                    value: Some(b"2".to_vec())
+                },
+            ]
+        );
+        assert_eq!(
+            received[1].mutations,
+            vec![Mutation {
+                key: b"a".to_vec(),
+                value: None
+            }]
+        );
+
+        let stats = store.stats();
+        assert_eq!(stats.mirror_batches, 2);
+        assert_eq!(stats.mirror_pending, 0);
+        assert_eq!(stats.mirror_lag_ns, 0);
+    }
+
+    #[tokio::test]
+    async fn mirror_to_store() {
+        let source_dir = TempDir::new("test").unwrap();
+        let target_dir = TempDir::new("test").unwrap();
+        let store = Store::new(options(&source_dir)).expect("should create store");
+
+        let mut target_opts = options(&target_dir);
+        target_opts.max_segment_size = 1 << 20;
+        store
+            .start_mirror(MirrorTarget::Store(target_opts.clone()))
+            .unwrap();
+
+        for i in 0..10u32 {
+            let mut txn = store.begin().unwrap();
+            txn.set(&i.to_be_bytes(), b"value").unwrap();
+            txn.commit().await.unwrap();
+        }
+        let mut txn = store.begin().unwrap();
+        txn.delete(&3u32.to_be_bytes()).unwrap();
+        txn.commit().await.unwrap();
+
+        store.close().await.unwrap();
+
+        let target = Store::new(target_opts).expect("should open mirror");
+        let txn = target.begin().unwrap();
+        let results = txn.scan(.., None).unwrap();
+        assert_eq!(results.len(), 9);
+        assert!(txn.get(&3u32.to_be_bytes()).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn mirror_system_keys() {
+        let source_dir = TempDir::new("test").unwrap();
+        let target_dir = TempDir::new("test").unwrap();
+        let store = Store::new(options(&source_dir)).expect("should create store");
+        let target_opts = options(&target_dir);
+        store
+            .start_mirror(MirrorTarget::Store(target_opts.clone()))
+            .unwrap();
+
+        // The keys of a keyspace and of a stream are mirrored with the user keys written
+        // along with them.
+        let keyspace = Keyspace::new(b"users").unwrap();
+        let mut txn = store.begin().unwrap();
+        txn.set(b"plain", b"1").unwrap();
+        txn.set_in(&keyspace, b"alice", b"2").unwrap();
+        txn.commit().await.unwrap();
+        store.append_to_stream(b"events", b"e0").await.unwrap();
+        store.append_to_stream(b"events", b"e1").await.unwrap();
+        let mut txn = store.begin().unwrap();
+        txn.delete_in(&keyspace, b"alice").unwrap();
+        txn.set_in(&keyspace, b"bob", b"3").unwrap();
+        txn.commit().await.unwrap();
+        store.stop_mirror().await.unwrap();
+        assert_eq!(store.stats().mirror_errors, 0);
+        store.close().await.unwrap();
+
+        let target = Store::new(target_opts).expect("should open mirror");
+        let txn = target.begin().unwrap();
+        assert_eq!(txn.get(b"plain").unwrap().unwrap(), b"1");
+        assert!(txn.get_in(&keyspace, b"alice").unwrap().is_none());
+        assert_eq!(txn.get_in(&keyspace, b"bob").unwrap().unwrap(), b"3");
+        drop(txn);
+        assert_eq!(
+            target.read_stream(b"events", ..).unwrap(),
+            vec![(0, b"e0".to_vec()), (1, b"e1".to_vec())]
+        );
+        target.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn mirror_clear() {
+        let source_dir = TempDir::new("test").unwrap();
+        let target_dir = TempDir::new("test").unwrap();
+        let store = Store::new(options(&source_dir)).expect("should create store");
+        let target_opts = options(&target_dir);
+        store
+            .start_mirror(MirrorTarget::Store(target_opts.clone()))
+            .unwrap();
+
+        // The clear drops the keys mirrored before it from the target, and the ones
+        // written after it are mirrored to the empty target.
+        let mut txn = store.begin().unwrap();
+        txn.set(b"a", b"1").unwrap();
+        txn.set(b"b", b"2").unwrap();
+        txn.commit().await.unwrap();
+        store.append_to_stream(b"events", b"e0").await.unwrap();
+        store.clear().await.unwrap();
+        let mut txn = store.begin().unwrap();
+        txn.set(b"c", b"3").unwrap();
+        txn.commit().await.unwrap();
+        store.stop_mirror().await.unwrap();
+        assert_eq!(store.stats().mirror_errors, 0);
+
+        // A sink is sent the clear as a batch without mutations.
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = {
+            let received = received.clone();
+            Arc::new(move |batch: &MirrorBatch| {
+                received.lock().push(batch.clone());
+                Ok(())
+            })
+        };
+        store.start_mirror(MirrorTarget::Sink(sink)).unwrap();
+        store.clear().await.unwrap();
+        store.stop_mirror().await.unwrap();
+        let batches = received.lock().clone();
+        assert_eq!(batches.len(), 1);
+        assert!(batches[0].cleared && batches[0].mutations.is_empty());
+        store.close().await.unwrap();
+
+        let target = Store::new(target_opts).expect("should open mirror");
+        let txn = target.begin().unwrap();
+        assert!(txn.get(b"a").unwrap().is_none());
+        assert!(txn.get(b"b").unwrap().is_none());
+        assert_eq!(txn.get(b"c").unwrap().unwrap(), b"3");
+        drop(txn);
+        assert!(target.read_stream(b"events", ..).unwrap().is_empty());
+        target.close().await.unwrap();
+    }
+}
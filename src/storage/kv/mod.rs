@@ -1,12 +1,61 @@
+pub(crate) mod active;
+#[cfg(feature = "tokio")]
+pub mod async_store;
+pub mod audit;
+pub(crate) mod backup;
+pub mod batch;
+pub(crate) mod checkpoint;
+pub(crate) mod clear;
+pub mod clock;
+pub mod compaction;
+pub mod compression;
+pub mod conflict;
+pub mod consistency;
+pub(crate) mod coordinator;
+pub mod cursor;
+pub mod durable;
 pub mod entry;
+pub(crate) mod envelope;
 pub mod error;
+pub mod events;
+#[cfg(feature = "arrow")]
+pub mod export;
+pub(crate) mod flags;
+pub mod fsync;
+pub mod gc;
+pub mod handle;
+pub(crate) mod headroom;
 pub(crate) mod indexer;
+pub mod ingest;
+pub(crate) mod intern;
+pub(crate) mod invariant;
+pub mod iterator;
+pub mod jobs;
+pub mod keyspace;
+pub mod lock;
+pub mod merge;
 pub(crate) mod meta;
+pub mod mirror;
+pub mod nested;
 pub mod option;
 pub(crate) mod oracle;
+pub(crate) mod partition;
+pub mod queue;
+pub mod quota;
+pub(crate) mod range_delete;
 pub(crate) mod reader;
+pub(crate) mod redundancy;
 pub(crate) mod repair;
+pub(crate) mod rewrite;
+pub(crate) mod sample;
+pub(crate) mod segments;
 pub mod snapshot;
+pub mod stats;
 pub mod store;
+pub(crate) mod stream;
+pub(crate) mod sweep;
+pub(crate) mod threshold;
 pub mod transaction;
 pub(crate) mod util;
+pub mod view;
+pub mod watch;
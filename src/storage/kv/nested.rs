@@ -0,0 +1,308 @@
+use std::collections::BTreeMap;
+use std::ops::{Bound, RangeBounds};
+
+use bytes::Bytes;
+
+use crate::storage::kv::{
+    entry::Entry,
+    error::{Error, Result},
+    transaction::{ScanResult, Transaction},
+};
+
+/// A transaction begun from another one with [`Transaction::begin_child`], for composing an
+/// operation of several steps that can be abandoned halfway.
+///
+/// The writes of the child are buffered apart from its parent's: the child reads them over
+/// the parent's, but the parent does not see them until the child is committed, which makes
+/// them writes of the parent. Rolling the child back, or dropping it, discards them. Nothing
+/// is written to the store until the outermost transaction is committed. A child can begin
+/// children of its own, to any depth.
+///
+/// The reads of a child are reads of its parent: they are checked for conflicts when the
+/// outermost transaction commits, even if the child is rolled back.
+pub struct ChildTransaction<'a> {
+    parent: &'a mut dyn Parent,
+    writes: BTreeMap<Bytes, Entry>,
+}
+
+impl<'a> ChildTransaction<'a> {
+    pub(crate) fn new(parent: &'a mut Transaction) -> Result<Self> {
+        if parent.is_closed() {
+            return Err(Error::TransactionClosed);
+        }
+        if !parent.mode().mutable() {
+            return Err(Error::TransactionReadOnly);
+        }
+        Ok(Self {
+            parent,
+            writes: BTreeMap::new(),
+        })
+    }
+
+    /// Adds a key-value pair to the transaction.
+    pub fn set(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.write(Entry::new(key, value))
+    }
+
+    /// Adds the deletion of a key to the transaction.
+    pub fn delete(&mut self, key: &[u8]) -> Result<()> {
+        let mut entry = Entry::new(key, &[]);
+        entry.mark_delete();
+        self.write(entry)
+    }
+
+    /// Gets the value of a key, if it exists.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self.writes.get(key) {
+            Some(entry) if entry.is_deleted() => Ok(None),
+            Some(entry) => Ok(Some(entry.value.to_vec())),
+            None => self.parent.get(key),
+        }
+    }
+
+    /// Scans a range of keys, as [`Transaction::scan`] does. The keys written by the child
+    /// that are not written by its parent are returned at the version the parent's writes
+    /// are returned at.
+    pub fn scan<'b, R>(&'b self, range: R, limit: Option<usize>) -> Result<Vec<ScanResult>>
+    where
+        R: RangeBounds<&'b [u8]>,
+    {
+        let range = (range.start_bound().cloned(), range.end_bound().cloned());
+        if is_empty(range) {
+            return Ok(Vec::new());
+        }
+        let mut parent = self.parent.scan(range)?.into_iter().peekable();
+        let mut writes = self.writes.range::<[u8], _>(range).peekable();
+        let version = self.parent.write_version();
+
+        let limit = limit.unwrap_or(usize::MAX);
+        let mut results = Vec::new();
+        while results.len() < limit {
+            let from_writes = match (parent.peek(), writes.peek()) {
+                (None, None) => break,
+                (Some(_), None) => false,
+                (None, Some(_)) => true,
+                (Some((key, ..)), Some((written, _))) => written[..] <= key[..],
+            };
+            if !from_writes {
+                results.extend(parent.next());
+                continue;
+            }
+            let (key, entry) = writes.next().unwrap();
+            let (version, ts) = match parent.peek() {
+                Some((parent_key, _, version, ts)) if parent_key[..] == key[..] => {
+                    let found = (*version, *ts);
+                    parent.next();
+                    found
+                }
+                _ => (version, version),
+            };
+            if !entry.is_deleted() {
+                results.push((key.to_vec(), entry.value.to_vec(), version, ts));
+            }
+        }
+        Ok(results)
+    }
+
+    /// Begins a child of this transaction.
+    pub fn begin_child(&mut self) -> Result<ChildTransaction<'_>> {
+        Ok(ChildTransaction {
+            parent: self,
+            writes: BTreeMap::new(),
+        })
+    }
+
+    /// Commits the transaction, making its writes writes of its parent. If they cannot be
+    /// made, because the outermost transaction would write too many entries, they are
+    /// discarded and the parent is left as it was.
+    pub fn commit(mut self) -> Result<()> {
+        let writes = std::mem::take(&mut self.writes);
+        self.parent.write_all(writes.into_values().collect())
+    }
+
+    /// Rolls the transaction back, discarding its writes.
+    pub fn rollback(self) {}
+
+    fn write(&mut self, entry: Entry) -> Result<()> {
+        self.parent.check_write(&entry)?;
+        self.writes.insert(entry.key.clone(), entry);
+        Ok(())
+    }
+}
+
+/// Returns true if no key is in the range, which a `BTreeMap` refuses to look up.
+fn is_empty(range: (Bound<&[u8]>, Bound<&[u8]>)) -> bool {
+    match range {
+        (Bound::Included(start), Bound::Included(end)) => start > end,
+        (Bound::Included(start) | Bound::Excluded(start), Bound::Excluded(end))
+        | (Bound::Excluded(start), Bound::Included(end)) => start >= end,
+        _ => false,
+    }
+}
+
+/// The transaction a child transaction is begun from.
+trait Parent {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    fn scan(&self, range: (Bound<&[u8]>, Bound<&[u8]>)) -> Result<Vec<ScanResult>>;
+
+    /// Returns the version the uncommitted writes are read at.
+    fn write_version(&self) -> u64;
+
+    fn check_write(&self, entry: &Entry) -> Result<()>;
+
+    fn write_all(&mut self, entries: Vec<Entry>) -> Result<()>;
+}
+
+impl Parent for Transaction {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Transaction::get(self, key)
+    }
+
+    fn scan(&self, range: (Bound<&[u8]>, Bound<&[u8]>)) -> Result<Vec<ScanResult>> {
+        // The snapshot of a write-only transaction is not taken.
+        if self.mode().is_write_only() {
+            return Err(Error::TransactionWriteOnly);
+        }
+        Transaction::scan(self, range, None)
+    }
+
+    fn write_version(&self) -> u64 {
+        self.snapshot
+            .as_ref()
+            .map_or(0, |snapshot| snapshot.read().write_version())
+    }
+
+    fn check_write(&self, entry: &Entry) -> Result<()> {
+        Transaction::check_write(self, entry)
+    }
+
+    fn write_all(&mut self, entries: Vec<Entry>) -> Result<()> {
+        Transaction::write_all(self, entries)
+    }
+}
+
+impl Parent for ChildTransaction<'_> {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        ChildTransaction::get(self, key)
+    }
+
+    fn scan(&self, range: (Bound<&[u8]>, Bound<&[u8]>)) -> Result<Vec<ScanResult>> {
+        ChildTransaction::scan(self, range, None)
+    }
+
+    fn write_version(&self) -> u64 {
+        self.parent.write_version()
+    }
+
+    fn check_write(&self, entry: &Entry) -> Result<()> {
+        self.parent.check_write(entry)
+    }
+
+    fn write_all(&mut self, entries: Vec<Entry>) -> Result<()> {
+        for entry in entries {
+            self.writes.insert(entry.key.clone(), entry);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::kv::error::Error;
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+    use crate::storage::kv::transaction::Mode;
+
+    use tempdir::TempDir;
+
+    fn keys(entries: Vec<crate::storage::kv::transaction::ScanResult>) -> Vec<Vec<u8>> {
+        entries.into_iter().map(|entry| entry.0).collect()
+    }
+
+    #[tokio::test]
+    async fn child_writes_reach_the_parent_on_commit() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        let store = Store::new(opts).expect("should create store");
+
+        let mut txn = store.begin().unwrap();
+        txn.set(b"a", b"1").unwrap();
+        txn.commit().await.unwrap();
+
+        let mut txn = store.begin().unwrap();
+        txn.set(b"b", b"1").unwrap();
+        let mut child = txn.begin_child().unwrap();
+        child.set(b"c", b"1").unwrap();
+        child.delete(b"a").unwrap();
+        assert!(child.get(b"a").unwrap().is_none());
+        assert_eq!(child.get(b"b").unwrap().unwrap(), b"1");
+        assert_eq!(keys(child.scan(.., None).unwrap()), [b"b", b"c"]);
+
+        // A grandchild rolled back leaves the child as it was.
+        let mut grandchild = child.begin_child().unwrap();
+        grandchild.set(b"d", b"1").unwrap();
+        grandchild.set(b"a", b"2").unwrap();
+        assert_eq!(keys(grandchild.scan(.., Some(2)).unwrap()), [b"a", b"b"]);
+        grandchild.rollback();
+        assert!(child.get(b"d").unwrap().is_none());
+
+        // A grandchild committed writes to the child.
+        let mut grandchild = child.begin_child().unwrap();
+        grandchild.set(b"b", b"2").unwrap();
+        grandchild.commit().unwrap();
+        assert_eq!(child.get(b"b").unwrap().unwrap(), b"2");
+        let scanned = child.scan(.., None).unwrap();
+        child.commit().unwrap();
+        assert_eq!(txn.scan(.., None).unwrap(), scanned);
+
+        assert!(txn.get(b"a").unwrap().is_none());
+        assert_eq!(txn.get(b"b").unwrap().unwrap(), b"2");
+        assert_eq!(keys(txn.scan(.., None).unwrap()), [b"b", b"c"]);
+
+        // A child dropped without commit is rolled back.
+        let mut child = txn.begin_child().unwrap();
+        child.set(b"e", b"1").unwrap();
+        drop(child);
+        assert!(txn.get(b"e").unwrap().is_none());
+        txn.commit().await.unwrap();
+
+        let txn = store.begin_with_mode(Mode::ReadOnly).unwrap();
+        let entries = txn.scan(.., None).unwrap();
+        assert_eq!(keys(entries.clone()), [b"b", b"c"]);
+        assert_eq!(entries[0].1, b"2");
+        drop(txn);
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn child_writes_are_limited_with_the_parent() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.max_entries_per_txn = 2;
+        let store = Store::new(opts).expect("should create store");
+
+        let mut txn = store.begin_with_mode(Mode::ReadOnly).unwrap();
+        assert!(matches!(
+            txn.begin_child().err(),
+            Some(Error::TransactionReadOnly)
+        ));
+
+        let mut txn = store.begin().unwrap();
+        txn.set(b"a", b"1").unwrap();
+        let mut child = txn.begin_child().unwrap();
+        child.set(b"a", b"2").unwrap();
+        child.set(b"b", b"1").unwrap();
+        child.set(b"c", b"1").unwrap();
+        assert!(matches!(
+            child.commit(),
+            Err(Error::MaxTransactionEntriesLimitExceeded)
+        ));
+        assert_eq!(txn.get(b"a").unwrap().unwrap(), b"1");
+        assert!(txn.get(b"b").unwrap().is_none());
+        drop(txn);
+        store.close().await.unwrap();
+    }
+}
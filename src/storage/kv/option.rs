@@ -1,8 +1,13 @@
 use std::path::PathBuf;
+use std::time::Duration;
 
 use crate::storage::{
+    kv::clock::SharedExpiryClock,
+    kv::compression::{decode_rules, encode_rules, CompressionRule},
     kv::error::{Error, Result},
+    kv::jobs::JobPriorities,
     log::Metadata,
+    vfs::SharedVfs,
 };
 
 // Defining constants for metadata keys
@@ -13,6 +18,18 @@ const META_KEY_MAX_VALUE_THRESHOLD: &str = "max_value_threshold";
 const META_KEY_MAX_ENTRIES_PER_TX: &str = "max_entries_per_txn";
 const META_KEY_MAX_FILE_SIZE: &str = "max_file_size";
 const META_KEY_MAX_VALUE_CACHE_SIZE: &str = "max_value_cache_size";
+const META_KEY_COMPRESSION: &str = "compression";
+const META_KEY_MAX_STREAM_LENGTH: &str = "max_stream_length";
+const META_KEY_READ_PROBE_INTERVAL: &str = "read_probe_interval";
+const META_KEY_MAX_ACTIVE_TRANSACTIONS: &str = "max_active_transactions";
+const META_KEY_SSI_READ_FINGERPRINTS: &str = "ssi_read_fingerprints";
+const META_KEY_SSI_EXACT_FALLBACK: &str = "ssi_exact_fallback";
+const META_KEY_INDEXED_FLAGS: &str = "indexed_flags";
+const META_KEY_INTERN_PREFIX_LEN: &str = "intern_prefix_len";
+const META_KEY_AUTO_VALUE_THRESHOLD: &str = "auto_value_threshold";
+const META_KEY_MIN_VALUE_THRESHOLD: &str = "min_value_threshold";
+const META_KEY_MAX_DB_SIZE: &str = "max_db_size";
+const META_KEY_ERASE_BLOCK_SIZE: &str = "erase_block_size";
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum IsolationLevel {
@@ -30,21 +47,145 @@ impl IsolationLevel {
     }
 }
 
+/// What a store does when one of its internal invariants is found violated, such as the
+/// versions of the index going backwards. Such a violation is a bug, or a sign that the
+/// files of the store were changed under it.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum InvariantPolicy {
+    /// Panic, which is the default.
+    Panic,
+    /// Poison the store: the operation and every later one return `Error::Poisoned`, until
+    /// the store is closed and reopened.
+    Poison,
+    /// Report the violation and carry on as well as possible.
+    Continue,
+}
+
+/// What a store does when writing or syncing its commit log fails, see
+/// `Store::set_fsync_failure_hook`. The bytes written since the last successful sync may then
+/// be lost even if a later sync succeeds, so the store does not write to the log again until
+/// it is closed and reopened, which repairs the log.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum FsyncFailurePolicy {
+    /// Make the store read-only, which is the default: the commits return
+    /// `Error::StoreReadOnly`, while the reads go on as far as they do not read the values
+    /// from the commit log.
+    ReadOnly,
+    /// Poison the store: every later operation returns `Error::Poisoned`.
+    Poison,
+    /// Panic.
+    Panic,
+}
+
+/// What a store writes to `Options::redundancy_dir` for each sealed segment of its commit log,
+/// for the blocks of the segment found damaged when the store is opened to be rebuilt.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SegmentRedundancy {
+    /// The checksums of the blocks of the segment only, which locate the damaged blocks but
+    /// do not rebuild them.
+    Checksums,
+    /// The checksums, and a parity block for each group of blocks, which is the default. A
+    /// group with a single damaged block is rebuilt, for about 6% of the size of the segment.
+    Parity,
+    /// The checksums, and a copy of the segment, which rebuilds any damaged block that is
+    /// intact in the copy.
+    Copy,
+}
+
+/// Which older versions of the keys `Store::shrink_index` keeps, besides the versions
+/// committed at or after the GC watermark, see `Store::set_gc_watermark`.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum VersionRetention {
+    /// The latest version of each key only, which is the default.
+    Latest,
+    /// The given number of latest versions of each key, the latest one included.
+    Count(usize),
+    /// The versions committed within the given duration before the shrink.
+    Duration(Duration),
+}
+
+/// What a store does when it is opened with segments of its commit log missing, such as
+/// segments deleted by an operator. A segment is missing if its key range was recorded, see
+/// `Store::segments_for_range`, which it is once the store moved on to the next segment.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum MissingSegments {
+    /// Fail to open with `Error::MissingSegments`, listing the ids of the segments missing,
+    /// which is the default.
+    Fail,
+    /// Open all the same, with the keys in the ranges of the segments missing unavailable:
+    /// their reads and the scans overlapping them return `Error::SegmentUnavailable`, see
+    /// `Store::missing_segments`.
+    Degraded,
+}
+
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct Options {
     // Required options.
-    pub dir: PathBuf, // Directory path for storing the database files.
+    pub dir: PathBuf,   // Directory path for storing the database files.
+    pub vfs: SharedVfs, // Filesystem the files of the store are stored on. Defaults to the one of the operating system.
 
     // Usually modified options.
     pub isolation_level: IsolationLevel, // Isolation level for transactions.
 
     // Fine tuning options.
-    pub max_key_size: u64,          // Maximum size in bytes for key.
-    pub max_value_size: u64,        // Maximum size in bytes for value.
+    pub max_key_size: u64,            // Maximum size in bytes for key.
+    pub max_value_size: u64,          // Maximum size in bytes for value.
     pub max_value_threshold: usize, // Threshold to decide value should be stored and read from memory or from log value files.
+    pub auto_value_threshold: bool, // If true, the threshold is tuned at runtime between `min_value_threshold` and `max_value_threshold`.
+    pub min_value_threshold: usize, // Lowest threshold the tuning may choose, see `auto_value_threshold`.
     pub max_entries_per_txn: u32,   // Maximum entries in a transaction.
     pub max_segment_size: u64,      // Maximum size of a single segment.
     pub max_value_cache_size: u64,  // Maximum size of the value cache.
+    pub read_probe_interval: u64, // Verify one in this many cached reads against the commit log. 0 disables probes.
+    pub max_active_transactions: u64, // Maximum number of transactions open at the same time. 0 means unlimited.
+    pub max_db_size: u64, // Maximum size in bytes of the files of the store, past which commits fail with `Error::QuotaExceeded`. 0 means unlimited.
+    pub soft_db_size: u64, // Size in bytes of the files of the store past which the hook set by `Store::set_soft_quota_hook` is invoked, to alert before `max_db_size` is reached. 0 means none.
+    pub reserved_space: u64, // Bytes of disk space reserved when the store is opened, released when the disk fills, see `Error::DiskFull`. 0 reserves none.
+    pub indexed_flags: u64, // Bit mask of the user flags for which the flagged keys are indexed, see `Store::flagged_keys`.
+    pub intern_prefix_len: usize, // Length of the key prefixes stored once in the index and referred to by a short label. 0 disables interning.
+
+    // Conflict detection options.
+    pub ssi_read_fingerprints: bool, // If true, serializable transactions record 64-bit fingerprints of the keys they read instead of the keys.
+    pub ssi_exact_fallback: bool, // If true, the read keys are kept as well, to rule out conflicts between distinct keys sharing a fingerprint.
+    pub max_update_attempts: u32, // Maximum number of times `Store::update` runs its transaction before returning the conflict.
+    pub prepare_timeout: u64, // Milliseconds a transaction prepared with `Transaction::prepare` may hold the commit lock, after which the lock is released and its commit fails with `Error::PrepareTimedOut`. 0 means no limit.
+
+    // Compression options.
+    pub compression: Vec<CompressionRule>, // Per-prefix value compression rules. The longest matching prefix wins.
+
+    // Stream options.
+    pub max_stream_length: u64, // Maximum number of entries retained per stream. 0 means unlimited.
+
+    // Expiry options.
+    pub expiry_sweep_interval: u64, // Milliseconds between two sweeps of the expired keys by a background thread, see `Store::sweep_expired`. 0 disables them.
+    pub expiry_clock: SharedExpiryClock, // Clock the expiry of the keys is evaluated against, see `ExpiryClock`. Defaults to the wall clock.
+
+    // History options.
+    pub version_retention: VersionRetention, // Older versions of the keys kept in the index by `Store::shrink_index`.
+
+    // Compaction options.
+    pub tiny_segment_merge: usize, // Number of sealed segments less than a quarter full from which opening the store merges them in the background, see `Store::compact_log`. 0 disables it.
+    pub target_space_amp: u64, // Space amplification in percent, 200 for files twice the live data, above which the commit log is compacted in the background, see `Store::space_amp`. 0 disables it.
+
+    // Background options.
+    pub background_threads: usize, // Number of threads running the background jobs: the sweeps, the compactions and the publications of the stats. 0 is taken as 1.
+    pub job_priorities: JobPriorities, // Order in which the background jobs queued run, see `JobKind`.
+
+    // Diagnostics options.
+    pub track_memory: bool, // If true, the approximate memory used by the subsystems is reported in the store stats.
+    pub capture_backtraces: bool, // If true, debug builds record where each transaction began, see `Store::active_transactions`.
+    pub write_audit_interval: u64, // Validate one in this many values written with the validator set by `Store::set_write_validator`. 0 disables the audit.
+    pub stats_publish_interval: u64, // Milliseconds between two publications of the stats while commits are written, see `Store::publish_stats`. 0 disables them.
+    pub invariant_policy: InvariantPolicy, // What to do when an internal invariant is violated, see `Store::set_invariant_hook`.
+    pub fsync_failure_policy: FsyncFailurePolicy, // What to do when writing or syncing the commit log fails.
+    pub missing_segments: MissingSegments, // What to do when the store is opened with segments of its commit log missing.
+
+    // Flash options.
+    pub erase_block_size: u64, // Erase block size of the flash storage, such as an SD card or eMMC, which turns on the flash-friendly writes, see `Store::sync_barrier`: the segments begin with a header padded to an erase block. It must divide `max_segment_size`. 0 disables them.
+
+    // Redundancy options.
+    pub redundancy_dir: Option<PathBuf>, // Directory the redundancy of the sealed segments of the commit log is written to, ideally on another disk. None writes none.
+    pub segment_redundancy: SegmentRedundancy, // What is written to `redundancy_dir` for each sealed segment.
 
     // Field to indicate whether the data should be stored completely in memory
     pub disk_persistence: bool, // If false, data will be stored completely in memory. If true, data will be stored on disk too.
@@ -55,13 +196,46 @@ impl Default for Options {
     fn default() -> Self {
         Self {
             dir: PathBuf::from(""),
+            vfs: SharedVfs::default(),
             max_key_size: 1024,
             max_value_size: 1024 * 1024,
             max_entries_per_txn: 1 << 12, // 4096 entries
             max_value_threshold: 64,      // 64 bytes
+            auto_value_threshold: false,
+            min_value_threshold: 0,
             isolation_level: IsolationLevel::SnapshotIsolation,
             max_segment_size: 1 << 29, // 512 MB
             max_value_cache_size: 100000,
+            read_probe_interval: 0,
+            max_active_transactions: 0,
+            max_db_size: 0,
+            soft_db_size: 0,
+            reserved_space: 0,
+            indexed_flags: 0,
+            intern_prefix_len: 0,
+            ssi_read_fingerprints: false,
+            ssi_exact_fallback: false,
+            max_update_attempts: 10,
+            prepare_timeout: 30_000,
+            compression: Vec::new(),
+            max_stream_length: 0,
+            expiry_sweep_interval: 0,
+            expiry_clock: SharedExpiryClock::default(),
+            version_retention: VersionRetention::Latest,
+            tiny_segment_merge: 0,
+            target_space_amp: 0,
+            background_threads: 1,
+            job_priorities: JobPriorities::default(),
+            track_memory: false,
+            capture_backtraces: false,
+            write_audit_interval: 0,
+            stats_publish_interval: 0,
+            invariant_policy: InvariantPolicy::Panic,
+            fsync_failure_policy: FsyncFailurePolicy::ReadOnly,
+            redundancy_dir: None,
+            segment_redundancy: SegmentRedundancy::Parity,
+            missing_segments: MissingSegments::Fail,
+            erase_block_size: 0,
             disk_persistence: true,
         }
     }
@@ -83,9 +257,35 @@ impl Options {
             META_KEY_MAX_VALUE_THRESHOLD,
             self.max_value_threshold as u64,
         );
+        metadata.put_uint(
+            META_KEY_AUTO_VALUE_THRESHOLD,
+            self.auto_value_threshold as u64,
+        );
+        metadata.put_uint(
+            META_KEY_MIN_VALUE_THRESHOLD,
+            self.min_value_threshold as u64,
+        );
         metadata.put_uint(META_KEY_MAX_ENTRIES_PER_TX, self.max_entries_per_txn as u64);
         metadata.put_uint(META_KEY_MAX_FILE_SIZE, self.max_segment_size);
         metadata.put_uint(META_KEY_MAX_VALUE_CACHE_SIZE, self.max_value_cache_size);
+        if !self.compression.is_empty() {
+            metadata.put(META_KEY_COMPRESSION, &encode_rules(&self.compression));
+        }
+        metadata.put_uint(META_KEY_MAX_STREAM_LENGTH, self.max_stream_length);
+        metadata.put_uint(META_KEY_READ_PROBE_INTERVAL, self.read_probe_interval);
+        metadata.put_uint(
+            META_KEY_MAX_ACTIVE_TRANSACTIONS,
+            self.max_active_transactions,
+        );
+        metadata.put_uint(META_KEY_MAX_DB_SIZE, self.max_db_size);
+        metadata.put_uint(META_KEY_INDEXED_FLAGS, self.indexed_flags);
+        metadata.put_uint(META_KEY_INTERN_PREFIX_LEN, self.intern_prefix_len as u64);
+        metadata.put_uint(
+            META_KEY_SSI_READ_FINGERPRINTS,
+            self.ssi_read_fingerprints as u64,
+        );
+        metadata.put_uint(META_KEY_SSI_EXACT_FALLBACK, self.ssi_exact_fallback as u64);
+        metadata.put_uint(META_KEY_ERASE_BLOCK_SIZE, self.erase_block_size);
 
         metadata
     }
@@ -98,17 +298,101 @@ impl Options {
 
         Ok(Options {
             dir,
+            vfs: SharedVfs::default(),
             isolation_level,
             max_key_size: metadata.get_uint(META_KEY_MAX_KEY_SIZE)?,
             max_value_size: metadata.get_uint(META_KEY_MAX_VALUE_SIZE)?,
             max_value_threshold: metadata.get_uint(META_KEY_MAX_VALUE_THRESHOLD)? as usize,
+            auto_value_threshold: match metadata.get(META_KEY_AUTO_VALUE_THRESHOLD) {
+                Some(_) => metadata.get_uint(META_KEY_AUTO_VALUE_THRESHOLD)? != 0,
+                None => false,
+            },
+            min_value_threshold: match metadata.get(META_KEY_MIN_VALUE_THRESHOLD) {
+                Some(_) => metadata.get_uint(META_KEY_MIN_VALUE_THRESHOLD)? as usize,
+                None => 0,
+            },
             max_entries_per_txn: metadata.get_uint(META_KEY_MAX_ENTRIES_PER_TX)? as u32,
             max_segment_size: metadata.get_uint(META_KEY_MAX_FILE_SIZE)?,
             max_value_cache_size: metadata.get_uint(META_KEY_MAX_VALUE_CACHE_SIZE)?,
+            read_probe_interval: match metadata.get(META_KEY_READ_PROBE_INTERVAL) {
+                Some(_) => metadata.get_uint(META_KEY_READ_PROBE_INTERVAL)?,
+                None => 0,
+            },
+            max_active_transactions: match metadata.get(META_KEY_MAX_ACTIVE_TRANSACTIONS) {
+                Some(_) => metadata.get_uint(META_KEY_MAX_ACTIVE_TRANSACTIONS)?,
+                None => 0,
+            },
+            max_db_size: match metadata.get(META_KEY_MAX_DB_SIZE) {
+                Some(_) => metadata.get_uint(META_KEY_MAX_DB_SIZE)?,
+                None => 0,
+            },
+            soft_db_size: 0,
+            reserved_space: 0,
+            indexed_flags: match metadata.get(META_KEY_INDEXED_FLAGS) {
+                Some(_) => metadata.get_uint(META_KEY_INDEXED_FLAGS)?,
+                None => 0,
+            },
+            intern_prefix_len: match metadata.get(META_KEY_INTERN_PREFIX_LEN) {
+                Some(_) => metadata.get_uint(META_KEY_INTERN_PREFIX_LEN)? as usize,
+                None => 0,
+            },
+            ssi_read_fingerprints: match metadata.get(META_KEY_SSI_READ_FINGERPRINTS) {
+                Some(_) => metadata.get_uint(META_KEY_SSI_READ_FINGERPRINTS)? != 0,
+                None => false,
+            },
+            ssi_exact_fallback: match metadata.get(META_KEY_SSI_EXACT_FALLBACK) {
+                Some(_) => metadata.get_uint(META_KEY_SSI_EXACT_FALLBACK)? != 0,
+                None => false,
+            },
+            max_update_attempts: 10,
+            prepare_timeout: 30_000,
+            compression: match metadata.get(META_KEY_COMPRESSION) {
+                Some(bytes) => decode_rules(bytes)?,
+                None => Vec::new(),
+            },
+            max_stream_length: match metadata.get(META_KEY_MAX_STREAM_LENGTH) {
+                Some(_) => metadata.get_uint(META_KEY_MAX_STREAM_LENGTH)?,
+                None => 0,
+            },
+            expiry_sweep_interval: 0,
+            expiry_clock: SharedExpiryClock::default(),
+            version_retention: VersionRetention::Latest,
+            tiny_segment_merge: 0,
+            target_space_amp: 0,
+            background_threads: 1,
+            job_priorities: JobPriorities::default(),
+            track_memory: false,
+            capture_backtraces: false,
+            write_audit_interval: 0,
+            stats_publish_interval: 0,
+            invariant_policy: InvariantPolicy::Panic,
+            fsync_failure_policy: FsyncFailurePolicy::ReadOnly,
+            redundancy_dir: None,
+            segment_redundancy: SegmentRedundancy::Parity,
+            missing_segments: MissingSegments::Fail,
+            erase_block_size: match metadata.get(META_KEY_ERASE_BLOCK_SIZE) {
+                Some(_) => metadata.get_uint(META_KEY_ERASE_BLOCK_SIZE)?,
+                None => 0,
+            },
             disk_persistence: true,
         })
     }
 
+    /// Lists the options that differ from the options persisted for an existing store and
+    /// cannot be changed without rewriting it, as `name: persisted -> provided` entries.
+    /// The other options are adopted when the store is opened, except that the maximum key
+    /// and value sizes cannot be decreased.
+    pub(crate) fn incompatible_changes(&self, persisted: &Options) -> Vec<String> {
+        let mut changes = Vec::new();
+        if self.max_segment_size != persisted.max_segment_size {
+            changes.push(format!(
+                "max_segment_size: {} -> {}",
+                persisted.max_segment_size, self.max_segment_size
+            ));
+        }
+        changes
+    }
+
     /// Returns true if the data should be persisted on disk.
     pub fn should_persist_data(&self) -> bool {
         self.disk_persistence
@@ -125,14 +409,47 @@ mod tests {
     fn default_options() {
         let options = Options::default();
 
+        assert_eq!(options.vfs, SharedVfs::default());
         assert_eq!(options.dir, PathBuf::from(""));
         assert_eq!(options.max_key_size, 1024);
         assert_eq!(options.max_value_size, 1024 * 1024);
         assert_eq!(options.max_entries_per_txn, 1 << 12);
         assert_eq!(options.max_value_threshold, 64);
+        assert!(!options.auto_value_threshold);
+        assert_eq!(options.min_value_threshold, 0);
         assert_eq!(options.isolation_level, IsolationLevel::SnapshotIsolation);
         assert_eq!(options.max_segment_size, 1 << 29);
         assert_eq!(options.max_value_cache_size, 100000);
+        assert!(options.compression.is_empty());
+        assert_eq!(options.max_stream_length, 0);
+        assert_eq!(options.read_probe_interval, 0);
+        assert_eq!(options.max_active_transactions, 0);
+        assert_eq!(options.max_db_size, 0);
+        assert_eq!(options.soft_db_size, 0);
+        assert_eq!(options.reserved_space, 0);
+        assert_eq!(options.indexed_flags, 0);
+        assert_eq!(options.intern_prefix_len, 0);
+        assert!(!options.ssi_read_fingerprints);
+        assert!(!options.ssi_exact_fallback);
+        assert_eq!(options.max_update_attempts, 10);
+        assert_eq!(options.prepare_timeout, 30_000);
+        assert_eq!(options.expiry_sweep_interval, 0);
+        assert_eq!(options.expiry_clock, SharedExpiryClock::default());
+        assert_eq!(options.version_retention, VersionRetention::Latest);
+        assert_eq!(options.tiny_segment_merge, 0);
+        assert_eq!(options.target_space_amp, 0);
+        assert_eq!(options.background_threads, 1);
+        assert_eq!(options.job_priorities, JobPriorities::default());
+        assert!(!options.track_memory);
+        assert!(!options.capture_backtraces);
+        assert_eq!(options.write_audit_interval, 0);
+        assert_eq!(options.stats_publish_interval, 0);
+        assert_eq!(options.invariant_policy, InvariantPolicy::Panic);
+        assert_eq!(options.fsync_failure_policy, FsyncFailurePolicy::ReadOnly);
+        assert!(options.redundancy_dir.is_none());
+        assert_eq!(options.segment_redundancy, SegmentRedundancy::Parity);
+        assert_eq!(options.missing_segments, MissingSegments::Fail);
+        assert_eq!(options.erase_block_size, 0);
         assert!(options.disk_persistence);
     }
 
@@ -140,13 +457,49 @@ mod tests {
     fn options_to_metadata() {
         let options = Options {
             dir: PathBuf::from("/test/dir"),
+            vfs: SharedVfs::default(),
             max_key_size: 2048,
             max_value_size: 4096,
             max_entries_per_txn: 500,
             max_value_threshold: 128,
+            auto_value_threshold: true,
+            min_value_threshold: 16,
             isolation_level: IsolationLevel::SerializableSnapshotIsolation,
             max_segment_size: 1 << 25, // 32 MB
             max_value_cache_size: 200000,
+            read_probe_interval: 100,
+            max_active_transactions: 8,
+            max_db_size: 1 << 30,
+            soft_db_size: 1 << 29,
+            reserved_space: 1 << 20,
+            indexed_flags: 0b11,
+            intern_prefix_len: 12,
+            ssi_read_fingerprints: true,
+            ssi_exact_fallback: false,
+            max_update_attempts: 3,
+            prepare_timeout: 1000,
+            compression: Vec::new(),
+            max_stream_length: 10,
+            expiry_sweep_interval: 0,
+            expiry_clock: SharedExpiryClock::default(),
+            version_retention: VersionRetention::Count(3),
+            tiny_segment_merge: 8,
+            target_space_amp: 300,
+            background_threads: 2,
+            job_priorities: JobPriorities {
+                compaction: 4,
+                ..JobPriorities::default()
+            },
+            track_memory: false,
+            capture_backtraces: false,
+            write_audit_interval: 0,
+            stats_publish_interval: 0,
+            invariant_policy: InvariantPolicy::Poison,
+            fsync_failure_policy: FsyncFailurePolicy::Poison,
+            redundancy_dir: Some(PathBuf::from("/test/redundancy")),
+            segment_redundancy: SegmentRedundancy::Copy,
+            missing_segments: MissingSegments::Degraded,
+            erase_block_size: 1 << 20,
             disk_persistence: true,
         };
 
@@ -162,12 +515,35 @@ mod tests {
             metadata.get_uint(META_KEY_MAX_VALUE_THRESHOLD).unwrap(),
             128
         );
+        assert_eq!(metadata.get_uint(META_KEY_AUTO_VALUE_THRESHOLD).unwrap(), 1);
+        assert_eq!(metadata.get_uint(META_KEY_MIN_VALUE_THRESHOLD).unwrap(), 16);
         assert_eq!(metadata.get_uint(META_KEY_MAX_ENTRIES_PER_TX).unwrap(), 500);
         assert_eq!(metadata.get_uint(META_KEY_MAX_FILE_SIZE).unwrap(), 1 << 25);
         assert_eq!(
             metadata.get_uint(META_KEY_MAX_VALUE_CACHE_SIZE).unwrap(),
             200000
         );
+        assert_eq!(metadata.get_uint(META_KEY_MAX_STREAM_LENGTH).unwrap(), 10);
+        assert_eq!(
+            metadata.get_uint(META_KEY_READ_PROBE_INTERVAL).unwrap(),
+            100
+        );
+        assert_eq!(
+            metadata.get_uint(META_KEY_MAX_ACTIVE_TRANSACTIONS).unwrap(),
+            8
+        );
+        assert_eq!(metadata.get_uint(META_KEY_MAX_DB_SIZE).unwrap(), 1 << 30);
+        assert_eq!(metadata.get_uint(META_KEY_INDEXED_FLAGS).unwrap(), 0b11);
+        assert_eq!(metadata.get_uint(META_KEY_INTERN_PREFIX_LEN).unwrap(), 12);
+        assert_eq!(
+            metadata.get_uint(META_KEY_SSI_READ_FINGERPRINTS).unwrap(),
+            1
+        );
+        assert_eq!(metadata.get_uint(META_KEY_SSI_EXACT_FALLBACK).unwrap(), 0);
+        assert_eq!(
+            metadata.get_uint(META_KEY_ERASE_BLOCK_SIZE).unwrap(),
+            1 << 20
+        );
     }
 
     #[test]
@@ -204,4 +580,36 @@ mod tests {
         assert_eq!(options.max_value_cache_size, 200000);
         assert!(options.disk_persistence);
     }
+
+    #[test]
+    fn incompatible_changes() {
+        let persisted = Options::new();
+
+        let mut options = persisted.clone();
+        options.max_entries_per_txn = 10;
+        options.max_value_cache_size = 10;
+        options.max_key_size *= 2;
+        assert!(options.incompatible_changes(&persisted).is_empty());
+
+        options.max_segment_size = 1 << 20;
+        assert_eq!(
+            options.incompatible_changes(&persisted),
+            vec!["max_segment_size: 536870912 -> 1048576".to_string()]
+        );
+    }
+
+    #[test]
+    fn compression_rules_roundtrip_through_metadata() {
+        use crate::storage::kv::compression::CompressionFormat;
+
+        let mut options = Options::new();
+        options.compression = vec![
+            CompressionRule::new(b"ts/", CompressionFormat::Zstd).with_level(7),
+            CompressionRule::new(b"blob/", CompressionFormat::None),
+        ];
+
+        let metadata = options.to_metadata();
+        let restored = Options::from_metadata(metadata, PathBuf::from("")).unwrap();
+        assert_eq!(restored.compression, options.compression);
+    }
 }
@@ -1,6 +1,7 @@
 use std::{
     cmp::Reverse,
-    collections::BinaryHeap,
+    collections::{hash_map::DefaultHasher, BinaryHeap},
+    hash::{Hash, Hasher},
     ops::Bound,
     sync::{
         atomic::{AtomicU64, Ordering},
@@ -12,12 +13,12 @@ use bytes::Bytes;
 use crossbeam_channel::{bounded, Receiver, Sender};
 use hashbrown::{HashMap, HashSet};
 use parking_lot::{Mutex, RwLock};
-use tokio::sync::Mutex as AsyncMutex;
+use tokio::sync::{Mutex as AsyncMutex, Notify};
 use vart::{TrieError, VariableSizeKey};
 
 use crate::storage::kv::{
     error::{Error, Result},
-    option::Options,
+    option::{IsolationLevel as IsolationLevelOption, Options},
     snapshot::Snapshot,
     transaction::Transaction,
 };
@@ -27,9 +28,21 @@ use crate::storage::kv::{
 /// It supports two isolation levels: SnapshotIsolation and SerializableSnapshotIsolation.
 pub(crate) struct Oracle {
     /// Write lock to ensure that only one transaction can commit at a time.
-    pub(crate) write_lock: AsyncMutex<()>,
+    pub(crate) write_lock: Arc<AsyncMutex<()>>,
     /// Isolation level of the transactions.
     isolation: IsolationLevel,
+    /// Transactions sent to the writer whose writes are not in the index yet.
+    in_flight: Mutex<Vec<InFlight>>,
+    /// Notified when a transaction in flight leaves it.
+    indexed: Notify,
+}
+
+/// A transaction sent to the writer, with the keys it writes. The commit lock is released
+/// once a transaction is sent, so the transactions committing next are validated before it
+/// is in the index, and check their reads against the keys it writes.
+struct InFlight {
+    version: u64,
+    keys: HashSet<Bytes>,
 }
 
 impl Oracle {
@@ -41,20 +54,78 @@ impl Oracle {
                 IsolationLevel::SnapshotIsolation(SnapshotIsolation::new())
             }
             crate::storage::kv::option::IsolationLevel::SerializableSnapshotIsolation => {
-                IsolationLevel::SerializableSnapshotIsolation(SerializableSnapshotIsolation::new())
+                IsolationLevel::SerializableSnapshotIsolation(SerializableSnapshotIsolation::new(
+                    opts.ssi_read_fingerprints,
+                ))
             }
         };
 
         Self {
-            write_lock: AsyncMutex::new(()),
+            write_lock: Arc::new(AsyncMutex::new(())),
             isolation,
+            in_flight: Mutex::new(Vec::new()),
+            indexed: Notify::new(),
         }
     }
 
-    /// Generates a new commit timestamp for the given transaction.
+    /// Generates a new commit timestamp for the given transaction, or takes `version` as its
+    /// commit timestamp if given.
     /// It delegates to the isolation level to generate the timestamp.
-    pub(crate) fn new_commit_ts(&self, txn: &mut Transaction) -> Result<u64> {
-        self.isolation.new_commit_ts(txn)
+    pub(crate) fn new_commit_ts(&self, txn: &mut Transaction, version: Option<u64>) -> Result<u64> {
+        // Snapshot isolation validates the reads against the index, which does not hold the
+        // transactions in flight yet. They are checked first: a transaction leaves them once
+        // it is in the index, so that it is seen in one or the other.
+        if let IsolationLevel::SnapshotIsolation(_) = &self.isolation {
+            if self.read_in_flight(txn) {
+                return Err(Error::TransactionReadConflict);
+            }
+        }
+        self.isolation.new_commit_ts(txn, version)
+    }
+
+    /// Returns true if a transaction in flight writes a key the transaction read a version
+    /// of before it.
+    fn read_in_flight(&self, txn: &Transaction) -> bool {
+        let in_flight = self.in_flight.lock();
+        if in_flight.is_empty() {
+            return false;
+        }
+        let read_set = txn.read_set.lock();
+        read_set.keys.iter().any(|(key, ts)| {
+            in_flight
+                .iter()
+                .any(|sent| sent.version > *ts && sent.keys.contains(key))
+        })
+    }
+
+    /// Records a transaction sent to the writer with the commit lock held, until `indexed`
+    /// is called for its version.
+    pub(crate) fn sent(&self, version: u64, keys: HashSet<Bytes>) {
+        self.in_flight.lock().push(InFlight { version, keys });
+    }
+
+    /// Records that the transaction with the given version is in the index, or will never
+    /// be as it failed.
+    pub(crate) fn indexed(&self, version: u64) {
+        let mut in_flight = self.in_flight.lock();
+        let len = in_flight.len();
+        in_flight.retain(|sent| sent.version != version);
+        if in_flight.len() < len {
+            self.indexed.notify_waiters();
+        }
+    }
+
+    /// Waits for the transactions in flight to be in the index. With the commit lock held,
+    /// the index then holds every transaction committed.
+    pub(crate) async fn wait_indexed(&self) {
+        loop {
+            // The future is registered before the check, so that no notification is missed.
+            let indexed = self.indexed.notified();
+            if self.in_flight.lock().is_empty() {
+                return;
+            }
+            indexed.await;
+        }
     }
 
     /// Returns the read timestamp.
@@ -63,6 +134,12 @@ impl Oracle {
         self.isolation.read_ts()
     }
 
+    /// Returns the read timestamp of a read-only transaction without taking a lock.
+    /// It delegates to the isolation level to get the timestamp.
+    pub(crate) fn snapshot_ts(&self) -> u64 {
+        self.isolation.snapshot_ts()
+    }
+
     /// Sets the timestamp and increments it.
     /// It delegates to the isolation level to set and increment the timestamp.
     pub(crate) fn set_ts(&self, ts: u64) {
@@ -81,6 +158,27 @@ impl Oracle {
         }
     }
 
+    /// Records that a transaction which began at the given read timestamp committed, or
+    /// will not: the transactions committed after it began no longer need to be kept to
+    /// check it for conflicts.
+    pub(crate) fn read_done(&self, read_ts: u64) {
+        if let IsolationLevel::SerializableSnapshotIsolation(oracle) = &self.isolation {
+            oracle.mark_read_operations_done(read_ts);
+        }
+    }
+
+    /// Records that the transaction given the commit timestamp was not written, so that the
+    /// transactions committing after it are not checked for conflicts against it.
+    pub(crate) fn abandoned(&self, ts: u64) {
+        if let IsolationLevel::SerializableSnapshotIsolation(oracle) = &self.isolation {
+            oracle
+                .commit_tracker
+                .lock()
+                .committed_transactions
+                .retain(|committed_txn| committed_txn.ts != ts);
+        }
+    }
+
     /// Waits for the transactions to commit up to the given timestamp.
     /// If the isolation level is SerializableSnapshotIsolation, it delegates to the isolation level to wait for the transactions.
     pub(crate) fn wait_for(&self, ts: u64) {
@@ -101,10 +199,10 @@ pub(crate) enum IsolationLevel {
 }
 
 macro_rules! isolation_level_method {
-    ($self:ident, $method:ident $(, $arg:ident)?) => {
+    ($self:ident, $method:ident $(, $arg:ident)*) => {
         match $self {
-            IsolationLevel::SnapshotIsolation(oracle) => oracle.$method($($arg)?),
-            IsolationLevel::SerializableSnapshotIsolation(oracle) => oracle.$method($($arg)?),
+            IsolationLevel::SnapshotIsolation(oracle) => oracle.$method($($arg),*),
+            IsolationLevel::SerializableSnapshotIsolation(oracle) => oracle.$method($($arg),*),
         }
     };
 }
@@ -112,8 +210,8 @@ macro_rules! isolation_level_method {
 impl IsolationLevel {
     /// Generates a new commit timestamp for the given transaction.
     /// It delegates to the specific isolation level to generate the timestamp.
-    pub(crate) fn new_commit_ts(&self, txn: &mut Transaction) -> Result<u64> {
-        isolation_level_method!(self, new_commit_ts, txn)
+    pub(crate) fn new_commit_ts(&self, txn: &mut Transaction, version: Option<u64>) -> Result<u64> {
+        isolation_level_method!(self, new_commit_ts, txn, version)
     }
 
     /// Returns the read timestamp.
@@ -122,6 +220,12 @@ impl IsolationLevel {
         isolation_level_method!(self, read_ts)
     }
 
+    /// Returns the read timestamp of a read-only transaction.
+    /// It delegates to the specific isolation level to get the timestamp.
+    pub(crate) fn snapshot_ts(&self) -> u64 {
+        isolation_level_method!(self, snapshot_ts)
+    }
+
     /// Sets the timestamp.
     /// It delegates to the specific isolation level to set the timestamp.
     pub(crate) fn set_ts(&self, ts: u64) {
@@ -158,11 +262,11 @@ impl SnapshotIsolation {
     /// It performs optimistic concurrency control (OCC) by checking if the read keys in the transaction
     /// are still valid in the latest snapshot, and if the timestamp of the read keys matches the timestamp
     /// of the latest snapshot. If the timestamp does not match, then there is a conflict.
-    pub(crate) fn new_commit_ts(&self, txn: &mut Transaction) -> Result<u64> {
+    pub(crate) fn new_commit_ts(&self, txn: &mut Transaction, version: Option<u64>) -> Result<u64> {
         let current_snapshot = Snapshot::take(txn.core.clone(), self.read_ts())?;
         let read_set = txn.read_set.lock();
 
-        for (key, ts) in read_set.iter() {
+        for (key, ts) in read_set.keys.iter() {
             match current_snapshot.get(&key[..].into()) {
                 Ok(val_ref) => {
                     if *ts != val_ref.ts() {
@@ -180,7 +284,8 @@ impl SnapshotIsolation {
         }
 
         let ts = self.next_tx_id.load(Ordering::SeqCst);
-        self.increment_ts();
+        let ts = checked_version(ts, version)?;
+        self.next_tx_id.store(ts + 1, Ordering::SeqCst);
         Ok(ts)
     }
 
@@ -189,33 +294,107 @@ impl SnapshotIsolation {
         self.next_tx_id.load(Ordering::SeqCst) - 1
     }
 
+    /// Returns the read timestamp of a read-only transaction, the same as `read_ts`.
+    pub(crate) fn snapshot_ts(&self) -> u64 {
+        self.read_ts()
+    }
+
     /// Increments the next transaction ID by 1.
     pub(crate) fn increment_ts(&self) {
         self.next_tx_id.fetch_add(1, Ordering::SeqCst);
     }
 }
 
+/// Returns the commit timestamp of a transaction, given the next timestamp of the store:
+/// `version` if it is given, which must not be before the next timestamp. The largest
+/// version is refused, as the next timestamp after it would overflow.
+fn checked_version(next: u64, version: Option<u64>) -> Result<u64> {
+    match version {
+        None if next < u64::MAX => Ok(next),
+        None => Err(Error::VersionOutOfRange(next)),
+        Some(u64::MAX) => Err(Error::VersionOutOfRange(u64::MAX)),
+        Some(version) if version >= next => Ok(version),
+        Some(version) => Err(Error::NonMonotonicVersion(version, next - 1)),
+    }
+}
+
+/// Returns the 64-bit fingerprint of a key, which read sets record in place of the key
+/// when `Options::ssi_read_fingerprints` is set.
+pub(crate) fn fingerprint(key: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The keys read by a transaction, with the timestamp of the version that was read
+/// (0 if the key was not found).
+///
+/// Snapshot isolation compares the versions read against the latest ones, so it needs the
+/// keys. Serializable snapshot isolation only needs to know whether a key written by a
+/// concurrent transaction was read, so with `Options::ssi_read_fingerprints` the read set
+/// holds 8 bytes per key read instead of the key. Distinct keys sharing a fingerprint then
+/// abort transactions that did not conflict; with `Options::ssi_exact_fallback` the keys are
+/// kept too, and such false positives are ruled out (and counted in the store stats).
+pub(crate) struct ReadSet {
+    record_keys: bool,
+    record_fingerprints: bool,
+    pub(crate) keys: Vec<(Bytes, u64)>,
+    pub(crate) fingerprints: Vec<u64>,
+}
+
+impl ReadSet {
+    pub(crate) fn new(opts: &Options) -> Self {
+        let record_fingerprints = opts.ssi_read_fingerprints
+            && opts.isolation_level == IsolationLevelOption::SerializableSnapshotIsolation;
+        Self {
+            record_keys: !record_fingerprints || opts.ssi_exact_fallback,
+            record_fingerprints,
+            keys: Vec::new(),
+            fingerprints: Vec::new(),
+        }
+    }
+
+    /// Records a read of the version of `key` with timestamp `ts`.
+    pub(crate) fn push(&mut self, key: Bytes, ts: u64) {
+        if self.record_fingerprints {
+            self.fingerprints.push(fingerprint(&key));
+        }
+        if self.record_keys {
+            self.keys.push((key, ts));
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.keys.is_empty() && self.fingerprints.is_empty()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.keys.clear();
+        self.fingerprints.clear();
+    }
+}
+
 /// Struct representing a commit marker in a transaction.
-/// It contains a timestamp and a set of conflict keys.
+/// It contains a timestamp and a set of conflict keys, and their fingerprints
+/// if the read sets record fingerprints.
 struct CommitMarker {
     ts: u64,
     conflict_keys: HashSet<Bytes>,
+    conflict_fingerprints: HashSet<u64>,
 }
 
 /// Struct for tracking committed transactions.
-/// It maintains the next timestamp, a list of committed transactions, and the last cleanup timestamp.
+/// It maintains a list of committed transactions, and the last cleanup timestamp.
 #[derive(Default)]
 struct CommitTracker {
-    next_ts: u64,
     committed_transactions: Vec<CommitMarker>,
     last_cleanup_ts: u64,
 }
 
 impl CommitTracker {
-    /// Creates a new CommitTracker instance with the next timestamp, committed transactions, and last cleanup timestamp set to 0.
+    /// Creates a new CommitTracker instance with no committed transactions, and the last cleanup timestamp set to 0.
     fn new() -> Self {
         Self {
-            next_ts: 0,
             committed_transactions: Vec::new(),
             last_cleanup_ts: 0,
         }
@@ -256,13 +435,43 @@ impl CommitTracker {
                 }
             }
 
+            let stats = &txn.core.stats;
             self.committed_transactions
                 .iter()
                 .filter(|committed_txn| committed_txn.ts > txn.read_ts)
                 .any(|committed_txn| {
-                    read_set
+                    if read_set.fingerprints.is_empty() {
+                        return read_set
+                            .keys
+                            .iter()
+                            .any(|read| committed_txn.conflict_keys.contains(&read.0));
+                    }
+
+                    let hits = read_set
+                        .fingerprints
+                        .iter()
+                        .filter(|fp| committed_txn.conflict_fingerprints.contains(*fp))
+                        .count() as u64;
+                    if hits == 0 {
+                        return false;
+                    }
+                    stats
+                        .ssi_fingerprint_hits
+                        .fetch_add(hits, Ordering::Relaxed);
+                    if read_set.keys.is_empty() {
+                        // Without the keys, a shared fingerprint is taken as a conflict.
+                        return true;
+                    }
+
+                    let conflicts = read_set
+                        .keys
                         .iter()
-                        .any(|read| committed_txn.conflict_keys.contains(&read.0))
+                        .filter(|read| committed_txn.conflict_keys.contains(&read.0))
+                        .count() as u64;
+                    stats
+                        .ssi_fingerprint_false_positives
+                        .fetch_add(hits - conflicts, Ordering::Relaxed);
+                    conflicts > 0
                 })
         }
     }
@@ -309,66 +518,105 @@ pub(crate) struct SerializableSnapshotIsolation {
     // The `commit_tracker` keeps track of committed transactions and their timestamps.
     commit_tracker: Mutex<CommitTracker>,
 
+    // Whether the read sets record fingerprints, so that the fingerprints of the
+    // conflict keys are needed too.
+    fingerprints: bool,
+
     // The `txn_mark` and `read_mark` are used to manage visibility of transactions.
     // `txn_mark` blocks `new_transaction` to ensure previous commits are visible to new reads.
     txn_mark: Arc<WaterMark>,
     // `read_mark` marks the visibility of read operations to other transactions.
     read_mark: Arc<RwLock<BinaryHeap<Reverse<u64>>>>,
+    // The next commit timestamp. It is only changed with the commit tracker locked, which
+    // keeps it in step with the read marks, and is loaded without the lock by read-only
+    // transactions.
+    next_ts: AtomicU64,
 }
 
 impl SerializableSnapshotIsolation {
     // Create a new instance of `SerializableSnapshotIsolation`.
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new(fingerprints: bool) -> Self {
         Self {
             commit_tracker: Mutex::new(CommitTracker::new()),
+            fingerprints,
             // Create a watermark for transactions.
             txn_mark: Arc::new(WaterMark::new()),
             // Create a watermark for read operations.
             read_mark: Arc::new(RwLock::new(BinaryHeap::new())),
+            next_ts: AtomicU64::new(0),
         }
     }
 
     // Retrieve the read timestamp for a new read operation.
     pub(crate) fn read_ts(&self) -> u64 {
         let commit_tracker = self.commit_tracker.lock();
-        let read_ts = commit_tracker.next_ts - 1;
+        let read_ts = self.next_ts.load(Ordering::Acquire) - 1;
 
         // Keep track of the read timestamp for active transactions.
         self.read_mark.write().push(Reverse(read_ts));
+        drop(commit_tracker);
 
         // Wait for the current read timestamp to be visible to new transactions.
         self.txn_mark.wait_for(read_ts);
         read_ts
     }
 
+    // Retrieve the read timestamp for a new read-only transaction. Read-only transactions
+    // are never checked for conflicts, so they do not need a read mark, and the timestamp is
+    // loaded without locking the commit tracker.
+    pub(crate) fn snapshot_ts(&self) -> u64 {
+        let read_ts = self.next_ts.load(Ordering::Acquire) - 1;
+        self.txn_mark.wait_for(read_ts);
+        read_ts
+    }
+
     // Generate a new commit timestamp for a transaction.
-    pub(crate) fn new_commit_ts(&self, txn: &mut Transaction) -> Result<u64> {
+    pub(crate) fn new_commit_ts(&self, txn: &mut Transaction, version: Option<u64>) -> Result<u64> {
         let mut commit_tracker = self.commit_tracker.lock();
 
         // Check for conflicts between the transaction and committed transactions.
         if commit_tracker.has_conflict(txn) {
             return Err(Error::TransactionReadConflict);
         }
+        let ts = checked_version(self.next_ts.load(Ordering::Acquire), version)?;
 
-        // Mark that read operations are done up to the transaction's read timestamp.
-        self.mark_read_operations_done(txn.read_ts);
+        // The read operations of the transaction are marked done once it is written, see
+        // `Oracle::read_done`: a transaction whose write fails is checked again on retry.
 
         // Clean up committed transactions up to the current read mark.
         let max_read_ts = self.read_mark.read().peek().map_or(0, |peek| peek.0);
         commit_tracker.cleanup_committed_transactions(max_read_ts);
 
-        let ts = commit_tracker.next_ts;
-        commit_tracker.next_ts += 1;
+        self.next_ts.store(ts + 1, Ordering::Release);
 
-        assert!(ts >= commit_tracker.last_cleanup_ts);
+        if ts < commit_tracker.last_cleanup_ts {
+            txn.core.invariants.violated(
+                "commit timestamps are issued after the cleaned up ones",
+                format!(
+                    "commit timestamp {} before {}",
+                    ts, commit_tracker.last_cleanup_ts
+                ),
+            )?;
+        }
 
         // Add the transaction to the list of committed transactions with conflict keys.
-        let conflict_keys: HashSet<Bytes> =
-            txn.write_set.iter().map(|(key, _)| key.clone()).collect();
+        let conflict_keys: HashSet<Bytes> = txn
+            .write_set
+            .iter()
+            .map(|(key, _)| key.clone())
+            .chain(txn.range_deleted.iter().cloned())
+            .collect();
+        let conflict_fingerprints: HashSet<u64> = if self.fingerprints {
+            conflict_keys.iter().map(|key| fingerprint(key)).collect()
+        } else {
+            HashSet::new()
+        };
 
-        commit_tracker
-            .committed_transactions
-            .push(CommitMarker { ts, conflict_keys });
+        commit_tracker.committed_transactions.push(CommitMarker {
+            ts,
+            conflict_keys,
+            conflict_fingerprints,
+        });
 
         Ok(ts)
     }
@@ -382,7 +630,9 @@ impl SerializableSnapshotIsolation {
 
     // Set the global timestamp for the system.
     pub(crate) fn set_ts(&self, ts: u64) {
-        self.commit_tracker.lock().next_ts = ts;
+        let commit_tracker = self.commit_tracker.lock();
+        self.next_ts.store(ts, Ordering::Release);
+        drop(commit_tracker);
 
         // Mark that read operations are done up to the given timestamp.
         self.txn_mark.done_upto(ts);
@@ -393,14 +643,15 @@ impl SerializableSnapshotIsolation {
 
     // Increment the global timestamp for the system.
     pub(crate) fn increment_ts(&self) {
-        let mut commit_info = self.commit_tracker.lock();
-        commit_info.next_ts += 1;
+        let _commit_tracker = self.commit_tracker.lock();
+        self.next_ts.fetch_add(1, Ordering::AcqRel);
     }
 }
 
 /// `WaterMark` is a synchronization mechanism for managing transaction timestamps.
 struct WaterMark {
     mark: RwLock<WaterMarkState>, // Keeps track of waiters for specific timestamps.
+    done_upto: AtomicU64,         // Mirrors `done_upto` of the state, for waits without a lock.
 }
 
 struct WaterMarkState {
@@ -442,6 +693,7 @@ impl WaterMark {
     fn new() -> Self {
         WaterMark {
             mark: RwLock::new(WaterMarkState::new()),
+            done_upto: AtomicU64::new(0),
         }
     }
 
@@ -454,33 +706,30 @@ impl WaterMark {
             return;
         }
 
-        for i in (done_upto + 1)..=t {
-            if let Some(wp) = mark.waiters.get(&i) {
+        // The timestamps may skip versions given to `Transaction::commit_at`, so the waiters
+        // are walked rather than the timestamps.
+        mark.waiters.retain(|ts, wp| {
+            if *ts > done_upto && *ts <= t {
                 wp.take();
-                mark.waiters.remove(&i);
+                return false;
             }
-        }
+            true
+        });
 
         mark.done_upto = t;
+        self.done_upto.store(t, Ordering::Release);
     }
 
     /// Waits for transactions to be done up to the specified timestamp.
     fn wait_for(&self, t: u64) {
-        let mark = self.mark.read();
-        if mark.done_upto >= t {
+        if self.done_upto.load(Ordering::Acquire) >= t {
             return;
         }
-        let should_insert = !mark.waiters.contains_key(&t);
-        drop(mark);
-
-        if should_insert {
-            let mut mark = self.mark.write();
-            mark.waiters.entry(t).or_insert_with(Mark::new);
-            drop(mark);
+        let mut mark = self.mark.write();
+        if mark.done_upto >= t {
+            return;
         }
-
-        let mark = self.mark.read(); // Re-acquire the read lock.
-        let wp = mark.waiters.get(&t).unwrap().clone();
+        let wp = mark.waiters.entry(t).or_insert_with(Mark::new).clone();
         drop(mark);
         matches!(wp.closer.recv(), Err(crossbeam_channel::RecvError));
     }
@@ -526,4 +775,121 @@ mod tests {
         // Now, wait for timestamp 1 in the main thread.
         hub.wait_for(10);
     }
+
+    fn ssi_store(temp_dir: &tempdir::TempDir) -> crate::Store {
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.isolation_level = IsolationLevelOption::SerializableSnapshotIsolation;
+        crate::Store::new(opts).expect("should create store")
+    }
+
+    #[tokio::test]
+    async fn read_only_begin_takes_no_lock() {
+        let temp_dir = tempdir::TempDir::new("test").unwrap();
+        let store = ssi_store(&temp_dir);
+        let mut txn = store.begin().unwrap();
+        txn.set(b"key", b"value").unwrap();
+        txn.commit().await.unwrap();
+
+        // A read-only transaction begins while a commit holds the commit tracker and a
+        // writer reads the index, and sees the last commit.
+        let core = store.inner.as_ref().unwrap().core.clone();
+        let IsolationLevel::SerializableSnapshotIsolation(oracle) = &core.oracle.isolation else {
+            panic!("expected serializable snapshot isolation");
+        };
+        {
+            let _commit_tracker = oracle.commit_tracker.lock();
+            let _index = core.indexer.read();
+            let txn = store
+                .begin_with_mode(crate::storage::kv::transaction::Mode::ReadOnly)
+                .unwrap();
+            assert_eq!(txn.get(b"key").unwrap().unwrap(), b"value");
+            assert!(oracle.read_mark.read().is_empty());
+        }
+        store.close().await.unwrap();
+    }
+
+    #[test]
+    fn concurrent_read_only_begins() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let _guard = rt.enter();
+        let temp_dir = tempdir::TempDir::new("test").unwrap();
+        let store = ssi_store(&temp_dir);
+        let mut txn = store.begin().unwrap();
+        txn.set(b"counter", &0u64.to_be_bytes()).unwrap();
+        rt.block_on(txn.commit()).unwrap();
+
+        let done = std::sync::atomic::AtomicBool::new(false);
+        thread::scope(|s| {
+            let readers: Vec<_> = (0..4)
+                .map(|_| {
+                    s.spawn(|| {
+                        // Each transaction sees a counter at least as high as the previous one.
+                        let mut last = 0;
+                        while !done.load(Ordering::Acquire) {
+                            let txn = store
+                                .begin_with_mode(crate::storage::kv::transaction::Mode::ReadOnly)
+                                .unwrap();
+                            let value = txn.get(b"counter").unwrap().unwrap();
+                            let counter = u64::from_be_bytes(value.try_into().unwrap());
+                            assert!(counter >= last);
+                            last = counter;
+                        }
+                        last
+                    })
+                })
+                .collect();
+
+            for i in 1..=100u64 {
+                let mut txn = store.begin().unwrap();
+                txn.set(b"counter", &i.to_be_bytes()).unwrap();
+                rt.block_on(txn.commit()).unwrap();
+            }
+            done.store(true, Ordering::Release);
+            for reader in readers {
+                assert!(reader.join().unwrap() <= 100);
+            }
+        });
+        rt.block_on(store.close()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn snapshot_isolation_checks_transactions_in_flight() {
+        let temp_dir = tempdir::TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.isolation_level = IsolationLevelOption::SnapshotIsolation;
+        let store = crate::Store::new(opts).expect("should create store");
+        let mut txn = store.begin().unwrap();
+        txn.set(b"key", b"value").unwrap();
+        txn.commit().await.unwrap();
+
+        // A transaction writing the key is sent to the writer, and not in the index yet.
+        let core = store.inner.as_ref().unwrap().core.clone();
+        let version = core.oracle.read_ts() + 1;
+        core.oracle
+            .sent(version, [Bytes::from_static(b"key")].into_iter().collect());
+
+        let mut txn = store.begin().unwrap();
+        txn.get(b"key").unwrap();
+        txn.set(b"other", b"value").unwrap();
+        assert!(matches!(
+            txn.commit().await,
+            Err(Error::TransactionReadConflict)
+        ));
+
+        // The transactions that did not read the key commit.
+        let mut txn = store.begin().unwrap();
+        txn.get(b"unrelated").unwrap();
+        txn.set(b"other", b"value").unwrap();
+        txn.commit().await.unwrap();
+
+        // Once it is in the index, the reads are checked against the index only.
+        core.oracle.indexed(version);
+        let mut txn = store.begin().unwrap();
+        txn.get(b"key").unwrap();
+        txn.set(b"other", b"new").unwrap();
+        txn.commit().await.unwrap();
+        store.close().await.unwrap();
+    }
 }
@@ -0,0 +1,123 @@
+use std::ops::RangeBounds;
+use std::sync::Arc;
+
+use crate::storage::kv::{
+    error::Result,
+    store::Core,
+    transaction::{Mode, Transaction},
+};
+
+/// Returns the keys splitting the live keys in the range into at most `parts` partitions of
+/// roughly equal size, in key order. The size of a key is the length of the key and of its
+/// value, so that for records of a similar size the partitions hold similar numbers of keys.
+///
+/// Each split key is the first key of a partition: with split keys `s1..sn`, the partitions
+/// are `[start, s1)`, `[s1, s2)`, ..., `[sn, end)`. Both walks over the keys happen at the
+/// same snapshot, and the values are not read.
+pub(crate) fn partition_range<'a, R>(
+    core: &Arc<Core>,
+    range: R,
+    parts: usize,
+) -> Result<Vec<Vec<u8>>>
+where
+    R: RangeBounds<&'a [u8]> + Clone,
+{
+    let mut splits = Vec::new();
+    if parts < 2 {
+        return Ok(splits);
+    }
+
+    let txn = Transaction::new(core.clone(), Mode::ReadOnly)?;
+
+    // Measure the range first.
+    let mut total = 0u64;
+    txn.walk_keys(range.clone(), |key, value_len| {
+        total += (key.len() + value_len) as u64;
+        true
+    })?;
+
+    // Then start a new partition at the key whose middle is the closest past the next
+    // fraction of the total (the sizes are doubled to keep to integers).
+    let mut walked = 0u64;
+    txn.walk_keys(range, |key, value_len| {
+        let size = (key.len() + value_len) as u64;
+        let next = splits.len() as u64 + 1;
+        if walked > 0 && 2 * walked + size >= 2 * total * next / parts as u64 {
+            splits.push(key.to_vec());
+        }
+        walked += size;
+        splits.len() + 1 < parts
+    })?;
+
+    Ok(splits)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+
+    use tempdir::TempDir;
+
+    fn create_store() -> (Store, TempDir) {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        (Store::new(opts).expect("should create store"), temp_dir)
+    }
+
+    #[tokio::test]
+    async fn partition_by_keys() {
+        let (store, _temp_dir) = create_store();
+        for i in 0..100u32 {
+            let mut txn = store.begin().unwrap();
+            txn.set(&i.to_be_bytes(), b"value").unwrap();
+            txn.commit().await.unwrap();
+        }
+
+        let splits = store.partition_range(.., 4).unwrap();
+        assert_eq!(
+            splits,
+            vec![
+                25u32.to_be_bytes().to_vec(),
+                50u32.to_be_bytes().to_vec(),
+                75u32.to_be_bytes().to_vec(),
+            ]
+        );
+
+        // Every partition is scanned exactly once.
+        let txn = store.begin().unwrap();
+        let mut bounds: Vec<&[u8]> = vec![&[]];
+        bounds.extend(splits.iter().map(|k| &k[..]));
+        let mut scanned = 0;
+        for (i, start) in bounds.iter().enumerate() {
+            scanned += match bounds.get(i + 1) {
+                Some(end) => txn.scan(*start..*end, None).unwrap().len(),
+                None => txn.scan(*start.., None).unwrap().len(),
+            };
+        }
+        assert_eq!(scanned, 100);
+
+        assert!(store.partition_range(.., 1).unwrap().is_empty());
+        let start = 0u32.to_be_bytes();
+        let end = 2u32.to_be_bytes();
+        assert_eq!(
+            store.partition_range(&start[..]..&end[..], 10).unwrap(),
+            vec![1u32.to_be_bytes().to_vec()]
+        );
+    }
+
+    #[tokio::test]
+    async fn partition_by_bytes() {
+        let (store, _temp_dir) = create_store();
+        let mut txn = store.begin().unwrap();
+        txn.set(b"a", &[0; 1000]).unwrap();
+        for i in 0..10u8 {
+            txn.set(&[b'b', i], &[0; 100]).unwrap();
+        }
+        txn.commit().await.unwrap();
+
+        // The large value makes up its own partition.
+        assert_eq!(store.partition_range(.., 2).unwrap(), vec![vec![b'b', 0]]);
+    }
+}
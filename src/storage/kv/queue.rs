@@ -0,0 +1,314 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::storage::kv::{
+    error::{Error, Result},
+    store::Core,
+    transaction::{Mode, Transaction},
+    util::{now, system_key},
+};
+
+/// Subsystem prefix of the keys holding the items of a queue.
+const QUEUE_ITEM_SUBSYSTEM: &[u8] = b"queue/i/";
+
+/// Number of times a claim is retried when another claimer takes the same item first.
+const MAX_CLAIM_ATTEMPTS: usize = 8;
+
+/// Size of the header stored in front of the payload of an item:
+/// [visible_at: u64][token: u64][attempts: u32]
+const ITEM_HEADER_SIZE: usize = 20;
+
+/// An item claimed from a queue. The item stays invisible to other claimers until its lease
+/// expires, and must then be acknowledged with [`Store::ack`](crate::Store::ack) or released
+/// with [`Store::nack`](crate::Store::nack).
+#[derive(Clone, Debug)]
+pub struct Claim {
+    pub payload: Vec<u8>, // Payload of the item.
+    pub attempts: u32,    // Number of times the item has been claimed, including this claim.
+    key: Bytes,           // Key of the item in the store.
+    token: u64,           // Token identifying this claim of the item.
+}
+
+/// The state of a queue item as stored in the value.
+struct Item {
+    visible_at: u64,
+    token: u64,
+    attempts: u32,
+    payload: Vec<u8>,
+}
+
+impl Item {
+    fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(ITEM_HEADER_SIZE + self.payload.len());
+        buf.put_u64(self.visible_at);
+        buf.put_u64(self.token);
+        buf.put_u32(self.attempts);
+        buf.put(self.payload.as_slice());
+        buf.freeze()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < ITEM_HEADER_SIZE {
+            return Err(Error::CorruptedIndex);
+        }
+        let u64_at = |i: usize| u64::from_be_bytes(bytes[i..i + 8].try_into().unwrap());
+        Ok(Self {
+            visible_at: u64_at(0),
+            token: u64_at(8),
+            attempts: u32::from_be_bytes(bytes[16..20].try_into().unwrap()),
+            payload: bytes[ITEM_HEADER_SIZE..].to_vec(),
+        })
+    }
+}
+
+/// `Queues` implements durable queues on top of transactions.
+///
+/// Items are stored under keys ordered by enqueue time, so queues are FIFO. Claiming an item
+/// hides it for the duration of a lease by moving its visibility timestamp forward; if the
+/// claimer neither acknowledges nor releases the item before the lease expires, it becomes
+/// visible again and can be claimed by someone else. Claims go through optimistic concurrency
+/// control, so two claimers can never hold the same item at the same time.
+pub(crate) struct Queues {
+    /// Counter disambiguating items enqueued within the same nanosecond.
+    counter: AtomicU64,
+}
+
+impl Queues {
+    pub(crate) fn new() -> Self {
+        Self {
+            counter: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the key of an item: [system prefix][queue/i/][name_len: u16][name][ts: u64][counter: u64]
+    fn item_key(&self, queue: &[u8]) -> Result<Bytes> {
+        let mut key = system_key(QUEUE_ITEM_SUBSYSTEM, queue)?;
+        key.put_u64(now());
+        key.put_u64(self.counter.fetch_add(1, Ordering::Relaxed));
+        Ok(key.freeze())
+    }
+
+    /// Adds an item to the end of the queue.
+    pub(crate) async fn enqueue(
+        &self,
+        core: &Arc<Core>,
+        queue: &[u8],
+        payload: &[u8],
+    ) -> Result<()> {
+        let item = Item {
+            visible_at: 0,
+            token: 0,
+            attempts: 0,
+            payload: payload.to_vec(),
+        };
+
+        let mut txn = Transaction::new(core.clone(), Mode::WriteOnly)?;
+        txn.set_system(&self.item_key(queue)?, &item.encode())?;
+        txn.commit().await
+    }
+
+    /// Claims the oldest visible item of the queue for the duration of the lease.
+    /// Returns `None` if no item is visible.
+    pub(crate) async fn claim(
+        core: &Arc<Core>,
+        queue: &[u8],
+        lease: Duration,
+    ) -> Result<Option<Claim>> {
+        let mut start = system_key(QUEUE_ITEM_SUBSYSTEM, queue)?;
+        let mut end = start.clone();
+        start.put_u64(0);
+        end.put_u64(u64::MAX);
+        end.put_u64(u64::MAX);
+
+        for _ in 0..MAX_CLAIM_ATTEMPTS {
+            // Find a candidate without registering the whole queue in the read set, so
+            // that concurrent claims only conflict when they race for the same item.
+            let ts = now();
+            let candidate = {
+                let txn = Transaction::new(core.clone(), Mode::ReadOnly)?;
+                let mut candidate = None;
+                for (key, value, _, _) in txn.scan(&start[..]..=&end[..], None)? {
+                    if Item::decode(&value)?.visible_at <= ts {
+                        candidate = Some(key);
+                        break;
+                    }
+                }
+                candidate
+            };
+
+            let key = match candidate {
+                Some(key) => key,
+                None => return Ok(None),
+            };
+
+            let mut txn = Transaction::new(core.clone(), Mode::ReadWrite)?;
+            let mut item = match txn.get(&key)? {
+                Some(value) => Item::decode(&value)?,
+                None => continue,
+            };
+            let ts = now();
+            if item.visible_at > ts {
+                continue;
+            }
+
+            item.visible_at = ts.saturating_add(lease.as_nanos() as u64);
+            item.token = ts;
+            item.attempts += 1;
+            txn.set_system(&key, &item.encode())?;
+
+            match txn.commit().await {
+                Ok(()) => {
+                    return Ok(Some(Claim {
+                        payload: item.payload,
+                        attempts: item.attempts,
+                        key: Bytes::from(key),
+                        token: item.token,
+                    }))
+                }
+                Err(Error::TransactionReadConflict) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(Error::TransactionReadConflict)
+    }
+
+    /// Loads the item of a claim, checking the claim still holds it.
+    fn load_claimed(txn: &Transaction, claim: &Claim) -> Result<Item> {
+        match txn.get(&claim.key)? {
+            Some(value) => {
+                let item = Item::decode(&value)?;
+                if item.token != claim.token {
+                    return Err(Error::ClaimLost);
+                }
+                Ok(item)
+            }
+            None => Err(Error::ClaimLost),
+        }
+    }
+
+    /// Removes a claimed item from the queue.
+    pub(crate) async fn ack(core: &Arc<Core>, claim: &Claim) -> Result<()> {
+        let mut txn = Transaction::new(core.clone(), Mode::ReadWrite)?;
+        Self::load_claimed(&txn, claim)?;
+        txn.delete_system(&claim.key)?;
+        txn.commit().await
+    }
+
+    /// Releases a claimed item, making it visible again after the given delay.
+    pub(crate) async fn nack(core: &Arc<Core>, claim: &Claim, delay: Duration) -> Result<()> {
+        let mut txn = Transaction::new(core.clone(), Mode::ReadWrite)?;
+        let mut item = Self::load_claimed(&txn, claim)?;
+        item.visible_at = now().saturating_add(delay.as_nanos() as u64);
+        item.token = 0;
+        txn.set_system(&claim.key, &item.encode())?;
+        txn.commit().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::storage::kv::error::Error;
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+
+    use tempdir::TempDir;
+
+    fn create_store() -> (Store, TempDir) {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        (Store::new(opts).expect("should create store"), temp_dir)
+    }
+
+    #[tokio::test]
+    async fn enqueue_claim_ack() {
+        let (store, _temp_dir) = create_store();
+        let lease = Duration::from_secs(60);
+
+        store.enqueue(b"jobs", b"a").await.unwrap();
+        store.enqueue(b"jobs", b"b").await.unwrap();
+
+        let first = store.claim(b"jobs", lease).await.unwrap().unwrap();
+        let second = store.claim(b"jobs", lease).await.unwrap().unwrap();
+        assert_eq!(first.payload, b"a");
+        assert_eq!(second.payload, b"b");
+        assert_eq!(first.attempts, 1);
+        assert!(store.claim(b"jobs", lease).await.unwrap().is_none());
+
+        store.ack(&first).await.unwrap();
+        assert!(matches!(store.ack(&first).await, Err(Error::ClaimLost)));
+
+        // Releasing the item makes it claimable again.
+        store.nack(&second, Duration::ZERO).await.unwrap();
+        let again = store.claim(b"jobs", lease).await.unwrap().unwrap();
+        assert_eq!(again.payload, b"b");
+        assert_eq!(again.attempts, 2);
+        assert!(matches!(store.ack(&second).await, Err(Error::ClaimLost)));
+        store.ack(&again).await.unwrap();
+
+        assert!(store.claim(b"jobs", lease).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn expired_lease_is_reclaimed() {
+        let (store, _temp_dir) = create_store();
+
+        store.enqueue(b"jobs", b"a").await.unwrap();
+        let first = store
+            .claim(b"jobs", Duration::from_millis(1))
+            .await
+            .unwrap()
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+
+        let second = store
+            .claim(b"jobs", Duration::from_secs(60))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(second.payload, b"a");
+        assert_eq!(second.attempts, 2);
+
+        // The first claimer lost the item when its lease expired and it was reclaimed.
+        assert!(matches!(store.ack(&first).await, Err(Error::ClaimLost)));
+        store.ack(&second).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn concurrent_claims_are_exclusive() {
+        let (store, _temp_dir) = create_store();
+        let store = Arc::new(store);
+
+        for i in 0..20u32 {
+            store.enqueue(b"jobs", &i.to_be_bytes()).await.unwrap();
+        }
+
+        let mut handles = Vec::new();
+        for _ in 0..4 {
+            let store = store.clone();
+            handles.push(tokio::spawn(async move {
+                let mut claimed = Vec::new();
+                while let Ok(Some(claim)) = store.claim(b"jobs", Duration::from_secs(60)).await {
+                    claimed.push(claim.payload.clone());
+                    store.ack(&claim).await.unwrap();
+                }
+                claimed
+            }));
+        }
+
+        let mut all = Vec::new();
+        for handle in handles {
+            all.extend(handle.await.unwrap());
+        }
+        all.sort();
+        let expected: Vec<Vec<u8>> = (0..20u32).map(|i| i.to_be_bytes().to_vec()).collect();
+        assert_eq!(all, expected);
+    }
+}
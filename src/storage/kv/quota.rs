@@ -0,0 +1,329 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+
+use crate::storage::{
+    kv::{
+        error::{Error, Result},
+        events::{self, Level, Value},
+        headroom::RESERVE_FILE,
+        option::Options,
+    },
+    vfs::{SharedVfs, Vfs},
+};
+
+/// Hook invoked when a commit is refused because the store would outgrow
+/// `Options::max_db_size`, with the size the files of the store would reach and the quota, or
+/// when the store grows past `Options::soft_db_size`, with its size and the soft limit.
+pub type QuotaHook = Arc<dyn Fn(u64, u64) + Send + Sync>;
+
+/// `Quota` enforces `Options::max_db_size` on the commits written to disk, and alerts once
+/// the store grows past `Options::soft_db_size`.
+///
+/// The size of the files of the store is measured when the store is opened, and again
+/// whenever the commit log moves to a new segment; the records appended in between are
+/// added to it. The other files written meanwhile, such as the index checkpoints, are only
+/// accounted for at the next measure, so the quota can be overrun by their size.
+pub(crate) struct Quota {
+    max_size: u64,
+    soft_size: u64,
+    vfs: SharedVfs,
+    dir: PathBuf,
+    size: AtomicU64,
+    segment_id: AtomicU64, // Segment of the commit log the last record was appended to.
+    hook: RwLock<Option<QuotaHook>>,
+    soft_hook: RwLock<Option<QuotaHook>>, // Invoked when the store grows past the soft limit.
+    alerted: AtomicBool, // Set while the store is past the soft limit, once the hook is invoked.
+}
+
+impl Quota {
+    pub(crate) fn new(opts: &Options) -> Result<Self> {
+        let (max_size, soft_size) = if opts.should_persist_data() {
+            (opts.max_db_size, opts.soft_db_size)
+        } else {
+            (0, 0)
+        };
+        let size = match (max_size, soft_size) {
+            (0, 0) => 0,
+            _ => dir_size(&*opts.vfs, &opts.dir)?,
+        };
+        Ok(Self {
+            max_size,
+            soft_size,
+            vfs: opts.vfs.clone(),
+            dir: opts.dir.clone(),
+            size: AtomicU64::new(size),
+            segment_id: AtomicU64::new(0),
+            hook: RwLock::new(None),
+            soft_hook: RwLock::new(None),
+            alerted: AtomicBool::new(false),
+        })
+    }
+
+    /// Returns true if the size of the store is tracked, for either limit.
+    fn enabled(&self) -> bool {
+        self.max_size > 0 || self.soft_size > 0
+    }
+
+    pub(crate) fn set_hook(&self, hook: Option<QuotaHook>) {
+        *self.hook.write() = hook;
+    }
+
+    pub(crate) fn hook(&self) -> Option<QuotaHook> {
+        self.hook.read().clone()
+    }
+
+    pub(crate) fn set_soft_hook(&self, hook: Option<QuotaHook>) {
+        *self.soft_hook.write() = hook;
+    }
+
+    pub(crate) fn soft_hook(&self) -> Option<QuotaHook> {
+        self.soft_hook.read().clone()
+    }
+
+    /// Returns `Error::QuotaExceeded` if appending `len` bytes to the commit log would grow
+    /// the store past the quota, after invoking the hook.
+    pub(crate) fn check(&self, len: usize) -> Result<()> {
+        if self.max_size == 0 {
+            return Ok(());
+        }
+        let size = self.size.load(Ordering::Acquire) + len as u64;
+        if size <= self.max_size {
+            return Ok(());
+        }
+
+        events::emit(
+            events::WRITER,
+            Level::Warn,
+            "commit refused, the store would exceed its maximum size",
+            &[
+                ("size", Value::U64(size)),
+                ("max_db_size", Value::U64(self.max_size)),
+            ],
+        );
+        let hook = self.hook();
+        if let Some(hook) = hook {
+            hook(size, self.max_size);
+        }
+        Err(Error::QuotaExceeded(size, self.max_size))
+    }
+
+    /// Measures the files of the store again, after some were removed.
+    pub(crate) fn measure(&self) -> Result<()> {
+        if self.enabled() {
+            let size = dir_size(&*self.vfs, &self.dir)?;
+            self.size.store(size, Ordering::Release);
+            self.alert(size);
+        }
+        Ok(())
+    }
+
+    /// Accounts for `len` bytes appended to the commit log at `offset`, measuring the files
+    /// of the store again if the commit log moved to a new segment. The record is already
+    /// written, so a failed measure only falls back to adding its length.
+    pub(crate) fn appended(&self, len: usize, offset: u64, segment_size: u64) {
+        if !self.enabled() {
+            return;
+        }
+        let segment_id = offset / segment_size;
+        let measured = match self.segment_id.swap(segment_id, Ordering::AcqRel) {
+            previous if previous != segment_id => dir_size(&*self.vfs, &self.dir).ok(),
+            _ => None,
+        };
+        let size = match measured {
+            Some(size) => {
+                self.size.store(size, Ordering::Release);
+                size
+            }
+            None => self.size.fetch_add(len as u64, Ordering::AcqRel) + len as u64,
+        };
+        self.alert(size);
+    }
+
+    /// Invokes the soft limit hook if the store grew past `Options::soft_db_size`, once until
+    /// it is measured below it again.
+    fn alert(&self, size: u64) {
+        if self.soft_size == 0 {
+            return;
+        }
+        if size <= self.soft_size {
+            self.alerted.store(false, Ordering::Release);
+            return;
+        }
+        if self.alerted.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        events::emit(
+            events::WRITER,
+            Level::Warn,
+            "the store grew past its soft size limit",
+            &[
+                ("size", Value::U64(size)),
+                ("soft_db_size", Value::U64(self.soft_size)),
+            ],
+        );
+        let hook = self.soft_hook();
+        if let Some(hook) = hook {
+            hook(size, self.soft_size);
+        }
+    }
+}
+
+/// Returns the total size of the files in `dir` and its subdirectories, except for the file
+/// of the reserved space.
+pub(crate) fn dir_size(vfs: &dyn Vfs, dir: &Path) -> Result<u64> {
+    let mut size = 0;
+    let entries = match vfs.read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    for entry in entries {
+        if entry.file_name() == RESERVE_FILE {
+            continue;
+        }
+        let metadata = entry.metadata();
+        size += if metadata.is_dir() {
+            dir_size(vfs, entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    use parking_lot::Mutex;
+
+    use crate::storage::kv::error::Error;
+    use crate::storage::kv::option::{IsolationLevel, Options};
+    use crate::storage::kv::store::Store;
+
+    use tempdir::TempDir;
+
+    #[tokio::test]
+    async fn commits_past_the_quota_are_refused() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.max_db_size = 64 * 1024;
+        let store = Store::new(opts.clone()).expect("should create store");
+        let refused = Arc::new(Mutex::new(Vec::new()));
+        let calls = refused.clone();
+        store.set_quota_hook(move |size, max| calls.lock().push((size, max)));
+
+        let value = vec![0u8; 1024];
+        let mut written = 0u32;
+        let err = loop {
+            let mut txn = store.begin().unwrap();
+            txn.set(&written.to_be_bytes(), &value).unwrap();
+            match txn.commit().await {
+                Ok(()) => written += 1,
+                Err(err) => break err,
+            }
+        };
+        assert!(matches!(err, Error::QuotaExceeded(size, 65536) if size > 65536));
+        assert!(written > 0 && written < 64);
+        assert_eq!(refused.lock().len(), 1);
+
+        // The refused commit left the store as it was.
+        let txn = store.begin().unwrap();
+        assert!(txn.get(&written.to_be_bytes()).unwrap().is_none());
+        assert_eq!(txn.scan(.., None).unwrap().len(), written as usize);
+        drop(txn);
+        store.close().await.unwrap();
+
+        // The size of the store is measured again when it is opened.
+        let store = Store::new(opts.clone()).expect("should open store");
+        let mut txn = store.begin().unwrap();
+        txn.set(b"big", &value).unwrap();
+        assert!(matches!(txn.commit().await, Err(Error::QuotaExceeded(..))));
+        store.close().await.unwrap();
+
+        // Raising the quota lets the store grow again.
+        opts.max_db_size = 0;
+        let store = Store::new(opts).expect("should open store");
+        let mut txn = store.begin().unwrap();
+        txn.set(b"big", &value).unwrap();
+        txn.commit().await.unwrap();
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_refused_commit_can_be_retried() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.max_db_size = 64 * 1024;
+        opts.isolation_level = IsolationLevel::SerializableSnapshotIsolation;
+        let store = Store::new(opts.clone()).expect("should create store");
+        let core = store.inner.as_ref().unwrap().core.clone();
+
+        let mut txn = store.begin().unwrap();
+        txn.set(b"key", b"1").unwrap();
+        txn.commit().await.unwrap();
+
+        // A commit refused as the store is full leaves the transaction open.
+        core.quota.size.store(opts.max_db_size, Ordering::Release);
+        let mut txn = store.begin().unwrap();
+        assert_eq!(txn.get(b"key").unwrap().unwrap(), b"1");
+        txn.set(b"key", b"2").unwrap();
+        txn.incr(b"hits", 1).unwrap();
+        assert!(matches!(txn.commit().await, Err(Error::QuotaExceeded(..))));
+
+        // It commits once space is freed, as it does not conflict with itself.
+        core.quota.size.store(0, Ordering::Release);
+        txn.commit().await.unwrap();
+        let txn = store.begin().unwrap();
+        assert_eq!(txn.get(b"key").unwrap().unwrap(), b"2");
+        assert_eq!(txn.get(b"hits").unwrap().unwrap(), 1u64.to_be_bytes());
+        drop(txn);
+
+        // A retried commit is checked for conflicts again.
+        core.quota.size.store(opts.max_db_size, Ordering::Release);
+        let mut txn = store.begin().unwrap();
+        txn.get(b"key").unwrap().unwrap();
+        txn.set(b"key", b"3").unwrap();
+        assert!(matches!(txn.commit().await, Err(Error::QuotaExceeded(..))));
+        core.quota.size.store(0, Ordering::Release);
+        let mut other = store.begin().unwrap();
+        other.set(b"key", b"4").unwrap();
+        other.commit().await.unwrap();
+        assert!(matches!(
+            txn.commit().await,
+            Err(Error::TransactionReadConflict)
+        ));
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn the_soft_limit_alerts_once() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.soft_db_size = 16 * 1024;
+        let store = Store::new(opts).expect("should create store");
+        let alerts = Arc::new(Mutex::new(Vec::new()));
+        let calls = alerts.clone();
+        store.set_soft_quota_hook(move |size, soft| calls.lock().push((size, soft)));
+
+        // The commits past the soft limit are written, and the hook is invoked once.
+        let value = vec![0u8; 1024];
+        for i in 0..32u32 {
+            let mut txn = store.begin().unwrap();
+            txn.set(&i.to_be_bytes(), &value).unwrap();
+            txn.commit().await.unwrap();
+        }
+        let calls = alerts.lock().clone();
+        assert_eq!(calls.len(), 1);
+        assert!(matches!(calls[0], (size, 16384) if size > 16384));
+        store.close().await.unwrap();
+    }
+}
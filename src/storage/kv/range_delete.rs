@@ -0,0 +1,267 @@
+use std::collections::BTreeSet;
+use std::ops::{Bound, RangeBounds};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use hashbrown::HashSet;
+
+use crate::storage::kv::{
+    entry::Entry,
+    error::{Error, Result},
+    indexer::Indexer,
+    meta::Metadata,
+    util::{is_system_key, sha256, system_key},
+};
+
+/// Subsystem of the system keys of the range deletes, see `util::system_key`.
+const RANGE_DELETES: &[u8] = b"range";
+
+/// A range of keys, with owned bounds.
+pub(crate) type KeyRange = (Bound<Vec<u8>>, Bound<Vec<u8>>);
+
+/// The key, metadata and value of an entry, as written to the commit log.
+pub(crate) type Fields<'a> = (&'a Bytes, Option<&'a Metadata>, &'a Bytes);
+
+/// Returns the entry deleting the keys of a range, written by `Transaction::delete_range`.
+///
+/// A range delete is written to the commit log as this one entry, whatever the number of keys
+/// it deletes. Its value is the range, as [start][end], each bound encoded as:
+/// [kind: u8 (0: unbounded, 1: included, 2: excluded)][key_len: u32][key]
+/// Its key is a system key derived from the range, so that deleting a range twice in a
+/// transaction writes one entry, and that it never collides with the keys written along it.
+///
+/// It is indexed as a tombstone for each key of the range that is live when it is committed,
+/// or replayed, see `expand`.
+pub(crate) fn entry<'a, R>(range: &R) -> Result<Entry>
+where
+    R: RangeBounds<&'a [u8]>,
+{
+    let mut value = BytesMut::new();
+    for bound in [range.start_bound(), range.end_bound()] {
+        let (kind, key): (u8, &[u8]) = match bound {
+            Bound::Unbounded => (0, &[]),
+            Bound::Included(key) => (1, key),
+            Bound::Excluded(key) => (2, key),
+        };
+        value.put_u8(kind);
+        value.put_u32(key.len() as u32);
+        value.put(key);
+    }
+    let value = value.freeze();
+
+    let key = system_key(RANGE_DELETES, &sha256(value.clone()))?;
+    let mut entry = Entry::new(&key, &value);
+    entry.mark_range_delete();
+    Ok(entry)
+}
+
+/// Decodes the range of a range delete entry.
+pub(crate) fn decode_range(mut value: &[u8]) -> Result<KeyRange> {
+    let mut bound = || {
+        if value.len() < 5 {
+            return Err(invalid());
+        }
+        let kind = value.get_u8();
+        let len = value.get_u32() as usize;
+        if value.len() < len {
+            return Err(invalid());
+        }
+        let key = value[..len].to_vec();
+        value.advance(len);
+        match kind {
+            0 => Ok(Bound::Unbounded),
+            1 => Ok(Bound::Included(key)),
+            2 => Ok(Bound::Excluded(key)),
+            _ => Err(invalid()),
+        }
+    };
+    let start = bound()?;
+    let end = bound()?;
+    Ok((start, end))
+}
+
+fn invalid() -> Error {
+    Error::CorruptedTransactionRecord("invalid range delete".to_string())
+}
+
+/// Returns the range with borrowed bounds.
+pub(crate) fn as_slices(range: &KeyRange) -> (Bound<&[u8]>, Bound<&[u8]>) {
+    fn as_slice(bound: &Bound<Vec<u8>>) -> Bound<&[u8]> {
+        match bound {
+            Bound::Included(key) => Bound::Included(key),
+            Bound::Excluded(key) => Bound::Excluded(key),
+            Bound::Unbounded => Bound::Unbounded,
+        }
+    }
+    (as_slice(&range.0), as_slice(&range.1))
+}
+
+/// Returns the range with owned bounds.
+pub(crate) fn to_owned(range: (Bound<&[u8]>, Bound<&[u8]>)) -> KeyRange {
+    fn to_owned(bound: Bound<&[u8]>) -> Bound<Vec<u8>> {
+        match bound {
+            Bound::Included(key) => Bound::Included(key.to_vec()),
+            Bound::Excluded(key) => Bound::Excluded(key.to_vec()),
+            Bound::Unbounded => Bound::Unbounded,
+        }
+    }
+    (to_owned(range.0), to_owned(range.1))
+}
+
+/// Returns true if a range delete of `range` deletes `key`. System keys are only deleted by
+/// the ranges starting inside the system keyspace, as they are only scanned then.
+pub(crate) fn covers(range: (Bound<&[u8]>, Bound<&[u8]>), key: &[u8]) -> bool {
+    let include_system_keys = match range.0 {
+        Bound::Included(start) | Bound::Excluded(start) => is_system_key(start),
+        Bound::Unbounded => false,
+    };
+    RangeBounds::<[u8]>::contains(&range, key) && (include_system_keys || !is_system_key(key))
+}
+
+/// Returns true if the metadata is the one of a range delete entry.
+pub(crate) fn is_range_delete(metadata: Option<&Metadata>) -> bool {
+    metadata.is_some_and(|md| md.range_deleted())
+}
+
+/// Returns the keys the range deletes among `entries` delete, in the latest version of the
+/// index, in key order: the live keys of their ranges, except the keys the other entries
+/// write, which are written over the range deletes.
+pub(crate) fn deleted_keys<'a, I>(indexer: &Indexer, entries: I) -> Result<Vec<Bytes>>
+where
+    I: IntoIterator<Item = Fields<'a>>,
+{
+    let (range_deletes, written): (Vec<_>, Vec<_>) = entries
+        .into_iter()
+        .partition(|(_, md, _)| is_range_delete(*md));
+    let written: HashSet<&[u8]> = written.into_iter().map(|(key, ..)| &key[..]).collect();
+
+    let mut deleted = BTreeSet::new();
+    for (_, _, value) in range_deletes {
+        let range = decode_range(value)?;
+        let range = as_slices(&range);
+        for key in indexer.live_keys(range)? {
+            if covers(range, &key) && !written.contains(&key[..]) {
+                deleted.insert(Bytes::from(key));
+            }
+        }
+    }
+    Ok(deleted.into_iter().collect())
+}
+
+/// Returns the entries indexed for `entries`, in which the range deletes are replaced by a
+/// tombstone for each key they delete as of the latest version of the index, or None if
+/// there is no range delete among them.
+///
+/// The commit log holds the range deletes: they are expanded again when it is replayed, in
+/// which case the index is at the version they were committed after, and holds the same keys.
+pub(crate) fn expand(indexer: &Indexer, entries: &[Entry]) -> Result<Option<Vec<Entry>>> {
+    let Some(range_delete) = entries.iter().find(|e| e.is_range_delete()) else {
+        return Ok(None);
+    };
+    let ts = range_delete.ts;
+
+    let mut expanded: Vec<Entry> = entries
+        .iter()
+        .filter(|e| !e.is_range_delete())
+        .cloned()
+        .collect();
+    let fields = entries
+        .iter()
+        .map(|e| (&e.key, e.metadata.as_ref(), &e.value));
+    for key in deleted_keys(indexer, fields)? {
+        let mut tombstone = Entry::new(&key, &[]);
+        tombstone.mark_delete();
+        tombstone.ts = ts;
+        expanded.push(tombstone);
+    }
+    Ok(Some(expanded))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use crate::storage::kv::error::Error;
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+
+    use tempdir::TempDir;
+
+    fn log_bytes(dir: &Path) -> u64 {
+        fs::read_dir(dir.join("clog"))
+            .unwrap()
+            .map(|entry| entry.unwrap().metadata().unwrap().len())
+            .sum()
+    }
+
+    fn keys(store: &Store) -> Vec<Vec<u8>> {
+        let txn = store.begin().unwrap();
+        let entries = txn.scan(.., None).unwrap();
+        entries.into_iter().map(|entry| entry.0).collect()
+    }
+
+    #[tokio::test]
+    async fn ranges_are_deleted_with_one_entry() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        let store = Store::new(opts.clone()).expect("should create store");
+
+        let mut txn = store.begin().unwrap();
+        for i in 0..1000u32 {
+            txn.set(format!("k{:04}", i).as_bytes(), b"1").unwrap();
+        }
+        txn.set(b"z", b"1").unwrap();
+        txn.commit().await.unwrap();
+        store.append_to_stream(b"events", b"1").await.unwrap();
+
+        // A key committed into the range after the transaction began is deleted too, and a
+        // transaction that read a deleted key conflicts.
+        let mut txn = store.begin().unwrap();
+        let mut reader = store.begin().unwrap();
+        reader.get(b"k0500").unwrap();
+        reader.set(b"other", b"1").unwrap();
+        let mut late = store.begin().unwrap();
+        late.set(b"k5000", b"1").unwrap();
+        late.commit().await.unwrap();
+
+        // The writes made before the range delete are deleted, the ones made after are kept.
+        txn.set(b"k9999", b"1").unwrap();
+        let start = log_bytes(temp_dir.path());
+        txn.delete_range(&b"k"[..]..&b"l"[..]).unwrap();
+        txn.set(b"k0001", b"2").unwrap();
+        txn.commit().await.unwrap();
+        assert!(log_bytes(temp_dir.path()) - start < 200);
+        assert!(matches!(
+            reader.commit().await,
+            Err(Error::TransactionReadConflict)
+        ));
+        assert_eq!(keys(&store), [&b"k0001"[..], b"z"]);
+
+        // The transaction reads its range deletes.
+        let mut txn = store.begin().unwrap();
+        txn.delete_range(&b"k"[..]..=&b"k0001"[..]).unwrap();
+        assert!(txn.get(b"k0001").unwrap().is_none());
+        let entries = txn.scan(.., None).unwrap();
+        assert_eq!(entries.len(), 1);
+        txn.commit().await.unwrap();
+        assert_eq!(keys(&store), [b"z"]);
+
+        // The range delete is replayed when the store is reopened, and spares the system keys.
+        let mut txn = store.begin().unwrap();
+        txn.delete_range(..).unwrap();
+        txn.commit().await.unwrap();
+        assert!(keys(&store).is_empty());
+        store.close().await.unwrap();
+
+        let store = Store::new(opts).expect("should open store");
+        assert!(keys(&store).is_empty());
+        let events = store.read_stream(b"events", ..).unwrap();
+        assert_eq!(events.len(), 1);
+        let mut txn = store.begin().unwrap();
+        txn.set(b"k0002", b"3").unwrap();
+        txn.commit().await.unwrap();
+        assert_eq!(keys(&store), [b"k0002"]);
+        store.close().await.unwrap();
+    }
+}
@@ -169,6 +169,11 @@ impl TxReader {
         }
     }
 
+    /// Returns the offset in the commit log of the next transaction record.
+    pub(crate) fn offset(&self) -> u64 {
+        self.r.offset()
+    }
+
     /// Reads the header of a transaction record.
     ///
     /// # Arguments
@@ -177,10 +182,17 @@ impl TxReader {
     pub(crate) fn read_header(&mut self, tx: &mut TxRecord) -> Result<()> {
         let id = self.r.read_uint64()?;
 
-        // Either the header is corrupted or we have reached the end of the file
-        // and encountered the padded zeros towards the end of the file.
+        // Either the header is corrupted, or the zeros a record left unfinished is filled
+        // with, see `WriteSlot`, after which nothing is appended: the tail of the log is
+        // repaired from there.
         if id == 0 {
-            return Err(Error::InvalidTransactionRecordId);
+            let (segment_id, offset) = (self.r.current_segment_id(), self.r.current_offset());
+            return Err(Error::LogError(Corruption(CorruptionError::new(
+                std::io::ErrorKind::Other,
+                Error::InvalidTransactionRecordId.to_string().as_str(),
+                segment_id,
+                offset,
+            ))));
         }
 
         tx.header.id = id;
@@ -334,10 +346,7 @@ impl TxReader {
         self.rec.clear();
 
         let mut tx = TxRecord::new(max_entries);
-        match self.read_into(&mut tx) {
-            Ok(value_offsets) => value_offsets,
-            Err(e) => return Err(e),
-        };
+        self.read_into(&mut tx)?;
 
         let rec = Self::serialize_tx_with_crc(&tx)?;
         self.rec.extend(&rec);
@@ -350,6 +359,7 @@ impl TxReader {
 mod tests {
     use super::*;
     use crate::storage::log::{aof::log::Aol, Options, SegmentRef};
+    use crate::storage::vfs::{OsVfs, SharedVfs};
     use tempdir::TempDir;
 
     fn create_temp_directory() -> TempDir {
@@ -364,7 +374,7 @@ mod tests {
         // Create aol options and open a aol file
         let mut opts = Options::default();
         opts.max_file_size = 4;
-        let mut a = Aol::open(temp_dir.path(), &opts).expect("should create aol");
+        let a = Aol::open(temp_dir.path(), &opts).expect("should create aol");
 
         // Test initial offset
         let sz = a.offset().unwrap();
@@ -382,9 +392,10 @@ mod tests {
 
         a.close().expect("should close aol");
 
-        let sr = SegmentRef::read_segments_from_directory(temp_dir.path())
+        let sr = SegmentRef::read_segments_from_directory(&OsVfs, temp_dir.path())
             .expect("should read segments");
-        let sr = MultiSegmentReader::new(sr).expect("should create segment reader");
+        let sr = MultiSegmentReader::new(&SharedVfs::default(), sr)
+            .expect("should create segment reader");
 
         let mut r = Reader::new_from(sr, 0, 200000);
 
@@ -418,7 +429,7 @@ mod tests {
         let mut opts = Options::default();
         opts.max_file_size = 40;
 
-        let mut a = Aol::open(temp_dir.path(), &opts).expect("should create aol");
+        let a = Aol::open(temp_dir.path(), &opts).expect("should create aol");
 
         // Append 10 records
         for i in 0..num_items {
@@ -429,9 +440,10 @@ mod tests {
 
         a.close().expect("should close aol");
 
-        let sr = SegmentRef::read_segments_from_directory(temp_dir.path())
+        let sr = SegmentRef::read_segments_from_directory(&OsVfs, temp_dir.path())
             .expect("should read segments");
-        let sr = MultiSegmentReader::new(sr).expect("should create segment reader");
+        let sr = MultiSegmentReader::new(&SharedVfs::default(), sr)
+            .expect("should create segment reader");
 
         let mut r = Reader::new_from(sr, 0, 200000);
 
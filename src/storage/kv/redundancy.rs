@@ -0,0 +1,539 @@
+use std::io::{self, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+
+use parking_lot::Mutex;
+
+use crate::storage::{
+    kv::{
+        error::Result,
+        events::{self, Level, Value},
+        option::{Options, SegmentRedundancy},
+        util::calculate_crc32,
+    },
+    log::segment_name,
+    vfs::{OpenOptions, SharedVfs, Vfs},
+};
+
+const REDUNDANCY_EXTENSION: &str = "redundancy";
+const SEGMENT_EXTENSION: &str = "clog";
+
+const MAGIC: &[u8; 4] = b"SKVR";
+const VERSION: u8 = 1;
+const HEADER_SIZE: usize = 4 + 1 + 1 + 8; // Magic, version, mode and length of the segment.
+
+/// Size of the blocks the segments are checked and rebuilt by.
+const BLOCK_SIZE: usize = 4096;
+/// Number of blocks covered by a parity block.
+const PARITY_GROUP: usize = 16;
+
+/// `Redundancy` writes the redundancy of the sealed segments of the commit log to
+/// `Options::redundancy_dir`, in `<id>.redundancy`, and rebuilds the damaged blocks of a
+/// segment from it when the commit log is replayed.
+///
+/// The redundancy of a segment is written on a background thread once the store moves on to
+/// the next segment, and again when the store is opened for the segments sealed before which
+/// have none, as it can be lost in a crash. The segment that is written to has none, and is
+/// repaired by truncating it as before.
+///
+/// A segment most of whose blocks differ from its redundancy is taken to be another segment
+/// with the same id, such as the redundancy of a store that was since rewritten, and is not
+/// rebuilt from it.
+pub(crate) struct Redundancy {
+    vfs: SharedVfs,
+    dir: Option<PathBuf>, // None if no redundancy is written.
+    clog_dir: PathBuf,
+    mode: SegmentRedundancy,
+    sealed: Mutex<u64>, // Segments below this id have their redundancy written or being written.
+    writers: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Redundancy {
+    pub(crate) fn new(opts: &Options) -> Result<Self> {
+        let dir = match &opts.redundancy_dir {
+            Some(dir) if opts.should_persist_data() => {
+                opts.vfs.create_dir_all(dir, None)?;
+                Some(dir.clone())
+            }
+            _ => None,
+        };
+        Ok(Self {
+            vfs: opts.vfs.clone(),
+            dir,
+            clog_dir: opts.dir.join("clog"),
+            mode: opts.segment_redundancy,
+            sealed: Mutex::new(0),
+            writers: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Returns a redundancy writing none, for a store that is not written to.
+    pub(crate) fn none() -> Self {
+        Self {
+            vfs: SharedVfs::default(),
+            dir: None,
+            clog_dir: PathBuf::new(),
+            mode: SegmentRedundancy::Checksums,
+            sealed: Mutex::new(0),
+            writers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Writes the redundancy of the segments below `segment_id` on a background thread. The
+    /// segments sealed when the store was opened, whose redundancy may have been written
+    /// already, are given it only if they have none.
+    pub(crate) fn seal_before(&self, segment_id: u64, opening: bool) {
+        let Some(dir) = &self.dir else {
+            return;
+        };
+        let ids = {
+            let mut sealed = self.sealed.lock();
+            if segment_id <= *sealed {
+                return;
+            }
+            let ids = *sealed..segment_id;
+            *sealed = segment_id;
+            ids
+        };
+
+        let (vfs, dir, clog_dir, mode) = (
+            self.vfs.clone(),
+            dir.clone(),
+            self.clog_dir.clone(),
+            self.mode,
+        );
+        let writer = thread::spawn(move || {
+            for id in ids {
+                let path = dir.join(segment_name(id, REDUNDANCY_EXTENSION));
+                if opening && vfs.exists(&path) {
+                    continue;
+                }
+                if let Err(err) = write_redundancy(&*vfs, &path, &clog_dir, mode, id) {
+                    let error = err.to_string();
+                    events::emit(
+                        events::WRITER,
+                        Level::Error,
+                        "the redundancy of a segment could not be written",
+                        &[
+                            ("segment_id", Value::U64(id)),
+                            ("error", Value::Str(&error)),
+                        ],
+                    );
+                }
+            }
+        });
+        let mut writers = self.writers.lock();
+        writers.retain(|writer| !writer.is_finished());
+        writers.push(writer);
+    }
+
+    /// Waits for the redundancy being written.
+    pub(crate) fn wait(&self) {
+        let writers = std::mem::take(&mut *self.writers.lock());
+        for writer in writers {
+            let _ = writer.join();
+        }
+    }
+
+    /// Rebuilds the damaged blocks of a segment from its redundancy, if it has any. It returns
+    /// true if a block was rebuilt.
+    pub(crate) fn restore(&self, segment_id: u64) -> Result<bool> {
+        let Some(dir) = &self.dir else {
+            return Ok(false);
+        };
+        let redundant = match self
+            .vfs
+            .read(&dir.join(segment_name(segment_id, REDUNDANCY_EXTENSION)))
+        {
+            Ok(bytes) => Redundant::decode(&bytes),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        let Some(redundant) = redundant else {
+            events::emit(
+                events::RECOVERY,
+                Level::Warn,
+                "the redundancy of a segment is damaged",
+                &[("segment_id", Value::U64(segment_id))],
+            );
+            return Ok(false);
+        };
+
+        let path = self
+            .clog_dir
+            .join(segment_name(segment_id, SEGMENT_EXTENSION));
+        let mut segment = match self.vfs.read(&path) {
+            Ok(segment) => segment,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        let damaged = redundant.damaged(&segment);
+        if damaged.is_empty() {
+            return Ok(false);
+        }
+        if damaged.len() * 4 > redundant.checksums.len() {
+            events::emit(
+                events::RECOVERY,
+                Level::Warn,
+                "the redundancy of a segment does not match it",
+                &[
+                    ("segment_id", Value::U64(segment_id)),
+                    ("damaged_blocks", Value::U64(damaged.len() as u64)),
+                ],
+            );
+            return Ok(false);
+        }
+
+        let rebuilt = redundant.rebuild(&mut segment, &damaged);
+        events::emit(
+            events::RECOVERY,
+            Level::Warn,
+            "rebuilding damaged blocks of a segment",
+            &[
+                ("segment_id", Value::U64(segment_id)),
+                ("damaged_blocks", Value::U64(damaged.len() as u64)),
+                ("rebuilt_blocks", Value::U64(rebuilt.len() as u64)),
+            ],
+        );
+        if rebuilt.is_empty() {
+            return Ok(false);
+        }
+        let mut file = self.vfs.open(&path, &OpenOptions::new().write(true))?;
+        for block in rebuilt {
+            let range = redundant.block(block);
+            file.seek(SeekFrom::Start(range.start as u64))?;
+            file.write_all(&segment[range])?;
+        }
+        file.set_len(redundant.len)?;
+        file.sync_all()?;
+        Ok(true)
+    }
+
+    /// Rebuilds the damaged blocks of all the segments that have redundancy. It returns true
+    /// if a block was rebuilt.
+    pub(crate) fn restore_all(&self) -> Result<bool> {
+        let Some(dir) = &self.dir else {
+            return Ok(false);
+        };
+        let mut restored = false;
+        for entry in self.vfs.read_dir(dir)? {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(REDUNDANCY_EXTENSION) {
+                continue;
+            }
+            let id = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| s.parse::<u64>().ok());
+            if let Some(id) = id {
+                restored |= self.restore(id)?;
+            }
+        }
+        Ok(restored)
+    }
+
+    /// Removes the redundancy of the segments below `segment_id`, once they are removed from
+    /// the commit log.
+    pub(crate) fn remove_before(&self, segment_id: u64) -> Result<()> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+        self.wait();
+        for entry in self.vfs.read_dir(dir)? {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(REDUNDANCY_EXTENSION) {
+                continue;
+            }
+            let id = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| s.parse::<u64>().ok());
+            if id.is_some_and(|id| id < segment_id) {
+                self.vfs.remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_redundancy(
+    vfs: &dyn Vfs,
+    path: &Path,
+    clog_dir: &Path,
+    mode: SegmentRedundancy,
+    id: u64,
+) -> Result<()> {
+    let segment = match vfs.read(&clog_dir.join(segment_name(id, SEGMENT_EXTENSION))) {
+        Ok(segment) => segment,
+        // The segment may have been removed since.
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let tmp = path.with_extension("tmp");
+    let mut file = vfs.open(&tmp, &OpenOptions::create_file())?;
+    file.write_all(&Redundant::build(mode, &segment).encode())?;
+    file.sync_all()?;
+    vfs.rename(&tmp, path)?;
+    Ok(())
+}
+
+/// The redundancy of a segment.
+struct Redundant {
+    mode: SegmentRedundancy,
+    len: u64, // Length of the segment.
+    checksums: Vec<u32>,
+    payload: Vec<u8>, // The parity blocks, or the copy of the segment.
+}
+
+impl Redundant {
+    fn build(mode: SegmentRedundancy, segment: &[u8]) -> Self {
+        let blocks = segment.len().div_ceil(BLOCK_SIZE);
+        let mut redundant = Self {
+            mode,
+            len: segment.len() as u64,
+            checksums: Vec::with_capacity(blocks),
+            payload: Vec::new(),
+        };
+        for block in 0..blocks {
+            let checksum = calculate_crc32(&segment[redundant.block(block)]);
+            redundant.checksums.push(checksum);
+        }
+        redundant.payload = match mode {
+            SegmentRedundancy::Checksums => Vec::new(),
+            SegmentRedundancy::Parity => {
+                let mut parity = vec![0; blocks.div_ceil(PARITY_GROUP) * BLOCK_SIZE];
+                for block in 0..blocks {
+                    let group = block / PARITY_GROUP;
+                    let parity = &mut parity[group * BLOCK_SIZE..(group + 1) * BLOCK_SIZE];
+                    xor(parity, &segment[redundant.block(block)]);
+                }
+                parity
+            }
+            SegmentRedundancy::Copy => segment.to_vec(),
+        };
+        redundant
+    }
+
+    /// Returns the range of a block in the segment.
+    fn block(&self, block: usize) -> Range<usize> {
+        let start = block * BLOCK_SIZE;
+        start..(start + BLOCK_SIZE).min(self.len as usize)
+    }
+
+    /// Returns the blocks of `segment` that differ from their checksum. The blocks past its
+    /// end, if it was truncated, are damaged.
+    fn damaged(&self, segment: &[u8]) -> Vec<usize> {
+        (0..self.checksums.len())
+            .filter(|block| {
+                let range = self.block(*block);
+                segment.get(range).map(calculate_crc32) != Some(self.checksums[*block])
+            })
+            .collect()
+    }
+
+    /// Rebuilds the damaged blocks of `segment`, which is resized to the length it was sealed
+    /// with. It returns the blocks rebuilt.
+    fn rebuild(&self, segment: &mut Vec<u8>, damaged: &[usize]) -> Vec<usize> {
+        segment.resize(self.len as usize, 0);
+        let mut rebuilt = Vec::new();
+        for &block in damaged {
+            let range = self.block(block);
+            let data = match self.mode {
+                SegmentRedundancy::Checksums => None,
+                SegmentRedundancy::Parity => {
+                    let group = block / PARITY_GROUP;
+                    let end = ((group + 1) * PARITY_GROUP).min(self.checksums.len());
+                    let members = group * PARITY_GROUP..end;
+                    // A group with another damaged block cannot be rebuilt from its parity.
+                    if damaged
+                        .iter()
+                        .any(|other| *other != block && members.contains(other))
+                    {
+                        None
+                    } else {
+                        let mut data =
+                            self.payload[group * BLOCK_SIZE..(group + 1) * BLOCK_SIZE].to_vec();
+                        for other in members.filter(|other| *other != block) {
+                            xor(&mut data, &segment[self.block(other)]);
+                        }
+                        data.truncate(range.len());
+                        Some(data)
+                    }
+                }
+                SegmentRedundancy::Copy => Some(self.payload[range.clone()].to_vec()),
+            };
+            if let Some(data) = data.filter(|data| calculate_crc32(data) == self.checksums[block]) {
+                segment[range].copy_from_slice(&data);
+                rebuilt.push(block);
+            }
+        }
+        rebuilt
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf =
+            Vec::with_capacity(HEADER_SIZE + self.checksums.len() * 4 + self.payload.len() + 4);
+        buf.extend_from_slice(MAGIC);
+        buf.push(VERSION);
+        buf.push(match self.mode {
+            SegmentRedundancy::Checksums => 0,
+            SegmentRedundancy::Parity => 1,
+            SegmentRedundancy::Copy => 2,
+        });
+        buf.extend_from_slice(&self.len.to_be_bytes());
+        for checksum in &self.checksums {
+            buf.extend_from_slice(&checksum.to_be_bytes());
+        }
+        buf.extend_from_slice(&self.payload);
+        let checksum = calculate_crc32(&buf);
+        buf.extend_from_slice(&checksum.to_be_bytes());
+        buf
+    }
+
+    /// Decodes the redundancy of a segment, or returns None if it is damaged.
+    fn decode(buf: &[u8]) -> Option<Self> {
+        let (body, checksum) = buf.split_at(buf.len().checked_sub(4)?);
+        if calculate_crc32(body).to_be_bytes() != checksum
+            || body.len() < HEADER_SIZE
+            || &body[..4] != MAGIC
+            || body[4] != VERSION
+        {
+            return None;
+        }
+        let mode = match body[5] {
+            0 => SegmentRedundancy::Checksums,
+            1 => SegmentRedundancy::Parity,
+            2 => SegmentRedundancy::Copy,
+            _ => return None,
+        };
+        let len = u64::from_be_bytes(body[6..HEADER_SIZE].try_into().ok()?);
+        let blocks = (len as usize).div_ceil(BLOCK_SIZE);
+        let checksums_end = HEADER_SIZE.checked_add(blocks.checked_mul(4)?)?;
+        let checksums = body
+            .get(HEADER_SIZE..checksums_end)?
+            .chunks_exact(4)
+            .map(|c| u32::from_be_bytes(c.try_into().unwrap()))
+            .collect();
+        let payload = body[checksums_end..].to_vec();
+        let payload_len = match mode {
+            SegmentRedundancy::Checksums => 0,
+            SegmentRedundancy::Parity => blocks.div_ceil(PARITY_GROUP) * BLOCK_SIZE,
+            SegmentRedundancy::Copy => len as usize,
+        };
+        if payload.len() != payload_len {
+            return None;
+        }
+        Some(Self {
+            mode,
+            len,
+            checksums,
+            payload,
+        })
+    }
+}
+
+fn xor(into: &mut [u8], block: &[u8]) {
+    for (byte, other) in into.iter_mut().zip(block) {
+        *byte ^= other;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{Redundant, BLOCK_SIZE};
+    use crate::storage::kv::option::{Options, SegmentRedundancy};
+    use crate::storage::kv::store::Store;
+    use crate::storage::log::segment_name;
+
+    use tempdir::TempDir;
+
+    fn segment(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn damaged_blocks_are_rebuilt() {
+        let original = segment(40 * BLOCK_SIZE + 100);
+        for mode in [SegmentRedundancy::Parity, SegmentRedundancy::Copy] {
+            let encoded = Redundant::build(mode, &original).encode();
+            let redundant = Redundant::decode(&encoded).unwrap();
+
+            // One block of the first group, two of the second, and the partial last block.
+            let mut damaged = original.clone();
+            for offset in [10, 17 * BLOCK_SIZE, 18 * BLOCK_SIZE, 40 * BLOCK_SIZE + 50] {
+                damaged[offset] ^= 0xff;
+            }
+            let blocks = redundant.damaged(&damaged);
+            assert_eq!(blocks, [0, 17, 18, 40]);
+            let rebuilt = redundant.rebuild(&mut damaged, &blocks);
+            match mode {
+                SegmentRedundancy::Parity => assert_eq!(rebuilt, [0, 40]),
+                _ => {
+                    assert_eq!(rebuilt, [0, 17, 18, 40]);
+                    assert_eq!(damaged, original);
+                }
+            }
+
+            // A truncated segment is extended again.
+            let mut truncated = original[..39 * BLOCK_SIZE].to_vec();
+            let blocks = redundant.damaged(&truncated);
+            assert_eq!(blocks, [39, 40]);
+            redundant.rebuild(&mut truncated, &blocks);
+            if mode == SegmentRedundancy::Copy {
+                assert_eq!(truncated, original);
+            }
+        }
+
+        // Damaged redundancy is not used.
+        let mut encoded = Redundant::build(SegmentRedundancy::Copy, &original).encode();
+        encoded[100] ^= 1;
+        assert!(Redundant::decode(&encoded).is_none());
+    }
+
+    #[tokio::test]
+    async fn sealed_segments_are_rebuilt_on_open() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let redundancy_dir = TempDir::new("redundancy").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.max_segment_size = 16 * BLOCK_SIZE as u64;
+        opts.redundancy_dir = Some(redundancy_dir.path().to_path_buf());
+
+        let store = Store::new(opts.clone()).expect("should create store");
+        for i in 0..400u32 {
+            let mut txn = store.begin().unwrap();
+            txn.set(&i.to_be_bytes(), &[i as u8; 500]).unwrap();
+            txn.commit().await.unwrap();
+        }
+        store.close().await.unwrap();
+        let sealed = fs::read_dir(redundancy_dir.path()).unwrap().count();
+        assert!(sealed >= 2);
+
+        let first = temp_dir.path().join("clog").join(segment_name(0, "clog"));
+        let original = fs::read(&first).unwrap();
+        let mut damaged = original.clone();
+        damaged[3 * BLOCK_SIZE + 10] ^= 0xff;
+        fs::write(&first, &damaged).unwrap();
+
+        let store = Store::new(opts.clone()).expect("should open store");
+        assert_eq!(fs::read(&first).unwrap(), original);
+        let txn = store.begin().unwrap();
+        assert_eq!(txn.scan(.., None).unwrap().len(), 400);
+        drop(txn);
+        store.close().await.unwrap();
+
+        // The checksums alone locate the damage, but the store cannot be opened. The missing
+        // redundancy is written when the store is opened.
+        opts.segment_redundancy = SegmentRedundancy::Checksums;
+        for entry in fs::read_dir(redundancy_dir.path()).unwrap() {
+            fs::remove_file(entry.unwrap().path()).unwrap();
+        }
+        let store = Store::new(opts.clone()).expect("should open store");
+        store.close().await.unwrap();
+        fs::write(&first, &damaged).unwrap();
+        assert!(Store::new(opts).is_err());
+    }
+}
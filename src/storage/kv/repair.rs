@@ -1,15 +1,19 @@
-use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
 
 use crate::storage::{
     kv::{
         error::{Error, Result},
+        events::{self, Level, Value},
         option::Options,
         reader::{Reader, TxReader},
-        util::sanitize_directory,
+        segments,
     },
-    log::{aof::log::Aol, Error as LogError, MultiSegmentReader, Segment, SegmentRef, BLOCK_SIZE},
+    log::{
+        aof::active::ActiveSegment, aof::log::Aol, AolSegment, Error as LogError,
+        MultiSegmentReader, SegmentRef, BLOCK_SIZE,
+    },
+    vfs::Vfs,
 };
 
 /// The last active segment being written to in the append-only log (AOL) is usually the WAL in database terminology.
@@ -22,12 +26,12 @@ pub(crate) fn repair_last_corrupted_segment(
     corrupted_offset_marker: u64,
 ) -> Result<()> {
     // Read the list of segments from the directory
-    let segs = SegmentRef::read_segments_from_directory(&aol.dir)?;
+    let segs = SegmentRef::read_segments_from_directory(&*aol.opts.vfs, &aol.dir)?;
 
     // Get the last segment
     let last_segment = segs
         .last()
-        .ok_or_else(|| Error::LogError(LogError::SegmentNotFound))?;
+        .ok_or(Error::LogError(LogError::SegmentNotFound))?;
 
     // Check if the last segment's ID is equal to the corrupted_segment_id
     if last_segment.id != corrupted_segment_id {
@@ -68,7 +72,7 @@ pub(crate) fn repair_corrupted_segment(
 
     {
         // Read the list of segments from the directory
-        let segs = SegmentRef::read_segments_from_directory(&aol.dir)?;
+        let segs = SegmentRef::read_segments_from_directory(&*aol.opts.vfs, &aol.dir)?;
 
         // Loop through the segments
         for s in &segs {
@@ -134,18 +138,20 @@ fn repair_segment(
     corrupted_segment_file_header_offset: u64,
 ) -> Result<()> {
     // Close the active segment if its ID matches
-    if aol.active_segment_id == corrupted_segment_id {
-        aol.active_segment.close()?;
+    if aol.active_segment_id() == corrupted_segment_id {
+        aol.active_segment.get_mut().close()?;
     }
 
     // Prepare the repaired segment path
     let repaired_segment_path = corrupted_segment_file_path.with_extension("repair");
 
     // Rename the corrupted segment to the repaired segment
-    std::fs::rename(&corrupted_segment_file_path, &repaired_segment_path)?;
+    aol.opts
+        .vfs
+        .rename(&corrupted_segment_file_path, &repaired_segment_path)?;
 
     // Open a new segment as the active segment
-    let mut new_segment: Segment<0> = Segment::open(&aol.dir, corrupted_segment_id, &aol.opts)?;
+    let mut new_segment = AolSegment::open(&aol.dir, corrupted_segment_id, &aol.opts)?;
 
     // Create a segment reader for the repaired segment
     let segments: Vec<SegmentRef> = vec![SegmentRef {
@@ -153,7 +159,7 @@ fn repair_segment(
         file_header_offset: corrupted_segment_file_header_offset,
         id: corrupted_segment_id,
     }];
-    let segment_reader = MultiSegmentReader::new(segments)?;
+    let segment_reader = MultiSegmentReader::new(&aol.opts.vfs, segments)?;
 
     // Initialize a reader for the segment
     let reader = Reader::new_from(segment_reader, aol.opts.max_file_size, BLOCK_SIZE);
@@ -174,15 +180,25 @@ fn repair_segment(
     new_segment.close()?;
 
     // Remove the repaired segment file
-    std::fs::remove_file(&repaired_segment_path)?;
+    aol.opts.vfs.remove_file(&repaired_segment_path)?;
 
     // Open the next segment and make it active
     if count == 0 {
-        println!("deleting empty file {:?}", corrupted_segment_file_path);
-        std::fs::remove_file(&corrupted_segment_file_path)?;
+        let path = corrupted_segment_file_path.display().to_string();
+        events::emit(
+            events::RECOVERY,
+            Level::Info,
+            "deleting empty repaired segment",
+            &[("path", Value::Str(&path))],
+        );
+        // Its key range goes first, for the segment not to be found missing on open.
+        let segments_dir = db_opts.dir.join("segments");
+        segments::remove_range(&*aol.opts.vfs, &segments_dir, corrupted_segment_id)?;
+        aol.opts.vfs.remove_file(&corrupted_segment_file_path)?;
     }
-    let new_segment = Segment::open(&aol.dir, aol.active_segment_id, &aol.opts)?;
-    aol.active_segment = new_segment;
+    let active_segment_id = aol.active_segment_id();
+    let new_segment = ActiveSegment::open(&aol.dir, active_segment_id, &aol.opts)?;
+    *aol.active_segment.get_mut() = new_segment;
 
     Ok(())
 }
@@ -194,38 +210,36 @@ fn repair_segment(
 // This function deletes the '.clog' file and renames the '.repair' file back to '.clog'.
 //
 // Parameters:
-// directory: A string slice that holds the path to the directory.
-pub(crate) fn restore_repair_files(directory: &str) -> std::io::Result<()> {
+// vfs: The filesystem the directory is on.
+// directory: The path to the directory.
+pub(crate) fn restore_repair_files(vfs: &dyn Vfs, directory: &Path) -> std::io::Result<()> {
     // Check if the directory exists
-    if !Path::new(directory).exists() {
+    if !vfs.exists(directory) {
         return Ok(());
     }
 
-    // Validate and sanitize the directory parameter
-    let directory = sanitize_directory(directory)?;
-
     // Read the directory
-    let entries = fs::read_dir(directory.clone())?;
+    let entries = vfs.read_dir(directory)?;
 
     // Iterate over each entry in the directory
-    for entry in entries.flatten() {
+    for entry in entries {
         // If the entry is a file
         let path = entry.path();
-        if path.is_file() {
+        if entry.metadata().is_file() {
             // Get the filename of the file
             let filename = path.file_name().unwrap().to_str().unwrap();
             // If the filename ends with '.repair'
             if let Some(stem) = filename.strip_suffix(".repair") {
                 // Construct the filename of the corresponding '.clog' file
                 let clog_filename = format!("{}.clog", stem);
-                let clog_path = Path::new(&directory).join(clog_filename);
+                let clog_path = directory.join(clog_filename);
                 // If the '.clog' file exists
-                if clog_path.exists() {
+                if vfs.exists(&clog_path) {
                     // Remove the '.clog' file
-                    fs::remove_file(&clog_path)?;
+                    vfs.remove_file(&clog_path)?;
                 }
                 // Rename the '.repair' file back to '.clog'
-                fs::rename(path, clog_path)?;
+                vfs.rename(path, &clog_path)?;
             }
         }
     }
@@ -251,8 +265,9 @@ mod tests {
     use crate::storage::kv::store::Store;
     use crate::storage::kv::transaction::Durability;
     use crate::storage::log::{read_file_header, SegmentRef};
+    use crate::storage::vfs::{OsVfs, SharedVfs};
 
-    use bytes::Bytes;
+    use bytes::{BufMut, Bytes};
     use tempdir::TempDir;
 
     use std::process::Command;
@@ -268,10 +283,7 @@ mod tests {
             // Subtract 1 for the header line
             Ok(if count > 0 { count - 1 } else { 0 })
         } else {
-            Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Failed to execute lsof",
-            ))
+            Err(std::io::Error::other("Failed to execute lsof"))
         }
     }
 
@@ -298,8 +310,8 @@ mod tests {
 
     fn corrupt_at_offset(opts: Options, segment_num: usize, corruption_offset: u64) {
         let clog_subdir = opts.dir.join("clog");
-        let sr =
-            SegmentRef::read_segments_from_directory(&clog_subdir).expect("should read segments");
+        let sr = SegmentRef::read_segments_from_directory(&OsVfs, &clog_subdir)
+            .expect("should read segments");
 
         // Open the nth segment file for corrupting
         let file_path = &sr[segment_num - 1].file_path;
@@ -326,8 +338,8 @@ mod tests {
             .unwrap()
             .write();
         let clog_subdir = opts.dir.join("clog");
-        let sr =
-            SegmentRef::read_segments_from_directory(&clog_subdir).expect("should read segments");
+        let sr = SegmentRef::read_segments_from_directory(&OsVfs, &clog_subdir)
+            .expect("should read segments");
 
         // Open the nth segment file for corruption
         corrupt_at_offset(opts.clone(), segment_num, corruption_offset);
@@ -350,7 +362,7 @@ mod tests {
     #[allow(unused)]
     fn find_corrupted_segment(sr: Vec<SegmentRef>, opts: Options) -> (u64, u64) {
         let reader = Reader::new_from(
-            MultiSegmentReader::new(sr).expect("should create"),
+            MultiSegmentReader::new(&SharedVfs::default(), sr).expect("should create"),
             opts.max_segment_size,
             1000,
         );
@@ -575,6 +587,43 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn repair_after_an_unfinished_write_slot() {
+        let temp_dir = create_temp_directory();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+
+        let keys = vec![Bytes::from("k1"), Bytes::from("k2")];
+        let default_value = Bytes::from("val");
+        let store = setup_store_with_data(opts.clone(), keys, default_value.clone()).await;
+
+        // A record whose encoding stopped halfway leaves zeros in the commit log, after
+        // which the commits fail.
+        {
+            let core = &store.inner.as_ref().unwrap().core;
+            let clog = core.clog.as_ref().unwrap().read();
+            let mut slot = clog.reserve(64).unwrap();
+            slot.put_slice(b"partial");
+        }
+        let mut txn = store.begin().unwrap();
+        txn.set(b"k3", &default_value).unwrap();
+        assert!(txn.commit().await.is_err());
+        drop(txn);
+        store.close().await.ok();
+
+        // The store opened again repairs the log, and commits after the records before it.
+        let store = Store::new(opts.clone()).expect("should open store");
+        append_data(&store, vec![Bytes::from("k4")], &default_value).await;
+        store.close().await.expect("should close store");
+
+        let store = Store::new(opts).expect("should open store");
+        let txn = store.begin().unwrap();
+        for key in ["k1", "k2", "k4"] {
+            assert_eq!(txn.get(key.as_bytes()).unwrap().unwrap(), default_value);
+        }
+        assert!(txn.get(b"k3").unwrap().is_none());
+    }
+
     #[tokio::test]
     async fn repair_single_segment_with_multiple_records() {
         let temp_dir = create_temp_directory();
@@ -774,7 +823,7 @@ mod tests {
         let mut file2 = File::create(dir.path().join("0002.clog")).unwrap();
         file1.write_all(b"Data for 0001.clog").unwrap();
         file2.write_all(b"Data for 0002.clog").unwrap();
-        assert!(restore_repair_files(dir.path().to_str().unwrap()).is_ok());
+        assert!(restore_repair_files(&OsVfs, dir.path()).is_ok());
         assert!(dir.path().join("0001.clog").exists());
         assert!(dir.path().join("0002.clog").exists());
     }
@@ -784,7 +833,7 @@ mod tests {
         let dir = create_temp_directory();
         File::create(dir.path().join("001.repair")).unwrap();
         File::create(dir.path().join("002.repair")).unwrap();
-        assert!(restore_repair_files(dir.path().to_str().unwrap()).is_ok());
+        assert!(restore_repair_files(&OsVfs, dir.path()).is_ok());
         assert!(dir.path().join("001.clog").exists());
         assert!(dir.path().join("002.clog").exists());
         assert!(!dir.path().join("001.repair").exists());
@@ -798,7 +847,7 @@ mod tests {
         File::create(dir.path().join("0002.clog")).unwrap();
         File::create(dir.path().join("0001.repair")).unwrap();
         File::create(dir.path().join("0002.repair")).unwrap();
-        assert!(restore_repair_files(dir.path().to_str().unwrap()).is_ok());
+        assert!(restore_repair_files(&OsVfs, dir.path()).is_ok());
         assert!(dir.path().join("0001.clog").exists());
         assert!(dir.path().join("0002.clog").exists());
         assert!(!dir.path().join("0001.repair").exists());
@@ -811,7 +860,7 @@ mod tests {
         File::create(dir.path().join("0001.clog")).unwrap();
         File::create(dir.path().join("0002.clog")).unwrap();
         File::create(dir.path().join("0001.repair")).unwrap();
-        assert!(restore_repair_files(dir.path().to_str().unwrap()).is_ok());
+        assert!(restore_repair_files(&OsVfs, dir.path()).is_ok());
         assert!(dir.path().join("0001.clog").exists());
         assert!(dir.path().join("0002.clog").exists());
         assert!(!dir.path().join("0001.repair").exists());
@@ -828,7 +877,7 @@ mod tests {
         file2.write_all(b"Data for 0002.clog").unwrap();
         repair_file1.write_all(b"Data for 0001.repair").unwrap();
         repair_file2.write_all(b"Data for 0002.repair").unwrap();
-        assert!(restore_repair_files(dir.path().to_str().unwrap()).is_ok());
+        assert!(restore_repair_files(&OsVfs, dir.path()).is_ok());
         assert!(dir.path().join("0001.clog").exists());
         assert!(dir.path().join("0002.clog").exists());
         assert!(!dir.path().join("0001.repair").exists());
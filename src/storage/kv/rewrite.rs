@@ -0,0 +1,277 @@
+use std::ffi::OsString;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+
+use crate::storage::{
+    kv::{
+        entry::Entry,
+        error::{Error, Result},
+        events::{self, Activity, Value},
+        option::Options,
+        store::{Core, Store},
+        transaction::Mode,
+        util::{prefix_end, SYSTEM_KEY_PREFIX},
+    },
+    vfs::{OpenOptions, Vfs},
+};
+
+/// Name of the marker file written once the rewritten store is complete.
+const REWRITE_COMPLETE_MARKER: &str = "REWRITE_COMPLETE";
+
+/// Returns the path of a sibling of the store directory with the given suffix.
+fn sibling(dir: &Path, suffix: &str) -> PathBuf {
+    let mut path: OsString = dir.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Directory the store is rewritten into before being swapped in.
+fn rewrite_dir(dir: &Path) -> PathBuf {
+    sibling(dir, ".rewrite")
+}
+
+/// Directory the original store is moved to while the rewritten one is swapped in.
+fn old_dir(dir: &Path) -> PathBuf {
+    sibling(dir, ".old")
+}
+
+/// Completes or rolls back a rewrite that was interrupted while swapping directories.
+///
+/// The swap moves the original directory aside, moves the rewritten one in place, and then
+/// removes the original. If the rewritten store was complete, the swap is finished; otherwise
+/// the original directory is put back.
+pub(crate) fn recover_interrupted_rewrite(vfs: &dyn Vfs, dir: &Path) -> Result<()> {
+    let old = old_dir(dir);
+    if !vfs.exists(&old) {
+        return Ok(());
+    }
+
+    let rewritten = rewrite_dir(dir);
+    if !vfs.exists(dir) {
+        if vfs.exists(&rewritten.join(REWRITE_COMPLETE_MARKER)) {
+            vfs.rename(&rewritten, dir)?;
+        } else {
+            vfs.rename(&old, dir)?;
+            return Ok(());
+        }
+    }
+
+    let marker = dir.join(REWRITE_COMPLETE_MARKER);
+    if vfs.exists(&marker) {
+        vfs.remove_file(&marker)?;
+    }
+    vfs.remove_dir_all(&old)?;
+    Ok(())
+}
+
+/// Copies the latest version of all the keys in `range` from `source` to `target`, in
+/// transactions of at most `batch_size` entries and about `batch_bytes` bytes.
+/// Returns the number of keys copied.
+async fn copy_range(
+    source: &Store,
+    target: &Store,
+    start: &[u8],
+    end: Option<&[u8]>,
+    batch_size: usize,
+    batch_bytes: usize,
+) -> Result<usize> {
+    let mut cursor: Option<Vec<u8>> = None;
+    let mut copied = 0;
+
+    loop {
+        let txn = source.begin_with_mode(Mode::ReadOnly)?;
+        let lower = match &cursor {
+            Some(key) => Bound::Excluded(&key[..]),
+            None => Bound::Included(start),
+        };
+        let upper = match end {
+            Some(end) => Bound::Excluded(end),
+            None => Bound::Unbounded,
+        };
+        let batch = txn.scan((lower, upper), Some(batch_size))?;
+        if batch.is_empty() {
+            return Ok(copied);
+        }
+
+        let mut wtxn = target.begin_with_mode(Mode::WriteOnly)?;
+        let mut bytes = 0;
+        for (key, value, _, _) in &batch {
+            // A transaction must fit in a segment of the target store.
+            if bytes > 0 && bytes + key.len() + value.len() > batch_bytes {
+                wtxn.commit().await?;
+                wtxn = target.begin_with_mode(Mode::WriteOnly)?;
+                bytes = 0;
+            }
+            // The system keys are copied too, with their flags and their expiry.
+            let mut entry = Entry::new(key, value);
+            entry.set_flags(txn.get_flags(key)?.unwrap_or_default());
+            entry.set_expiry(txn.expires_at(key)?);
+            wtxn.write_system(entry)?;
+            bytes += key.len() + value.len();
+        }
+        wtxn.commit().await?;
+
+        copied += batch.len();
+        cursor = batch.last().map(|(key, _, _, _)| key.clone());
+    }
+}
+
+/// Rewrites the store in `dir` into a store configured with `new_opts`, then swaps the
+/// directories. Both stores are on the filesystem of `new_opts`. Returns the number of keys
+/// copied.
+pub(crate) async fn rewrite(dir: &Path, mut new_opts: Options) -> Result<usize> {
+    if !new_opts.should_persist_data() {
+        return Err(Error::InvalidOptions(
+            "a store can only be rewritten into a persistent store".to_string(),
+        ));
+    }
+
+    let vfs = new_opts.vfs.clone();
+    recover_interrupted_rewrite(&*vfs, dir)?;
+
+    // Open the source store with the options it was created with.
+    let mut source_opts = Options::new();
+    source_opts.dir = dir.to_path_buf();
+    source_opts.vfs = vfs.clone();
+    let source_opts = Core::persisted_options(&source_opts)?.ok_or(Error::ManifestNotFound)?;
+
+    let rewritten = rewrite_dir(dir);
+    if vfs.exists(&rewritten) {
+        // Leftover of a rewrite that failed before the swap.
+        vfs.remove_dir_all(&rewritten)?;
+    }
+    new_opts.dir = rewritten.clone();
+
+    let source = Store::new(source_opts)?;
+    let target = Store::new(new_opts)?;
+    let target_opts = &target.inner.as_ref().unwrap().core.opts;
+    let activity = Activity::start(
+        events::COMPACTION,
+        "rewrite",
+        &[("max_segment_size", Value::U64(target_opts.max_segment_size))],
+    );
+    let batch_size = target_opts.max_entries_per_txn as usize;
+    let batch_bytes = target_opts.max_segment_size as usize / 2;
+
+    // User keys first, then the keys of the internal subsystems which user scans skip.
+    let system_end = prefix_end(SYSTEM_KEY_PREFIX);
+    let copied = async {
+        let mut copied = copy_range(&source, &target, &[], None, batch_size, batch_bytes).await?;
+        copied += copy_range(
+            &source,
+            &target,
+            SYSTEM_KEY_PREFIX,
+            system_end.as_deref(),
+            batch_size,
+            batch_bytes,
+        )
+        .await?;
+        Ok::<usize, Error>(copied)
+    }
+    .await;
+
+    let swapped = async {
+        source.close().await?;
+        target.close().await?;
+        let copied = copied?;
+
+        // Mark the rewritten store as complete, and swap it in.
+        let marker = rewritten.join(REWRITE_COMPLETE_MARKER);
+        vfs.open(&marker, &OpenOptions::create_file())?.sync_all()?;
+        vfs.rename(dir, &old_dir(dir))?;
+        recover_interrupted_rewrite(&*vfs, dir)?;
+        Ok(copied)
+    }
+    .await;
+    activity.finish(swapped, |copied| vec![("keys_copied", *copied as u64)])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::storage::vfs::OsVfs;
+
+    use tempdir::TempDir;
+
+    #[tokio::test]
+    async fn rewrite_with_new_segment_size() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let dir = temp_dir.path().join("db");
+
+        let mut opts = Options::new();
+        opts.dir = dir.clone();
+        let store = Store::new(opts.clone()).expect("should create store");
+        for i in 0..100u32 {
+            let mut txn = store.begin().unwrap();
+            txn.set(&i.to_be_bytes(), &[i as u8; 100]).unwrap();
+            txn.commit().await.unwrap();
+        }
+        let mut txn = store.begin().unwrap();
+        txn.delete(&7u32.to_be_bytes()).unwrap();
+        txn.set_with_flags(&9u32.to_be_bytes(), &[9; 100], 0b10)
+            .unwrap();
+        txn.set_with_ttl(&11u32.to_be_bytes(), &[11; 100], Duration::from_secs(3600))
+            .unwrap();
+        txn.commit().await.unwrap();
+        store.append_to_stream(b"events", b"e0").await.unwrap();
+        let expires_at = store
+            .begin()
+            .unwrap()
+            .expires_at(&11u32.to_be_bytes())
+            .unwrap();
+        assert!(expires_at.is_some());
+        store.close().await.unwrap();
+
+        let mut new_opts = opts.clone();
+        new_opts.max_segment_size = 4096;
+        let copied = Store::rewrite(&dir, new_opts.clone()).await.unwrap();
+        assert_eq!(copied, 99 + 2);
+        assert!(!old_dir(&dir).exists());
+        assert!(!rewrite_dir(&dir).exists());
+        assert!(!dir.join(REWRITE_COMPLETE_MARKER).exists());
+
+        let persisted = Store::persisted_options(&dir).unwrap().unwrap();
+        assert_eq!(persisted.max_segment_size, 4096);
+
+        let store = Store::new(new_opts).expect("should open rewritten store");
+        let txn = store.begin().unwrap();
+        assert_eq!(txn.scan(.., None).unwrap().len(), 99);
+        assert!(txn.get(&7u32.to_be_bytes()).unwrap().is_none());
+        assert_eq!(txn.get(&8u32.to_be_bytes()).unwrap().unwrap(), vec![8; 100]);
+        assert_eq!(txn.get_flags(&9u32.to_be_bytes()).unwrap(), Some(0b10));
+        assert_eq!(
+            txn.get(&11u32.to_be_bytes()).unwrap().unwrap(),
+            vec![11; 100]
+        );
+        assert_eq!(txn.expires_at(&11u32.to_be_bytes()).unwrap(), expires_at);
+        assert!(txn.expires_at(&8u32.to_be_bytes()).unwrap().is_none());
+        assert_eq!(store.read_stream(b"events", ..).unwrap().len(), 1);
+        assert_eq!(store.append_to_stream(b"events", b"e1").await.unwrap(), 1);
+    }
+
+    #[test]
+    fn recover_interrupted_swap() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let dir = temp_dir.path().join("db");
+
+        // Interrupted before the rewritten store was complete: the original is restored.
+        fs::create_dir_all(old_dir(&dir)).unwrap();
+        fs::create_dir_all(rewrite_dir(&dir)).unwrap();
+        recover_interrupted_rewrite(&OsVfs, &dir).unwrap();
+        assert!(dir.exists());
+        assert!(!old_dir(&dir).exists());
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Interrupted after the rewritten store was complete: the swap is finished.
+        fs::create_dir_all(old_dir(&dir)).unwrap();
+        fs::File::create(rewrite_dir(&dir).join(REWRITE_COMPLETE_MARKER)).unwrap();
+        recover_interrupted_rewrite(&OsVfs, &dir).unwrap();
+        assert!(dir.exists());
+        assert!(!old_dir(&dir).exists());
+        assert!(!rewrite_dir(&dir).exists());
+        assert!(!dir.join(REWRITE_COMPLETE_MARKER).exists());
+    }
+}
@@ -0,0 +1,105 @@
+use std::ops::RangeBounds;
+use std::sync::Arc;
+
+use crate::storage::kv::{
+    error::Result,
+    store::Core,
+    transaction::{Mode, Transaction},
+    util::now,
+};
+
+/// A small xorshift generator, good enough to pick sample positions.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // The state of a xorshift generator must not be zero.
+        Self(seed | 1)
+    }
+
+    /// Returns a number in `0..bound`.
+    fn below(&mut self, bound: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % bound
+    }
+}
+
+/// Returns up to `n` keys sampled uniformly from the live keys in the range, in key order.
+///
+/// The index does not maintain subtree counts, so the keys of the range are walked once
+/// without reading their values, and the sample is drawn with reservoir sampling.
+pub(crate) fn sample_keys<'a, R>(core: &Arc<Core>, n: usize, range: R) -> Result<Vec<Vec<u8>>>
+where
+    R: RangeBounds<&'a [u8]>,
+{
+    let mut reservoir: Vec<Vec<u8>> = Vec::with_capacity(n);
+    if n == 0 {
+        return Ok(reservoir);
+    }
+
+    let txn = Transaction::new(core.clone(), Mode::ReadOnly)?;
+    let mut rng = Rng::new(now());
+    let mut seen = 0u64;
+    txn.walk_keys(range, |key, _| {
+        seen += 1;
+        if reservoir.len() < n {
+            reservoir.push(key.to_vec());
+        } else {
+            let i = rng.below(seen) as usize;
+            if i < n {
+                reservoir[i] = key.to_vec();
+            }
+        }
+        true
+    })?;
+
+    reservoir.sort();
+    Ok(reservoir)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+
+    use tempdir::TempDir;
+
+    #[tokio::test]
+    async fn sample_keys() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        let store = Store::new(opts).expect("should create store");
+
+        for i in 0..1000u32 {
+            let mut txn = store.begin().unwrap();
+            txn.set(&i.to_be_bytes(), b"value").unwrap();
+            txn.commit().await.unwrap();
+        }
+        let mut txn = store.begin().unwrap();
+        txn.delete(&5u32.to_be_bytes()).unwrap();
+        txn.commit().await.unwrap();
+
+        // Fewer keys than requested: all of them are returned.
+        let start = 0u32.to_be_bytes();
+        let end = 10u32.to_be_bytes();
+        let sample = store.sample_keys(100, &start[..]..&end[..]).unwrap();
+        assert_eq!(sample.len(), 9);
+        assert!(!sample.contains(&5u32.to_be_bytes().to_vec()));
+
+        let sample = store.sample_keys(100, ..).unwrap();
+        assert_eq!(sample.len(), 100);
+        assert!(sample.windows(2).all(|w| w[0] < w[1]));
+
+        // The sample is spread over the whole range.
+        let below_half = sample
+            .iter()
+            .filter(|k| k[..] < 500u32.to_be_bytes()[..])
+            .count();
+        assert!((20..80).contains(&below_half), "{}", below_half);
+
+        assert!(store.sample_keys(0, ..).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,397 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::ops::{Bound, RangeBounds};
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+
+use crate::storage::{
+    kv::{
+        error::{Error, Result},
+        util::is_system_key,
+    },
+    log::{segment_name, Metadata},
+    vfs::{SharedVfs, Vfs},
+};
+
+const SIDECAR_EXTENSION: &str = "keys";
+
+const META_KEY_USER_MIN: &str = "user_min";
+const META_KEY_USER_MAX: &str = "user_max";
+const META_KEY_SYSTEM_MIN: &str = "system_min";
+const META_KEY_SYSTEM_MAX: &str = "system_max";
+
+/// The smallest and the largest key written to a segment of the commit log, for the user
+/// keys and for the system keys (see `util::SYSTEM_KEY_PREFIX`) separately, so that a range
+/// of user keys does not overlap every segment holding a system key.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct SegmentKeys {
+    user: Option<(Bytes, Bytes)>,
+    system: Option<(Bytes, Bytes)>,
+}
+
+impl SegmentKeys {
+    fn observe(&mut self, key: &[u8]) {
+        let range = if is_system_key(key) {
+            &mut self.system
+        } else {
+            &mut self.user
+        };
+        match range {
+            Some((min, max)) => {
+                if key < &min[..] {
+                    *min = Bytes::copy_from_slice(key);
+                } else if key > &max[..] {
+                    *max = Bytes::copy_from_slice(key);
+                }
+            }
+            None => {
+                let key = Bytes::copy_from_slice(key);
+                *range = Some((key.clone(), key));
+            }
+        }
+    }
+
+    /// Returns true if keys of the segment may lie in the range.
+    fn overlaps<'a, R>(&self, range: &R) -> bool
+    where
+        R: RangeBounds<&'a [u8]>,
+    {
+        [&self.user, &self.system]
+            .into_iter()
+            .flatten()
+            .any(|(min, max)| {
+                let after_start = match range.start_bound() {
+                    Bound::Included(start) => &max[..] >= *start,
+                    Bound::Excluded(start) => &max[..] > *start,
+                    Bound::Unbounded => true,
+                };
+                let before_end = match range.end_bound() {
+                    Bound::Included(end) => &min[..] <= *end,
+                    Bound::Excluded(end) => &min[..] < *end,
+                    Bound::Unbounded => true,
+                };
+                after_start && before_end
+            })
+    }
+
+    fn to_metadata(&self) -> Metadata {
+        let mut metadata = Metadata::new(None);
+        if let Some((min, max)) = &self.user {
+            metadata.put(META_KEY_USER_MIN, min);
+            metadata.put(META_KEY_USER_MAX, max);
+        }
+        if let Some((min, max)) = &self.system {
+            metadata.put(META_KEY_SYSTEM_MIN, min);
+            metadata.put(META_KEY_SYSTEM_MAX, max);
+        }
+        metadata
+    }
+
+    fn from_metadata(metadata: &Metadata) -> Result<Self> {
+        let range = |min_key, max_key| match (metadata.get(min_key), metadata.get(max_key)) {
+            (Some(min), Some(max)) => Ok(Some((
+                Bytes::copy_from_slice(min),
+                Bytes::copy_from_slice(max),
+            ))),
+            (None, None) => Ok(None),
+            _ => Err(Error::CorruptedMetadata),
+        };
+        Ok(Self {
+            user: range(META_KEY_USER_MIN, META_KEY_USER_MAX)?,
+            system: range(META_KEY_SYSTEM_MIN, META_KEY_SYSTEM_MAX)?,
+        })
+    }
+}
+
+/// `SegmentKeyRanges` keeps the key range of each segment of the commit log, so that range
+/// scans, compaction and garbage collection can skip the segments that cannot hold keys of
+/// interest.
+///
+/// The range of a segment is written next to the commit log, in `segments/<id>.keys`, when
+/// the store moves on to the next segment. The ranges are also rebuilt while the commit log
+/// is replayed on open, which writes the ranges missing for the segments sealed before.
+pub(crate) struct SegmentKeyRanges {
+    vfs: SharedVfs,
+    dir: Option<PathBuf>,
+    segments: BTreeMap<u64, SegmentKeys>,
+    sealed: u64,  // Segments below this id are sealed and have their range written.
+    cleared: u64, // Segments below this id were dropped by a clear of the store.
+}
+
+impl SegmentKeyRanges {
+    /// Loads the recorded key ranges from `dir` on `vfs`, or keeps them in memory only if there
+    /// is no directory.
+    pub(crate) fn open(vfs: &SharedVfs, dir: Option<&Path>) -> Result<Self> {
+        let mut segments = BTreeMap::new();
+        if let Some(dir) = dir {
+            vfs.create_dir_all(dir, None)?;
+            for entry in vfs.read_dir(dir)? {
+                let path = entry.path();
+                if path.extension().and_then(|e| e.to_str()) != Some(SIDECAR_EXTENSION) {
+                    continue;
+                }
+                let id = path
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .and_then(|s| s.parse::<u64>().ok())
+                    .ok_or(Error::CorruptedMetadata)?;
+
+                let mut metadata = Metadata::new(None);
+                metadata.read_from(&mut &vfs.read(path)?[..])?;
+                segments.insert(id, SegmentKeys::from_metadata(&metadata)?);
+            }
+        }
+
+        // The last range may have been written before its segment was sealed (see
+        // `persist`), in which case it is written again when the segment is sealed.
+        let sealed = segments.keys().next_back().map_or(0, |id| *id);
+        Ok(Self {
+            vfs: vfs.clone(),
+            dir: dir.map(Path::to_path_buf),
+            segments,
+            sealed,
+            cleared: 0,
+        })
+    }
+
+    /// Records keys written to a segment. Writing to a segment seals the segments before it.
+    pub(crate) fn record<'a, I>(&mut self, segment_id: u64, keys: I) -> Result<()>
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        let range = self.segments.entry(segment_id).or_default();
+        for key in keys {
+            range.observe(key);
+        }
+        self.seal_before(segment_id)
+    }
+
+    /// Writes the ranges of the segments below `segment_id` that were not written yet.
+    fn seal_before(&mut self, segment_id: u64) -> Result<()> {
+        if segment_id <= self.sealed {
+            return Ok(());
+        }
+        if let Some(dir) = &self.dir {
+            for (id, range) in self.segments.range(self.sealed..segment_id) {
+                write_range(&*self.vfs, dir, *id, range)?;
+            }
+        }
+        self.sealed = segment_id;
+        Ok(())
+    }
+
+    /// Drops the ranges of the segments below `segment_id`, which a clear of the store drops,
+    /// see `clear::remove_segments`.
+    pub(crate) fn remove_before(&mut self, segment_id: u64) -> Result<()> {
+        let kept = self.segments.split_off(&segment_id);
+        let removed = std::mem::replace(&mut self.segments, kept);
+        if let Some(dir) = &self.dir {
+            for id in removed.keys() {
+                remove_range(&*self.vfs, dir, *id)?;
+            }
+        }
+        self.sealed = self.sealed.max(segment_id);
+        self.cleared = self.cleared.max(segment_id);
+        Ok(())
+    }
+
+    /// Returns the id below which the segments were dropped by a clear of the store, or 0.
+    pub(crate) fn cleared(&self) -> u64 {
+        self.cleared
+    }
+
+    /// Writes the ranges of the segments that are not sealed yet, so that they are known
+    /// without replaying the commit log (see `checkpoint::IndexCheckpoint`).
+    pub(crate) fn persist(&self) -> Result<()> {
+        if let Some(dir) = &self.dir {
+            for (id, range) in self.segments.range(self.sealed..) {
+                write_range(&*self.vfs, dir, *id, range)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the ids of the segments whose range was recorded that are missing from the
+    /// `present` ones, which are in order. The ranges recorded below the first segment present
+    /// are left over from a compaction or a clear that removed their segments.
+    pub(crate) fn missing(&self, present: &[u64]) -> Vec<u64> {
+        let first = present.first().copied().unwrap_or(0);
+        self.segments
+            .range(first..)
+            .map(|(id, _)| *id)
+            .filter(|id| present.binary_search(id).is_err())
+            .collect()
+    }
+
+    /// Returns the segments of `ids` with their range, see `missing`.
+    pub(crate) fn unavailable(&self, ids: &[u64]) -> Unavailable {
+        Unavailable {
+            segments: ids
+                .iter()
+                .filter_map(|id| Some((*id, self.segments.get(id)?.clone())))
+                .collect(),
+        }
+    }
+
+    /// Returns the ids of the segments that may hold keys in the range, in order.
+    pub(crate) fn overlapping<'a, R>(&self, range: R) -> Vec<u64>
+    where
+        R: RangeBounds<&'a [u8]>,
+    {
+        self.segments
+            .iter()
+            .filter(|(_, keys)| keys.overlaps(&range))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+}
+
+/// `Unavailable` keeps the segments of the commit log found missing when the store was opened,
+/// in `MissingSegments::Degraded` mode, with their key range. The versions of the keys they
+/// held were lost, so the keys in their ranges are not read, rather than read at an older
+/// version.
+#[derive(Default)]
+pub(crate) struct Unavailable {
+    segments: Vec<(u64, SegmentKeys)>,
+}
+
+impl Unavailable {
+    /// Returns the ids of the segments missing, in order.
+    pub(crate) fn ids(&self) -> Vec<u64> {
+        self.segments.iter().map(|(id, _)| *id).collect()
+    }
+
+    /// Returns `Error::SegmentUnavailable` if keys of a missing segment may lie in the range.
+    pub(crate) fn check<'a, R>(&self, range: &R) -> Result<()>
+    where
+        R: RangeBounds<&'a [u8]>,
+    {
+        let found = self.segments.iter().find(|(_, keys)| keys.overlaps(range));
+        match found {
+            Some((id, _)) => Err(Error::SegmentUnavailable(*id)),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns `Error::SegmentUnavailable` if the key may have been written to a missing
+    /// segment.
+    pub(crate) fn check_key(&self, key: &[u8]) -> Result<()> {
+        self.check(&(Bound::Included(key), Bound::Included(key)))
+    }
+}
+
+/// Removes the range written for a segment the store removes, if any.
+pub(crate) fn remove_range(vfs: &dyn Vfs, dir: &Path, id: u64) -> Result<()> {
+    match vfs.remove_file(&dir.join(segment_name(id, SIDECAR_EXTENSION))) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+fn write_range(vfs: &dyn Vfs, dir: &Path, id: u64, range: &SegmentKeys) -> Result<()> {
+    let path = dir.join(segment_name(id, SIDECAR_EXTENSION));
+    let tmp = path.with_extension("tmp");
+    vfs.write(&tmp, &range.to_metadata().to_bytes()?)?;
+    vfs.rename(&tmp, &path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::kv::error::Error;
+    use crate::storage::kv::option::{MissingSegments, Options};
+    use crate::storage::kv::store::Store;
+    use crate::storage::kv::util::SYSTEM_KEY_PREFIX;
+    use crate::storage::log::segment_name;
+
+    use tempdir::TempDir;
+
+    #[tokio::test]
+    async fn segments_for_range() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.max_segment_size = 4096;
+
+        // Each batch of keys fills about a segment.
+        let store = Store::new(opts.clone()).expect("should create store");
+        for batch in 0..4u8 {
+            for i in 0..30u8 {
+                let mut txn = store.begin().unwrap();
+                txn.set(&[batch, i], &[0; 100]).unwrap();
+                txn.commit().await.unwrap();
+            }
+        }
+        let mut system_key = SYSTEM_KEY_PREFIX.to_vec();
+        system_key.push(0);
+        let mut txn = store.begin().unwrap();
+        txn.set_system(&system_key, b"v").unwrap();
+        txn.commit().await.unwrap();
+
+        let all = store.segments_for_range(..);
+        assert!(all.len() > 4);
+        let first = store.segments_for_range(..=&[0, 29][..]);
+        assert!(!first.is_empty() && first.len() < all.len());
+        assert_eq!(first[0], all[0]);
+        let last = store.segments_for_range(&[3, 0][..]..&[4][..]);
+        assert!(!last.is_empty() && last.len() < all.len());
+        assert!(first.last() < last.first());
+        assert_eq!(
+            store.segments_for_range(&system_key[..]..),
+            vec![*all.last().unwrap()]
+        );
+        store.close().await.unwrap();
+
+        // The sealed segments have their range written, and all ranges are kept on reopen.
+        let sidecars = std::fs::read_dir(temp_dir.path().join("segments"))
+            .unwrap()
+            .count();
+        assert_eq!(sidecars, all.len() - 1);
+        let store = Store::new(opts).expect("should reopen store");
+        assert_eq!(store.segments_for_range(..), all);
+        assert_eq!(store.segments_for_range(..=&[0, 29][..]), first);
+    }
+
+    #[tokio::test]
+    async fn missing_segments() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.max_segment_size = 4096;
+
+        let store = Store::new(opts.clone()).expect("should create store");
+        for batch in 0..4u8 {
+            for i in 0..30u8 {
+                let mut txn = store.begin().unwrap();
+                txn.set(&[batch, i], &[0; 100]).unwrap();
+                txn.commit().await.unwrap();
+            }
+        }
+        let key = &[1, 10][..];
+        let id = store.segments_for_range(key..=key)[0];
+        store.close().await.unwrap();
+        std::fs::remove_file(temp_dir.path().join("clog").join(segment_name(id, "clog"))).unwrap();
+
+        // By default, the store fails to open, listing the segments missing.
+        match Store::new(opts.clone()) {
+            Err(Error::MissingSegments(ids)) => assert_eq!(ids, vec![id]),
+            _ => panic!("should fail with the missing segments"),
+        }
+
+        // Opened degraded, the keys of the segment missing are unavailable, the others not.
+        opts.missing_segments = MissingSegments::Degraded;
+        let store = Store::new(opts).expect("should open degraded");
+        assert_eq!(store.missing_segments(), vec![id]);
+        let txn = store.begin().unwrap();
+        assert!(matches!(txn.get(key), Err(Error::SegmentUnavailable(found)) if found == id));
+        assert!(matches!(
+            txn.scan(&[1][..]..&[2][..], None),
+            Err(Error::SegmentUnavailable(_))
+        ));
+        assert_eq!(txn.get(&[3, 29]).unwrap(), Some(vec![0; 100]));
+        assert_eq!(txn.scan(&[3][..]..&[4][..], None).unwrap().len(), 30);
+        drop(txn);
+        store.close().await.unwrap();
+    }
+}
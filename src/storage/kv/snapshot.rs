@@ -12,7 +12,7 @@ use vart::{
     iter::IterationPointer, snapshot::Snapshot as TartSnapshot, TrieError, VariableSizeKey,
 };
 
-pub(crate) const FILTERS: [fn(&ValueRef, u64) -> Result<()>; 1] = [ignore_deleted];
+pub(crate) const FILTERS: [fn(&ValueRef, u64) -> Result<()>; 2] = [ignore_deleted, ignore_expired];
 
 /// A versioned snapshot for snapshot isolation.
 pub(crate) struct Snapshot {
@@ -26,7 +26,9 @@ pub(crate) struct Snapshot {
 
 impl Snapshot {
     pub(crate) fn take(store: Arc<Core>, ts: u64) -> Result<Self> {
-        let snapshot = store.indexer.write().snapshot()?;
+        // Taking a snapshot only clones the root of the index, which readers can do
+        // concurrently.
+        let snapshot = store.indexer.read().snapshot()?;
 
         Ok(Self {
             ts,
@@ -40,20 +42,18 @@ impl Snapshot {
         // TODO: need to fix this to avoid cloning the key
         // This happens because the VariableSizeKey transfrom from
         // a &[u8] does not terminate the key with a null byte.
-        let key = &key.terminate();
+        let key = &self.store.keys.encode(key.to_slice());
         self.snap.insert(key, value, self.snap.ts())?;
         Ok(())
     }
 
     /// Retrieves the value and timestamp associated with the given key from the snapshot.
     pub fn get(&self, key: &VariableSizeKey) -> Result<Box<dyn Value>> {
-        // TODO: need to fix this to avoid cloning the key
-        // This happens because the VariableSizeKey transfrom from
-        // a &[u8] does not terminate the key with a null byte.
-        let key = &key.terminate();
         self.get_with_filters(key, &FILTERS)
     }
 
+    /// Retrieves the value associated with the given key, like `get`, keeping it only if
+    /// it passes the filters.
     pub fn get_with_filters<F>(
         &self,
         key: &VariableSizeKey,
@@ -62,7 +62,29 @@ impl Snapshot {
     where
         F: FilterFn,
     {
-        let (val, version, _) = self.snap.get(key)?;
+        Ok(Box::new(self.get_ref_with_filters(key, filters)?))
+    }
+
+    /// Retrieves the value reference associated with the given key, as `get_with_filters`
+    /// does.
+    pub(crate) fn get_ref_with_filters<F>(
+        &self,
+        key: &VariableSizeKey,
+        filters: &[F],
+    ) -> Result<ValueRef>
+    where
+        F: FilterFn,
+    {
+        self.store.unavailable.check_key(key.to_slice())?;
+
+        // TODO: need to fix this to avoid cloning the key
+        // This happens because the VariableSizeKey transfrom from
+        // a &[u8] does not terminate the key with a null byte.
+        let key = match self.store.keys.lookup(key.to_slice()) {
+            Some(key) => key,
+            None => return Err(Error::IndexError(TrieError::KeyNotFound)),
+        };
+        let (val, version, _) = self.snap.get(&key)?;
         let mut val_ref = ValueRef::new(self.store.clone());
         let val_bytes_ref: &Bytes = &val;
         val_ref.decode(version, val_bytes_ref)?;
@@ -71,7 +93,17 @@ impl Snapshot {
             filter.apply(&val_ref, self.ts)?
         }
 
-        Ok(Box::new(val_ref))
+        Ok(val_ref)
+    }
+
+    /// Returns the version of the index the snapshot was taken at.
+    pub(crate) fn version(&self) -> u64 {
+        self.snap.version()
+    }
+
+    /// Returns the version the writes of the transaction are set at in the snapshot.
+    pub(crate) fn write_version(&self) -> u64 {
+        self.snap.ts()
     }
 
     pub fn new_reader(&mut self) -> Result<IterationPointer<VariableSizeKey, Bytes>> {
@@ -83,7 +115,7 @@ pub(crate) trait FilterFn {
     fn apply(&self, val_ref: &ValueRef, ts: u64) -> Result<()>;
 }
 
-fn ignore_deleted(val_ref: &ValueRef, _: u64) -> Result<()> {
+pub(crate) fn ignore_deleted(val_ref: &ValueRef, _: u64) -> Result<()> {
     let md = val_ref.key_value_metadata();
     if let Some(md) = md {
         if md.deleted() {
@@ -93,6 +125,23 @@ fn ignore_deleted(val_ref: &ValueRef, _: u64) -> Result<()> {
     Ok(())
 }
 
+/// Filters out the values that expired, at the time they are read.
+fn ignore_expired(val_ref: &ValueRef, _: u64) -> Result<()> {
+    if is_expired(
+        &val_ref.store,
+        val_ref.key_value_metadata().and_then(|md| md.expires_at()),
+    ) {
+        return Err(Error::IndexError(TrieError::KeyNotFound));
+    }
+    Ok(())
+}
+
+/// Returns true if a value of the store of `core` expiring at `expires_at` has expired, by
+/// `Options::expiry_clock`.
+pub(crate) fn is_expired(core: &Core, expires_at: Option<u64>) -> bool {
+    expires_at.is_some_and(|at| at <= core.opts.expiry_clock.now())
+}
+
 impl<F> FilterFn for F
 where
     F: Fn(&ValueRef, u64) -> Result<()>,
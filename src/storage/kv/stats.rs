@@ -0,0 +1,657 @@
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+use quick_cache::Lifecycle;
+
+use crate::storage::{kv::error::Result, log::Metadata, vfs::Vfs};
+
+/// Name of the file of the store directory the stats are published to.
+pub(crate) const STATS_FILE: &str = "stats";
+
+/// Counters updated by the store while it runs.
+pub(crate) struct Stats {
+    /// Whether the memory gauges are updated, see `Options::track_memory`.
+    track_memory: bool,
+    /// Number of values served from the value cache.
+    pub(crate) cache_reads: AtomicU64,
+    /// Number of values read from the commit log because they were not cached.
+    pub(crate) log_reads: AtomicU64,
+    /// Number of bytes appended to the commit log.
+    pub(crate) log_bytes_written: AtomicU64,
+    /// Number of cached reads verified against the commit log.
+    pub(crate) read_probes: AtomicU64,
+    /// Number of verified reads whose cached value did not match the commit log.
+    pub(crate) read_probe_mismatches: AtomicU64,
+    /// Number of writes validated, see `Options::write_audit_interval`.
+    pub(crate) write_audits: AtomicU64,
+    /// Number of validated writes that failed their validation.
+    pub(crate) write_audit_failures: AtomicU64,
+    /// Number of batches applied by the mirror.
+    pub(crate) mirror_batches: AtomicU64,
+    /// Number of batches queued for the mirror and not applied yet.
+    pub(crate) mirror_pending: AtomicU64,
+    /// Number of batches the mirror failed to apply.
+    pub(crate) mirror_errors: AtomicU64,
+    /// Commit timestamp of the last batch queued for the mirror.
+    pub(crate) mirror_last_committed_ts: AtomicU64,
+    /// Commit timestamp of the last batch applied by the mirror.
+    pub(crate) mirror_last_applied_ts: AtomicU64,
+    /// Number of keys written by concurrent transactions whose fingerprint was found in a read set.
+    pub(crate) ssi_fingerprint_hits: AtomicU64,
+    /// Number of fingerprint hits that the exact keys showed not to be conflicts.
+    pub(crate) ssi_fingerprint_false_positives: AtomicU64,
+    /// Bytes of the values held by the value cache.
+    pub(crate) value_cache_bytes: Arc<AtomicU64>,
+    /// Bytes of the pending writes of the open transactions.
+    pub(crate) transaction_bytes: AtomicU64,
+    /// Bytes of the committed entries queued for or being written to the commit log.
+    pub(crate) write_buffer_bytes: AtomicU64,
+    /// Number of entries of each batch written to the commit log.
+    pub(crate) commit_batch_entries: Histogram,
+    /// Nanoseconds each commit waited in the queue before being written.
+    pub(crate) commit_queue_ns: Histogram,
+    /// Nanoseconds each fsync of the commit log took.
+    pub(crate) commit_fsync_ns: Histogram,
+    /// Space amplification of the store in percent, as last measured by `Store::space_amp`.
+    pub(crate) space_amp_pct: AtomicU64,
+    /// Numbers of fsyncs of the commit log in the current and the previous second.
+    fsync_rate: Mutex<FsyncRate>,
+    /// Time the counters were created, which the fsync rate is measured from.
+    started_at: Instant,
+}
+
+impl Stats {
+    pub(crate) fn new(track_memory: bool) -> Self {
+        Self {
+            track_memory,
+            cache_reads: AtomicU64::default(),
+            log_reads: AtomicU64::default(),
+            log_bytes_written: AtomicU64::default(),
+            read_probes: AtomicU64::default(),
+            read_probe_mismatches: AtomicU64::default(),
+            write_audits: AtomicU64::default(),
+            write_audit_failures: AtomicU64::default(),
+            mirror_batches: AtomicU64::default(),
+            mirror_pending: AtomicU64::default(),
+            mirror_errors: AtomicU64::default(),
+            mirror_last_committed_ts: AtomicU64::default(),
+            mirror_last_applied_ts: AtomicU64::default(),
+            ssi_fingerprint_hits: AtomicU64::default(),
+            ssi_fingerprint_false_positives: AtomicU64::default(),
+            value_cache_bytes: Arc::default(),
+            transaction_bytes: AtomicU64::default(),
+            write_buffer_bytes: AtomicU64::default(),
+            commit_batch_entries: Histogram::default(),
+            commit_queue_ns: Histogram::default(),
+            commit_fsync_ns: Histogram::default(),
+            space_amp_pct: AtomicU64::default(),
+            fsync_rate: Mutex::new(FsyncRate::default()),
+            started_at: Instant::now(),
+        }
+    }
+
+    /// Records an fsync of the commit log that took `elapsed`.
+    pub(crate) fn record_fsync(&self, elapsed: Duration) {
+        self.commit_fsync_ns.record(elapsed.as_nanos() as u64);
+        let second = self.started_at.elapsed().as_secs();
+        self.fsync_rate.lock().record(second);
+    }
+
+    /// Adds to a memory gauge, if memory tracking is enabled.
+    pub(crate) fn memory_add(&self, gauge: &AtomicU64, bytes: usize) {
+        if self.track_memory {
+            gauge.fetch_add(bytes as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Subtracts from a memory gauge, if memory tracking is enabled.
+    pub(crate) fn memory_sub(&self, gauge: &AtomicU64, bytes: usize) {
+        if self.track_memory {
+            gauge.fetch_sub(bytes as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the lifecycle of the value cache, which releases the bytes
+    /// of the evicted values from the memory gauge.
+    pub(crate) fn cache_lifecycle(&self) -> CacheLifecycle {
+        CacheLifecycle {
+            bytes: self.track_memory.then(|| self.value_cache_bytes.clone()),
+        }
+    }
+
+    /// Takes a point-in-time copy of the counters. The size of the index, the number
+    /// of commits queued for the writer and the value threshold are maintained by the
+    /// index, the queue and the threshold tuning themselves, and are passed in by the
+    /// caller.
+    pub(crate) fn snapshot(
+        &self,
+        index_bytes: u64,
+        commit_queue_depth: u64,
+        value_threshold: u64,
+    ) -> StoreStats {
+        StoreStats {
+            uptime_ms: self.started_at.elapsed().as_millis() as u64,
+            cache_reads: self.cache_reads.load(Ordering::Relaxed),
+            log_reads: self.log_reads.load(Ordering::Relaxed),
+            log_bytes_written: self.log_bytes_written.load(Ordering::Relaxed),
+            value_threshold,
+            read_probes: self.read_probes.load(Ordering::Relaxed),
+            read_probe_mismatches: self.read_probe_mismatches.load(Ordering::Relaxed),
+            write_audits: self.write_audits.load(Ordering::Relaxed),
+            write_audit_failures: self.write_audit_failures.load(Ordering::Relaxed),
+            mirror_batches: self.mirror_batches.load(Ordering::Relaxed),
+            mirror_pending: self.mirror_pending.load(Ordering::Relaxed),
+            mirror_errors: self.mirror_errors.load(Ordering::Relaxed),
+            mirror_lag_ns: self
+                .mirror_last_committed_ts
+                .load(Ordering::Relaxed)
+                .saturating_sub(self.mirror_last_applied_ts.load(Ordering::Relaxed)),
+            ssi_fingerprint_hits: self.ssi_fingerprint_hits.load(Ordering::Relaxed),
+            ssi_fingerprint_false_positives: self
+                .ssi_fingerprint_false_positives
+                .load(Ordering::Relaxed),
+            index_bytes: if self.track_memory { index_bytes } else { 0 },
+            value_cache_bytes: self.value_cache_bytes.load(Ordering::Relaxed),
+            transaction_bytes: self.transaction_bytes.load(Ordering::Relaxed),
+            write_buffer_bytes: self.write_buffer_bytes.load(Ordering::Relaxed),
+            // Filled in by the caller from the index.
+            interned_prefixes: 0,
+            interned_prefix_bytes: 0,
+            interned_key_bytes_saved: 0,
+            commit_queue_depth,
+            commit_batch_entries: self.commit_batch_entries.percentiles(),
+            commit_queue_ns: self.commit_queue_ns.percentiles(),
+            commit_fsync_ns: self.commit_fsync_ns.percentiles(),
+            fsyncs_per_sec: self
+                .fsync_rate
+                .lock()
+                .per_sec(self.started_at.elapsed().as_secs()),
+            space_amp_pct: self.space_amp_pct.load(Ordering::Relaxed),
+            // Filled in by the caller from the fsync gate.
+            fsync_failures: 0,
+            // Filled in by the caller from the commit clock.
+            clock_regressions: 0,
+        }
+    }
+}
+
+/// Counts the fsyncs of the last two seconds, to report the number of fsyncs in the last
+/// full second.
+#[derive(Default)]
+struct FsyncRate {
+    second: u64,
+    current: u64,
+    previous: u64,
+}
+
+impl FsyncRate {
+    fn record(&mut self, second: u64) {
+        self.roll(second);
+        self.current += 1;
+    }
+
+    fn per_sec(&mut self, second: u64) -> u64 {
+        self.roll(second);
+        self.previous
+    }
+
+    fn roll(&mut self, second: u64) {
+        if second != self.second {
+            self.previous = if second == self.second + 1 {
+                self.current
+            } else {
+                0
+            };
+            self.current = 0;
+            self.second = second;
+        }
+    }
+}
+
+/// Number of buckets of a histogram: one for 0, and one for each power of two.
+const HISTOGRAM_BUCKETS: usize = 65;
+
+/// A histogram of values in power of two buckets, which can be updated concurrently.
+pub(crate) struct Histogram {
+    buckets: [AtomicU64; HISTOGRAM_BUCKETS],
+    count: AtomicU64,
+    max: AtomicU64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            count: AtomicU64::new(0),
+            max: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Histogram {
+    /// Records a value. Bucket `i` holds the values in `2^(i-1)..2^i`.
+    pub(crate) fn record(&self, value: u64) {
+        let bucket = (u64::BITS - value.leading_zeros()) as usize;
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.max.fetch_max(value, Ordering::Relaxed);
+    }
+
+    /// Returns the number of values recorded.
+    pub(crate) fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Forgets the recorded values. Values recorded concurrently may be kept or lost.
+    pub(crate) fn reset(&self) {
+        for bucket in &self.buckets {
+            bucket.store(0, Ordering::Relaxed);
+        }
+        self.count.store(0, Ordering::Relaxed);
+        self.max.store(0, Ordering::Relaxed);
+    }
+
+    /// Returns the `p`th percentile of the recorded values, reported as the upper bound of
+    /// its bucket, so it overestimates the value by less than a factor of two.
+    pub(crate) fn percentile(&self, p: u64) -> u64 {
+        let counts: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|b| b.load(Ordering::Relaxed))
+            .collect();
+        percentile(&counts, self.max.load(Ordering::Relaxed), p)
+    }
+
+    /// Returns the percentiles of the recorded values. A percentile is reported as the
+    /// upper bound of its bucket, so it overestimates the value by less than a factor of two.
+    pub(crate) fn percentiles(&self) -> Percentiles {
+        let counts: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|b| b.load(Ordering::Relaxed))
+            .collect();
+        let count: u64 = counts.iter().sum();
+        let max = self.max.load(Ordering::Relaxed);
+
+        if count == 0 {
+            return Percentiles::default();
+        }
+        Percentiles {
+            count,
+            p50: percentile(&counts, max, 50),
+            p90: percentile(&counts, max, 90),
+            p99: percentile(&counts, max, 99),
+            max,
+        }
+    }
+}
+
+/// Returns the `p`th percentile of the values counted in power of two buckets.
+fn percentile(counts: &[u64], max: u64, p: u64) -> u64 {
+    let count: u64 = counts.iter().sum();
+    // The rank of the percentile, counting from 1.
+    let rank = (count * p).div_ceil(100).max(1);
+    let mut seen = 0;
+    for (i, c) in counts.iter().enumerate() {
+        seen += c;
+        if seen >= rank {
+            let upper = if i == 0 { 0 } else { u64::MAX >> (64 - i) };
+            return upper.min(max);
+        }
+    }
+    max
+}
+
+/// Percentiles of a distribution reported in the store stats.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Percentiles {
+    pub count: u64, // Number of values recorded.
+    pub p50: u64,   // Median value.
+    pub p90: u64,   // 90th percentile.
+    pub p99: u64,   // 99th percentile.
+    pub max: u64,   // Largest value.
+}
+
+/// Lifecycle of the value cache, keeping the memory gauge of the cache up to date.
+#[derive(Clone, Default)]
+pub(crate) struct CacheLifecycle {
+    bytes: Option<Arc<AtomicU64>>,
+}
+
+impl Lifecycle<u64, Bytes> for CacheLifecycle {
+    type RequestState = ();
+
+    fn begin_request(&self) -> Self::RequestState {}
+
+    fn on_evict(&self, _state: &mut Self::RequestState, _key: u64, val: Bytes) {
+        if let Some(bytes) = &self.bytes {
+            bytes.fetch_sub(val.len() as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+/// A point-in-time copy of the statistics of a store, returned by
+/// [`Store::stats`](crate::Store::stats).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StoreStats {
+    pub uptime_ms: u64,             // Milliseconds since the store was opened.
+    pub cache_reads: u64,           // Number of values served from the value cache.
+    pub log_reads: u64, // Number of values read from the commit log because they were not cached.
+    pub log_bytes_written: u64, // Number of bytes appended to the commit log since the store was opened, to estimate the wear of flash storage.
+    pub value_threshold: u64, // Largest value stored in the index, see `Options::auto_value_threshold`.
+    pub read_probes: u64,     // Number of cached reads verified against the commit log.
+    pub read_probe_mismatches: u64, // Number of verified reads that did not match the commit log.
+    pub write_audits: u64,    // Number of writes validated, see `Options::write_audit_interval`.
+    pub write_audit_failures: u64, // Number of validated writes that failed their validation.
+    pub mirror_batches: u64,  // Number of batches applied by the mirror.
+    pub mirror_pending: u64,  // Number of batches waiting to be applied by the mirror.
+    pub mirror_errors: u64,   // Number of batches the mirror failed to apply.
+    pub mirror_lag_ns: u64, // Commit time between the last queued and the last applied mirror batch.
+    pub ssi_fingerprint_hits: u64, // Number of conflicts detected on read key fingerprints.
+    pub ssi_fingerprint_false_positives: u64, // Number of fingerprint conflicts ruled out by `Options::ssi_exact_fallback`.
+
+    // Approximate memory used by the subsystems, if `Options::track_memory` is set.
+    pub index_bytes: u64, // Bytes of the keys and value references held by the index.
+    pub value_cache_bytes: u64, // Bytes of the values held by the value cache.
+    pub transaction_bytes: u64, // Bytes of the pending writes of the open transactions.
+    pub write_buffer_bytes: u64, // Bytes of the committed entries waiting to be written.
+
+    // Key prefix interning, if `Options::intern_prefix_len` is set.
+    pub interned_prefixes: u64, // Number of key prefixes interned by the index.
+    pub interned_prefix_bytes: u64, // Bytes of the table of the interned prefixes.
+    pub interned_key_bytes_saved: u64, // Bytes the interning saved on the keys of the index.
+
+    // Commit pipeline. Each batch written to the commit log holds a single transaction.
+    pub commit_queue_depth: u64, // Number of commits waiting for the writer.
+    pub commit_batch_entries: Percentiles, // Entries per batch written to the commit log.
+    pub commit_queue_ns: Percentiles, // Time commits waited in the queue before being written.
+    pub commit_fsync_ns: Percentiles, // Time taken by the fsyncs of the commit log.
+    pub fsyncs_per_sec: u64,     // Number of fsyncs of the commit log in the last full second.
+    pub fsync_failures: u64, // Number of failed writes or syncs of the commit log, see `Options::fsync_failure_policy`.
+    pub clock_regressions: u64, // Number of times the wall clock went back behind the last commit, see `Store::timestamp_source`.
+
+    // Space amplification.
+    pub space_amp_pct: u64, // Space amplification of the store in percent, as last measured by `Store::space_amp`, or 0.
+}
+
+impl StoreStats {
+    /// Lists the values of the stats with their names, the percentiles as one value each.
+    fn fields_mut(&mut self) -> Vec<(String, &mut u64)> {
+        let mut fields = vec![
+            ("uptime_ms".to_string(), &mut self.uptime_ms),
+            ("cache_reads".to_string(), &mut self.cache_reads),
+            ("log_reads".to_string(), &mut self.log_reads),
+            ("log_bytes_written".to_string(), &mut self.log_bytes_written),
+            ("value_threshold".to_string(), &mut self.value_threshold),
+            ("read_probes".to_string(), &mut self.read_probes),
+            (
+                "read_probe_mismatches".to_string(),
+                &mut self.read_probe_mismatches,
+            ),
+            ("write_audits".to_string(), &mut self.write_audits),
+            (
+                "write_audit_failures".to_string(),
+                &mut self.write_audit_failures,
+            ),
+            ("mirror_batches".to_string(), &mut self.mirror_batches),
+            ("mirror_pending".to_string(), &mut self.mirror_pending),
+            ("mirror_errors".to_string(), &mut self.mirror_errors),
+            ("mirror_lag_ns".to_string(), &mut self.mirror_lag_ns),
+            (
+                "ssi_fingerprint_hits".to_string(),
+                &mut self.ssi_fingerprint_hits,
+            ),
+            (
+                "ssi_fingerprint_false_positives".to_string(),
+                &mut self.ssi_fingerprint_false_positives,
+            ),
+            ("index_bytes".to_string(), &mut self.index_bytes),
+            ("value_cache_bytes".to_string(), &mut self.value_cache_bytes),
+            ("transaction_bytes".to_string(), &mut self.transaction_bytes),
+            (
+                "write_buffer_bytes".to_string(),
+                &mut self.write_buffer_bytes,
+            ),
+            ("interned_prefixes".to_string(), &mut self.interned_prefixes),
+            (
+                "interned_prefix_bytes".to_string(),
+                &mut self.interned_prefix_bytes,
+            ),
+            (
+                "interned_key_bytes_saved".to_string(),
+                &mut self.interned_key_bytes_saved,
+            ),
+            (
+                "commit_queue_depth".to_string(),
+                &mut self.commit_queue_depth,
+            ),
+            ("fsyncs_per_sec".to_string(), &mut self.fsyncs_per_sec),
+            ("fsync_failures".to_string(), &mut self.fsync_failures),
+            ("clock_regressions".to_string(), &mut self.clock_regressions),
+            ("space_amp_pct".to_string(), &mut self.space_amp_pct),
+        ];
+        for (name, percentiles) in [
+            ("commit_batch_entries", &mut self.commit_batch_entries),
+            ("commit_queue_ns", &mut self.commit_queue_ns),
+            ("commit_fsync_ns", &mut self.commit_fsync_ns),
+        ] {
+            let Percentiles {
+                count,
+                p50,
+                p90,
+                p99,
+                max,
+            } = percentiles;
+            for (suffix, value) in [
+                ("count", count),
+                ("p50", p50),
+                ("p90", p90),
+                ("p99", p99),
+                ("max", max),
+            ] {
+                fields.push((format!("{}.{}", name, suffix), value));
+            }
+        }
+        fields
+    }
+
+    /// Encodes the stats, as published to the `stats` file of the store directory.
+    pub(crate) fn to_metadata(&self) -> Metadata {
+        let mut metadata = Metadata::new(None);
+        for (name, value) in self.clone().fields_mut() {
+            metadata.put_uint(&name, *value);
+        }
+        metadata
+    }
+
+    /// Decodes published stats. The stats missing from the metadata are left at zero.
+    pub(crate) fn from_metadata(metadata: &Metadata) -> Result<Self> {
+        let mut stats = Self::default();
+        for (name, value) in stats.fields_mut() {
+            if metadata.get(&name).is_some() {
+                *value = metadata.get_uint(&name)?;
+            }
+        }
+        Ok(stats)
+    }
+}
+
+/// Writes the stats to the `stats` file of `dir` on `vfs`, replacing the stats published
+/// before.
+pub(crate) fn publish(vfs: &dyn Vfs, dir: &Path, stats: &StoreStats) -> Result<()> {
+    let path = dir.join(STATS_FILE);
+    let tmp = path.with_extension("tmp");
+    vfs.write(&tmp, &stats.to_metadata().to_bytes()?)?;
+    vfs.rename(&tmp, &path)?;
+    Ok(())
+}
+
+/// Reads the stats last published to `dir`, if any.
+pub(crate) fn read_published(dir: &Path) -> Result<Option<StoreStats>> {
+    let bytes = match fs::read(dir.join(STATS_FILE)) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let mut metadata = Metadata::new(None);
+    metadata.read_from(&mut &bytes[..])?;
+    Ok(Some(StoreStats::from_metadata(&metadata)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Histogram, Percentiles};
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+    use crate::storage::kv::transaction::Durability;
+
+    use tempdir::TempDir;
+
+    #[tokio::test]
+    async fn memory_tracking() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.track_memory = true;
+        opts.max_value_cache_size = 2;
+        let store = Store::new(opts).expect("should create store");
+        assert_eq!(store.stats().index_bytes, 0);
+
+        // Pending writes are accounted to the transaction until it is closed.
+        let mut txn = store.begin().unwrap();
+        txn.set(b"a", &[0; 100]).unwrap();
+        txn.set(b"a", &[0; 200]).unwrap();
+        txn.set(b"b", &[0; 300]).unwrap();
+        assert_eq!(store.stats().transaction_bytes, 502);
+        txn.commit().await.unwrap();
+        drop(txn);
+
+        let stats = store.stats();
+        assert_eq!(stats.transaction_bytes, 0);
+        assert_eq!(stats.write_buffer_bytes, 0);
+        assert!(stats.index_bytes > 0);
+
+        // Values read from the commit log are accounted to the cache until evicted.
+        let txn = store.begin().unwrap();
+        txn.get(b"a").unwrap().unwrap();
+        txn.get(b"b").unwrap().unwrap();
+        assert_eq!(store.stats().value_cache_bytes, 500);
+        for i in 0..10u8 {
+            let mut txn = store.begin().unwrap();
+            txn.set(&[b'c', i], &[0; 100]).unwrap();
+            txn.commit().await.unwrap();
+            store.begin().unwrap().get(&[b'c', i]).unwrap().unwrap();
+        }
+        assert!(store.stats().value_cache_bytes <= 300);
+    }
+
+    #[tokio::test]
+    async fn memory_tracking_disabled() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        let store = Store::new(opts).expect("should create store");
+
+        let mut txn = store.begin().unwrap();
+        txn.set(b"a", &[0; 100]).unwrap();
+        txn.commit().await.unwrap();
+        store.begin().unwrap().get(b"a").unwrap().unwrap();
+
+        let stats = store.stats();
+        assert_eq!(stats.index_bytes, 0);
+        assert_eq!(stats.value_cache_bytes, 0);
+        assert_eq!(stats.transaction_bytes, 0);
+    }
+
+    #[test]
+    fn histogram_percentiles() {
+        let histogram = Histogram::default();
+        assert_eq!(histogram.percentiles(), Percentiles::default());
+
+        for value in 1..=100 {
+            histogram.record(value);
+        }
+        assert_eq!(
+            histogram.percentiles(),
+            Percentiles {
+                count: 100,
+                p50: 63,
+                p90: 100,
+                p99: 100,
+                max: 100,
+            }
+        );
+
+        histogram.record(0);
+        histogram.record(u64::MAX);
+        let percentiles = histogram.percentiles();
+        assert_eq!(percentiles.count, 102);
+        assert_eq!(percentiles.max, u64::MAX);
+    }
+
+    #[tokio::test]
+    async fn commit_pipeline() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        let store = Store::new(opts).expect("should create store");
+
+        for i in 0..10u8 {
+            let mut txn = store.begin().unwrap();
+            txn.set_durability(Durability::Immediate);
+            txn.set(&[b'a', i], b"v").unwrap();
+            txn.set(&[b'b', i], b"v").unwrap();
+            txn.commit().await.unwrap();
+        }
+        let mut txn = store.begin().unwrap();
+        txn.set(b"eventual", b"v").unwrap();
+        txn.commit().await.unwrap();
+
+        let stats = store.stats();
+        assert_eq!(stats.commit_queue_depth, 0);
+        assert_eq!(stats.commit_batch_entries.count, 11);
+        assert_eq!(stats.commit_batch_entries.p50, 2);
+        assert_eq!(stats.commit_batch_entries.max, 2);
+        assert_eq!(stats.commit_queue_ns.count, 11);
+        assert_eq!(stats.commit_fsync_ns.count, 10);
+        assert!(stats.commit_fsync_ns.max > 0);
+    }
+
+    #[tokio::test]
+    async fn published_stats() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.stats_publish_interval = 1;
+        let store = Store::new(opts).expect("should create store");
+        assert_eq!(Store::published_stats(temp_dir.path()).unwrap(), None);
+
+        // The writer publishes the stats after a commit.
+        let mut txn = store.begin().unwrap();
+        txn.set(b"a", b"v").unwrap();
+        txn.commit().await.unwrap();
+        let published = loop {
+            if let Some(stats) = Store::published_stats(temp_dir.path()).unwrap() {
+                break stats;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+        };
+        assert_eq!(published.commit_batch_entries.count, 1);
+
+        store.publish_stats().unwrap();
+        let stats = store.stats();
+        let published = Store::published_stats(temp_dir.path()).unwrap().unwrap();
+        assert_eq!(published.commit_batch_entries, stats.commit_batch_entries);
+        assert_eq!(published.value_threshold, 64);
+        assert!(published.uptime_ms <= stats.uptime_ms);
+
+        // Closing the store removes the published stats.
+        store.close().await.unwrap();
+        assert_eq!(Store::published_stats(temp_dir.path()).unwrap(), None);
+    }
+}
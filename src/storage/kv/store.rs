@@ -1,42 +1,97 @@
-use std::sync::atomic::AtomicBool;
+use std::ops::{Bound, RangeBounds};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::vec;
 
 use async_channel::{bounded, Receiver, Sender};
 use futures::{select, FutureExt};
-use tokio::task::{spawn, JoinHandle};
+use tokio::task::{spawn, spawn_blocking, JoinHandle};
 
 use bytes::{Bytes, BytesMut};
-use hashbrown::HashMap;
-use parking_lot::RwLock;
-use quick_cache::sync::Cache;
-use tokio::sync::Mutex as AsyncMutex;
+use hashbrown::{HashMap, HashSet};
+use parking_lot::{Mutex, RwLock};
+use quick_cache::{sync::Cache, DefaultHashBuilder, UnitWeighter};
+use tokio::sync::{Mutex as AsyncMutex, OwnedSemaphorePermit, Semaphore};
 use vart::art::KV;
 
+#[cfg(feature = "arrow")]
+use crate::storage::kv::{export, transaction::ScanResult};
+
 use crate::storage::{
     kv::{
+        active::{ActiveTransactions, TransactionInfo},
+        audit::{WriteAudit, WriteValidator},
+        backup,
+        batch::{self, WriteBatch},
+        checkpoint::IndexCheckpoint,
+        clear,
+        clock::{CommitClock, TimestampSource},
+        compaction::{self, CompactionFilter, Marker, ProgressCallback},
+        compression::{self, CompressionRule, Compressor},
+        conflict::{self, ConflictPolicy},
+        consistency::{CommitToken, VersionWatch},
+        coordinator,
+        cursor::Cursor,
+        durable::DurabilityWatch,
         entry::{Entry, TxRecord, ValueRef},
+        envelope::{self, Migrations},
         error::{Error, Result},
-        indexer::Indexer,
-        option::Options,
+        events::{self, Activity, Level, Value},
+        flags::FlagIndex,
+        fsync::{FsyncGate, FsyncHook},
+        gc::{self, DiskStats, GcEstimate, GcSchedule, SegmentUsage},
+        headroom::Headroom,
+        indexer::{IndexVersion, Indexer, KeyRange, Retention},
+        ingest::IngestBuffer,
+        intern::KeyCodec,
+        invariant::{InvariantHook, InvariantViolation, Invariants},
+        iterator::ScanIterator,
+        jobs::{JobKind, Jobs},
+        lock::{self, LockToken},
+        merge::{self, MergeOperator, Merger},
+        meta::Metadata as KvMetadata,
+        mirror::{Mirror, MirrorBatch, MirrorTarget},
+        option::{MissingSegments, Options, VersionRetention},
         oracle::Oracle,
+        partition,
+        queue::{Claim, Queues},
+        quota::{Quota, QuotaHook},
+        range_delete::{self, Fields},
         reader::{Reader, TxReader},
+        redundancy::Redundancy,
         repair::{repair_last_corrupted_segment, restore_repair_files},
+        rewrite, sample,
+        segments::{SegmentKeyRanges, Unavailable},
+        stats::{self, CacheLifecycle, Stats, StoreStats},
+        stream::Streams,
+        sweep::{self, Sweeper},
+        threshold::ValueThreshold,
         transaction::{Mode, Transaction},
+        util::now,
+        view::ReadView,
+        watch::{self, Watches},
     },
     log::{
-        aof::log::Aol, write_field, Error as LogError, Metadata, MultiSegmentReader,
-        Options as LogOptions, SegmentRef, BLOCK_SIZE,
+        aof::log::Aol, list_segment_ids, write_field, Error as LogError, Metadata,
+        MultiSegmentReader, Options as LogOptions, SegmentRef, BLOCK_SIZE,
     },
 };
 
 use super::transaction::Durability;
 
+/// Delay before the second attempt of `Store::update`, doubled for each of the next ones.
+const UPDATE_BACKOFF: Duration = Duration::from_millis(1);
+/// Longest delay between two attempts of `Store::update`.
+const UPDATE_MAX_BACKOFF: Duration = Duration::from_millis(100);
+
 pub(crate) struct StoreInner {
     pub(crate) core: Arc<Core>,
     pub(crate) is_closed: AtomicBool,
     stop_tx: Sender<()>,
     task_runner_handle: Arc<AsyncMutex<Option<JoinHandle<()>>>>,
+    sweeper: Option<Sweeper>,
 }
 
 // Inner representation of the store. The wrapper will handle the asynchronous closing of the store.
@@ -45,18 +100,35 @@ impl StoreInner {
     /// It creates a new core with the options and wraps it in an atomic reference counter.
     /// It returns the store.
     pub fn new(opts: Options) -> Result<Self> {
+        Self::with_core(|writes_tx| Core::new(opts, writes_tx))
+    }
+
+    /// Opens the store in `dir` read-only, from its last index checkpoint.
+    fn open_checkpoint(dir: &Path) -> Result<Self> {
+        let opts = Options {
+            dir: dir.to_path_buf(),
+            ..Options::new()
+        };
+        let opts = Core::persisted_options(&opts)?.ok_or(Error::ManifestNotFound)?;
+        Self::with_core(|writes_tx| Core::open_checkpoint(opts, writes_tx))
+    }
+
+    fn with_core(core: impl FnOnce(Sender<Task>) -> Result<Core>) -> Result<Self> {
         // TODO: make this channel size configurable
         let (writes_tx, writes_rx) = bounded(10000);
         let (stop_tx, stop_rx) = bounded(1);
 
-        let core = Arc::new(Core::new(opts, writes_tx)?);
+        let core = Arc::new(core(writes_tx)?);
         let task_runner_handle = TaskRunner::new(core.clone(), writes_rx, stop_rx).spawn();
+        let sweeper = Sweeper::start(&core);
+        compaction::merge_tiny_segments(&core);
 
         Ok(Self {
             core,
             stop_tx,
             is_closed: AtomicBool::new(false),
             task_runner_handle: Arc::new(AsyncMutex::new(Some(task_runner_handle))),
+            sweeper,
         })
     }
 
@@ -66,6 +138,13 @@ impl StoreInner {
             return Ok(());
         }
 
+        if let Some(sweeper) = &self.sweeper {
+            sweeper.stop();
+        }
+
+        // Let the background jobs queued finish, while the writer commits their writes.
+        self.core.jobs.stop().await;
+
         // Send stop signal
         self.stop_tx
             .send(())
@@ -82,6 +161,10 @@ impl StoreInner {
             })?;
         }
 
+        // Apply the mutations still queued for the mirror.
+        self.core.mirror.stop().await?;
+        self.core.watches.close();
+
         self.core.close()?;
 
         self.is_closed
@@ -100,6 +183,20 @@ impl StoreInner {
 #[derive(Default)]
 pub struct Store {
     pub(crate) inner: Option<StoreInner>,
+    suspended: Option<Box<Suspended>>,
+}
+
+/// What a suspended store is resumed with, see `Store::suspend`.
+struct Suspended {
+    opts: Options,
+    invariant_hook: Option<InvariantHook>,
+    quota_hook: Option<QuotaHook>,
+    write_validator: Option<WriteValidator>,
+    soft_quota_hook: Option<QuotaHook>,
+    fsync_hook: Option<FsyncHook>,
+    merge_operator: Option<MergeOperator>,
+    compaction_filter: Option<Arc<dyn CompactionFilter>>,
+    migrations: Migrations,
 }
 
 impl Store {
@@ -107,6 +204,23 @@ impl Store {
     pub fn new(opts: Options) -> Result<Self> {
         Ok(Self {
             inner: Some(StoreInner::new(opts)?),
+            suspended: None,
+        })
+    }
+
+    /// Opens the store in `dir` read-only, as of its last index checkpoint, while the store
+    /// may be open and written to by another process.
+    ///
+    /// The index is loaded from the checkpoint only: the commit log written after it is not
+    /// replayed, and nothing is written to the directory, so no repair or recovery of the
+    /// store is done. Opening is then about as fast as reading the checkpoint, and a job can
+    /// open a fresh view of the store after each `checkpoint_index`. The transactions begun
+    /// on the store must be read-only, and other writes return `Error::StoreReadOnly`. It
+    /// returns `Error::CheckpointUnavailable` if the store has no checkpoint that can be read.
+    pub fn open_checkpoint(dir: &Path) -> Result<Self> {
+        Ok(Self {
+            inner: Some(StoreInner::open_checkpoint(dir)?),
+            suspended: None,
         })
     }
 
@@ -114,7 +228,7 @@ impl Store {
     /// It creates a new transaction with the core and read-write mode, and sets the read timestamp from the oracle.
     /// It returns the transaction.
     pub fn begin(&self) -> Result<Transaction> {
-        let txn = Transaction::new(self.inner.as_ref().unwrap().core.clone(), Mode::ReadWrite)?;
+        let txn = Transaction::new(self.open_core()?.clone(), Mode::ReadWrite)?;
         Ok(txn)
     }
 
@@ -122,10 +236,90 @@ impl Store {
     /// It creates a new transaction with the core and the given mode, and sets the read timestamp from the oracle.
     /// It returns the transaction.
     pub fn begin_with_mode(&self, mode: Mode) -> Result<Transaction> {
-        let txn = Transaction::new(self.inner.as_ref().unwrap().core.clone(), mode)?;
+        let txn = Transaction::new(self.open_core()?.clone(), mode)?;
         Ok(txn)
     }
 
+    /// Begins a new transaction with the given mode, waiting for one of the open transactions
+    /// to finish if `max_active_transactions` are open. Waiting callers are admitted in the
+    /// order in which they started waiting. `begin` and `begin_with_mode` return
+    /// `Error::TooManyTransactions` instead of waiting.
+    pub async fn begin_queued(&self, mode: Mode) -> Result<Transaction> {
+        Transaction::new_queued(self.open_core()?.clone(), mode).await
+    }
+
+    /// Returns the core of the store, or `Error::StoreSuspended` if the store is suspended.
+    fn open_core(&self) -> Result<&Arc<Core>> {
+        match &self.inner {
+            Some(inner) => Ok(&inner.core),
+            None if self.suspended.is_some() => Err(Error::StoreSuspended),
+            None => Err(Error::StoreClosed),
+        }
+    }
+
+    /// Waits until the commit of `token` is visible to the transactions begun on the store, as
+    /// the ones of the store do once committed, and those of another store once the store
+    /// applied them at their versions, as its mirrors do. It returns `Error::StoreClosed` if
+    /// the store is closed or suspended first. The wait is bounded with the timeout of the
+    /// async runtime, if needed.
+    pub async fn wait_for_version(&self, token: CommitToken) -> Result<()> {
+        self.open_core()?.versions.wait_for(token.version()).await
+    }
+
+    /// Returns the token of the last commit visible to the transactions begun on the store.
+    pub fn latest_commit_token(&self) -> Result<CommitToken> {
+        Ok(CommitToken::new(self.open_core()?.versions.version()))
+    }
+
+    /// Returns a buffer of writes for one loading thread, committing them in batches of
+    /// `batch_size` entries (at most `max_entries_per_txn`).
+    pub fn ingest_buffer(&self, batch_size: usize) -> IngestBuffer {
+        IngestBuffer::new(self.inner.as_ref().unwrap().core.clone(), batch_size)
+    }
+
+    /// Applies the writes of a batch atomically, without the snapshot and the conflict checks
+    /// of a transaction, see [`WriteBatch`].
+    pub async fn write_batch(&self, batch: WriteBatch) -> Result<()> {
+        batch::apply(self.open_core()?, batch).await
+    }
+
+    /// Drops all the keys of the store atomically, including the ones of its streams, queues
+    /// and other system keyspaces, and leaves it open. The commit log moves on to a new
+    /// segment, and the segments before it are removed once the clear is durable, so that the
+    /// space of the store is reclaimed without closing it.
+    ///
+    /// A clear is committed as one transaction: the transactions that read keys it drops
+    /// conflict with it, and the ones committed after it write to the empty store. The values
+    /// stored in the removed segments can no longer be read by the transactions begun before
+    /// it. The running mirrors are sent the clear, and clear the stores they mirror to.
+    pub async fn clear(&self) -> Result<()> {
+        clear::clear(self.open_core()?).await
+    }
+
+    /// Writes a tombstone for each key whose latest version is expired, and returns the number
+    /// of keys deleted. The expired keys are only hidden from the reads until then: once
+    /// deleted, they are dropped from the index by `shrink_index`, and their values from the
+    /// commit log by `Store::rewrite`, as the keys deleted by the transactions are.
+    ///
+    /// The keys are deleted in transactions of up to `max_entries_per_txn` keys, which
+    /// conflict with the writes of the keys they delete, so that a key written again since it
+    /// expired is kept. Only the user keys are swept, as the subsystems expire their system
+    /// keys on their own. The store sweeps itself every `Options::expiry_sweep_interval`
+    /// milliseconds in the background if set.
+    pub async fn sweep_expired(&self) -> Result<usize> {
+        sweep::sweep(self.open_core()?).await
+    }
+
+    /// Applies a batch of mutations received from another store, such as the batches of a
+    /// mirror sink, in one transaction, resolving the mutations of the keys written locally
+    /// with `policy`. It returns the number of mutations applied, and
+    /// `Error::TransactionReadConflict` if a key read to resolve a mutation is written
+    /// concurrently, in which case nothing is applied and the batch can be applied again.
+    /// A batch of a clear clears the store first, whatever the policy.
+    pub async fn apply_batch(&self, batch: &MirrorBatch, policy: &ConflictPolicy) -> Result<usize> {
+        conflict::apply_batch(self.open_core()?, batch, policy).await
+    }
+
     /// Executes a function in a read-only transaction.
     /// It begins a new read-only transaction and executes the function with the transaction.
     /// It returns the result of the function.
@@ -150,6 +344,681 @@ impl Store {
         Ok(())
     }
 
+    /// Runs a function in a read-write transaction and commits it, running them again while
+    /// the commit fails with `Error::TransactionReadConflict`, up to
+    /// `Options::max_update_attempts` times. The attempts are spaced by a delay doubling from
+    /// 1 millisecond up to 100 milliseconds. The function may run more than once, so it should
+    /// have no effect outside of the transaction. An error returned by the function is
+    /// returned right away. It returns the value returned by the function on the attempt
+    /// that committed.
+    pub async fn update<T, F>(&self, mut f: F) -> Result<T>
+    where
+        F: FnMut(&mut Transaction) -> Result<T>,
+    {
+        let attempts = self.open_core()?.opts.max_update_attempts.max(1);
+        let mut backoff = UPDATE_BACKOFF;
+        let mut attempt = 1;
+        loop {
+            let mut txn = self.begin()?;
+            let value = f(&mut txn)?;
+            match txn.commit().await {
+                Ok(()) => return Ok(value),
+                Err(Error::TransactionReadConflict) if attempt < attempts => {}
+                Err(e) => return Err(e),
+            }
+
+            // The runtime may not drive timers, so the delay is slept on the blocking pool.
+            spawn_blocking(move || std::thread::sleep(backoff))
+                .await
+                .map_err(|e| {
+                    Error::ReceiveError(format!(
+                        "Error occurred while waiting to retry the transaction. JoinError: {}",
+                        e
+                    ))
+                })?;
+            backoff = (backoff * 2).min(UPDATE_MAX_BACKOFF);
+            attempt += 1;
+        }
+    }
+
+    /// Writes `new` to a key if its current value is `expected` in a transaction of its own,
+    /// see [`Transaction::cas`]. The comparison is made again if the commit conflicts, as with
+    /// `Store::update`.
+    pub async fn cas(&self, key: &[u8], expected: Option<&[u8]>, new: Option<&[u8]>) -> Result<()> {
+        self.update(|txn| txn.cas(key, expected, new)).await
+    }
+
+    /// Adds `delta` to the counter stored at a key in a write-only transaction of its own, see
+    /// [`Transaction::incr`], and returns the new value of the counter.
+    pub async fn incr(&self, key: &[u8], delta: u64) -> Result<u64> {
+        let mut txn = self.begin_with_mode(Mode::WriteOnly)?;
+        txn.incr(key, delta)?;
+        txn.commit().await?;
+        Ok(txn.counter(key).unwrap_or_default())
+    }
+
+    /// Subtracts `delta` from the counter stored at a key in a write-only transaction of its
+    /// own, see [`Transaction::decr`], and returns the new value of the counter.
+    pub async fn decr(&self, key: &[u8], delta: u64) -> Result<u64> {
+        let mut txn = self.begin_with_mode(Mode::WriteOnly)?;
+        txn.decr(key, delta)?;
+        txn.commit().await?;
+        Ok(txn.counter(key).unwrap_or_default())
+    }
+
+    /// Appends a value to the end of a stream and returns the sequence number assigned to it.
+    /// Sequence numbers of a stream start at 0 and increase with every append. They are
+    /// allocated before the append commits, so an append whose commit fails leaves a gap:
+    /// the sequence numbers read back are increasing, but not always consecutive.
+    /// If `max_stream_length` is set, the entry falling out of the retention window is dropped.
+    pub async fn append_to_stream(&self, stream_key: &[u8], value: &[u8]) -> Result<u64> {
+        let core = &self.inner.as_ref().unwrap().core;
+        core.streams.append(core, stream_key, value).await
+    }
+
+    /// Reads the entries of a stream whose sequence numbers fall in the given range.
+    /// It returns the sequence number and value of each entry, in sequence order.
+    pub fn read_stream<R>(&self, stream_key: &[u8], range: R) -> Result<Vec<(u64, Vec<u8>)>>
+    where
+        R: RangeBounds<u64>,
+    {
+        Streams::read(&self.inner.as_ref().unwrap().core, stream_key, range)
+    }
+
+    /// Deletes the entries of a stream with a sequence number lower than `before_seq`.
+    /// It returns the number of entries deleted.
+    pub async fn trim_stream(&self, stream_key: &[u8], before_seq: u64) -> Result<usize> {
+        Streams::trim(&self.inner.as_ref().unwrap().core, stream_key, before_seq).await
+    }
+
+    /// Adds an item to the end of a queue.
+    pub async fn enqueue(&self, queue: &[u8], payload: &[u8]) -> Result<()> {
+        let core = &self.inner.as_ref().unwrap().core;
+        core.queues.enqueue(core, queue, payload).await
+    }
+
+    /// Claims the oldest visible item of a queue, hiding it from other claimers for the duration
+    /// of the lease. It returns `None` if the queue has no visible item.
+    /// The claim must be acknowledged with `ack` once processed, or released with `nack`.
+    /// If neither happens before the lease expires, the item can be claimed again.
+    pub async fn claim(&self, queue: &[u8], lease: Duration) -> Result<Option<Claim>> {
+        Queues::claim(&self.inner.as_ref().unwrap().core, queue, lease).await
+    }
+
+    /// Acknowledges a claimed item, removing it from the queue.
+    /// It returns `Error::ClaimLost` if the item is no longer held by this claim.
+    pub async fn ack(&self, claim: &Claim) -> Result<()> {
+        Queues::ack(&self.inner.as_ref().unwrap().core, claim).await
+    }
+
+    /// Releases a claimed item, making it visible to claimers again after the given delay.
+    /// It returns `Error::ClaimLost` if the item is no longer held by this claim.
+    pub async fn nack(&self, claim: &Claim, delay: Duration) -> Result<()> {
+        Queues::nack(&self.inner.as_ref().unwrap().core, claim, delay).await
+    }
+
+    /// Acquires a durable advisory lock with the given name, held until it is unlocked or the
+    /// `ttl` lease expires. It returns `Error::LockHeld` if the lock is held by someone else.
+    pub async fn lock(&self, name: &[u8], ttl: Duration) -> Result<LockToken> {
+        lock::lock(&self.inner.as_ref().unwrap().core, name, ttl).await
+    }
+
+    /// Releases an advisory lock acquired with `lock`.
+    /// It returns `Error::LockNotHeld` if the token no longer holds the lock.
+    pub async fn unlock(&self, token: &LockToken) -> Result<()> {
+        lock::unlock(&self.inner.as_ref().unwrap().core, token).await
+    }
+
+    /// Registers a schema migration for the enveloped values under `prefix`.
+    /// Values written with `Transaction::set_versioned` at a version older than `version` are
+    /// converted with `f(old_version, payload)` when read with `Transaction::get_versioned`,
+    /// and rewritten when the reading transaction commits. The values at a newer version, as
+    /// written by a newer release of the application, are returned as they are.
+    pub fn register_migration<F>(&self, prefix: &[u8], version: u8, f: F)
+    where
+        F: Fn(u8, &[u8]) -> Vec<u8> + Send + Sync + 'static,
+    {
+        let core = &self.inner.as_ref().unwrap().core;
+        core.migrations.register(prefix, version, Arc::new(f));
+    }
+
+    /// Eagerly rewrites the enveloped values under `prefix` older than `version`, using
+    /// `f(old_version, payload)` to convert them. The migration is also registered for lazy
+    /// reads, so reads see migrated values while the rewrite is in progress.
+    /// Values are rewritten in batches of `max_entries_per_txn`; to run the migration as a
+    /// background job, spawn it on a task holding an `Arc<Store>`.
+    /// It returns the number of values rewritten.
+    pub async fn migrate_values<F>(&self, prefix: &[u8], version: u8, f: F) -> Result<usize>
+    where
+        F: Fn(u8, &[u8]) -> Vec<u8> + Send + Sync + 'static,
+    {
+        envelope::migrate(
+            &self.inner.as_ref().unwrap().core,
+            prefix,
+            version,
+            Arc::new(f),
+        )
+        .await
+    }
+
+    /// Starts mirroring the mutations committed from now on to the target, asynchronously and
+    /// in commit order. The lag of the mirror is reported in the store stats.
+    /// It returns `Error::MirrorAlreadyRunning` if a mirror is already running.
+    pub fn start_mirror(&self, target: MirrorTarget) -> Result<()> {
+        let core = &self.inner.as_ref().unwrap().core;
+        core.mirror.start(core, target)
+    }
+
+    /// Stops mirroring, waiting for the queued mutations to be applied to the target.
+    /// The mirror is also stopped when the store is closed.
+    pub async fn stop_mirror(&self) -> Result<()> {
+        self.inner.as_ref().unwrap().core.mirror.stop().await
+    }
+
+    /// Returns an iterator over the live entries of the range, in key order. The entries are
+    /// read in batches from a snapshot taken when the iterator is created, see [`ScanIterator`].
+    pub fn iter<'a, R>(&self, range: R) -> Result<ScanIterator>
+    where
+        R: RangeBounds<&'a [u8]>,
+    {
+        ScanIterator::new(&self.inner.as_ref().unwrap().core, range)
+    }
+
+    /// Returns the live entries of the range as Arrow record batches of the schema `schema`,
+    /// in key order, see [`ArrowExport`](crate::ArrowExport). `decode` is called with the
+    /// entries of each batch and returns the columns of the batch, one array per field of the
+    /// schema, each holding a row per entry.
+    #[cfg(feature = "arrow")]
+    pub fn export_arrow<'a, R, F>(
+        &self,
+        range: R,
+        schema: arrow_schema::SchemaRef,
+        decode: F,
+    ) -> Result<export::ArrowExport<F>>
+    where
+        R: RangeBounds<&'a [u8]>,
+        F: FnMut(
+            &[ScanResult],
+        )
+            -> std::result::Result<Vec<arrow_array::ArrayRef>, arrow_schema::ArrowError>,
+    {
+        let entries = ScanIterator::new(&self.inner.as_ref().unwrap().core, range)?;
+        Ok(export::ArrowExport::new(entries, schema, decode))
+    }
+
+    /// Writes the live entries of the range to `writer` as a Parquet file of the schema
+    /// `schema`, decoded as [`export_arrow`](Store::export_arrow) decodes them, and returns
+    /// the number of rows written. The errors of `decode` and of the writer are returned as
+    /// `Error::ExportError`.
+    #[cfg(feature = "arrow")]
+    pub fn export_parquet<'a, R, F, W>(
+        &self,
+        range: R,
+        schema: arrow_schema::SchemaRef,
+        decode: F,
+        writer: W,
+    ) -> Result<u64>
+    where
+        R: RangeBounds<&'a [u8]>,
+        F: FnMut(
+            &[ScanResult],
+        )
+            -> std::result::Result<Vec<arrow_array::ArrayRef>, arrow_schema::ArrowError>,
+        W: std::io::Write + Send,
+    {
+        export::write_parquet(self.export_arrow(range, schema, decode)?, writer)
+    }
+
+    /// Returns a read-only view of the store at its current version, which can be cloned
+    /// and read from many threads concurrently, see [`ReadView`].
+    pub fn snapshot(&self) -> Result<ReadView> {
+        ReadView::new(self.open_core()?)
+    }
+
+    /// Returns a cursor over the live entries of the store, read from a snapshot taken when
+    /// the cursor is created, see [`Cursor`].
+    pub fn cursor(&self) -> Result<Cursor> {
+        Cursor::new(&self.inner.as_ref().unwrap().core)
+    }
+
+    /// Returns the keys in the range whose latest version carries the user flag `flag` (a bit
+    /// position, from 0 to 63), in key order. The flag must be listed in
+    /// `Options::indexed_flags`, otherwise `Error::FlagNotIndexed` is returned.
+    pub fn flagged_keys<'a, R>(&self, flag: u8, range: R) -> Result<Vec<Vec<u8>>>
+    where
+        R: RangeBounds<&'a [u8]>,
+    {
+        let core = &self.inner.as_ref().unwrap().core;
+        core.flag_index.read().flagged(flag, range)
+    }
+
+    /// Returns the ids of the commit log segments that may hold keys in the range, in order.
+    /// The other segments hold no version of any key in the range.
+    pub fn segments_for_range<'a, R>(&self, range: R) -> Vec<u64>
+    where
+        R: RangeBounds<&'a [u8]>,
+    {
+        let core = &self.inner.as_ref().unwrap().core;
+        core.segment_keys.lock().overlapping(range)
+    }
+
+    /// Returns the ids of the commit log segments found missing when the store was opened in
+    /// `MissingSegments::Degraded` mode, in order. The keys they may have held are
+    /// unavailable until the store is reopened with the segments restored.
+    pub fn missing_segments(&self) -> Vec<u64> {
+        let core = &self.inner.as_ref().unwrap().core;
+        core.unavailable.ids()
+    }
+
+    /// Returns up to `n` keys sampled uniformly from the range, in key order.
+    /// This is useful to pick split points or to estimate statistics without scanning values:
+    /// the keys of the range are walked once, but their values are not read.
+    pub fn sample_keys<'a, R>(&self, n: usize, range: R) -> Result<Vec<Vec<u8>>>
+    where
+        R: RangeBounds<&'a [u8]>,
+    {
+        sample::sample_keys(&self.inner.as_ref().unwrap().core, n, range)
+    }
+
+    /// Returns up to `parts - 1` keys splitting the range into partitions holding roughly the
+    /// same number of bytes of keys and values, so that the range can be scanned by several
+    /// workers in parallel. Each split key is the first key of a partition: the partitions are
+    /// `[start, s1)`, `[s1, s2)`, ..., `[sn, end)`. The keys of the range are walked twice,
+    /// but their values are not read.
+    pub fn partition_range<'a, R>(&self, range: R, parts: usize) -> Result<Vec<Vec<u8>>>
+    where
+        R: RangeBounds<&'a [u8]> + Clone,
+    {
+        partition::partition_range(&self.inner.as_ref().unwrap().core, range, parts)
+    }
+
+    /// Rewrites the store in `dir` into a freshly created store configured with `new_opts`,
+    /// and atomically swaps the directories once the copy is complete. This changes options
+    /// which are otherwise fixed for the lifetime of the store, such as the segment size or
+    /// the compression rules. Only the latest version of each key is copied.
+    /// The store must be closed while it is rewritten; `new_opts.dir` is ignored, and the store
+    /// is read from and rewritten on the filesystem of `new_opts.vfs`.
+    /// If the rewrite is interrupted, it is completed or rolled back when the store is next opened.
+    /// It returns the number of keys copied.
+    pub async fn rewrite(dir: &Path, new_opts: Options) -> Result<usize> {
+        rewrite::rewrite(dir, new_opts).await
+    }
+
+    /// Writes a backup of the transactions committed after version `since` to `path`, and
+    /// returns the version it goes up to. A full backup starts after version 0, and each
+    /// incremental backup after the version returned by the previous one. Commits continue
+    /// while the backup is written; it holds the transactions committed when it started.
+    /// The commit log is read from its start, and the transactions are kept with their
+    /// versions, so a store rewritten with `Store::rewrite` needs a new full backup.
+    pub async fn backup(&self, path: &Path, since: u64) -> Result<u64> {
+        backup::backup(&self.inner.as_ref().unwrap().core, path, since).await
+    }
+
+    /// Restores the backup in `path` into the store in `dir`, which must not be open, and
+    /// returns the version it is restored up to. A full backup creates the store, and the
+    /// incremental backups are restored on top of it in order.
+    pub async fn restore(path: &Path, dir: &Path) -> Result<u64> {
+        backup::restore(path, dir).await
+    }
+
+    /// Checks the backups in `paths`, a full or incremental backup followed by the
+    /// incremental backups taken after it, without restoring them, and returns the version
+    /// they go up to. The checksums of the transactions and the chain of the backups and of
+    /// their options are checked, so that a backup can be validated before the older ones are
+    /// deleted. `Error::InvalidBackup` is returned for a backup that would not restore.
+    pub fn verify_backup(paths: &[&Path]) -> Result<u64> {
+        backup::verify(paths)
+    }
+
+    /// Returns the options persisted for the store in `dir` without opening it,
+    /// or `None` if no store has been created in `dir`.
+    pub fn persisted_options(dir: &Path) -> Result<Option<Options>> {
+        let mut opts = Options::new();
+        opts.dir = dir.to_path_buf();
+        Core::persisted_options(&opts)
+    }
+
+    /// Commits transactions of different stores of the process atomically: either all of
+    /// them commit or none does. On error, none of the transactions is committed.
+    ///
+    /// The transactions are prepared first, which takes the commit lock of each store, and
+    /// each store records how to undo its transaction along with it. If the process stops
+    /// before all of them are committed, the transactions that were are undone by
+    /// `recover_commits`, which should be called with the same stores after they are
+    /// reopened. The transactions must belong to different stores.
+    pub async fn commit_all(txns: &mut [Transaction]) -> Result<()> {
+        coordinator::commit_all(txns).await
+    }
+
+    /// Settles the atomic commits of `commit_all` that were in progress when the process
+    /// stopped. The stores are given in the same order as the transactions were to
+    /// `commit_all`, and this is done before they are written to. It returns the number of
+    /// commits undone.
+    pub async fn recover_commits(stores: &[&Store]) -> Result<usize> {
+        let cores: Vec<Arc<Core>> = stores
+            .iter()
+            .map(|store| store.inner.as_ref().unwrap().core.clone())
+            .collect();
+        coordinator::recover(&cores).await
+    }
+
+    /// Rebuilds the in-memory index compactly, keeping only the latest version of the keys
+    /// and dropping the deleted ones, which reclaims the memory held by old versions and
+    /// tombstones. The older versions kept by `Options::version_retention`, and the ones
+    /// committed after the watermark set with `set_gc_watermark`, are kept. Open transactions keep reading from the index as it was when they began.
+    /// Commits wait while the index is rebuilt, which takes time proportional to its size.
+    /// It returns the approximate number of bytes reclaimed.
+    pub fn shrink_index(&self) -> Result<u64> {
+        self.inner.as_ref().unwrap().core.shrink_index()
+    }
+
+    /// Sets the GC watermark of the index: `shrink_index` keeps the versions of the keys
+    /// committed at or after `ts`, and the older ones that `Options::version_retention`
+    /// keeps, bounding the history read by `Transaction::get_at_ts` and
+    /// `Transaction::history`. Without a watermark, only the retained versions are kept. The
+    /// versions dropped stay visible to the transactions begun before the shrink, which read
+    /// the index as it was when they began. The watermark is not persisted.
+    pub fn set_gc_watermark(&self, ts: u64) {
+        let core = &self.inner.as_ref().unwrap().core;
+        core.gc_watermark.store(ts, Ordering::Release);
+    }
+
+    /// Estimates the bytes of the commit log that `Store::rewrite` would reclaim, and the
+    /// bytes it would read and write, from a sample of the segments, so that a scheduler can
+    /// decide whether a rewrite is worth its cost. See [`GcEstimate`].
+    pub fn gc_estimate(&self) -> Result<GcEstimate> {
+        gc::estimate(&self.inner.as_ref().unwrap().core)
+    }
+
+    /// Returns the space amplification of the store: the bytes of its files on disk, the
+    /// space reserved aside, divided by the bytes of the keys and values, as stored, of the
+    /// latest versions of the keys that are not deleted. It is 0 for a store without live
+    /// keys or that does not persist its data. Measuring it reads the whole index, and
+    /// records it in the stats, see `StoreStats::space_amp_pct`.
+    ///
+    /// The files of the store hold the older versions of the keys, the tombstones and the
+    /// index checkpoints, so that the space amplification grows with the writes until the
+    /// commit log is compacted, see `Options::target_space_amp`.
+    pub fn space_amp(&self) -> Result<f64> {
+        gc::space_amp(&self.inner.as_ref().unwrap().core)
+    }
+
+    /// Compacts the commit log in place: the versions the index keeps, the ones
+    /// `shrink_index` keeps, are rewritten at the end of the log with their version and
+    /// commit timestamp, the index is rebuilt with them, and the segments before are removed.
+    /// It returns the number of bytes of the commit log reclaimed.
+    ///
+    /// Unlike `Store::rewrite`, the store stays open, but commits wait while the versions are
+    /// rewritten. A compaction interrupted before it is durable is dropped when the store is
+    /// opened, which replays the segments left from before it. The transactions begun before
+    /// the compaction read the values of the segments removed where the compaction moved
+    /// them, and fail to read the versions it dropped.
+    pub async fn compact_log(&self) -> Result<u64> {
+        self.compact(.., None).await
+    }
+
+    /// Compacts the commit log as `compact_log` does, but only drops the versions of the keys
+    /// of `range`: the keys outside it keep all their versions and tombstones. `progress` is
+    /// told how many of the versions are rewritten, see
+    /// [`CompactionProgress`](crate::CompactionProgress).
+    ///
+    /// The segments of the commit log are always compacted together, since the versions of a
+    /// key may be in any of them, so there is no restricting it to some of the segments.
+    pub async fn compact<'a, R>(&self, range: R, progress: Option<ProgressCallback>) -> Result<u64>
+    where
+        R: RangeBounds<&'a [u8]>,
+    {
+        let owned = |bound: Bound<&&[u8]>| match bound {
+            Bound::Included(key) => Bound::Included(key.to_vec()),
+            Bound::Excluded(key) => Bound::Excluded(key.to_vec()),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let range = (owned(range.start_bound()), owned(range.end_bound()));
+        compaction::compact(&self.inner.as_ref().unwrap().core, range, progress).await
+    }
+
+    /// Returns the use of the sealed segments of the commit log by the values stored in them,
+    /// see [`SegmentUsage`](crate::SegmentUsage). The live values are the ones of the versions
+    /// `shrink_index` keeps: the other bytes of a segment are the values overwritten since,
+    /// and the records of the smaller values and of the deletions. Measuring it reads every
+    /// version the index keeps.
+    pub fn segment_usage(&self) -> Result<Vec<SegmentUsage>> {
+        gc::segment_usage(&self.inner.as_ref().unwrap().core)
+    }
+
+    /// Returns the use of the disk by the store, see [`DiskStats`]: the use of each sealed
+    /// segment of the commit log, the live keys and the space amplification, so that an
+    /// operator can decide when to compact the log. Measuring it reads every version the
+    /// index keeps, as `segment_usage` and `space_amp` do.
+    pub fn disk_stats(&self) -> Result<DiskStats> {
+        gc::disk_stats(&self.inner.as_ref().unwrap().core)
+    }
+
+    /// Collects the dead values of the commit log once a sealed segment holds less than
+    /// `min_live_ratio` of live values, see `segment_usage`: the live versions are relocated
+    /// into fresh segments and the segments before are removed, as `compact_log` does. It
+    /// returns the number of bytes of the commit log reclaimed, 0 if no segment is below the
+    /// ratio.
+    ///
+    /// The segments below the ratio are not relocated alone: the records of a segment hold the
+    /// versions the index keeps inline too, and the touched and renamed keys refer to the
+    /// values of the segments they were written before, so that the segments are all
+    /// relocated together.
+    pub async fn collect_values(&self, min_live_ratio: f64) -> Result<u64> {
+        gc::collect_values(&self.inner.as_ref().unwrap().core, min_live_ratio).await
+    }
+
+    /// Writes the parts of the index changed since the last checkpoint to disk, so that
+    /// opening the store loads them and only replays the commit log written since, rather
+    /// than the whole log. Commits wait while the point of the checkpoint is taken, but not
+    /// while it is written. It returns the number of index shards written.
+    ///
+    /// Like `shrink_index`, a checkpoint keeps only the latest version of each key, and drops
+    /// the tombstones of the keys deleted before the GC watermark: the older versions and the
+    /// purged tombstones are no longer in the index after the store is reopened from it.
+    pub async fn checkpoint_index(&self) -> Result<usize> {
+        self.inner.as_ref().unwrap().core.checkpoint_index().await
+    }
+
+    /// Suspends the store, to release its files when the process is about to be suspended by
+    /// the OS or an app goes to the background. It writes a checkpoint of the index, as
+    /// `checkpoint_index` does, and closes the store, which makes the commits written so far
+    /// durable and closes its files; the memory of the index is released too.
+    ///
+    /// [`resume`](Store::resume) opens the store again from the checkpoint, with nothing left
+    /// to replay from the commit log, and if the process is killed meanwhile, opening the
+    /// store is just as fast. The hooks and migrations registered on the store are kept, but
+    /// a mirror is stopped and must be started again. While the store is suspended, beginning
+    /// a transaction returns `Error::StoreSuspended`, and the other methods, other than
+    /// `resume` and `close`, must not be called. The transactions, iterators and cursors
+    /// opened before can no longer commit, and keep the files of the store open until they
+    /// are dropped.
+    ///
+    /// Like `checkpoint_index`, only the latest version of each key is kept once the store is
+    /// resumed. A store that does not persist its data, or was opened with `open_checkpoint`,
+    /// cannot be suspended.
+    pub async fn suspend(&mut self) -> Result<()> {
+        let Some(inner) = &self.inner else {
+            return Ok(());
+        };
+        let core = &inner.core;
+        if !core.opts.should_persist_data() {
+            return Err(Error::InvalidOptions(
+                "a store that does not persist its data cannot be suspended".to_string(),
+            ));
+        }
+        core.checkpoint_index().await?;
+        inner.close().await?;
+
+        let suspended = Suspended {
+            opts: core.opts.clone(),
+            invariant_hook: core.invariants.hook(),
+            quota_hook: core.quota.hook(),
+            write_validator: core.write_audit.validator(),
+            soft_quota_hook: core.quota.soft_hook(),
+            fsync_hook: core.fsync.hook(),
+            merge_operator: core.merger.get(),
+            compaction_filter: core.compaction_filter.read().clone(),
+            migrations: Migrations::new(),
+        };
+        suspended.migrations.register_all(&core.migrations);
+        self.inner = None;
+        self.suspended = Some(Box::new(suspended));
+        Ok(())
+    }
+
+    /// Resumes a store suspended with [`suspend`](Store::suspend): the store is opened again,
+    /// loading the index from the checkpoint written when it was suspended. The files of the
+    /// store are checked as when the store is opened, and the commits written to them by
+    /// another process meanwhile are replayed.
+    pub fn resume(&mut self) -> Result<()> {
+        let Some(suspended) = &self.suspended else {
+            return Ok(());
+        };
+        let inner = StoreInner::new(suspended.opts.clone())?;
+        let core = &inner.core;
+        core.invariants.set_hook(suspended.invariant_hook.clone());
+        core.quota.set_hook(suspended.quota_hook.clone());
+        core.write_audit
+            .set_validator(suspended.write_validator.clone());
+        core.quota.set_soft_hook(suspended.soft_quota_hook.clone());
+        core.fsync.set_hook(suspended.fsync_hook.clone());
+        core.merger.set_operator(suspended.merge_operator.clone());
+        *core.compaction_filter.write() = suspended.compaction_filter.clone();
+        core.migrations.register_all(&suspended.migrations);
+        self.inner = Some(inner);
+        self.suspended = None;
+        Ok(())
+    }
+
+    /// Writes out the commit log and syncs it: every commit written before is durable once it
+    /// returns, whatever its durability.
+    ///
+    /// With `Options::erase_block_size` set, for flash storage, each new segment of the commit
+    /// log begins with a header padded to an erase block, so that its records, a whole number
+    /// of erase blocks, are aligned to the erase blocks within the file. The commits with
+    /// `Durability::Eventual` are not written out as they commit, but with the 32 KiB block of
+    /// the commit log holding them once it fills, so that the storage sees sequential, aligned
+    /// writes of whole blocks. The writes are not gathered into whole erase blocks, as the
+    /// commit log only buffers a few blocks ahead of the file. The barrier is then
+    /// the point at which the commits are durable, along with the commits with
+    /// `Durability::Immediate`, the index checkpoints and closing the store, and the
+    /// application calls it as seldom as it can afford to lose commits, to spare the fsyncs.
+    /// `StoreStats::log_bytes_written` counts the bytes written, to estimate the wear.
+    pub async fn sync_barrier(&self) -> Result<()> {
+        self.inner.as_ref().unwrap().core.sync_barrier().await
+    }
+
+    /// Returns the open transactions, the one reading the oldest versions first.
+    /// An open transaction keeps the versions it reads, and in serializable snapshot isolation
+    /// the transactions committed since it began, alive until it is committed or dropped, so a
+    /// transaction that is never closed shows up here with a growing age.
+    /// In debug builds with `capture_backtraces` set, the backtrace of where each transaction
+    /// began is included.
+    pub fn active_transactions(&self) -> Vec<TransactionInfo> {
+        self.inner.as_ref().unwrap().core.active_transactions.list()
+    }
+
+    /// Returns the open transaction reading the oldest versions, if any.
+    pub fn oldest_transaction(&self) -> Option<TransactionInfo> {
+        self.active_transactions().into_iter().next()
+    }
+
+    /// Returns a point-in-time copy of the statistics of the store.
+    pub fn stats(&self) -> StoreStats {
+        self.inner.as_ref().unwrap().core.stats()
+    }
+
+    /// Returns where the commit timestamps of the store are taken from: the wall clock, or
+    /// increments of the last commit timestamp while the wall clock is behind it after going
+    /// back, which is counted in `StoreStats::clock_regressions`.
+    pub fn timestamp_source(&self) -> TimestampSource {
+        self.inner.as_ref().unwrap().core.clock.source()
+    }
+
+    /// Writes the current stats to the `stats` file of the store directory, where
+    /// [`Store::published_stats`] reads them from another process. A background job publishes
+    /// them every `Options::stats_publish_interval` milliseconds while commits are written, and
+    /// the file is removed when the store is closed.
+    pub fn publish_stats(&self) -> Result<()> {
+        self.inner.as_ref().unwrap().core.publish_stats()
+    }
+
+    /// Sets the hook invoked with the internal invariant violations of the store, with their
+    /// diagnostic context, before `Options::invariant_policy` is applied. With the `Panic`
+    /// policy, it is the last chance to record the state of the store.
+    pub fn set_invariant_hook<F>(&self, hook: F)
+    where
+        F: Fn(&InvariantViolation) + Send + Sync + 'static,
+    {
+        let core = &self.inner.as_ref().unwrap().core;
+        core.invariants.set_hook(Some(Arc::new(hook)));
+    }
+
+    /// Sets the hook invoked when a commit is refused with `Error::QuotaExceeded`, with the
+    /// size the store would grow to and `Options::max_db_size`.
+    pub fn set_quota_hook<F>(&self, hook: F)
+    where
+        F: Fn(u64, u64) + Send + Sync + 'static,
+    {
+        let core = &self.inner.as_ref().unwrap().core;
+        core.quota.set_hook(Some(Arc::new(hook)));
+    }
+
+    /// Sets the hook invoked when the store grows past `Options::soft_db_size`, with the size
+    /// of its files and the soft limit, to alert before commits are refused. It is invoked
+    /// once, until the store is measured below the soft limit again, such as after a
+    /// compaction.
+    pub fn set_soft_quota_hook<F>(&self, hook: F)
+    where
+        F: Fn(u64, u64) + Send + Sync + 'static,
+    {
+        let core = &self.inner.as_ref().unwrap().core;
+        core.quota.set_soft_hook(Some(Arc::new(hook)));
+    }
+
+    /// Sets the hook invoked when writing or syncing the commit log fails, with the error,
+    /// before `Options::fsync_failure_policy` is applied. The commit that failed returns
+    /// `Error::FsyncFailed`, and is not durable. When the disk is full, the commit returns
+    /// `Error::DiskFull` instead, and the store is read-only whatever the policy.
+    pub fn set_fsync_failure_hook<F>(&self, hook: F)
+    where
+        F: Fn(&Error) + Send + Sync + 'static,
+    {
+        let core = &self.inner.as_ref().unwrap().core;
+        core.fsync.set_hook(Some(Arc::new(hook)));
+    }
+
+    /// Sets the merge operator, which combines the operands written by `Transaction::merge`
+    /// with the value of their key. It is called with the key, its base value, if any, and the
+    /// operands merged into it since, oldest first, and must return the same value for the
+    /// same arguments: the operands are merged again at every read, until the index is shrunk
+    /// or checkpointed.
+    ///
+    /// The operator is not stored with the store: it must be set again, and to the same
+    /// function, every time the store is opened, before the keys holding operands are read.
+    /// Until it is, they fail with `Error::MergeOperatorMissing`.
+    pub fn set_merge_operator<F>(&self, operator: F)
+    where
+        F: Fn(&[u8], Option<&[u8]>, &[&[u8]]) -> Vec<u8> + Send + Sync + 'static,
+    {
+        let core = &self.inner.as_ref().unwrap().core;
+        core.merger.set_operator(Some(Arc::new(operator)));
+    }
+
+    /// Sets the filter of the versions rewritten by the compactions of the commit log, see
+    /// [`CompactionFilter`], or removes it. Like the merge operator, the filter is not stored
+    /// with the store, and must be set again every time the store is opened.
+    pub fn set_compaction_filter(&self, filter: Option<Arc<dyn CompactionFilter>>) {
+        let core = &self.inner.as_ref().unwrap().core;
+        *core.compaction_filter.write() = filter;
+    }
+
+    /// Reads the stats last published by the store open in `dir`, if any.
+    pub fn published_stats(dir: &Path) -> Result<Option<StoreStats>> {
+        stats::read_published(dir)
+    }
+
     /// Closes the inner store
     pub async fn close(&self) -> Result<()> {
         if let Some(inner) = self.inner.as_ref() {
@@ -166,8 +1035,13 @@ impl Drop for Store {
             // Close the store asynchronously
             tokio::spawn(async move {
                 if let Err(err) = inner.close().await {
-                    // TODO: use log/tracing instead of eprintln
-                    eprintln!("Error occurred while closing the kv store: {}", err);
+                    let error = err.to_string();
+                    events::emit(
+                        events::WRITER,
+                        Level::Error,
+                        "closing the dropped store failed",
+                        &[("error", Value::Str(&error))],
+                    );
                 }
             });
         }
@@ -213,8 +1087,15 @@ impl TaskRunner {
     async fn handle_task(&self, task: Task) {
         let core = self.core.clone();
         if let Err(err) = core.write_request(task).await {
-            eprintln!("failed to write: {:?}", err);
+            let error = err.to_string();
+            events::emit(
+                events::WRITER,
+                Level::Error,
+                "commit write failed",
+                &[("error", Value::Str(&error))],
+            );
         }
+        core.publish_stats_if_due();
     }
 }
 
@@ -222,6 +1103,8 @@ impl TaskRunner {
 pub struct Core {
     /// Index for store.
     pub(crate) indexer: RwLock<Indexer>,
+    /// Translation of the keys of the store to the keys of the index.
+    pub(crate) keys: Arc<KeyCodec>,
     /// Options for store.
     pub(crate) opts: Options,
     /// Commit log for store.
@@ -230,13 +1113,74 @@ pub struct Core {
     pub(crate) manifest: Option<RwLock<Aol>>,
     /// Transaction ID Oracle for store.
     pub(crate) oracle: Arc<Oracle>,
+    /// Commit timestamps of the store.
+    pub(crate) clock: CommitClock,
+    /// Commit timestamp from which `shrink_index` keeps all the versions, see
+    /// `Store::set_gc_watermark`.
+    gc_watermark: AtomicU64,
+    /// Durability callbacks of the batches waiting for the commit log to be synced.
+    pub(crate) durable: DurabilityWatch,
+    /// Compactions of the commit log run to meet `Options::target_space_amp`.
+    pub(crate) gc_schedule: GcSchedule,
+    /// Threads running the background jobs of the store.
+    pub(crate) jobs: Jobs,
+    /// Validation of a sample of the writes, see `Options::write_audit_interval`.
+    pub(crate) write_audit: WriteAudit,
     /// Value cache for store.
     /// The assumption for this cache is that it should be useful for
     /// storing offsets that are frequently accessed (especially in
     /// the case of range scans)
-    pub(crate) value_cache: Cache<u64, Bytes>,
+    pub(crate) value_cache: Cache<u64, Bytes, UnitWeighter, DefaultHashBuilder, CacheLifecycle>,
+    /// Counters describing the activity of the store.
+    pub(crate) stats: Stats,
+    /// Compressor applying the per-prefix compression rules to values.
+    pub(crate) compressor: Compressor,
+    /// Sequence numbers of the append-only streams.
+    pub(crate) streams: Streams,
+    /// Durable queues.
+    pub(crate) queues: Queues,
+    /// Schema migrations of enveloped values.
+    pub(crate) migrations: Migrations,
+    /// Mirror of the committed mutations.
+    pub(crate) mirror: Mirror,
+    /// Watches of the committed mutations, see `Store::watch`.
+    pub(crate) watches: Watches,
+    /// Slots limiting the number of active transactions, if `max_active_transactions` is set.
+    transaction_slots: Option<Arc<Semaphore>>,
+    /// Registry of the open transactions.
+    pub(crate) active_transactions: ActiveTransactions,
+    /// Key ranges of the segments of the commit log.
+    pub(crate) segment_keys: Mutex<SegmentKeyRanges>,
+    /// Segments of the commit log found missing on open, see `Options::missing_segments`.
+    pub(crate) unavailable: Unavailable,
+    /// Keys carrying the indexed user flags.
+    pub(crate) flag_index: RwLock<FlagIndex>,
+    /// Incremental checkpoints of the index.
+    pub(crate) index_checkpoint: IndexCheckpoint,
+    /// Size up to which values are stored in the index.
+    pub(crate) value_threshold: ValueThreshold,
+    /// Policy and hook for the internal invariant violations.
+    pub(crate) invariants: Invariants,
+    /// Maximum size of the files of the store, see `Options::max_db_size`.
+    pub(crate) quota: Quota,
+    /// Policy and hook for the failures of the commit log.
+    pub(crate) fsync: FsyncGate,
+    /// Merge operator the merge operands are combined with.
+    pub(crate) merger: Merger,
+    /// Filter of the versions rewritten by the compactions of the commit log.
+    pub(crate) compaction_filter: RwLock<Option<Arc<dyn CompactionFilter>>>,
+    /// Disk space reserved for the store to close once the disk is full.
+    headroom: Headroom,
+    /// Redundancy of the sealed segments of the commit log.
+    pub(crate) redundancy: Redundancy,
+    /// Time the stats were last published, see `Options::stats_publish_interval`.
+    stats_published_at: Mutex<Option<Instant>>,
+    /// Last version visible to new transactions, see `Store::wait_for_version`.
+    versions: VersionWatch,
     /// Flag to indicate if the store is closed.
     is_closed: AtomicBool,
+    /// Set if the store was opened from a checkpoint, see `Store::open_checkpoint`.
+    pub(crate) read_only: bool,
     /// Channel to send write requests to the writer
     writes_tx: Sender<Task>,
 }
@@ -253,31 +1197,51 @@ pub struct Task {
     commit_ts: u64,
     /// Durability
     durability: Durability,
+    /// Time the task was queued for the writer
+    enqueued_at: Instant,
+    /// Entries indexed in place of `entries` if they hold range deletes, see
+    /// `range_delete::expand`
+    expanded: Option<Vec<Entry>>,
+}
+
+impl Task {
+    /// Returns the entries written to the index, and to the mirror.
+    fn indexed_entries(&self) -> &[Entry] {
+        self.expanded.as_deref().unwrap_or(&self.entries)
+    }
 }
 
 impl Core {
-    fn initialize_indexer() -> Indexer {
-        Indexer::new()
+    fn initialize_indexer(keys: &Arc<KeyCodec>) -> Indexer {
+        Indexer::new(keys.clone())
     }
 
     // This function initializes the manifest log for the database to store all settings.
     fn initialize_manifest(opts: &Options) -> Result<Aol> {
         let manifest_subdir = opts.dir.join("manifest");
-        let mopts = LogOptions::default().with_file_extension("manifest".to_string());
+        let mopts = LogOptions::default()
+            .with_file_extension("manifest".to_string())
+            .with_vfs(opts.vfs.clone());
         Aol::open(&manifest_subdir, &mopts).map_err(Error::from)
     }
 
+    // The options of the commit log (clog) of the database.
+    // The maximum file size for the clog is set to the max_segment_size option from the database options.
+    // The file extension for the clog files is set to "clog".
+    // With an erase block size, the segments begin with a header padded to an erase block.
+    fn clog_options(opts: &Options) -> LogOptions {
+        LogOptions::default()
+            .with_max_file_size(opts.max_segment_size)
+            .with_file_extension("clog".to_string())
+            .with_header_alignment(opts.erase_block_size)
+            .with_vfs(opts.vfs.clone())
+    }
+
     // This function initializes the commit log (clog) for the database.
     fn initialize_clog(opts: &Options) -> Result<Aol> {
         // It first constructs the path to the clog subdirectory within the database directory.
         let clog_subdir = opts.dir.join("clog");
-
-        // Then it creates a LogOptions object to configure the clog.
-        // The maximum file size for the clog is set to the max_segment_size option from the database options.
-        // The file extension for the clog files is set to "clog".
-        let copts = LogOptions::default()
-            .with_max_file_size(opts.max_segment_size)
-            .with_file_extension("clog".to_string());
+        let copts = Self::clog_options(opts);
 
         // It then attempts to restore any repair files in the clog subdirectory.
         // If this fails, the error is propagated up to the caller of the function.
@@ -286,7 +1250,7 @@ impl Core {
         //
         // Even though we are restoring the corrupted files, it will get repaired
         // during in the load_index function.
-        restore_repair_files(clog_subdir.as_path().to_str().unwrap())?;
+        restore_repair_files(&*opts.vfs, &clog_subdir)?;
 
         // Finally, it attempts to open the clog with the specified options.
         // If this fails, the error is converted to a database error and then propagated up to the caller of the function.
@@ -300,67 +1264,584 @@ impl Core {
     /// and initializes an Oracle, creates and initializes a value cache, and constructs and returns
     /// the Core instance.
     pub fn new(opts: Options, writes_tx: Sender<Task>) -> Result<Self> {
+        Self::open(opts, false, writes_tx)
+    }
+
+    /// Creates a read-only Core from the last index checkpoint of the store in `opts.dir`,
+    /// see `Store::open_checkpoint`. The options are the ones persisted by the store.
+    pub(crate) fn open_checkpoint(opts: Options, writes_tx: Sender<Task>) -> Result<Self> {
+        Self::open(opts, true, writes_tx)
+    }
+
+    fn open(opts: Options, read_only: bool, writes_tx: Sender<Task>) -> Result<Self> {
+        compression::check_rules(&opts.compression)?;
+
         // Initialize a new Indexer with the provided options.
-        let mut indexer = Self::initialize_indexer();
+        let keys = Arc::new(KeyCodec::new(opts.intern_prefix_len));
+        let mut indexer = Self::initialize_indexer(&keys);
 
         let mut manifest = None;
         let mut clog = None;
+        let mut historic_rules = Vec::new();
+        let mut segment_keys = SegmentKeyRanges::open(&opts.vfs, None)?;
+        let mut flag_index = FlagIndex::new(opts.indexed_flags);
+        let mut index_checkpoint = IndexCheckpoint::open(&opts.vfs, None, opts.max_segment_size)?;
+        let mut redundancy = Redundancy::none();
+        let mut unavailable = Unavailable::default();
+
+        if read_only {
+            // Nothing is written to the files of the store, which may be open and written to.
+            historic_rules = Core::load_compression_rules(&opts)?;
+            segment_keys = SegmentKeyRanges::open(&opts.vfs, Some(&opts.dir.join("segments")))?;
+            let activity = Activity::start(events::RECOVERY, "checkpoint load", &[]);
+            let loaded = Core::load_checkpoint(&opts, &mut indexer, &mut flag_index);
+            let (log, checkpoint) =
+                activity.finish(loaded, |_| vec![("version", indexer.version())])?;
+            clog = Some(log);
+            index_checkpoint = checkpoint;
+        } else if opts.should_persist_data() {
+            // Finish or roll back a rewrite of the store that was interrupted.
+            rewrite::recover_interrupted_rewrite(&*opts.vfs, &opts.dir)?;
 
-        if opts.should_persist_data() {
             // Determine options for the manifest file and open or create it.
             manifest = Some(Self::initialize_manifest(&opts)?);
 
+            // The redundancy options are not persisted.
+            redundancy = Redundancy::new(&opts)?;
+
+            // The mode for the missing segments is not persisted.
+            let missing_segments = opts.missing_segments;
+
             // Load options from the manifest file.
             let opts = Core::load_options(&opts, manifest.as_mut().unwrap())?;
 
+            // Collect the compression rules of earlier runs, as values written with
+            // their dictionaries may still be stored in the commit log.
+            historic_rules = Core::load_compression_rules(&opts)?;
+
             // Determine options for the commit log file and open or create it.
             clog = Some(Self::initialize_clog(&opts)?);
+            segment_keys = SegmentKeyRanges::open(&opts.vfs, Some(&opts.dir.join("segments")))?;
+            unavailable = Core::check_segments(&opts, &segment_keys, missing_segments)?;
+            index_checkpoint = IndexCheckpoint::open(
+                &opts.vfs,
+                Some(&opts.dir.join("checkpoint")),
+                opts.max_segment_size,
+            )?;
+
+            // Load the index from the last checkpoint, if any, and from the commit log
+            // written after it.
+            let log_size = clog.as_ref().unwrap().size()?;
+            let activity = Activity::start(
+                events::RECOVERY,
+                "index load",
+                &[("log_bytes", Value::U64(log_size))],
+            );
+            let loaded = index_checkpoint
+                .load(log_size, &mut indexer, &mut flag_index)
+                .and_then(|start| {
+                    if log_size > start {
+                        Core::load_index(
+                            &opts,
+                            clog.as_mut().unwrap(),
+                            start,
+                            &mut indexer,
+                            &mut segment_keys,
+                            &mut flag_index,
+                            &index_checkpoint,
+                            &redundancy,
+                        )?;
+                    }
+                    Ok(start)
+                });
+            activity.finish(loaded, |start| {
+                vec![
+                    ("checkpoint_offset", *start),
+                    ("replayed_bytes", log_size - start),
+                    ("version", indexer.version()),
+                ]
+            })?;
 
-            // Load the index from the commit log if it exists.
-            if clog.as_ref().unwrap().size()? > 0 {
-                Core::load_index(&opts, clog.as_mut().unwrap(), &mut indexer)?;
+            // Finish removing the segments of the commit log dropped by a clear, if the store
+            // crashed before they were removed.
+            let cleared = segment_keys.cleared();
+            if cleared > 0 {
+                clear::remove_segments(
+                    clog.as_ref().unwrap(),
+                    &mut segment_keys,
+                    &redundancy,
+                    cleared,
+                )?;
             }
+
+            // Write the redundancy missing for the segments sealed before.
+            redundancy.seal_before(clog.as_ref().unwrap().active_segment_id(), true);
         }
 
         // Create and initialize an Oracle.
         let oracle = Oracle::new(&opts);
-        oracle.set_ts(indexer.version());
+        oracle.set_ts(indexer.version().max(indexer.compacted_at()));
 
         // Create and initialize value cache.
-        let value_cache = Cache::new(opts.max_value_cache_size as usize);
+        let stats = Stats::new(opts.track_memory);
+        let value_cache = Cache::with(
+            opts.max_value_cache_size as usize,
+            opts.max_value_cache_size,
+            UnitWeighter,
+            DefaultHashBuilder::default(),
+            stats.cache_lifecycle(),
+        );
+
+        // Create the compressor for the configured compression rules.
+        let compressor = Compressor::new(&opts.compression, &historic_rules);
+
+        let active_transactions = ActiveTransactions::new(opts.capture_backtraces);
+
+        // Tokio semaphores hand out permits in FIFO order.
+        let transaction_slots = match opts.max_active_transactions {
+            0 => None,
+            n => Some(Arc::new(Semaphore::new(n as usize))),
+        };
+
+        let value_threshold = ValueThreshold::new(&opts);
+        let invariants = Invariants::new(opts.invariant_policy);
+        let quota = Quota::new(&opts)?;
+        let fsync = FsyncGate::new(opts.fsync_failure_policy);
+        let headroom = match read_only {
+            true => Headroom::none(),
+            false => Headroom::new(&opts),
+        };
+        let jobs = Jobs::new(opts.background_threads, opts.job_priorities);
+
+        let version = indexer.version();
+        let clock = CommitClock::new(indexer.last_ts());
 
         // Construct and return the Core instance.
         Ok(Self {
             indexer: RwLock::new(indexer),
+            keys,
             opts,
             manifest: manifest.map(RwLock::new),
             clog: clog.map(|c| Arc::new(RwLock::new(c))),
             oracle: Arc::new(oracle),
+            clock,
+            gc_watermark: AtomicU64::new(u64::MAX),
+            durable: DurabilityWatch::default(),
+            gc_schedule: GcSchedule::default(),
+            jobs,
+            write_audit: WriteAudit::default(),
             value_cache,
+            compressor,
+            stats,
+            streams: Streams::new(),
+            queues: Queues::new(),
+            migrations: Migrations::new(),
+            mirror: Mirror::new(),
+            watches: Watches::default(),
+            transaction_slots,
+            active_transactions,
+            segment_keys: Mutex::new(segment_keys),
+            unavailable,
+            flag_index: RwLock::new(flag_index),
+            index_checkpoint,
+            value_threshold,
+            invariants,
+            quota,
+            fsync,
+            merger: Merger::new(),
+            compaction_filter: RwLock::new(None),
+            headroom,
+            redundancy,
+            stats_published_at: Mutex::new(None),
+            versions: VersionWatch::new(version),
             is_closed: AtomicBool::new(false),
+            read_only,
             writes_tx,
         })
     }
 
-    pub(crate) fn read_ts(&self) -> Result<u64> {
+    /// Takes a slot for a new transaction without waiting.
+    /// It returns `Error::TooManyTransactions` if no slot is free.
+    pub(crate) fn try_admit_transaction(&self) -> Result<Option<OwnedSemaphorePermit>> {
+        match &self.transaction_slots {
+            Some(slots) => match slots.clone().try_acquire_owned() {
+                Ok(permit) => Ok(Some(permit)),
+                Err(_) => Err(Error::TooManyTransactions),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Takes a slot for a new transaction, waiting for one to be free.
+    pub(crate) async fn admit_transaction(&self) -> Result<Option<OwnedSemaphorePermit>> {
+        match &self.transaction_slots {
+            Some(slots) => match slots.clone().acquire_owned().await {
+                Ok(permit) => Ok(Some(permit)),
+                // The slots are closed when the store is closed.
+                Err(_) => Err(Error::StoreClosed),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Rebuilds the index with only the latest version of the live keys, whose lists of merge
+    /// operands are collapsed into their merged value.
+    /// It returns the approximate number of bytes reclaimed.
+    pub(crate) fn shrink_index(self: &Arc<Self>) -> Result<u64> {
+        self.invariants.check()?;
+        // Commits are blocked while the index is rebuilt.
+        let mut indexer = self.indexer.write();
+        let activity = Activity::start(
+            events::GC,
+            "index shrink",
+            &[("index_bytes", Value::U64(indexer.bytes()))],
+        );
+        let merged = merge::collapse(self, &*indexer, indexer.index.iter());
+        let reclaimed = merged
+            .and_then(|merged| {
+                self.retained_versions(&indexer, &merged, &(Bound::Unbounded, Bound::Unbounded))
+            })
+            .and_then(|versions| indexer.rebuild(versions));
+        activity.finish(reclaimed, |reclaimed| vec![("bytes_reclaimed", *reclaimed)])
+    }
+
+    /// Returns the versions of the keys `shrink_index` keeps: the latest version of each key,
+    /// with the value `merged` for it if any, and the older versions retained, collapsed into
+    /// their merged value if they are lists of operands. A deleted key is dropped, unless its
+    /// tombstone or an older version of it is retained. The keys outside `range` keep all
+    /// their versions.
+    pub(crate) fn retained_versions(
+        self: &Arc<Self>,
+        indexer: &Indexer,
+        merged: &merge::Merged,
+        range: &KeyRange,
+    ) -> Result<Vec<IndexVersion>> {
+        let retention = self.retention();
+        let all = Retention {
+            versions: usize::MAX,
+            since_ts: 0,
+        };
+        let unbounded = matches!(range, (Bound::Unbounded, Bound::Unbounded));
+        let mut versions = Vec::new();
+        for (key, value, version, ts) in indexer.index.iter() {
+            let index_key = vart::VariableSizeKey::from_slice(&key);
+            let retention = match unbounded || range.contains(&self.keys.decode(key.clone())?) {
+                true => &retention,
+                false => &all,
+            };
+            let older = indexer.retained_versions(&index_key, *version, retention)?;
+            let mut val_ref = ValueRef::new(self.clone());
+            val_ref.decode(*version, value)?;
+            let deleted = val_ref
+                .key_value_metadata
+                .as_ref()
+                .is_some_and(|md| md.deleted());
+            if deleted && older.is_empty() && *ts < retention.since_ts {
+                continue;
+            }
+
+            for (value, version, ts) in older {
+                let value =
+                    merge::collapse_version(self, indexer, &key, &value, version)?.unwrap_or(value);
+                versions.push((key.clone(), value, version, ts));
+            }
+            let value = merged.get(&key).cloned().unwrap_or_else(|| value.clone());
+            versions.push((key, value, *version, *ts));
+        }
+        Ok(versions)
+    }
+
+    /// Returns the versions `shrink_index` keeps, from `Options::version_retention` and the
+    /// GC watermark.
+    pub(crate) fn retention(&self) -> Retention {
+        let watermark = self.gc_watermark.load(Ordering::Acquire);
+        match self.opts.version_retention {
+            VersionRetention::Latest => Retention {
+                versions: 1,
+                since_ts: watermark,
+            },
+            VersionRetention::Count(count) => Retention {
+                versions: count.max(1),
+                since_ts: watermark,
+            },
+            VersionRetention::Duration(duration) => {
+                let age = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+                Retention {
+                    versions: 1,
+                    since_ts: watermark.min(now().saturating_sub(age)),
+                }
+            }
+        }
+    }
+
+    /// Syncs the commit log, see `Store::sync_barrier`.
+    pub(crate) async fn sync_barrier(&self) -> Result<()> {
+        if self.is_closed() {
+            return Err(Error::StoreClosed);
+        }
+        self.invariants.check()?;
+        if !self.opts.should_persist_data() {
+            return Ok(());
+        }
+        // Commits are held so that the commits of the versions synced are all written.
+        let _commits = self.oracle.write_lock.lock().await;
+        let version = self.indexer.read().version();
+        let clog = self.clog.as_ref().unwrap().read();
+        let started = Instant::now();
+        clog.sync()
+            .map_err(|err| self.log_failed(&clog, err.into()))?;
+        self.stats.record_fsync(started.elapsed());
+        self.durable.synced(version);
+        Ok(())
+    }
+
+    /// Writes a checkpoint of the index shards changed since the last one.
+    pub(crate) async fn checkpoint_index(self: &Arc<Self>) -> Result<usize> {
+        if self.is_closed() {
+            return Err(Error::StoreClosed);
+        }
+        self.invariants.check()?;
+        if self.is_read_only() {
+            return Err(Error::StoreReadOnly);
+        }
+        if !self.opts.should_persist_data() {
+            return Ok(0);
+        }
+
+        // Commits are held while the point is taken, so that the index holds exactly the
+        // transactions written to the commit log before the offset, which is made durable.
+        let activity = Activity::start(events::CHECKPOINT, "index checkpoint", &[]);
+        let (offset, dirty, mut snapshot) = {
+            let _commits = self.oracle.write_lock.lock().await;
+            self.oracle.wait_indexed().await;
+            let offset = {
+                let version = self.indexer.read().version();
+                let clog = self.clog.as_ref().unwrap().read();
+                clog.sync()
+                    .map_err(|err| self.log_failed(&clog, err.into()))?;
+                self.durable.synced(version);
+                clog.offset()?
+            };
+            self.segment_keys.lock().persist()?;
+            let snapshot = self.indexer.read().snapshot()?;
+            (offset, self.index_checkpoint.take_dirty(), snapshot)
+        };
+
+        // The lists of merge operands are written as their merged value, which the
+        // checkpoint keeps as the only version of their key. The tombstones committed before
+        // the GC watermark are purged, as `shrink_index` purges them, but for the one of the
+        // last commit, which the index loaded from the checkpoint resumes its version from.
+        let since_ts = self.retention().since_ts;
+        let last_version = snapshot.version();
+        let written = match snapshot.new_reader() {
+            Ok(reader) => merge::collapse(self, &self.indexer, reader.iter()).and_then(|merged| {
+                let entries = reader
+                    .iter()
+                    .filter(|(_, value, version, ts)| {
+                        **ts >= since_ts || **version == last_version || !is_tombstone(value)
+                    })
+                    .map(|(key, value, version, ts)| {
+                        let value = merged.get(&key).cloned().unwrap_or_else(|| value.clone());
+                        (key, value, *version, *ts)
+                    });
+                self.index_checkpoint
+                    .write(offset, dirty, &self.keys, entries)
+            }),
+            Err(vart::TrieError::SnapshotEmpty) => {
+                self.index_checkpoint
+                    .write(offset, dirty, &self.keys, std::iter::empty())
+            }
+            Err(err) => Err(err.into()),
+        };
+        let written = written.map_err(|err| match err.is_disk_full() {
+            true => self.disk_failed(err),
+            false => err,
+        });
+        if written.is_err() {
+            self.index_checkpoint.restore(dirty);
+        }
+        activity.finish(written, |written| {
+            vec![("shards_written", *written as u64), ("offset", offset)]
+        })
+    }
+
+    /// Caches a value read from the commit log at the given offset.
+    pub(crate) fn stats(&self) -> StoreStats {
+        let (index_bytes, key_bytes_saved) = {
+            let indexer = self.indexer.read();
+            (indexer.bytes(), indexer.key_bytes_saved())
+        };
+        let mut stats = self.stats.snapshot(
+            index_bytes,
+            self.writes_tx.len() as u64,
+            self.value_threshold.get() as u64,
+        );
+        (stats.interned_prefixes, stats.interned_prefix_bytes) = self.keys.stats();
+        stats.interned_key_bytes_saved = key_bytes_saved;
+        stats.fsync_failures = self.fsync.failures();
+        stats.clock_regressions = self.clock.regressions();
+        stats
+    }
+
+    pub(crate) fn publish_stats(&self) -> Result<()> {
+        if !self.opts.should_persist_data() {
+            return Ok(());
+        }
+        if self.read_only {
+            return Err(Error::StoreReadOnly);
+        }
+        *self.stats_published_at.lock() = Some(Instant::now());
+        stats::publish(&*self.opts.vfs, &self.opts.dir, &self.stats())
+    }
+
+    /// Queues a publication of the stats if `Options::stats_publish_interval` has elapsed
+    /// since they were last published.
+    fn publish_stats_if_due(self: &Arc<Self>) {
+        let interval = Duration::from_millis(self.opts.stats_publish_interval);
+        if interval.is_zero() {
+            return;
+        }
+        let mut published_at = self.stats_published_at.lock();
+        if published_at.is_some_and(|at| at.elapsed() < interval) {
+            return;
+        }
+        // The publication queued is not queued again while it waits to run.
+        *published_at = Some(Instant::now());
+        drop(published_at);
+        let core = self.clone();
+        self.jobs.submit(JobKind::Flush, async move {
+            if let Err(err) = core.publish_stats() {
+                let error = err.to_string();
+                events::emit(
+                    events::WRITER,
+                    Level::Warn,
+                    "stats publishing failed",
+                    &[("error", Value::Str(&error))],
+                );
+            }
+        });
+    }
+
+    pub(crate) fn cache_value(&self, offset: u64, value: Bytes) {
+        // Replaced values are not reported as evicted by the cache.
+        if let Some((_, old)) = self.value_cache.remove(&offset) {
+            self.stats
+                .memory_sub(&self.stats.value_cache_bytes, old.len());
+        }
+        self.stats
+            .memory_add(&self.stats.value_cache_bytes, value.len());
+        self.value_cache.insert(offset, value);
+    }
+
+    /// Returns the read timestamp of a transaction beginning in `mode`. Read-only
+    /// transactions get it without taking a lock.
+    pub(crate) fn read_ts(&self, mode: Mode) -> Result<u64> {
         if self.is_closed() {
             return Err(Error::StoreClosed);
         }
+        self.invariants.check()?;
+
+        match mode.is_read_only() {
+            true => Ok(self.oracle.snapshot_ts()),
+            false => Ok(self.oracle.read_ts()),
+        }
+    }
+
+    // The load_index function is responsible for loading the index from the log, starting
+    // at the given offset.
+    #[allow(clippy::too_many_arguments)]
+    #[allow(clippy::too_many_arguments)]
+    fn load_index(
+        opts: &Options,
+        clog: &mut Aol,
+        mut start: u64,
+        indexer: &mut Indexer,
+        segment_keys: &mut SegmentKeyRanges,
+        flag_index: &mut FlagIndex,
+        index_checkpoint: &IndexCheckpoint,
+        redundancy: &Redundancy,
+    ) -> Result<()> {
+        // A segment is rebuilt from its redundancy at most once, after which the replay
+        // goes on from the transaction that was found corrupted.
+        let mut restored = HashSet::new();
+        let corruption_info = loop {
+            let corruption = Core::replay_log(
+                opts,
+                start,
+                indexer,
+                segment_keys,
+                flag_index,
+                index_checkpoint,
+                redundancy,
+            )?;
+            match corruption {
+                Some((segment_id, _, tx_offset))
+                    if restored.insert(segment_id) && redundancy.restore(segment_id)? =>
+                {
+                    start = tx_offset;
+                }
+                corruption => break corruption,
+            }
+        };
+
+        // If a corruption was encountered, the last segment is repaired using the stored segment ID and offset.
+        // The reason why the last segment is repaired is because the last segment is the one that was being actively
+        // written to and acts like the active WAL file. Any corruption in the previous immutable segments is pure
+        // corruption of the data and should be handled by the user, or rebuilt from the redundancy of the
+        // segment above if it has any.
+        if let Some((corrupted_segment_id, corrupted_offset, _)) = corruption_info {
+            events::emit(
+                events::RECOVERY,
+                Level::Warn,
+                "repairing corrupted commit log segment",
+                &[
+                    ("segment_id", Value::U64(corrupted_segment_id)),
+                    ("offset", Value::U64(corrupted_offset)),
+                ],
+            );
+            repair_last_corrupted_segment(clog, opts, corrupted_segment_id, corrupted_offset)?;
+        }
 
-        Ok(self.oracle.read_ts())
+        Ok(())
     }
 
-    // The load_index function is responsible for loading the index from the log.
-    fn load_index(opts: &Options, clog: &mut Aol, indexer: &mut Indexer) -> Result<()> {
+    /// Replays the commit log from `start` into the index. It returns the segment and the
+    /// offset of the corruption that stopped it, if any, with the offset in the commit log of
+    /// the transaction found corrupted.
+    fn replay_log(
+        opts: &Options,
+        start: u64,
+        indexer: &mut Indexer,
+        segment_keys: &mut SegmentKeyRanges,
+        flag_index: &mut FlagIndex,
+        index_checkpoint: &IndexCheckpoint,
+        redundancy: &Redundancy,
+    ) -> Result<Option<(u64, u64, u64)>> {
         // The directory where the log segments are stored is determined.
         let clog_subdir = opts.dir.join("clog");
 
-        // The segments are read from the directory.
-        let sr = SegmentRef::read_segments_from_directory(clog_subdir.as_path())
-            .expect("should read segments");
+        // The segments are read from the directory, from the one holding the start offset. A
+        // damaged segment header may be rebuilt from the redundancy of the segment.
+        let mut segments = SegmentRef::read_segments_from_directory(&*opts.vfs, &clog_subdir);
+        if segments.is_err() && redundancy.restore_all()? {
+            segments = SegmentRef::read_segments_from_directory(&*opts.vfs, &clog_subdir);
+        }
+        let mut sr = segments.expect("should read segments");
+        sr.retain(|segment| segment.id >= start / opts.max_segment_size);
+
+        // The segment holding the start offset was removed by a clear, whose marker is the
+        // first record of the first segment left: the log is replayed from there.
+        let mut position = start % opts.max_segment_size;
+        if sr
+            .first()
+            .is_some_and(|segment| segment.id > start / opts.max_segment_size)
+        {
+            position = 0;
+        }
 
         // A MultiSegmentReader is created to read from multiple segments.
-        let reader = MultiSegmentReader::new(sr)?;
+        let mut reader = MultiSegmentReader::new(&opts.vfs, sr)?;
+        reader.seek_to(position as usize)?;
 
         // A Reader is created from the MultiSegmentReader with the maximum segment size and block size.
         let reader = Reader::new_from(reader, opts.max_segment_size, BLOCK_SIZE);
@@ -372,24 +1853,104 @@ impl Core {
         let mut tx = TxRecord::new(opts.max_entries_per_txn as usize);
 
         // An Option is created to hold the segment ID and offset in case of corruption.
-        let mut corruption_info: Option<(u64, u64)> = None;
+        let mut corruption_info: Option<(u64, u64, u64)> = None;
+
+        // The records of a compaction are replayed into a new index, which replaces the index
+        // at the end marker of the compaction. They are dropped if a commit follows them
+        // without the end marker, as the compaction then failed.
+        let mut compacting: Option<compaction::Replay> = None;
 
         // A loop is started to read transactions.
         loop {
             // The TxRecord is reset for each iteration.
             tx.reset();
+            let tx_offset = tx_reader.offset();
 
             // The TxReader attempts to read into the TxRecord.
             match tx_reader.read_into(&mut tx) {
                 // If the read is successful, the entries are processed.
-                Ok(value_offsets) => Core::process_entries(&tx, opts, &value_offsets, indexer)?,
+                Ok(value_offsets) => {
+                    match compaction::marker(&tx) {
+                        Some(Marker::Start) => {
+                            let segment_id = tx_reader.offset() / opts.max_segment_size;
+                            let replay = compaction::Replay::new(opts, &tx, segment_id, indexer);
+                            compacting = Some(replay);
+                            continue;
+                        }
+                        Some(Marker::End) => {
+                            if let Some(replay) = compacting.take() {
+                                replay.finish(
+                                    indexer,
+                                    flag_index,
+                                    index_checkpoint,
+                                    segment_keys,
+                                )?;
+                            }
+                            continue;
+                        }
+                        None => {
+                            if compacting.as_ref().is_some_and(|replay| !replay.holds(&tx)) {
+                                compacting = None;
+                            }
+                        }
+                    }
+                    let (indexer, flag_index) = match &mut compacting {
+                        Some(replay) => (&mut replay.indexer, &mut replay.flag_index),
+                        None => (&mut *indexer, &mut *flag_index),
+                    };
+
+                    // A clear drops the keys indexed before it, and the segments before its own,
+                    // which it is the first record of.
+                    if tx
+                        .entries
+                        .iter()
+                        .any(|e| clear::is_clear(e.metadata.as_ref()))
+                    {
+                        indexer.clear();
+                        flag_index.clear();
+                        index_checkpoint.mark_all();
+                        segment_keys.remove_before(tx_reader.offset() / opts.max_segment_size)?;
+                    }
+
+                    // The range deletes are indexed as a tombstone for each key they delete.
+                    let mut entries: Vec<Fields> = tx
+                        .entries
+                        .iter()
+                        .map(|e| (&e.key, e.metadata.as_ref(), &e.value))
+                        .collect();
+                    let deleted = range_delete::deleted_keys(indexer, entries.iter().copied())?;
+                    let mut tombstone = KvMetadata::new();
+                    tombstone.as_deleted(true)?;
+                    let empty = Bytes::new();
+                    entries.retain(|(_, md, _)| !range_delete::is_range_delete(*md));
+                    entries.extend(deleted.iter().map(|key| (key, Some(&tombstone), &empty)));
+
+                    // The entries of a transaction are all in the same segment.
+                    if let Some(offset) = value_offsets.values().next() {
+                        segment_keys.record(
+                            *offset as u64 / opts.max_segment_size,
+                            entries.iter().map(|(key, ..)| &key[..]),
+                        )?;
+                    }
+                    // Touch entries keep the flags of their key, unless they replace them.
+                    flag_index.apply(
+                        entries
+                            .iter()
+                            .filter(|(_, md, _)| {
+                                !md.is_some_and(|md| md.touched() && !md.reflagged())
+                            })
+                            .map(|(key, md, _)| (*key, *md)),
+                    );
+                    index_checkpoint.mark(entries.iter().map(|(key, ..)| &key[..]));
+                    Core::process_entries(&tx, &entries, opts, &value_offsets, indexer)?
+                }
 
                 // If the end of the file is reached, the loop is broken.
                 Err(Error::LogError(LogError::Eof(_))) => break,
 
                 // If a corruption error is encountered, the segment ID and offset are stored and the loop is broken.
                 Err(Error::LogError(LogError::Corruption(err))) => {
-                    corruption_info = Some((err.segment_id, err.offset));
+                    corruption_info = Some((err.segment_id, err.offset, tx_offset));
                     break;
                 }
 
@@ -398,51 +1959,107 @@ impl Core {
             };
         }
 
-        // If a corruption was encountered, the last segment is repaired using the stored segment ID and offset.
-        // The reason why the last segment is repaired is because the last segment is the one that was being actively
-        // written to and acts like the active WAL file. Any corruption in the previous immutable segments is pure
-        // corruption of the data and should be handled by the user.
-        if let Some((corrupted_segment_id, corrupted_offset)) = corruption_info {
-            eprintln!(
-                "Repairing corrupted segment with id: {} and offset: {}",
-                corrupted_segment_id, corrupted_offset
-            );
-            repair_last_corrupted_segment(clog, opts, corrupted_segment_id, corrupted_offset)?;
-        }
-
-        Ok(())
+        Ok(corruption_info)
     }
 
     fn process_entries(
         tx: &TxRecord,
+        entries: &[Fields],
         opts: &Options,
         value_offsets: &HashMap<Bytes, usize>,
         indexer: &mut Indexer,
     ) -> Result<()> {
-        let mut kv_pairs: Vec<KV<vart::VariableSizeKey, Bytes>> = tx
-            .entries
-            .iter()
-            .map(|entry| {
-                let index_value = ValueRef::encode(
-                    &entry.key,
-                    &entry.value,
-                    entry.metadata.as_ref(),
-                    value_offsets,
-                    opts.max_value_threshold,
-                );
-
-                KV {
-                    key: entry.key[..].into(),
-                    value: index_value,
-                    version: tx.header.id,
-                    ts: tx.header.ts,
+        let mut kv_pairs: Vec<KV<vart::VariableSizeKey, Bytes>> = Vec::new();
+        for &(key, md, value) in entries {
+            let index_value = if md.is_some_and(|md| md.touched()) {
+                let md = md.unwrap();
+                let flags = md.reflagged().then(|| md.flags());
+                match indexer.touched(key, md.expires_at(), flags)? {
+                    Some(index_value) => index_value,
+                    None => continue,
                 }
-            })
-            .collect();
+            } else if md.is_some_and(|md| md.renamed()) {
+                let md = md.unwrap();
+                match indexer.renamed(value, md.expires_at(), md.flags())? {
+                    Some(index_value) => index_value,
+                    None => continue,
+                }
+            } else {
+                ValueRef::encode(key, value, md, value_offsets, opts.max_value_threshold)
+            };
+
+            kv_pairs.push(KV {
+                key: key[..].into(),
+                value: index_value,
+                version: tx.header.id,
+                ts: tx.header.ts,
+            });
+        }
 
         indexer.bulk_insert(&mut kv_pairs)
     }
 
+    /// Looks for the segments of the commit log missing, such as segments deleted under the
+    /// store, and fails or returns them as `mode` says, see `Options::missing_segments`.
+    fn check_segments(
+        opts: &Options,
+        segment_keys: &SegmentKeyRanges,
+        mode: MissingSegments,
+    ) -> Result<Unavailable> {
+        let present = list_segment_ids(&*opts.vfs, &opts.dir.join("clog"))?;
+        let missing = segment_keys.missing(&present);
+        if missing.is_empty() {
+            return Ok(Unavailable::default());
+        }
+        match mode {
+            MissingSegments::Fail => Err(Error::MissingSegments(missing)),
+            MissingSegments::Degraded => {
+                for id in &missing {
+                    events::emit(
+                        events::RECOVERY,
+                        Level::Warn,
+                        "commit log segment missing, its keys are unavailable",
+                        &[("segment_id", Value::U64(*id))],
+                    );
+                }
+                Ok(segment_keys.unavailable(&missing))
+            }
+        }
+    }
+
+    /// Loads the index from the last checkpoint in `opts.dir`, without replaying the commit
+    /// log written after it, and opens the commit log to read the values from.
+    fn load_checkpoint(
+        opts: &Options,
+        indexer: &mut Indexer,
+        flag_index: &mut FlagIndex,
+    ) -> Result<(Aol, IndexCheckpoint)> {
+        let dir = opts.dir.join("checkpoint");
+        let checkpoint = loop {
+            let checkpoint =
+                IndexCheckpoint::open_read_only(&opts.vfs, &dir, opts.max_segment_size)?;
+            let generation = checkpoint.generation();
+            if generation == 0 {
+                return Err(Error::CheckpointUnavailable);
+            }
+            // The store made the commit log durable up to the offset of the checkpoint
+            // before writing it, so the offset is not checked against the log.
+            if checkpoint.load(u64::MAX, indexer, flag_index)? > 0 {
+                break checkpoint;
+            }
+            // The files of the checkpoint are removed once the store has written the next
+            // one, which is loaded instead.
+            let next = IndexCheckpoint::open_read_only(&opts.vfs, &dir, opts.max_segment_size)?;
+            if next.generation() == generation {
+                return Err(Error::CheckpointUnavailable);
+            }
+        };
+
+        // The log is opened once the checkpoint is read, so that it holds its offset.
+        let clog = Aol::open(&opts.dir.join("clog"), &Self::clog_options(opts))?;
+        Ok((clog, checkpoint))
+    }
+
     fn load_options(opts: &Options, manifest: &mut Aol) -> Result<Options> {
         let current_metadata = opts.to_metadata();
         let existing_metadata_list = if !manifest.size()? > 0 {
@@ -455,7 +2072,7 @@ impl Core {
 
         if let Some(existing) = existing_metadata_list.last() {
             if *existing == current_metadata {
-                return Options::from_metadata(current_metadata, opts.dir.clone());
+                return Core::stored_options(opts, current_metadata);
             }
         }
 
@@ -465,16 +2082,19 @@ impl Core {
         // Write the metadata to the manifest [md_len: u32][md_bytes: Vec<u8>]
         write_field(&md_bytes, &mut buf)?;
         manifest.append(&buf)?;
+        // The options are read from the manifest by `Store::open_checkpoint` while the store
+        // is open.
+        manifest.sync()?;
 
         // Update options with the loaded metadata.
-        Options::from_metadata(current_metadata, opts.dir.clone())
+        Core::stored_options(opts, current_metadata)
     }
 
     fn validate_options(opts: &Options, existing_metadata_list: &[Metadata]) -> Result<()> {
         let mut last_max_value_size = 0;
         let mut last_max_key_size = 0;
         for metadata in existing_metadata_list {
-            let options = Options::from_metadata(metadata.clone(), opts.dir.clone())?;
+            let options = Core::stored_options(opts, metadata.clone())?;
             if options.max_value_size < last_max_value_size {
                 return Err(Error::MaxValueSizeCannotBeDecreased);
             }
@@ -493,14 +2113,70 @@ impl Core {
             return Err(Error::MaxKeySizeCannotBeDecreased);
         }
 
+        if opts.erase_block_size > 0
+            && (opts.erase_block_size % BLOCK_SIZE as u64 != 0
+                || opts.max_segment_size % opts.erase_block_size != 0)
+        {
+            return Err(Error::InvalidOptions(format!(
+                "erase_block_size must be a multiple of {} bytes dividing max_segment_size",
+                BLOCK_SIZE
+            )));
+        }
+
+        if opts.auto_value_threshold && opts.min_value_threshold > opts.max_value_threshold {
+            return Err(Error::InvalidOptions(
+                "min_value_threshold is larger than max_value_threshold".to_string(),
+            ));
+        }
+
+        // Reject the options that cannot change for an existing store, the others are adopted.
+        if let Some(metadata) = existing_metadata_list.last() {
+            let persisted = Core::stored_options(opts, metadata.clone())?;
+            let changes = opts.incompatible_changes(&persisted);
+            if !changes.is_empty() {
+                return Err(Error::IncompatibleOptions(changes.join(", ")));
+            }
+        }
+
         Ok(())
     }
+
+    /// Returns the options stored in `metadata` for the store opened with `opts`, which is
+    /// in the same directory, on the same filesystem.
+    fn stored_options(opts: &Options, metadata: Metadata) -> Result<Options> {
+        Ok(Options {
+            vfs: opts.vfs.clone(),
+            ..Options::from_metadata(metadata, opts.dir.clone())?
+        })
+    }
+
+    /// Loads the compression rules of all the options stored in the manifest log.
+    fn load_compression_rules(opts: &Options) -> Result<Vec<CompressionRule>> {
+        let mut rules = Vec::new();
+        for metadata in Core::load_manifests(opts)? {
+            let options = Core::stored_options(opts, metadata)?;
+            rules.extend(options.compression);
+        }
+        Ok(rules)
+    }
+
+    /// Returns the options last stored in the manifest log, if the store has been created.
+    pub(crate) fn persisted_options(opts: &Options) -> Result<Option<Options>> {
+        if !opts.vfs.exists(&opts.dir.join("manifest")) {
+            return Ok(None);
+        }
+        match Core::load_manifests(opts)?.pop() {
+            Some(metadata) => Ok(Some(Core::stored_options(opts, metadata)?)),
+            None => Ok(None),
+        }
+    }
+
     /// Loads the latest options from the manifest log.
-    fn load_manifests(opts: &Options) -> Result<Vec<Metadata>> {
+    pub(crate) fn load_manifests(opts: &Options) -> Result<Vec<Metadata>> {
         let manifest_subdir = opts.dir.join("manifest");
-        let sr = SegmentRef::read_segments_from_directory(manifest_subdir.as_path())
+        let sr = SegmentRef::read_segments_from_directory(&*opts.vfs, &manifest_subdir)
             .expect("should read segments");
-        let reader = MultiSegmentReader::new(sr)?;
+        let reader = MultiSegmentReader::new(&opts.vfs, sr)?;
         let mut reader = Reader::new_from(reader, 0, BLOCK_SIZE);
 
         let mut manifests: Vec<Metadata> = Vec::new(); // Initialize with an empty Vec
@@ -526,10 +2202,38 @@ impl Core {
         Ok(manifests)
     }
 
-    fn is_closed(&self) -> bool {
+    pub(crate) fn is_closed(&self) -> bool {
         self.is_closed.load(std::sync::atomic::Ordering::Relaxed)
     }
 
+    /// Returns true if the store was opened from a checkpoint, or a failure of the commit log
+    /// made it read-only.
+    pub(crate) fn is_read_only(&self) -> bool {
+        self.read_only || self.fsync.is_failed()
+    }
+
+    /// Applies `Options::fsync_failure_policy` if `err` failed the commit log, which refuses
+    /// the writes from then on, and returns the error to report.
+    pub(crate) fn log_failed(&self, clog: &Aol, err: Error) -> Error {
+        if err.is_disk_full() || clog.disk_full() {
+            return self.disk_failed(err);
+        }
+        if !clog.fsync_failed() {
+            return err;
+        }
+        // The writes not synced before the fsync failed may be lost.
+        let err = self.fsync.failed(&self.invariants, err);
+        self.durable.failed(&err);
+        err
+    }
+
+    /// Reports that the disk is full: the reserved space is freed, for the store to be able
+    /// to close, and the writes are refused from then on. It returns `Error::DiskFull`.
+    pub(crate) fn disk_failed(&self, err: Error) -> Error {
+        self.headroom.release();
+        self.fsync.disk_full(err)
+    }
+
     pub(crate) fn close(&self) -> Result<()> {
         if self.is_closed() {
             return Ok(());
@@ -545,23 +2249,96 @@ impl Core {
 
         // Close the commit log if it exists
         if let Some(clog) = &self.clog {
-            clog.write().close()?;
+            let clog = clog.write();
+            clog.close().map_err(|err| {
+                let err = self.log_failed(&clog, err.into());
+                self.durable.failed(&err);
+                err
+            })?;
+            self.durable.synced(u64::MAX);
         }
 
+        // Wait for the redundancy of the sealed segments being written.
+        self.redundancy.wait();
+
         // Close the manifest if it exists
         if let Some(manifest) = &self.manifest {
             manifest.write().close()?;
         }
+
+        // Remove the published stats, which no longer describe a running store.
+        if self.stats_published_at.lock().is_some() {
+            match self
+                .opts
+                .vfs
+                .remove_file(&self.opts.dir.join(stats::STATS_FILE))
+            {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        // Wake up the callers waiting to begin a transaction, or for a version.
+        if let Some(slots) = &self.transaction_slots {
+            slots.close();
+        }
+        self.versions.close();
+
         self.is_closed
             .store(true, std::sync::atomic::Ordering::Relaxed);
 
         Ok(())
     }
 
-    pub(crate) async fn write_request(&self, req: Task) -> Result<()> {
+    pub(crate) async fn write_request(self: &Arc<Self>, req: Task) -> Result<()> {
+        let version = req.tx_id;
+        let result = self.write_task(req).await;
+        // The transaction is in the index, or never will be.
+        self.oracle.indexed(version);
+        result
+    }
+
+    async fn write_task(self: &Arc<Self>, mut req: Task) -> Result<()> {
         let done = req.done.clone();
 
+        self.stats
+            .commit_queue_ns
+            .record(req.enqueued_at.elapsed().as_nanos() as u64);
+        if !req.entries.is_empty() {
+            self.stats
+                .commit_batch_entries
+                .record(req.entries.len() as u64);
+        }
+
+        // The range deletes are expanded against the index before the entries are written;
+        // the writer is the only one to change it meanwhile.
+        req.expanded = range_delete::expand(&self.indexer.read(), &req.entries)?;
+
+        // Capture the mutations before the entries are consumed, if they are mirrored.
+        let mirror_batch = if self.mirror.is_running() {
+            Some(MirrorBatch::from_entries(
+                self,
+                req.indexed_entries(),
+                req.commit_ts,
+            )?)
+        } else {
+            None
+        };
+        let deliveries = self
+            .watches
+            .batches(self, req.indexed_entries(), req.commit_ts)?;
+
+        let entry_bytes = entries_size(&req.entries);
         let result = self.write_entries(req);
+        self.stats
+            .memory_sub(&self.stats.write_buffer_bytes, entry_bytes);
+
+        if let (Ok(()), Some(batch)) = (&result, mirror_batch) {
+            self.mirror.send(&self.stats, batch);
+        }
+        if result.is_ok() {
+            watch::deliver(deliveries);
+            self.gc_schedule.committed(self);
+        }
 
         if let Some(done) = done {
             done.send(result.clone()).await?;
@@ -574,6 +2351,18 @@ impl Core {
         if req.entries.is_empty() {
             return Ok(());
         }
+        self.invariants.check()?;
+        self.fsync.check()?;
+        let version = self.indexer.read().version();
+        if req.tx_id <= version {
+            self.invariants.violated(
+                "the versions written increase",
+                format!("version {} after version {}", req.tx_id, version),
+            )?;
+            // The index cannot take the transaction, so it is not written to the commit log
+            // either, where it would fail the next replay.
+            return Err(Error::InvalidTransactionRecordId);
+        }
 
         if self.opts.should_persist_data() {
             self.write_entries_to_disk(req)
@@ -583,47 +2372,130 @@ impl Core {
     }
 
     fn write_entries_to_disk(&self, req: Task) -> Result<()> {
-        let current_offset = self.clog.as_ref().unwrap().read().offset()?;
         let tx_record = TxRecord::new_with_entries(req.entries.clone(), req.tx_id, req.commit_ts);
-        let mut buf = BytesMut::new();
         let mut committed_values_offsets = HashMap::new();
 
-        tx_record.encode(&mut buf, current_offset, &mut committed_values_offsets)?;
+        // A clear starts a new segment, so that the segments before it are removed whole.
+        let cleared = req.entries.iter().any(Entry::is_clear);
+        if cleared {
+            let clog = self.clog.as_ref().unwrap().read();
+            clog.start_segment()
+                .map_err(|err| self.log_failed(&clog, err.into()))?;
+        }
+
+        let offset = self
+            .append_log(&tx_record, &mut committed_values_offsets, req.durability)
+            .map_err(|err| self.log_failed(&self.clog.as_ref().unwrap().read(), err))?;
+        if matches!(req.durability, Durability::Immediate) {
+            // Syncing the record synced the ones written before it too.
+            self.durable.synced(req.tx_id);
+        }
+        self.segment_keys.lock().record(
+            offset / self.opts.max_segment_size,
+            req.indexed_entries().iter().map(|e| &e.key[..]),
+        )?;
+        self.redundancy
+            .seal_before(offset / self.opts.max_segment_size, false);
+        if let Err(err) = self.write_index_with_committed_offsets(&req, &committed_values_offsets) {
+            // The transaction is in the commit log, and will be seen when it is replayed.
+            self.invariants.violated(
+                "the index takes the transactions of the commit log",
+                format!("version {} at offset {}: {}", req.tx_id, offset, err),
+            )?;
+            return Err(err);
+        }
+        if cleared {
+            self.remove_cleared_segments(offset / self.opts.max_segment_size);
+        }
+        Ok(())
+    }
 
-        self.append_log(&buf, req.durability)?;
-        self.write_index_with_committed_offsets(&req, &committed_values_offsets)
+    /// Removes the segments of the commit log before the one started by a clear, which is
+    /// durable. The clear is committed whether they are removed or not: the segments left are
+    /// dropped again when the store is opened.
+    fn remove_cleared_segments(&self, segment_id: u64) {
+        let removed = clear::remove_segments(
+            &self.clog.as_ref().unwrap().read(),
+            &mut self.segment_keys.lock(),
+            &self.redundancy,
+            segment_id,
+        )
+        .and_then(|()| self.quota.measure());
+        if let Err(err) = removed {
+            let error = err.to_string();
+            events::emit(
+                events::WRITER,
+                Level::Warn,
+                "the commit log segments dropped by a clear could not be removed",
+                &[
+                    ("segment_id", Value::U64(segment_id)),
+                    ("error", Value::Str(&error)),
+                ],
+            );
+        }
     }
 
     fn write_entries_to_memory(&self, req: Task) -> Result<()> {
         self.write_index_in_memory(&req)
     }
 
-    fn append_log(&self, tx_record: &BytesMut, durability: Durability) -> Result<()> {
-        let mut clog = self.clog.as_ref().unwrap().write();
-
+    /// Appends a transaction record to the commit log, and returns its offset.
+    pub(crate) fn append_log(
+        &self,
+        tx_record: &TxRecord,
+        committed_values_offsets: &mut HashMap<Bytes, usize>,
+        durability: Durability,
+    ) -> Result<u64> {
+        // Appending takes a shared lock, the values read from the commit log meanwhile do
+        // not wait for it.
+        let clog = self.clog.as_ref().unwrap().read();
+
+        let len = tx_record.encoded_len();
+        self.quota.check(len)?;
+        let offset = if len <= BLOCK_SIZE {
+            // The record is encoded in place, into the block of the commit log.
+            let mut slot = clog.reserve(len)?;
+            let offset = slot.offset();
+            tx_record.encode(&mut slot, offset, committed_values_offsets)?;
+            slot.finish()?.0
+        } else {
+            let mut buf = BytesMut::with_capacity(len);
+            tx_record.encode(&mut buf, clog.offset()?, committed_values_offsets)?;
+            clog.append(&buf)?.0
+        };
+        self.quota.appended(len, offset, self.opts.max_segment_size);
+        self.stats
+            .log_bytes_written
+            .fetch_add(len as u64, Ordering::Relaxed);
+
+        // The flash-friendly writes leave the eventual commits in the block being filled,
+        // which is written out whole, see `Store::sync_barrier`.
+        let durability = match durability {
+            Durability::Eventual if self.opts.erase_block_size > 0 => Durability::Weak,
+            durability => durability,
+        };
         match durability {
             Durability::Immediate => {
                 // Immediate durability means that the transaction is made to
                 // fsync the data to disk before returning.
-                clog.append(tx_record)?;
+                let started = Instant::now();
                 clog.sync()?;
+                self.stats.record_fsync(started.elapsed());
             }
             Durability::Eventual => {
                 // Eventual durability means that the transaction is made to
                 // write to disk using the write_all method. But it does not
                 // fsync the data to disk before returning.
-                clog.append(tx_record)?;
                 clog.flush()?;
             }
             Durability::Weak => {
                 // Weak durability means that the transaction is made to
                 // write to disk in size of BLOCK_SIZE. And it does not
                 // fsync the data to disk before returning.
-                clog.append(tx_record)?;
             }
         }
 
-        Ok(())
+        Ok(offset)
     }
 
     fn write_entries_to_index<F>(&self, task: &Task, encode_entry: F) -> Result<()>
@@ -633,8 +2505,32 @@ impl Core {
         let mut index = self.indexer.write();
         let mut kv_pairs = Vec::new();
 
-        for entry in &task.entries {
-            let index_value = encode_entry(entry);
+        // A clear drops the keys indexed before it, see `clear::entry`.
+        if task.entries.iter().any(Entry::is_clear) {
+            index.clear();
+            self.flag_index.write().clear();
+            self.index_checkpoint.mark_all();
+            self.value_cache.clear();
+            self.streams.reset();
+        }
+
+        for entry in task.indexed_entries() {
+            // Touch entries keep the value of the key in the index, with a new expiry.
+            let index_value = if entry.is_touch() {
+                let flags = entry.is_reflag().then(|| entry.flags());
+                match index.touched(&entry.key, entry.expires_at(), flags)? {
+                    Some(index_value) => index_value,
+                    None => continue,
+                }
+            } else if entry.is_rename() {
+                // Rename entries take the value of the key they rename, see `Entry::mark_rename`.
+                match index.renamed(&entry.value, entry.expires_at(), entry.flags())? {
+                    Some(index_value) => index_value,
+                    None => continue,
+                }
+            } else {
+                encode_entry(entry)
+            };
 
             kv_pairs.push(KV {
                 key: entry.key[..].into(),
@@ -645,6 +2541,12 @@ impl Core {
         }
 
         index.bulk_insert(&mut kv_pairs)?;
+        self.versions.advance(task.tx_id);
+        self.flag_index
+            .write()
+            .apply_entries(task.indexed_entries());
+        self.index_checkpoint
+            .mark(task.indexed_entries().iter().map(|e| &e.key[..]));
 
         Ok(())
     }
@@ -654,15 +2556,18 @@ impl Core {
         task: &Task,
         committed_values_offsets: &HashMap<Bytes, usize>,
     ) -> Result<()> {
+        let threshold = self.value_threshold.get();
         self.write_entries_to_index(task, |entry| {
             ValueRef::encode(
                 &entry.key,
                 &entry.value,
                 entry.metadata.as_ref(),
                 committed_values_offsets,
-                self.opts.max_value_threshold,
+                threshold,
             )
-        })
+        })?;
+        self.value_threshold.observe(&task.entries, &self.stats);
+        Ok(())
     }
 
     fn write_index_in_memory(&self, task: &Task) -> Result<()> {
@@ -678,28 +2583,45 @@ impl Core {
         commit_ts: u64,
         durability: Durability,
     ) -> Result<Receiver<Result<()>>> {
+        self.invariants.check()?;
         let (tx, rx) = bounded(1);
+        self.stats
+            .memory_add(&self.stats.write_buffer_bytes, entries_size(&entries));
         let req = Task {
             entries,
             done: Some(tx),
             tx_id,
             commit_ts,
             durability,
+            enqueued_at: Instant::now(),
+            expanded: None,
         };
         self.writes_tx.send(req).await?;
         Ok(rx)
     }
 }
 
+/// Returns the number of bytes of the keys and values of the entries.
+fn entries_size(entries: &[Entry]) -> usize {
+    entries.iter().map(|e| e.key.len() + e.value.len()).sum()
+}
+
+/// Returns true if an index value is the tombstone of a deleted key. A value whose metadata
+/// cannot be decoded is not taken for one.
+fn is_tombstone(value: &Bytes) -> bool {
+    ValueRef::decode_metadata(value).is_ok_and(|md| md.is_some_and(|md| md.deleted()))
+}
+
 #[cfg(test)]
 mod tests {
     use rand::prelude::SliceRandom;
     use rand::Rng;
     use std::sync::Arc;
 
-    use crate::storage::kv::option::Options;
+    use crate::storage::kv::error::{Error, Result};
+    use crate::storage::kv::option::{Options, VersionRetention};
     use crate::storage::kv::store::{Store, Task, TaskRunner};
-    use crate::storage::kv::transaction::Durability;
+    use crate::storage::kv::transaction::{Durability, Transaction};
 
     use async_channel::bounded;
     use std::sync::atomic::{AtomicU64, Ordering};
@@ -979,6 +2901,8 @@ mod tests {
                     tx_id: i,
                     commit_ts: i,
                     durability: Durability::default(),
+                    enqueued_at: std::time::Instant::now(),
+                    expanded: None,
                 })
                 .await
                 .unwrap();
@@ -1302,6 +3226,186 @@ mod tests {
         test_durability(Durability::Immediate, false).await;
     }
 
+    #[tokio::test]
+    async fn shrink_index() {
+        let temp_dir = create_temp_directory();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.track_memory = true;
+        let store = Store::new(opts.clone()).expect("should create store");
+
+        for round in 0..3u8 {
+            let mut txn = store.begin().unwrap();
+            for i in 0..100u32 {
+                txn.set(&i.to_be_bytes(), &[round; 8]).unwrap();
+            }
+            txn.commit().await.unwrap();
+        }
+        let mut txn = store.begin().unwrap();
+        for i in 0..50u32 {
+            txn.delete(&i.to_be_bytes()).unwrap();
+        }
+        txn.commit().await.unwrap();
+
+        let before = store.begin().unwrap();
+        let index_bytes = store.stats().index_bytes;
+        let reclaimed = store.shrink_index().unwrap();
+        assert!(reclaimed > index_bytes / 2);
+        assert_eq!(store.stats().index_bytes, index_bytes - reclaimed);
+
+        // Transactions see the latest versions, and keep working after the rebuild.
+        let txn = store.begin().unwrap();
+        let results = txn.scan(.., None).unwrap();
+        assert_eq!(results.len(), 50);
+        assert!(results.iter().all(|(_, v, _, _)| v[..] == [2; 8]));
+        assert_eq!(before.scan(.., None).unwrap().len(), 50);
+
+        let mut txn = store.begin().unwrap();
+        txn.set(&0u32.to_be_bytes(), b"new").unwrap();
+        txn.commit().await.unwrap();
+        store.close().await.unwrap();
+
+        let store = Store::new(opts).expect("should reopen store");
+        let txn = store.begin().unwrap();
+        assert_eq!(txn.scan(.., None).unwrap().len(), 51);
+    }
+
+    #[tokio::test]
+    async fn shrink_index_retains_versions() {
+        let temp_dir = create_temp_directory();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.version_retention = VersionRetention::Count(2);
+        let store = Store::new(opts.clone()).expect("should create store");
+
+        for round in [&b"1"[..], b"2", b"3"] {
+            let mut txn = store.begin().unwrap();
+            txn.set(b"a", round).unwrap();
+            match round {
+                b"1" => txn.set(b"b", round).unwrap(),
+                b"3" => txn.delete(b"b").unwrap(),
+                _ => {}
+            }
+            txn.append_value(b"c", round).unwrap();
+            txn.commit().await.unwrap();
+        }
+        let values = |store: &Store, key: &[u8]| {
+            let txn = store.begin().unwrap();
+            let history = txn.history(key).unwrap();
+            history
+                .into_iter()
+                .map(|(_, value)| value)
+                .collect::<Vec<_>>()
+        };
+        let some = |value: &[u8]| Some(value.to_vec());
+
+        // The two latest versions of each key are kept, with the merged value of the chunks.
+        store.shrink_index().unwrap();
+        assert_eq!(values(&store, b"a"), vec![some(b"2"), some(b"3")]);
+        assert_eq!(values(&store, b"b"), vec![some(b"1"), None]);
+        assert_eq!(values(&store, b"c"), vec![some(b"12"), some(b"123")]);
+        store.close().await.unwrap();
+
+        // The versions committed from the watermark are kept, the replayed ones included.
+        opts.version_retention = VersionRetention::Latest;
+        let store = Store::new(opts).expect("should reopen store");
+        let txn = store.begin().unwrap();
+        let history = txn.history(b"a").unwrap();
+        drop(txn);
+        assert_eq!(history.len(), 3);
+        store.set_gc_watermark(history[1].0);
+        store.shrink_index().unwrap();
+        assert_eq!(values(&store, b"a"), vec![some(b"2"), some(b"3")]);
+        assert_eq!(values(&store, b"b"), vec![None]);
+
+        store.set_gc_watermark(u64::MAX);
+        store.shrink_index().unwrap();
+        assert_eq!(values(&store, b"a"), vec![some(b"3")]);
+        assert!(values(&store, b"b").is_empty());
+        assert_eq!(values(&store, b"c"), vec![some(b"123")]);
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn suspend_and_resume() {
+        let temp_dir = create_temp_directory();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        let mut store = Store::new(opts.clone()).expect("should create store");
+        store.register_migration(b"user/", 2, |_, payload| [payload, b"+v2"].concat());
+
+        let mut txn = store.begin().unwrap();
+        txn.set(b"k1", b"v1").unwrap();
+        txn.set_versioned(b"user/1", 1, b"alice").unwrap();
+        txn.commit().await.unwrap();
+        let mut txn = store.begin().unwrap();
+        txn.set(b"k1", b"v2").unwrap();
+        txn.set(b"k2", b"v2").unwrap();
+        txn.commit().await.unwrap();
+
+        store.suspend().await.unwrap();
+        assert!(matches!(store.begin(), Err(Error::StoreSuspended)));
+        store.suspend().await.unwrap();
+        assert!(temp_dir.path().join("checkpoint").exists());
+
+        store.resume().unwrap();
+        let mut txn = store.begin().unwrap();
+        assert_eq!(txn.get(b"k1").unwrap().unwrap(), b"v2");
+        assert_eq!(txn.get(b"k2").unwrap().unwrap(), b"v2");
+        // The migrations registered before the store was suspended are kept.
+        assert_eq!(
+            txn.get_versioned(b"user/1").unwrap().unwrap(),
+            (2, b"alice+v2".to_vec())
+        );
+        txn.set(b"k3", b"v3").unwrap();
+        txn.commit().await.unwrap();
+        store.close().await.unwrap();
+
+        let store = Store::new(opts).expect("should open store");
+        let txn = store.begin().unwrap();
+        assert_eq!(txn.scan(.., None).unwrap().len(), 4);
+        drop(txn);
+        store.close().await.unwrap();
+
+        // A store that does not persist its data cannot be suspended.
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().join("memory");
+        opts.disk_persistence = false;
+        let mut store = Store::new(opts).expect("should create store");
+        assert!(store.suspend().await.is_err());
+        assert!(store.begin().is_ok());
+    }
+
+    #[tokio::test]
+    async fn option_drift_on_reopen() {
+        let temp_dir = create_temp_directory();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+
+        assert!(Store::persisted_options(&opts.dir).unwrap().is_none());
+        let store = Store::new(opts.clone()).expect("should create store");
+        store.close().await.unwrap();
+        assert_eq!(Store::persisted_options(&opts.dir).unwrap().unwrap(), opts);
+
+        // Changeable options are adopted.
+        opts.max_entries_per_txn = 10;
+        let store = Store::new(opts.clone()).expect("should reopen store");
+        store.close().await.unwrap();
+        let persisted = Store::persisted_options(&opts.dir).unwrap().unwrap();
+        assert_eq!(persisted.max_entries_per_txn, 10);
+
+        // Incompatible options are rejected with the difference.
+        let mut changed = opts.clone();
+        changed.max_segment_size = 1 << 20;
+        match Store::new(changed) {
+            Err(Error::IncompatibleOptions(diff)) => {
+                assert_eq!(diff, "max_segment_size: 536870912 -> 1048576")
+            }
+            _ => panic!("expected incompatible options"),
+        }
+        assert_eq!(Store::persisted_options(&opts.dir).unwrap().unwrap(), opts);
+    }
+
     #[tokio::test]
     async fn store_without_persistance() {
         // Create a temporary directory for testing
@@ -1360,4 +3464,103 @@ mod tests {
             assert!(txn.get(key).unwrap().is_none());
         }
     }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn update_retries_conflicts() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        let store = Store::new(opts.clone()).expect("should create store");
+
+        // The first attempt conflicts with a commit made after its read.
+        fn increment(store: &Store, txn: &mut Transaction, conflict: bool) -> Result<u8> {
+            let n = txn.get(b"n")?.map_or(0, |v| v[0]);
+            if conflict {
+                let mut other = store.begin()?;
+                other.set(b"n", &[n + 10])?;
+                futures::executor::block_on(other.commit())?;
+            }
+            txn.set(b"n", &[n + 1])?;
+            Ok(n + 1)
+        }
+        let mut calls = 0;
+        let n = store
+            .update(|txn| {
+                calls += 1;
+                increment(&store, txn, calls == 1)
+            })
+            .await
+            .unwrap();
+        assert_eq!((n, calls), (11, 2));
+        store.close().await.unwrap();
+
+        opts.max_update_attempts = 1;
+        let store = Store::new(opts).expect("should open store");
+        let mut calls = 0;
+        let err = store
+            .update(|txn| {
+                calls += 1;
+                increment(&store, txn, true)
+            })
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::TransactionReadConflict));
+        assert_eq!(calls, 1);
+
+        // An error of the function is returned without retrying.
+        let mut calls = 0;
+        let err = store
+            .update(|_| -> Result<()> {
+                calls += 1;
+                Err(Error::EmptyKey)
+            })
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::EmptyKey));
+        assert_eq!(calls, 1);
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn flash_writes_wait_for_the_barrier() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let clog_size = || -> u64 {
+            std::fs::read_dir(temp_dir.path().join("clog"))
+                .unwrap()
+                .map(|entry| entry.unwrap().metadata().unwrap().len())
+                .sum()
+        };
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.max_segment_size = 1 << 20;
+        opts.erase_block_size = 1000;
+        assert!(matches!(
+            Store::new(opts.clone()),
+            Err(Error::InvalidOptions(_))
+        ));
+        opts.erase_block_size = 256 * 1024;
+        let store = Store::new(opts.clone()).expect("should create store");
+
+        // The eventual commits stay in the block being filled until the barrier.
+        let before = clog_size();
+        let mut txn = store.begin().unwrap();
+        txn.set(b"a", b"1").unwrap();
+        txn.commit().await.unwrap();
+        assert_eq!(clog_size(), before);
+        let txn = store.begin().unwrap();
+        assert_eq!(txn.get(b"a").unwrap(), Some(b"1".to_vec()));
+        drop(txn);
+
+        // The records of the segment begin after a header padded to an erase block.
+        assert_eq!(before, 256 * 1024);
+        store.sync_barrier().await.unwrap();
+        assert!(clog_size() > before);
+        let written = store.stats().log_bytes_written;
+        assert!(written > 0 && written <= clog_size());
+        store.close().await.unwrap();
+
+        // The erase block size is persisted with the options of the store.
+        let persisted = super::Core::persisted_options(&opts).unwrap().unwrap();
+        assert_eq!(persisted.erase_block_size, 256 * 1024);
+    }
 }
@@ -0,0 +1,310 @@
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+
+use bytes::{BufMut, Bytes};
+use hashbrown::HashMap;
+use parking_lot::Mutex;
+
+use crate::storage::kv::{
+    error::{Error, Result},
+    store::Core,
+    transaction::{Mode, Transaction},
+    util::system_key,
+};
+
+/// Subsystem prefix of the keys holding the entries of a stream.
+const STREAM_ENTRY_SUBSYSTEM: &[u8] = b"stream/e/";
+/// Subsystem prefix of the keys holding the sequence hint of a stream.
+const STREAM_META_SUBSYSTEM: &[u8] = b"stream/m/";
+
+/// Returns the key of the entry with the given sequence number in a stream:
+/// [system prefix][stream/e/][name_len: u16][name][seq: u64]
+fn entry_key(stream_key: &[u8], seq: u64) -> Result<Bytes> {
+    let mut key = system_key(STREAM_ENTRY_SUBSYSTEM, stream_key)?;
+    key.put_u64(seq);
+    Ok(key.freeze())
+}
+
+/// Returns the key holding the next sequence number of a stream as last written.
+fn meta_key(stream_key: &[u8]) -> Result<Bytes> {
+    Ok(system_key(STREAM_META_SUBSYSTEM, stream_key)?.freeze())
+}
+
+/// Decodes the sequence number from a stream entry key.
+fn decode_seq(key: &[u8]) -> Result<u64> {
+    if key.len() < 8 {
+        return Err(Error::CorruptedIndex);
+    }
+    let mut seq = [0u8; 8];
+    seq.copy_from_slice(&key[key.len() - 8..]);
+    Ok(u64::from_be_bytes(seq))
+}
+
+/// Borrows the key of a bound as a slice.
+fn as_slice_bound(bound: &Bound<Bytes>) -> Bound<&[u8]> {
+    match bound {
+        Bound::Included(key) => Bound::Included(&key[..]),
+        Bound::Excluded(key) => Bound::Excluded(&key[..]),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+/// `Streams` hands out the sequence numbers of append-only streams.
+///
+/// Every entry of a stream is stored under its own key, so appending is a blind write that
+/// never reads or rewrites earlier entries, and concurrent appends to the same stream never
+/// conflict. Sequence numbers are allocated in memory, before the append commits, and are not
+/// handed back when the commit fails: a failed append leaves a gap in the sequence numbers of
+/// the stream. The next sequence number of a stream is recovered from the store the first
+/// time the stream is used after opening.
+pub(crate) struct Streams {
+    next_seqs: Mutex<HashMap<Bytes, u64>>,
+}
+
+impl Streams {
+    pub(crate) fn new() -> Self {
+        Self {
+            next_seqs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Forgets the sequence numbers allocated, when the store is cleared.
+    pub(crate) fn reset(&self) {
+        self.next_seqs.lock().clear();
+    }
+
+    /// Allocates the next sequence number of the stream.
+    fn allocate(&self, core: &Arc<Core>, stream_key: &[u8]) -> Result<u64> {
+        let mut next_seqs = self.next_seqs.lock();
+        if let Some(next) = next_seqs.get_mut(stream_key) {
+            let seq = *next;
+            *next += 1;
+            return Ok(seq);
+        }
+
+        let seq = Self::recover(core, stream_key)?;
+        next_seqs.insert(Bytes::copy_from_slice(stream_key), seq + 1);
+        Ok(seq)
+    }
+
+    /// Recovers the next sequence number of a stream from the store.
+    ///
+    /// The sequence hint is written with every append, but appends may commit out of order,
+    /// so the entries following the hint are scanned to find the highest sequence number used.
+    fn recover(core: &Arc<Core>, stream_key: &[u8]) -> Result<u64> {
+        let txn = Transaction::new(core.clone(), Mode::ReadOnly)?;
+
+        let mut next = match txn.get(&meta_key(stream_key)?)? {
+            Some(value) if value.len() == 8 => {
+                let mut buf = [0u8; 8];
+                buf.copy_from_slice(&value);
+                u64::from_be_bytes(buf)
+            }
+            Some(_) => return Err(Error::CorruptedIndex),
+            None => 0,
+        };
+
+        let start = entry_key(stream_key, next)?;
+        let end = entry_key(stream_key, u64::MAX)?;
+        for (key, _, _, _) in txn.scan(&start[..]..=&end[..], None)? {
+            next = next.max(decode_seq(&key)? + 1);
+        }
+
+        Ok(next)
+    }
+
+    /// Appends a value to a stream and returns its sequence number.
+    pub(crate) async fn append(
+        &self,
+        core: &Arc<Core>,
+        stream_key: &[u8],
+        value: &[u8],
+    ) -> Result<u64> {
+        let seq = self.allocate(core, stream_key)?;
+
+        let mut txn = Transaction::new(core.clone(), Mode::WriteOnly)?;
+        txn.set_system(&entry_key(stream_key, seq)?, value)?;
+        txn.set_system(&meta_key(stream_key)?, &(seq + 1).to_be_bytes())?;
+
+        // Drop the entry falling out of the retention window.
+        let max_len = core.opts.max_stream_length;
+        if max_len > 0 && seq >= max_len {
+            txn.delete_system(&entry_key(stream_key, seq - max_len)?)?;
+        }
+
+        txn.commit().await?;
+        Ok(seq)
+    }
+
+    /// Reads the entries of a stream whose sequence numbers fall in the given range.
+    pub(crate) fn read<R>(
+        core: &Arc<Core>,
+        stream_key: &[u8],
+        range: R,
+    ) -> Result<Vec<(u64, Vec<u8>)>>
+    where
+        R: RangeBounds<u64>,
+    {
+        let start = match range.start_bound() {
+            Bound::Included(seq) => Bound::Included(entry_key(stream_key, *seq)?),
+            Bound::Excluded(seq) => Bound::Excluded(entry_key(stream_key, *seq)?),
+            Bound::Unbounded => Bound::Included(entry_key(stream_key, 0)?),
+        };
+        let end = match range.end_bound() {
+            Bound::Included(seq) => Bound::Included(entry_key(stream_key, *seq)?),
+            Bound::Excluded(seq) => Bound::Excluded(entry_key(stream_key, *seq)?),
+            Bound::Unbounded => Bound::Included(entry_key(stream_key, u64::MAX)?),
+        };
+
+        let txn = Transaction::new(core.clone(), Mode::ReadOnly)?;
+        let results = txn.scan((as_slice_bound(&start), as_slice_bound(&end)), None)?;
+
+        results
+            .into_iter()
+            .map(|(key, value, _, _)| Ok((decode_seq(&key)?, value)))
+            .collect()
+    }
+
+    /// Deletes the entries of a stream with a sequence number lower than `before_seq`.
+    /// Returns the number of entries deleted.
+    pub(crate) async fn trim(
+        core: &Arc<Core>,
+        stream_key: &[u8],
+        before_seq: u64,
+    ) -> Result<usize> {
+        let start = entry_key(stream_key, 0)?;
+        let end = entry_key(stream_key, before_seq)?;
+        let batch_size = core.opts.max_entries_per_txn as usize;
+
+        let mut deleted = 0;
+        loop {
+            let mut txn = Transaction::new(core.clone(), Mode::ReadWrite)?;
+            let keys: Vec<Vec<u8>> = txn
+                .scan(&start[..]..&end[..], Some(batch_size))?
+                .into_iter()
+                .map(|(key, _, _, _)| key)
+                .collect();
+
+            if keys.is_empty() {
+                return Ok(deleted);
+            }
+
+            for key in &keys {
+                txn.delete_system(key)?;
+            }
+            txn.commit().await?;
+            deleted += keys.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+
+    use tempdir::TempDir;
+
+    fn create_temp_directory() -> TempDir {
+        TempDir::new("test").unwrap()
+    }
+
+    fn create_store(max_stream_length: u64) -> (Store, TempDir) {
+        let temp_dir = create_temp_directory();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.max_stream_length = max_stream_length;
+        (Store::new(opts).expect("should create store"), temp_dir)
+    }
+
+    #[tokio::test]
+    async fn append_and_read() {
+        let (store, _temp_dir) = create_store(0);
+
+        for i in 0..10u64 {
+            let seq = store
+                .append_to_stream(b"metrics", format!("v{}", i).as_bytes())
+                .await
+                .unwrap();
+            assert_eq!(seq, i);
+        }
+        store.append_to_stream(b"other", b"x").await.unwrap();
+
+        let entries = store.read_stream(b"metrics", 3..6).unwrap();
+        let seqs: Vec<u64> = entries.iter().map(|(seq, _)| *seq).collect();
+        assert_eq!(seqs, vec![3, 4, 5]);
+        assert_eq!(entries[0].1, b"v3");
+
+        assert_eq!(store.read_stream(b"metrics", ..).unwrap().len(), 10);
+        assert_eq!(store.read_stream(b"other", ..).unwrap().len(), 1);
+
+        // Stream entries are not visible to user scans.
+        let txn = store.begin().unwrap();
+        assert!(txn.scan(.., None).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn sequence_numbers_survive_reopen() {
+        let temp_dir = create_temp_directory();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+
+        let store = Store::new(opts.clone()).expect("should create store");
+        for _ in 0..5 {
+            store.append_to_stream(b"s", b"v").await.unwrap();
+        }
+        store.close().await.unwrap();
+
+        let store = Store::new(opts).expect("should reopen store");
+        assert_eq!(store.append_to_stream(b"s", b"v").await.unwrap(), 5);
+        assert_eq!(store.read_stream(b"s", ..).unwrap().len(), 6);
+    }
+
+    #[tokio::test]
+    async fn retention_and_trim() {
+        let (store, _temp_dir) = create_store(3);
+
+        for _ in 0..10 {
+            store.append_to_stream(b"s", b"v").await.unwrap();
+        }
+        let seqs: Vec<u64> = store
+            .read_stream(b"s", ..)
+            .unwrap()
+            .iter()
+            .map(|(seq, _)| *seq)
+            .collect();
+        assert_eq!(seqs, vec![7, 8, 9]);
+
+        assert_eq!(store.trim_stream(b"s", 9).await.unwrap(), 2);
+        assert_eq!(store.read_stream(b"s", ..).unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn concurrent_appends() {
+        let (store, _temp_dir) = create_store(0);
+        let store = Arc::new(store);
+
+        let mut handles = Vec::new();
+        for _ in 0..4 {
+            let store = store.clone();
+            handles.push(tokio::spawn(async move {
+                for _ in 0..25 {
+                    store.append_to_stream(b"s", b"v").await.unwrap();
+                }
+            }));
+        }
+        for handle in handles {
+            handle.await.unwrap();
+        }
+
+        let seqs: Vec<u64> = store
+            .read_stream(b"s", ..)
+            .unwrap()
+            .iter()
+            .map(|(seq, _)| *seq)
+            .collect();
+        assert_eq!(seqs, (0..100).collect::<Vec<u64>>());
+    }
+}
@@ -0,0 +1,204 @@
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::Duration;
+
+use crossbeam_channel::{bounded, RecvTimeoutError, Sender};
+
+use crate::storage::kv::{
+    error::{Error, Result},
+    events::{self, Activity},
+    jobs::JobKind,
+    store::Core,
+    transaction::{Mode, Transaction},
+};
+
+/// Number of keys of the index examined at a time, under its read lock.
+const SCAN_CHUNK: usize = 4096;
+
+/// Writes a tombstone for each user key whose latest version is expired, and returns the
+/// number of keys swept, see `Store::sweep_expired`.
+pub(crate) async fn sweep(core: &Arc<Core>) -> Result<usize> {
+    if core.is_read_only() {
+        return Err(Error::StoreReadOnly);
+    }
+    let activity = Activity::start(events::GC, "expiry sweep", &[]);
+    let swept = sweep_expired(core).await;
+    activity.finish(swept, |swept| vec![("swept_keys", *swept as u64)])
+}
+
+async fn sweep_expired(core: &Arc<Core>) -> Result<usize> {
+    let batch_size = core.opts.max_entries_per_txn.max(1) as usize;
+    let mut swept = 0;
+    let mut after = None;
+    loop {
+        let mut expired = Vec::new();
+        let last = core.indexer.read().expired_keys(
+            after.as_deref(),
+            SCAN_CHUNK,
+            core.opts.expiry_clock.now(),
+            &mut expired,
+        )?;
+        for keys in expired.chunks(batch_size) {
+            swept += delete_expired(core, keys).await?;
+        }
+        match last {
+            Some(key) => after = Some(key),
+            None => return Ok(swept),
+        }
+    }
+}
+
+/// Deletes the keys that are still expired in one transaction, and returns their number. The
+/// keys rewritten since they were found conflict with the transaction, which is then dropped:
+/// the next sweep finds the keys that expired again.
+async fn delete_expired(core: &Arc<Core>, keys: &[Vec<u8>]) -> Result<usize> {
+    let mut txn = Transaction::new(core.clone(), Mode::ReadWrite)?;
+    let mut deleted = 0;
+    for key in keys {
+        if txn.get(key)?.is_none() {
+            txn.delete(key)?;
+            deleted += 1;
+        }
+    }
+    if deleted == 0 {
+        return Ok(0);
+    }
+    match txn.commit().await {
+        Ok(()) => Ok(deleted),
+        Err(Error::TransactionReadConflict) => Ok(0),
+        Err(err) => Err(err),
+    }
+}
+
+/// The thread sweeping the expired keys every `Options::expiry_sweep_interval` milliseconds.
+///
+/// The thread queues each sweep as a background job and waits for it to run, and holds the
+/// core only while sweeping, so that the store is dropped as usual. It stops once the sweeper is
+/// stopped or dropped, after the sweep it may be running.
+pub(crate) struct Sweeper {
+    stop_tx: Sender<()>,
+}
+
+impl Sweeper {
+    /// Starts sweeping the store of `core`, or returns None if the sweeps are disabled or the
+    /// store cannot be written to.
+    pub(crate) fn start(core: &Arc<Core>) -> Option<Self> {
+        let interval = Duration::from_millis(core.opts.expiry_sweep_interval);
+        if interval.is_zero() || core.is_read_only() {
+            return None;
+        }
+        if !core.jobs.has_runtime() {
+            return None;
+        }
+        let core = Arc::downgrade(core);
+        let (stop_tx, stop_rx) = bounded(1);
+        thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stop_rx.recv_timeout(interval) {
+                let Some(core) = Weak::upgrade(&core) else {
+                    return;
+                };
+                let job = core.clone();
+                let queued = core.jobs.submit(JobKind::Expiry, async move {
+                    // A failed sweep is reported by its activity, and the next one is due as usual.
+                    let _ = sweep(&job).await;
+                });
+                drop(core);
+                match queued {
+                    Some(queued) => queued.wait_blocking(),
+                    None => return,
+                }
+            }
+        });
+        Some(Self { stop_tx })
+    }
+
+    /// Stops the sweeps, without waiting for the one running to finish.
+    pub(crate) fn stop(&self) {
+        let _ = self.stop_tx.try_send(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::storage::kv::entry::ValueRef;
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+    use crate::storage::kv::util::now;
+
+    use tempdir::TempDir;
+
+    fn expired_keys(store: &Store) -> Vec<Vec<u8>> {
+        let core = &store.inner.as_ref().unwrap().core;
+        let mut expired = Vec::new();
+        let indexer = core.indexer.read();
+        indexer
+            .expired_keys(None, usize::MAX, now(), &mut expired)
+            .unwrap();
+        expired
+    }
+
+    fn deleted(store: &Store, key: &[u8]) -> bool {
+        let core = &store.inner.as_ref().unwrap().core;
+        let indexer = core.indexer.read();
+        let key = core.keys.lookup(key).unwrap();
+        let (value, ..) = indexer.get_at(&key, indexer.version()).unwrap().unwrap();
+        let metadata = ValueRef::decode_metadata(&value).unwrap();
+        metadata.is_some_and(|md| md.deleted())
+    }
+
+    #[tokio::test]
+    async fn expired_keys_are_swept() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        let store = Store::new(opts.clone()).expect("should create store");
+
+        let mut txn = store.begin().unwrap();
+        for key in [&b"a"[..], b"b", b"c"] {
+            txn.set_with_ttl(key, b"1", Duration::from_millis(1))
+                .unwrap();
+        }
+        txn.set_with_ttl(b"kept", b"1", Duration::from_secs(3600))
+            .unwrap();
+        txn.set(b"plain", b"1").unwrap();
+        txn.commit().await.unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+
+        // A key rewritten after it expired is not swept.
+        let mut txn = store.begin().unwrap();
+        txn.set(b"c", b"2").unwrap();
+        txn.commit().await.unwrap();
+
+        assert_eq!(store.sweep_expired().await.unwrap(), 2);
+        assert!(deleted(&store, b"a") && deleted(&store, b"b"));
+        assert!(expired_keys(&store).is_empty());
+        assert_eq!(store.sweep_expired().await.unwrap(), 0);
+        let txn = store.begin().unwrap();
+        assert_eq!(txn.get(b"c").unwrap().unwrap(), b"2");
+        assert!(txn.get(b"kept").unwrap().is_some());
+        assert!(txn.get(b"plain").unwrap().is_some());
+        drop(txn);
+
+        // The tombstones are replayed when the store is reopened.
+        store.close().await.unwrap();
+        opts.expiry_sweep_interval = 1;
+        let store = Store::new(opts).expect("should open store");
+        assert!(deleted(&store, b"a"));
+
+        // The sweeper of the store sweeps the keys expiring.
+        let mut txn = store.begin().unwrap();
+        txn.set_with_ttl(b"d", b"1", Duration::from_millis(1))
+            .unwrap();
+        txn.commit().await.unwrap();
+        let mut attempts = 0;
+        while !deleted(&store, b"d") {
+            assert!(attempts < 1000, "the sweeper should delete the key");
+            attempts += 1;
+            tokio::task::yield_now().await;
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        store.close().await.unwrap();
+    }
+}
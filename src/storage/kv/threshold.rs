@@ -0,0 +1,153 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use parking_lot::Mutex;
+
+use crate::storage::kv::{
+    entry::Entry,
+    option::Options,
+    stats::{Histogram, Stats},
+};
+
+/// Number of values written between two adjustments of the threshold.
+const TUNING_WINDOW: u64 = 1024;
+
+/// Percentile of the written value sizes chosen as threshold when every read is a cache hit.
+const MIN_TARGET_PERCENTILE: u64 = 50;
+
+/// Percentile of the written value sizes chosen as threshold when every read is a cache miss.
+const MAX_TARGET_PERCENTILE: u64 = 90;
+
+/// `ValueThreshold` is the size up to which values are stored in the index rather than read
+/// from the commit log.
+///
+/// With `Options::auto_value_threshold`, the threshold is adjusted every `TUNING_WINDOW`
+/// values written, to a percentile of the sizes written since the last adjustment, clamped
+/// between `Options::min_value_threshold` and `Options::max_value_threshold`. The percentile
+/// grows with the share of the reads missing the value cache: when most reads are served
+/// from the cache, reading a value from the commit log is cheap and only the small values
+/// are kept in the index memory; when most reads go to the commit log, more values are kept
+/// in the index to save them.
+///
+/// The threshold only applies to the values written after it changes, the values already
+/// indexed stay where they are. The tuning starts again from `Options::max_value_threshold`
+/// when the store is opened.
+pub(crate) struct ValueThreshold {
+    current: AtomicUsize,
+    bounds: Option<(usize, usize)>,
+    sizes: Histogram,
+    // Values of the cache counters at the last adjustment.
+    reads: Mutex<(u64, u64)>,
+}
+
+impl ValueThreshold {
+    pub(crate) fn new(opts: &Options) -> Self {
+        Self {
+            current: AtomicUsize::new(opts.max_value_threshold),
+            bounds: opts
+                .auto_value_threshold
+                .then_some((opts.min_value_threshold, opts.max_value_threshold)),
+            sizes: Histogram::default(),
+            reads: Mutex::new((0, 0)),
+        }
+    }
+
+    /// Returns the current threshold.
+    pub(crate) fn get(&self) -> usize {
+        self.current.load(Ordering::Relaxed)
+    }
+
+    /// Records the sizes of the values written, and adjusts the threshold once enough values
+    /// have been seen.
+    pub(crate) fn observe(&self, entries: &[Entry], stats: &Stats) {
+        let Some((min, max)) = self.bounds else {
+            return;
+        };
+        // Touch and rename entries carry no value.
+        for entry in entries.iter().filter(|e| !e.is_touch() && !e.is_rename()) {
+            self.sizes.record(entry.value.len() as u64);
+        }
+        if self.sizes.count() < TUNING_WINDOW {
+            return;
+        }
+        let Some(mut reads) = self.reads.try_lock() else {
+            return;
+        };
+
+        let hits = stats.cache_reads.load(Ordering::Relaxed);
+        let misses = stats.log_reads.load(Ordering::Relaxed);
+        let (last_hits, last_misses) = *reads;
+        *reads = (hits, misses);
+        let window_hits = hits - last_hits;
+        let window_misses = misses - last_misses;
+
+        let target = match window_hits + window_misses {
+            0 => MIN_TARGET_PERCENTILE,
+            total => {
+                MIN_TARGET_PERCENTILE
+                    + (MAX_TARGET_PERCENTILE - MIN_TARGET_PERCENTILE) * window_misses / total
+            }
+        };
+        let threshold = (self.sizes.percentile(target) as usize).clamp(min, max);
+        self.current.store(threshold, Ordering::Relaxed);
+        self.sizes.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+
+    use tempdir::TempDir;
+
+    #[tokio::test]
+    async fn threshold_follows_value_sizes() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.auto_value_threshold = true;
+        opts.min_value_threshold = 8;
+        opts.max_value_threshold = 256;
+        let store = Store::new(opts.clone()).expect("should create store");
+        assert_eq!(store.stats().value_threshold, 256);
+
+        // Mostly small values: the threshold follows their size down.
+        let mut txn = store.begin().unwrap();
+        for i in 0..1024u32 {
+            let len = if i % 10 == 0 { 200 } else { 20 };
+            txn.set(&i.to_be_bytes(), &vec![1; len]).unwrap();
+        }
+        txn.commit().await.unwrap();
+        assert_eq!(store.stats().value_threshold, 31);
+
+        // Values above the threshold are read from the commit log, the others are not.
+        let txn = store.begin().unwrap();
+        assert_eq!(txn.get(&0u32.to_be_bytes()).unwrap().unwrap(), vec![1; 200]);
+        assert_eq!(txn.get(&1u32.to_be_bytes()).unwrap().unwrap(), vec![1; 20]);
+        drop(txn);
+        let mut txn = store.begin().unwrap();
+        txn.set(b"large", &[2; 100]).unwrap();
+        txn.set(b"small", &[2; 10]).unwrap();
+        txn.commit().await.unwrap();
+        let reads = store.stats().log_reads;
+        let txn = store.begin().unwrap();
+        txn.get(b"small").unwrap().unwrap();
+        assert_eq!(store.stats().log_reads, reads);
+        txn.get(b"large").unwrap().unwrap();
+        assert_eq!(store.stats().log_reads, reads + 1);
+        drop(txn);
+
+        // Tiny values: the threshold does not go below the lower bound.
+        let mut txn = store.begin().unwrap();
+        for i in 0..1024u32 {
+            txn.set(&i.to_be_bytes(), &[1]).unwrap();
+        }
+        txn.commit().await.unwrap();
+        assert_eq!(store.stats().value_threshold, 8);
+        store.close().await.unwrap();
+
+        // Bounds out of order are rejected.
+        opts.min_value_threshold = 512;
+        assert!(Store::new(opts).is_err());
+    }
+}
@@ -1,17 +1,31 @@
+use std::collections::BTreeMap;
 use std::ops::{Bound, RangeBounds};
 use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
+use async_channel::Receiver;
 use bytes::{Bytes, BytesMut};
+use crossbeam_channel::{bounded, RecvTimeoutError, Sender};
 use hashbrown::HashMap;
 use parking_lot::{Mutex, RwLock};
+use tokio::sync::{OwnedMutexGuard, OwnedSemaphorePermit};
 use vart::{TrieError, VariableSizeKey};
 
 use crate::storage::kv::{
+    consistency::CommitToken,
     entry::{Entry, Value, ValueRef},
+    envelope,
     error::{Error, Result},
-    snapshot::{FilterFn, Snapshot, FILTERS},
+    keyspace::Keyspace,
+    merge::{self, encode_operand, Origin},
+    meta::Metadata,
+    nested::ChildTransaction,
+    oracle::{Oracle, ReadSet},
+    range_delete::{self, KeyRange},
+    snapshot::{ignore_deleted, is_expired, Snapshot},
     store::Core,
-    util::{now, sha256},
+    util::{check_deadline, is_system_key, now, prefix_end, sha256},
 };
 
 /// `Mode` is an enumeration representing the different modes a transaction can have in an MVCC (Multi-Version Concurrency Control) system.
@@ -61,6 +75,24 @@ impl Mode {
 /// ScanResult is a tuple containing the key, value, timestamp, and commit timestamp of a key-value pair.
 pub type ScanResult = (Vec<u8>, Vec<u8>, u64, u64);
 
+/// Continuation token returned by [`Transaction::scan_paginated`], from which the next page
+/// of a scan starts. It holds the key of the last entry of the page, and can be kept as
+/// bytes between the requests of a client.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PageToken(Vec<u8>);
+
+impl PageToken {
+    /// Returns the token encoded in `bytes`, as returned by `as_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self(bytes.to_vec())
+    }
+
+    /// Returns the encoding of the token.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
 #[derive(Default, Debug, Copy, Clone)]
 pub enum Durability {
     /// Commits with this durability level will be queued for persitance to disk, and will be
@@ -82,6 +114,28 @@ pub enum Durability {
     Immediate,
 }
 
+/// Options of a read of a transaction, see [`Transaction::get_with_options`] and
+/// [`Transaction::scan_with_options`].
+#[derive(Default, Debug, Copy, Clone)]
+pub struct ReadOptions {
+    /// Time by which the read must complete, or fail with `Error::DeadlineExceeded`.
+    ///
+    /// The deadline is checked before each value is read from the commit log, and bounds the
+    /// wait for the commit log while it is written to, so that a read abandoned by its caller
+    /// does not keep reading in the background. A read of the commit log already started is
+    /// completed, which can outlast the deadline when a segment has to be opened.
+    pub deadline: Option<Instant>,
+}
+
+impl ReadOptions {
+    /// Returns the options of a read that must complete within `timeout`.
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self {
+            deadline: Some(Instant::now() + timeout),
+        }
+    }
+}
+
 /// `Transaction` is a struct representing a transaction in a database.
 pub struct Transaction {
     /// `read_ts` is the read timestamp of the transaction. This is the time at which the transaction started.
@@ -106,8 +160,17 @@ pub struct Transaction {
     /// These are the changes that the transaction intends to make to the data.
     pub(crate) write_set: Vec<(Bytes, Entry)>,
 
+    /// `increments` is the sum of the increments of each counter, applied to the latest value of the counter on commit.
+    increments: BTreeMap<Bytes, i128>,
+
+    /// `deleted_ranges` is the ranges deleted by the transaction. The reads of their keys are not recorded, as the keys are deleted on commit whatever their value.
+    deleted_ranges: Vec<KeyRange>,
+
+    /// `range_deleted` is the keys deleted by the range deletes of the transaction, listed when its commit is prepared. This is used for conflict detection.
+    pub(crate) range_deleted: Vec<Bytes>,
+
     /// `read_set` is the keys that are read in the transaction from the snapshot. This is used for conflict detection.
-    pub(crate) read_set: Mutex<Vec<(Bytes, u64)>>,
+    pub(crate) read_set: Mutex<ReadSet>,
 
     /// `read_key_ranges` is the key ranges that are read in the transaction from the snapshot. This is used for conflict detection.
     pub(crate) read_key_ranges: Mutex<Vec<(Bound<VariableSizeKey>, Bound<VariableSizeKey>)>>,
@@ -120,18 +183,53 @@ pub struct Transaction {
 
     /// `closed` indicates if the transaction is closed. A closed transaction cannot make any more changes to the data.
     closed: bool,
+
+    /// `write_set_bytes` is the number of bytes of the keys and values in the write set, as reported in the memory stats.
+    write_set_bytes: usize,
+
+    /// `slot` is the slot held by the transaction while it is open, if the number of active transactions is limited.
+    slot: Option<OwnedSemaphorePermit>,
+
+    /// `id` is the identifier of the transaction in the registry of active transactions.
+    id: u64,
+
+    /// `commit_token` is the token of the commit of the transaction, once it is committed.
+    commit_token: Option<CommitToken>,
 }
 
 impl Transaction {
     /// Prepare a new transaction in the given mode.
+    /// It returns `Error::TooManyTransactions` if `max_active_transactions` transactions are open.
     pub fn new(core: Arc<Core>, mode: Mode) -> Result<Self> {
-        let read_ts = core.read_ts()?;
+        let slot = core.try_admit_transaction()?;
+        Self::new_with_slot(core, mode, slot)
+    }
+
+    /// Prepare a new transaction in the given mode, waiting for a slot to be free
+    /// if `max_active_transactions` transactions are open.
+    pub(crate) async fn new_queued(core: Arc<Core>, mode: Mode) -> Result<Self> {
+        let slot = core.admit_transaction().await?;
+        Self::new_with_slot(core, mode, slot)
+    }
+
+    fn new_with_slot(
+        core: Arc<Core>,
+        mode: Mode,
+        slot: Option<OwnedSemaphorePermit>,
+    ) -> Result<Self> {
+        if core.is_read_only() && mode.mutable() {
+            return Err(Error::StoreReadOnly);
+        }
+        let read_ts = core.read_ts(mode)?;
 
         let mut snapshot = None;
         if !mode.is_write_only() {
             snapshot = Some(RwLock::new(Snapshot::take(core.clone(), now())?));
         }
 
+        let id = core.active_transactions.register(mode, read_ts);
+        let read_set = ReadSet::new(&core.opts);
+
         Ok(Self {
             read_ts,
             mode,
@@ -140,11 +238,18 @@ impl Transaction {
             core,
             write_order_map: HashMap::new(),
             write_set: Vec::new(),
-            read_set: Mutex::new(Vec::new()),
+            increments: BTreeMap::new(),
+            deleted_ranges: Vec::new(),
+            range_deleted: Vec::new(),
+            read_set: Mutex::new(read_set),
             read_key_ranges: Mutex::new(Vec::new()),
             committed_values_offsets: HashMap::new(),
             durability: Durability::Eventual,
             closed: false,
+            write_set_bytes: 0,
+            slot,
+            id,
+            commit_token: None,
         })
     }
 
@@ -153,6 +258,17 @@ impl Transaction {
         self.mode
     }
 
+    /// Returns true if the transaction is committed or rolled back.
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Begins a child transaction, whose writes are made to this transaction when it is
+    /// committed, and discarded when it is rolled back or dropped. See [`ChildTransaction`].
+    pub fn begin_child(&mut self) -> Result<ChildTransaction<'_>> {
+        ChildTransaction::new(self)
+    }
+
     /// Sets the durability level of the transaction.
     pub fn set_durability(&mut self, durability: Durability) {
         self.durability = durability;
@@ -165,1362 +281,2332 @@ impl Transaction {
         Ok(())
     }
 
-    /// Deletes a key from the store.
-    pub fn delete(&mut self, key: &[u8]) -> Result<()> {
-        let value = Bytes::new();
-        let mut entry = Entry::new(key, &value);
-        entry.mark_delete();
+    /// Adds a key-value pair to the store, carrying the given user flags. The flags of a key
+    /// are replaced by every write; `set` writes a key without flags.
+    pub fn set_with_flags(&mut self, key: &[u8], value: &[u8], flags: u64) -> Result<()> {
+        let mut entry = Entry::new(key, value);
+        entry.set_flags(flags);
         self.write(entry)?;
         Ok(())
     }
 
-    /// Gets a value for a key if it exists.
-    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        // If the transaction is closed, return an error.
+    /// Adds a key-value pair to the store that expires once `ttl` has elapsed. An expired key
+    /// is read as if it was deleted; `set` writes a key that does not expire.
+    pub fn set_with_ttl(&mut self, key: &[u8], value: &[u8], ttl: Duration) -> Result<()> {
+        let mut entry = Entry::new(key, value);
+        entry.set_expiry(Some(expiry(&self.core, ttl)));
+        self.write(entry)?;
+        Ok(())
+    }
+
+    /// Makes a key expire once `ttl` has elapsed, keeping its value and flags. The value is
+    /// not written again: the transaction only writes the new expiry to the commit log.
+    /// Returns false if the key does not exist, or has expired, in which case nothing is
+    /// written. The key is read, and checked for conflicts on commit.
+    pub fn touch(&mut self, key: &[u8], ttl: Duration) -> Result<bool> {
+        if !self.mode.mutable() {
+            return Err(Error::TransactionReadOnly);
+        }
+        if self.mode.is_write_only() {
+            return Err(Error::TransactionWriteOnly);
+        }
         if self.closed {
             return Err(Error::TransactionClosed);
         }
-        // If the key is empty, return an error.
         if key.is_empty() {
             return Err(Error::EmptyKey);
         }
-
-        // Do not allow reads if it is a write-only transaction
-        if self.mode.is_write_only() {
-            return Err(Error::TransactionWriteOnly);
+        if is_system_key(key) {
+            return Err(Error::ReservedKey);
         }
+        let expires_at = expiry(&self.core, ttl);
 
-        // Create a copy of the key.
+        // The expiry of a key written by the transaction is changed in its write.
         let key = Bytes::copy_from_slice(key);
-        let hashed_key = sha256(key.clone());
+        if let Some(order) = self.write_order_map.get(&sha256(key.clone())) {
+            let entry = &mut self.write_set[*order as usize].1;
+            if entry.is_deleted() || is_expired(&self.core, entry.expires_at()) {
+                return Ok(false);
+            }
+            entry.set_expiry(Some(expires_at));
+            return Ok(true);
+        }
 
-        // Attempt to get the value for the key from the snapshot.
-        match self.snapshot.as_ref().unwrap().read().get(&key[..].into()) {
+        let snapshot = self.snapshot.as_ref().unwrap().read();
+        let merged = match snapshot.get(&key[..].into()) {
             Ok(val_ref) => {
-                // RYOW semantics: Read your own write. If the key is in the write set, return the value.
-                // Check if the key is in the write set by checking in the write_order_map map.
-                if let Some(order) = self.write_order_map.get(&hashed_key) {
-                    if let Some(entry) = self.write_set.get(*order as usize) {
-                        return Ok(if entry.1.is_deleted() {
-                            None
-                        } else {
-                            Some(entry.1.value.clone().to_vec())
-                        });
-                    }
+                if val_ref.ts() > 0 {
+                    self.read_set.lock().push(key.clone(), val_ref.ts());
                 }
-
-                // If the transaction is not read-only and the value reference has a timestamp greater than 0,
-                // add the key and its timestamp to the read set for conflict detection.
-                if !self.mode.is_read_only() && val_ref.ts() > 0 {
-                    self.read_set.lock().push((key, val_ref.ts()));
+                // The index value of a key holding merge operands is not its value, which is
+                // written with the new expiry instead.
+                match val_ref.key_value_metadata().is_some_and(|md| md.merged()) {
+                    true => Some(val_ref.resolve_merged(&key, None)?),
+                    false => None,
                 }
-
-                // Resolve the value reference to get the actual value.
-                val_ref.resolve().map(Some)
             }
-            Err(e) => {
-                match &e {
-                    // If the key is not found in the index, and the transaction is not read-only,
-                    // add the key to the read set with a timestamp of 0.
-                    Error::IndexError(trie_error) => {
-                        if let TrieError::KeyNotFound = trie_error {
-                            if !self.mode.is_read_only() {
-                                self.read_set.lock().push((key, 0));
-                            }
-                        }
-                        Ok(None)
-                    }
-                    // For other errors, just propagate them.
-                    _ => Err(e),
+            Err(Error::IndexError(TrieError::KeyNotFound)) => {
+                if !self.in_deleted_range(&key) {
+                    self.read_set.lock().push(key, 0);
                 }
+                return Ok(false);
             }
+            Err(e) => return Err(e),
+        };
+        drop(snapshot);
+
+        if let Some(value) = merged {
+            let mut entry = Entry::new(&key, &value);
+            entry.set_expiry(Some(expires_at));
+            self.write(entry)?;
+            return Ok(true);
+        }
+        if self.pending_writes() >= self.core.opts.max_entries_per_txn as usize {
+            return Err(Error::MaxTransactionEntriesLimitExceeded);
         }
+        let mut entry = Entry::new(&key, &[]);
+        entry.mark_touch();
+        entry.set_expiry(Some(expires_at));
+        self.push_write(entry);
+        Ok(true)
     }
 
-    /// Writes a value for a key. None is used for deletion.
-    fn write(&mut self, e: Entry) -> Result<()> {
-        // If the transaction mode is not mutable (i.e., it's read-only), return an error.
+    /// Sets the bits of `set_mask` and clears the bits of `clear_mask` in the user flags of a
+    /// key, keeping its value and expiry, and returns the new flags. The value is not written
+    /// again: the transaction only writes the new flags to the commit log, which makes the
+    /// transitions of a state kept in the flags cheap whatever the size of the value.
+    /// Returns None if the key does not exist, or has expired, in which case nothing is
+    /// written. The key is read, and checked for conflicts on commit, so that the flags are
+    /// changed from the ones read.
+    pub fn set_meta_bits(
+        &mut self,
+        key: &[u8],
+        set_mask: u64,
+        clear_mask: u64,
+    ) -> Result<Option<u64>> {
         if !self.mode.mutable() {
             return Err(Error::TransactionReadOnly);
         }
-        // If the transaction is closed, return an error.
+        if self.mode.is_write_only() {
+            return Err(Error::TransactionWriteOnly);
+        }
         if self.closed {
             return Err(Error::TransactionClosed);
         }
-        // If the key is empty, return an error.
-        if e.key.is_empty() {
+        if key.is_empty() {
             return Err(Error::EmptyKey);
         }
-        // If the key length exceeds the maximum allowed key size, return an error.
-        if e.key.len() as u64 > self.core.opts.max_key_size {
-            return Err(Error::MaxKeyLengthExceeded);
+        if is_system_key(key) {
+            return Err(Error::ReservedKey);
         }
-        // If the value length exceeds the maximum allowed value size, return an error.
-        if e.value.len() as u64 > self.core.opts.max_value_size {
-            return Err(Error::MaxValueLengthExceeded);
+        let changed = |flags: u64| (flags | set_mask) & !clear_mask;
+
+        // The flags of a key written or touched by the transaction are changed in its write.
+        let key = Bytes::copy_from_slice(key);
+        if let Some(order) = self.write_order_map.get(&sha256(key.clone())) {
+            let entry = &mut self.write_set[*order as usize].1;
+            if entry.is_deleted() || is_expired(&self.core, entry.expires_at()) {
+                return Ok(None);
+            }
+            if entry.is_touch() && !entry.is_reflag() {
+                let Some(flags) = self.get_flags(&key)? else {
+                    return Ok(None);
+                };
+                let entry = &mut self.write_set[*order as usize].1;
+                entry.set_flags(changed(flags));
+                entry.mark_reflag();
+                return Ok(Some(entry.flags()));
+            }
+            entry.set_flags(changed(entry.flags()));
+            return Ok(Some(entry.flags()));
         }
 
-        if self.write_set.len() as u32 >= self.core.opts.max_entries_per_txn {
+        let snapshot = self.snapshot.as_ref().unwrap().read();
+        let (md, merged) = match snapshot.get(&key[..].into()) {
+            Ok(val_ref) => {
+                if val_ref.ts() > 0 {
+                    self.read_set.lock().push(key.clone(), val_ref.ts());
+                }
+                let md = val_ref.key_value_metadata().cloned();
+                if !self.is_live(&key, md.as_ref()) {
+                    return Ok(None);
+                }
+                // The index value of a key holding merge operands is not its value, which is
+                // written with the new flags instead.
+                match md.as_ref().is_some_and(|md| md.merged()) {
+                    true => (md, Some(val_ref.resolve_merged(&key, None)?)),
+                    false => (md, None),
+                }
+            }
+            Err(Error::IndexError(TrieError::KeyNotFound)) => {
+                if !self.in_deleted_range(&key) {
+                    self.read_set.lock().push(key, 0);
+                }
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        };
+        drop(snapshot);
+        let flags = changed(md.as_ref().map_or(0, |md| md.flags()));
+        let expires_at = md.as_ref().and_then(|md| md.expires_at());
+
+        if let Some(value) = merged {
+            let mut entry = Entry::new(&key, &value);
+            entry.set_flags(flags);
+            entry.set_expiry(expires_at);
+            self.write(entry)?;
+            return Ok(Some(flags));
+        }
+        if self.pending_writes() >= self.core.opts.max_entries_per_txn as usize {
             return Err(Error::MaxTransactionEntriesLimitExceeded);
         }
+        let mut entry = Entry::new(&key, &[]);
+        entry.mark_reflag();
+        entry.set_flags(flags);
+        entry.set_expiry(expires_at);
+        self.push_write(entry);
+        Ok(Some(flags))
+    }
 
-        // If the transaction mode is not write-only, update the snapshot.
-        if !self.mode.is_write_only() {
-            // Convert the value to Bytes.
-            let index_value = ValueRef::encode_mem(&e.value, e.metadata.as_ref());
-
-            // Set the key-value pair in the snapshot.
-            self.snapshot
-                .as_ref()
-                .unwrap()
-                .write()
-                .set(&e.key[..].into(), index_value)?;
+    /// Makes all the keys with the given prefix expire once `ttl` has elapsed, as `touch`
+    /// does, and returns the number of keys touched. The range of the prefix is checked for
+    /// conflicts on commit.
+    pub fn touch_prefix(&mut self, prefix: &[u8], ttl: Duration) -> Result<usize> {
+        if !self.mode.mutable() {
+            return Err(Error::TransactionReadOnly);
         }
-
-        // Add the entry to the set of pending writes.
-        let hashed_key = sha256(e.key.clone());
-
-        // Check if the key already exists in write_order_map, if so, update the entry in write_set.
-        if let Some(order) = self.write_order_map.get(&hashed_key) {
-            self.write_set[*order as usize] = (e.key.clone(), e);
-        } else {
-            self.write_set.push((e.key.clone(), e));
-            self.write_order_map
-                .insert(hashed_key, self.write_order_map.len() as u32);
+        if prefix.is_empty() {
+            return Err(Error::EmptyKey);
+        }
+        if is_system_key(prefix) {
+            return Err(Error::ReservedKey);
         }
 
-        Ok(())
-    }
-
-    /// Scans a range of keys and returns a vector of tuples containing the value, version, and timestamp for each key.
-    pub fn scan<'b, R>(&'b self, range: R, limit: Option<usize>) -> Result<Vec<ScanResult>>
-    where
-        R: RangeBounds<&'b [u8]>,
-    {
-        // Convert the range to a tuple of bounds of variable keys.
+        let end = prefix_end(prefix);
         let range = (
-            match range.start_bound() {
-                Bound::Included(start) => {
-                    Bound::Included(VariableSizeKey::from_slice_with_termination(start))
-                }
-                Bound::Excluded(start) => {
-                    Bound::Excluded(VariableSizeKey::from_slice_with_termination(start))
-                }
-                Bound::Unbounded => Bound::Unbounded,
-            },
-            match range.end_bound() {
-                Bound::Included(end) => {
-                    Bound::Included(VariableSizeKey::from_slice_with_termination(end))
-                }
-                Bound::Excluded(end) => {
-                    Bound::Excluded(VariableSizeKey::from_slice_with_termination(end))
-                }
-                Bound::Unbounded => Bound::Unbounded,
-            },
+            Bound::Included(prefix),
+            end.as_deref().map_or(Bound::Unbounded, Bound::Excluded),
         );
+        let mut keys = Vec::new();
+        self.walk_keys(range, |key, _| {
+            keys.push(key.to_vec());
+            true
+        })?;
+        self.read_key_ranges.lock().push((
+            Bound::Included(VariableSizeKey::from_slice_with_termination(prefix)),
+            match &end {
+                Some(end) => Bound::Included(VariableSizeKey::from_slice_with_termination(end)),
+                None => Bound::Unbounded,
+            },
+        ));
 
-        // Keep track of the range bound predicates for conflict detection in case of SSI.
-        {
-            let range = (
-                match range.start_bound() {
-                    Bound::Included(start) => Bound::Included((*start).clone()),
-                    Bound::Excluded(start) => Bound::Included((*start).clone()),
-                    Bound::Unbounded => Bound::Unbounded,
-                },
-                match range.end_bound() {
-                    Bound::Included(end) => Bound::Included((*end).clone()),
-                    Bound::Excluded(end) => Bound::Included((*end).clone()),
-                    Bound::Unbounded => Bound::Unbounded,
-                },
-            );
-
-            self.read_key_ranges.lock().push(range);
+        let mut touched = 0;
+        for key in keys {
+            if self.touch(&key, ttl)? {
+                touched += 1;
+            }
         }
+        Ok(touched)
+    }
 
-        // Initialize an empty vector to store the results.
-        let mut results = Vec::new();
-
-        // Create a new reader for the snapshot.
-        let iterator = match self.snapshot.as_ref().unwrap().write().new_reader() {
-            Ok(reader) => reader,
-            Err(Error::IndexError(TrieError::SnapshotEmpty)) => return Ok(Vec::new()),
-            Err(e) => return Err(e),
-        };
+    /// Moves the value of `old_key` to `new_key`, with its expiry and flags, and deletes
+    /// `old_key`, as setting `new_key` to the value and deleting `old_key` would. A value
+    /// stored in the commit log is not written again: the transaction writes a record naming
+    /// `old_key` for `new_key`, and the index points `new_key` to the value of `old_key`.
+    /// Returns false if `old_key` does not exist, or has expired, in which case nothing is
+    /// written. `old_key` is read, and checked for conflicts on commit; `new_key` is written
+    /// over.
+    pub fn rename(&mut self, old_key: &[u8], new_key: &[u8]) -> Result<bool> {
+        if self.mode.is_write_only() {
+            return Err(Error::TransactionWriteOnly);
+        }
+        let mut entry = Entry::new(new_key, old_key);
+        entry.mark_rename();
+        self.check_write(&entry)?;
+        if old_key.is_empty() {
+            return Err(Error::EmptyKey);
+        }
+        if is_system_key(old_key) {
+            return Err(Error::ReservedKey);
+        }
+        if old_key == new_key {
+            return Ok(self.get(old_key)?.is_some());
+        }
+        let new_keys = [&entry.key, &entry.value]
+            .into_iter()
+            .filter(|key| !self.write_order_map.contains_key(&sha256((*key).clone())))
+            .count();
+        if self.pending_writes() + new_keys > self.core.opts.max_entries_per_txn as usize {
+            return Err(Error::MaxTransactionEntriesLimitExceeded);
+        }
 
-        // Get a range iterator for the specified range.
-        let ranger = iterator.range(range);
+        // A key written by the transaction is moved with its write, a key it only touched
+        // with the expiry and the flags the touch sets.
+        let key = entry.value.clone();
+        let written = self
+            .write_order_map
+            .get(&sha256(key.clone()))
+            .map(|order| self.write_set[*order as usize].1.clone());
+        if let Some(written) = written.as_ref().filter(|e| !e.is_touch()) {
+            if written.is_deleted() || is_expired(&self.core, written.expires_at()) {
+                return Ok(false);
+            }
+            let moved = match written.is_rename() {
+                true => Entry {
+                    key: entry.key,
+                    ..written.clone()
+                },
+                false => {
+                    let mut moved = Entry::new(new_key, &self.resolve_written(written, None)?);
+                    moved.set_flags(written.flags());
+                    moved.set_expiry(written.expires_at());
+                    moved
+                }
+            };
+            self.move_write(moved, old_key)?;
+            return Ok(true);
+        }
 
-        // Iterate over the keys in the range.
-        'outer: for (key, value, version, ts) in ranger {
-            // If a limit is set and we've already got enough results, break the loop.
-            if let Some(limit) = limit {
-                if results.len() >= limit {
-                    break;
+        let snapshot = self.snapshot.as_ref().unwrap().read();
+        let (md, merged) = match snapshot.get(&key[..].into()) {
+            Ok(val_ref) => {
+                if val_ref.ts() > 0 {
+                    self.read_set.lock().push(key.clone(), val_ref.ts());
+                }
+                let md = val_ref.key_value_metadata().cloned();
+                if !self.is_live(&key, md.as_ref()) {
+                    return Ok(false);
+                }
+                // The index value of a key holding merge operands is not its value, which is
+                // written for the new key instead.
+                match md.as_ref().is_some_and(|md| md.merged()) {
+                    true => (md, Some(val_ref.resolve_merged(&key, None)?)),
+                    false => (md, None),
                 }
             }
-
-            // Create a new value reference and decode the value.
-            let mut val_ref = ValueRef::new(self.core.clone());
-            let val_bytes_ref: &Bytes = value;
-            val_ref.decode(*version, val_bytes_ref)?;
-
-            // Apply all filters. If any filter fails, skip this key and continue with the next one.
-            for filter in &FILTERS {
-                if filter.apply(&val_ref, self.read_ts).is_err() {
-                    continue 'outer;
+            Err(Error::IndexError(TrieError::KeyNotFound)) => {
+                if !self.in_deleted_range(&key) {
+                    self.read_set.lock().push(key, 0);
                 }
+                return Ok(false);
             }
+            Err(e) => return Err(e),
+        };
+        drop(snapshot);
+        let flags = md.as_ref().map_or(0, |md| md.flags());
+        let expires_at = md.as_ref().and_then(|md| md.expires_at());
+        let (flags, expires_at) = match written {
+            Some(touch) if touch.is_reflag() => (touch.flags(), touch.expires_at()),
+            Some(touch) => (flags, touch.expires_at()),
+            None => (flags, expires_at),
+        };
 
-            // Only add the key to the read set if the timestamp is less than or equal to the
-            // read timestamp. This is to prevent adding keys that are added during the transaction.
-            if val_ref.ts() <= self.read_ts {
-                self.read_set.lock().push((
-                    Bytes::copy_from_slice(&key[..&key.len() - 1]), // the keys in the vart leaf are terminated with a null byte
-                    val_ref.ts,
-                ));
-            }
+        if let Some(value) = merged {
+            entry = Entry::new(new_key, &value);
+        }
+        entry.set_flags(flags);
+        entry.set_expiry(expires_at);
+        self.move_write(entry, old_key)?;
+        Ok(true)
+    }
 
-            // Resolve the value reference to get the actual value.
-            let v = val_ref.resolve()?;
+    /// Writes the entry a key is renamed to, and deletes the key, once the number of entries
+    /// of the transaction is checked.
+    fn move_write(&mut self, moved: Entry, old_key: &[u8]) -> Result<()> {
+        self.apply_write(moved)?;
+        let mut tombstone = Entry::new(old_key, &[]);
+        tombstone.mark_delete();
+        self.apply_write(tombstone)
+    }
 
-            // Add the value, version, and timestamp to the results vector.
-            let mut key = key;
-            key.truncate(key.len() - 1);
-            results.push((key, v, *version, *ts));
+    /// Returns true if a value read from the snapshot, with the given metadata, is neither
+    /// deleted nor expired. A key touched by the transaction expires when the touch makes it.
+    fn is_live(&self, key: &[u8], md: Option<&Metadata>) -> bool {
+        if md.is_some_and(|md| md.deleted()) {
+            return false;
         }
+        let touched = if self.write_set.is_empty() {
+            None
+        } else {
+            self.write_order_map
+                .get(&sha256(Bytes::copy_from_slice(key)))
+                .map(|order| &self.write_set[*order as usize].1)
+                .filter(|entry| entry.is_touch())
+        };
+        let expires_at = match touched {
+            Some(entry) => entry.expires_at(),
+            None => md.and_then(|md| md.expires_at()),
+        };
+        !is_expired(&self.core, expires_at)
+    }
 
-        // Return the results.
-        Ok(results)
+    /// Deletes a key from the store.
+    pub fn delete(&mut self, key: &[u8]) -> Result<()> {
+        let value = Bytes::new();
+        let mut entry = Entry::new(key, &value);
+        entry.mark_delete();
+        self.write(entry)?;
+        Ok(())
     }
 
-    /// Commits the transaction, by writing all pending entries to the store.
-    pub async fn commit(&mut self) -> Result<()> {
-        // If the transaction is closed, return an error.
+    /// Deletes the keys of a range from the store. The deletion is written to the commit log
+    /// as one range tombstone, rather than as a tombstone for each key of the range.
+    ///
+    /// The keys of the range are deleted as of the commit: the keys written into it by the
+    /// transactions committed after this one began are deleted too. The keys written by this
+    /// transaction are deleted if they were written before the call, and kept if they are
+    /// written after it. System keys are never deleted: a range starting inside the system
+    /// keyspace is refused with `Error::ReservedKey`, as writes to it are. The transactions
+    /// that read a deleted key conflict with this one, as they do with `delete`.
+    pub fn delete_range<'b, R>(&mut self, range: R) -> Result<()>
+    where
+        R: RangeBounds<&'b [u8]>,
+    {
+        if !self.mode.mutable() {
+            return Err(Error::TransactionReadOnly);
+        }
         if self.closed {
             return Err(Error::TransactionClosed);
         }
-
-        // If the transaction is read-only, return an error.
-        if self.mode.is_read_only() {
-            return Err(Error::TransactionReadOnly);
+        if let Bound::Included(start) | Bound::Excluded(start) = range.start_bound() {
+            if is_system_key(start) {
+                return Err(Error::ReservedKey);
+            }
         }
-
-        // If there are no pending writes, there's nothing to commit, so return early.
-        if self.write_set.is_empty() {
-            return Ok(());
+        if self.pending_writes() >= self.core.opts.max_entries_per_txn as usize {
+            return Err(Error::MaxTransactionEntriesLimitExceeded);
         }
+        let entry = range_delete::entry(&range)?;
+        let range = (range.start_bound().cloned(), range.end_bound().cloned());
 
-        // Lock the oracle to serialize commits to the transaction log.
-        let oracle = self.core.oracle.clone();
-        let write_ch_lock = oracle.write_lock.lock().await;
-
-        // Prepare for the commit by getting a transaction ID and a commit timestamp.
-        let (tx_id, commit_ts) = self.prepare_commit()?;
-
-        // Sort the keys in the write set and create a vector of entries.
-        let entries: Vec<Entry> = self
+        // The writes of the range are replaced by deletes.
+        let written: Vec<Bytes> = self
             .write_set
             .iter()
-            .map(|(_, entry)| entry.clone())
+            .filter(|(key, e)| !e.is_range_delete() && range_delete::covers(range, key))
+            .map(|(key, _)| key.clone())
             .collect();
+        for key in written {
+            let mut tombstone = Entry::new(&key, &[]);
+            tombstone.mark_delete();
+            self.apply_write(tombstone)?;
+        }
+        self.increments
+            .retain(|key, _| !range_delete::covers(range, key));
+
+        // The other keys of the range are deleted from the snapshot, for the reads of the
+        // transaction.
+        if let Some(snapshot) = &self.snapshot {
+            let mut keys = Vec::new();
+            self.walk_keys(range, |key, _| {
+                keys.push(key.to_vec());
+                true
+            })?;
+            let mut md = Metadata::new();
+            md.as_deleted(true)?;
+            let tombstone = ValueRef::encode_mem(&Bytes::new(), Some(&md));
+            let mut snapshot = snapshot.write();
+            for key in keys {
+                snapshot.set(&key[..].into(), tombstone.clone())?;
+            }
+        }
 
-        // Commit the changes to the store index.
-        let done = self
-            .core
-            .send_to_write_channel(entries, tx_id, commit_ts, self.durability)
-            .await;
+        self.deleted_ranges.push(range_delete::to_owned(range));
+        self.push_write(entry);
+        Ok(())
+    }
 
-        if let Err(err) = done {
-            oracle.committed_upto(commit_ts);
-            return Err(err);
-        }
+    /// Returns true if a key is in a range deleted by the transaction. The writes made after
+    /// the range delete are in the write set, which is read first.
+    fn in_deleted_range(&self, key: &[u8]) -> bool {
+        self.deleted_ranges
+            .iter()
+            .any(|range| range_delete::covers(range_delete::as_slices(range), key))
+    }
 
-        drop(write_ch_lock);
+    /// Writes `new` to a key if its current value is `expected`, or deletes it if `new` is
+    /// None. An `expected` value of None requires the key not to exist. If the current value
+    /// differs, nothing is written and `Error::ValueMismatch` is returned with it.
+    ///
+    /// The key is read, and checked for conflicts on commit, so the write is only committed
+    /// if the value compared is still current.
+    pub fn cas(&mut self, key: &[u8], expected: Option<&[u8]>, new: Option<&[u8]>) -> Result<()> {
+        if !self.mode.mutable() {
+            return Err(Error::TransactionReadOnly);
+        }
+        let current = self.get(key)?;
+        if current.as_deref() != expected {
+            return Err(Error::ValueMismatch(current));
+        }
+        match new {
+            Some(value) => self.set(key, value),
+            None => self.delete(key),
+        }
+    }
 
-        // Check if the transaction is written to the transaction log.
-        let done = done.unwrap();
-        let ret = done.recv().await?;
+    /// Adds `delta` to the counter stored at a key as a big-endian `u64`, a missing key
+    /// counting as 0. The increment is applied to the latest value of the counter when the
+    /// transaction commits: the key is not read, so concurrent increments do not conflict, and
+    /// the reads of the transaction do not see it. A counter written by the transaction is
+    /// incremented in its write instead.
+    ///
+    /// The commit fails with `Error::CounterOverflow` if the counter would go past `u64::MAX`,
+    /// and with `Error::InvalidCounter` if the value of the key is not 8 bytes long. The
+    /// counter written does not expire.
+    pub fn incr(&mut self, key: &[u8], delta: u64) -> Result<()> {
+        self.increment(key, i128::from(delta))
+    }
 
-        // Update the oracle to indicate that the transaction has been committed up to the given transaction ID.
-        oracle.committed_upto(tx_id);
+    /// Subtracts `delta` from the counter stored at a key, as `incr` adds to it. The commit
+    /// fails with `Error::CounterOverflow` if the counter would go below 0.
+    pub fn decr(&mut self, key: &[u8], delta: u64) -> Result<()> {
+        self.increment(key, -i128::from(delta))
+    }
 
-        // Mark the transaction as closed.
-        self.closed = true;
+    fn increment(&mut self, key: &[u8], delta: i128) -> Result<()> {
+        let entry = Entry::new(key, &[0; COUNTER_SIZE]);
+        self.check_write(&entry)?;
+        let key = entry.key;
+
+        if let Some(order) = self.write_order_map.get(&sha256(key.clone())) {
+            let written = &self.write_set[*order as usize].1;
+            if !written.is_touch() {
+                let live = !written.is_deleted() && !is_expired(&self.core, written.expires_at());
+                let value = match live {
+                    true => Some(self.resolve_written(written, None)?),
+                    false => None,
+                };
+                let counter = add_to_counter(value.as_deref(), delta)?;
+                return self.write(Entry::new(&key, &counter.to_be_bytes()));
+            }
+        }
 
-        // Ok(())
-        ret
+        if !self.increments.contains_key(&key)
+            && self.pending_writes() >= self.core.opts.max_entries_per_txn as usize
+        {
+            return Err(Error::MaxTransactionEntriesLimitExceeded);
+        }
+        *self.increments.entry(key).or_insert(0) += delta;
+        Ok(())
     }
 
-    /// Prepares for the commit by assigning commit timestamps and preparing records.
-    fn prepare_commit(&mut self) -> Result<(u64, u64)> {
-        let oracle = self.core.oracle.clone();
-        let tx_id = oracle.new_commit_ts(self)?;
-        let commit_ts = self.assign_commit_ts();
-        Ok((tx_id, commit_ts))
+    /// Merges an operand into the value of a key with the merge operator of the store, see
+    /// `Store::set_merge_operator`. The operand is written without reading the key, so
+    /// concurrent merges into a key do not conflict: the value of the key is merged from the
+    /// operands and the value they were committed over when the key is read, and for good
+    /// when the index is shrunk or checkpointed. An operand merged into a value written by
+    /// the transaction is merged into the write instead.
+    ///
+    /// The reads of the transaction see the operands it merges, merged with the versions of
+    /// its snapshot, whose reads are not recorded for conflict detection. The merged value has
+    /// no flags, and the operands do not expire: they are merged with a value that expires
+    /// while it lives, and are dropped with it.
+    pub fn merge(&mut self, key: &[u8], operand: &[u8]) -> Result<()> {
+        let operator = self.core.merger.operator()?;
+        let mut list = BytesMut::new();
+        encode_operand(&mut list, operand);
+        let mut entry = Entry::new(key, &list);
+        entry.mark_merge();
+        self.check_write(&entry)?;
+
+        if let Some(order) = self.write_order_map.get(&sha256(entry.key.clone())) {
+            let written = &self.write_set[*order as usize].1;
+            if written.is_merge() && !written.is_append() {
+                let mut list = BytesMut::from(&written.value[..]);
+                encode_operand(&mut list, operand);
+                entry.value = list.freeze();
+                entry.set_expiry(written.expires_at());
+            } else if written.is_touch() {
+                entry.set_expiry(written.expires_at());
+            } else {
+                let live = !written.is_deleted() && !is_expired(&self.core, written.expires_at());
+                let value = match live {
+                    true => Some(self.resolve_written(written, None)?),
+                    false => None,
+                };
+                let value = operator(key, value.as_deref(), &[operand]);
+                entry = Entry::new(key, &value);
+            }
+        }
+        self.write(entry)
     }
 
-    /// Assigns commit timestamps to transaction entries.
-    fn assign_commit_ts(&mut self) -> u64 {
-        let commit_ts = now();
-        self.write_set.iter_mut().for_each(|(_, entry)| {
-            entry.ts = commit_ts;
-        });
-        commit_ts
+    /// Appends `suffix` to the value of a key, which is written as `suffix` if the key is
+    /// missing. Only the suffix is written to the commit log: the index keeps the chunks as
+    /// versions of the key, which are appended to the value they were committed over when
+    /// the key is read, and for good when the index is shrunk or checkpointed. As with
+    /// `merge`, the key is not read, so concurrent appends to a key do not conflict and are
+    /// applied in commit order, and no merge operator is needed. A suffix appended to a value
+    /// written by the transaction is appended to the write instead.
+    ///
+    /// The value appended to has no flags, and the chunks do not expire: they are appended to
+    /// a value that expires while it lives, and are dropped with it.
+    pub fn append_value(&mut self, key: &[u8], suffix: &[u8]) -> Result<()> {
+        let mut list = BytesMut::new();
+        encode_operand(&mut list, suffix);
+        let mut entry = Entry::new(key, &list);
+        entry.mark_append();
+        self.check_write(&entry)?;
+
+        if let Some(order) = self.write_order_map.get(&sha256(entry.key.clone())) {
+            let written = &self.write_set[*order as usize].1;
+            if written.is_append() {
+                let mut list = BytesMut::from(&written.value[..]);
+                encode_operand(&mut list, suffix);
+                entry.value = list.freeze();
+                entry.set_expiry(written.expires_at());
+            } else if written.is_touch() {
+                entry.set_expiry(written.expires_at());
+            } else {
+                let live = !written.is_deleted() && !is_expired(&self.core, written.expires_at());
+                let mut value = match live {
+                    true => self.resolve_written(written, None)?,
+                    false => Vec::new(),
+                };
+                value.extend_from_slice(suffix);
+                entry = Entry::new(key, &value);
+            }
+        }
+        self.write(entry)
     }
 
-    /// Rolls back the transaction by removing all updated entries.
-    pub fn rollback(&mut self) {
-        self.closed = true;
-        self.committed_values_offsets.clear();
-        self.buf.clear();
-        self.write_set.clear();
-        self.read_set.lock().clear();
-        self.snapshot.take();
+    /// Returns the number of keys the transaction writes, or increments.
+    fn pending_writes(&self) -> usize {
+        self.write_set.len() + self.increments.len()
     }
-}
 
-impl Drop for Transaction {
-    fn drop(&mut self) {
-        self.rollback();
+    /// Returns true if the transaction has anything to commit.
+    pub(crate) fn has_writes(&self) -> bool {
+        self.pending_writes() > 0
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use bytes::Bytes;
-    use std::mem::size_of;
+    /// Returns the entries writing the counters incremented by the transaction, with their
+    /// increments applied to the latest committed values.
+    fn counters(&self) -> Result<Vec<Entry>> {
+        let mut counters = Vec::with_capacity(self.increments.len());
+        for (key, delta) in &self.increments {
+            let current = self.latest_value(key)?;
+            let counter = add_to_counter(current.as_deref(), *delta)?;
+            counters.push(Entry::new(key, &counter.to_be_bytes()));
+        }
+        Ok(counters)
+    }
 
-    use super::*;
-    use crate::storage::kv::option::{IsolationLevel, Options};
-    use crate::storage::kv::store::Store;
+    /// Returns the latest committed value of a key, rather than the value of the snapshot.
+    fn latest_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let Some(index_key) = self.core.keys.lookup(key) else {
+            return Ok(None);
+        };
+        let found = {
+            let indexer = self.core.indexer.read();
+            indexer.get_at(&index_key, indexer.version())?
+        };
+        let Some((value, version, _)) = found else {
+            return Ok(None);
+        };
 
-    use tempdir::TempDir;
+        let mut val_ref = ValueRef::new(self.core.clone());
+        val_ref.decode(version, &value)?;
+        let md = val_ref.key_value_metadata();
+        if md.is_some_and(|md| md.deleted())
+            || is_expired(&self.core, md.and_then(|md| md.expires_at()))
+        {
+            return Ok(None);
+        }
+        val_ref.resolve_merged(key, None).map(Some)
+    }
 
-    fn create_temp_directory() -> TempDir {
-        TempDir::new("test").unwrap()
+    /// Returns the value written to a counter by the transaction, once it is committed.
+    pub(crate) fn counter(&self, key: &[u8]) -> Option<u64> {
+        let order = self
+            .write_order_map
+            .get(&sha256(Bytes::copy_from_slice(key)))?;
+        let value = &self.write_set[*order as usize].1.value;
+        Some(u64::from_be_bytes(value[..].try_into().ok()?))
     }
 
-    // Common setup logic for creating a store
-    fn create_store(is_ssi: bool) -> (Store, TempDir) {
-        let temp_dir = create_temp_directory();
-        let mut opts = Options::new();
-        opts.dir = temp_dir.path().to_path_buf();
-        if is_ssi {
-            opts.isolation_level = IsolationLevel::SerializableSnapshotIsolation;
+    /// Resolves a value written by the transaction, merging it with the versions the
+    /// transaction reads if it is a list of merge operands.
+    fn resolve_written(&self, entry: &Entry, deadline: Option<Instant>) -> Result<Vec<u8>> {
+        // A transaction renaming a key has a snapshot, whose version the key is read at.
+        if entry.is_rename() {
+            let version = self.snapshot.as_ref().unwrap().read().version();
+            return resolve_renamed(&self.core, &entry.value, version, deadline);
         }
-        (
-            Store::new(opts.clone()).expect("should create store"),
-            temp_dir,
+        if !entry.is_merge() {
+            return Ok(entry.value.to_vec());
+        }
+        // A write-only transaction has no snapshot, its writes are merged with the latest
+        // versions.
+        let version = match &self.snapshot {
+            Some(snapshot) => snapshot.read().write_version(),
+            None => self.core.indexer.read().version() + 1,
+        };
+        merge::merge_value(
+            &self.core,
+            &self.core.indexer,
+            &entry.key,
+            version,
+            &entry.value,
+            entry.is_append(),
+            Origin::Uncommitted,
+            deadline,
         )
     }
 
-    #[tokio::test]
-    async fn basic_transaction() {
-        let (store, temp_dir) = create_store(false);
+    /// Resolves a value read from the snapshot, which holds the writes of the transaction: the
+    /// value of a key written by the transaction is taken from its write.
+    fn resolve_read(
+        &self,
+        key: &[u8],
+        val_ref: &ValueRef,
+        deadline: Option<Instant>,
+    ) -> Result<Vec<u8>> {
+        let written = self
+            .write_order_map
+            .get(&sha256(Bytes::copy_from_slice(key)))
+            .map(|order| &self.write_set[*order as usize].1)
+            .filter(|entry| !entry.is_touch());
+        match written {
+            Some(entry) => self.resolve_written(entry, deadline),
+            None => val_ref.resolve_merged(key, deadline),
+        }
+    }
 
-        // Define key-value pairs for the test
-        let key1 = Bytes::from("foo1");
-        let key2 = Bytes::from("foo2");
-        let value1 = Bytes::from("baz");
-        let value2 = Bytes::from("bar");
+    /// Gets a value for a key if it exists.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.get_with_options(key, &ReadOptions::default())
+    }
 
-        {
-            // Start a new read-write transaction (txn1)
-            let mut txn1 = store.begin().unwrap();
-            txn1.set(&key1, &value1).unwrap();
-            txn1.set(&key2, &value1).unwrap();
-            txn1.commit().await.unwrap();
+    /// Gets a value for a key if it exists, as `get` does, with the given read options.
+    pub fn get_with_options(&self, key: &[u8], options: &ReadOptions) -> Result<Option<Vec<u8>>> {
+        // If the transaction is closed, return an error.
+        if self.closed {
+            return Err(Error::TransactionClosed);
         }
-
-        {
-            // Start a read-only transaction (txn3)
-            let txn3 = store.begin().unwrap();
-            let val = txn3.get(&key1).unwrap().unwrap();
-            assert_eq!(val, value1.as_ref());
+        self.core.invariants.check()?;
+        check_deadline(options.deadline)?;
+        // If the key is empty, return an error.
+        if key.is_empty() {
+            return Err(Error::EmptyKey);
         }
 
-        {
-            // Start another read-write transaction (txn2)
-            let mut txn2 = store.begin().unwrap();
-            txn2.set(&key1, &value2).unwrap();
-            txn2.set(&key2, &value2).unwrap();
-            txn2.commit().await.unwrap();
+        // Do not allow reads if it is a write-only transaction
+        if self.mode.is_write_only() {
+            return Err(Error::TransactionWriteOnly);
         }
 
-        // Drop the store to simulate closing it
-        store.close().await.unwrap();
+        // Create a copy of the key.
+        let key = Bytes::copy_from_slice(key);
+        let hashed_key = sha256(key.clone());
 
-        // Create a new Core instance with VariableSizeKey after dropping the previous one
-        let mut opts = Options::new();
-        opts.dir = temp_dir.path().to_path_buf();
-        let store = Store::new(opts).expect("should create store");
+        // RYOW semantics: Read your own write. If the key is in the write set, return the value.
+        // Check if the key is in the write set by checking in the write_order_map map. A key the
+        // transaction only touched keeps the value read from the snapshot.
+        if let Some(order) = self.write_order_map.get(&hashed_key) {
+            if let Some((_, entry)) = self.write_set.get(*order as usize) {
+                if !entry.is_touch() {
+                    if entry.is_deleted() || is_expired(&self.core, entry.expires_at()) {
+                        return Ok(None);
+                    }
+                    return self.resolve_written(entry, options.deadline).map(Some);
+                }
+            }
+        }
 
-        // Start a read-only transaction (txn4)
-        let txn4 = store.begin().unwrap();
-        let val = txn4.get(&key1).unwrap().unwrap();
+        // Attempt to get the value for the key from the snapshot.
+        let snapshot = self.snapshot.as_ref().unwrap().read();
+        match snapshot.get_with_filters(&key[..].into(), &[ignore_deleted]) {
+            Ok(val_ref) => {
+                // If the transaction is not read-only and the value reference has a timestamp greater than 0,
+                // add the key and its timestamp to the read set for conflict detection.
+                if !self.mode.is_read_only() && val_ref.ts() > 0 {
+                    self.read_set
+                        .lock()
+                        .push(key.clone(), read_ts(&self.core, &*val_ref));
+                }
+                if !self.is_live(&key, val_ref.key_value_metadata()) {
+                    return Ok(None);
+                }
 
-        // Assert that the value retrieved in txn4 matches value2
-        assert_eq!(val, value2.as_ref());
+                // Resolve the value reference to get the actual value.
+                val_ref.resolve_merged(&key, options.deadline).map(Some)
+            }
+            Err(e) => {
+                match &e {
+                    // If the key is not found in the index, and the transaction is not read-only,
+                    // add the key to the read set with a timestamp of 0.
+                    Error::IndexError(trie_error) => {
+                        if let TrieError::KeyNotFound = trie_error {
+                            if !self.mode.is_read_only() && !self.in_deleted_range(&key) {
+                                self.read_set.lock().push(key, 0);
+                            }
+                        }
+                        Ok(None)
+                    }
+                    // For other errors, just propagate them.
+                    _ => Err(e),
+                }
+            }
+        }
     }
 
-    #[tokio::test]
-    async fn transaction_delete_scan() {
-        let (store, _) = create_store(false);
+    /// Gets the values of several keys, as `get` does for each, and returns them in the order
+    /// of `keys`. The keys are looked up in key order, and the values to read from the commit
+    /// log are read together, in one read per run of nearby values of a segment, rather than
+    /// with a read per value.
+    pub fn get_many<K: AsRef<[u8]>>(&self, keys: &[K]) -> Result<Vec<Option<Vec<u8>>>> {
+        if self.closed {
+            return Err(Error::TransactionClosed);
+        }
+        self.core.invariants.check()?;
+        if keys.iter().any(|key| key.as_ref().is_empty()) {
+            return Err(Error::EmptyKey);
+        }
+        if self.mode.is_write_only() {
+            return Err(Error::TransactionWriteOnly);
+        }
 
-        // Define key-value pairs for the test
-        let key1 = Bytes::from("k1");
-        let value1 = Bytes::from("baz");
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_by(|a, b| keys[*a].as_ref().cmp(keys[*b].as_ref()));
+
+        let mut values = vec![None; keys.len()];
+        let mut stored = Vec::new();
+        let snapshot = self.snapshot.as_ref().unwrap().read();
+        for i in order {
+            let key = Bytes::copy_from_slice(keys[i].as_ref());
+
+            // RYOW semantics, as in `get`.
+            if let Some(order) = self.write_order_map.get(&sha256(key.clone())) {
+                let entry = &self.write_set[*order as usize].1;
+                if !entry.is_touch() {
+                    if !entry.is_deleted() && !is_expired(&self.core, entry.expires_at()) {
+                        values[i] = Some(self.resolve_written(entry, None)?);
+                    }
+                    continue;
+                }
+            }
 
-        {
-            // Start a new read-write transaction (txn1)
-            let mut txn1 = store.begin().unwrap();
-            txn1.set(&key1, &value1).unwrap();
-            txn1.set(&key1, &value1).unwrap();
-            txn1.commit().await.unwrap();
+            match snapshot.get_ref_with_filters(&key[..].into(), &[ignore_deleted]) {
+                Ok(val_ref) => {
+                    if !self.mode.is_read_only() && val_ref.ts() > 0 {
+                        self.read_set
+                            .lock()
+                            .push(key.clone(), read_ts(&self.core, &val_ref));
+                    }
+                    if !self.is_live(&key, val_ref.key_value_metadata()) {
+                        continue;
+                    }
+                    if val_ref.is_merge() {
+                        values[i] = Some(val_ref.resolve_merged(&key, None)?);
+                    } else {
+                        stored.push((i, (key, val_ref)));
+                    }
+                }
+                Err(Error::IndexError(TrieError::KeyNotFound)) => {
+                    if !self.mode.is_read_only() && !self.in_deleted_range(&key) {
+                        self.read_set.lock().push(key, 0);
+                    }
+                }
+                Err(e) => return Err(e),
+            }
         }
+        drop(snapshot);
 
+        let (positions, refs): (Vec<_>, Vec<_>) = stored.into_iter().unzip();
+        for (i, value) in positions
+            .into_iter()
+            .zip(ValueRef::resolve_all(&refs, None)?)
         {
-            // Start a read-only transaction (txn)
-            let mut txn = store.begin().unwrap();
-            txn.delete(&key1).unwrap();
-            txn.commit().await.unwrap();
+            values[i] = Some(value);
         }
+        Ok(values)
+    }
 
-        {
-            // Start another read-write transaction (txn)
-            let txn = store.begin().unwrap();
-            assert!(txn.get(&key1).unwrap().is_none());
+    /// Gets the user flags of a key if it exists.
+    pub fn get_flags(&self, key: &[u8]) -> Result<Option<u64>> {
+        if self.closed {
+            return Err(Error::TransactionClosed);
+        }
+        if key.is_empty() {
+            return Err(Error::EmptyKey);
+        }
+        if self.mode.is_write_only() {
+            return Err(Error::TransactionWriteOnly);
         }
 
+        // Read your own writes. A key the transaction only touched keeps its flags, unless
+        // the touch replaces them.
+        if let Some(order) = self
+            .write_order_map
+            .get(&sha256(Bytes::copy_from_slice(key)))
         {
-            let range = "k1".as_bytes()..="k3".as_bytes();
-            let txn = store.begin().unwrap();
-            let results = txn.scan(range, None).unwrap();
-            assert_eq!(results.len(), 0);
+            let entry = &self.write_set[*order as usize].1;
+            if !entry.is_touch() || entry.is_reflag() {
+                return Ok(
+                    (!entry.is_deleted() && !is_expired(&self.core, entry.expires_at()))
+                        .then(|| entry.flags()),
+                );
+            }
         }
-    }
 
-    async fn mvcc_tests(is_ssi: bool) {
-        let (store, _) = create_store(is_ssi);
+        let key = Bytes::copy_from_slice(key);
+        let snapshot = self.snapshot.as_ref().unwrap().read();
+        match snapshot.get_with_filters(&key[..].into(), &[ignore_deleted]) {
+            Ok(val_ref) => {
+                if !self.mode.is_read_only() && val_ref.ts() > 0 {
+                    self.read_set
+                        .lock()
+                        .push(key.clone(), read_ts(&self.core, &*val_ref));
+                }
+                let md = val_ref.key_value_metadata();
+                Ok(self
+                    .is_live(&key, md)
+                    .then(|| md.map_or(0, |md| md.flags())))
+            }
+            Err(Error::IndexError(TrieError::KeyNotFound)) => {
+                if !self.mode.is_read_only() && !self.in_deleted_range(&key) {
+                    self.read_set.lock().push(key, 0);
+                }
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
 
-        let key1 = Bytes::from("key1");
-        let key2 = Bytes::from("key2");
-        let value1 = Bytes::from("baz");
-        let value2 = Bytes::from("bar");
+    /// Returns the time a key of the snapshot expires at, in nanoseconds since the Unix epoch,
+    /// if it exists and expires. The key is not recorded for conflict detection.
+    pub(crate) fn expires_at(&self, key: &[u8]) -> Result<Option<u64>> {
+        let snapshot = self.snapshot.as_ref().unwrap().read();
+        match snapshot.get_with_filters(&key.into(), &[ignore_deleted]) {
+            Ok(val_ref) => Ok(val_ref.key_value_metadata().and_then(|md| md.expires_at())),
+            Err(Error::IndexError(TrieError::KeyNotFound)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
 
-        // no read conflict
-        {
-            let mut txn1 = store.begin().unwrap();
-            let mut txn2 = store.begin().unwrap();
-
-            txn1.set(&key1, &value1).unwrap();
-            txn1.commit().await.unwrap();
-
-            assert!(txn2.get(&key2).unwrap().is_none());
-            txn2.set(&key2, &value2).unwrap();
-            txn2.commit().await.unwrap();
+    /// Returns true if a key exists, as `get` would find it, without reading its value.
+    ///
+    /// The store keeps no bloom filter: the key is answered from the index alone, whose
+    /// lookup first checks the interned prefix of the key, so the keys of unknown prefixes
+    /// are rejected without a traversal. The commit log is never read and the value cache is
+    /// neither read nor populated, so the lookup costs about as much as that of the index,
+    /// whatever the size of the value, and is safe to call at very high rates, such as to
+    /// deduplicate. As with `get`, the key is checked for conflicts on commit.
+    pub fn contains_key(&self, key: &[u8]) -> Result<bool> {
+        if self.closed {
+            return Err(Error::TransactionClosed);
+        }
+        if key.is_empty() {
+            return Err(Error::EmptyKey);
+        }
+        if self.mode.is_write_only() {
+            return Err(Error::TransactionWriteOnly);
         }
 
-        // read conflict when the read key was updated by another transaction
+        // Read your own writes. A key the transaction only touched keeps existing, unless
+        // the touch makes it expire.
+        if let Some(order) = self
+            .write_order_map
+            .get(&sha256(Bytes::copy_from_slice(key)))
         {
-            let mut txn1 = store.begin().unwrap();
-            let mut txn2 = store.begin().unwrap();
-
-            txn1.set(&key1, &value1).unwrap();
-            txn1.commit().await.unwrap();
+            let entry = &self.write_set[*order as usize].1;
+            if !entry.is_touch() {
+                return Ok(!entry.is_deleted() && !is_expired(&self.core, entry.expires_at()));
+            }
+        }
 
-            assert!(txn2.get(&key1).is_ok());
-            txn2.set(&key1, &value2).unwrap();
-            assert!(match txn2.commit().await {
-                Err(err) => {
-                    matches!(err, Error::TransactionReadConflict)
+        let key = Bytes::copy_from_slice(key);
+        let snapshot = self.snapshot.as_ref().unwrap().read();
+        match snapshot.get_ref_with_filters(&key[..].into(), &[ignore_deleted]) {
+            Ok(val_ref) => {
+                if !self.mode.is_read_only() && val_ref.ts() > 0 {
+                    self.read_set
+                        .lock()
+                        .push(key.clone(), read_ts(&self.core, &val_ref));
                 }
-                _ => false,
-            });
+                Ok(self.is_live(&key, val_ref.key_value_metadata()))
+            }
+            Err(Error::IndexError(TrieError::KeyNotFound)) => {
+                if !self.mode.is_read_only() && !self.in_deleted_range(&key) {
+                    self.read_set.lock().push(key, 0);
+                }
+                Ok(false)
+            }
+            Err(e) => Err(e),
         }
+    }
 
-        // blind writes should succeed if key wasn't read first
-        {
-            let mut txn1 = store.begin().unwrap();
-            let mut txn2 = store.begin().unwrap();
-
-            txn1.set(&key1, &value1).unwrap();
-            txn2.set(&key1, &value2).unwrap();
+    /// Adds a key-value pair to the store, wrapping the value in an envelope tagged with the
+    /// schema version of the payload.
+    pub fn set_versioned(&mut self, key: &[u8], version: u8, payload: &[u8]) -> Result<()> {
+        self.set(key, &envelope::encode(version, payload))
+    }
 
-            txn1.commit().await.unwrap();
-            txn2.commit().await.unwrap();
+    /// Gets the schema version and payload of an enveloped value if it exists.
+    /// If a migration is registered for the key and the value is at an older version, the
+    /// migrated payload is returned, and in mutable transactions the migrated value is written
+    /// back so that it is persisted when the transaction commits.
+    pub fn get_versioned(&mut self, key: &[u8]) -> Result<Option<(u8, Vec<u8>)>> {
+        let value = match self.get(key)? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
 
-            let txn3 = store.begin().unwrap();
-            let val = txn3.get(&key1).unwrap().unwrap();
-            assert_eq!(val, value2.as_ref());
+        let (version, payload, migrated) = self.core.migrations.apply(key, &value)?;
+        if migrated && self.mode.mutable() {
+            self.set_versioned(key, version, &payload)?;
         }
 
-        // read conflict when the read key was updated by another transaction
-        {
-            let key = Bytes::from("key3");
+        Ok(Some((version, payload)))
+    }
 
-            let mut txn1 = store.begin().unwrap();
-            let mut txn2 = store.begin().unwrap();
+    /// Adds a key-value pair to a keyspace.
+    pub fn set_in(&mut self, keyspace: &Keyspace, key: &[u8], value: &[u8]) -> Result<()> {
+        self.set_system(&keyspace.key(key)?, value)
+    }
 
-            txn1.set(&key, &value1).unwrap();
-            txn1.commit().await.unwrap();
+    /// Deletes a key from a keyspace.
+    pub fn delete_in(&mut self, keyspace: &Keyspace, key: &[u8]) -> Result<()> {
+        self.delete_system(&keyspace.key(key)?)
+    }
 
-            assert!(txn2.get(&key).unwrap().is_none());
-            txn2.set(&key, &value1).unwrap();
-            assert!(match txn2.commit().await {
-                Err(err) => {
-                    matches!(err, Error::TransactionReadConflict)
-                }
-                _ => false,
-            });
+    /// Gets the value of a key of a keyspace if it exists, as `get` does.
+    pub fn get_in(&self, keyspace: &Keyspace, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.get(&keyspace.key(key)?)
+    }
+
+    /// Scans a range of keys of a keyspace, as `scan` does. The keys returned are the keys of
+    /// the keyspace.
+    pub fn scan_in<'b, R>(
+        &self,
+        keyspace: &Keyspace,
+        range: R,
+        limit: Option<usize>,
+    ) -> Result<Vec<ScanResult>>
+    where
+        R: RangeBounds<&'b [u8]>,
+    {
+        let (start, end) = keyspace.range(&range);
+        fn as_slice(bound: &Bound<Vec<u8>>) -> Bound<&[u8]> {
+            match bound {
+                Bound::Included(key) => Bound::Included(key),
+                Bound::Excluded(key) => Bound::Excluded(key),
+                Bound::Unbounded => Bound::Unbounded,
+            }
         }
+        let range = (as_slice(&start), as_slice(&end));
+        let mut results = self.scan(range, limit)?;
+        for result in results.iter_mut() {
+            result.0 = keyspace.strip(std::mem::take(&mut result.0));
+        }
+        Ok(results)
+    }
 
-        // read conflict when the read key was deleted by another transaction
-        {
-            let key = Bytes::from("key4");
+    /// Writes a value for a key. None is used for deletion.
+    fn write(&mut self, e: Entry) -> Result<()> {
+        self.check_write(&e)?;
+        self.write_checked(e)
+    }
 
-            let mut txn1 = store.begin().unwrap();
-            txn1.set(&key, &value1).unwrap();
-            txn1.commit().await.unwrap();
+    /// Writes a key of the system keyspace, which `set` refuses, for the data kept by the
+    /// store itself: keyspaces, streams, queues, locks and coordinated commits.
+    pub(crate) fn set_system(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.write_system(Entry::new(key, value))
+    }
 
-            let mut txn2 = store.begin().unwrap();
-            let mut txn3 = store.begin().unwrap();
+    /// Deletes a key of the system keyspace, which `delete` refuses.
+    pub(crate) fn delete_system(&mut self, key: &[u8]) -> Result<()> {
+        let mut entry = Entry::new(key, &[]);
+        entry.mark_delete();
+        self.write_system(entry)
+    }
 
-            txn2.delete(&key).unwrap();
-            assert!(txn2.commit().await.is_ok());
+    /// Writes an entry whose key may be in the system keyspace.
+    pub(crate) fn write_system(&mut self, e: Entry) -> Result<()> {
+        self.check_entry(&e)?;
+        self.write_checked(e)
+    }
 
-            assert!(txn3.get(&key).is_ok());
-            txn3.set(&key, &value2).unwrap();
-            assert!(match txn3.commit().await {
-                Err(err) => {
-                    matches!(err, Error::TransactionReadConflict)
-                }
-                _ => false,
-            });
+    fn write_checked(&mut self, e: Entry) -> Result<()> {
+        if self.pending_writes() >= self.core.opts.max_entries_per_txn as usize {
+            return Err(Error::MaxTransactionEntriesLimitExceeded);
         }
+        self.apply_write(e)
     }
 
-    #[tokio::test]
-    async fn mvcc_serialized_snapshot_isolation() {
-        mvcc_tests(true).await;
+    /// Writes the entries committed by a child transaction, which are checked already. They
+    /// are written all or none, as far as the limit on the number of entries goes.
+    pub(crate) fn write_all(&mut self, entries: Vec<Entry>) -> Result<()> {
+        if self.closed {
+            return Err(Error::TransactionClosed);
+        }
+        let new_keys = entries
+            .iter()
+            .filter(|e| !self.write_order_map.contains_key(&sha256(e.key.clone())))
+            .count();
+        if self.pending_writes() + new_keys > self.core.opts.max_entries_per_txn as usize {
+            return Err(Error::MaxTransactionEntriesLimitExceeded);
+        }
+        for e in entries {
+            self.apply_write(e)?;
+        }
+        Ok(())
     }
 
-    #[tokio::test]
-    async fn mvcc_snapshot_isolation() {
-        mvcc_tests(false).await;
-    }
+    /// Adds a checked entry to the pending writes and to the snapshot.
+    fn apply_write(&mut self, e: Entry) -> Result<()> {
+        // A write replaces the increments of its key.
+        self.increments.remove(&e.key);
 
-    #[tokio::test]
-    async fn basic_scan_single_key() {
-        let (store, _) = create_store(false);
-        // Define key-value pairs for the test
-        let keys_to_insert = vec![Bytes::from("key1")];
+        // If the transaction mode is not write-only, update the snapshot.
+        if !self.mode.is_write_only() {
+            // Convert the value to Bytes.
+            let index_value = ValueRef::encode_mem(&e.value, e.metadata.as_ref());
 
-        for key in &keys_to_insert {
-            let mut txn = store.begin().unwrap();
-            txn.set(key, key).unwrap();
-            txn.commit().await.unwrap();
+            // Set the key-value pair in the snapshot.
+            self.snapshot
+                .as_ref()
+                .unwrap()
+                .write()
+                .set(&e.key[..].into(), index_value)?;
         }
 
-        let range = "key1".as_bytes()..="key3".as_bytes();
-
-        let txn = store.begin().unwrap();
-        let results = txn.scan(range, None).unwrap();
-        assert_eq!(results.len(), keys_to_insert.len());
+        self.push_write(e);
+        Ok(())
     }
 
-    #[tokio::test]
-    async fn basic_scan_multiple_keys() {
-        let (store, _) = create_store(false);
-        // Define key-value pairs for the test
-        let keys_to_insert = vec![
-            Bytes::from("key1"),
-            Bytes::from("key2"),
-            Bytes::from("key3"),
-            Bytes::from("lemon"),
-        ];
+    /// Checks that the transaction can write an entry, leaving aside the number of entries
+    /// it writes.
+    pub(crate) fn check_write(&self, e: &Entry) -> Result<()> {
+        self.check_entry(e)?;
+        // The system keyspace holds the data of the store, which is only written by the store.
+        if is_system_key(&e.key) {
+            return Err(Error::ReservedKey);
+        }
+        Ok(())
+    }
 
-        for key in &keys_to_insert {
-            let mut txn = store.begin().unwrap();
-            txn.set(key, key).unwrap();
-            txn.commit().await.unwrap();
+    fn check_entry(&self, e: &Entry) -> Result<()> {
+        // If the transaction mode is not mutable (i.e., it's read-only), return an error.
+        if !self.mode.mutable() {
+            return Err(Error::TransactionReadOnly);
+        }
+        // If the transaction is closed, return an error.
+        if self.closed {
+            return Err(Error::TransactionClosed);
+        }
+        // If the key is empty, return an error.
+        if e.key.is_empty() {
+            return Err(Error::EmptyKey);
+        }
+        // If the key length exceeds the maximum allowed key size, return an error.
+        if e.key.len() as u64 > self.core.opts.max_key_size {
+            return Err(Error::MaxKeyLengthExceeded);
+        }
+        // If the value length exceeds the maximum allowed value size, return an error.
+        if e.value.len() as u64 > self.core.opts.max_value_size {
+            return Err(Error::MaxValueLengthExceeded);
         }
+        Ok(())
+    }
 
-        let range = "key1".as_bytes()..="key3".as_bytes();
+    /// Adds an entry to the set of pending writes, replacing the pending write of its key.
+    fn push_write(&mut self, e: Entry) {
+        let hashed_key = sha256(e.key.clone());
 
-        let txn = store.begin().unwrap();
-        let results = txn.scan(range, None).unwrap();
-        assert_eq!(results.len(), 3);
-        assert_eq!(results[0].1, keys_to_insert[0]);
-        assert_eq!(results[1].1, keys_to_insert[1]);
-        assert_eq!(results[2].1, keys_to_insert[2]);
+        let stats = &self.core.stats;
+        let entry_bytes = e.key.len() + e.value.len();
+        stats.memory_add(&stats.transaction_bytes, entry_bytes);
+        self.write_set_bytes += entry_bytes;
+
+        // Check if the key already exists in write_order_map, if so, update the entry in write_set.
+        if let Some(order) = self.write_order_map.get(&hashed_key) {
+            let (key, old) = &self.write_set[*order as usize];
+            let old_bytes = key.len() + old.value.len();
+            stats.memory_sub(&stats.transaction_bytes, old_bytes);
+            self.write_set_bytes -= old_bytes;
+            self.write_set[*order as usize] = (e.key.clone(), e);
+        } else {
+            self.write_set.push((e.key.clone(), e));
+            self.write_order_map
+                .insert(hashed_key, self.write_order_map.len() as u32);
+        }
     }
 
-    #[tokio::test]
-    async fn scan_multiple_keys_within_single_transaction() {
-        let (store, _) = create_store(false);
-        // Define key-value pairs for the test
-        let keys_to_insert = vec![
-            Bytes::from("test1"),
-            Bytes::from("test2"),
-            Bytes::from("test3"),
-        ];
+    /// Scans a range of keys and returns a vector of tuples containing the value, version, and timestamp for each key.
+    pub fn scan<'b, R>(&'b self, range: R, limit: Option<usize>) -> Result<Vec<ScanResult>>
+    where
+        R: RangeBounds<&'b [u8]>,
+    {
+        self.scan_range(range, limit, true, None)
+    }
 
-        let mut txn = store.begin().unwrap();
-        for key in &keys_to_insert {
-            txn.set(key, key).unwrap();
-        }
-        txn.commit().await.unwrap();
+    /// Scans a range of keys as `scan` does, with the given read options.
+    pub fn scan_with_options<'b, R>(
+        &'b self,
+        range: R,
+        limit: Option<usize>,
+        options: &ReadOptions,
+    ) -> Result<Vec<ScanResult>>
+    where
+        R: RangeBounds<&'b [u8]>,
+    {
+        self.scan_range(range, limit, true, options.deadline)
+    }
 
-        let range = "test1".as_bytes()..="test7".as_bytes();
+    /// Scans one page of at most `limit` entries of a range, starting after the page `token`
+    /// was returned with, or at the start of the range without a token. It returns the
+    /// entries and the token of the next page, or None once the range is exhausted.
+    ///
+    /// The pages can be read by different transactions, so that a large range is consumed
+    /// without holding one snapshot open for the whole traversal. Each page is read from the
+    /// snapshot of its transaction: the entries written or deleted between two pages are seen
+    /// by the pages that follow, but every key is returned at most once, in key order. A page
+    /// can come back full and be followed by an empty last page.
+    pub fn scan_paginated<'b, R>(
+        &'b self,
+        range: R,
+        limit: usize,
+        token: Option<&'b PageToken>,
+    ) -> Result<(Vec<ScanResult>, Option<PageToken>)>
+    where
+        R: RangeBounds<&'b [u8]>,
+    {
+        let limit = limit.max(1);
+        // A token from before the start of the range does not move it.
+        let start = match (token, range.start_bound()) {
+            (Some(token), Bound::Included(start) | Bound::Excluded(start))
+                if token.0[..] < **start =>
+            {
+                range.start_bound().cloned()
+            }
+            (Some(token), _) => Bound::Excluded(&token.0[..]),
+            (None, _) => range.start_bound().cloned(),
+        };
+        let results = self.scan((start, range.end_bound().cloned()), Some(limit))?;
+        let next = match results.last() {
+            Some((key, ..)) if results.len() == limit => Some(PageToken(key.clone())),
+            _ => None,
+        };
+        Ok((results, next))
+    }
 
-        let txn = store.begin().unwrap();
-        let results = txn.scan(range, None).unwrap();
-        assert_eq!(results.len(), 3);
-        assert_eq!(results[0].0, keys_to_insert[0]);
-        assert_eq!(results[1].0, keys_to_insert[1]);
-        assert_eq!(results[2].0, keys_to_insert[2]);
-        assert_eq!(results[0].1, keys_to_insert[0]);
-        assert_eq!(results[1].1, keys_to_insert[1]);
-        assert_eq!(results[2].1, keys_to_insert[2]);
+    /// Scans a range of keys as `scan` does, returning only the keys. The values are not
+    /// read from the commit log nor added to the value cache, which makes key enumeration and
+    /// existence checks of keys with large values much cheaper.
+    pub fn keys<'b, R>(&'b self, range: R, limit: Option<usize>) -> Result<Vec<Vec<u8>>>
+    where
+        R: RangeBounds<&'b [u8]>,
+    {
+        let results = self.scan_range(range, limit, false, None)?;
+        Ok(results.into_iter().map(|(key, ..)| key).collect())
     }
 
-    async fn mvcc_with_scan_tests(is_ssi: bool) {
-        let (store, _) = create_store(is_ssi);
+    /// Scans a range of keys, recording the range for conflict detection, and resolves their
+    /// values by the deadline if `resolve_values` is set.
+    fn scan_range<'b, R>(
+        &'b self,
+        range: R,
+        limit: Option<usize>,
+        resolve_values: bool,
+        deadline: Option<Instant>,
+    ) -> Result<Vec<ScanResult>>
+    where
+        R: RangeBounds<&'b [u8]>,
+    {
+        self.core.invariants.check()?;
+        self.core.unavailable.check(&range)?;
 
-        let key1 = Bytes::from("key1");
-        let key2 = Bytes::from("key2");
-        let key3 = Bytes::from("key3");
-        let key4 = Bytes::from("key4");
-        let value1 = Bytes::from("value1");
-        let value2 = Bytes::from("value2");
-        let value3 = Bytes::from("value3");
-        let value4 = Bytes::from("value4");
-        let value5 = Bytes::from("value5");
-        let value6 = Bytes::from("value6");
+        // System keys are only returned if the range starts inside the system keyspace.
+        let include_system_keys = match range.start_bound() {
+            Bound::Included(start) | Bound::Excluded(start) => is_system_key(start),
+            Bound::Unbounded => false,
+        };
 
-        // read conflict when scan keys have been updated in another transaction
+        // Convert the range to a tuple of bounds of variable keys.
+        let range = to_key_range(&range);
+
+        // Keep track of the range bound predicates for conflict detection in case of SSI.
         {
-            let mut txn1 = store.begin().unwrap();
+            let range = (
+                match range.start_bound() {
+                    Bound::Included(start) => Bound::Included((*start).clone()),
+                    Bound::Excluded(start) => Bound::Included((*start).clone()),
+                    Bound::Unbounded => Bound::Unbounded,
+                },
+                match range.end_bound() {
+                    Bound::Included(end) => Bound::Included((*end).clone()),
+                    Bound::Excluded(end) => Bound::Included((*end).clone()),
+                    Bound::Unbounded => Bound::Unbounded,
+                },
+            );
 
-            txn1.set(&key1, &value1).unwrap();
-            txn1.commit().await.unwrap();
+            self.read_key_ranges.lock().push(range);
+        }
 
-            let mut txn2 = store.begin().unwrap();
-            let mut txn3 = store.begin().unwrap();
+        self.scan_keys(
+            range,
+            include_system_keys,
+            limit,
+            true,
+            resolve_values,
+            deadline,
+        )
+    }
 
-            txn2.set(&key1, &value4).unwrap();
-            txn2.set(&key2, &value2).unwrap();
-            txn2.set(&key3, &value3).unwrap();
-            txn2.commit().await.unwrap();
+    /// Scans a range of keys (terminated with a null byte), recording the keys read in the
+    /// read set if `track_reads` is set. The values are left empty unless `resolve_values`
+    /// is set, and are resolved by the deadline, if any.
+    pub(crate) fn scan_keys(
+        &self,
+        range: (Bound<VariableSizeKey>, Bound<VariableSizeKey>),
+        include_system_keys: bool,
+        limit: Option<usize>,
+        track_reads: bool,
+        resolve_values: bool,
+        deadline: Option<Instant>,
+    ) -> Result<Vec<ScanResult>> {
+        // Initialize an empty vector to store the results.
+        let mut results = Vec::new();
 
-            let range = "key1".as_bytes()..="key4".as_bytes();
-            let results = txn3.scan(range, None).unwrap();
-            assert_eq!(results.len(), 1);
-            txn3.set(&key2, &value5).unwrap();
-            txn3.set(&key3, &value6).unwrap();
+        // Translate the range to the keys of the index.
+        let Some(range) = self.core.keys.range(range) else {
+            return Ok(results);
+        };
 
-            assert!(match txn3.commit().await {
-                Err(err) => {
-                    matches!(err, Error::TransactionReadConflict)
+        // Create a new reader for the snapshot.
+        let iterator = match self.snapshot.as_ref().unwrap().write().new_reader() {
+            Ok(reader) => reader,
+            Err(Error::IndexError(TrieError::SnapshotEmpty)) => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        // Get a range iterator for the specified range.
+        let ranger = iterator.range(range);
+
+        // Iterate over the keys in the range.
+        for (key, value, version, ts) in ranger {
+            // If a limit is set and we've already got enough results, break the loop.
+            if let Some(limit) = limit {
+                if results.len() >= limit {
+                    break;
                 }
-                _ => false,
-            });
-        }
+            }
+            check_deadline(deadline)?;
 
-        // read conflict when read keys are deleted by other transaction
-        {
-            let mut txn1 = store.begin().unwrap();
+            // Skip the keys reserved for the internal subsystems of the store.
+            let key = self.core.keys.decode(key)?;
+            if !include_system_keys && is_system_key(&key) {
+                continue;
+            }
 
-            txn1.set(&key4, &value1).unwrap();
-            txn1.commit().await.unwrap();
+            // A key written more than once by the transaction has a version in the snapshot
+            // for each write, all at the same version, of which the iterator returns the first.
+            // Its last write is taken from the write set instead.
+            if !self.write_set.is_empty() {
+                let hashed_key = sha256(Bytes::copy_from_slice(&key));
+                // A key the transaction only touched keeps the value of the snapshot.
+                if let Some(order) = self.write_order_map.get(&hashed_key) {
+                    let entry = &self.write_set[*order as usize].1;
+                    if !entry.is_touch() {
+                        if !entry.is_deleted() && !is_expired(&self.core, entry.expires_at()) {
+                            let value = if resolve_values {
+                                self.resolve_written(entry, deadline)?
+                            } else {
+                                Vec::new()
+                            };
+                            results.push((key, value, *version, *ts));
+                        }
+                        continue;
+                    }
+                }
+            }
 
-            let mut txn2 = store.begin().unwrap();
-            let mut txn3 = store.begin().unwrap();
+            // Create a new value reference and decode the value.
+            let mut val_ref = ValueRef::new(self.core.clone());
+            let val_bytes_ref: &Bytes = value;
+            val_ref.decode(*version, val_bytes_ref)?;
 
-            txn2.delete(&key4).unwrap();
-            txn2.commit().await.unwrap();
+            // Skip the deleted and expired keys.
+            if !self.is_live(&key, val_ref.key_value_metadata()) {
+                continue;
+            }
 
-            let range = "key1".as_bytes()..="key5".as_bytes();
-            txn3.scan(range, None).unwrap();
-            txn3.set(&key4, &value2).unwrap();
+            // Only add the key to the read set if the timestamp is less than or equal to the
+            // read timestamp. This is to prevent adding keys that are added during the transaction.
+            if track_reads && val_ref.ts() <= self.read_ts {
+                self.read_set
+                    .lock()
+                    .push(Bytes::copy_from_slice(&key), val_ref.ts);
+            }
 
-            assert!(match txn3.commit().await {
-                Err(err) => {
-                    matches!(err, Error::TransactionReadConflict)
-                }
-                _ => false,
-            });
+            // Resolve the value reference to get the actual value.
+            let v = if resolve_values {
+                val_ref.resolve_merged(&key, deadline)?
+            } else {
+                Vec::new()
+            };
+
+            // Add the value, version, and timestamp to the results vector.
+            results.push((key, v, *version, *ts));
         }
-    }
 
-    #[tokio::test]
-    async fn mvcc_serialized_snapshot_isolation_scan() {
-        mvcc_with_scan_tests(true).await;
+        // Return the results.
+        Ok(results)
     }
 
-    #[tokio::test]
-    async fn mvcc_snapshot_isolation_scan() {
-        mvcc_with_scan_tests(false).await;
+    /// Returns the entry with the smallest key greater than or equal to `key`, if any.
+    pub fn get_ge(&self, key: &[u8]) -> Result<Option<ScanResult>> {
+        self.seek(key, true)
     }
 
-    #[tokio::test]
-    async fn ryow() {
-        let temp_dir = create_temp_directory();
-        let mut opts = Options::new();
-        opts.dir = temp_dir.path().to_path_buf();
-
-        let store = Store::new(opts.clone()).expect("should create store");
-        let key1 = Bytes::from("k1");
-        let key2 = Bytes::from("k2");
-        let key3 = Bytes::from("k3");
-        let value1 = Bytes::from("v1");
-        let value2 = Bytes::from("v2");
+    /// Returns the entry with the largest key less than or equal to `key`, if any.
+    ///
+    /// This is not a point query: the index has no reverse iterator, so this walks all the
+    /// keys from the first one up to `key`, without resolving their values, and its cost
+    /// grows linearly with the number of keys before `key`. Keys looked up backward often are
+    /// better written under an encoding reversing their order, and read with `get_ge`.
+    pub fn get_le(&self, key: &[u8]) -> Result<Option<ScanResult>> {
+        self.seek(key, false)
+    }
 
-        // Set a key, delete it and read it in the same transaction. Should return None.
-        {
-            // Start a new read-write transaction (txn1)
-            let mut txn1 = store.begin().unwrap();
-            txn1.set(&key1, &value1).unwrap();
-            txn1.delete(&key1).unwrap();
-            let res = txn1.get(&key1).unwrap();
-            assert!(res.is_none());
-            txn1.commit().await.unwrap();
+    /// Finds the nearest live key at-or-after (`forward`) or at-or-before `key`, and resolves
+    /// only its value. The range between `key` and the found key is recorded for conflict
+    /// detection, as any key inserted in it would change the result.
+    fn seek(&self, key: &[u8], forward: bool) -> Result<Option<ScanResult>> {
+        // If the transaction is closed, return an error.
+        if self.closed {
+            return Err(Error::TransactionClosed);
         }
-
-        {
-            let mut txn = store.begin().unwrap();
-            txn.set(&key1, &value1).unwrap();
-            txn.commit().await.unwrap();
+        // If the key is empty, return an error.
+        if key.is_empty() {
+            return Err(Error::EmptyKey);
         }
-
-        {
-            // Start a new read-write transaction (txn)
-            let mut txn = store.begin().unwrap();
-            txn.set(&key1, &value2).unwrap();
-            assert_eq!(txn.get(&key1).unwrap().unwrap(), value2.as_ref());
-            assert!(txn.get(&key3).unwrap().is_none());
-            txn.set(&key2, &value1).unwrap();
-            assert_eq!(txn.get(&key2).unwrap().unwrap(), value1.as_ref());
-            txn.commit().await.unwrap();
+        // Do not allow reads if it is a write-only transaction
+        if self.mode.is_write_only() {
+            return Err(Error::TransactionWriteOnly);
         }
-    }
 
-    // Common setup logic for creating a store
-    async fn create_hermitage_store(is_ssi: bool) -> Store {
-        let (store, _) = create_store(is_ssi);
+        let include_system_keys = is_system_key(key);
+        let seek_key = VariableSizeKey::from_slice_with_termination(key);
+        let range = if forward {
+            (Bound::Included(seek_key.clone()), Bound::Unbounded)
+        } else {
+            (Bound::Unbounded, Bound::Included(seek_key.clone()))
+        };
 
-        let key1 = Bytes::from("k1");
-        let key2 = Bytes::from("k2");
-        let value1 = Bytes::from("v1");
-        let value2 = Bytes::from("v2");
-        // Start a new read-write transaction (txn)
-        let mut txn = store.begin().unwrap();
-        txn.set(&key1, &value1).unwrap();
-        txn.set(&key2, &value2).unwrap();
-        txn.commit().await.unwrap();
+        let iterator = match self.snapshot.as_ref().unwrap().write().new_reader() {
+            Ok(reader) => reader,
+            Err(Error::IndexError(TrieError::SnapshotEmpty)) => return Ok(None),
+            Err(e) => return Err(e),
+        };
 
-        store
-    }
+        let mut found: Option<(Vec<u8>, ValueRef, u64, u64)> = None;
+        let ranger = self
+            .core
+            .keys
+            .range(range)
+            .into_iter()
+            .flat_map(|range| iterator.range(range));
+        for (k, value, version, ts) in ranger {
+            // Skip the keys reserved for the internal subsystems of the store.
+            let k = self.core.keys.decode(k)?;
+            if !include_system_keys && is_system_key(&k) {
+                continue;
+            }
 
-    // The following tests are taken from hermitage (https://github.com/ept/hermitage)
-    // Specifically, the tests are derived from FoundationDB tests: https://github.com/ept/hermitage/blob/master/foundationdb.md
+            let mut val_ref = ValueRef::new(self.core.clone());
+            val_ref.decode(*version, value)?;
+            if !self.is_live(&k, val_ref.key_value_metadata()) {
+                continue;
+            }
 
-    // G0: Write Cycles (dirty writes)
-    async fn g0_tests(is_ssi: bool) {
-        let store = create_hermitage_store(is_ssi).await;
-        let key1 = Bytes::from("k1");
-        let key2 = Bytes::from("k2");
-        let value3 = Bytes::from("v3");
-        let value4 = Bytes::from("v4");
-        let value5 = Bytes::from("v5");
-        let value6 = Bytes::from("v6");
+            found = Some((k, val_ref, *version, *ts));
+            if forward {
+                break;
+            }
+        }
 
-        {
-            let mut txn1 = store.begin().unwrap();
-            let mut txn2 = store.begin().unwrap();
+        // Keep track of the range read for conflict detection in case of SSI.
+        let found_key = found
+            .as_ref()
+            .map(|(k, _, _, _)| VariableSizeKey::from_slice_with_termination(k));
+        let read_range = match (forward, found_key) {
+            (true, Some(found_key)) => (Bound::Included(seek_key), Bound::Included(found_key)),
+            (true, None) => (Bound::Included(seek_key), Bound::Unbounded),
+            (false, Some(found_key)) => (Bound::Included(found_key), Bound::Included(seek_key)),
+            (false, None) => (Bound::Unbounded, Bound::Included(seek_key)),
+        };
+        self.read_key_ranges.lock().push(read_range);
 
-            assert!(txn1.get(&key1).is_ok());
-            assert!(txn1.get(&key2).is_ok());
-            assert!(txn2.get(&key1).is_ok());
-            assert!(txn2.get(&key2).is_ok());
+        let (key, val_ref, version, ts) = match found {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+        if val_ref.ts() <= self.read_ts {
+            self.read_set
+                .lock()
+                .push(Bytes::copy_from_slice(&key), val_ref.ts);
+        }
 
-            txn1.set(&key1, &value3).unwrap();
-            txn2.set(&key1, &value4).unwrap();
+        let v = self.resolve_read(&key, &val_ref, None)?;
+        Ok(Some((key, v, version, ts)))
+    }
 
-            txn1.set(&key2, &value5).unwrap();
+    /// Walks the live keys in the range without resolving their values, calling `f` with each
+    /// key and the length of its stored value until it returns false. System keys are skipped
+    /// unless the range starts inside the system keyspace, as in `scan`.
+    /// The keys are not recorded for conflict detection.
+    pub(crate) fn walk_keys<'r, R, F>(&self, range: R, mut f: F) -> Result<()>
+    where
+        R: RangeBounds<&'r [u8]>,
+        F: FnMut(&[u8], usize) -> bool,
+    {
+        if self.closed {
+            return Err(Error::TransactionClosed);
+        }
+        if self.mode.is_write_only() {
+            return Err(Error::TransactionWriteOnly);
+        }
 
-            txn1.commit().await.unwrap();
+        let include_system_keys = match range.start_bound() {
+            Bound::Included(start) | Bound::Excluded(start) => is_system_key(start),
+            Bound::Unbounded => false,
+        };
+        let Some(range) = self.core.keys.range(to_key_range(&range)) else {
+            return Ok(());
+        };
 
-            txn2.set(&key2, &value6).unwrap();
-            assert!(match txn2.commit().await {
-                Err(err) => {
-                    matches!(err, Error::TransactionReadConflict)
-                }
-                _ => false,
-            });
-        }
+        let iterator = match self.snapshot.as_ref().unwrap().write().new_reader() {
+            Ok(reader) => reader,
+            Err(Error::IndexError(TrieError::SnapshotEmpty)) => return Ok(()),
+            Err(e) => return Err(e),
+        };
 
-        {
-            let txn3 = store.begin().unwrap();
-            let val1 = txn3.get(&key1).unwrap().unwrap();
-            assert_eq!(val1, value3.as_ref());
-            let val2 = txn3.get(&key2).unwrap().unwrap();
-            assert_eq!(val2, value5.as_ref());
+        for (key, value, version, _) in iterator.range(range) {
+            let key = self.core.keys.decode(key)?;
+            if !include_system_keys && is_system_key(&key) {
+                continue;
+            }
+
+            let mut val_ref = ValueRef::new(self.core.clone());
+            val_ref.decode(*version, value)?;
+            if !self.is_live(&key, val_ref.key_value_metadata()) {
+                continue;
+            }
+
+            if !f(&key, val_ref.length()) {
+                break;
+            }
         }
-    }
 
-    #[tokio::test]
-    async fn g0() {
-        g0_tests(false).await; // snapshot isolation
-        g0_tests(true).await; // serializable snapshot isolation
+        Ok(())
     }
 
-    // G1a: Aborted Reads (dirty reads, cascaded aborts)
-    async fn g1a_tests(is_ssi: bool) {
-        let store = create_hermitage_store(is_ssi).await;
-        let key1 = Bytes::from("k1");
-        let key2 = Bytes::from("k2");
-        let value1 = Bytes::from("v1");
-        let value2 = Bytes::from("v2");
-        let value3 = Bytes::from("v3");
-
-        {
-            let mut txn1 = store.begin().unwrap();
-            let mut txn2 = store.begin().unwrap();
+    /// Gets the value a key had at `version`, as left by the transaction that committed that
+    /// version. Versions newer than the snapshot of the transaction are not visible, and the
+    /// versions dropped by `Store::shrink_index` are no longer found. The writes of the
+    /// transaction are not seen, and the read is not recorded for conflict detection.
+    pub fn get_at_version(&self, key: &[u8], version: u64) -> Result<Option<Vec<u8>>> {
+        if self.closed {
+            return Err(Error::TransactionClosed);
+        }
+        if key.is_empty() {
+            return Err(Error::EmptyKey);
+        }
+        if self.mode.is_write_only() {
+            return Err(Error::TransactionWriteOnly);
+        }
 
-            assert!(txn1.get(&key1).is_ok());
+        let Some(index_key) = self.core.keys.lookup(key) else {
+            return Ok(None);
+        };
+        Ok(self
+            .read_at_version(key, &index_key, version, u64::MAX)?
+            .map(|(value, ..)| value))
+    }
 
-            txn1.set(&key1, &value3).unwrap();
+    /// Gets the value a key had at the commit timestamp `ts`, in nanoseconds since the Unix
+    /// epoch: the value left by the last transaction committed at or before `ts`, as the
+    /// timestamps returned by `scan_at_version` tell. The value is otherwise read as
+    /// `get_at_version` reads it.
+    pub fn get_at_ts(&self, key: &[u8], ts: u64) -> Result<Option<Vec<u8>>> {
+        if self.closed {
+            return Err(Error::TransactionClosed);
+        }
+        if key.is_empty() {
+            return Err(Error::EmptyKey);
+        }
+        if self.mode.is_write_only() {
+            return Err(Error::TransactionWriteOnly);
+        }
 
-            let range = "k1".as_bytes()..="k3".as_bytes();
-            let res = txn2.scan(range.clone(), None).unwrap();
-            assert_eq!(res.len(), 2);
-            assert_eq!(res[0].1, value1);
+        let Some(index_key) = self.core.keys.lookup(key) else {
+            return Ok(None);
+        };
+        Ok(self
+            .read_at_version(key, &index_key, u64::MAX, ts)?
+            .map(|(value, ..)| value))
+    }
 
-            drop(txn1);
+    /// Scans a range of keys as they were at `version`, as `get_at_version` reads them. The
+    /// keys deleted since `version` are returned, and the version and timestamp returned
+    /// with each key are the ones of the value read.
+    pub fn scan_at_version<'b, R>(
+        &'b self,
+        range: R,
+        version: u64,
+        limit: Option<usize>,
+    ) -> Result<Vec<ScanResult>>
+    where
+        R: RangeBounds<&'b [u8]>,
+    {
+        self.scan_at(range, version, u64::MAX, limit)
+    }
 
-            let res = txn2.scan(range, None).unwrap();
-            assert_eq!(res.len(), 2);
-            assert_eq!(res[0].1, value1);
+    /// Scans a range of keys as they were at the commit timestamp `ts`, as `get_at_ts` reads
+    /// them, for audits and debugging. The keys written after `ts` only are not returned.
+    pub fn scan_at_ts<'b, R>(
+        &'b self,
+        range: R,
+        ts: u64,
+        limit: Option<usize>,
+    ) -> Result<Vec<ScanResult>>
+    where
+        R: RangeBounds<&'b [u8]>,
+    {
+        self.scan_at(range, u64::MAX, ts, limit)
+    }
 
-            txn2.commit().await.unwrap();
+    /// Scans a range of keys with `read_at_version`, at `version` and `ts`.
+    fn scan_at<'b, R>(
+        &'b self,
+        range: R,
+        version: u64,
+        ts: u64,
+        limit: Option<usize>,
+    ) -> Result<Vec<ScanResult>>
+    where
+        R: RangeBounds<&'b [u8]>,
+    {
+        if self.closed {
+            return Err(Error::TransactionClosed);
         }
-
-        {
-            let txn3 = store.begin().unwrap();
-            let val1 = txn3.get(&key1).unwrap().unwrap();
-            assert_eq!(val1, value1.as_ref());
-            let val2 = txn3.get(&key2).unwrap().unwrap();
-            assert_eq!(val2, value2.as_ref());
+        if self.mode.is_write_only() {
+            return Err(Error::TransactionWriteOnly);
         }
-    }
 
-    #[tokio::test]
-    async fn g1a() {
-        g1a_tests(false).await; // snapshot isolation
-        g1a_tests(true).await; // serializable snapshot isolation
-    }
+        let include_system_keys = match range.start_bound() {
+            Bound::Included(start) | Bound::Excluded(start) => is_system_key(start),
+            Bound::Unbounded => false,
+        };
+        let mut results = Vec::new();
+        let Some(range) = self.core.keys.range(to_key_range(&range)) else {
+            return Ok(results);
+        };
 
-    // G1b: Intermediate Reads (dirty reads)
-    async fn g1b_tests(is_ssi: bool) {
-        let store = create_hermitage_store(is_ssi).await;
+        let iterator = match self.snapshot.as_ref().unwrap().write().new_reader() {
+            Ok(reader) => reader,
+            Err(Error::IndexError(TrieError::SnapshotEmpty)) => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
 
-        let key1 = Bytes::from("k1");
-        let key2 = Bytes::from("k2");
-        let value1 = Bytes::from("v1");
-        let value3 = Bytes::from("v3");
-        let value4 = Bytes::from("v4");
+        for (index_key, ..) in iterator.range(range) {
+            if limit.is_some_and(|limit| results.len() >= limit) {
+                break;
+            }
+            let terminated = VariableSizeKey::from_slice(&index_key);
+            let key = self.core.keys.decode(index_key)?;
+            if !include_system_keys && is_system_key(&key) {
+                continue;
+            }
 
-        {
-            let mut txn1 = store.begin().unwrap();
-            let mut txn2 = store.begin().unwrap();
+            if let Some((value, version, ts)) =
+                self.read_at_version(&key, &terminated, version, ts)?
+            {
+                results.push((key, value, version, ts));
+            }
+        }
 
-            assert!(txn1.get(&key1).is_ok());
-            assert!(txn1.get(&key2).is_ok());
+        Ok(results)
+    }
 
-            txn1.set(&key1, &value3).unwrap();
+    /// Reads the live value of a key, with its index key, at `version`, with the version and
+    /// the timestamp of the value. The versions committed after `ts` are skipped, walking the
+    /// versions of the key down to the one committed at or before it.
+    fn read_at_version(
+        &self,
+        key: &[u8],
+        index_key: &VariableSizeKey,
+        version: u64,
+        ts: u64,
+    ) -> Result<Option<(Vec<u8>, u64, u64)>> {
+        self.core.invariants.check()?;
+        let mut version = version.min(self.snapshot.as_ref().unwrap().read().version());
+        let indexer = self.core.indexer.read();
+        let (value, version, ts) = loop {
+            let Some((value, found, found_ts)) = indexer.get_at(index_key, version)? else {
+                return Ok(None);
+            };
+            if found_ts <= ts {
+                break (value, found, found_ts);
+            }
+            version = found - 1;
+        };
+        drop(indexer);
 
-            let range = "k1".as_bytes()..="k3".as_bytes();
-            let res = txn2.scan(range.clone(), None).unwrap();
-            assert_eq!(res.len(), 2);
-            assert_eq!(res[0].1, value1);
+        Ok(self
+            .resolve_version(key, version, &value)?
+            .map(|value| (value, version, ts)))
+    }
 
-            txn1.set(&key1, &value4).unwrap();
-            txn1.commit().await.unwrap();
+    /// Returns the value of a key at each version of it the index retains, with the commit
+    /// timestamp of the version, oldest first. A deletion is returned as a value of `None`.
+    /// The versions are the ones `get_at_version` reads, and the ones dropped by
+    /// `Store::shrink_index` are not returned.
+    pub fn history(&self, key: &[u8]) -> Result<Vec<(u64, Option<Vec<u8>>)>> {
+        if self.closed {
+            return Err(Error::TransactionClosed);
+        }
+        if key.is_empty() {
+            return Err(Error::EmptyKey);
+        }
+        if self.mode.is_write_only() {
+            return Err(Error::TransactionWriteOnly);
+        }
+        self.core.invariants.check()?;
 
-            let res = txn2.scan(range, None).unwrap();
-            assert_eq!(res.len(), 2);
-            assert_eq!(res[0].1, value1);
+        let Some(index_key) = self.core.keys.lookup(key) else {
+            return Ok(Vec::new());
+        };
+        let mut versions = Vec::new();
+        let mut version = self.snapshot.as_ref().unwrap().read().version();
+        let indexer = self.core.indexer.read();
+        while let Some((value, found, ts)) = indexer.get_at(&index_key, version)? {
+            versions.push((value, found, ts));
+            version = found - 1;
+        }
+        drop(indexer);
 
-            txn2.commit().await.unwrap();
+        versions
+            .into_iter()
+            .rev()
+            .map(|(value, version, ts)| Ok((ts, self.resolve_version(key, version, &value)?)))
+            .collect()
+    }
+
+    /// Decodes the value of a key at `version`, resolving its merge operands, or returns None
+    /// if the key is deleted at that version.
+    fn resolve_version(&self, key: &[u8], version: u64, value: &Bytes) -> Result<Option<Vec<u8>>> {
+        let mut val_ref = ValueRef::new(self.core.clone());
+        val_ref.decode(version, value)?;
+        if val_ref.key_value_metadata().is_some_and(|md| md.deleted()) {
+            return Ok(None);
         }
+        val_ref.resolve_merged(key, None).map(Some)
     }
 
-    #[tokio::test]
-    async fn g1b() {
-        g1b_tests(false).await; // snapshot isolation
-        g1b_tests(true).await; // serializable snapshot isolation
+    /// Returns the commit timestamp of the latest version of a key in the snapshot, deleted or
+    /// not, or 0 if it has none. The read is recorded for conflict detection as `get` records it.
+    pub(crate) fn latest_ts(&self, key: &[u8]) -> Result<u64> {
+        self.get(key)?;
+        let Some(index_key) = self.core.keys.lookup(key) else {
+            return Ok(0);
+        };
+        let version = self.snapshot.as_ref().unwrap().read().version();
+        let latest = self.core.indexer.read().get_at(&index_key, version)?;
+        Ok(latest.map_or(0, |(_, _, ts)| ts))
     }
 
-    // G1c: Circular Information Flow (dirty reads)
-    async fn g1c_tests(is_ssi: bool) {
-        let store = create_hermitage_store(is_ssi).await;
+    /// Returns the token of the commit of the transaction, once it is committed, or None if it
+    /// is not committed or wrote nothing. See [`Store::wait_for_version`](crate::Store::wait_for_version).
+    pub fn commit_token(&self) -> Option<CommitToken> {
+        self.commit_token
+    }
 
-        let key1 = Bytes::from("k1");
-        let key2 = Bytes::from("k2");
-        let value1 = Bytes::from("v1");
-        let value2 = Bytes::from("v2");
-        let value3 = Bytes::from("v3");
-        let value4 = Bytes::from("v4");
+    /// Commits the transaction, by writing all pending entries to the store.
+    pub async fn commit(&mut self) -> Result<()> {
+        self.prepare_at(None, None).await?.commit().await
+    }
 
-        {
-            let mut txn1 = store.begin().unwrap();
-            let mut txn2 = store.begin().unwrap();
+    /// Commits the transaction at the given version rather than at the next version of the
+    /// store, as a replica applying the transactions of another store does to keep their
+    /// versions. The version must be after the last version of the store, but can skip
+    /// versions; the transactions committed next get versions after it.
+    ///
+    /// It returns `Error::NonMonotonicVersion` if the store is already at or past `version`,
+    /// and `Error::VersionOutOfRange` for `u64::MAX`, and the transaction can then still be
+    /// committed or rolled back.
+    pub async fn commit_at(&mut self, version: u64) -> Result<()> {
+        self.prepare_at(Some(version), None).await?.commit().await
+    }
 
-            assert!(txn1.get(&key1).is_ok());
-            assert!(txn2.get(&key2).is_ok());
+    /// Prepares the commit of the transaction: it is validated against the transactions
+    /// committed since it began, and given its commit timestamp, but nothing is written yet.
+    ///
+    /// The returned `PreparedTransaction` holds the commit lock of the store, so that no other
+    /// transaction commits in between, until it is committed or dropped. Dropping it aborts
+    /// the commit and rolls the transaction back. The lock is released if it is held for
+    /// longer than `Options::prepare_timeout`, and the commit then fails with
+    /// `Error::PrepareTimedOut`.
+    ///
+    /// A commit which fails to be written, or times out, leaves the transaction open: it can
+    /// be committed again, and is checked for conflicts again.
+    pub async fn prepare(&mut self) -> Result<PreparedTransaction<'_>> {
+        let timeout = match self.core.opts.prepare_timeout {
+            0 => None,
+            millis => Some(Duration::from_millis(millis)),
+        };
+        self.prepare_at(None, timeout).await
+    }
 
-            txn1.set(&key1, &value3).unwrap();
-            txn2.set(&key2, &value4).unwrap();
+    /// Prepares the commit of the transaction, at the given version if any, releasing the
+    /// commit lock after the given timeout if any.
+    async fn prepare_at(
+        &mut self,
+        version: Option<u64>,
+        timeout: Option<Duration>,
+    ) -> Result<PreparedTransaction<'_>> {
+        // If the transaction is closed, return an error.
+        if self.closed {
+            return Err(Error::TransactionClosed);
+        }
 
-            assert_eq!(txn1.get(&key2).unwrap().unwrap(), value2.as_ref());
-            assert_eq!(txn2.get(&key1).unwrap().unwrap(), value1.as_ref());
+        // If the transaction is read-only, return an error.
+        if self.mode.is_read_only() {
+            return Err(Error::TransactionReadOnly);
+        }
+        self.core.invariants.check()?;
+        self.core.fsync.check()?;
 
-            txn1.commit().await.unwrap();
-            assert!(match txn2.commit().await {
-                Err(err) => {
-                    matches!(err, Error::TransactionReadConflict)
-                }
-                _ => false,
+        // If there are no pending writes, there's nothing to commit, so return early.
+        if !self.has_writes() {
+            return Ok(PreparedTransaction {
+                txn: self,
+                prepared: None,
             });
         }
-    }
-
-    #[tokio::test]
-    async fn g1c() {
-        g1c_tests(false).await; // snapshot isolation
-        g1c_tests(true).await;
-    }
 
-    // PMP: Predicate-Many-Preceders
-    async fn pmp_tests(is_ssi: bool) {
-        let store = create_hermitage_store(is_ssi).await;
+        // Create a vector of entries from the write set, compressing the values according to
+        // the compression rules. This is done before taking the commit lock to keep it short.
+        let mut entries: Vec<Entry> = self
+            .write_set
+            .iter()
+            .map(|(_, entry)| entry.clone())
+            .collect();
+        self.core.write_audit.check(&self.core, &entries);
+        self.core.compressor.compress_entries(&mut entries)?;
 
-        let key3 = Bytes::from("k3");
-        let value1 = Bytes::from("v1");
-        let value2 = Bytes::from("v2");
-        let value3 = Bytes::from("v3");
+        // Lock the oracle to serialize commits to the transaction log.
+        let write_ch_lock = self.core.oracle.write_lock.clone().lock_owned().await;
 
+        // The counters and the keys of the range deletes are read from the index, which must
+        // hold the transactions committed before this one.
+        if !self.increments.is_empty()
+            || entries.iter().any(|e| e.is_range_delete() || e.is_clear())
         {
-            let txn1 = store.begin().unwrap();
-            let mut txn2 = store.begin().unwrap();
-
-            // k3 should not be visible to txn1
-            let range = "k1".as_bytes()..="k3".as_bytes();
-            let res = txn1.scan(range.clone(), None).unwrap();
-            assert_eq!(res.len(), 2);
-            assert_eq!(res[0].1, value1);
-            assert_eq!(res[1].1, value2);
+            self.core.oracle.wait_indexed().await;
+        }
 
-            // k3 is committed by txn2
-            txn2.set(&key3, &value3).unwrap();
-            txn2.commit().await.unwrap();
+        // The increments are applied to the latest values of the counters, which no other
+        // transaction can change while the commit lock is held. They are kept until the commit
+        // is prepared, so that a commit retried after it failed applies them again.
+        if !self.increments.is_empty() {
+            let counters = self.counters()?;
+            entries.retain(|entry| !self.increments.contains_key(&entry.key));
+            let mut compressed = counters.clone();
+            self.core.compressor.compress_entries(&mut compressed)?;
+            entries.extend(compressed);
+            counters
+                .into_iter()
+                .for_each(|counter| self.push_write(counter));
+        }
 
-            // k3 should still not be visible to txn1
-            let range = "k1".as_bytes()..="k3".as_bytes();
-            let res = txn1.scan(range.clone(), None).unwrap();
-            assert_eq!(res.len(), 2);
-            assert_eq!(res[0].1, value1);
-            assert_eq!(res[1].1, value2);
+        // The keys the range deletes delete are listed for the transactions checked for
+        // conflicts against this one. The index holds the transactions committed before it, as
+        // they were waited for.
+        if entries.iter().any(|e| e.is_range_delete()) {
+            let fields = entries
+                .iter()
+                .map(|e| (&e.key, e.metadata.as_ref(), &e.value));
+            self.range_deleted = range_delete::deleted_keys(&self.core.indexer.read(), fields)?;
+        } else if entries.iter().any(|e| e.is_clear()) {
+            // A clear deletes all the keys.
+            let all = (Bound::Unbounded, Bound::Unbounded);
+            let keys = self.core.indexer.read().live_keys(all)?;
+            self.range_deleted = keys.into_iter().map(Bytes::from).collect();
         }
+
+        // Prepare for the commit by getting a transaction ID and a commit timestamp.
+        let (tx_id, commit_ts) = self.prepare_commit(version)?;
+        entries.iter_mut().for_each(|entry| entry.ts = commit_ts);
+        let oracle = self.core.oracle.clone();
+
+        Ok(PreparedTransaction {
+            txn: self,
+            prepared: Some(Prepared {
+                entries,
+                tx_id,
+                commit_ts,
+                sent: false,
+                written: false,
+                write_ch_lock: CommitLock::new(write_ch_lock, timeout, oracle, tx_id),
+            }),
+        })
     }
 
-    #[tokio::test]
-    async fn pmp() {
-        pmp_tests(false).await;
-        pmp_tests(true).await;
+    /// Prepares for the commit by assigning commit timestamps and preparing records.
+    fn prepare_commit(&mut self, version: Option<u64>) -> Result<(u64, u64)> {
+        let oracle = self.core.oracle.clone();
+        let tx_id = oracle.new_commit_ts(self, version)?;
+        let commit_ts = self.assign_commit_ts();
+        Ok((tx_id, commit_ts))
     }
 
-    // PMP-Write: Circular Information Flow (dirty reads)
-    async fn pmp_write_tests(is_ssi: bool) {
-        let store = create_hermitage_store(is_ssi).await;
+    /// Assigns commit timestamps to transaction entries.
+    fn assign_commit_ts(&mut self) -> u64 {
+        let commit_ts = self.core.clock.next();
+        self.write_set.iter_mut().for_each(|(_, entry)| {
+            entry.ts = commit_ts;
+        });
+        commit_ts
+    }
 
-        let key1 = Bytes::from("k1");
-        let key2 = Bytes::from("k2");
-        let value1 = Bytes::from("v1");
-        let value2 = Bytes::from("v2");
-        let value3 = Bytes::from("v3");
+    /// Rolls back the transaction by removing all updated entries.
+    pub fn rollback(&mut self) {
+        self.closed = true;
+        self.committed_values_offsets.clear();
+        self.buf.clear();
+        self.write_set.clear();
+        self.increments.clear();
+        self.deleted_ranges.clear();
+        self.range_deleted.clear();
+        self.read_set.lock().clear();
+        self.snapshot.take();
 
-        {
-            let mut txn1 = store.begin().unwrap();
-            let mut txn2 = store.begin().unwrap();
+        let stats = &self.core.stats;
+        stats.memory_sub(&stats.transaction_bytes, self.write_set_bytes);
+        self.write_set_bytes = 0;
+        self.slot.take();
+        self.core.active_transactions.unregister(self.id);
+    }
+}
 
-            assert!(txn1.get(&key1).is_ok());
-            txn1.set(&key1, &value3).unwrap();
-
-            let range = "k1".as_bytes()..="k2".as_bytes();
-            let res = txn2.scan(range.clone(), None).unwrap();
-            assert_eq!(res.len(), 2);
-            assert_eq!(res[0].1, value1);
-            assert_eq!(res[1].1, value2);
-
-            txn2.delete(&key2).unwrap();
-            txn1.commit().await.unwrap();
+/// What a prepared transaction writes when it is committed.
+struct Prepared {
+    entries: Vec<Entry>,
+    tx_id: u64,
+    commit_ts: u64,
+    sent: bool,
+    written: bool,
+    write_ch_lock: CommitLock,
+}
 
-            let range = "k1".as_bytes()..="k3".as_bytes();
-            let res = txn2.scan(range.clone(), None).unwrap();
-            assert_eq!(res.len(), 1);
-            assert_eq!(res[0].1, value1);
+/// The commit lock held by a prepared transaction. If a timeout is given, a watchdog thread
+/// releases the lock once it is held for longer before the commit starts, and the
+/// transaction is then abandoned.
+struct CommitLock {
+    // The lock while the watchdog may release it.
+    watched: Arc<Mutex<Option<OwnedMutexGuard<()>>>>,
+    // The lock once the commit started.
+    held: Option<OwnedMutexGuard<()>>,
+    // Stops the watchdog thread when dropped.
+    stop: Option<Sender<()>>,
+}
 
-            assert!(match txn2.commit().await {
-                Err(err) => {
-                    matches!(err, Error::TransactionReadConflict)
+impl CommitLock {
+    fn new(
+        guard: OwnedMutexGuard<()>,
+        timeout: Option<Duration>,
+        oracle: Arc<Oracle>,
+        tx_id: u64,
+    ) -> Self {
+        let watched = Arc::new(Mutex::new(Some(guard)));
+        let stop = timeout.map(|timeout| {
+            let (stop_tx, stop_rx) = bounded::<()>(0);
+            let guard = watched.clone();
+            thread::spawn(move || {
+                if let Err(RecvTimeoutError::Timeout) = stop_rx.recv_timeout(timeout) {
+                    let mut guard = guard.lock();
+                    if guard.is_some() {
+                        // The transactions committing next are not checked against this one,
+                        // nor wait for it.
+                        oracle.abandoned(tx_id);
+                        oracle.committed_upto(tx_id);
+                        guard.take();
+                    }
                 }
-                _ => false,
             });
+            stop_tx
+        });
+        Self {
+            watched,
+            held: None,
+            stop,
         }
     }
 
-    #[tokio::test]
-    async fn pmp_write() {
-        pmp_write_tests(false).await;
-        pmp_write_tests(true).await;
+    /// Takes the lock from the watchdog as the commit starts, or fails if the watchdog
+    /// released it.
+    fn hold(&mut self) -> Result<()> {
+        if self.held.is_none() {
+            self.held = Some(self.watched.lock().take().ok_or(Error::PrepareTimedOut)?);
+            self.stop.take();
+        }
+        Ok(())
     }
 
-    // P4: Lost Update
-    async fn p4_tests(is_ssi: bool) {
-        let store = create_hermitage_store(is_ssi).await;
-
-        let key1 = Bytes::from("k1");
-        let value3 = Bytes::from("v3");
-
-        {
-            let mut txn1 = store.begin().unwrap();
-            let mut txn2 = store.begin().unwrap();
-
-            assert!(txn1.get(&key1).is_ok());
-            assert!(txn2.get(&key1).is_ok());
+    /// Releases the lock.
+    fn release(&mut self) {
+        self.held.take();
+        self.watched.lock().take();
+    }
+}
 
-            txn1.set(&key1, &value3).unwrap();
-            txn2.set(&key1, &value3).unwrap();
+impl Drop for CommitLock {
+    fn drop(&mut self) {
+        // The lock is released now rather than when the watchdog thread stops.
+        self.release();
+    }
+}
 
-            txn1.commit().await.unwrap();
+/// A transaction whose commit is prepared, returned by
+/// [`Transaction::prepare`](Transaction::prepare).
+///
+/// Aborting a prepared transaction, by dropping it, may make the transactions running
+/// concurrently fail with a conflict as if it had committed.
+pub struct PreparedTransaction<'a> {
+    txn: &'a mut Transaction,
+    prepared: Option<Prepared>,
+}
 
-            assert!(match txn2.commit().await {
-                Err(err) => {
-                    matches!(err, Error::TransactionReadConflict)
-                }
-                _ => false,
-            });
+impl PreparedTransaction<'_> {
+    /// Writes the entries of the transaction to the store.
+    pub async fn commit(mut self) -> Result<()> {
+        if let Some(done) = self.send().await? {
+            // The commit lock is released once the entries are sent to the writer, rather than
+            // held while they are written to the commit log: the transactions committing
+            // meanwhile are validated against them, see `Oracle::sent`.
+            if let Some(prepared) = &mut self.prepared {
+                prepared.write_ch_lock.release();
+            }
+            self.wait(done).await?;
         }
+        self.finish();
+        Ok(())
     }
 
-    #[tokio::test]
-    async fn p4() {
-        p4_tests(false).await;
-        p4_tests(true).await;
+    /// Writes the entries of the transaction to the store, keeping the commit lock until the
+    /// transaction is dropped.
+    pub(crate) async fn write(&mut self) -> Result<()> {
+        if let Some(done) = self.send().await? {
+            self.wait(done).await?;
+        }
+        Ok(())
     }
 
-    // G-single: Single Anti-dependency Cycles (read skew)
-    async fn g_single_tests(is_ssi: bool) {
-        let store = create_hermitage_store(is_ssi).await;
-
-        let key1 = Bytes::from("k1");
-        let key2 = Bytes::from("k2");
-        let value1 = Bytes::from("v1");
-        let value2 = Bytes::from("v2");
-        let value3 = Bytes::from("v3");
-        let value4 = Bytes::from("v4");
+    /// Sends the entries of the transaction to the writer, and returns the channel its result
+    /// is sent on, or None if there is nothing to write.
+    async fn send(&mut self) -> Result<Option<Receiver<Result<()>>>> {
+        let prepared = match &mut self.prepared {
+            Some(prepared) if !prepared.written => prepared,
+            _ => return Ok(None),
+        };
+        let txn = &mut *self.txn;
 
-        {
-            let mut txn1 = store.begin().unwrap();
-            let mut txn2 = store.begin().unwrap();
+        // The watchdog no longer releases the lock once the entries are sent.
+        if let Err(err) = prepared.write_ch_lock.hold() {
+            self.abandon();
+            return Err(err);
+        }
 
-            assert_eq!(txn1.get(&key1).unwrap().unwrap(), value1.as_ref());
-            assert_eq!(txn2.get(&key1).unwrap().unwrap(), value1.as_ref());
-            assert_eq!(txn2.get(&key2).unwrap().unwrap(), value2.as_ref());
-            txn2.set(&key1, &value3).unwrap();
-            txn2.set(&key2, &value4).unwrap();
+        // The transactions committing before this one is indexed check their reads against it.
+        let keys = txn
+            .write_set
+            .iter()
+            .map(|(key, _)| key.clone())
+            .chain(txn.range_deleted.iter().cloned())
+            .collect();
+        txn.core.oracle.sent(prepared.tx_id, keys);
 
-            txn2.commit().await.unwrap();
+        // Commit the changes to the store index.
+        let done = txn
+            .core
+            .send_to_write_channel(
+                std::mem::take(&mut prepared.entries),
+                prepared.tx_id,
+                prepared.commit_ts,
+                txn.durability,
+            )
+            .await;
 
-            assert_eq!(txn1.get(&key2).unwrap().unwrap(), value2.as_ref());
-            txn1.commit().await.unwrap();
+        if let Err(err) = done {
+            self.abandon();
+            return Err(err);
         }
+        prepared.sent = true;
+        done.map(Some)
     }
 
-    #[tokio::test]
-    async fn g_single() {
-        g_single_tests(false).await;
-        g_single_tests(true).await;
+    /// Waits for the entries sent to be written to the transaction log and the index.
+    async fn wait(&mut self, done: Receiver<Result<()>>) -> Result<()> {
+        if let Err(err) = done.recv().await? {
+            self.abandon();
+            return Err(err);
+        }
+        if let Some(prepared) = &mut self.prepared {
+            prepared.written = true;
+        }
+        Ok(())
     }
 
-    // G-single-write-1: Single Anti-dependency Cycles (read skew)
-    async fn g_single_write_1_tests(is_ssi: bool) {
-        let store = create_hermitage_store(is_ssi).await;
+    /// Abandons the commit of a transaction which was not written, leaving the transaction
+    /// open so that its commit can be retried.
+    fn abandon(&mut self) {
+        let Some(prepared) = self.prepared.take() else {
+            return;
+        };
+        let oracle = &self.txn.core.oracle;
 
-        let key1 = Bytes::from("k1");
-        let key2 = Bytes::from("k2");
-        let value1 = Bytes::from("v1");
-        let value2 = Bytes::from("v2");
-        let value3 = Bytes::from("v3");
-        let value4 = Bytes::from("v4");
+        // The writer marks the transactions it was sent as indexed, written or not.
+        if !prepared.sent {
+            oracle.indexed(prepared.tx_id);
+        }
+        oracle.abandoned(prepared.tx_id);
+        oracle.committed_upto(prepared.tx_id);
+    }
 
-        {
-            let mut txn1 = store.begin().unwrap();
-            let mut txn2 = store.begin().unwrap();
+    /// Releases the commit lock of a written transaction, and closes it.
+    fn finish(&mut self) {
+        let Some(prepared) = self.prepared.take() else {
+            return;
+        };
+        let txn = &mut *self.txn;
 
-            assert_eq!(txn1.get(&key1).unwrap().unwrap(), value1.as_ref());
+        drop(prepared.write_ch_lock);
 
-            let range = "k1".as_bytes()..="k2".as_bytes();
-            let res = txn2.scan(range.clone(), None).unwrap();
-            assert_eq!(res.len(), 2);
-            assert_eq!(res[0].1, value1);
-            assert_eq!(res[1].1, value2);
+        // Update the oracle to indicate that the transaction has been committed up to the given transaction ID.
+        txn.core.oracle.committed_upto(prepared.tx_id);
+        txn.core.oracle.read_done(txn.read_ts);
+        txn.increments.clear();
+
+        // Mark the transaction as closed, and free its slot.
+        txn.commit_token = Some(CommitToken::new(prepared.tx_id));
+        txn.closed = true;
+        txn.slot.take();
+        txn.core.active_transactions.unregister(txn.id);
+    }
 
-            txn2.set(&key1, &value3).unwrap();
-            txn2.set(&key2, &value4).unwrap();
+    /// Returns the entries the transaction writes, or none if it writes nothing.
+    pub(crate) fn entries(&self) -> &[Entry] {
+        self.prepared.as_ref().map_or(&[], |p| &p.entries[..])
+    }
 
-            txn2.commit().await.unwrap();
+    /// Returns the version the transaction writes, if it writes anything. The value of each
+    /// key it writes can be read back at this version with `Transaction::get_at_version`.
+    pub fn version(&self) -> Option<u64> {
+        self.prepared.as_ref().map(|p| p.tx_id)
+    }
 
-            txn1.delete(&key2).unwrap();
-            assert!(txn1.get(&key2).unwrap().is_none());
-            assert!(match txn1.commit().await {
-                Err(err) => {
-                    matches!(err, Error::TransactionReadConflict)
-                }
-                _ => false,
-            });
+    /// Adds an entry to write along with the entries of the transaction.
+    pub(crate) fn add_entry(&mut self, mut entry: Entry) {
+        if let Some(prepared) = &mut self.prepared {
+            entry.ts = prepared.commit_ts;
+            prepared.entries.push(entry);
         }
     }
 
-    #[tokio::test]
-    async fn g_single_write_1() {
-        g_single_write_1_tests(false).await;
-        g_single_write_1_tests(true).await;
+    /// Returns the store the transaction commits to.
+    pub(crate) fn core(&self) -> &Arc<Core> {
+        &self.txn.core
     }
+}
 
-    // G-single-write-2: Single Anti-dependency Cycles (read skew)
-    async fn g_single_write_2_tests(is_ssi: bool) {
-        let store = create_hermitage_store(is_ssi).await;
-
-        let key1 = Bytes::from("k1");
-        let key2 = Bytes::from("k2");
-        let value1 = Bytes::from("v1");
-        let value2 = Bytes::from("v2");
-        let value3 = Bytes::from("v3");
-        let value4 = Bytes::from("v4");
+impl Drop for PreparedTransaction<'_> {
+    fn drop(&mut self) {
+        match &self.prepared {
+            Some(prepared) if prepared.written => self.finish(),
+            Some(_) => {
+                self.abandon();
+                self.txn.core.oracle.read_done(self.txn.read_ts);
+                self.txn.rollback();
+            }
+            None => {}
+        }
+    }
+}
 
-        {
-            let mut txn1 = store.begin().unwrap();
-            let mut txn2 = store.begin().unwrap();
+/// Size of the value of a counter, see `Transaction::incr`.
+const COUNTER_SIZE: usize = 8;
 
-            assert_eq!(txn1.get(&key1).unwrap().unwrap(), value1.as_ref());
-            let range = "k1".as_bytes()..="k2".as_bytes();
-            let res = txn2.scan(range.clone(), None).unwrap();
-            assert_eq!(res.len(), 2);
-            assert_eq!(res[0].1, value1);
-            assert_eq!(res[1].1, value2);
+/// Adds `delta` to the value of a counter, which is 0 if the key is missing.
+fn add_to_counter(value: Option<&[u8]>, delta: i128) -> Result<u64> {
+    let current = match value {
+        Some(value) => {
+            let value = value.try_into().map_err(|_| Error::InvalidCounter)?;
+            u64::from_be_bytes(value)
+        }
+        None => 0,
+    };
+    u64::try_from(i128::from(current) + delta).map_err(|_| Error::CounterOverflow)
+}
 
-            txn2.set(&key1, &value3).unwrap();
+/// Returns the time, in nanoseconds since the Unix epoch, at which `ttl` elapses from now, by
+/// `Options::expiry_clock`.
+fn expiry(core: &Core, ttl: Duration) -> u64 {
+    core.opts
+        .expiry_clock
+        .now()
+        .saturating_add(u64::try_from(ttl.as_nanos()).unwrap_or(u64::MAX))
+}
 
-            txn1.delete(&key2).unwrap();
+/// Resolves the value a rename entry moves (see `Entry::mark_rename`): the value of the key
+/// `from` it renames, as of `version`.
+pub(crate) fn resolve_renamed(
+    core: &Arc<Core>,
+    from: &[u8],
+    version: u64,
+    deadline: Option<Instant>,
+) -> Result<Vec<u8>> {
+    let found = match core.keys.lookup(from) {
+        Some(key) => core.indexer.read().get_at(&key, version)?,
+        None => None,
+    };
+    // The key was read when it was renamed: the rename conflicts if it is gone since.
+    let Some((value, version, _)) = found else {
+        return Err(Error::TransactionReadConflict);
+    };
+    let mut val_ref = ValueRef::new(core.clone());
+    val_ref.decode(version, &value)?;
+    val_ref.resolve_by(deadline)
+}
 
-            txn2.set(&key2, &value4).unwrap();
+/// Returns the timestamp a read of `val_ref` records in the read set: 0 if the value expired,
+/// as the checks of the conflicts on commit read the expired keys as missing ones.
+fn read_ts(core: &Core, val_ref: &dyn Value) -> u64 {
+    match is_expired(
+        core,
+        val_ref.key_value_metadata().and_then(|md| md.expires_at()),
+    ) {
+        true => 0,
+        false => val_ref.ts(),
+    }
+}
 
-            drop(txn1);
+/// Converts a range of keys to a range of null-terminated index keys.
+pub(crate) fn to_key_range<'b, R>(range: &R) -> (Bound<VariableSizeKey>, Bound<VariableSizeKey>)
+where
+    R: RangeBounds<&'b [u8]>,
+{
+    (
+        match range.start_bound() {
+            Bound::Included(start) => {
+                Bound::Included(VariableSizeKey::from_slice_with_termination(start))
+            }
+            Bound::Excluded(start) => {
+                Bound::Excluded(VariableSizeKey::from_slice_with_termination(start))
+            }
+            Bound::Unbounded => Bound::Unbounded,
+        },
+        match range.end_bound() {
+            Bound::Included(end) => {
+                Bound::Included(VariableSizeKey::from_slice_with_termination(end))
+            }
+            Bound::Excluded(end) => {
+                Bound::Excluded(VariableSizeKey::from_slice_with_termination(end))
+            }
+            Bound::Unbounded => Bound::Unbounded,
+        },
+    )
+}
 
-            txn2.commit().await.unwrap();
-        }
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        self.rollback();
     }
+}
 
-    #[tokio::test]
-    async fn g_single_write_2() {
-        g_single_write_2_tests(false).await;
-        g_single_write_2_tests(true).await;
-    }
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use std::mem::size_of;
 
-    async fn g2_item_tests(is_ssi: bool) {
-        let store = create_hermitage_store(is_ssi).await;
+    use super::*;
+    use crate::storage::kv::option::{IsolationLevel, Options};
+    use crate::storage::kv::store::Store;
 
-        let key1 = Bytes::from("k1");
-        let key2 = Bytes::from("k2");
-        let value1 = Bytes::from("v1");
-        let value2 = Bytes::from("v2");
-        let value3 = Bytes::from("v3");
-        let value4 = Bytes::from("v4");
+    use tempdir::TempDir;
 
-        {
-            let mut txn1 = store.begin().unwrap();
-            let mut txn2 = store.begin().unwrap();
+    fn create_temp_directory() -> TempDir {
+        TempDir::new("test").unwrap()
+    }
 
-            let range = "k1".as_bytes()..="k2".as_bytes();
-            let res = txn1.scan(range.clone(), None).unwrap();
-            assert_eq!(res.len(), 2);
-            assert_eq!(res[0].1, value1);
-            assert_eq!(res[1].1, value2);
+    // Common setup logic for creating a store
+    fn create_store(is_ssi: bool) -> (Store, TempDir) {
+        let temp_dir = create_temp_directory();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        if is_ssi {
+            opts.isolation_level = IsolationLevel::SerializableSnapshotIsolation;
+        }
+        (
+            Store::new(opts.clone()).expect("should create store"),
+            temp_dir,
+        )
+    }
 
-            let res = txn2.scan(range.clone(), None).unwrap();
-            assert_eq!(res.len(), 2);
-            assert_eq!(res[0].1, value1);
-            assert_eq!(res[1].1, value2);
+    #[tokio::test]
+    async fn basic_transaction() {
+        let (store, temp_dir) = create_store(false);
 
-            txn1.set(&key1, &value3).unwrap();
-            txn2.set(&key2, &value4).unwrap();
+        // Define key-value pairs for the test
+        let key1 = Bytes::from("foo1");
+        let key2 = Bytes::from("foo2");
+        let value1 = Bytes::from("baz");
+        let value2 = Bytes::from("bar");
 
+        {
+            // Start a new read-write transaction (txn1)
+            let mut txn1 = store.begin().unwrap();
+            txn1.set(&key1, &value1).unwrap();
+            txn1.set(&key2, &value1).unwrap();
             txn1.commit().await.unwrap();
+        }
 
-            assert!(match txn2.commit().await {
-                Err(err) => {
-                    matches!(err, Error::TransactionReadConflict)
-                }
-                _ => false,
-            });
-        }
-    }
-
-    #[tokio::test]
-    async fn g2_item() {
-        g2_item_tests(false).await;
-        g2_item_tests(true).await;
-    }
-
-    fn require_send<T: Send>(_: T) {}
-    fn require_sync<T: Sync + Send>(_: T) {}
-
-    #[tokio::test]
-    async fn is_send_sync() {
-        let (db, _) = create_store(false);
-
-        let txn = db.begin().unwrap();
-        require_send(txn);
-
-        let txn = db.begin().unwrap();
-        require_sync(txn);
-    }
-
-    #[tokio::test]
-    async fn max_transaction_entries_limit_exceeded() {
-        let temp_dir = create_temp_directory();
-        let mut opts = Options::new();
-        opts.dir = temp_dir.path().to_path_buf();
-        opts.max_entries_per_txn = 5;
-
-        let store = Store::new(opts.clone()).expect("should create store");
-
-        let num_keys = 6;
-        let mut keys = Vec::new();
-
-        for (counter, _) in (1..=num_keys).enumerate() {
-            // Convert the counter to Bytes
-            let key_bytes = Bytes::from(counter.to_le_bytes().to_vec());
-
-            // Add the key to the vector
-            keys.push(key_bytes);
-        }
-
-        let default_value = Bytes::from("default_value".to_string());
-
-        // Writing the 6th key should fail
-        let mut txn = store.begin().unwrap();
-
-        for (i, key) in keys.iter().enumerate() {
-            // Start a new write transaction
-            if i < 5 {
-                assert!(txn.set(key, &default_value).is_ok());
-            } else {
-                assert!(txn.set(key, &default_value).is_err());
-            }
+        {
+            // Start a read-only transaction (txn3)
+            let txn3 = store.begin().unwrap();
+            let val = txn3.get(&key1).unwrap().unwrap();
+            assert_eq!(val, value1.as_ref());
         }
-    }
-
-    const ENTRIES: usize = 400_000;
-    const KEY_SIZE: usize = 24;
-    const VALUE_SIZE: usize = 150;
-    const RNG_SEED: u64 = 3;
 
-    fn fill_slice(slice: &mut [u8], rng: &mut fastrand::Rng) {
-        let mut i = 0;
-        while i + size_of::<u128>() < slice.len() {
-            let tmp = rng.u128(..);
-            slice[i..(i + size_of::<u128>())].copy_from_slice(&tmp.to_le_bytes());
-            i += size_of::<u128>()
-        }
-        if i + size_of::<u64>() < slice.len() {
-            let tmp = rng.u64(..);
-            slice[i..(i + size_of::<u64>())].copy_from_slice(&tmp.to_le_bytes());
-            i += size_of::<u64>()
-        }
-        if i + size_of::<u32>() < slice.len() {
-            let tmp = rng.u32(..);
-            slice[i..(i + size_of::<u32>())].copy_from_slice(&tmp.to_le_bytes());
-            i += size_of::<u32>()
-        }
-        if i + size_of::<u16>() < slice.len() {
-            let tmp = rng.u16(..);
-            slice[i..(i + size_of::<u16>())].copy_from_slice(&tmp.to_le_bytes());
-            i += size_of::<u16>()
-        }
-        if i + size_of::<u8>() < slice.len() {
-            slice[i] = rng.u8(..);
+        {
+            // Start another read-write transaction (txn2)
+            let mut txn2 = store.begin().unwrap();
+            txn2.set(&key1, &value2).unwrap();
+            txn2.set(&key2, &value2).unwrap();
+            txn2.commit().await.unwrap();
         }
-    }
-
-    fn gen_pair(rng: &mut fastrand::Rng) -> ([u8; KEY_SIZE], Vec<u8>) {
-        let mut key = [0u8; KEY_SIZE];
-        fill_slice(&mut key, rng);
-        let mut value = vec![0u8; VALUE_SIZE];
-        fill_slice(&mut value, rng);
-
-        (key, value)
-    }
 
-    fn make_rng() -> fastrand::Rng {
-        fastrand::Rng::with_seed(RNG_SEED)
-    }
+        // Drop the store to simulate closing it
+        store.close().await.unwrap();
 
-    #[tokio::test]
-    #[ignore]
-    async fn insert_large_txn_and_get() {
-        let temp_dir = create_temp_directory();
+        // Create a new Core instance with VariableSizeKey after dropping the previous one
         let mut opts = Options::new();
         opts.dir = temp_dir.path().to_path_buf();
-        opts.max_entries_per_txn = ENTRIES as u32;
-
-        let store = Store::new(opts.clone()).expect("should create store");
-        let mut rng = make_rng();
-
-        let mut txn = store.begin().unwrap();
-        for _ in 0..ENTRIES {
-            let (key, value) = gen_pair(&mut rng);
-            txn.set(&key, &value).unwrap();
-        }
-        txn.commit().await.unwrap();
-        drop(txn);
-
-        // Read the keys from the store
-        let mut rng = make_rng();
-        let txn = store.begin_with_mode(Mode::ReadOnly).unwrap();
-        for _i in 0..ENTRIES {
-            let (key, _) = gen_pair(&mut rng);
-            txn.get(&key).unwrap();
-        }
-    }
-
-    #[tokio::test]
-    async fn empty_scan_should_not_return_an_error() {
-        let (store, _) = create_store(false);
+        let store = Store::new(opts).expect("should create store");
 
-        let range = "key1".as_bytes()..="key3".as_bytes();
+        // Start a read-only transaction (txn4)
+        let txn4 = store.begin().unwrap();
+        let val = txn4.get(&key1).unwrap().unwrap();
 
-        let txn = store.begin().unwrap();
-        let results = txn.scan(range, None).unwrap();
-        assert_eq!(results.len(), 0);
+        // Assert that the value retrieved in txn4 matches value2
+        assert_eq!(val, value2.as_ref());
     }
 
     #[tokio::test]
-    async fn transaction_delete_and_scan_test() {
+    async fn transaction_delete_scan() {
         let (store, _) = create_store(false);
 
         // Define key-value pairs for the test
@@ -1531,19 +2617,23 @@ mod tests {
             // Start a new read-write transaction (txn1)
             let mut txn1 = store.begin().unwrap();
             txn1.set(&key1, &value1).unwrap();
+            txn1.set(&key1, &value1).unwrap();
             txn1.commit().await.unwrap();
         }
 
         {
             // Start a read-only transaction (txn)
             let mut txn = store.begin().unwrap();
-            txn.get(&key1).unwrap();
             txn.delete(&key1).unwrap();
-            let range = "k1".as_bytes()..="k3".as_bytes();
-            let results = txn.scan(range, None).unwrap();
-            assert_eq!(results.len(), 0);
             txn.commit().await.unwrap();
         }
+
+        {
+            // Start another read-write transaction (txn)
+            let txn = store.begin().unwrap();
+            assert!(txn.get(&key1).unwrap().is_none());
+        }
+
         {
             let range = "k1".as_bytes()..="k3".as_bytes();
             let txn = store.begin().unwrap();
@@ -1552,204 +2642,73 @@ mod tests {
         }
     }
 
-    #[tokio::test]
-    async fn sdb_delete_record_id_bug() {
-        let (store, _) = create_store(false);
+    async fn mvcc_tests(is_ssi: bool) {
+        let (store, _) = create_store(is_ssi);
 
-        // Define key-value pairs for the test
-        let key1 = Bytes::copy_from_slice(&[
-            47, 33, 110, 100, 166, 192, 229, 30, 101, 24, 73, 242, 185, 36, 233, 242, 54, 96, 72,
-            52,
-        ]);
-        let key2 = Bytes::copy_from_slice(&[
-            47, 33, 104, 98, 0, 0, 1, 141, 141, 42, 113, 8, 47, 166, 192, 229, 30, 101, 24, 73,
-            242, 185, 36, 233, 242, 54, 96, 72, 52,
-        ]);
+        let key1 = Bytes::from("key1");
+        let key2 = Bytes::from("key2");
         let value1 = Bytes::from("baz");
+        let value2 = Bytes::from("bar");
 
+        // no read conflict
         {
-            // Start a new read-write transaction (txn)
-            let mut txn = store.begin().unwrap();
-            txn.set(&key1, &value1).unwrap();
-            txn.set(&key2, &value1).unwrap();
-            txn.commit().await.unwrap();
+            let mut txn1 = store.begin().unwrap();
+            let mut txn2 = store.begin().unwrap();
+
+            txn1.set(&key1, &value1).unwrap();
+            txn1.commit().await.unwrap();
+
+            assert!(txn2.get(&key2).unwrap().is_none());
+            txn2.set(&key2, &value2).unwrap();
+            txn2.commit().await.unwrap();
         }
 
-        let key3 = Bytes::copy_from_slice(&[47, 33, 117, 115, 114, 111, 111, 116, 0]);
+        // read conflict when the read key was updated by another transaction
         {
-            // Start a new read-write transaction (txn)
-            let mut txn = store.begin().unwrap();
-            txn.set(&key3, &value1).unwrap();
-            txn.commit().await.unwrap();
-        }
+            let mut txn1 = store.begin().unwrap();
+            let mut txn2 = store.begin().unwrap();
 
-        let key4 = Bytes::copy_from_slice(&[47, 33, 117, 115, 114, 111, 111, 116, 0]);
-        let txn1 = store.begin().unwrap();
-        txn1.get(&key4).unwrap();
+            txn1.set(&key1, &value1).unwrap();
+            txn1.commit().await.unwrap();
+
+            assert!(txn2.get(&key1).is_ok());
+            txn2.set(&key1, &value2).unwrap();
+            assert!(match txn2.commit().await {
+                Err(err) => {
+                    matches!(err, Error::TransactionReadConflict)
+                }
+                _ => false,
+            });
+        }
 
+        // blind writes should succeed if key wasn't read first
         {
+            let mut txn1 = store.begin().unwrap();
             let mut txn2 = store.begin().unwrap();
-            txn2.get(&Bytes::copy_from_slice(&[
-                47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 42, 48, 49, 72, 80, 54, 72, 71, 72,
-                50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74, 69, 0, 33, 116,
-                98, 117, 115, 101, 114, 0,
-            ]))
-            .unwrap();
-            txn2.get(&Bytes::copy_from_slice(&[
-                47, 33, 110, 115, 116, 101, 115, 116, 45, 110, 115, 0,
-            ]))
-            .unwrap();
-            txn2.get(&Bytes::copy_from_slice(&[
-                47, 33, 110, 115, 116, 101, 115, 116, 45, 110, 115, 0,
-            ]))
-            .unwrap();
-            txn2.set(
-                &Bytes::copy_from_slice(&[47, 33, 110, 115, 116, 101, 115, 116, 45, 110, 115, 0]),
-                &value1,
-            )
-            .unwrap();
 
-            txn2.get(&Bytes::copy_from_slice(&[
-                47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 33, 100, 98, 48, 49, 72, 80, 54, 72,
-                71, 72, 50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74, 69, 0,
-            ]))
-            .unwrap();
-            txn2.get(&Bytes::copy_from_slice(&[
-                47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 33, 100, 98, 48, 49, 72, 80, 54, 72,
-                71, 72, 50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74, 69, 0,
-            ]))
-            .unwrap();
-            txn2.set(
-                &Bytes::copy_from_slice(&[
-                    47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 33, 100, 98, 48, 49, 72, 80, 54,
-                    72, 71, 72, 50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74,
-                    69, 0,
-                ]),
-                &value1,
-            )
-            .unwrap();
-
-            txn2.get(&Bytes::copy_from_slice(&[
-                47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 42, 48, 49, 72, 80, 54, 72, 71, 72,
-                50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74, 69, 0, 33, 116,
-                98, 117, 115, 101, 114, 0,
-            ]))
-            .unwrap();
-            txn2.get(&Bytes::copy_from_slice(&[
-                47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 42, 48, 49, 72, 80, 54, 72, 71, 72,
-                50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74, 69, 0, 33, 116,
-                98, 117, 115, 101, 114, 0,
-            ]))
-            .unwrap();
-            txn2.set(
-                &Bytes::copy_from_slice(&[
-                    47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 42, 48, 49, 72, 80, 54, 72, 71,
-                    72, 50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74, 69, 0,
-                    33, 116, 98, 117, 115, 101, 114, 0,
-                ]),
-                &value1,
-            )
-            .unwrap();
-
-            txn2.get(&Bytes::copy_from_slice(&[
-                47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 42, 48, 49, 72, 80, 54, 72, 71, 72,
-                50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74, 69, 0, 33, 116,
-                98, 117, 115, 101, 114, 0,
-            ]))
-            .unwrap();
-            txn2.get(&Bytes::copy_from_slice(&[
-                47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 42, 48, 49, 72, 80, 54, 72, 71, 72,
-                50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74, 69, 0, 42, 117,
-                115, 101, 114, 0, 42, 0, 0, 0, 1, 106, 111, 104, 110, 0,
-            ]))
-            .unwrap();
-            txn2.set(
-                &Bytes::copy_from_slice(&[
-                    47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 42, 48, 49, 72, 80, 54, 72, 71,
-                    72, 50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74, 69, 0,
-                    42, 117, 115, 101, 114, 0, 42, 0, 0, 0, 1, 106, 111, 104, 110, 0,
-                ]),
-                &value1,
-            )
-            .unwrap();
-
-            txn2.get(&Bytes::copy_from_slice(&[
-                47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 33, 100, 98, 48, 49, 72, 80, 54, 72,
-                71, 72, 50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74, 69, 0,
-            ]))
-            .unwrap();
+            txn1.set(&key1, &value1).unwrap();
+            txn2.set(&key1, &value2).unwrap();
 
+            txn1.commit().await.unwrap();
             txn2.commit().await.unwrap();
-        }
 
-        {
-            let mut txn3 = store.begin().unwrap();
-            txn3.get(&Bytes::copy_from_slice(&[
-                47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 42, 48, 49, 72, 80, 54, 72, 71, 72,
-                50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74, 69, 0, 42, 117,
-                115, 101, 114, 0, 42, 0, 0, 0, 1, 106, 111, 104, 110, 0,
-            ]))
-            .unwrap();
-            txn3.get(&Bytes::copy_from_slice(&[
-                47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 42, 48, 49, 72, 80, 54, 72, 71, 72,
-                50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74, 69, 0, 33, 116,
-                98, 117, 115, 101, 114, 0,
-            ]))
-            .unwrap();
-            txn3.delete(&Bytes::copy_from_slice(&[
-                47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 42, 48, 49, 72, 80, 54, 72, 71, 72,
-                50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74, 69, 0, 42, 117,
-                115, 101, 114, 0, 42, 0, 0, 0, 1, 106, 111, 104, 110, 0,
-            ]))
-            .unwrap();
-            txn3.get(&Bytes::copy_from_slice(&[
-                47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 33, 100, 98, 48, 49, 72, 80, 54, 72,
-                71, 72, 50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74, 69, 0,
-            ]))
-            .unwrap();
-            txn3.get(&Bytes::copy_from_slice(&[
-                47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 33, 100, 98, 48, 49, 72, 80, 54, 72,
-                71, 72, 50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74, 69, 0,
-            ]))
-            .unwrap();
-            txn3.set(
-                &Bytes::copy_from_slice(&[
-                    47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 33, 100, 98, 48, 49, 72, 80, 54,
-                    72, 71, 72, 50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74,
-                    69, 0,
-                ]),
-                &value1,
-            )
-            .unwrap();
-            txn3.commit().await.unwrap();
+            let txn3 = store.begin().unwrap();
+            let val = txn3.get(&key1).unwrap().unwrap();
+            assert_eq!(val, value2.as_ref());
         }
-    }
-
-    async fn g2_item_predicate(is_ssi: bool) {
-        let store = create_hermitage_store(is_ssi).await;
 
-        let key3 = Bytes::from("k3");
-        let key4 = Bytes::from("k4");
-        let key5 = Bytes::from("k5");
-        let key6 = Bytes::from("k6");
-        let key7 = Bytes::from("k7");
-        let value3 = Bytes::from("v3");
-        let value4 = Bytes::from("v4");
-
-        // inserts into read ranges of already-committed transaction(s) should fail
+        // read conflict when the read key was updated by another transaction
         {
+            let key = Bytes::from("key3");
+
             let mut txn1 = store.begin().unwrap();
             let mut txn2 = store.begin().unwrap();
 
-            let range = "k1".as_bytes()..="k4".as_bytes();
-            txn1.scan(range.clone(), None).unwrap();
-            txn2.scan(range.clone(), None).unwrap();
-
-            txn1.set(&key3, &value3).unwrap();
-            txn2.set(&key4, &value4).unwrap();
-
+            txn1.set(&key, &value1).unwrap();
             txn1.commit().await.unwrap();
 
+            assert!(txn2.get(&key).unwrap().is_none());
+            txn2.set(&key, &value1).unwrap();
             assert!(match txn2.commit().await {
                 Err(err) => {
                     matches!(err, Error::TransactionReadConflict)
@@ -1758,39 +2717,23 @@ mod tests {
             });
         }
 
-        // k1, k2, k3 already committed
-        // inserts beyond scan range should pass
+        // read conflict when the read key was deleted by another transaction
         {
-            let mut txn1 = store.begin().unwrap();
-            let mut txn2 = store.begin().unwrap();
-
-            let range = "k1".as_bytes()..="k3".as_bytes();
-            txn1.scan(range.clone(), None).unwrap();
-            txn2.scan(range.clone(), None).unwrap();
-
-            txn1.set(&key4, &value3).unwrap();
-            txn2.set(&key5, &value4).unwrap();
+            let key = Bytes::from("key4");
 
+            let mut txn1 = store.begin().unwrap();
+            txn1.set(&key, &value1).unwrap();
             txn1.commit().await.unwrap();
-            txn2.commit().await.unwrap();
-        }
 
-        // k1, k2, k3, k4, k5 already committed
-        // inserts in subset scan ranges should fail
-        {
-            let mut txn1 = store.begin().unwrap();
             let mut txn2 = store.begin().unwrap();
+            let mut txn3 = store.begin().unwrap();
 
-            let range = "k1".as_bytes()..="k7".as_bytes();
-            txn1.scan(range.clone(), None).unwrap();
-            let range = "k3".as_bytes()..="k7".as_bytes();
-            txn2.scan(range.clone(), None).unwrap();
-
-            txn1.set(&key6, &value3).unwrap();
-            txn2.set(&key7, &value4).unwrap();
+            txn2.delete(&key).unwrap();
+            assert!(txn2.commit().await.is_ok());
 
-            txn1.commit().await.unwrap();
-            assert!(match txn2.commit().await {
+            assert!(txn3.get(&key).is_ok());
+            txn3.set(&key, &value2).unwrap();
+            assert!(match txn3.commit().await {
                 Err(err) => {
                     matches!(err, Error::TransactionReadConflict)
                 }
@@ -1800,7 +2743,1938 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn g2_predicate() {
-        g2_item_predicate(true).await;
+    async fn mvcc_serialized_snapshot_isolation() {
+        mvcc_tests(true).await;
+    }
+
+    #[tokio::test]
+    async fn mvcc_snapshot_isolation() {
+        mvcc_tests(false).await;
+    }
+
+    #[tokio::test]
+    async fn basic_scan_single_key() {
+        let (store, _) = create_store(false);
+        // Define key-value pairs for the test
+        let keys_to_insert = vec![Bytes::from("key1")];
+
+        for key in &keys_to_insert {
+            let mut txn = store.begin().unwrap();
+            txn.set(key, key).unwrap();
+            txn.commit().await.unwrap();
+        }
+
+        let range = "key1".as_bytes()..="key3".as_bytes();
+
+        let txn = store.begin().unwrap();
+        let results = txn.scan(range, None).unwrap();
+        assert_eq!(results.len(), keys_to_insert.len());
+    }
+
+    #[tokio::test]
+    async fn basic_scan_multiple_keys() {
+        let (store, _) = create_store(false);
+        // Define key-value pairs for the test
+        let keys_to_insert = vec![
+            Bytes::from("key1"),
+            Bytes::from("key2"),
+            Bytes::from("key3"),
+            Bytes::from("lemon"),
+        ];
+
+        for key in &keys_to_insert {
+            let mut txn = store.begin().unwrap();
+            txn.set(key, key).unwrap();
+            txn.commit().await.unwrap();
+        }
+
+        let range = "key1".as_bytes()..="key3".as_bytes();
+
+        let txn = store.begin().unwrap();
+        let results = txn.scan(range, None).unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].1, keys_to_insert[0]);
+        assert_eq!(results[1].1, keys_to_insert[1]);
+        assert_eq!(results[2].1, keys_to_insert[2]);
+    }
+
+    #[tokio::test]
+    async fn scan_multiple_keys_within_single_transaction() {
+        let (store, _) = create_store(false);
+        // Define key-value pairs for the test
+        let keys_to_insert = vec![
+            Bytes::from("test1"),
+            Bytes::from("test2"),
+            Bytes::from("test3"),
+        ];
+
+        let mut txn = store.begin().unwrap();
+        for key in &keys_to_insert {
+            txn.set(key, key).unwrap();
+        }
+        txn.commit().await.unwrap();
+
+        let range = "test1".as_bytes()..="test7".as_bytes();
+
+        let txn = store.begin().unwrap();
+        let results = txn.scan(range, None).unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].0, keys_to_insert[0]);
+        assert_eq!(results[1].0, keys_to_insert[1]);
+        assert_eq!(results[2].0, keys_to_insert[2]);
+        assert_eq!(results[0].1, keys_to_insert[0]);
+        assert_eq!(results[1].1, keys_to_insert[1]);
+        assert_eq!(results[2].1, keys_to_insert[2]);
+    }
+
+    async fn mvcc_with_scan_tests(is_ssi: bool) {
+        let (store, _) = create_store(is_ssi);
+
+        let key1 = Bytes::from("key1");
+        let key2 = Bytes::from("key2");
+        let key3 = Bytes::from("key3");
+        let key4 = Bytes::from("key4");
+        let value1 = Bytes::from("value1");
+        let value2 = Bytes::from("value2");
+        let value3 = Bytes::from("value3");
+        let value4 = Bytes::from("value4");
+        let value5 = Bytes::from("value5");
+        let value6 = Bytes::from("value6");
+
+        // read conflict when scan keys have been updated in another transaction
+        {
+            let mut txn1 = store.begin().unwrap();
+
+            txn1.set(&key1, &value1).unwrap();
+            txn1.commit().await.unwrap();
+
+            let mut txn2 = store.begin().unwrap();
+            let mut txn3 = store.begin().unwrap();
+
+            txn2.set(&key1, &value4).unwrap();
+            txn2.set(&key2, &value2).unwrap();
+            txn2.set(&key3, &value3).unwrap();
+            txn2.commit().await.unwrap();
+
+            let range = "key1".as_bytes()..="key4".as_bytes();
+            let results = txn3.scan(range, None).unwrap();
+            assert_eq!(results.len(), 1);
+            txn3.set(&key2, &value5).unwrap();
+            txn3.set(&key3, &value6).unwrap();
+
+            assert!(match txn3.commit().await {
+                Err(err) => {
+                    matches!(err, Error::TransactionReadConflict)
+                }
+                _ => false,
+            });
+        }
+
+        // read conflict when read keys are deleted by other transaction
+        {
+            let mut txn1 = store.begin().unwrap();
+
+            txn1.set(&key4, &value1).unwrap();
+            txn1.commit().await.unwrap();
+
+            let mut txn2 = store.begin().unwrap();
+            let mut txn3 = store.begin().unwrap();
+
+            txn2.delete(&key4).unwrap();
+            txn2.commit().await.unwrap();
+
+            let range = "key1".as_bytes()..="key5".as_bytes();
+            txn3.scan(range, None).unwrap();
+            txn3.set(&key4, &value2).unwrap();
+
+            assert!(match txn3.commit().await {
+                Err(err) => {
+                    matches!(err, Error::TransactionReadConflict)
+                }
+                _ => false,
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn mvcc_serialized_snapshot_isolation_scan() {
+        mvcc_with_scan_tests(true).await;
+    }
+
+    #[tokio::test]
+    async fn mvcc_snapshot_isolation_scan() {
+        mvcc_with_scan_tests(false).await;
+    }
+
+    #[tokio::test]
+    async fn ryow() {
+        let temp_dir = create_temp_directory();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+
+        let store = Store::new(opts.clone()).expect("should create store");
+        let key1 = Bytes::from("k1");
+        let key2 = Bytes::from("k2");
+        let key3 = Bytes::from("k3");
+        let value1 = Bytes::from("v1");
+        let value2 = Bytes::from("v2");
+
+        // Set a key, delete it and read it in the same transaction. Should return None.
+        {
+            // Start a new read-write transaction (txn1)
+            let mut txn1 = store.begin().unwrap();
+            txn1.set(&key1, &value1).unwrap();
+            txn1.delete(&key1).unwrap();
+            let res = txn1.get(&key1).unwrap();
+            assert!(res.is_none());
+            txn1.commit().await.unwrap();
+        }
+
+        {
+            let mut txn = store.begin().unwrap();
+            txn.set(&key1, &value1).unwrap();
+            txn.commit().await.unwrap();
+        }
+
+        {
+            // Start a new read-write transaction (txn)
+            let mut txn = store.begin().unwrap();
+            txn.set(&key1, &value2).unwrap();
+            assert_eq!(txn.get(&key1).unwrap().unwrap(), value2.as_ref());
+            assert!(txn.get(&key3).unwrap().is_none());
+            txn.set(&key2, &value1).unwrap();
+            assert_eq!(txn.get(&key2).unwrap().unwrap(), value1.as_ref());
+            txn.commit().await.unwrap();
+        }
+    }
+
+    // Common setup logic for creating a store
+    async fn create_hermitage_store(is_ssi: bool) -> Store {
+        let (store, _) = create_store(is_ssi);
+
+        let key1 = Bytes::from("k1");
+        let key2 = Bytes::from("k2");
+        let value1 = Bytes::from("v1");
+        let value2 = Bytes::from("v2");
+        // Start a new read-write transaction (txn)
+        let mut txn = store.begin().unwrap();
+        txn.set(&key1, &value1).unwrap();
+        txn.set(&key2, &value2).unwrap();
+        txn.commit().await.unwrap();
+
+        store
+    }
+
+    // The following tests are taken from hermitage (https://github.com/ept/hermitage)
+    // Specifically, the tests are derived from FoundationDB tests: https://github.com/ept/hermitage/blob/master/foundationdb.md
+
+    // G0: Write Cycles (dirty writes)
+    async fn g0_tests(is_ssi: bool) {
+        let store = create_hermitage_store(is_ssi).await;
+        let key1 = Bytes::from("k1");
+        let key2 = Bytes::from("k2");
+        let value3 = Bytes::from("v3");
+        let value4 = Bytes::from("v4");
+        let value5 = Bytes::from("v5");
+        let value6 = Bytes::from("v6");
+
+        {
+            let mut txn1 = store.begin().unwrap();
+            let mut txn2 = store.begin().unwrap();
+
+            assert!(txn1.get(&key1).is_ok());
+            assert!(txn1.get(&key2).is_ok());
+            assert!(txn2.get(&key1).is_ok());
+            assert!(txn2.get(&key2).is_ok());
+
+            txn1.set(&key1, &value3).unwrap();
+            txn2.set(&key1, &value4).unwrap();
+
+            txn1.set(&key2, &value5).unwrap();
+
+            txn1.commit().await.unwrap();
+
+            txn2.set(&key2, &value6).unwrap();
+            assert!(match txn2.commit().await {
+                Err(err) => {
+                    matches!(err, Error::TransactionReadConflict)
+                }
+                _ => false,
+            });
+        }
+
+        {
+            let txn3 = store.begin().unwrap();
+            let val1 = txn3.get(&key1).unwrap().unwrap();
+            assert_eq!(val1, value3.as_ref());
+            let val2 = txn3.get(&key2).unwrap().unwrap();
+            assert_eq!(val2, value5.as_ref());
+        }
+    }
+
+    #[tokio::test]
+    async fn g0() {
+        g0_tests(false).await; // snapshot isolation
+        g0_tests(true).await; // serializable snapshot isolation
+    }
+
+    // G1a: Aborted Reads (dirty reads, cascaded aborts)
+    async fn g1a_tests(is_ssi: bool) {
+        let store = create_hermitage_store(is_ssi).await;
+        let key1 = Bytes::from("k1");
+        let key2 = Bytes::from("k2");
+        let value1 = Bytes::from("v1");
+        let value2 = Bytes::from("v2");
+        let value3 = Bytes::from("v3");
+
+        {
+            let mut txn1 = store.begin().unwrap();
+            let mut txn2 = store.begin().unwrap();
+
+            assert!(txn1.get(&key1).is_ok());
+
+            txn1.set(&key1, &value3).unwrap();
+
+            let range = "k1".as_bytes()..="k3".as_bytes();
+            let res = txn2.scan(range.clone(), None).unwrap();
+            assert_eq!(res.len(), 2);
+            assert_eq!(res[0].1, value1);
+
+            drop(txn1);
+
+            let res = txn2.scan(range, None).unwrap();
+            assert_eq!(res.len(), 2);
+            assert_eq!(res[0].1, value1);
+
+            txn2.commit().await.unwrap();
+        }
+
+        {
+            let txn3 = store.begin().unwrap();
+            let val1 = txn3.get(&key1).unwrap().unwrap();
+            assert_eq!(val1, value1.as_ref());
+            let val2 = txn3.get(&key2).unwrap().unwrap();
+            assert_eq!(val2, value2.as_ref());
+        }
+    }
+
+    #[tokio::test]
+    async fn g1a() {
+        g1a_tests(false).await; // snapshot isolation
+        g1a_tests(true).await; // serializable snapshot isolation
+    }
+
+    // G1b: Intermediate Reads (dirty reads)
+    async fn g1b_tests(is_ssi: bool) {
+        let store = create_hermitage_store(is_ssi).await;
+
+        let key1 = Bytes::from("k1");
+        let key2 = Bytes::from("k2");
+        let value1 = Bytes::from("v1");
+        let value3 = Bytes::from("v3");
+        let value4 = Bytes::from("v4");
+
+        {
+            let mut txn1 = store.begin().unwrap();
+            let mut txn2 = store.begin().unwrap();
+
+            assert!(txn1.get(&key1).is_ok());
+            assert!(txn1.get(&key2).is_ok());
+
+            txn1.set(&key1, &value3).unwrap();
+
+            let range = "k1".as_bytes()..="k3".as_bytes();
+            let res = txn2.scan(range.clone(), None).unwrap();
+            assert_eq!(res.len(), 2);
+            assert_eq!(res[0].1, value1);
+
+            txn1.set(&key1, &value4).unwrap();
+            txn1.commit().await.unwrap();
+
+            let res = txn2.scan(range, None).unwrap();
+            assert_eq!(res.len(), 2);
+            assert_eq!(res[0].1, value1);
+
+            txn2.commit().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn g1b() {
+        g1b_tests(false).await; // snapshot isolation
+        g1b_tests(true).await; // serializable snapshot isolation
+    }
+
+    // G1c: Circular Information Flow (dirty reads)
+    async fn g1c_tests(is_ssi: bool) {
+        let store = create_hermitage_store(is_ssi).await;
+
+        let key1 = Bytes::from("k1");
+        let key2 = Bytes::from("k2");
+        let value1 = Bytes::from("v1");
+        let value2 = Bytes::from("v2");
+        let value3 = Bytes::from("v3");
+        let value4 = Bytes::from("v4");
+
+        {
+            let mut txn1 = store.begin().unwrap();
+            let mut txn2 = store.begin().unwrap();
+
+            assert!(txn1.get(&key1).is_ok());
+            assert!(txn2.get(&key2).is_ok());
+
+            txn1.set(&key1, &value3).unwrap();
+            txn2.set(&key2, &value4).unwrap();
+
+            assert_eq!(txn1.get(&key2).unwrap().unwrap(), value2.as_ref());
+            assert_eq!(txn2.get(&key1).unwrap().unwrap(), value1.as_ref());
+
+            txn1.commit().await.unwrap();
+            assert!(match txn2.commit().await {
+                Err(err) => {
+                    matches!(err, Error::TransactionReadConflict)
+                }
+                _ => false,
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn g1c() {
+        g1c_tests(false).await; // snapshot isolation
+        g1c_tests(true).await;
+    }
+
+    // PMP: Predicate-Many-Preceders
+    async fn pmp_tests(is_ssi: bool) {
+        let store = create_hermitage_store(is_ssi).await;
+
+        let key3 = Bytes::from("k3");
+        let value1 = Bytes::from("v1");
+        let value2 = Bytes::from("v2");
+        let value3 = Bytes::from("v3");
+
+        {
+            let txn1 = store.begin().unwrap();
+            let mut txn2 = store.begin().unwrap();
+
+            // k3 should not be visible to txn1
+            let range = "k1".as_bytes()..="k3".as_bytes();
+            let res = txn1.scan(range.clone(), None).unwrap();
+            assert_eq!(res.len(), 2);
+            assert_eq!(res[0].1, value1);
+            assert_eq!(res[1].1, value2);
+
+            // k3 is committed by txn2
+            txn2.set(&key3, &value3).unwrap();
+            txn2.commit().await.unwrap();
+
+            // k3 should still not be visible to txn1
+            let range = "k1".as_bytes()..="k3".as_bytes();
+            let res = txn1.scan(range.clone(), None).unwrap();
+            assert_eq!(res.len(), 2);
+            assert_eq!(res[0].1, value1);
+            assert_eq!(res[1].1, value2);
+        }
+    }
+
+    #[tokio::test]
+    async fn pmp() {
+        pmp_tests(false).await;
+        pmp_tests(true).await;
+    }
+
+    // PMP-Write: Circular Information Flow (dirty reads)
+    async fn pmp_write_tests(is_ssi: bool) {
+        let store = create_hermitage_store(is_ssi).await;
+
+        let key1 = Bytes::from("k1");
+        let key2 = Bytes::from("k2");
+        let value1 = Bytes::from("v1");
+        let value2 = Bytes::from("v2");
+        let value3 = Bytes::from("v3");
+
+        {
+            let mut txn1 = store.begin().unwrap();
+            let mut txn2 = store.begin().unwrap();
+
+            assert!(txn1.get(&key1).is_ok());
+            txn1.set(&key1, &value3).unwrap();
+
+            let range = "k1".as_bytes()..="k2".as_bytes();
+            let res = txn2.scan(range.clone(), None).unwrap();
+            assert_eq!(res.len(), 2);
+            assert_eq!(res[0].1, value1);
+            assert_eq!(res[1].1, value2);
+
+            txn2.delete(&key2).unwrap();
+            txn1.commit().await.unwrap();
+
+            let range = "k1".as_bytes()..="k3".as_bytes();
+            let res = txn2.scan(range.clone(), None).unwrap();
+            assert_eq!(res.len(), 1);
+            assert_eq!(res[0].1, value1);
+
+            assert!(match txn2.commit().await {
+                Err(err) => {
+                    matches!(err, Error::TransactionReadConflict)
+                }
+                _ => false,
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn pmp_write() {
+        pmp_write_tests(false).await;
+        pmp_write_tests(true).await;
+    }
+
+    // P4: Lost Update
+    async fn p4_tests(is_ssi: bool) {
+        let store = create_hermitage_store(is_ssi).await;
+
+        let key1 = Bytes::from("k1");
+        let value3 = Bytes::from("v3");
+
+        {
+            let mut txn1 = store.begin().unwrap();
+            let mut txn2 = store.begin().unwrap();
+
+            assert!(txn1.get(&key1).is_ok());
+            assert!(txn2.get(&key1).is_ok());
+
+            txn1.set(&key1, &value3).unwrap();
+            txn2.set(&key1, &value3).unwrap();
+
+            txn1.commit().await.unwrap();
+
+            assert!(match txn2.commit().await {
+                Err(err) => {
+                    matches!(err, Error::TransactionReadConflict)
+                }
+                _ => false,
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn p4() {
+        p4_tests(false).await;
+        p4_tests(true).await;
+    }
+
+    // G-single: Single Anti-dependency Cycles (read skew)
+    async fn g_single_tests(is_ssi: bool) {
+        let store = create_hermitage_store(is_ssi).await;
+
+        let key1 = Bytes::from("k1");
+        let key2 = Bytes::from("k2");
+        let value1 = Bytes::from("v1");
+        let value2 = Bytes::from("v2");
+        let value3 = Bytes::from("v3");
+        let value4 = Bytes::from("v4");
+
+        {
+            let mut txn1 = store.begin().unwrap();
+            let mut txn2 = store.begin().unwrap();
+
+            assert_eq!(txn1.get(&key1).unwrap().unwrap(), value1.as_ref());
+            assert_eq!(txn2.get(&key1).unwrap().unwrap(), value1.as_ref());
+            assert_eq!(txn2.get(&key2).unwrap().unwrap(), value2.as_ref());
+            txn2.set(&key1, &value3).unwrap();
+            txn2.set(&key2, &value4).unwrap();
+
+            txn2.commit().await.unwrap();
+
+            assert_eq!(txn1.get(&key2).unwrap().unwrap(), value2.as_ref());
+            txn1.commit().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn g_single() {
+        g_single_tests(false).await;
+        g_single_tests(true).await;
+    }
+
+    // G-single-write-1: Single Anti-dependency Cycles (read skew)
+    async fn g_single_write_1_tests(is_ssi: bool) {
+        let store = create_hermitage_store(is_ssi).await;
+
+        let key1 = Bytes::from("k1");
+        let key2 = Bytes::from("k2");
+        let value1 = Bytes::from("v1");
+        let value2 = Bytes::from("v2");
+        let value3 = Bytes::from("v3");
+        let value4 = Bytes::from("v4");
+
+        {
+            let mut txn1 = store.begin().unwrap();
+            let mut txn2 = store.begin().unwrap();
+
+            assert_eq!(txn1.get(&key1).unwrap().unwrap(), value1.as_ref());
+
+            let range = "k1".as_bytes()..="k2".as_bytes();
+            let res = txn2.scan(range.clone(), None).unwrap();
+            assert_eq!(res.len(), 2);
+            assert_eq!(res[0].1, value1);
+            assert_eq!(res[1].1, value2);
+
+            txn2.set(&key1, &value3).unwrap();
+            txn2.set(&key2, &value4).unwrap();
+
+            txn2.commit().await.unwrap();
+
+            txn1.delete(&key2).unwrap();
+            assert!(txn1.get(&key2).unwrap().is_none());
+            assert!(match txn1.commit().await {
+                Err(err) => {
+                    matches!(err, Error::TransactionReadConflict)
+                }
+                _ => false,
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn g_single_write_1() {
+        g_single_write_1_tests(false).await;
+        g_single_write_1_tests(true).await;
+    }
+
+    // G-single-write-2: Single Anti-dependency Cycles (read skew)
+    async fn g_single_write_2_tests(is_ssi: bool) {
+        let store = create_hermitage_store(is_ssi).await;
+
+        let key1 = Bytes::from("k1");
+        let key2 = Bytes::from("k2");
+        let value1 = Bytes::from("v1");
+        let value2 = Bytes::from("v2");
+        let value3 = Bytes::from("v3");
+        let value4 = Bytes::from("v4");
+
+        {
+            let mut txn1 = store.begin().unwrap();
+            let mut txn2 = store.begin().unwrap();
+
+            assert_eq!(txn1.get(&key1).unwrap().unwrap(), value1.as_ref());
+            let range = "k1".as_bytes()..="k2".as_bytes();
+            let res = txn2.scan(range.clone(), None).unwrap();
+            assert_eq!(res.len(), 2);
+            assert_eq!(res[0].1, value1);
+            assert_eq!(res[1].1, value2);
+
+            txn2.set(&key1, &value3).unwrap();
+
+            txn1.delete(&key2).unwrap();
+
+            txn2.set(&key2, &value4).unwrap();
+
+            drop(txn1);
+
+            txn2.commit().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn g_single_write_2() {
+        g_single_write_2_tests(false).await;
+        g_single_write_2_tests(true).await;
+    }
+
+    async fn g2_item_tests(is_ssi: bool) {
+        let store = create_hermitage_store(is_ssi).await;
+
+        let key1 = Bytes::from("k1");
+        let key2 = Bytes::from("k2");
+        let value1 = Bytes::from("v1");
+        let value2 = Bytes::from("v2");
+        let value3 = Bytes::from("v3");
+        let value4 = Bytes::from("v4");
+
+        {
+            let mut txn1 = store.begin().unwrap();
+            let mut txn2 = store.begin().unwrap();
+
+            let range = "k1".as_bytes()..="k2".as_bytes();
+            let res = txn1.scan(range.clone(), None).unwrap();
+            assert_eq!(res.len(), 2);
+            assert_eq!(res[0].1, value1);
+            assert_eq!(res[1].1, value2);
+
+            let res = txn2.scan(range.clone(), None).unwrap();
+            assert_eq!(res.len(), 2);
+            assert_eq!(res[0].1, value1);
+            assert_eq!(res[1].1, value2);
+
+            txn1.set(&key1, &value3).unwrap();
+            txn2.set(&key2, &value4).unwrap();
+
+            txn1.commit().await.unwrap();
+
+            assert!(match txn2.commit().await {
+                Err(err) => {
+                    matches!(err, Error::TransactionReadConflict)
+                }
+                _ => false,
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn g2_item() {
+        g2_item_tests(false).await;
+        g2_item_tests(true).await;
+    }
+
+    fn require_send<T: Send>(_: T) {}
+    fn require_sync<T: Sync + Send>(_: T) {}
+
+    #[tokio::test]
+    async fn is_send_sync() {
+        let (db, _) = create_store(false);
+
+        let txn = db.begin().unwrap();
+        require_send(txn);
+
+        let txn = db.begin().unwrap();
+        require_sync(txn);
+    }
+
+    #[tokio::test]
+    async fn max_transaction_entries_limit_exceeded() {
+        let temp_dir = create_temp_directory();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.max_entries_per_txn = 5;
+
+        let store = Store::new(opts.clone()).expect("should create store");
+
+        let num_keys = 6;
+        let mut keys = Vec::new();
+
+        for (counter, _) in (1..=num_keys).enumerate() {
+            // Convert the counter to Bytes
+            let key_bytes = Bytes::from(counter.to_le_bytes().to_vec());
+
+            // Add the key to the vector
+            keys.push(key_bytes);
+        }
+
+        let default_value = Bytes::from("default_value".to_string());
+
+        // Writing the 6th key should fail
+        let mut txn = store.begin().unwrap();
+
+        for (i, key) in keys.iter().enumerate() {
+            // Start a new write transaction
+            if i < 5 {
+                assert!(txn.set(key, &default_value).is_ok());
+            } else {
+                assert!(txn.set(key, &default_value).is_err());
+            }
+        }
+    }
+
+    const ENTRIES: usize = 400_000;
+    const KEY_SIZE: usize = 24;
+    const VALUE_SIZE: usize = 150;
+    const RNG_SEED: u64 = 3;
+
+    fn fill_slice(slice: &mut [u8], rng: &mut fastrand::Rng) {
+        let mut i = 0;
+        while i + size_of::<u128>() < slice.len() {
+            let tmp = rng.u128(..);
+            slice[i..(i + size_of::<u128>())].copy_from_slice(&tmp.to_le_bytes());
+            i += size_of::<u128>()
+        }
+        if i + size_of::<u64>() < slice.len() {
+            let tmp = rng.u64(..);
+            slice[i..(i + size_of::<u64>())].copy_from_slice(&tmp.to_le_bytes());
+            i += size_of::<u64>()
+        }
+        if i + size_of::<u32>() < slice.len() {
+            let tmp = rng.u32(..);
+            slice[i..(i + size_of::<u32>())].copy_from_slice(&tmp.to_le_bytes());
+            i += size_of::<u32>()
+        }
+        if i + size_of::<u16>() < slice.len() {
+            let tmp = rng.u16(..);
+            slice[i..(i + size_of::<u16>())].copy_from_slice(&tmp.to_le_bytes());
+            i += size_of::<u16>()
+        }
+        if i + size_of::<u8>() < slice.len() {
+            slice[i] = rng.u8(..);
+        }
+    }
+
+    fn gen_pair(rng: &mut fastrand::Rng) -> ([u8; KEY_SIZE], Vec<u8>) {
+        let mut key = [0u8; KEY_SIZE];
+        fill_slice(&mut key, rng);
+        let mut value = vec![0u8; VALUE_SIZE];
+        fill_slice(&mut value, rng);
+
+        (key, value)
+    }
+
+    fn make_rng() -> fastrand::Rng {
+        fastrand::Rng::with_seed(RNG_SEED)
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn insert_large_txn_and_get() {
+        let temp_dir = create_temp_directory();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.max_entries_per_txn = ENTRIES as u32;
+
+        let store = Store::new(opts.clone()).expect("should create store");
+        let mut rng = make_rng();
+
+        let mut txn = store.begin().unwrap();
+        for _ in 0..ENTRIES {
+            let (key, value) = gen_pair(&mut rng);
+            txn.set(&key, &value).unwrap();
+        }
+        txn.commit().await.unwrap();
+        drop(txn);
+
+        // Read the keys from the store
+        let mut rng = make_rng();
+        let txn = store.begin_with_mode(Mode::ReadOnly).unwrap();
+        for _i in 0..ENTRIES {
+            let (key, _) = gen_pair(&mut rng);
+            txn.get(&key).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn empty_scan_should_not_return_an_error() {
+        let (store, _) = create_store(false);
+
+        let range = "key1".as_bytes()..="key3".as_bytes();
+
+        let txn = store.begin().unwrap();
+        let results = txn.scan(range, None).unwrap();
+        assert_eq!(results.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn transaction_delete_and_scan_test() {
+        let (store, _) = create_store(false);
+
+        // Define key-value pairs for the test
+        let key1 = Bytes::from("k1");
+        let value1 = Bytes::from("baz");
+
+        {
+            // Start a new read-write transaction (txn1)
+            let mut txn1 = store.begin().unwrap();
+            txn1.set(&key1, &value1).unwrap();
+            txn1.commit().await.unwrap();
+        }
+
+        {
+            // Start a read-only transaction (txn)
+            let mut txn = store.begin().unwrap();
+            txn.get(&key1).unwrap();
+            txn.delete(&key1).unwrap();
+            let range = "k1".as_bytes()..="k3".as_bytes();
+            let results = txn.scan(range, None).unwrap();
+            assert_eq!(results.len(), 0);
+            txn.commit().await.unwrap();
+        }
+        {
+            let range = "k1".as_bytes()..="k3".as_bytes();
+            let txn = store.begin().unwrap();
+            let results = txn.scan(range, None).unwrap();
+            assert_eq!(results.len(), 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn sdb_delete_record_id_bug() {
+        let (store, _) = create_store(false);
+
+        // Define key-value pairs for the test
+        let key1 = Bytes::copy_from_slice(&[
+            47, 33, 110, 100, 166, 192, 229, 30, 101, 24, 73, 242, 185, 36, 233, 242, 54, 96, 72,
+            52,
+        ]);
+        let key2 = Bytes::copy_from_slice(&[
+            47, 33, 104, 98, 0, 0, 1, 141, 141, 42, 113, 8, 47, 166, 192, 229, 30, 101, 24, 73,
+            242, 185, 36, 233, 242, 54, 96, 72, 52,
+        ]);
+        let value1 = Bytes::from("baz");
+
+        {
+            // Start a new read-write transaction (txn)
+            let mut txn = store.begin().unwrap();
+            txn.set(&key1, &value1).unwrap();
+            txn.set(&key2, &value1).unwrap();
+            txn.commit().await.unwrap();
+        }
+
+        let key3 = Bytes::copy_from_slice(&[47, 33, 117, 115, 114, 111, 111, 116, 0]);
+        {
+            // Start a new read-write transaction (txn)
+            let mut txn = store.begin().unwrap();
+            txn.set(&key3, &value1).unwrap();
+            txn.commit().await.unwrap();
+        }
+
+        let key4 = Bytes::copy_from_slice(&[47, 33, 117, 115, 114, 111, 111, 116, 0]);
+        let txn1 = store.begin().unwrap();
+        txn1.get(&key4).unwrap();
+
+        {
+            let mut txn2 = store.begin().unwrap();
+            txn2.get(&Bytes::copy_from_slice(&[
+                47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 42, 48, 49, 72, 80, 54, 72, 71, 72,
+                50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74, 69, 0, 33, 116,
+                98, 117, 115, 101, 114, 0,
+            ]))
+            .unwrap();
+            txn2.get(&Bytes::copy_from_slice(&[
+                47, 33, 110, 115, 116, 101, 115, 116, 45, 110, 115, 0,
+            ]))
+            .unwrap();
+            txn2.get(&Bytes::copy_from_slice(&[
+                47, 33, 110, 115, 116, 101, 115, 116, 45, 110, 115, 0,
+            ]))
+            .unwrap();
+            txn2.set(
+                &Bytes::copy_from_slice(&[47, 33, 110, 115, 116, 101, 115, 116, 45, 110, 115, 0]),
+                &value1,
+            )
+            .unwrap();
+
+            txn2.get(&Bytes::copy_from_slice(&[
+                47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 33, 100, 98, 48, 49, 72, 80, 54, 72,
+                71, 72, 50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74, 69, 0,
+            ]))
+            .unwrap();
+            txn2.get(&Bytes::copy_from_slice(&[
+                47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 33, 100, 98, 48, 49, 72, 80, 54, 72,
+                71, 72, 50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74, 69, 0,
+            ]))
+            .unwrap();
+            txn2.set(
+                &Bytes::copy_from_slice(&[
+                    47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 33, 100, 98, 48, 49, 72, 80, 54,
+                    72, 71, 72, 50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74,
+                    69, 0,
+                ]),
+                &value1,
+            )
+            .unwrap();
+
+            txn2.get(&Bytes::copy_from_slice(&[
+                47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 42, 48, 49, 72, 80, 54, 72, 71, 72,
+                50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74, 69, 0, 33, 116,
+                98, 117, 115, 101, 114, 0,
+            ]))
+            .unwrap();
+            txn2.get(&Bytes::copy_from_slice(&[
+                47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 42, 48, 49, 72, 80, 54, 72, 71, 72,
+                50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74, 69, 0, 33, 116,
+                98, 117, 115, 101, 114, 0,
+            ]))
+            .unwrap();
+            txn2.set(
+                &Bytes::copy_from_slice(&[
+                    47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 42, 48, 49, 72, 80, 54, 72, 71,
+                    72, 50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74, 69, 0,
+                    33, 116, 98, 117, 115, 101, 114, 0,
+                ]),
+                &value1,
+            )
+            .unwrap();
+
+            txn2.get(&Bytes::copy_from_slice(&[
+                47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 42, 48, 49, 72, 80, 54, 72, 71, 72,
+                50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74, 69, 0, 33, 116,
+                98, 117, 115, 101, 114, 0,
+            ]))
+            .unwrap();
+            txn2.get(&Bytes::copy_from_slice(&[
+                47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 42, 48, 49, 72, 80, 54, 72, 71, 72,
+                50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74, 69, 0, 42, 117,
+                115, 101, 114, 0, 42, 0, 0, 0, 1, 106, 111, 104, 110, 0,
+            ]))
+            .unwrap();
+            txn2.set(
+                &Bytes::copy_from_slice(&[
+                    47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 42, 48, 49, 72, 80, 54, 72, 71,
+                    72, 50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74, 69, 0,
+                    42, 117, 115, 101, 114, 0, 42, 0, 0, 0, 1, 106, 111, 104, 110, 0,
+                ]),
+                &value1,
+            )
+            .unwrap();
+
+            txn2.get(&Bytes::copy_from_slice(&[
+                47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 33, 100, 98, 48, 49, 72, 80, 54, 72,
+                71, 72, 50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74, 69, 0,
+            ]))
+            .unwrap();
+
+            txn2.commit().await.unwrap();
+        }
+
+        {
+            let mut txn3 = store.begin().unwrap();
+            txn3.get(&Bytes::copy_from_slice(&[
+                47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 42, 48, 49, 72, 80, 54, 72, 71, 72,
+                50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74, 69, 0, 42, 117,
+                115, 101, 114, 0, 42, 0, 0, 0, 1, 106, 111, 104, 110, 0,
+            ]))
+            .unwrap();
+            txn3.get(&Bytes::copy_from_slice(&[
+                47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 42, 48, 49, 72, 80, 54, 72, 71, 72,
+                50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74, 69, 0, 33, 116,
+                98, 117, 115, 101, 114, 0,
+            ]))
+            .unwrap();
+            txn3.delete(&Bytes::copy_from_slice(&[
+                47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 42, 48, 49, 72, 80, 54, 72, 71, 72,
+                50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74, 69, 0, 42, 117,
+                115, 101, 114, 0, 42, 0, 0, 0, 1, 106, 111, 104, 110, 0,
+            ]))
+            .unwrap();
+            txn3.get(&Bytes::copy_from_slice(&[
+                47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 33, 100, 98, 48, 49, 72, 80, 54, 72,
+                71, 72, 50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74, 69, 0,
+            ]))
+            .unwrap();
+            txn3.get(&Bytes::copy_from_slice(&[
+                47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 33, 100, 98, 48, 49, 72, 80, 54, 72,
+                71, 72, 50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74, 69, 0,
+            ]))
+            .unwrap();
+            txn3.set(
+                &Bytes::copy_from_slice(&[
+                    47, 42, 116, 101, 115, 116, 45, 110, 115, 0, 33, 100, 98, 48, 49, 72, 80, 54,
+                    72, 71, 72, 50, 50, 51, 89, 82, 49, 54, 51, 90, 52, 78, 56, 72, 69, 90, 80, 74,
+                    69, 0,
+                ]),
+                &value1,
+            )
+            .unwrap();
+            txn3.commit().await.unwrap();
+        }
+    }
+
+    async fn g2_item_predicate(is_ssi: bool) {
+        let store = create_hermitage_store(is_ssi).await;
+
+        let key3 = Bytes::from("k3");
+        let key4 = Bytes::from("k4");
+        let key5 = Bytes::from("k5");
+        let key6 = Bytes::from("k6");
+        let key7 = Bytes::from("k7");
+        let value3 = Bytes::from("v3");
+        let value4 = Bytes::from("v4");
+
+        // inserts into read ranges of already-committed transaction(s) should fail
+        {
+            let mut txn1 = store.begin().unwrap();
+            let mut txn2 = store.begin().unwrap();
+
+            let range = "k1".as_bytes()..="k4".as_bytes();
+            txn1.scan(range.clone(), None).unwrap();
+            txn2.scan(range.clone(), None).unwrap();
+
+            txn1.set(&key3, &value3).unwrap();
+            txn2.set(&key4, &value4).unwrap();
+
+            txn1.commit().await.unwrap();
+
+            assert!(match txn2.commit().await {
+                Err(err) => {
+                    matches!(err, Error::TransactionReadConflict)
+                }
+                _ => false,
+            });
+        }
+
+        // k1, k2, k3 already committed
+        // inserts beyond scan range should pass
+        {
+            let mut txn1 = store.begin().unwrap();
+            let mut txn2 = store.begin().unwrap();
+
+            let range = "k1".as_bytes()..="k3".as_bytes();
+            txn1.scan(range.clone(), None).unwrap();
+            txn2.scan(range.clone(), None).unwrap();
+
+            txn1.set(&key4, &value3).unwrap();
+            txn2.set(&key5, &value4).unwrap();
+
+            txn1.commit().await.unwrap();
+            txn2.commit().await.unwrap();
+        }
+
+        // k1, k2, k3, k4, k5 already committed
+        // inserts in subset scan ranges should fail
+        {
+            let mut txn1 = store.begin().unwrap();
+            let mut txn2 = store.begin().unwrap();
+
+            let range = "k1".as_bytes()..="k7".as_bytes();
+            txn1.scan(range.clone(), None).unwrap();
+            let range = "k3".as_bytes()..="k7".as_bytes();
+            txn2.scan(range.clone(), None).unwrap();
+
+            txn1.set(&key6, &value3).unwrap();
+            txn2.set(&key7, &value4).unwrap();
+
+            txn1.commit().await.unwrap();
+            assert!(match txn2.commit().await {
+                Err(err) => {
+                    matches!(err, Error::TransactionReadConflict)
+                }
+                _ => false,
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn g2_predicate() {
+        g2_item_predicate(true).await;
+    }
+
+    #[tokio::test]
+    async fn max_active_transactions() {
+        let temp_dir = create_temp_directory();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.max_active_transactions = 2;
+        let store = Arc::new(Store::new(opts).expect("should create store"));
+
+        let mut txn1 = store.begin().unwrap();
+        let txn2 = store.begin_with_mode(Mode::ReadOnly).unwrap();
+        assert!(matches!(store.begin(), Err(Error::TooManyTransactions)));
+
+        // A committed transaction frees its slot.
+        txn1.set(b"k1", b"v1").unwrap();
+        txn1.commit().await.unwrap();
+        let txn3 = store.begin().unwrap();
+
+        // Waiting callers are admitted in order as slots are freed.
+        let (order_tx, order_rx) = async_channel::unbounded();
+        let mut waiters = Vec::new();
+        for i in 0..3 {
+            let store = store.clone();
+            let order_tx = order_tx.clone();
+            waiters.push(tokio::spawn(async move {
+                let txn = store.begin_queued(Mode::ReadOnly).await.unwrap();
+                order_tx.send(i).await.unwrap();
+                drop(txn);
+            }));
+            // Let the waiter queue up before spawning the next one.
+            tokio::task::yield_now().await;
+        }
+        assert!(order_rx.is_empty());
+
+        drop(txn2);
+        drop(txn3);
+        for waiter in waiters {
+            waiter.await.unwrap();
+        }
+        let order: Vec<i32> = (0..3).map(|_| order_rx.try_recv().unwrap()).collect();
+        assert_eq!(order, vec![0, 1, 2]);
+        assert!(store.begin().is_ok());
+    }
+
+    #[tokio::test]
+    async fn get_ge_le() {
+        let (store, _temp_dir) = create_store(false);
+
+        let mut txn = store.begin().unwrap();
+        for key in [b"k10", b"k20", b"k30", b"k40"] {
+            txn.set(key, key).unwrap();
+        }
+        txn.commit().await.unwrap();
+        let mut txn = store.begin().unwrap();
+        txn.delete(b"k30").unwrap();
+        txn.commit().await.unwrap();
+        store.append_to_stream(b"events", b"e0").await.unwrap();
+
+        let mut txn = store.begin().unwrap();
+        let key_of = |res: Option<ScanResult>| res.map(|(k, _, _, _)| k);
+        assert_eq!(key_of(txn.get_ge(b"k20").unwrap()), Some(b"k20".to_vec()));
+        assert_eq!(key_of(txn.get_ge(b"k21").unwrap()), Some(b"k40".to_vec()));
+        assert_eq!(key_of(txn.get_ge(b"k41").unwrap()), None);
+        assert_eq!(key_of(txn.get_le(b"k20").unwrap()), Some(b"k20".to_vec()));
+        assert_eq!(key_of(txn.get_le(b"k39").unwrap()), Some(b"k20".to_vec()));
+        assert_eq!(key_of(txn.get_le(b"k0").unwrap()), None);
+        assert_eq!(key_of(txn.get_le(b"\xff").unwrap()), Some(b"k40".to_vec()));
+
+        // The transaction sees its own writes.
+        txn.set(b"k35", b"v").unwrap();
+        let (key, value, _, _) = txn.get_le(b"k39").unwrap().unwrap();
+        assert_eq!((key, value), (b"k35".to_vec(), b"v".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn get_ge_conflict() {
+        let (store, _temp_dir) = create_store(true);
+
+        let mut txn = store.begin().unwrap();
+        txn.set(b"k10", b"v").unwrap();
+        txn.set(b"k30", b"v").unwrap();
+        txn.commit().await.unwrap();
+
+        // An insert between the seek key and the found key changes the result.
+        let mut txn1 = store.begin().unwrap();
+        let mut txn2 = store.begin().unwrap();
+        txn1.get_ge(b"k11").unwrap().unwrap();
+        txn1.set(b"other", b"v").unwrap();
+        txn2.set(b"k20", b"v").unwrap();
+        txn2.commit().await.unwrap();
+        assert!(matches!(
+            txn1.commit().await,
+            Err(Error::TransactionReadConflict)
+        ));
+
+        // An insert past the found key does not.
+        let mut txn1 = store.begin().unwrap();
+        let mut txn2 = store.begin().unwrap();
+        txn1.get_ge(b"k11").unwrap().unwrap();
+        txn1.set(b"other", b"v").unwrap();
+        txn2.set(b"k40", b"v").unwrap();
+        txn2.commit().await.unwrap();
+        txn1.commit().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn scan_paginated() {
+        let (store, _temp_dir) = create_store(false);
+
+        let mut txn = store.begin().unwrap();
+        for key in [b"k1", b"k2", b"k3", b"k4", b"k5", b"k6"] {
+            txn.set(key, key).unwrap();
+        }
+        txn.commit().await.unwrap();
+
+        let keys = |entries: &[ScanResult]| -> Vec<Vec<u8>> {
+            entries.iter().map(|(k, ..)| k.clone()).collect()
+        };
+        let range = &b"k2"[..]..&b"k9"[..];
+        let txn = store.begin().unwrap();
+        let (page, token) = txn.scan_paginated(range.clone(), 2, None).unwrap();
+        assert_eq!(keys(&page), [b"k2".to_vec(), b"k3".to_vec()]);
+        let token = PageToken::from_bytes(token.unwrap().as_bytes());
+        drop(txn);
+
+        // The next page is read by another transaction, which sees the writes made since.
+        let mut txn = store.begin().unwrap();
+        txn.delete(b"k4").unwrap();
+        txn.set(b"k35", b"v").unwrap();
+        txn.set(b"k0", b"v").unwrap();
+        txn.commit().await.unwrap();
+
+        let txn = store.begin().unwrap();
+        let (page, token) = txn.scan_paginated(range.clone(), 2, Some(&token)).unwrap();
+        assert_eq!(keys(&page), [b"k35".to_vec(), b"k5".to_vec()]);
+        let (page, token) = txn
+            .scan_paginated(range.clone(), 2, token.as_ref())
+            .unwrap();
+        assert_eq!(keys(&page), [b"k6".to_vec()]);
+        assert!(token.is_none());
+
+        // A token from before the range starts the scan at the start of the range.
+        let token = PageToken::from_bytes(b"k0");
+        let (page, _) = txn.scan_paginated(range, 1, Some(&token)).unwrap();
+        assert_eq!(keys(&page), [b"k2".to_vec()]);
+    }
+
+    async fn read_fingerprints(exact_fallback: bool) {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.isolation_level = IsolationLevel::SerializableSnapshotIsolation;
+        opts.ssi_read_fingerprints = true;
+        opts.ssi_exact_fallback = exact_fallback;
+        let store = Store::new(opts).expect("should create store");
+
+        let mut txn = store.begin().unwrap();
+        txn.set(b"k1", b"v").unwrap();
+        txn.set(b"k2", b"v").unwrap();
+        txn.commit().await.unwrap();
+
+        // Only the fingerprints are recorded, unless the exact keys are asked for.
+        let mut txn1 = store.begin().unwrap();
+        txn1.get(b"k1").unwrap().unwrap();
+        txn1.get(b"missing").unwrap();
+        {
+            let read_set = txn1.read_set.lock();
+            assert_eq!(read_set.fingerprints.len(), 2);
+            assert_eq!(read_set.keys.len(), if exact_fallback { 2 } else { 0 });
+        }
+
+        // A write to a key read concurrently is a conflict.
+        let mut txn2 = store.begin().unwrap();
+        txn1.set(b"k3", b"v").unwrap();
+        txn2.set(b"k1", b"v2").unwrap();
+        txn2.commit().await.unwrap();
+        assert!(matches!(
+            txn1.commit().await,
+            Err(Error::TransactionReadConflict)
+        ));
+
+        // A write to a key that was not read is not.
+        let mut txn1 = store.begin().unwrap();
+        let mut txn2 = store.begin().unwrap();
+        txn1.get(b"k1").unwrap().unwrap();
+        txn1.set(b"k3", b"v").unwrap();
+        txn2.set(b"k2", b"v2").unwrap();
+        txn2.commit().await.unwrap();
+        txn1.commit().await.unwrap();
+
+        let stats = store.stats();
+        assert_eq!(stats.ssi_fingerprint_hits, 1);
+        assert_eq!(stats.ssi_fingerprint_false_positives, 0);
+    }
+
+    #[tokio::test]
+    async fn read_fingerprints_only() {
+        read_fingerprints(false).await;
+    }
+
+    #[tokio::test]
+    async fn read_fingerprints_with_exact_fallback() {
+        read_fingerprints(true).await;
+    }
+
+    #[test]
+    fn read_fingerprints_under_snapshot_isolation() {
+        // Snapshot isolation validates the versions read, so it always records the keys.
+        let mut opts = Options::new();
+        opts.ssi_read_fingerprints = true;
+        let mut read_set = ReadSet::new(&opts);
+        read_set.push(Bytes::from_static(b"k"), 1);
+        assert!(read_set.fingerprints.is_empty());
+        assert_eq!(read_set.keys.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn prepare_timeout() {
+        for is_ssi in [false, true] {
+            let temp_dir = create_temp_directory();
+            let mut opts = Options::new();
+            opts.dir = temp_dir.path().to_path_buf();
+            opts.prepare_timeout = 50;
+            if is_ssi {
+                opts.isolation_level = IsolationLevel::SerializableSnapshotIsolation;
+            }
+            let store = Store::new(opts).expect("should create store");
+
+            let mut txn = store.begin().unwrap();
+            txn.get(b"k1").unwrap();
+            txn.set(b"k1", b"v1").unwrap();
+            let prepared = txn.prepare().await.unwrap();
+
+            // The commit lock is released once the prepared transaction held it for too long,
+            // and the transactions committing next are not checked against it.
+            let mut other = store.begin().unwrap();
+            other.get(b"k1").unwrap();
+            other.set(b"k2", b"v2").unwrap();
+            tokio::time::timeout(Duration::from_secs(10), other.commit())
+                .await
+                .expect("the commit lock should be released")
+                .unwrap();
+
+            // The commit of the prepared transaction fails, and leaves it open to be retried.
+            assert!(matches!(
+                prepared.commit().await,
+                Err(Error::PrepareTimedOut)
+            ));
+            txn.commit().await.unwrap();
+
+            let txn = store.begin().unwrap();
+            assert_eq!(txn.get(b"k1").unwrap().unwrap(), b"v1");
+            assert_eq!(txn.get(b"k2").unwrap().unwrap(), b"v2");
+        }
+    }
+
+    #[tokio::test]
+    async fn read_at_version() {
+        let (store, _temp_dir) = create_store(false);
+        let write = |key: &'static [u8], value: Option<&'static [u8]>| {
+            let store = &store;
+            async move {
+                let mut txn = store.begin().unwrap();
+                match value {
+                    Some(value) => txn.set(key, value).unwrap(),
+                    None => txn.delete(key).unwrap(),
+                }
+                let prepared = txn.prepare().await.unwrap();
+                let version = prepared.version().unwrap();
+                prepared.commit().await.unwrap();
+                version
+            }
+        };
+        let v1 = write(b"a", Some(b"1")).await;
+        let v2 = write(b"b", Some(b"1")).await;
+        let v3 = write(b"a", Some(b"2")).await;
+        let v4 = write(b"b", None).await;
+
+        let txn = store.begin().unwrap();
+        assert_eq!(txn.get_at_version(b"a", v1 - 1).unwrap(), None);
+        assert_eq!(txn.get_at_version(b"a", v1).unwrap(), Some(b"1".to_vec()));
+        assert_eq!(txn.get_at_version(b"a", v3).unwrap(), Some(b"2".to_vec()));
+        assert_eq!(txn.get_at_version(b"b", v4).unwrap(), None);
+
+        // The keys deleted since are returned, with the version of the value read.
+        let scanned = txn.scan_at_version(.., v2, None).unwrap();
+        assert_eq!(
+            scanned
+                .iter()
+                .map(|(k, v, version, _)| (k.clone(), v.clone(), *version))
+                .collect::<Vec<_>>(),
+            vec![
+                (b"a".to_vec(), b"1".to_vec(), v1),
+                (b"b".to_vec(), b"1".to_vec(), v2)
+            ]
+        );
+        assert_eq!(txn.scan_at_version(.., v4, None).unwrap().len(), 1);
+        assert_eq!(txn.scan_at_version(.., v2, Some(1)).unwrap().len(), 1);
+
+        // The values are read at the timestamps of their commits.
+        let ts = |version| txn.scan_at_version(..=&b"a"[..], version, None).unwrap()[0].3;
+        let (ts1, ts3) = (ts(v1), ts(v3));
+        assert_eq!(txn.get_at_ts(b"a", ts1 - 1).unwrap(), None);
+        assert_eq!(txn.get_at_ts(b"a", ts1).unwrap(), Some(b"1".to_vec()));
+        assert_eq!(txn.get_at_ts(b"a", ts3 - 1).unwrap(), Some(b"1".to_vec()));
+        assert_eq!(txn.get_at_ts(b"a", ts3).unwrap(), Some(b"2".to_vec()));
+        assert_eq!(txn.get_at_ts(b"b", ts3).unwrap(), Some(b"1".to_vec()));
+        assert_eq!(txn.get_at_ts(b"b", u64::MAX).unwrap(), None);
+        let scanned = txn.scan_at_ts(.., ts3, None).unwrap();
+        assert_eq!(
+            scanned
+                .iter()
+                .map(|(k, v, version, _)| (k.clone(), v.clone(), *version))
+                .collect::<Vec<_>>(),
+            vec![
+                (b"a".to_vec(), b"2".to_vec(), v3),
+                (b"b".to_vec(), b"1".to_vec(), v2)
+            ]
+        );
+        assert!(txn.scan_at_ts(.., ts1 - 1, None).unwrap().is_empty());
+        assert_eq!(txn.scan_at_ts(.., ts1, None).unwrap().len(), 1);
+
+        // The history of a key lists its versions, deletions included, oldest first.
+        let history = txn.history(b"b").unwrap();
+        assert_eq!(
+            history
+                .iter()
+                .map(|(_, value)| value.clone())
+                .collect::<Vec<_>>(),
+            vec![Some(b"1".to_vec()), None]
+        );
+        assert!(history[0].0 < history[1].0);
+        assert_eq!(txn.history(b"a").unwrap()[0], (ts1, Some(b"1".to_vec())));
+        assert!(txn.history(b"missing").unwrap().is_empty());
+
+        // Versions committed after the transaction began are not visible.
+        write(b"a", Some(b"3")).await;
+        assert_eq!(
+            txn.get_at_version(b"a", u64::MAX).unwrap(),
+            Some(b"2".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn commit_at_version() {
+        for is_ssi in [false, true] {
+            let (store, temp_dir) = create_store(is_ssi);
+            let mut txn = store.begin().unwrap();
+            txn.set(b"a", b"1").unwrap();
+            txn.commit().await.unwrap();
+
+            // A version after the last one is taken as it is, skipping the ones between.
+            let reader = store.begin().unwrap();
+            let mut txn = store.begin().unwrap();
+            txn.set(b"a", b"2").unwrap();
+            txn.commit_at(10).await.unwrap();
+            let txn = store.begin().unwrap();
+            assert_eq!(txn.get_at_version(b"a", 9).unwrap(), Some(b"1".to_vec()));
+            assert_eq!(txn.get_at_version(b"a", 10).unwrap(), Some(b"2".to_vec()));
+            assert_eq!(reader.get(b"a").unwrap(), Some(b"1".to_vec()));
+
+            // A version that is not after the last one is refused, and the transaction can
+            // still be committed.
+            let mut txn = store.begin().unwrap();
+            txn.set(b"b", b"1").unwrap();
+            assert!(matches!(
+                txn.commit_at(10).await,
+                Err(Error::NonMonotonicVersion(10, 10))
+            ));
+            assert!(matches!(
+                txn.commit_at(u64::MAX).await,
+                Err(Error::VersionOutOfRange(u64::MAX))
+            ));
+            txn.commit().await.unwrap();
+            let txn = store.begin().unwrap();
+            assert_eq!(txn.get_at_version(b"b", 10).unwrap(), None);
+            assert_eq!(txn.get_at_version(b"b", 11).unwrap(), Some(b"1".to_vec()));
+            drop(txn);
+            store.close().await.unwrap();
+
+            // The versions are kept across a reopen.
+            let mut opts = Options::new();
+            opts.dir = temp_dir.path().to_path_buf();
+            if is_ssi {
+                opts.isolation_level = IsolationLevel::SerializableSnapshotIsolation;
+            }
+            let store = Store::new(opts).expect("should reopen store");
+            let mut txn = store.begin().unwrap();
+            assert_eq!(txn.get_at_version(b"a", 10).unwrap(), Some(b"2".to_vec()));
+            txn.set(b"c", b"1").unwrap();
+            assert!(matches!(
+                txn.commit_at(5).await,
+                Err(Error::NonMonotonicVersion(5, 11))
+            ));
+            txn.commit_at(12).await.unwrap();
+            store.close().await.unwrap();
+        }
+    }
+
+    fn clog_size(dir: &std::path::Path) -> u64 {
+        std::fs::read_dir(dir.join("clog"))
+            .unwrap()
+            .map(|entry| entry.unwrap().metadata().unwrap().len())
+            .sum()
+    }
+
+    #[tokio::test]
+    async fn touch_keys() {
+        let (store, temp_dir) = create_store(false);
+        let big = vec![7u8; 16 * 1024];
+
+        let mut txn = store.begin().unwrap();
+        txn.set_with_ttl(b"short", b"1", Duration::from_millis(50))
+            .unwrap();
+        txn.set_with_ttl(b"gone", b"1", Duration::ZERO).unwrap();
+        txn.set(b"big", &big).unwrap();
+        txn.set_with_flags(b"flagged", b"v", 0b10).unwrap();
+        for key in [&b"p/1"[..], b"p/2", b"p/3", b"q"] {
+            txn.set(key, b"v").unwrap();
+        }
+        assert!(txn.get(b"gone").unwrap().is_none());
+        txn.commit().await.unwrap();
+
+        // Touching a key writes its new expiry, not its value.
+        let before = clog_size(temp_dir.path());
+        let mut txn = store.begin().unwrap();
+        assert!(txn.touch(b"short", Duration::from_secs(3600)).unwrap());
+        assert!(txn.touch(b"big", Duration::from_secs(3600)).unwrap());
+        assert!(txn.touch(b"flagged", Duration::from_secs(3600)).unwrap());
+        assert!(!txn.touch(b"gone", Duration::from_secs(3600)).unwrap());
+        assert!(!txn.touch(b"missing", Duration::from_secs(3600)).unwrap());
+        assert_eq!(txn.get(b"big").unwrap().unwrap(), big);
+        txn.commit().await.unwrap();
+        assert!(clog_size(temp_dir.path()) - before < 1024);
+
+        std::thread::sleep(Duration::from_millis(100));
+        let txn = store.begin().unwrap();
+        assert_eq!(txn.get(b"short").unwrap().unwrap(), b"1");
+        assert_eq!(txn.get(b"big").unwrap().unwrap(), big);
+        assert_eq!(txn.get_flags(b"flagged").unwrap(), Some(0b10));
+        assert!(txn.get(b"gone").unwrap().is_none());
+        drop(txn);
+
+        // A prefix is touched at once, and the touch is read by the transaction.
+        let mut txn = store.begin().unwrap();
+        assert_eq!(txn.touch_prefix(b"p/", Duration::ZERO).unwrap(), 3);
+        assert!(txn.get(b"p/1").unwrap().is_none());
+        assert_eq!(txn.scan(&b"p/"[..]..&b"q"[..], None).unwrap().len(), 0);
+        txn.commit().await.unwrap();
+        let txn = store.begin().unwrap();
+        assert!(txn.get(b"p/2").unwrap().is_none());
+        assert_eq!(txn.get(b"q").unwrap().unwrap(), b"v");
+        drop(txn);
+
+        // The expiries are replayed from the commit log.
+        store.close().await.unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        let store = Store::new(opts).expect("should open store");
+        let txn = store.begin().unwrap();
+        assert_eq!(txn.get(b"short").unwrap().unwrap(), b"1");
+        assert_eq!(txn.get(b"big").unwrap().unwrap(), big);
+        assert_eq!(txn.get_flags(b"flagged").unwrap(), Some(0b10));
+        assert!(txn.get(b"p/3").unwrap().is_none());
+        assert_eq!(txn.scan(.., None).unwrap().len(), 4);
+        drop(txn);
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn contains_key_does_not_read_the_values() {
+        let (store, _temp_dir) = create_store(false);
+        let core = store.inner.as_ref().unwrap().core.clone();
+        let big = vec![7u8; 16 * 1024];
+
+        let mut txn = store.begin().unwrap();
+        txn.set(b"big", &big).unwrap();
+        txn.set(b"deleted", b"1").unwrap();
+        txn.set_with_ttl(b"gone", b"1", Duration::ZERO).unwrap();
+        txn.commit().await.unwrap();
+        let mut txn = store.begin().unwrap();
+        txn.delete(b"deleted").unwrap();
+        txn.commit().await.unwrap();
+        core.value_cache.clear();
+
+        let mut txn = store.begin().unwrap();
+        assert!(txn.contains_key(b"big").unwrap());
+        assert!(!txn.contains_key(b"deleted").unwrap());
+        assert!(!txn.contains_key(b"gone").unwrap());
+        assert!(!txn.contains_key(b"missing").unwrap());
+        assert_eq!(core.value_cache.len(), 0);
+
+        // The writes of the transaction are seen.
+        txn.set(b"missing", b"1").unwrap();
+        txn.delete(b"big").unwrap();
+        assert!(txn.contains_key(b"missing").unwrap());
+        assert!(!txn.contains_key(b"big").unwrap());
+        drop(txn);
+
+        // A key found missing conflicts with its concurrent write.
+        let mut first = store.begin().unwrap();
+        let mut second = store.begin().unwrap();
+        assert!(!first.contains_key(b"new").unwrap());
+        first.set(b"other", b"1").unwrap();
+        second.set(b"new", b"1").unwrap();
+        second.commit().await.unwrap();
+        assert!(matches!(
+            first.commit().await,
+            Err(Error::TransactionReadConflict)
+        ));
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn meta_bits_are_set_without_the_value() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.indexed_flags = 0b11;
+        let store = Store::new(opts.clone()).expect("should create store");
+        let big = vec![7u8; 16 * 1024];
+
+        let mut txn = store.begin().unwrap();
+        txn.set_with_flags(b"big", &big, 0b01).unwrap();
+        txn.set_with_ttl(b"gone", b"1", Duration::ZERO).unwrap();
+        txn.commit().await.unwrap();
+
+        // Setting the bits writes the new flags, not the value.
+        let before = clog_size(temp_dir.path());
+        let mut txn = store.begin().unwrap();
+        assert_eq!(txn.set_meta_bits(b"big", 0b10, 0b01).unwrap(), Some(0b10));
+        assert_eq!(txn.set_meta_bits(b"gone", 0b10, 0).unwrap(), None);
+        assert_eq!(txn.set_meta_bits(b"missing", 0b10, 0).unwrap(), None);
+        assert_eq!(txn.get_flags(b"big").unwrap(), Some(0b10));
+        assert_eq!(txn.get(b"big").unwrap().unwrap(), big);
+        txn.commit().await.unwrap();
+        assert!(clog_size(temp_dir.path()) - before < 1024);
+        assert!(store.flagged_keys(0, ..).unwrap().is_empty());
+        assert_eq!(store.flagged_keys(1, ..).unwrap(), [b"big"]);
+
+        // The bits are changed from the flags read: a concurrent change conflicts.
+        let mut first = store.begin().unwrap();
+        let mut second = store.begin().unwrap();
+        assert_eq!(first.set_meta_bits(b"big", 0b01, 0).unwrap(), Some(0b11));
+        assert_eq!(second.set_meta_bits(b"big", 0, 0b10).unwrap(), Some(0));
+        first.commit().await.unwrap();
+        assert!(matches!(
+            second.commit().await,
+            Err(Error::TransactionReadConflict)
+        ));
+
+        // The flags are replayed from the commit log.
+        store.close().await.unwrap();
+        let store = Store::new(opts).expect("should open store");
+        let txn = store.begin().unwrap();
+        assert_eq!(txn.get(b"big").unwrap().unwrap(), big);
+        assert_eq!(txn.get_flags(b"big").unwrap(), Some(0b11));
+        assert_eq!(store.flagged_keys(0, ..).unwrap(), [b"big"]);
+        drop(txn);
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn keys_are_renamed_without_the_value() {
+        let (store, temp_dir) = create_store(false);
+        let big = vec![7u8; 16 * 1024];
+
+        let mut txn = store.begin().unwrap();
+        txn.set_with_flags(b"old", &big, 0b10).unwrap();
+        txn.set(b"new", b"overwritten").unwrap();
+        txn.set_with_ttl(b"gone", b"1", Duration::ZERO).unwrap();
+        txn.commit().await.unwrap();
+
+        // Renaming a key writes a record naming it, not its value.
+        let before = clog_size(temp_dir.path());
+        let mut txn = store.begin().unwrap();
+        assert!(txn.rename(b"old", b"new").unwrap());
+        assert!(!txn.rename(b"gone", b"other").unwrap());
+        assert!(!txn.rename(b"missing", b"other").unwrap());
+        assert_eq!(txn.get(b"new").unwrap().unwrap(), big);
+        assert!(txn.get(b"old").unwrap().is_none());
+        assert_eq!(txn.get_flags(b"new").unwrap(), Some(0b10));
+        let entries = txn.scan(.., None).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].1, big);
+        txn.commit().await.unwrap();
+        assert!(clog_size(temp_dir.path()) - before < 1024);
+
+        let txn = store.begin().unwrap();
+        assert_eq!(txn.get(b"new").unwrap().unwrap(), big);
+        assert!(txn.get(b"old").unwrap().is_none());
+        assert!(txn.get(b"other").unwrap().is_none());
+        assert_eq!(txn.get_flags(b"new").unwrap(), Some(0b10));
+        drop(txn);
+
+        // A key written by the transaction is renamed with its write.
+        let mut txn = store.begin().unwrap();
+        txn.set(b"a", b"1").unwrap();
+        assert!(txn.rename(b"a", b"b").unwrap());
+        assert!(txn.rename(b"new", b"c").unwrap());
+        assert!(txn.rename(b"c", b"d").unwrap());
+        assert_eq!(txn.get(b"d").unwrap().unwrap(), big);
+        txn.commit().await.unwrap();
+
+        // A rename conflicts with the writes of the key it renames.
+        let mut txn = store.begin().unwrap();
+        assert!(txn.rename(b"d", b"e").unwrap());
+        let mut other = store.begin().unwrap();
+        other.set(b"d", b"2").unwrap();
+        other.commit().await.unwrap();
+        assert!(matches!(
+            txn.commit().await,
+            Err(Error::TransactionReadConflict)
+        ));
+        let mut txn = store.begin().unwrap();
+        txn.set(b"d", &big).unwrap();
+        txn.commit().await.unwrap();
+        let mut txn = store.begin().unwrap();
+        assert!(txn.rename(b"d", b"e").unwrap());
+        txn.commit().await.unwrap();
+
+        // The renames are replayed when the store is reopened.
+        store.close().await.unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        let store = Store::new(opts).expect("should open store");
+        let txn = store.begin().unwrap();
+        let entries = txn.scan(.., None).unwrap();
+        let keys: Vec<&[u8]> = entries.iter().map(|entry| &entry.0[..]).collect();
+        assert_eq!(keys, [&b"b"[..], b"e"]);
+        assert_eq!(txn.get(b"b").unwrap().unwrap(), b"1");
+        assert_eq!(txn.get(b"e").unwrap().unwrap(), big);
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn reads_fail_past_their_deadline() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.max_value_threshold = 0;
+        let store = Store::new(opts.clone()).expect("should create store");
+        let mut txn = store.begin().unwrap();
+        txn.set(b"k1", b"v1").unwrap();
+        txn.set(b"k2", b"v2").unwrap();
+        txn.commit().await.unwrap();
+        store.close().await.unwrap();
+
+        // The values are read from the commit log once the store is reopened.
+        let store = Store::new(opts).expect("should open store");
+        let txn = store.begin().unwrap();
+        let passed = ReadOptions {
+            deadline: Some(Instant::now()),
+        };
+        assert!(matches!(
+            txn.get_with_options(b"k1", &passed),
+            Err(Error::DeadlineExceeded)
+        ));
+        assert!(matches!(
+            txn.scan_with_options(.., None, &passed),
+            Err(Error::DeadlineExceeded)
+        ));
+
+        // A read waiting for the commit log gives up at the deadline.
+        let core = &store.inner.as_ref().unwrap().core;
+        let writing = core.clog.as_ref().unwrap().write();
+        let options = ReadOptions::with_timeout(Duration::from_millis(20));
+        assert!(matches!(
+            txn.get_with_options(b"k1", &options),
+            Err(Error::DeadlineExceeded)
+        ));
+        drop(writing);
+
+        let options = ReadOptions::with_timeout(Duration::from_secs(60));
+        assert_eq!(
+            txn.get_with_options(b"k1", &options).unwrap().unwrap(),
+            b"v1"
+        );
+        let entries = txn.scan_with_options(.., None, &options).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].1, b"v2");
+        drop(txn);
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn compare_and_swap() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        let store = Store::new(opts).expect("should create store");
+
+        // A missing key is expected as None.
+        store.cas(b"lock", None, Some(b"owner1")).await.unwrap();
+        let err = store.cas(b"lock", None, Some(b"owner2")).await.unwrap_err();
+        assert!(matches!(err, Error::ValueMismatch(Some(v)) if v == b"owner1"));
+
+        let mut txn = store.begin().unwrap();
+        txn.cas(b"lock", Some(b"owner1"), Some(b"owner2")).unwrap();
+        // The transaction reads its own write.
+        assert!(matches!(
+            txn.cas(b"lock", Some(b"owner1"), None),
+            Err(Error::ValueMismatch(Some(_)))
+        ));
+        txn.commit().await.unwrap();
+
+        // A swap made concurrently makes the commit conflict.
+        let mut txn = store.begin().unwrap();
+        txn.cas(b"lock", Some(b"owner2"), None).unwrap();
+        store
+            .cas(b"lock", Some(b"owner2"), Some(b"owner3"))
+            .await
+            .unwrap();
+        assert!(matches!(
+            txn.commit().await,
+            Err(Error::TransactionReadConflict)
+        ));
+
+        store.cas(b"lock", Some(b"owner3"), None).await.unwrap();
+        let txn = store.begin_with_mode(Mode::ReadOnly).unwrap();
+        assert!(txn.get(b"lock").unwrap().is_none());
+        drop(txn);
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn counters() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.max_value_threshold = 0;
+        let store = Store::new(opts).expect("should create store");
+
+        // A missing counter starts at 0.
+        assert_eq!(store.incr(b"seq", 5).await.unwrap(), 5);
+        assert_eq!(store.decr(b"seq", 2).await.unwrap(), 3);
+
+        // Concurrent increments do not conflict, and are applied to the latest value.
+        let mut first = store.begin().unwrap();
+        let mut second = store.begin().unwrap();
+        first.incr(b"seq", 10).unwrap();
+        first.incr(b"seq", 10).unwrap();
+        second.incr(b"seq", 1).unwrap();
+        second.set(b"other", b"v").unwrap();
+        second.commit().await.unwrap();
+        first.commit().await.unwrap();
+        // The reads of the transaction do not see its increments.
+        let mut txn = store.begin().unwrap();
+        txn.incr(b"seq", 1).unwrap();
+        assert_eq!(txn.get(b"seq").unwrap().unwrap(), 24u64.to_be_bytes());
+        txn.rollback();
+
+        // A counter written by the transaction is incremented in its write.
+        let mut txn = store.begin().unwrap();
+        txn.set(b"seq", &u64::MAX.to_be_bytes()).unwrap();
+        assert!(matches!(txn.incr(b"seq", 1), Err(Error::CounterOverflow)));
+        txn.decr(b"seq", 1).unwrap();
+        assert_eq!(
+            txn.get(b"seq").unwrap().unwrap(),
+            (u64::MAX - 1).to_be_bytes()
+        );
+        txn.commit().await.unwrap();
+
+        // Overflows are found on commit, with the key left as it was.
+        let mut txn = store.begin().unwrap();
+        txn.incr(b"seq", 2).unwrap();
+        assert!(matches!(txn.commit().await, Err(Error::CounterOverflow)));
+        assert!(matches!(
+            store.decr(b"missing", 1).await,
+            Err(Error::CounterOverflow)
+        ));
+        assert!(matches!(
+            store.incr(b"other", 1).await,
+            Err(Error::InvalidCounter)
+        ));
+
+        // A write replaces the increments of its key.
+        let mut txn = store.begin_with_mode(Mode::WriteOnly).unwrap();
+        txn.incr(b"seq", 1).unwrap();
+        txn.delete(b"seq").unwrap();
+        txn.commit().await.unwrap();
+        assert_eq!(store.incr(b"seq", 1).await.unwrap(), 1);
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn get_many() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.max_value_threshold = 0;
+        let store = Store::new(opts).expect("should create store");
+
+        let mut txn = store.begin().unwrap();
+        for i in 0..100u32 {
+            txn.set(&i.to_be_bytes(), &[i as u8; 100]).unwrap();
+        }
+        txn.commit().await.unwrap();
+
+        let mut txn = store.begin().unwrap();
+        txn.set(&5u32.to_be_bytes(), b"own").unwrap();
+        txn.delete(&7u32.to_be_bytes()).unwrap();
+        let keys: Vec<_> = [50u32, 5, 7, 200, 3, 50]
+            .iter()
+            .map(|i| i.to_be_bytes())
+            .collect();
+        let reads = store.stats().log_reads;
+        let values = txn.get_many(&keys).unwrap();
+        assert_eq!(
+            values,
+            [
+                Some(vec![50; 100]),
+                Some(b"own".to_vec()),
+                None,
+                None,
+                Some(vec![3; 100]),
+                Some(vec![50; 100]),
+            ]
+        );
+        assert_eq!(store.stats().log_reads, reads + 3);
+
+        // The values read are cached.
+        let reads = store.stats().cache_reads;
+        assert_eq!(txn.get(&3u32.to_be_bytes()).unwrap().unwrap(), [3; 100]);
+        assert_eq!(store.stats().cache_reads, reads + 1);
+        assert!(matches!(
+            txn.get_many(&[b"".as_slice()]),
+            Err(Error::EmptyKey)
+        ));
+        txn.commit().await.unwrap();
+        store.close().await.unwrap();
     }
 }
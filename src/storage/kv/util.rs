@@ -1,10 +1,62 @@
-use std::path::PathBuf;
+use std::time::Instant;
 
-use bytes::Bytes;
+use bytes::{BufMut, Bytes, BytesMut};
 use chrono::Utc;
 use crc32fast::Hasher as crc32Hasher;
 use sha2::{Digest, Sha256};
 
+use crate::storage::kv::error::{Error, Result};
+
+/// Prefix of the keys reserved for the internal subsystems of the store (streams, queues, ...).
+/// User scans skip these keys unless the scanned range starts inside the prefix.
+pub(crate) const SYSTEM_KEY_PREFIX: &[u8] = b"\xffskv/";
+
+/// Builds a key in the system keyspace for the given subsystem and name.
+/// The name is length prefixed so that keys of different names never prefix each other:
+/// [SYSTEM_KEY_PREFIX][subsystem][name_len: u16][name]
+pub(crate) fn system_key(subsystem: &[u8], name: &[u8]) -> Result<BytesMut> {
+    if name.is_empty() {
+        return Err(Error::EmptyKey);
+    }
+    if name.len() > u16::MAX as usize {
+        return Err(Error::MaxKeyLengthExceeded);
+    }
+
+    let mut key =
+        BytesMut::with_capacity(SYSTEM_KEY_PREFIX.len() + subsystem.len() + 2 + name.len() + 8);
+    key.put(SYSTEM_KEY_PREFIX);
+    key.put(subsystem);
+    key.put_u16(name.len() as u16);
+    key.put(name);
+    Ok(key)
+}
+
+/// Returns `Error::DeadlineExceeded` if the deadline, if any, has passed.
+pub(crate) fn check_deadline(deadline: Option<Instant>) -> Result<()> {
+    match deadline {
+        Some(deadline) if Instant::now() >= deadline => Err(Error::DeadlineExceeded),
+        _ => Ok(()),
+    }
+}
+
+/// Returns true if the key belongs to the system keyspace.
+pub(crate) fn is_system_key(key: &[u8]) -> bool {
+    key.starts_with(SYSTEM_KEY_PREFIX)
+}
+
+/// Returns the smallest key greater than all the keys starting with the prefix,
+/// or `None` if there is no such key (the prefix is empty or only made of 0xff bytes).
+pub(crate) fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
 /// Calculates the CRC32 hash of a byte array.
 /// It creates a new CRC32 hasher, updates it with the byte array, and finalizes the hash.
 /// It returns the hash as a 32-bit unsigned integer.
@@ -37,32 +89,3 @@ pub(crate) fn sha256(arg: Bytes) -> Bytes {
     let result = hasher.finalize();
     Bytes::copy_from_slice(result.as_slice())
 }
-
-pub(crate) fn sanitize_directory(directory: &str) -> std::io::Result<PathBuf> {
-    // Convert the directory string to a PathBuf
-    let mut path = PathBuf::from(directory);
-
-    // Normalize the path (resolve '..' and '.' components)
-    path = path.canonicalize()?;
-
-    // Check if the path is absolute
-    if !path.is_absolute() {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            "Directory path must be absolute",
-        ));
-    }
-
-    // Check if the path contains any '..' components after normalization
-    if path
-        .components()
-        .any(|component| component.as_os_str() == "..")
-    {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            "Directory path must not contain '..' components",
-        ));
-    }
-
-    Ok(path)
-}
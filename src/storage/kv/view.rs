@@ -0,0 +1,201 @@
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use vart::{
+    iter::IterationPointer, snapshot::Snapshot as VartSnapshot, TrieError, VariableSizeKey,
+};
+
+use crate::storage::kv::{
+    entry::{Value, ValueRef},
+    error::{Error, Result},
+    snapshot::is_expired,
+    store::Core,
+    transaction::{to_key_range, ScanResult},
+    util::is_system_key,
+};
+
+/// A read-only view of the store pinned at the version of the index it was taken at,
+/// returned by [`Store::snapshot`](crate::Store::snapshot).
+///
+/// A view is cheap to clone, and its clones share the same version: they can be sent to
+/// other threads and read concurrently, without opening a transaction nor taking the lock of
+/// the index. Commits made after the view was taken are not seen by it.
+///
+/// A view is not a transaction: its reads are not checked for conflicts, it is not listed by
+/// `Store::active_transactions` and does not count towards `Options::max_active_transactions`.
+/// It keeps the versions it refers to in memory until its last clone is dropped.
+#[derive(Clone)]
+pub struct ReadView {
+    core: Arc<Core>,
+    snap: Arc<VartSnapshot<VariableSizeKey, Bytes>>,
+    reader: Option<Arc<IterationPointer<VariableSizeKey, Bytes>>>, // None if the index is empty.
+}
+
+impl ReadView {
+    pub(crate) fn new(core: &Arc<Core>) -> Result<Self> {
+        core.invariants.check()?;
+        let mut snap = core.indexer.read().snapshot()?;
+        let reader = match snap.new_reader() {
+            Ok(reader) => Some(Arc::new(reader)),
+            Err(TrieError::SnapshotEmpty) => None,
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            core: core.clone(),
+            snap: Arc::new(snap),
+            reader,
+        })
+    }
+
+    /// Returns the version of the index the view is pinned at.
+    pub fn version(&self) -> u64 {
+        self.snap.version()
+    }
+
+    /// Gets the value of a key, if it exists.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.core.invariants.check()?;
+        if key.is_empty() {
+            return Err(Error::EmptyKey);
+        }
+        match self.latest(key)? {
+            Some(val_ref) => val_ref.resolve_merged(key, None).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the version of the live value of a key, if it exists.
+    pub(crate) fn live_version(&self, key: &[u8]) -> Result<Option<u64>> {
+        Ok(self.latest(key)?.map(|val_ref| val_ref.ts()))
+    }
+
+    /// Scans a range of keys, as [`Transaction::scan`](crate::Transaction::scan) does.
+    pub fn scan<'b, R>(&self, range: R, limit: Option<usize>) -> Result<Vec<ScanResult>>
+    where
+        R: RangeBounds<&'b [u8]>,
+    {
+        self.core.invariants.check()?;
+
+        // System keys are only returned if the range starts inside the system keyspace.
+        let include_system_keys = match range.start_bound() {
+            Bound::Included(start) | Bound::Excluded(start) => is_system_key(start),
+            Bound::Unbounded => false,
+        };
+
+        let mut results = Vec::new();
+        let Some(reader) = &self.reader else {
+            return Ok(results);
+        };
+        let Some(range) = self.core.keys.range(to_key_range(&range)) else {
+            return Ok(results);
+        };
+
+        let limit = limit.unwrap_or(usize::MAX);
+        for (key, value, version, ts) in reader.range(range) {
+            if results.len() >= limit {
+                break;
+            }
+            let key = self.core.keys.decode(key)?;
+            if !include_system_keys && is_system_key(&key) {
+                continue;
+            }
+            if let Some(val_ref) = self.decode(*version, value)? {
+                let value = val_ref.resolve_merged(&key, None)?;
+                results.push((key, value, *version, *ts));
+            }
+        }
+        Ok(results)
+    }
+
+    /// Returns the live value of a key, without resolving it.
+    fn latest(&self, key: &[u8]) -> Result<Option<ValueRef>> {
+        let Some(key) = self.core.keys.lookup(key) else {
+            return Ok(None);
+        };
+        let (value, version, _) = match self.snap.get(&key) {
+            Ok(found) => found,
+            Err(TrieError::KeyNotFound) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        self.decode(version, &value)
+    }
+
+    /// Decodes a value of the index, or returns None if it is deleted or expired.
+    fn decode(&self, version: u64, value: &Bytes) -> Result<Option<ValueRef>> {
+        let mut val_ref = ValueRef::new(self.core.clone());
+        val_ref.decode(version, value)?;
+        let md = val_ref.key_value_metadata();
+        if md.is_some_and(|md| md.deleted())
+            || is_expired(&self.core, md.and_then(|md| md.expires_at()))
+        {
+            return Ok(None);
+        }
+        Ok(Some(val_ref))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::ReadView;
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+
+    use tempdir::TempDir;
+
+    #[tokio::test]
+    async fn views_are_read_from_many_threads() {
+        fn is_send_sync<T: Send + Sync + Clone>() {}
+        is_send_sync::<ReadView>();
+
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        opts.max_value_threshold = 0;
+        let store = Store::new(opts).expect("should create store");
+
+        let empty = store.snapshot().unwrap();
+        let mut txn = store.begin().unwrap();
+        for i in 0..100u32 {
+            txn.set(&i.to_be_bytes(), &i.to_le_bytes()).unwrap();
+        }
+        txn.commit().await.unwrap();
+        assert!(empty.get(&1u32.to_be_bytes()).unwrap().is_none());
+        assert!(empty.scan(.., None).unwrap().is_empty());
+
+        let view = store.snapshot().unwrap();
+        // The commits made after the view was taken are not seen by it.
+        let mut txn = store.begin().unwrap();
+        txn.delete(&1u32.to_be_bytes()).unwrap();
+        txn.set(&100u32.to_be_bytes(), b"new").unwrap();
+        txn.commit().await.unwrap();
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let view = view.clone();
+                thread::spawn(move || {
+                    for i in 0..100u32 {
+                        let value = view.get(&i.to_be_bytes()).unwrap().unwrap();
+                        assert_eq!(value, i.to_le_bytes());
+                    }
+                    assert!(view.get(&100u32.to_be_bytes()).unwrap().is_none());
+                    view.scan(.., Some(50)).unwrap().len()
+                })
+            })
+            .collect();
+        for reader in readers {
+            assert_eq!(reader.join().unwrap(), 50);
+        }
+        assert_eq!(view.scan(.., None).unwrap().len(), 100);
+
+        let view = store.snapshot().unwrap();
+        assert!(view.get(&1u32.to_be_bytes()).unwrap().is_none());
+        let start = 99u32.to_be_bytes();
+        let entries = view.scan(&start[..].., None).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].1, b"new");
+        store.close().await.unwrap();
+    }
+}
@@ -0,0 +1,199 @@
+use std::sync::Arc;
+
+use async_channel::{unbounded, Receiver, Sender};
+use parking_lot::Mutex;
+
+use crate::storage::kv::{
+    entry::Entry,
+    error::Result,
+    mirror::MirrorBatch,
+    store::{Core, Store},
+};
+
+/// The kind of a mutation, see [`WatchFilter::operation`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    /// A write of a value.
+    Set,
+    /// A deletion of the key.
+    Delete,
+}
+
+/// Which of the committed mutations a [`Watch`] receives. The mutations are filtered as they
+/// are committed, before they are decoded and queued, so that a watch of a few keys of a busy
+/// store is not sent the others. The default filter passes all the mutations.
+#[derive(Clone, Debug, Default)]
+pub struct WatchFilter {
+    pub prefix: Vec<u8>, // Prefix of the keys watched, empty for all the keys.
+    pub flags: u64, // User flags the values written must all carry, see `Transaction::set_with_flags`.
+    pub operation: Option<Operation>, // Kind of the mutations watched, or None for both.
+}
+
+impl WatchFilter {
+    /// Returns the filter of the mutations of the keys starting with `prefix`.
+    pub fn prefix(prefix: &[u8]) -> Self {
+        Self {
+            prefix: prefix.to_vec(),
+            ..Self::default()
+        }
+    }
+
+    /// Returns true if the filter passes the mutation of an entry. A deletion carries no
+    /// flags, so the filters requiring flags only pass writes.
+    fn matches(&self, entry: &Entry) -> bool {
+        let operation = match entry.is_deleted() {
+            true => Operation::Delete,
+            false => Operation::Set,
+        };
+        entry.key.starts_with(&self.prefix)
+            && entry.flags() & self.flags == self.flags
+            && self.operation.map_or(true, |watched| watched == operation)
+    }
+}
+
+/// A subscription to the mutations committed to a store that pass its filter, returned by
+/// [`Store::watch`]. The transactions are received in commit order, each as a batch of its
+/// mutations that pass the filter; the ones with none are not sent. The mutations are queued
+/// until they are received, and the watch stops when it is dropped.
+pub struct Watch {
+    rx: Receiver<MirrorBatch>,
+}
+
+impl Watch {
+    /// Waits for the next batch of mutations, or returns None once the store is closed and
+    /// the batches queued are received.
+    pub async fn recv(&self) -> Option<MirrorBatch> {
+        self.rx.recv().await.ok()
+    }
+
+    /// Returns the next batch of mutations if one is queued.
+    pub fn try_recv(&self) -> Option<MirrorBatch> {
+        self.rx.try_recv().ok()
+    }
+}
+
+/// The batches of a commit to send to the watches, once it is written.
+pub(crate) type Deliveries = Vec<(Sender<MirrorBatch>, MirrorBatch)>;
+
+/// `Watches` keeps the watches of a store, and filters the mutations committed for them.
+#[derive(Default)]
+pub(crate) struct Watches {
+    watches: Mutex<Vec<(WatchFilter, Sender<MirrorBatch>)>>,
+}
+
+impl Watches {
+    /// Returns a new watch of the mutations committed from now on that pass `filter`.
+    pub(crate) fn watch(&self, filter: WatchFilter) -> Watch {
+        let (tx, rx) = unbounded();
+        self.watches.lock().push((filter, tx));
+        Watch { rx }
+    }
+
+    /// Returns the batches of the mutations of a transaction to send to the watches once it
+    /// is written, built as the batches of the mirror are. The watches dropped are removed.
+    pub(crate) fn batches(
+        &self,
+        core: &Arc<Core>,
+        entries: &[Entry],
+        commit_ts: u64,
+    ) -> Result<Deliveries> {
+        let mut watches = self.watches.lock();
+        watches.retain(|(_, tx)| !tx.is_closed());
+        let mut deliveries = Vec::new();
+        for (filter, tx) in watches.iter() {
+            let entries: Vec<Entry> = entries
+                .iter()
+                .filter(|entry| filter.matches(entry))
+                .cloned()
+                .collect();
+            if entries.is_empty() {
+                continue;
+            }
+            let batch = MirrorBatch::from_entries(core, &entries, commit_ts)?;
+            if !batch.mutations.is_empty() {
+                deliveries.push((tx.clone(), batch));
+            }
+        }
+        Ok(deliveries)
+    }
+
+    /// Stops the watches, which receive the batches queued and then None.
+    pub(crate) fn close(&self) {
+        self.watches.lock().clear();
+    }
+}
+
+/// Sends the batches of a commit written to the watches.
+pub(crate) fn deliver(deliveries: Deliveries) {
+    for (tx, batch) in deliveries {
+        // The channels are unbounded, and closed only when the watch is dropped.
+        let _ = tx.try_send(batch);
+    }
+}
+
+impl Store {
+    /// Watches the mutations committed to the store from now on that pass `filter`, see
+    /// [`Watch`]. The values are the ones the mirrors are sent: decompressed, with the merge
+    /// operands merged and the renamed keys resolved.
+    pub fn watch(&self, filter: WatchFilter) -> Watch {
+        self.inner.as_ref().unwrap().core.watches.watch(filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Operation, WatchFilter};
+    use crate::storage::kv::mirror::Mutation;
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+
+    use tempdir::TempDir;
+
+    #[tokio::test]
+    async fn watches_receive_the_mutations_they_filter() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().to_path_buf();
+        let store = Store::new(opts).expect("should create store");
+
+        let users = store.watch(WatchFilter::prefix(b"user/"));
+        let flagged = store.watch(WatchFilter {
+            flags: 0b10,
+            ..WatchFilter::default()
+        });
+        let deletes = store.watch(WatchFilter {
+            operation: Some(Operation::Delete),
+            ..WatchFilter::prefix(b"user/")
+        });
+
+        let mut txn = store.begin().unwrap();
+        txn.set(b"user/1", b"a").unwrap();
+        txn.set_with_flags(b"order/1", b"b", 0b11).unwrap();
+        txn.commit().await.unwrap();
+        let mut txn = store.begin().unwrap();
+        txn.delete(b"user/1").unwrap();
+        txn.set(b"order/2", b"c").unwrap();
+        txn.commit().await.unwrap();
+
+        let mutation = |key: &[u8], value: Option<&[u8]>| Mutation {
+            key: key.to_vec(),
+            value: value.map(|value| value.to_vec()),
+        };
+        let batch = users.try_recv().unwrap();
+        assert_eq!(batch.mutations, vec![mutation(b"user/1", Some(b"a"))]);
+        let batch = users.try_recv().unwrap();
+        assert_eq!(batch.mutations, vec![mutation(b"user/1", None)]);
+        assert!(users.try_recv().is_none());
+
+        let batch = flagged.try_recv().unwrap();
+        assert_eq!(batch.mutations, vec![mutation(b"order/1", Some(b"b"))]);
+        assert!(flagged.try_recv().is_none());
+
+        let batch = deletes.try_recv().unwrap();
+        assert_eq!(batch.mutations, vec![mutation(b"user/1", None)]);
+        assert!(deletes.try_recv().is_none());
+
+        store.close().await.unwrap();
+        assert!(users.recv().await.is_none());
+    }
+}
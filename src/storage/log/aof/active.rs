@@ -0,0 +1,439 @@
+use std::cell::UnsafeCell;
+use std::cmp;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::slice;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+
+use parking_lot::Mutex;
+
+use crate::storage::log::{open_segment_file, Error, IOError, Options, Result, BLOCK_SIZE};
+use crate::storage::vfs::VfsFile;
+
+/// The number of pages the appends can fill ahead of the flusher.
+const PAGES: usize = 4;
+
+/// A page of the active segment, holding the bytes from `start` to `start + BLOCK_SIZE`.
+struct Page {
+    buf: Box<[UnsafeCell<u8>]>,
+
+    /// The offset of the first byte of the page. The flusher moves it `PAGES` pages ahead
+    /// once the page is written, which recycles the page for the appends waiting for it.
+    start: AtomicU64,
+
+    /// The number of bytes copied into the page.
+    filled: AtomicUsize,
+}
+
+// The appends copy into disjoint ranges of a page, and the flusher reads a range only once
+// `filled` counts it, so the buffer is never read and written at the same place at the same
+// time.
+unsafe impl Sync for Page {}
+
+impl Page {
+    fn new(start: u64) -> Self {
+        Self {
+            buf: (0..BLOCK_SIZE).map(|_| UnsafeCell::new(0)).collect(),
+            start: AtomicU64::new(start),
+            filled: AtomicUsize::new(0),
+        }
+    }
+
+    fn ptr(&self) -> *mut u8 {
+        UnsafeCell::raw_get(self.buf.as_ptr())
+    }
+}
+
+/// A byte range reserved in the active segment with `ActiveSegment::reserve`, that the
+/// caller fills in place. It spans at most two pages.
+pub(crate) struct Reservation {
+    offset: u64,
+    len: usize,
+}
+
+impl Reservation {
+    pub(crate) fn offset(&self) -> u64 {
+        self.offset
+    }
+}
+
+/// The file of the active segment, held by the flusher.
+struct Flusher {
+    file: Box<dyn VfsFile>,
+
+    /// The offset up to which the bytes are written to the file.
+    flushed: u64,
+}
+
+/// The active segment of an append-only log, appended to by concurrent producers.
+///
+/// An append reserves the byte range of its record by moving `tail` with an atomic
+/// operation, then copies the record into the pages covering the range, concurrently with
+/// the other appends: nothing is locked on the way. The bytes go through a ring of `PAGES`
+/// pages, and an append past the ring waits for the flusher to recycle the page it needs.
+///
+/// A single flusher at a time, holding the file, writes the pages in order once all the
+/// bytes reserved in them are filled, so that the file never has a hole. The records are
+/// laid out as with `Segment<0>`, one after the other.
+pub(crate) struct ActiveSegment {
+    /// The unique identifier of the segment.
+    pub(crate) id: u64,
+
+    /// The maximum size of the segment file.
+    max_file_size: u64,
+
+    /// The base offset of the file.
+    file_header_offset: u64,
+
+    /// The offset of the first page when the segment was opened.
+    base: u64,
+
+    /// The offset up to which the bytes are reserved by the appends.
+    tail: AtomicU64,
+
+    pages: Box<[Page]>,
+
+    flusher: Mutex<Flusher>,
+
+    /// A flag indicating whether the segment is closed or not.
+    closed: AtomicBool,
+
+    /// A flag set when writing to the file failed, for the appends waiting for a page to
+    /// give up.
+    failed: AtomicBool,
+}
+
+impl ActiveSegment {
+    pub(crate) fn open(dir: &Path, id: u64, opts: &Options) -> Result<Self> {
+        opts.validate()?;
+
+        let (file, _, file_header_offset, file_offset) = open_segment_file(dir, id, opts)?;
+        let pages = (0..PAGES)
+            .map(|i| Page::new(file_offset + (i * BLOCK_SIZE) as u64))
+            .collect();
+
+        Ok(Self {
+            id,
+            max_file_size: opts.max_file_size,
+            file_header_offset,
+            base: file_offset,
+            tail: AtomicU64::new(file_offset),
+            pages,
+            flusher: Mutex::new(Flusher {
+                file,
+                flushed: file_offset,
+            }),
+            closed: AtomicBool::new(false),
+            failed: AtomicBool::new(false),
+        })
+    }
+
+    /// Appends a record to the segment, and returns its offset, or None if it does not fit
+    /// into the remaining space of the segment.
+    pub(crate) fn append(&self, rec: &[u8]) -> Result<Option<u64>> {
+        if self.closed.load(Ordering::Acquire) {
+            return Err(Error::SegmentClosed);
+        }
+
+        if rec.is_empty() {
+            return Err(Error::EmptyBuffer);
+        }
+
+        match self.allocate(rec.len()) {
+            Some(offset) => {
+                self.fill(offset, rec)?;
+                Ok(Some(offset))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Reserves `len` bytes, at most `BLOCK_SIZE`, for the caller to fill in place, or
+    /// returns None if they do not fit into the remaining space of the segment. The pages
+    /// of the bytes are ready once it returns.
+    pub(crate) fn reserve(&self, len: usize) -> Result<Option<Reservation>> {
+        if self.closed.load(Ordering::Acquire) {
+            return Err(Error::SegmentClosed);
+        }
+
+        if len == 0 {
+            return Err(Error::EmptyBuffer);
+        }
+
+        if len > BLOCK_SIZE {
+            return Err(Error::RecordTooLarge);
+        }
+
+        let offset = match self.allocate(len) {
+            Some(offset) => offset,
+            None => return Ok(None),
+        };
+        for pos in [offset, offset + len as u64 - 1] {
+            let (page, start) = self.page_of(pos);
+            self.wait_for_page(page, start)?;
+        }
+
+        Ok(Some(Reservation { offset, len }))
+    }
+
+    /// Returns the bytes of a reservation: a single slice, or two across a page boundary.
+    pub(crate) fn slices<'a>(
+        &'a self,
+        reservation: &'a mut Reservation,
+    ) -> (&'a mut [u8], &'a mut [u8]) {
+        let (page, start) = self.page_of(reservation.offset);
+        let at = (reservation.offset - start) as usize;
+        let first = cmp::min(BLOCK_SIZE - at, reservation.len);
+        // SAFETY: the pages of the reservation are ready since it was made, and they are not
+        // recycled before it is committed. The bytes are reserved for the holder of the
+        // reservation, borrowed mutably for as long as the slices.
+        unsafe {
+            let head = slice::from_raw_parts_mut(page.ptr().add(at), first);
+            let tail = match reservation.len - first {
+                0 => &mut [],
+                n => {
+                    let (next, _) = self.page_of(start + BLOCK_SIZE as u64);
+                    slice::from_raw_parts_mut(next.ptr(), n)
+                }
+            };
+            (head, tail)
+        }
+    }
+
+    /// Counts the bytes of a filled reservation as written, and writes the page it completed.
+    pub(crate) fn commit(&self, reservation: Reservation) -> Result<()> {
+        let (page, start) = self.page_of(reservation.offset);
+        let at = (reservation.offset - start) as usize;
+        let first = cmp::min(BLOCK_SIZE - at, reservation.len);
+        page.filled.fetch_add(first, Ordering::Release);
+        if first < reservation.len {
+            let (next, _) = self.page_of(start + BLOCK_SIZE as u64);
+            next.filled
+                .fetch_add(reservation.len - first, Ordering::Release);
+        }
+
+        if at + first == BLOCK_SIZE {
+            self.write_filled_pages()?;
+        }
+        Ok(())
+    }
+
+    /// Reserves `len` bytes at the tail of the segment, and returns their offset.
+    fn allocate(&self, len: usize) -> Option<u64> {
+        let mut tail = self.tail.load(Ordering::Acquire);
+        loop {
+            let end = tail + len as u64;
+            if end > self.max_file_size {
+                return None;
+            }
+            match self
+                .tail
+                .compare_exchange_weak(tail, end, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => return Some(tail),
+                Err(current) => tail = current,
+            }
+        }
+    }
+
+    /// Returns the page holding the byte at `offset`, and the offset of the page.
+    fn page_of(&self, offset: u64) -> (&Page, u64) {
+        let n = (offset - self.base) / BLOCK_SIZE as u64;
+        let page = &self.pages[n as usize % PAGES];
+        (page, self.base + n * BLOCK_SIZE as u64)
+    }
+
+    /// Copies `rec` into the pages of the bytes reserved at `offset`.
+    fn fill(&self, offset: u64, mut rec: &[u8]) -> Result<()> {
+        let mut pos = offset;
+        let mut page_filled = false;
+
+        while !rec.is_empty() {
+            let (page, start) = self.page_of(pos);
+            self.wait_for_page(page, start)?;
+
+            let at = (pos - start) as usize;
+            let n = cmp::min(BLOCK_SIZE - at, rec.len());
+            // SAFETY: the range is within the page, and it is reserved by this append: no
+            // other append writes it, and the flusher does not read it until `filled`
+            // counts it.
+            unsafe {
+                std::ptr::copy_nonoverlapping(rec.as_ptr(), page.ptr().add(at), n);
+            }
+            page.filled.fetch_add(n, Ordering::Release);
+
+            page_filled |= at + n == BLOCK_SIZE;
+            pos += n as u64;
+            rec = &rec[n..];
+        }
+
+        // Write the pages this append completed. The flusher is only waited for once the
+        // whole record is copied, as it may itself be waiting for the bytes of this append.
+        if page_filled {
+            self.write_filled_pages()?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the pages filled, waiting for the flusher if another one runs.
+    fn write_filled_pages(&self) -> Result<()> {
+        let mut flusher = self.flusher.lock();
+        self.write_pages(&mut flusher, None)
+    }
+
+    /// Waits until the page of the bytes from `start` is ready, writing the pages filled to
+    /// recycle it when no other flusher runs.
+    fn wait_for_page(&self, page: &Page, start: u64) -> Result<()> {
+        while page.start.load(Ordering::Acquire) != start {
+            if self.failed.load(Ordering::Acquire) {
+                return Err(write_failed());
+            }
+            if let Some(mut flusher) = self.flusher.try_lock() {
+                self.write_pages(&mut flusher, None)?;
+            }
+            thread::yield_now();
+        }
+        Ok(())
+    }
+
+    /// Writes the bytes filled to the file, page after page, and recycles the pages written
+    /// completely. With `until`, waits for the appends in flight to fill the bytes up to it;
+    /// otherwise stops at the first byte not filled yet.
+    fn write_pages(&self, flusher: &mut Flusher, until: Option<u64>) -> Result<()> {
+        loop {
+            if until.is_some_and(|until| flusher.flushed >= until) {
+                return Ok(());
+            }
+
+            let (page, start) = self.page_of(flusher.flushed);
+            // The number of bytes filled is loaded before the tail: the bytes counted are
+            // all reserved below it.
+            let filled = page.filled.load(Ordering::Acquire) as u64;
+            let end = cmp::min(start + BLOCK_SIZE as u64, self.tail.load(Ordering::Acquire));
+
+            if filled < end - start {
+                match until {
+                    Some(_) => {
+                        thread::yield_now();
+                        continue;
+                    }
+                    None => return Ok(()),
+                }
+            }
+
+            if end > flusher.flushed {
+                let from = (flusher.flushed - start) as usize;
+                // SAFETY: all the bytes of the page up to `end` are filled, and no append
+                // writes them again until the page is recycled.
+                let bytes = unsafe {
+                    slice::from_raw_parts(page.ptr().add(from), (end - flusher.flushed) as usize)
+                };
+                if let Err(e) = flusher.file.write_all(bytes) {
+                    self.failed.store(true, Ordering::Release);
+                    return Err(e.into());
+                }
+                flusher.flushed = end;
+            }
+
+            if end - start < BLOCK_SIZE as u64 {
+                // Everything reserved is written.
+                return Ok(());
+            }
+
+            // The page is written completely, recycle it.
+            page.filled.store(0, Ordering::Relaxed);
+            page.start
+                .store(start + (PAGES * BLOCK_SIZE) as u64, Ordering::Release);
+        }
+    }
+
+    /// Writes the bytes appended so far to the file.
+    pub(crate) fn flush(&self) -> Result<()> {
+        self.check_open()?;
+
+        let mut flusher = self.flusher.lock();
+        self.write_pages(&mut flusher, Some(self.offset()))
+    }
+
+    /// Writes the bytes appended so far to the file, and syncs it.
+    pub(crate) fn sync(&self) -> Result<()> {
+        self.check_open()?;
+        self.flush_and_sync()
+    }
+
+    fn flush_and_sync(&self) -> Result<()> {
+        let mut flusher = self.flusher.lock();
+        self.write_pages(&mut flusher, Some(self.offset()))?;
+        flusher.file.sync_all()?;
+        Ok(())
+    }
+
+    pub(crate) fn close(&self) -> Result<()> {
+        self.check_open()?;
+        self.closed.store(true, Ordering::Release);
+        self.flush_and_sync()
+    }
+
+    // Returns the current offset within the segment.
+    pub(crate) fn offset(&self) -> u64 {
+        self.tail.load(Ordering::Acquire)
+    }
+
+    // Returns the number of bytes written to the file of the segment.
+    pub(crate) fn flushed(&self) -> u64 {
+        self.flusher.lock().flushed
+    }
+
+    /// Reads data from the segment at the specified offset, which must be lower than the
+    /// offset of the segment. The bytes not written to the file yet are written first.
+    pub(crate) fn read_at(&self, bs: &mut [u8], off: u64) -> Result<usize> {
+        self.check_open()?;
+
+        let mut flusher = self.flusher.lock();
+        let tail = self.offset();
+        if off > tail {
+            return Err(Error::IO(IOError::new(
+                io::ErrorKind::Other,
+                "Offset beyond current position",
+            )));
+        }
+
+        let end = cmp::min(off + bs.len() as u64, tail);
+        if end > flusher.flushed {
+            self.write_pages(&mut flusher, Some(end))?;
+        }
+
+        let n = (end - off) as usize;
+        let file = &mut flusher.file;
+        file.seek(SeekFrom::Start(self.file_header_offset + off))?;
+        file.read_exact(&mut bs[..n])?;
+
+        if n < bs.len() {
+            return Err(Error::Eof(n));
+        }
+        Ok(n)
+    }
+
+    fn check_open(&self) -> Result<()> {
+        if self.closed.load(Ordering::Acquire) {
+            return Err(Error::SegmentClosed);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ActiveSegment {
+    /// Attempt to fsync data on drop, in case we're running without sync.
+    fn drop(&mut self) {
+        self.close().ok();
+    }
+}
+
+fn write_failed() -> Error {
+    Error::IO(IOError::new(
+        io::ErrorKind::Other,
+        "Writing the active segment failed",
+    ))
+}
@@ -1,29 +1,30 @@
-use std::fs;
 use std::io;
-use std::mem;
 use std::num::NonZeroUsize;
 
 use std::path::Path;
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
 
+use bytes::{buf::UninitSlice, BufMut};
 use lru::LruCache;
-use parking_lot::{Mutex, RwLock};
+use parking_lot::{RwLock, RwLockReadGuard};
 
-use crate::storage::log::{get_segment_range, Error, IOError, Options, Result, Segment};
-
-const RECORD_HEADER_SIZE: usize = 0;
+use crate::storage::log::aof::active::{ActiveSegment, Reservation};
+use crate::storage::log::{
+    get_segment_range, list_segment_ids, segment_name, AolSegment, Error, IOError, Options, Result,
+};
 
 /// Append-Only Log (Aol) is a data structure used to sequentially store records
 /// in a series of segments. It provides efficient write operations,
 /// making it suitable for use cases like storing large amounts of data and
 /// writing data in a sequential manner.
+///
+/// Appends do not lock each other out: the records are copied into the active segment
+/// concurrently, and only the rotation to a new segment is exclusive.
 pub struct Aol {
-    /// The currently active segment where data is being written.
-    pub(crate) active_segment: Segment<RECORD_HEADER_SIZE>,
-
-    /// The ID of the currently active segment.
-    pub(crate) active_segment_id: u64,
+    /// The currently active segment where data is being written. It is locked for writing
+    /// only to be replaced by the next segment.
+    pub(crate) active_segment: RwLock<ActiveSegment>,
 
     /// The directory where the segment files are located.
     pub(crate) dir: PathBuf,
@@ -32,16 +33,16 @@ pub struct Aol {
     pub(crate) opts: Options,
 
     /// A flag indicating whether the AOL instance is closed or not.
-    closed: bool,
-
-    /// A read-write lock used to synchronize concurrent access to the AOL instance.
-    mutex: Mutex<()>,
+    closed: AtomicBool,
 
     /// A cache used to store recently used segments to avoid opening and closing the files.
-    segment_cache: RwLock<LruCache<u64, Segment<RECORD_HEADER_SIZE>>>,
+    segment_cache: RwLock<LruCache<u64, AolSegment>>,
 
     /// A flag indicating whether the AOL instance has encountered an IO error or not.
     fsync_failed: AtomicBool,
+
+    /// A flag indicating whether the IO error was raised by a full disk.
+    disk_full: AtomicBool,
 }
 
 impl Aol {
@@ -62,54 +63,37 @@ impl Aol {
         Self::prepare_directory(dir, opts)?;
 
         // Determine the active segment ID
-        let active_segment_id = Self::calculate_current_write_segment_id(dir)?;
+        let active_segment_id = Self::calculate_current_write_segment_id(dir, opts)?;
 
         // Open the active segment
-        let active_segment = Segment::open(dir, active_segment_id, opts)?;
+        let active_segment = ActiveSegment::open(dir, active_segment_id, opts)?;
 
         // Create the segment cache
         // TODO: fix unwrap and return error
         let cache = LruCache::new(NonZeroUsize::new(opts.max_open_files).unwrap());
 
         Ok(Self {
-            active_segment,
-            active_segment_id,
+            active_segment: RwLock::new(active_segment),
             dir: dir.to_path_buf(),
             opts: opts.clone(),
-            closed: false,
-            mutex: Mutex::new(()),
+            closed: AtomicBool::new(false),
             segment_cache: RwLock::new(cache),
             fsync_failed: Default::default(),
+            disk_full: Default::default(),
         })
     }
 
     // Helper function to prepare the directory with proper permissions
     fn prepare_directory(dir: &Path, opts: &Options) -> Result<()> {
-        fs::create_dir_all(dir)?;
-
-        if let Ok(metadata) = fs::metadata(dir) {
-            let mut permissions = metadata.permissions();
-
-            #[cfg(unix)]
-            {
-                use std::os::unix::fs::PermissionsExt;
-                permissions.set_mode(opts.dir_mode.unwrap_or(0o750));
-            }
-
-            #[cfg(windows)]
-            {
-                permissions.set_readonly(false);
-            }
-
-            fs::set_permissions(dir, permissions)?;
-        }
+        opts.vfs
+            .create_dir_all(dir, Some(opts.dir_mode.unwrap_or(0o750)))?;
 
         Ok(())
     }
 
     // Helper function to calculate the active segment ID
-    fn calculate_current_write_segment_id(dir: &Path) -> Result<u64> {
-        let (_, last) = get_segment_range(dir)?;
+    fn calculate_current_write_segment_id(dir: &Path, opts: &Options) -> Result<u64> {
+        let (_, last) = get_segment_range(&*opts.vfs, dir)?;
         Ok(last)
     }
 
@@ -133,8 +117,8 @@ impl Aol {
     ///
     /// This function may return an error if the active segment is closed, the provided record
     /// is empty, or any I/O error occurs during the appending process.
-    pub fn append(&mut self, rec: &[u8]) -> Result<(u64, usize)> {
-        if self.closed {
+    pub fn append(&self, rec: &[u8]) -> Result<(u64, usize)> {
+        if self.closed.load(Ordering::Acquire) {
             return Err(Error::SegmentClosed);
         }
 
@@ -149,69 +133,163 @@ impl Aol {
             return Err(Error::RecordTooLarge);
         }
 
-        let _lock = self.mutex.lock();
+        loop {
+            let active_segment = self.active_segment.read();
+
+            // Write the record to the segment
+            match active_segment.append(rec) {
+                Ok(Some(off)) => {
+                    let offset = off + active_segment.id * self.opts.max_file_size;
+                    return Ok((offset, rec.len()));
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    self.record_failure(&e);
+                    return Err(e);
+                }
+            }
+
+            // The entire record can't fit into the remaining space of the current segment,
+            // rotate to a new one and retry.
+            let full_segment_id = active_segment.id;
+            drop(active_segment);
+            self.rotate(full_segment_id)?;
+        }
+    }
 
-        // Get options and initialize variables
-        let opts = &self.opts;
+    /// Reserves `len` bytes at the tail of the log, for the caller to serialize a record into
+    /// in place rather than building it in a buffer to append. `len` is at most one
+    /// block: the slot is a single slice, or two across a block boundary.
+    ///
+    /// The active segment is not rotated until the slot is finished, and the records
+    /// appended after it are only written to disk once it is.
+    pub(crate) fn reserve(&self, len: usize) -> Result<WriteSlot<'_>> {
+        if self.closed.load(Ordering::Acquire) {
+            return Err(Error::SegmentClosed);
+        }
 
-        // Calculate available space in the active segment
-        let available = opts.max_file_size as i64 - self.active_segment.offset() as i64;
+        self.check_if_fsync_failed()?;
 
-        // If the entire record can't fit into the remaining space of the current segment,
-        // close the current segment and create a new one
-        if available < rec.len() as i64 {
-            // Rotate to a new segment
+        loop {
+            let active_segment = self.active_segment.read();
+            match active_segment.reserve(len) {
+                Ok(Some(reservation)) => {
+                    let offset = reservation.offset() + active_segment.id * self.opts.max_file_size;
+                    return Ok(WriteSlot {
+                        aol: self,
+                        segment: active_segment,
+                        reservation: Some(reservation),
+                        offset,
+                        len,
+                        written: 0,
+                    });
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    self.record_failure(&e);
+                    return Err(e);
+                }
+            }
 
-            // Sync and close the active segment
-            // Note that closing the segment will
-            // not close the underlying file until
-            // it is dropped.
-            self.active_segment.close()?;
+            let full_segment_id = active_segment.id;
+            drop(active_segment);
+            self.rotate(full_segment_id)?;
+        }
+    }
 
-            // Increment the active segment id
-            self.active_segment_id += 1;
+    // Helper function to replace the full active segment with a new one
+    fn rotate(&self, full_segment_id: u64) -> Result<()> {
+        let mut active_segment = self.active_segment.write();
+
+        // Another append may have rotated the segment already
+        if active_segment.id == full_segment_id {
+            // Sync and close the active segment
+            self.gate(active_segment.close())?;
 
             // Open a new segment for writing
-            let new_segment = Segment::open(&self.dir, self.active_segment_id, &self.opts)?;
+            let next_segment_id = full_segment_id + 1;
+            *active_segment = ActiveSegment::open(&self.dir, next_segment_id, &self.opts)?;
+        }
 
-            // Retrieve the previous active segment and replace it with the new one
-            let _ = mem::replace(&mut self.active_segment, new_segment);
+        Ok(())
+    }
+
+    /// Seals the active segment and moves on to a new one, whatever the size of the active
+    /// segment. It returns the ID of the new segment.
+    pub(crate) fn start_segment(&self) -> Result<u64> {
+        if self.closed.load(Ordering::Acquire) {
+            return Err(Error::SegmentClosed);
         }
+        self.check_if_fsync_failed()?;
 
-        // Write the record to the segment
-        let result = self.active_segment.append(rec);
-        match result {
-            Ok((off, _)) => off,
-            Err(e) => {
-                if let Error::IO(_) = e {
-                    self.set_fsync_failed(true);
-                }
-                return Err(e);
-            }
-        };
+        self.rotate(self.active_segment_id())?;
+        Ok(self.active_segment_id())
+    }
 
-        let (off, _) = result.unwrap();
-        // Calculate offset only for the first chunk of data
-        let offset = off + self.calculate_offset();
+    /// Removes the files of the segments below `segment_id`, which is at most the active
+    /// segment. The records of the segments removed can no longer be read.
+    pub(crate) fn remove_segments_before(&self, segment_id: u64) -> Result<()> {
+        let segment_id = segment_id.min(self.active_segment_id());
+        let extension = self.opts.file_extension.as_deref().unwrap_or("");
+        for id in list_segment_ids(&*self.opts.vfs, &self.dir)? {
+            if id >= segment_id {
+                break;
+            }
+            self.segment_cache.write().pop(&id);
+            self.opts
+                .vfs
+                .remove_file(&self.dir.join(segment_name(id, extension)))?;
+        }
+        Ok(())
+    }
 
-        Ok((offset, rec.len()))
+    /// Returns the ID of the currently active segment.
+    pub(crate) fn active_segment_id(&self) -> u64 {
+        self.active_segment.read().id
     }
 
     /// Flushes and syncs the active segment.
-    pub fn sync(&mut self) -> Result<()> {
+    pub fn sync(&self) -> Result<()> {
         self.check_if_fsync_failed()?;
-        self.active_segment.sync()
+        let result = self.active_segment.read().sync();
+        self.gate(result)
     }
 
     /// Flushes the active segment.
-    pub fn flush(&mut self) -> Result<()> {
+    pub fn flush(&self) -> Result<()> {
         self.check_if_fsync_failed()?;
-        self.active_segment.flush()
+        let result = self.active_segment.read().flush();
+        self.gate(result)
     }
 
-    // Helper function to calculate offset
-    fn calculate_offset(&self) -> u64 {
-        self.active_segment_id * self.opts.max_file_size
+    /// Returns true if writing or syncing the log failed, after which the log refuses the
+    /// writes and reads.
+    pub(crate) fn fsync_failed(&self) -> bool {
+        self.fsync_failed.load(Ordering::Acquire)
+    }
+
+    /// Marks the log as failed if `result` is an IO error: the bytes written may not have
+    /// reached the disk, whatever the later syncs return.
+    fn gate(&self, result: Result<()>) -> Result<()> {
+        if let Err(e) = &result {
+            self.record_failure(e);
+        }
+        result
+    }
+
+    /// Returns true if the failure of the log was raised by a full disk.
+    pub(crate) fn disk_full(&self) -> bool {
+        self.disk_full.load(Ordering::Acquire)
+    }
+
+    /// Marks the log as failed if `e` is an IO error.
+    fn record_failure(&self, e: &Error) {
+        if let Error::IO(_) = e {
+            if e.is_disk_full() {
+                self.disk_full.store(true, Ordering::Release);
+            }
+            self.set_fsync_failed(true);
+        }
     }
 
     /// Reads data from the segment at the specified offset into the provided buffer.
@@ -277,17 +355,16 @@ impl Aol {
         segment_id: u64,
         read_offset: u64,
     ) -> Result<usize> {
-        // During read, we acquire a lock to not allow concurrent writes and reads
-        // to the active segment file to avoid seek errors.
-        if segment_id == self.active_segment.id {
-            let _lock = self.mutex.lock();
-            self.active_segment.read_at(buf, read_offset)
+        let active_segment = self.active_segment.read();
+        if segment_id == active_segment.id {
+            active_segment.read_at(buf, read_offset)
         } else {
+            drop(active_segment);
             let mut cache = self.segment_cache.write();
             match cache.get(&segment_id) {
                 Some(segment) => segment.read_at(buf, read_offset),
                 None => {
-                    let segment = Segment::open(&self.dir, segment_id, &self.opts)?;
+                    let segment = AolSegment::open(&self.dir, segment_id, &self.opts)?;
                     let read_bytes = segment.read_at(buf, read_offset)?;
                     cache.push(segment_id, segment);
                     Ok(read_bytes)
@@ -296,31 +373,24 @@ impl Aol {
         }
     }
 
-    pub fn close(&mut self) -> Result<()> {
-        let _lock = self.mutex.lock();
-        self.active_segment.close()?;
-        Ok(())
+    pub fn close(&self) -> Result<()> {
+        self.closed.store(true, Ordering::Release);
+        let result = self.active_segment.write().close();
+        self.gate(result)
     }
 
     // Returns the current offset within the segment.
     pub fn offset(&self) -> Result<u64> {
-        // Lock the mutex to ensure thread safety
-        let _lock = self.mutex.lock();
-
-        // Calculate the base offset
-        let base_offset = self.calculate_offset();
-
-        // Get the offset of the active segment
-        let active_segment_offset = self.active_segment.offset();
+        let active_segment = self.active_segment.read();
 
-        // Add the calculated offset to the offset of the active segment
-        Ok(base_offset + active_segment_offset)
+        // Add the base offset of the active segment to the offset within it
+        Ok(active_segment.id * self.opts.max_file_size + active_segment.offset())
     }
 
     pub fn size(&self) -> Result<u64> {
-        let _lock = self.mutex.lock();
-        let cur_segment_size = self.active_segment.file_offset;
-        let total_size = (self.active_segment_id * self.opts.max_file_size) + cur_segment_size;
+        let active_segment = self.active_segment.read();
+        let cur_segment_size = active_segment.flushed();
+        let total_size = (active_segment.id * self.opts.max_file_size) + cur_segment_size;
         Ok(total_size)
     }
 
@@ -345,6 +415,79 @@ impl Aol {
     }
 }
 
+/// A byte range reserved at the tail of the log with `Aol::reserve`, that a record is
+/// serialized into, either through its slices or as a `BufMut`.
+pub(crate) struct WriteSlot<'a> {
+    aol: &'a Aol,
+    segment: RwLockReadGuard<'a, ActiveSegment>,
+    reservation: Option<Reservation>,
+    offset: u64,
+    len: usize,
+    written: usize,
+}
+
+impl WriteSlot<'_> {
+    /// Returns the offset of the slot in the log.
+    pub(crate) fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Returns the bytes of the slot: a single slice, or two across a block boundary.
+    pub(crate) fn slices(&mut self) -> (&mut [u8], &mut [u8]) {
+        let reservation = self.reservation.as_mut().unwrap();
+        self.segment.slices(reservation)
+    }
+
+    /// Appends the record serialized into the slot, and returns its offset and length.
+    pub(crate) fn finish(mut self) -> Result<(u64, usize)> {
+        let reservation = self.reservation.take().unwrap();
+        if let Err(e) = self.segment.commit(reservation) {
+            self.aol.record_failure(&e);
+            return Err(e);
+        }
+        Ok((self.offset, self.len))
+    }
+}
+
+unsafe impl BufMut for WriteSlot<'_> {
+    fn remaining_mut(&self) -> usize {
+        self.len - self.written
+    }
+
+    unsafe fn advance_mut(&mut self, cnt: usize) {
+        assert!(
+            cnt <= self.remaining_mut(),
+            "advance past the end of the slot"
+        );
+        self.written += cnt;
+    }
+
+    fn chunk_mut(&mut self) -> &mut UninitSlice {
+        let written = self.written;
+        let (head, tail) = self.slices();
+        let chunk = match written.checked_sub(head.len()) {
+            None => &mut head[written..],
+            Some(n) => &mut tail[n..],
+        };
+        UninitSlice::new(chunk)
+    }
+}
+
+impl Drop for WriteSlot<'_> {
+    /// A slot dropped before it is finished is filled with zeros, as the log cannot have a
+    /// hole, and the log is marked as failed: nothing is appended after the corrupted
+    /// record, which is repaired away when the log is opened again.
+    fn drop(&mut self) {
+        if let Some(mut reservation) = self.reservation.take() {
+            self.aol.set_fsync_failed(true);
+            let (head, tail) = self.segment.slices(&mut reservation);
+            head.fill(0);
+            tail.fill(0);
+            self.segment.commit(reservation).ok();
+        }
+    }
+}
+
 impl Drop for Aol {
     /// Attempt to fsync data on drop, in case we're running without sync.
     fn drop(&mut self) {
@@ -368,7 +511,7 @@ mod tests {
 
         // Create aol options and open a aol file
         let opts = Options::default();
-        let mut a = Aol::open(temp_dir.path(), &opts).expect("should create aol");
+        let a = Aol::open(temp_dir.path(), &opts).expect("should create aol");
 
         // Test initial offset
         let sz = a.offset().unwrap();
@@ -438,7 +581,7 @@ mod tests {
 
         // Create aol options and open a aol file
         let opts = Options::default();
-        let mut a = Aol::open(temp_dir.path(), &opts).expect("should create aol");
+        let a = Aol::open(temp_dir.path(), &opts).expect("should create aol");
 
         // Create two slices of bytes of different sizes
         let data1 = vec![1; 31 * 1024];
@@ -477,7 +620,7 @@ mod tests {
 
         // Create aol options and open a aol file
         let opts = Options::default();
-        let mut a = Aol::open(temp_dir.path(), &opts).expect("should create aol");
+        let a = Aol::open(temp_dir.path(), &opts).expect("should create aol");
 
         // Create two slices of bytes of different sizes
         let data1 = vec![1; 31 * 1024];
@@ -537,7 +680,7 @@ mod tests {
             max_file_size: 1024,
             ..Default::default()
         };
-        let mut a = Aol::open(temp_dir.path(), &opts).expect("should create aol");
+        let a = Aol::open(temp_dir.path(), &opts).expect("should create aol");
 
         let large_record = vec![1; 1025];
         let small_record = vec![1; 1024];
@@ -560,7 +703,7 @@ mod tests {
             max_file_size: 1024,
             ..Default::default()
         };
-        let mut a = Aol::open(temp_dir.path(), &opts).expect("should create aol");
+        let a = Aol::open(temp_dir.path(), &opts).expect("should create aol");
 
         let small_record = vec![1; 1024];
         let r = a.append(&small_record);
@@ -590,7 +733,7 @@ mod tests {
             max_file_size: 1024,
             ..Default::default()
         };
-        let mut a = Aol::open(temp_dir.path(), &opts).expect("should create aol");
+        let a = Aol::open(temp_dir.path(), &opts).expect("should create aol");
 
         let large_record = vec![1; 1024];
         let small_record = vec![1; 512];
@@ -598,16 +741,126 @@ mod tests {
         assert!(r.is_ok());
         assert_eq!(1024, a.offset().unwrap());
 
-        assert_eq!(0, a.active_segment_id);
+        assert_eq!(0, a.active_segment_id());
 
         a.close().expect("should close");
 
-        let mut a = Aol::open(temp_dir.path(), &opts).expect("should create aol");
-        assert_eq!(0, a.active_segment_id);
+        let a = Aol::open(temp_dir.path(), &opts).expect("should create aol");
+        assert_eq!(0, a.active_segment_id());
 
         let r = a.append(&small_record);
         assert!(r.is_ok());
         assert_eq!(1536, a.offset().unwrap());
-        assert_eq!(1, a.active_segment_id);
+        assert_eq!(1, a.active_segment_id());
+    }
+
+    #[test]
+    fn concurrent_appends() {
+        // Create a temporary directory
+        let temp_dir = create_temp_directory();
+
+        // Small segments, for the appends to rotate them while others are in flight
+        let opts = Options {
+            max_file_size: 512 * 1024,
+            ..Default::default()
+        };
+        let a = Aol::open(temp_dir.path(), &opts).expect("should create aol");
+
+        // Records of all sizes from 8 producers, some larger than the pages of the active
+        // segment together
+        let appended: Vec<(u64, Vec<u8>)> = std::thread::scope(|s| {
+            let producers: Vec<_> = (0..8u8)
+                .map(|p| {
+                    let a = &a;
+                    s.spawn(move || {
+                        (0..64usize)
+                            .map(|i| {
+                                let len = match i % 8 {
+                                    0 => 200 * 1024,
+                                    1 => 32 * 1024,
+                                    _ => 1 + (i * 997 + p as usize * 131) % 4096,
+                                };
+                                let rec: Vec<u8> =
+                                    (0..len).map(|j| (j as u8).wrapping_add(p)).collect();
+                                let (offset, n) = a.append(&rec).expect("should append");
+                                assert_eq!(n, rec.len());
+                                (offset, rec)
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            producers
+                .into_iter()
+                .flat_map(|p| p.join().unwrap())
+                .collect()
+        });
+        assert!(a.active_segment_id() > 0);
+
+        // Every record reads back, before and after reopening the log
+        for (offset, rec) in &appended {
+            let mut bs = vec![0; rec.len()];
+            a.read_at(&mut bs, *offset).expect("should read");
+            assert_eq!(&bs, rec);
+        }
+        a.close().expect("should close");
+
+        let a = Aol::open(temp_dir.path(), &opts).expect("should open aol");
+        for (offset, rec) in &appended {
+            let mut bs = vec![0; rec.len()];
+            a.read_at(&mut bs, *offset).expect("should read");
+            assert_eq!(&bs, rec);
+        }
+    }
+
+    #[test]
+    fn reserve_and_fill() {
+        // Create a temporary directory
+        let temp_dir = create_temp_directory();
+
+        // Create aol options and open a aol file
+        let opts = Options::default();
+        let a = Aol::open(temp_dir.path(), &opts).expect("should create aol");
+
+        // Leave 10 bytes in the first block, for the slot to cross into the next one
+        let data1 = vec![1; 32 * 1024 - 10];
+        a.append(&data1).expect("should append");
+
+        let mut slot = a.reserve(100).expect("should reserve");
+        assert_eq!(slot.offset(), data1.len() as u64);
+        {
+            let (head, tail) = slot.slices();
+            assert_eq!((head.len(), tail.len()), (10, 90));
+        }
+        slot.put_slice(&[2; 50]);
+        slot.put_u64(u64::MAX);
+        slot.put_slice(&[3; 42]);
+        assert_eq!(slot.finish().unwrap(), (data1.len() as u64, 100));
+
+        // Slots are at most one block
+        assert!(matches!(
+            a.reserve(32 * 1024 + 1),
+            Err(Error::RecordTooLarge)
+        ));
+
+        // A slot dropped unfinished is filled with zeros, and nothing is appended after it
+        let mut slot = a.reserve(8).expect("should reserve");
+        slot.put_u32(4);
+        drop(slot);
+        assert!(a.append(&[5; 4]).is_err());
+        assert!(a.reserve(4).is_err());
+        a.close().ok();
+        drop(a);
+
+        // The log opened again holds the records before the slot, then the zeros
+        let a = Aol::open(temp_dir.path(), &opts).expect("should open aol");
+        assert_eq!(a.offset().unwrap(), data1.len() as u64 + 108);
+        let mut bs = vec![0; 108];
+        a.read_at(&mut bs, data1.len() as u64).expect("should read");
+        let mut expected = vec![2; 50];
+        expected.extend_from_slice(&[0xff; 8]);
+        expected.extend_from_slice(&[3; 42]);
+        expected.extend_from_slice(&[0; 8]);
+        assert_eq!(bs, expected);
     }
 }
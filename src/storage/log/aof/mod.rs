@@ -1 +1,2 @@
+pub(crate) mod active;
 pub mod log;
@@ -1,9 +1,8 @@
 pub mod aof;
+pub(crate) mod record;
 pub mod wal;
 
 use std::fmt;
-use std::fs::File;
-use std::fs::{read_dir, OpenOptions};
 use std::io::BufReader;
 use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
@@ -12,6 +11,10 @@ use std::sync::PoisonError;
 use crc32fast::Hasher;
 use hashbrown::HashMap;
 
+use record::{RecordFlags, RecordHeader, RecordType, RECORD_HEADER_SIZE, RECORD_HEADER_VERSION};
+
+use crate::storage::vfs::{OpenOptions, SharedVfs, Vfs, VfsFile};
+
 /// The size of a single block in bytes.
 ///
 /// The `BLOCK_SIZE` constant represents the size of a block used for buffering disk writes in the
@@ -25,7 +28,7 @@ pub(crate) const BLOCK_SIZE: usize = 32 * 1024;
 /// used in the write-ahead log. The record header contains information
 /// about the type of the record, the length of the payload, and a checksum value.
 /// The constant is set to 7, reflecting the size of the record header structure.
-pub const WAL_RECORD_HEADER_SIZE: usize = 7;
+pub const WAL_RECORD_HEADER_SIZE: usize = RECORD_HEADER_SIZE;
 
 /// The magic value for identifying file headers.
 ///
@@ -37,8 +40,9 @@ const MAGIC: u64 = 0xFB98F92D;
 /// The version number of the file format.
 ///
 /// The `VERSION` constant represents the version number of the file format used in write-ahead log
-/// segments. It provides information about the structure and layout of the data within the segment.
-const VERSION: u64 = 1;
+/// segments. It provides information about the structure and layout of the data within the segment,
+/// including the layout of the record headers.
+const VERSION: u64 = RECORD_HEADER_VERSION;
 
 /// Default file mode for newly created files, represented in octal format.
 /// The default mode is set to read and write permissions for the owner,
@@ -213,12 +217,33 @@ pub struct Options {
     /// By default, this flag is set to `false`, indicating that Write-Ahead Logging is not enabled.
     is_wal: bool,
 
+    /// A flag indicating whether the records of a WAL are written with a checksum.
+    ///
+    /// If this flag is set to `false`, the records are written with `RecordFlags::UNCHECKED`
+    /// and a zero CRC32, which readers do not verify: a corrupted record is then read as it
+    /// is. The flag is kept by each record, so a segment may hold both kinds.
+    ///
+    /// By default, this flag is set to `true`.
+    checksums: bool,
+
     /// The maximum number of open files allowed.
     ///
     /// If specified, this option sets the maximum number of open files allowed.
     ///
     /// This is used by aol to initialize the segment cache.
     pub(crate) max_open_files: usize,
+
+    /// The alignment of the first record of the segment files, in bytes.
+    ///
+    /// If it is not 0, the file header is padded with zeros to a multiple of it, so that the
+    /// records of a new segment begin at a boundary of, for example, an erase block of flash
+    /// storage. The padding is part of the header, which the readers skip by its length.
+    ///
+    /// By default, it is 0, and the records follow the header.
+    header_alignment: u64,
+
+    /// The filesystem the segment files are stored on.
+    pub(crate) vfs: SharedVfs,
 }
 
 impl Default for Options {
@@ -232,7 +257,10 @@ impl Default for Options {
             file_extension: None,                                 // default extension
             max_file_size: DEFAULT_FILE_SIZE,                     // default max file size (20mb)
             is_wal: false,
+            checksums: true,
             max_open_files: DEFAULT_MAX_OPEN_FILES,
+            header_alignment: 0,
+            vfs: SharedVfs::default(),
         }
     }
 }
@@ -291,11 +319,27 @@ impl Options {
         self
     }
 
+    pub fn with_vfs(mut self, vfs: SharedVfs) -> Self {
+        self.vfs = vfs;
+        self
+    }
+
     #[allow(dead_code)]
     pub fn with_wal(mut self) -> Self {
         self.is_wal = true;
         self
     }
+
+    #[allow(dead_code)]
+    pub fn with_checksums(mut self, checksums: bool) -> Self {
+        self.checksums = checksums;
+        self
+    }
+
+    pub fn with_header_alignment(mut self, header_alignment: u64) -> Self {
+        self.header_alignment = header_alignment;
+        self
+    }
 }
 
 /// Represents metadata associated with a file.
@@ -443,57 +487,8 @@ impl Metadata {
     }
 }
 
-/// Enum representing different types of records in a write-ahead log.
-///
-/// This enumeration defines different types of records that can be used in a write-ahead log.
-/// Each record type indicates a particular state of a record in the log. The enum variants
-/// represent various stages of a record, including full records, fragments, and special cases.
-///
-/// # Variants
-///
-/// - `Empty`: Indicates that the rest of the block is empty.
-/// - `Full`: Represents a full record.
-/// - `First`: Denotes the first fragment of a record.
-/// - `Middle`: Denotes middle fragments of a record.
-/// - `Last`: Denotes the final fragment of a record.
-#[derive(PartialEq)]
-enum RecordType {
-    Empty = 0,  // Rest of block is empty.
-    Full = 1,   // Full record.
-    First = 2,  // First fragment of a record.
-    Middle = 3, // Middle fragments of a record.
-    Last = 4,   // Final fragment of a record.
-}
-
-impl RecordType {
-    fn from_u8(value: u8) -> Result<Self> {
-        match value {
-            0 => Ok(RecordType::Empty),
-            1 => Ok(RecordType::Full),
-            2 => Ok(RecordType::First),
-            3 => Ok(RecordType::Middle),
-            4 => Ok(RecordType::Last),
-            _ => Err(Error::IO(IOError::new(
-                std::io::ErrorKind::InvalidInput,
-                "Invalid Record Type",
-            ))),
-        }
-    }
-}
-
-/*
-    Encodes a record with the provided information into the given buffer.
-
-    It has the following format:
-
-    Record Header
-
-        0      1      2      3      4      5      6      7
-        +------+------+------+------+------+------+------+------+------+------+
-        | Type |    Length   |         CRC32             |       Payload      |
-        +------+------+------+------+------+------+------+------+------+------+
-*/
-fn encode_record_header(buf: &mut [u8], rec_len: usize, part: &[u8], i: usize) {
+// Encodes the header of a record, see `record`, into the given buffer.
+fn encode_record_header(buf: &mut [u8], rec_len: usize, part: &[u8], i: usize, flags: RecordFlags) {
     let typ = if i == 0 && part.len() == rec_len {
         RecordType::Full
     } else if part.len() == rec_len {
@@ -504,12 +499,17 @@ fn encode_record_header(buf: &mut [u8], rec_len: usize, part: &[u8], i: usize) {
         RecordType::Middle
     };
 
-    buf[0] = typ as u8;
-    let len_part = part.len() as u16;
-    buf[1..3].copy_from_slice(&len_part.to_be_bytes());
-    // calculate the CRC32 checksum based on the record type and data
-    let crc = calculate_crc32(&buf[0..1], part);
-    buf[3..7].copy_from_slice(&crc.to_be_bytes());
+    let mut header = RecordHeader {
+        record_type: typ,
+        flags,
+        length: part.len() as u16,
+        crc: 0,
+    };
+    // calculate the CRC32 checksum based on the type byte and data
+    if !flags.contains(RecordFlags::UNCHECKED) {
+        header.crc = calculate_crc32(&[header.type_byte()], part);
+    }
+    header.encode(buf);
 }
 
 // Reads a field from the given reader
@@ -531,18 +531,23 @@ pub(crate) fn write_field<W: Write>(b: &[u8], writer: &mut W) -> Result<()> {
     Ok(())
 }
 
-pub(crate) fn read_file_header(file: &mut File) -> Result<Vec<u8>> {
+pub(crate) fn read_file_header<R: Read>(file: &mut R) -> Result<Vec<u8>> {
     // Read the header using read_field
     read_field(file)
 }
 
-fn write_file_header(file: &mut File, id: u64, opts: &Options) -> Result<usize> {
+fn write_file_header(file: &mut dyn VfsFile, id: u64, opts: &Options) -> Result<usize> {
     // Create a buffer to hold the header
     let mut buf = Vec::new();
 
-    // Write the header using write_field
-    let meta = Metadata::new_file_header(id, opts)?;
-    write_field(&meta.to_bytes()?, &mut buf)?;
+    // Write the header using write_field, padded to the alignment of the records. The
+    // metadata is read by the number of its pairs, so the zeros after them are left unread.
+    let mut meta = Metadata::new_file_header(id, opts)?.to_bytes()?;
+    if opts.header_alignment > 0 {
+        let len = (4 + meta.len() as u64).next_multiple_of(opts.header_alignment);
+        meta.resize(len as usize - 4, 0);
+    }
+    write_field(&meta, &mut buf)?;
 
     // Write header to the file
     file.write_all(&buf)?;
@@ -748,8 +753,8 @@ pub(crate) fn segment_name(index: u64, ext: &str) -> String {
 ///
 /// This function returns a tuple containing the minimum and maximum segment IDs
 /// found in the directory. If no segments are found, the tuple will contain (0, 0).
-fn get_segment_range(dir: &Path) -> Result<(u64, u64)> {
-    let refs = list_segment_ids(dir)?;
+fn get_segment_range(vfs: &dyn Vfs, dir: &Path) -> Result<(u64, u64)> {
+    let refs = list_segment_ids(vfs, dir)?;
     if refs.is_empty() {
         return Ok((0, 0));
     }
@@ -761,15 +766,13 @@ fn get_segment_range(dir: &Path) -> Result<(u64, u64)> {
 /// This function reads the names of segment files in the directory and extracts the segment IDs.
 /// The segment IDs are returned as a sorted vector. If no segment files are found, an empty
 /// vector is returned.
-fn list_segment_ids(dir: &Path) -> Result<Vec<u64>> {
+pub(crate) fn list_segment_ids(vfs: &dyn Vfs, dir: &Path) -> Result<Vec<u64>> {
     let mut refs: Vec<u64> = Vec::new();
-    let entries = read_dir(dir)?;
-
-    for entry in entries {
-        let file = entry?;
+    let entries = vfs.read_dir(dir)?;
 
+    for file in entries {
         // Check if the entry is a file
-        if std::fs::metadata(file.path())?.is_file() {
+        if file.metadata().is_file() {
             let fn_name = file.file_name();
             let fn_str = fn_name.to_string_lossy();
             let (index, _) = parse_segment_name(&fn_str)?;
@@ -794,20 +797,22 @@ pub(crate) struct SegmentRef {
 
 impl SegmentRef {
     /// Creates a vector of SegmentRef instances by reading segments in the specified directory.
-    pub fn read_segments_from_directory(directory_path: &Path) -> Result<Vec<SegmentRef>> {
+    pub fn read_segments_from_directory(
+        vfs: &dyn Vfs,
+        directory_path: &Path,
+    ) -> Result<Vec<SegmentRef>> {
         let mut segment_refs = Vec::new();
 
         // Read the directory and iterate through its entries
-        let files = read_dir(directory_path)?;
-        for file in files {
-            let entry = file?;
-            if entry.file_type()?.is_file() {
-                let file_path = entry.path();
+        let files = vfs.read_dir(directory_path)?;
+        for entry in files {
+            if entry.metadata().is_file() {
+                let file_path = entry.path().to_path_buf();
                 let fn_name = entry.file_name();
                 let fn_str = fn_name.to_string_lossy();
                 let (index, _) = parse_segment_name(&fn_str)?;
 
-                let mut file = OpenOptions::new().read(true).open(&file_path)?;
+                let mut file = vfs.open(&file_path, &OpenOptions::new().read(true))?;
                 let header = read_file_header(&mut file)?;
                 validate_magic_version(&header)?;
                 validate_segment_id(&header, index)?;
@@ -823,7 +828,7 @@ impl SegmentRef {
             }
         }
 
-        segment_refs.sort_by(|a, b| a.id.cmp(&b.id));
+        segment_refs.sort_by_key(|a| a.id);
 
         Ok(segment_refs)
     }
@@ -858,6 +863,7 @@ impl SegmentRef {
      +------+------+------+------+------+------+------+------+
 */
 pub(crate) struct Segment<const RECORD_HEADER_SIZE: usize> {
+    #[allow(dead_code)]
     /// The unique identifier of the segment.
     pub(crate) id: u64,
 
@@ -869,7 +875,7 @@ pub(crate) struct Segment<const RECORD_HEADER_SIZE: usize> {
     block: Block<BLOCK_SIZE, RECORD_HEADER_SIZE>,
 
     /// The underlying file for storing the segment's data.
-    file: File,
+    file: Box<dyn VfsFile>,
 
     /// The base offset of the file.
     pub(crate) file_header_offset: u64,
@@ -886,84 +892,108 @@ pub(crate) struct Segment<const RECORD_HEADER_SIZE: usize> {
 
     /// A flag indicating whether the segment is a Write-Ahead Logging (WAL).
     is_wal: bool,
+
+    /// The flags the records of the segment are written with.
+    record_flags: RecordFlags,
 }
 
-impl<const RECORD_HEADER_SIZE: usize> Segment<RECORD_HEADER_SIZE> {
-    pub(crate) fn open(dir: &Path, id: u64, opts: &Options) -> Result<Self> {
-        // Ensure the options are valid
-        opts.validate()?;
+/// A segment of the write-ahead log, whose records are framed by a record header.
+pub(crate) type WalSegment = Segment<WAL_RECORD_HEADER_SIZE>;
+
+/// A segment of the append-only log, whose records are written as they are.
+pub(crate) type AolSegment = Segment<0>;
+
+/// Opens or creates the file of the segment `id`, with its header validated or written.
+///
+/// Returns the file, its path, the length of its header and the number of bytes after
+/// the header.
+pub(crate) fn open_segment_file(
+    dir: &Path,
+    id: u64,
+    opts: &Options,
+) -> Result<(Box<dyn VfsFile>, PathBuf, u64, u64)> {
+    // Build the file path using the segment name and extension
+    let extension = opts.file_extension.as_deref().unwrap_or("");
+    let file_name = segment_name(id, extension);
+    let file_path = dir.join(&file_name);
+    let metadata = opts.vfs.metadata(&file_path).ok();
+    let file_path_exists = metadata.is_some();
+    let file_path_is_file = metadata.is_some_and(|metadata| metadata.is_file());
+
+    // Open the file with the specified options
+    let mut file = open_file(&file_path, opts)?;
+
+    // Initialize the file header offset
+    let mut file_header_offset = 0;
+
+    // If the file already exists
+    if file_path_exists && file_path_is_file {
+        // Handle existing file
+        let header = read_file_header(&mut file)?;
+        validate_file_header(&header, id, opts)?;
+
+        file_header_offset += 4 + header.len();
+        let (index, _) = parse_segment_name(&file_name)?;
+        if index != id {
+            return Err(Error::IO(IOError::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid segment id",
+            )));
+        }
+    } else {
+        // Write new file header
+        let header_len = write_file_header(file.as_mut(), id, opts)?;
+        file_header_offset += header_len;
+    }
 
-        // Build the file path using the segment name and extension
-        let extension = opts.file_extension.as_deref().unwrap_or("");
-        let file_name = segment_name(id, extension);
-        let file_path = dir.join(&file_name);
-        let file_path_exists = file_path.exists();
-        let file_path_is_file = file_path.is_file();
+    // Seek to the end of the file to get the file offset
+    let file_offset = file.seek(io::SeekFrom::End(0))?;
 
-        // Open the file with the specified options
-        let mut file = Self::open_file(&file_path, opts)?;
+    Ok((
+        file,
+        file_path,
+        file_header_offset as u64,
+        file_offset - file_header_offset as u64,
+    ))
+}
 
-        // Initialize the file header offset
-        let mut file_header_offset = 0;
+fn open_file(file_path: &Path, opts: &Options) -> Result<Box<dyn VfsFile>> {
+    let open_options = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .mode(opts.file_mode)
+        .create(!opts.vfs.exists(file_path)); // Create the file if it doesn't exist
 
-        // If the file already exists
-        if file_path_exists && file_path_is_file {
-            // Handle existing file
-            let header = read_file_header(&mut file)?;
-            validate_file_header(&header, id, opts)?;
+    let file = opts.vfs.open(file_path, &open_options)?;
 
-            file_header_offset += 4 + header.len();
-            let (index, _) = parse_segment_name(&file_name)?;
-            if index != id {
-                return Err(Error::IO(IOError::new(
-                    io::ErrorKind::InvalidInput,
-                    "Invalid segment id",
-                )));
-            }
-        } else {
-            // Write new file header
-            let header_len = write_file_header(&mut file, id, opts)?;
-            file_header_offset += header_len;
-        }
+    Ok(file)
+}
 
-        // Seek to the end of the file to get the file offset
-        let file_offset = file.seek(io::SeekFrom::End(0))?;
+impl<const RECORD_HEADER_SIZE: usize> Segment<RECORD_HEADER_SIZE> {
+    pub(crate) fn open(dir: &Path, id: u64, opts: &Options) -> Result<Self> {
+        // Ensure the options are valid
+        opts.validate()?;
+
+        let (file, file_path, file_header_offset, file_offset) = open_segment_file(dir, id, opts)?;
 
         // Initialize and return the Segment
         Ok(Segment {
             file,
-            file_header_offset: file_header_offset as u64,
-            file_offset: file_offset - file_header_offset as u64,
+            file_header_offset,
+            file_offset,
             file_path,
             id,
             closed: false,
             block: Block::new(),
             is_wal: opts.is_wal,
+            record_flags: match opts.checksums {
+                true => RecordFlags::NONE,
+                false => RecordFlags::UNCHECKED,
+            },
             file_size: opts.max_file_size,
         })
     }
 
-    fn open_file(file_path: &Path, opts: &Options) -> Result<File> {
-        let mut open_options = OpenOptions::new();
-        open_options.read(true).append(true);
-
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::OpenOptionsExt;
-            if let Some(file_mode) = opts.file_mode {
-                open_options.mode(file_mode);
-            }
-        }
-
-        if !file_path.exists() {
-            open_options.create(true); // Create the file if it doesn't exist
-        }
-
-        let file = open_options.open(file_path)?;
-
-        Ok(file)
-    }
-
     fn flush(&mut self) -> Result<()> {
         if self.block.written > 0 {
             // Flush the full block to disk if it is a WAL with zero padded
@@ -1091,7 +1121,7 @@ impl<const RECORD_HEADER_SIZE: usize> Segment<RECORD_HEADER_SIZE> {
         let buf = &mut active_block.buf[active_block.written..];
 
         if self.is_wal {
-            encode_record_header(buf, rec.len(), partial_record, i);
+            encode_record_header(buf, rec.len(), partial_record, i, self.record_flags);
             // Copy the 'partial_record' into the buffer starting from the WAL_RECORD_HEADER_SIZE offset
             copy_slice(&mut buf[WAL_RECORD_HEADER_SIZE..], partial_record);
             active_block.written += partial_record.len() + WAL_RECORD_HEADER_SIZE;
@@ -1148,9 +1178,7 @@ impl<const RECORD_HEADER_SIZE: usize> Segment<RECORD_HEADER_SIZE> {
         let mut n = 0;
         if off < self.file_offset {
             // Read from the file
-            let mut file = &self.file;
-            file.seek(SeekFrom::Start(self.file_header_offset + off))?;
-            n = file.read(bs)?;
+            n = self.file.read_at(bs, self.file_header_offset + off)?;
         } else {
             boff = (off - self.file_offset) as usize;
         }
@@ -1229,10 +1257,31 @@ impl From<io::Error> for Error {
         Error::IO(IOError {
             kind: e.kind(),
             message: e.to_string(),
+            disk_full: is_disk_full(&e),
         })
     }
 }
 
+impl Error {
+    /// Returns true if the error reports that the disk is full.
+    pub(crate) fn is_disk_full(&self) -> bool {
+        matches!(self, Error::IO(err) if err.disk_full)
+    }
+}
+
+/// Returns true if `e` reports that the disk is full. The `io::ErrorKind` of a full disk is
+/// not stable on the oldest supported Rust, so the code of the OS error is checked instead.
+pub(crate) fn is_disk_full(e: &io::Error) -> bool {
+    #[cfg(unix)]
+    const DISK_FULL: &[i32] = &[28]; // ENOSPC
+    #[cfg(windows)]
+    const DISK_FULL: &[i32] = &[39, 112]; // ERROR_HANDLE_DISK_FULL, ERROR_DISK_FULL
+    #[cfg(not(any(unix, windows)))]
+    const DISK_FULL: &[i32] = &[];
+    e.raw_os_error()
+        .is_some_and(|code| DISK_FULL.contains(&code))
+}
+
 // Implementation to convert PoisonError into Error
 impl<T: Sized> From<PoisonError<T>> for Error {
     fn from(e: PoisonError<T>) -> Error {
@@ -1244,6 +1293,7 @@ impl<T: Sized> From<PoisonError<T>> for Error {
 pub struct IOError {
     kind: io::ErrorKind,
     message: String,
+    disk_full: bool,
 }
 
 impl IOError {
@@ -1251,6 +1301,7 @@ impl IOError {
         IOError {
             kind,
             message: message.to_string(),
+            disk_full: false,
         }
     }
 }
@@ -1299,14 +1350,15 @@ impl std::error::Error for CorruptionError {}
 // written to WAL in multiples of BLOCK_SIZE, non-block aligned segments
 // are padded with zeros. This is done to avoid partial reads from the WAL.
 pub struct MultiSegmentReader {
-    buf: BufReader<File>,      // Buffer for reading from the current segment.
-    segments: Vec<SegmentRef>, // List of segments to read from.
-    cur: usize,                // Index of current segment in segments.
-    off: usize,                // Offset in current segment.
+    buf: BufReader<Box<dyn VfsFile>>, // Buffer for reading from the current segment.
+    segments: Vec<SegmentRef>,        // List of segments to read from.
+    cur: usize,                       // Index of current segment in segments.
+    off: usize,                       // Offset in current segment.
+    vfs: SharedVfs,                   // Filesystem the segments are opened on.
 }
 
 impl MultiSegmentReader {
-    pub(crate) fn new(segments: Vec<SegmentRef>) -> Result<MultiSegmentReader> {
+    pub(crate) fn new(vfs: &SharedVfs, segments: Vec<SegmentRef>) -> Result<MultiSegmentReader> {
         if segments.is_empty() {
             return Err(Error::IO(IOError::new(
                 io::ErrorKind::InvalidInput,
@@ -1318,7 +1370,7 @@ impl MultiSegmentReader {
         let off = 0;
 
         // Open the first segment's file for reading
-        let mut file = File::open(&segments[cur].file_path)?;
+        let mut file = vfs.open(&segments[cur].file_path, &OpenOptions::new().read(true))?;
         file.seek(SeekFrom::Start(segments[cur].file_header_offset))?;
 
         let buf = BufReader::with_capacity(BLOCK_SIZE, file);
@@ -1328,6 +1380,7 @@ impl MultiSegmentReader {
             segments,
             cur,
             off,
+            vfs: vfs.clone(),
         })
     }
 
@@ -1370,7 +1423,10 @@ impl MultiSegmentReader {
         self.cur += 1;
         self.off = 0;
 
-        let next_file = File::open(&self.segments[self.cur].file_path)?;
+        let next_file = self.vfs.open(
+            &self.segments[self.cur].file_path,
+            &OpenOptions::new().read(true),
+        )?;
         let header_offset = self.segments[self.cur].file_header_offset;
         let mut next_buf_reader = BufReader::with_capacity(BLOCK_SIZE, next_file);
         next_buf_reader.seek(SeekFrom::Start(header_offset))?;
@@ -1380,6 +1436,20 @@ impl MultiSegmentReader {
         Ok(())
     }
 
+    /// Moves to the given offset of the first segment.
+    pub(crate) fn seek_to(&mut self, off: usize) -> io::Result<()> {
+        // The reads are aligned to the blocks of the segment, so the block holding the
+        // offset is read from its start and the bytes before the offset are skipped.
+        let block_start = off - off % BLOCK_SIZE;
+        let header_offset = self.segments[self.cur].file_header_offset;
+        self.buf
+            .seek(SeekFrom::Start(header_offset + block_start as u64))?;
+        self.off = block_start;
+
+        let mut skipped = vec![0; off - block_start];
+        self.read_exact(&mut skipped)
+    }
+
     pub(crate) fn current_segment_id(&self) -> u64 {
         self.segments[self.cur].id
     }
@@ -1417,6 +1487,8 @@ mod tests {
     use std::io::{Read, SeekFrom, Write};
     use tempdir::TempDir;
 
+    use crate::storage::vfs::OsVfs;
+
     #[test]
     fn new_empty() {
         let metadata = Metadata::new(None);
@@ -1532,6 +1604,32 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn aligned_file_header() {
+        // Create a temporary directory
+        let temp_dir = TempDir::new("test").expect("should create temp dir");
+        let opts = Options::default().with_header_alignment(4096);
+
+        // The records of a new segment begin at the alignment
+        let mut segment: Segment<0> =
+            Segment::open(temp_dir.path(), 0, &opts).expect("should create segment");
+        assert_eq!(segment.file_header_offset, 4096);
+        segment.append(&[1, 2, 3, 4]).expect("should append");
+        segment.close().expect("should close segment");
+        let len = std::fs::metadata(temp_dir.path().join("00000000000000000000"))
+            .unwrap()
+            .len();
+        assert_eq!(len, 4096 + 4);
+
+        // The padded header is read and validated as any other
+        let segment: Segment<0> =
+            Segment::open(temp_dir.path(), 0, &Options::default()).expect("should open segment");
+        assert_eq!(segment.file_header_offset, 4096);
+        let mut bs = [0; 4];
+        segment.read_at(&mut bs, 0).expect("should read");
+        assert_eq!(bs, [1, 2, 3, 4]);
+    }
+
     #[test]
     fn bad_file_header() {
         // Create a temporary directory
@@ -1607,7 +1705,7 @@ mod tests {
         let temp_dir = create_temp_directory();
         let dir = temp_dir.path().to_path_buf();
 
-        let result = get_segment_range(&dir).unwrap();
+        let result = get_segment_range(&OsVfs, &dir).unwrap();
         assert_eq!(result, (0, 0));
     }
 
@@ -1621,7 +1719,7 @@ mod tests {
         create_segment_file(&dir, "00000000000000000002.log");
         create_segment_file(&dir, "00000000000000000004.log");
 
-        let result = get_segment_range(&dir).unwrap();
+        let result = get_segment_range(&OsVfs, &dir).unwrap();
         assert_eq!(result, (1, 4));
     }
 
@@ -2136,7 +2234,8 @@ mod tests {
         let segments: Vec<SegmentRef> = vec![create_test_segment_ref(&segment)];
 
         // Create a MultiSegmentReader for testing
-        let mut buf_reader = MultiSegmentReader::new(segments).expect("should create");
+        let mut buf_reader =
+            MultiSegmentReader::new(&SharedVfs::default(), segments).expect("should create");
 
         // Read first record from the MultiSegmentReader
         let mut bs = [0u8; 11];
@@ -2190,7 +2289,8 @@ mod tests {
         ];
 
         // Create a MultiSegmentReader for testing
-        let mut buf_reader = MultiSegmentReader::new(segments).expect("should create");
+        let mut buf_reader =
+            MultiSegmentReader::new(&SharedVfs::default(), segments).expect("should create");
 
         // Read first record from the MultiSegmentReader
         let mut bs = [0u8; 11];
@@ -2239,7 +2339,8 @@ mod tests {
         let segments: Vec<SegmentRef> = vec![create_test_segment_ref(&segment)];
 
         // Create a MultiSegmentReader for testing
-        let mut buf_reader = MultiSegmentReader::new(segments).expect("should create");
+        let mut buf_reader =
+            MultiSegmentReader::new(&SharedVfs::default(), segments).expect("should create");
 
         // Read data from the MultiSegmentReader
         let mut bs = [0u8; 50];
@@ -2279,7 +2380,8 @@ mod tests {
         // Create a Vec of segments containing our sample segment
         let segments: Vec<SegmentRef> = vec![create_test_segment_ref(&segment)];
         // Create a MultiSegmentReader for testing
-        let mut buf_reader = MultiSegmentReader::new(segments).expect("should create");
+        let mut buf_reader =
+            MultiSegmentReader::new(&SharedVfs::default(), segments).expect("should create");
 
         // Read data from the MultiSegmentReader
         let mut bs = [0u8; 50];
@@ -2326,7 +2428,8 @@ mod tests {
         ];
 
         // Create a MultiSegmentReader for testing
-        let mut buf_reader = MultiSegmentReader::new(segments).expect("should create");
+        let mut buf_reader =
+            MultiSegmentReader::new(&SharedVfs::default(), segments).expect("should create");
 
         // Read first record from the MultiSegmentReader
         let mut bs = [0u8; BLOCK_SIZE];
@@ -2360,7 +2463,7 @@ mod tests {
         assert!(segment2.close().is_ok());
         assert!(segment3.close().is_ok());
 
-        let sr = SegmentRef::read_segments_from_directory(temp_dir.path())
+        let sr = SegmentRef::read_segments_from_directory(&OsVfs, temp_dir.path())
             .expect("should read segments");
         assert!(sr.len() == 3);
         assert!(sr[0].id == 4);
@@ -2479,7 +2582,8 @@ mod tests {
         ];
 
         // Create a MultiSegmentReader for testing
-        let mut buf_reader = MultiSegmentReader::new(segments).expect("should create");
+        let mut buf_reader =
+            MultiSegmentReader::new(&SharedVfs::default(), segments).expect("should create");
 
         // Read first record from the MultiSegmentReader
         let mut bs = [0u8; 4];
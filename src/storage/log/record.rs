@@ -0,0 +1,175 @@
+//! The header of the records of the write-ahead log.
+//!
+//! ```text
+//!     0      1      2      3      4      5      6      7
+//!     +------+------+------+------+------+------+------+------+------+------+
+//!     | Type |    Length   |         CRC32             |       Payload      |
+//!     +------+------+------+------+------+------+------+------+------+------+
+//! ```
+//!
+//! The low bits of the type byte hold the [`RecordType`], and the high bits the
+//! [`RecordFlags`], which the CRC32 covers along with the payload. A record written with
+//! `RecordFlags::UNCHECKED` has no checksum: its CRC32 is zero, and is not verified.
+//!
+//! New features of a record, such as a compressed or an encrypted payload, take one of the
+//! reserved flag bits. Fields that do not fit into a flag, such as a tenant id, make a new
+//! layout of the header: the layout is versioned with the segment files, whose header holds
+//! the version their records are written with. Only the flags and the version are public.
+
+use std::io;
+
+use crate::storage::log::{Error, IOError, Result};
+
+/// The version of the layout of the record header written to new segment files.
+pub const RECORD_HEADER_VERSION: u64 = 1;
+
+/// The size of the record header in bytes, in version 1 of the layout.
+pub(crate) const RECORD_HEADER_SIZE: usize = 7;
+
+/// The bits of the type byte holding the record type. The others hold the flags.
+pub(crate) const RECORD_TYPE_MASK: u8 = 0b0000_0111;
+
+/// The type of a record, telling whether it holds a whole payload or a fragment of one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum RecordType {
+    /// The rest of the block is empty.
+    Empty = 0,
+    /// A full record.
+    Full = 1,
+    /// The first fragment of a record.
+    First = 2,
+    /// A middle fragment of a record.
+    Middle = 3,
+    /// The final fragment of a record.
+    Last = 4,
+}
+
+impl RecordType {
+    pub(crate) fn from_u8(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(RecordType::Empty),
+            1 => Ok(RecordType::Full),
+            2 => Ok(RecordType::First),
+            3 => Ok(RecordType::Middle),
+            4 => Ok(RecordType::Last),
+            _ => Err(Error::IO(IOError::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid Record Type",
+            ))),
+        }
+    }
+}
+
+/// The flags of a record, in the bits of the type byte outside `RECORD_TYPE_MASK`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RecordFlags(u8);
+
+impl RecordFlags {
+    /// No flag set: the record is checked with its CRC32.
+    pub const NONE: RecordFlags = RecordFlags(0);
+
+    /// The record is written without a checksum, see `Options::with_checksums`.
+    pub const UNCHECKED: RecordFlags = RecordFlags(0b0000_1000);
+
+    /// The flags readers know.
+    const KNOWN: u8 = Self::UNCHECKED.0;
+
+    /// The bits available to flags which are reserved: a reader finding one of them set
+    /// refuses the record rather than misreading it.
+    pub const RESERVED: u8 = !RECORD_TYPE_MASK & !Self::KNOWN;
+
+    /// Returns the flags set in `bits`, or an error if one of them is not known.
+    pub(crate) fn from_bits(bits: u8) -> Result<Self> {
+        if bits & !Self::KNOWN != 0 {
+            return Err(Error::IO(IOError::new(
+                io::ErrorKind::InvalidData,
+                "Unknown record flags",
+            )));
+        }
+        Ok(RecordFlags(bits))
+    }
+
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Returns true if all the flags of `other` are set.
+    pub fn contains(self, other: RecordFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+/// The header of a record or of a fragment of one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct RecordHeader {
+    pub(crate) record_type: RecordType,
+    pub(crate) flags: RecordFlags,
+    /// The length of the payload that follows the header.
+    pub(crate) length: u16,
+    /// The CRC32 of the type byte and the payload, or zero for an unchecked record.
+    pub(crate) crc: u32,
+}
+
+impl RecordHeader {
+    /// Returns the type byte of the header: the record type and the flags.
+    pub(crate) fn type_byte(&self) -> u8 {
+        self.record_type as u8 | self.flags.bits()
+    }
+
+    /// Writes the header into the first `RECORD_HEADER_SIZE` bytes of `buf`.
+    pub(crate) fn encode(&self, buf: &mut [u8]) {
+        buf[0] = self.type_byte();
+        buf[1..3].copy_from_slice(&self.length.to_be_bytes());
+        buf[3..7].copy_from_slice(&self.crc.to_be_bytes());
+    }
+
+    /// Reads a header from the first `RECORD_HEADER_SIZE` bytes of `buf`.
+    pub(crate) fn decode(buf: &[u8]) -> Result<Self> {
+        Ok(RecordHeader {
+            record_type: RecordType::from_u8(buf[0] & RECORD_TYPE_MASK)?,
+            flags: RecordFlags::from_bits(buf[0] & !RECORD_TYPE_MASK)?,
+            length: u16::from_be_bytes([buf[1], buf[2]]),
+            crc: u32::from_be_bytes([buf[3], buf[4], buf[5], buf[6]]),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_decode_header() {
+        let header = RecordHeader {
+            record_type: RecordType::Middle,
+            flags: RecordFlags::NONE,
+            length: 300,
+            crc: 0xDEADBEEF,
+        };
+        let mut buf = [0; RECORD_HEADER_SIZE];
+        header.encode(&mut buf);
+        assert_eq!(buf, [3, 1, 44, 0xDE, 0xAD, 0xBE, 0xEF]);
+        assert_eq!(RecordHeader::decode(&buf).unwrap(), header);
+
+        // The unchecked flag is read apart from the type
+        let header = RecordHeader {
+            flags: RecordFlags::UNCHECKED,
+            crc: 0,
+            ..header
+        };
+        header.encode(&mut buf);
+        assert_eq!(buf[0], 3 | 0b1000);
+        let decoded = RecordHeader::decode(&buf).unwrap();
+        assert_eq!(decoded, header);
+        assert!(decoded.flags.contains(RecordFlags::UNCHECKED));
+        assert!(!RecordFlags::NONE.contains(RecordFlags::UNCHECKED));
+
+        // A reserved flag is refused, rather than read as part of the type
+        for bit in 4..8 {
+            buf[0] = 3 | 1 << bit;
+            assert_eq!(RecordFlags::RESERVED & 1 << bit, 1 << bit);
+            assert!(RecordHeader::decode(&buf).is_err());
+        }
+        assert_eq!(RecordFlags::RESERVED & RecordFlags::UNCHECKED.bits(), 0);
+    }
+}
@@ -1,4 +1,3 @@
-use std::fs;
 use std::io;
 use std::path::Path;
 use std::path::PathBuf;
@@ -7,7 +6,7 @@ use parking_lot::RwLock;
 
 use crate::storage::log::wal::reader::Reader;
 use crate::storage::log::{
-    get_segment_range, Error, IOError, MultiSegmentReader, Options, Result, Segment, SegmentRef,
+    get_segment_range, Error, IOError, MultiSegmentReader, Options, Result, SegmentRef, WalSegment,
     WAL_RECORD_HEADER_SIZE,
 };
 
@@ -16,7 +15,7 @@ use crate::storage::log::{
 /// making it suitable for use cases like write-ahead logging.
 pub struct Wal {
     /// The currently active segment where data is being written.
-    active_segment: Segment<WAL_RECORD_HEADER_SIZE>,
+    active_segment: WalSegment,
 
     /// The ID of the currently active segment.
     active_segment_id: u64,
@@ -55,10 +54,10 @@ impl Wal {
         Self::prepare_directory(dir, &opts)?;
 
         // Determine the active segment ID
-        let active_segment_id = Self::calculate_active_segment_id(dir)?;
+        let active_segment_id = Self::calculate_active_segment_id(dir, &opts)?;
 
         // Open the active segment
-        let active_segment = Segment::open(dir, active_segment_id, &opts)?;
+        let active_segment = WalSegment::open(dir, active_segment_id, &opts)?;
 
         Ok(Self {
             active_segment,
@@ -72,31 +71,15 @@ impl Wal {
 
     // Helper function to prepare the directory with proper permissions
     fn prepare_directory(dir: &Path, opts: &Options) -> Result<()> {
-        fs::create_dir_all(dir)?;
-
-        if let Ok(metadata) = fs::metadata(dir) {
-            let mut permissions = metadata.permissions();
-
-            #[cfg(unix)]
-            {
-                use std::os::unix::fs::PermissionsExt;
-                permissions.set_mode(opts.dir_mode.unwrap_or(0o750));
-            }
-
-            #[cfg(windows)]
-            {
-                permissions.set_readonly(false);
-            }
-
-            fs::set_permissions(dir, permissions)?;
-        }
+        opts.vfs
+            .create_dir_all(dir, Some(opts.dir_mode.unwrap_or(0o750)))?;
 
         Ok(())
     }
 
     // Helper function to calculate the active segment ID
-    fn calculate_active_segment_id(dir: &Path) -> Result<u64> {
-        let (_, last) = get_segment_range(dir)?;
+    fn calculate_active_segment_id(dir: &Path, opts: &Options) -> Result<u64> {
+        let (_, last) = get_segment_range(&*opts.vfs, dir)?;
         Ok(if last > 0 { last + 1 } else { 0 })
     }
 
@@ -156,7 +139,7 @@ impl Wal {
 
             // Update the active segment id and create a new segment
             self.active_segment_id += 1;
-            let new_segment = Segment::open(&self.dir, self.active_segment_id, &self.opts)?;
+            let new_segment = WalSegment::open(&self.dir, self.active_segment_id, &self.opts)?;
             self.active_segment = new_segment;
         }
 
@@ -220,8 +203,7 @@ impl Wal {
         if segment_id == self.active_segment_id {
             self.active_segment.read_at(buf, read_offset)
         } else {
-            let segment: Segment<WAL_RECORD_HEADER_SIZE> =
-                Segment::open(&self.dir, segment_id, &self.opts)?;
+            let segment: WalSegment = WalSegment::open(&self.dir, segment_id, &self.opts)?;
             segment.read_at(buf, read_offset)
         }
     }
@@ -261,7 +243,7 @@ impl Wal {
         corrupted_offset_marker: u64,
     ) -> Result<()> {
         // Read the list of segments from the directory
-        let segs = SegmentRef::read_segments_from_directory(&self.dir)?;
+        let segs = SegmentRef::read_segments_from_directory(&*self.opts.vfs, &self.dir)?;
 
         // Prepare to store information about the corrupted segment
         let mut corrupted_segment_info = None;
@@ -280,7 +262,7 @@ impl Wal {
 
             // Remove segments newer than the corrupted segment
             if s.id > corrupted_segment_id {
-                std::fs::remove_file(&s.file_path)?;
+                self.opts.vfs.remove_file(&s.file_path)?;
             }
         }
 
@@ -300,10 +282,12 @@ impl Wal {
         let repaired_segment_path = corrupted_segment_path.with_extension("repair");
 
         // Rename the corrupted segment to the repaired segment
-        std::fs::rename(&corrupted_segment_path, &repaired_segment_path)?;
+        self.opts
+            .vfs
+            .rename(&corrupted_segment_path, &repaired_segment_path)?;
 
         // Open a new segment as the active segment
-        let new_segment = Segment::open(&self.dir, corrupted_segment_id, &self.opts)?;
+        let new_segment = WalSegment::open(&self.dir, corrupted_segment_id, &self.opts)?;
         self.active_segment = new_segment;
         self.active_segment_id = corrupted_segment_id;
 
@@ -313,7 +297,7 @@ impl Wal {
             file_header_offset: corrupted_segment_file_header_offset,
             id: corrupted_segment_id,
         }];
-        let segment_reader = MultiSegmentReader::new(segments)?;
+        let segment_reader = MultiSegmentReader::new(&self.opts.vfs, segments)?;
 
         // Initialize a reader for the segment
         let mut reader = Reader::new(segment_reader);
@@ -331,11 +315,11 @@ impl Wal {
         self.active_segment.close()?;
 
         // Remove the repaired segment file
-        std::fs::remove_file(&repaired_segment_path)?;
+        self.opts.vfs.remove_file(&repaired_segment_path)?;
 
         // Open the next segment and make it active
         self.active_segment_id += 1;
-        let new_segment = Segment::open(&self.dir, self.active_segment_id, &self.opts)?;
+        let new_segment = WalSegment::open(&self.dir, self.active_segment_id, &self.opts)?;
         self.active_segment = new_segment;
 
         Ok(())
@@ -2,15 +2,17 @@ use std::io::{self, Read};
 use std::vec::Vec;
 
 use crate::storage::log::{
-    calculate_crc32, validate_record_type, CorruptionError, Error, IOError, MultiSegmentReader,
-    RecordType, Result, BLOCK_SIZE, WAL_RECORD_HEADER_SIZE,
+    calculate_crc32,
+    record::{RecordFlags, RecordHeader, RecordType},
+    validate_record_type, CorruptionError, Error, IOError, MultiSegmentReader, Result, BLOCK_SIZE,
+    WAL_RECORD_HEADER_SIZE,
 };
 
 // Reader reads records from a MultiSegmentReader. The records are returned in the order they were written.
 // The current implementation of Reader is inspired by levelDB and prometheus implementation for WAL.
 // Since the WAL is append-only, the records are written in frames of BLOCK_SIZE bytes. The first byte of
-// each frame is the record type, followed by a reserved byte, followed by the record length (2 bytes) and
-// the CRC32 checksum (4 bytes). The record data follows the checksum.
+// each frame is the record type and flags, followed by the record length (2 bytes) and the CRC32 checksum
+// (4 bytes), as laid out by `RecordHeader`. The record data follows the checksum.
 //
 // No partial writes are allowed. If the segment is not full, and the record can't fit in the remaining space, the
 // segment is padded with zeros. This is important for the reader to be able to read the records in BLOCK_SIZE chunks.
@@ -45,7 +47,7 @@ impl Reader {
         Ok(buf[0])
     }
 
-    fn read_remaining_header<R: Read>(rdr: &mut R, buf: &mut [u8]) -> Result<(u16, u32)> {
+    fn read_remaining_header<R: Read>(rdr: &mut R, buf: &mut [u8]) -> Result<RecordHeader> {
         if rdr.read_exact(&mut buf[1..WAL_RECORD_HEADER_SIZE]).is_err() {
             return Err(Error::IO(IOError::new(
                 io::ErrorKind::Other,
@@ -53,24 +55,20 @@ impl Reader {
             )));
         }
 
-        let length = u16::from_be_bytes([buf[1], buf[2]]);
-        let crc = u32::from_be_bytes([buf[3], buf[4], buf[5], buf[6]]);
-        Ok((length, crc))
+        RecordHeader::decode(&buf[..WAL_RECORD_HEADER_SIZE])
     }
 
     fn read_and_validate_record<R: Read>(
         rdr: &mut R,
         buf: &mut [u8],
-        length: u16,
-        crc: u32,
-        rec_type: &RecordType,
+        header: &RecordHeader,
         current_index: usize,
     ) -> Result<(usize, usize)> {
         // Validate the record type.
-        validate_record_type(rec_type, current_index)?;
+        validate_record_type(&header.record_type, current_index)?;
 
         let record_start = WAL_RECORD_HEADER_SIZE;
-        let record_end = record_start + length as usize;
+        let record_end = record_start + header.length as usize;
         if rdr.read_exact(&mut buf[record_start..record_end]).is_err() {
             return Err(Error::IO(IOError::new(
                 io::ErrorKind::Other,
@@ -78,9 +76,12 @@ impl Reader {
             )));
         }
 
-        // Validate the checksum.
+        // Validate the checksum, unless the record was written without one.
+        if header.flags.contains(RecordFlags::UNCHECKED) {
+            return Ok((record_start, record_end));
+        }
         let calculated_crc = calculate_crc32(&buf[0..1], &buf[record_start..record_end]);
-        if calculated_crc != crc {
+        if calculated_crc != header.crc {
             return Err(Error::IO(IOError::new(
                 io::ErrorKind::Other,
                 "unexpected checksum",
@@ -121,11 +122,10 @@ impl Reader {
             // Read first byte of header to determine record type.
             let first_byte = Self::read_first_header_byte(&mut self.rdr, &mut self.buf[0..1])?;
             self.total_read += 1;
-            self.cur_rec_type = RecordType::from_u8(first_byte)?;
 
             // If the first byte is 0, it's a padded page.
             // Read the rest of the page of zeros and continue.
-            if self.cur_rec_type == RecordType::Empty {
+            if first_byte == RecordType::Empty as u8 {
                 let remaining = BLOCK_SIZE - (self.total_read % BLOCK_SIZE);
                 if remaining == BLOCK_SIZE {
                     continue;
@@ -150,19 +150,14 @@ impl Reader {
             }
 
             // Read the rest of the header.
-            let (length, crc) = Self::read_remaining_header(&mut self.rdr, &mut self.buf)?;
+            let header = Self::read_remaining_header(&mut self.rdr, &mut self.buf)?;
             self.total_read += WAL_RECORD_HEADER_SIZE - 1;
+            self.cur_rec_type = header.record_type;
 
             // Read the record data.
-            let (record_start, record_end) = Self::read_and_validate_record(
-                &mut self.rdr,
-                &mut self.buf,
-                length,
-                crc,
-                &self.cur_rec_type,
-                i,
-            )?;
-            self.total_read += length as usize;
+            let (record_start, record_end) =
+                Self::read_and_validate_record(&mut self.rdr, &mut self.buf, &header, i)?;
+            self.total_read += header.length as usize;
 
             // Copy the record data to the output buffer.
             self.rec
@@ -187,10 +182,11 @@ mod tests {
     use std::io::{Read, Seek, SeekFrom, Write};
     use std::vec::Vec;
 
+    use crate::storage::log::record::RecordFlags;
+
     use crate::storage::log::wal::log::Wal;
-    use crate::storage::log::{
-        read_file_header, Options, Segment, SegmentRef, WAL_RECORD_HEADER_SIZE,
-    };
+    use crate::storage::log::{read_file_header, Options, SegmentRef, WalSegment};
+    use crate::storage::vfs::{OsVfs, SharedVfs};
     use tempdir::TempDir;
 
     // BufferReader does not return EOF when the underlying reader returns 0 bytes read.
@@ -228,13 +224,10 @@ mod tests {
         assert_eq!(bytes_read, 0); // Only "World!" left to read
     }
 
-    fn create_test_segment(
-        temp_dir: &TempDir,
-        id: u64,
-        data: &[u8],
-    ) -> Segment<WAL_RECORD_HEADER_SIZE> {
+    fn create_test_segment(temp_dir: &TempDir, id: u64, data: &[u8]) -> WalSegment {
         let opts = Options::default().with_wal();
-        let mut segment = Segment::open(temp_dir.path(), id, &opts).expect("should create segment");
+        let mut segment =
+            WalSegment::open(temp_dir.path(), id, &opts).expect("should create segment");
         let r = segment.append(data);
         assert!(r.is_ok());
         assert_eq!(data.len(), r.unwrap().1);
@@ -254,10 +247,11 @@ mod tests {
         assert!(segment2.close().is_ok());
         assert!(segment3.close().is_ok());
 
-        let sr = SegmentRef::read_segments_from_directory(temp_dir.path())
+        let sr = SegmentRef::read_segments_from_directory(&OsVfs, temp_dir.path())
             .expect("should read segments");
 
-        let mut reader = Reader::new(MultiSegmentReader::new(sr).expect("should create"));
+        let mut reader =
+            Reader::new(MultiSegmentReader::new(&SharedVfs::default(), sr).expect("should create"));
         reader.next().expect("should read");
         assert_eq!(reader.rec, vec![1, 2, 3, 4]);
         assert_eq!(reader.total_read, 11);
@@ -271,12 +265,10 @@ mod tests {
         assert_eq!(reader.total_read, BLOCK_SIZE * 2 + 10);
     }
 
-    fn create_test_segment_with_data(
-        temp_dir: &TempDir,
-        id: u64,
-    ) -> Segment<WAL_RECORD_HEADER_SIZE> {
+    fn create_test_segment_with_data(temp_dir: &TempDir, id: u64) -> WalSegment {
         let opts = Options::default().with_wal();
-        let mut segment = Segment::open(temp_dir.path(), id, &opts).expect("should create segment");
+        let mut segment =
+            WalSegment::open(temp_dir.path(), id, &opts).expect("should create segment");
 
         let record_size = 4;
         let num_records = 1000;
@@ -300,10 +292,11 @@ mod tests {
         let mut segment1 = create_test_segment_with_data(&temp_dir, 4);
         assert!(segment1.close().is_ok());
 
-        let sr = SegmentRef::read_segments_from_directory(temp_dir.path())
+        let sr = SegmentRef::read_segments_from_directory(&OsVfs, temp_dir.path())
             .expect("should read segments");
 
-        let mut reader = Reader::new(MultiSegmentReader::new(sr).expect("should create"));
+        let mut reader =
+            Reader::new(MultiSegmentReader::new(&SharedVfs::default(), sr).expect("should create"));
 
         let mut i = 0;
         while let Ok((data, _)) = reader.read() {
@@ -327,10 +320,11 @@ mod tests {
         assert!(segment2.close().is_ok());
         assert!(segment3.close().is_ok());
 
-        let sr = SegmentRef::read_segments_from_directory(temp_dir.path())
+        let sr = SegmentRef::read_segments_from_directory(&OsVfs, temp_dir.path())
             .expect("should read segments");
 
-        let mut reader = Reader::new(MultiSegmentReader::new(sr).expect("should create"));
+        let mut reader =
+            Reader::new(MultiSegmentReader::new(&SharedVfs::default(), sr).expect("should create"));
 
         let mut i = 0;
         while let Ok((data, _)) = reader.read() {
@@ -363,10 +357,11 @@ mod tests {
 
         a.sync().expect("should sync");
 
-        let sr = SegmentRef::read_segments_from_directory(temp_dir.path())
+        let sr = SegmentRef::read_segments_from_directory(&OsVfs, temp_dir.path())
             .expect("should read segments");
 
-        let mut reader = Reader::new(MultiSegmentReader::new(sr).expect("should create"));
+        let mut reader =
+            Reader::new(MultiSegmentReader::new(&SharedVfs::default(), sr).expect("should create"));
 
         let mut i = 0;
         while let Ok((data, _)) = reader.read() {
@@ -377,6 +372,44 @@ mod tests {
         assert_eq!(i, num_records);
     }
 
+    #[test]
+    fn reader_with_unchecked_records() {
+        let temp_dir = TempDir::new("test").expect("should create temp dir");
+        let opts = Options::default().with_wal().with_checksums(false);
+        let mut segment =
+            WalSegment::open(temp_dir.path(), 4, &opts).expect("should create segment");
+        segment.append(&[1, 2, 3, 4]).expect("should append");
+        segment.append(&[5, 6, 7, 8]).expect("should append");
+        assert!(segment.close().is_ok());
+
+        // The records are flagged, with no checksum
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&segment.file_path)
+            .expect("should open");
+        read_file_header(&mut file).expect("should read");
+        let start = file.stream_position().expect("should tell");
+        let mut header = [0; WAL_RECORD_HEADER_SIZE];
+        file.read_exact(&mut header).expect("should read");
+        assert_eq!(
+            header,
+            [1 | RecordFlags::UNCHECKED.bits(), 0, 4, 0, 0, 0, 0]
+        );
+
+        // A corrupted byte of the payload is read as it is
+        file.seek(SeekFrom::Start(start + WAL_RECORD_HEADER_SIZE as u64 + 1))
+            .expect("should seek");
+        file.write_all(&[0x55]).expect("should write");
+
+        let sr = SegmentRef::read_segments_from_directory(&OsVfs, temp_dir.path())
+            .expect("should read segments");
+        let mut reader =
+            Reader::new(MultiSegmentReader::new(&SharedVfs::default(), sr).expect("should create"));
+        assert_eq!(reader.read().expect("should read").0, vec![1, 0x55, 3, 4]);
+        assert_eq!(reader.read().expect("should read").0, vec![5, 6, 7, 8]);
+    }
+
     #[test]
     fn wal_repair() {
         // Create a temporary directory to hold the segment files
@@ -411,10 +444,12 @@ mod tests {
         let corrupted_offset_marker;
 
         {
-            let sr = SegmentRef::read_segments_from_directory(temp_dir.path())
+            let sr = SegmentRef::read_segments_from_directory(&OsVfs, temp_dir.path())
                 .expect("should read segments");
 
-            let mut reader = Reader::new(MultiSegmentReader::new(sr).expect("should create"));
+            let mut reader = Reader::new(
+                MultiSegmentReader::new(&SharedVfs::default(), sr).expect("should create"),
+            );
 
             // Read the valid records before corruption
             let rec = reader.read().expect("should read");
@@ -443,10 +478,12 @@ mod tests {
 
         // Verify the repaired segment
         {
-            let sr = SegmentRef::read_segments_from_directory(temp_dir.path())
+            let sr = SegmentRef::read_segments_from_directory(&OsVfs, temp_dir.path())
                 .expect("should read segments");
 
-            let mut reader = Reader::new(MultiSegmentReader::new(sr).expect("should create"));
+            let mut reader = Reader::new(
+                MultiSegmentReader::new(&SharedVfs::default(), sr).expect("should create"),
+            );
 
             // Read the valid records after repair
             let rec = reader.read().expect("should read");
@@ -465,10 +502,12 @@ mod tests {
 
         // Verify the appended data
         {
-            let sr = SegmentRef::read_segments_from_directory(temp_dir.path())
+            let sr = SegmentRef::read_segments_from_directory(&OsVfs, temp_dir.path())
                 .expect("should read segments");
 
-            let mut reader = Reader::new(MultiSegmentReader::new(sr).expect("should create"));
+            let mut reader = Reader::new(
+                MultiSegmentReader::new(&SharedVfs::default(), sr).expect("should create"),
+            );
 
             // Read the valid records after append
             let rec = reader.read().expect("should read");
@@ -1,3 +1,4 @@
 pub mod cache;
 pub(crate) mod kv;
 pub(crate) mod log;
+pub mod vfs;
@@ -0,0 +1,438 @@
+use std::collections::BTreeMap;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::storage::vfs::{DirEntry, Metadata, OpenOptions, Vfs, VfsFile};
+
+/// The contents of a file, shared by the handles it is open with. The bytes are shared with
+/// the snapshots taken since they were last written, and copied by the next write.
+type Contents = Arc<Mutex<Arc<Vec<u8>>>>;
+
+#[derive(Clone)]
+enum Node {
+    Dir,
+    File(Contents),
+}
+
+/// A filesystem holding its files in memory, for tests and for embedding.
+///
+/// A clone is a handle to the same filesystem. A snapshot of the files, taken with
+/// `snapshot`, costs a copy of the list of the files, not of their bytes: the bytes of a file
+/// are only copied when it is written after the snapshot. A snapshot is forked into a new
+/// filesystem, independent of the one it was taken from, on which a store is opened as it
+/// would be on a copy of the directory of the original store.
+///
+/// A snapshot holds the bytes written to the files, synced or not, and not the ones a store
+/// still buffers: the snapshot of an open store is the state of its files after a crash of
+/// the process, and the snapshot of a closed store is its whole state.
+#[derive(Clone, Default)]
+pub struct MemVfs {
+    nodes: Arc<Mutex<BTreeMap<PathBuf, Node>>>,
+}
+
+/// The files of a [`MemVfs`] at the time `MemVfs::snapshot` was called.
+#[derive(Clone, Default)]
+pub struct MemVfsSnapshot {
+    files: Arc<BTreeMap<PathBuf, Option<Arc<Vec<u8>>>>>, // None for the directories.
+}
+
+impl MemVfs {
+    /// Creates an empty filesystem.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes a snapshot of the files.
+    pub fn snapshot(&self) -> MemVfsSnapshot {
+        let nodes = self.nodes.lock();
+        let files = nodes
+            .iter()
+            .map(|(path, node)| {
+                let contents = match node {
+                    Node::Dir => None,
+                    Node::File(contents) => Some(contents.lock().clone()),
+                };
+                (path.clone(), contents)
+            })
+            .collect();
+        MemVfsSnapshot {
+            files: Arc::new(files),
+        }
+    }
+
+    /// Returns a new filesystem holding a copy of the files, as `snapshot().fork()` does.
+    pub fn fork(&self) -> Self {
+        self.snapshot().fork()
+    }
+}
+
+impl MemVfsSnapshot {
+    /// Returns a new filesystem holding the files of the snapshot.
+    pub fn fork(&self) -> MemVfs {
+        let nodes = self
+            .files
+            .iter()
+            .map(|(path, contents)| {
+                let node = match contents {
+                    None => Node::Dir,
+                    Some(bytes) => Node::File(Arc::new(Mutex::new(bytes.clone()))),
+                };
+                (path.clone(), node)
+            })
+            .collect();
+        MemVfs {
+            nodes: Arc::new(Mutex::new(nodes)),
+        }
+    }
+}
+
+/// Returns the path without its `.` components, as the files are keyed.
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} not found", path.display()),
+    )
+}
+
+fn is_dir(nodes: &BTreeMap<PathBuf, Node>, path: &Path) -> bool {
+    path.parent().is_none() || matches!(nodes.get(path), Some(Node::Dir))
+}
+
+/// Checks that the parent directory of `path` exists.
+fn check_parent(nodes: &BTreeMap<PathBuf, Node>, path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) if !is_dir(nodes, parent) => Err(not_found(parent)),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the paths of `path` and of the files and directories under it.
+fn subtree(nodes: &BTreeMap<PathBuf, Node>, path: &Path) -> Vec<PathBuf> {
+    nodes
+        .range(path.to_path_buf()..)
+        .map(|(key, _)| key)
+        .take_while(|key| key.starts_with(path))
+        .cloned()
+        .collect()
+}
+
+impl Vfs for MemVfs {
+    fn open(&self, path: &Path, options: &OpenOptions) -> io::Result<Box<dyn VfsFile>> {
+        let path = normalize(path);
+        let mut nodes = self.nodes.lock();
+        let contents = match nodes.get(&path) {
+            Some(Node::Dir) => {
+                return Err(io::Error::other(format!(
+                    "{} is a directory",
+                    path.display()
+                )))
+            }
+            Some(Node::File(contents)) => {
+                if options.truncate && (options.write || options.append) {
+                    *contents.lock() = Arc::default();
+                }
+                contents.clone()
+            }
+            None if options.create => {
+                check_parent(&nodes, &path)?;
+                let contents = Contents::default();
+                nodes.insert(path, Node::File(contents.clone()));
+                contents
+            }
+            None => return Err(not_found(&path)),
+        };
+        Ok(Box::new(MemFile {
+            contents,
+            pos: 0,
+            read: options.read,
+            write: options.write || options.append,
+            append: options.append,
+        }))
+    }
+
+    fn create_dir_all(&self, path: &Path, _mode: Option<u32>) -> io::Result<()> {
+        let path = normalize(path);
+        let mut nodes = self.nodes.lock();
+        let mut ancestors: Vec<&Path> = path.ancestors().collect();
+        ancestors.reverse();
+        for dir in ancestors {
+            if dir.parent().is_none() {
+                continue;
+            }
+            match nodes.get(dir) {
+                Some(Node::Dir) => {}
+                Some(Node::File(_)) => {
+                    return Err(io::Error::other(format!(
+                        "{} is not a directory",
+                        dir.display()
+                    )))
+                }
+                None => {
+                    nodes.insert(dir.to_path_buf(), Node::Dir);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        let path = normalize(path);
+        let nodes = self.nodes.lock();
+        if !is_dir(&nodes, &path) {
+            return Err(not_found(&path));
+        }
+        let entries = nodes
+            .range(path.clone()..)
+            .take_while(|(key, _)| key.starts_with(&path))
+            .filter(|(key, _)| key.parent() == Some(&path))
+            .map(|(key, node)| DirEntry::new(key.clone(), metadata(node)))
+            .collect();
+        Ok(entries)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let path = normalize(path);
+        if path.parent().is_none() {
+            return Ok(Metadata::new(0, true));
+        }
+        match self.nodes.lock().get(&path) {
+            Some(node) => Ok(metadata(node)),
+            None => Err(not_found(&path)),
+        }
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let path = normalize(path);
+        let mut nodes = self.nodes.lock();
+        match nodes.get(&path) {
+            Some(Node::File(_)) => {
+                nodes.remove(&path);
+                Ok(())
+            }
+            Some(Node::Dir) => Err(io::Error::other(format!(
+                "{} is a directory",
+                path.display()
+            ))),
+            None => Err(not_found(&path)),
+        }
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        let path = normalize(path);
+        let mut nodes = self.nodes.lock();
+        if !matches!(nodes.get(&path), Some(Node::Dir)) {
+            return Err(not_found(&path));
+        }
+        for key in subtree(&nodes, &path) {
+            nodes.remove(&key);
+        }
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let (from, to) = (normalize(from), normalize(to));
+        let mut nodes = self.nodes.lock();
+        let Some(node) = nodes.get(&from).cloned() else {
+            return Err(not_found(&from));
+        };
+        check_parent(&nodes, &to)?;
+        if from == to {
+            return Ok(());
+        }
+        match (&node, nodes.get(&to)) {
+            (_, None) | (Node::File(_), Some(Node::File(_))) => {}
+            (Node::Dir, Some(Node::Dir)) if subtree(&nodes, &to).len() == 1 => {}
+            _ => {
+                return Err(io::Error::other(format!(
+                    "{} cannot be replaced",
+                    to.display()
+                )))
+            }
+        }
+        if to.starts_with(&from) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is inside {}", to.display(), from.display()),
+            ));
+        }
+
+        for key in subtree(&nodes, &from) {
+            let node = nodes.remove(&key).unwrap();
+            let moved = to.join(key.strip_prefix(&from).unwrap());
+            nodes.insert(normalize(&moved), node);
+        }
+        Ok(())
+    }
+}
+
+fn metadata(node: &Node) -> Metadata {
+    match node {
+        Node::Dir => Metadata::new(0, true),
+        Node::File(contents) => Metadata::new(contents.lock().len() as u64, false),
+    }
+}
+
+/// A file of a [`MemVfs`], open.
+struct MemFile {
+    contents: Contents,
+    pos: u64,
+    read: bool,
+    write: bool,
+    append: bool,
+}
+
+impl MemFile {
+    fn check(allowed: bool, operation: &str) -> io::Result<()> {
+        match allowed {
+            true => Ok(()),
+            false => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("the file is not open for {}", operation),
+            )),
+        }
+    }
+}
+
+impl Read for MemFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Self::check(self.read, "reading")?;
+        let n = self.read_at(buf, self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Write for MemFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Self::check(self.write, "writing")?;
+        let mut contents = self.contents.lock();
+        let bytes = Arc::make_mut(&mut contents);
+        if self.append {
+            self.pos = bytes.len() as u64;
+        }
+        let start = self.pos as usize;
+        let end = start + buf.len();
+        if bytes.len() < end {
+            bytes.resize(end, 0);
+        }
+        bytes[start..end].copy_from_slice(buf);
+        self.pos = end as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for MemFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => {
+                self.pos = offset;
+                return Ok(offset);
+            }
+            SeekFrom::End(offset) => (self.size()?, offset),
+            SeekFrom::Current(offset) => (self.pos, offset),
+        };
+        match base.checked_add_signed(offset) {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+impl VfsFile for MemFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let contents = self.contents.lock();
+        let start = (offset as usize).min(contents.len());
+        let n = buf.len().min(contents.len() - start);
+        buf[..n].copy_from_slice(&contents[start..start + n]);
+        Ok(n)
+    }
+
+    fn sync_all(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_len(&self, size: u64) -> io::Result<()> {
+        Self::check(self.write, "writing")?;
+        let mut contents = self.contents.lock();
+        Arc::make_mut(&mut contents).resize(size as usize, 0);
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.contents.lock().len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemVfs;
+    use crate::storage::kv::option::Options;
+    use crate::storage::kv::store::Store;
+    use crate::storage::vfs::SharedVfs;
+
+    use tempdir::TempDir;
+
+    fn get(store: &Store, key: &[u8]) -> Option<Vec<u8>> {
+        store.begin().unwrap().get(key).unwrap()
+    }
+
+    async fn set(store: &Store, key: &[u8], value: &[u8]) {
+        let mut txn = store.begin().unwrap();
+        txn.set(key, value).unwrap();
+        txn.commit().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn stores_are_forked_in_memory() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let vfs = MemVfs::new();
+        let mut opts = Options::new();
+        opts.dir = temp_dir.path().join("db");
+        opts.vfs = SharedVfs::new(vfs.clone());
+        let store = Store::new(opts.clone()).expect("should create store");
+        set(&store, b"a", b"1").await;
+
+        // The snapshot of an open store holds the commits written to the commit log.
+        let crashed = vfs.snapshot();
+        set(&store, b"b", b"1").await;
+        store.close().await.unwrap();
+        assert!(!opts.dir.exists());
+
+        // A fork is a copy of the store, written apart from the original.
+        let mut fork_opts = opts.clone();
+        fork_opts.vfs = SharedVfs::new(vfs.fork());
+        let fork = Store::new(fork_opts).expect("should open fork");
+        assert_eq!(get(&fork, b"b").unwrap(), b"1");
+        set(&fork, b"a", b"2").await;
+        fork.close().await.unwrap();
+
+        let store = Store::new(opts.clone()).expect("should reopen store");
+        assert_eq!(get(&store, b"a").unwrap(), b"1");
+        store.close().await.unwrap();
+
+        opts.vfs = SharedVfs::new(crashed.fork());
+        let store = Store::new(opts).expect("should open crashed store");
+        assert_eq!(get(&store, b"a").unwrap(), b"1");
+        assert_eq!(get(&store, b"b"), None);
+        store.close().await.unwrap();
+    }
+}
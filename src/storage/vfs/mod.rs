@@ -0,0 +1,236 @@
+//! The filesystem the files of a store are read from and written to, see `Options::vfs`.
+//!
+//! A store reaches its files through a [`Vfs`] only, which is the operating system by
+//! default, see [`OsVfs`]. Another filesystem can be plugged in, such as [`MemVfs`], which
+//! keeps the files in memory and can be snapshotted and forked, for tests to clone the state of
+//! a store instantly, or a filesystem of an embedder backed by its own storage.
+
+mod mem;
+mod os;
+
+use std::ffi::OsStr;
+use std::fmt;
+use std::io::{self, Read, Seek, Write};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+
+pub use mem::{MemVfs, MemVfsSnapshot};
+pub use os::OsVfs;
+
+/// A filesystem, as the store uses it. The paths are the ones derived from `Options::dir`.
+///
+/// The methods follow the semantics of their counterparts of `std::fs`: in particular, an
+/// open file stays readable and writable once it is removed or renamed, and a file written
+/// and synced is expected to be read back whole after a crash.
+pub trait Vfs: Send + Sync {
+    /// Opens a file, as `std::fs::OpenOptions::open` does.
+    fn open(&self, path: &Path, options: &OpenOptions) -> io::Result<Box<dyn VfsFile>>;
+
+    /// Creates a directory and its missing parents, as `std::fs::create_dir_all` does. The
+    /// permissions of the directory are set to `mode` if given, on the filesystems which have
+    /// permissions.
+    fn create_dir_all(&self, path: &Path, mode: Option<u32>) -> io::Result<()>;
+
+    /// Returns the entries of a directory, in no particular order.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>>;
+
+    /// Returns the metadata of a file or directory.
+    fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    /// Removes a file.
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    /// Removes a directory and its contents.
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// Renames a file or directory, replacing the file `to` if it exists.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Returns true if a file or directory exists at `path`.
+    fn exists(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok()
+    }
+
+    /// Reads a whole file, as `std::fs::read` does.
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let mut file = self.open(path, &OpenOptions::new().read(true))?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        Ok(contents)
+    }
+
+    /// Writes a whole file, creating or truncating it, as `std::fs::write` does.
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.open(path, &OpenOptions::create_file())?
+            .write_all(contents)
+    }
+}
+
+/// A file opened by a [`Vfs`].
+pub trait VfsFile: Read + Write + Seek + Send + Sync {
+    /// Reads from `offset` into `buf`, without moving the cursor of the file, and returns the
+    /// number of bytes read.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize>;
+
+    /// Syncs the data and the metadata of the file to its storage.
+    fn sync_all(&self) -> io::Result<()>;
+
+    /// Truncates or extends the file to `size` bytes.
+    fn set_len(&self, size: u64) -> io::Result<()>;
+
+    /// Returns the size of the file in bytes.
+    fn size(&self) -> io::Result<u64>;
+}
+
+/// The options a file is opened with, as the ones of `std::fs::OpenOptions`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OpenOptions {
+    pub read: bool,
+    pub write: bool,
+    pub append: bool,
+    pub create: bool,
+    pub truncate: bool,
+    pub mode: Option<u32>, // Permissions of a file created, on the filesystems which have permissions.
+}
+
+impl OpenOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn read(mut self, read: bool) -> Self {
+        self.read = read;
+        self
+    }
+
+    pub fn write(mut self, write: bool) -> Self {
+        self.write = write;
+        self
+    }
+
+    pub fn append(mut self, append: bool) -> Self {
+        self.append = append;
+        self
+    }
+
+    pub fn create(mut self, create: bool) -> Self {
+        self.create = create;
+        self
+    }
+
+    pub fn truncate(mut self, truncate: bool) -> Self {
+        self.truncate = truncate;
+        self
+    }
+
+    pub fn mode(mut self, mode: Option<u32>) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Returns the options creating or truncating a file to write, as `std::fs::File::create`.
+    pub(crate) fn create_file() -> Self {
+        Self::new().write(true).create(true).truncate(true)
+    }
+}
+
+/// The metadata of a file or directory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    len: u64,
+    is_dir: bool,
+}
+
+impl Metadata {
+    pub fn new(len: u64, is_dir: bool) -> Self {
+        Self { len, is_dir }
+    }
+
+    /// Returns the size of the file in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns true if the file is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+
+    pub fn is_file(&self) -> bool {
+        !self.is_dir
+    }
+}
+
+/// An entry of a directory, returned by `Vfs::read_dir`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirEntry {
+    path: PathBuf,
+    metadata: Metadata,
+}
+
+impl DirEntry {
+    pub fn new(path: PathBuf, metadata: Metadata) -> Self {
+        Self { path, metadata }
+    }
+
+    /// Returns the path of the entry, in the directory read.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the name of the entry.
+    pub fn file_name(&self) -> &OsStr {
+        self.path.file_name().unwrap_or_default()
+    }
+
+    pub fn metadata(&self) -> Metadata {
+        self.metadata
+    }
+}
+
+/// A [`Vfs`] shared by the stores and logs opened on it, set in `Options::vfs`.
+///
+/// Two handles are equal if they share the same filesystem. The default handle is the one of
+/// the operating system, [`OsVfs`], shared by all the default options.
+#[derive(Clone)]
+pub struct SharedVfs(Arc<dyn Vfs>);
+
+impl SharedVfs {
+    pub fn new<V: Vfs + 'static>(vfs: V) -> Self {
+        Self(Arc::new(vfs))
+    }
+}
+
+impl Default for SharedVfs {
+    fn default() -> Self {
+        static OS: OnceLock<SharedVfs> = OnceLock::new();
+        OS.get_or_init(|| Self::new(OsVfs)).clone()
+    }
+}
+
+impl Deref for SharedVfs {
+    type Target = dyn Vfs;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.0
+    }
+}
+
+impl PartialEq for SharedVfs {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::as_ptr(&self.0) as *const () == Arc::as_ptr(&other.0) as *const ()
+    }
+}
+
+impl Eq for SharedVfs {}
+
+impl fmt::Debug for SharedVfs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SharedVfs({:p})", Arc::as_ptr(&self.0) as *const ())
+    }
+}
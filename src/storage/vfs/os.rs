@@ -0,0 +1,125 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+use crate::storage::vfs::{DirEntry, Metadata, OpenOptions, Vfs, VfsFile};
+
+/// The filesystem of the operating system, which the stores use by default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OsVfs;
+
+impl Vfs for OsVfs {
+    fn open(&self, path: &Path, options: &OpenOptions) -> io::Result<Box<dyn VfsFile>> {
+        let mut open_options = fs::OpenOptions::new();
+        open_options
+            .read(options.read)
+            .write(options.write)
+            .append(options.append)
+            .create(options.create)
+            .truncate(options.truncate);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            if let Some(mode) = options.mode {
+                open_options.mode(mode);
+            }
+        }
+
+        Ok(Box::new(open_options.open(path)?))
+    }
+
+    fn create_dir_all(&self, path: &Path, mode: Option<u32>) -> io::Result<()> {
+        fs::create_dir_all(path)?;
+        let Some(_mode) = mode else {
+            return Ok(());
+        };
+
+        let mut permissions = fs::metadata(path)?.permissions();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            permissions.set_mode(_mode);
+        }
+
+        #[cfg(windows)]
+        {
+            permissions.set_readonly(false);
+        }
+
+        fs::set_permissions(path, permissions)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            entries.push(DirEntry::new(
+                entry.path(),
+                Metadata::new(metadata.len(), metadata.is_dir()),
+            ));
+        }
+        Ok(entries)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let metadata = fs::metadata(path)?;
+        Ok(Metadata::new(metadata.len(), metadata.is_dir()))
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir_all(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        fs::write(path, contents)
+    }
+}
+
+impl VfsFile for File {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::FileExt::read_at(self, buf, offset)
+        }
+
+        #[cfg(windows)]
+        {
+            std::os::windows::fs::FileExt::seek_read(self, buf, offset)
+        }
+
+        #[cfg(not(any(unix, windows)))]
+        {
+            use std::io::{Read, Seek, SeekFrom};
+            let mut file = self;
+            file.seek(SeekFrom::Start(offset))?;
+            file.read(buf)
+        }
+    }
+
+    fn sync_all(&self) -> io::Result<()> {
+        File::sync_all(self)
+    }
+
+    fn set_len(&self, size: u64) -> io::Result<()> {
+        File::set_len(self, size)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+}